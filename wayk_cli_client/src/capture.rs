@@ -0,0 +1,80 @@
+use crate::config::Cli;
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wayk_proto::pcap::PcapngWriter;
+use wayk_proto::trace::{PacketDirection, PacketRecorder};
+
+/// Optional packet captures requested on the command line.
+pub struct Capture {
+    trace: Option<(PathBuf, PacketRecorder)>,
+    pcap: Option<PcapngWriter<File>>,
+}
+
+impl Capture {
+    pub fn new(args: &Cli) -> Self {
+        let trace = args
+            .trace
+            .clone()
+            .map(|path| (path, PacketRecorder::with_system_clock()));
+
+        let pcap = args.pcap.as_ref().and_then(|path| match File::create(path) {
+            Ok(file) => match PcapngWriter::new(file) {
+                Ok(pcap) => Some(pcap),
+                Err(err) => {
+                    log::error!("Couldn't write pcapng header: {}", err);
+                    None
+                }
+            },
+            Err(err) => {
+                log::error!("Couldn't create pcapng file: {}", err);
+                None
+            }
+        });
+
+        Self { trace, pcap }
+    }
+
+    pub fn on_received(&mut self, bytes: &[u8]) {
+        self.record(PacketDirection::Received, bytes);
+    }
+
+    pub fn on_sent(&mut self, bytes: &[u8]) {
+        self.record(PacketDirection::Sent, bytes);
+    }
+
+    fn record(&mut self, direction: PacketDirection, bytes: &[u8]) {
+        if let Some((_, recorder)) = &mut self.trace {
+            if let Err(err) = recorder.record_raw(direction, bytes) {
+                log::error!("Couldn't record packet, trace disabled: {}", err);
+                self.trace = None;
+            }
+        }
+
+        if let Some(pcap) = &mut self.pcap {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or(0);
+            if let Err(err) = pcap.write_raw(direction, timestamp, bytes) {
+                log::error!("Couldn't write pcapng file, capture disabled: {}", err);
+                self.pcap = None;
+            }
+        }
+    }
+
+    pub fn finish(self) {
+        if let Some((path, recorder)) = self.trace {
+            match std::fs::write(&path, recorder.log()) {
+                Ok(()) => log::info!("{} packets recorded into {}.", recorder.len(), path.display()),
+                Err(err) => log::error!("Couldn't write trace file: {}", err),
+            }
+        }
+
+        if let Some(mut pcap) = self.pcap {
+            if let Err(err) = pcap.flush() {
+                log::error!("Couldn't flush pcapng file: {}", err);
+            }
+        }
+    }
+}
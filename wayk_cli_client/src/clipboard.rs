@@ -0,0 +1,124 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use wayk_proto::message::{NowClipboardFormatDataReqMsg, NowClipboardFormatDataRspMsg, NowClipboardFormatListReqMsg};
+use wayk_proto::sm::client_channels::clipboard::formats::{ClipboardContent, ClipboardFormats, StandardFormat};
+use wayk_proto::sm::{ChannelResponses, ClipboardHandle};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const TEXT_FORMATS: [StandardFormat; 2] = [StandardFormat::Utf8String, StandardFormat::UnicodeText];
+
+#[derive(Default)]
+struct BridgeState {
+    local: Option<ClipboardContent>,
+    peer_formats: ClipboardFormats,
+    /// Text received from the peer, to be written into the local clipboard.
+    to_write: Option<String>,
+    sequence_id: u16,
+}
+
+/// Bridges the local system clipboard with the peer one.
+///
+/// The system clipboard is only accessed from the watcher thread, clipboard callbacks exchange data
+/// with it through the shared state.
+#[derive(Clone, Default)]
+pub struct ClipboardBridge {
+    state: Arc<Mutex<BridgeState>>,
+}
+
+impl ClipboardBridge {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advertises the local clipboard content once the channel is enabled.
+    pub fn on_enabled(&self, to_send: &mut ChannelResponses<'_>) {
+        if let Some(req) = self.h_format_list_req() {
+            to_send.push(req);
+        }
+    }
+
+    /// Peer took the ownership, fetches its content as text.
+    pub fn on_peer_formats(&self, to_send: &mut ChannelResponses<'_>, msg: &NowClipboardFormatListReqMsg) {
+        let mut state = self.state.lock().unwrap();
+        state.peer_formats = ClipboardFormats::from_defs(&msg.formats.0);
+        state.sequence_id = state.sequence_id.wrapping_add(1);
+        match state.peer_formats.format_data_req(state.sequence_id, &TEXT_FORMATS) {
+            Some((_, req)) => to_send.push(req),
+            None => log::info!("Peer clipboard holds no text, ignored."),
+        }
+    }
+
+    pub fn on_format_data_req(&self, to_send: &mut ChannelResponses<'_>, req: &NowClipboardFormatDataReqMsg) {
+        let state = self.state.lock().unwrap();
+        match &state.local {
+            Some(content) => {
+                to_send.push(ClipboardFormats::for_content(content).format_data_rsp(req, content));
+            }
+            None => log::warn!("Peer asked for clipboard data while local clipboard is empty"),
+        }
+    }
+
+    pub fn on_format_data_rsp(&self, rsp: &NowClipboardFormatDataRspMsg<'_>) {
+        let mut state = self.state.lock().unwrap();
+        match state.peer_formats.decode_format_data_rsp(rsp) {
+            Ok(ClipboardContent::Text(text)) => {
+                state.local = Some(ClipboardContent::Text(text.clone()));
+                state.to_write = Some(text);
+            }
+            Ok(content) => log::warn!("Unexpected clipboard content received: {:?}", content),
+            Err(e) => log::warn!("Couldn't fetch peer clipboard: {}", e),
+        }
+    }
+
+    /// Polls the system clipboard on a separate thread and pushes local changes to the peer.
+    pub fn spawn_watcher(&self, handle: ClipboardHandle) {
+        let bridge = self.clone();
+        thread::spawn(move || {
+            let mut clipboard = match arboard::Clipboard::new() {
+                Ok(clipboard) => clipboard,
+                Err(e) => {
+                    log::error!("Couldn't access system clipboard: {}", e);
+                    return;
+                }
+            };
+
+            let mut last_text = None;
+            loop {
+                thread::sleep(POLL_INTERVAL);
+
+                let to_write = bridge.state.lock().unwrap().to_write.take();
+                if let Some(text) = to_write {
+                    if let Err(e) = clipboard.set_text(text.clone()) {
+                        log::warn!("Couldn't write system clipboard: {}", e);
+                    }
+                    last_text = Some(text);
+                    continue;
+                }
+
+                let text = match clipboard.get_text() {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                };
+
+                if last_text.as_ref() == Some(&text) {
+                    continue;
+                }
+
+                log::debug!("Local clipboard changed, taking ownership.");
+                last_text = Some(text.clone());
+                let content = ClipboardContent::Text(text);
+                handle.announce_formats(ClipboardFormats::for_content(&content).into_defs());
+                bridge.state.lock().unwrap().local = Some(content);
+            }
+        });
+    }
+
+    fn h_format_list_req(&self) -> Option<NowClipboardFormatListReqMsg> {
+        let mut state = self.state.lock().unwrap();
+        let formats = ClipboardFormats::for_content(state.local.as_ref()?);
+        state.sequence_id = state.sequence_id.wrapping_add(1);
+        Some(formats.format_list_req(state.sequence_id))
+    }
+}
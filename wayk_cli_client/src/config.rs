@@ -1,11 +1,11 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
 use structopt::StructOpt;
-use wayk_proto::message::{AuthType, ChannelName, NowCapset};
+use wayk_proto::message::{AuthType, CapabilitiesBuilder, ChannelName};
 
 #[derive(StructOpt, Debug)]
 #[structopt(author, about)]
-
 pub struct Cli {
     pub addr: SocketAddr,
 
@@ -31,9 +31,25 @@ pub struct Cli {
     /// Message to send on synchronisation
     pub on_sync_message: Option<String>,
 
+    #[structopt(long)]
+    /// Interactive chat: stdin lines are sent to the peer
+    pub chat: bool,
+
     #[structopt(long)]
     /// Text to put into server clipboard
     pub on_clipboard_ready: Option<String>,
+
+    #[structopt(long)]
+    /// Keep the local system clipboard in sync with the server one
+    pub sync_clipboard: bool,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Record every sent and received packet into a replayable trace file
+    pub trace: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Capture every sent and received packet into a pcapng file
+    pub pcap: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -42,6 +58,7 @@ pub struct PFPConfig {
     pub friendly_text: String,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone)]
 pub enum AuthConfig {
     PFP(PFPConfig),
@@ -127,20 +144,12 @@ impl FromStr for ChatConfig {
     }
 }
 
-pub fn configure_capabilities() -> Vec<NowCapset<'static>> {
+pub fn configure_capabilities() -> CapabilitiesBuilder {
     use wayk_proto::message::connection_sequence::capabilities::*;
-    use wayk_proto::message::now_messages::MouseMode;
-
-    vec![
-        NowCapset::Transport(TransportCapset::default()),
-        NowCapset::Update(UpdateCapset::new_with_supported_codecs(vec![
-            NowCodecDef::new_with_flags(Codec::JPEG, 0x0000_0001),
-        ])),
-        NowCapset::License(LicenseCapset {
-            flags: LicenseCapsetFlags::new_empty(),
-        }),
-        NowCapset::Mouse(MouseCapset::new(MouseMode::Primary, MouseCapsetFlags::new_empty())),
-    ]
+
+    CapabilitiesBuilder::new()
+        .codec_defs(vec![NowCodecDef::new_with_flags(Codec::JPEG, 0x0000_0001)])
+        .os_info_from_env()
 }
 
 pub fn configure_available_auth_types() -> Vec<AuthType> {
@@ -1,27 +1,32 @@
 mod authentication;
+mod capture;
+mod clipboard;
 mod config;
 
 use crate::authentication::AuthenticateSM;
+use crate::capture::Capture;
+use crate::clipboard::ClipboardBridge;
 use crate::config::{configure_available_auth_types, configure_capabilities, configure_channels_to_open};
 use config::Cli;
 use std::convert::TryFrom;
-use std::io::{Read, Write};
-use std::net::{Shutdown, TcpStream};
+use std::io::{self, BufRead};
+use std::net::TcpStream;
 use std::str::FromStr;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
-use wayk_proto::channels_manager::ChannelsManager;
-use wayk_proto::header::AbstractNowHeader;
+use wayk_client::{ClientError, ClientRunner, EventHandler};
+use wayk_proto::channels_manager::{ChannelCommandSender, ChannelsManager};
 use wayk_proto::message::{
-    ClipboardFormatDef, NowChatTextMsg, NowClipboardControlRspMsg, NowClipboardFormatDataReqMsg,
-    NowClipboardFormatDataRspMsgOwned, NowClipboardFormatListReqMsg, NowString256, NowString65535,
+    ChannelName, ClipboardFormatDef, NowChatTextMsg, NowClipboardControlRspMsg, NowClipboardFormatDataReqMsg,
+    NowClipboardFormatDataRspMsg, NowClipboardFormatDataRspMsgOwned, NowClipboardFormatListReqMsg, NowString256,
+    NowString65535,
 };
-use wayk_proto::packet::{NowPacket, NowPacketAccumulator};
-use wayk_proto::serialization::Encode;
+use wayk_proto::packet::NowPacket;
 use wayk_proto::sharee::Sharee;
 use wayk_proto::sm::{
     ChannelResponses, ChatChannelCallbackTrait, ChatChannelSM, ChatData, ClientConnectionSeqSM,
-    ClipboardChannelCallbackTrait, ClipboardChannelSM, ClipboardData, SMData, SMEvent,
+    ClipboardChannelCallbackTrait, ClipboardChannelSM, ClipboardData, ClipboardHandle, SMData, SMEvent,
 };
 
 fn main() {
@@ -33,45 +38,40 @@ fn main() {
     log::trace!("{:?}", args);
 
     match TcpStream::connect(args.addr) {
-        Ok(mut stream) => {
+        Ok(stream) => {
             log::info!("Connected to server at {}", stream.peer_addr().unwrap());
 
-            let mut sharee = build_sharee(&args);
-            let mut acc = NowPacketAccumulator::new();
-            let mut buf = [0; 512];
-            'main: loop {
-                while sharee.waiting_for_packet() {
-                    if let Some(packet) = acc.next_packet(&sharee.get_channels_ctx()) {
-                        match packet {
-                            Ok(packet) => {
-                                log::debug!("Received {:?} packet.", packet.header.body_type());
-                                handle_events(&mut stream, sharee.update_with_body(&packet.body));
-                            }
-                            Err(err) => log::error!("Invalid packet: {}", err),
-                        }
-                        acc.purge_old_packets();
-
-                        if sharee.is_terminated() {
-                            break 'main;
-                        }
-                    } else {
-                        let n = stream.read(&mut buf).unwrap();
-                        acc.accumulate(&buf[..n]);
-                    }
-                }
-
-                while !sharee.waiting_for_packet() {
-                    handle_events(&mut stream, sharee.update_without_body());
+            let clipboard_bridge = if args.sync_clipboard {
+                Some(ClipboardBridge::new())
+            } else {
+                None
+            };
 
-                    if sharee.is_terminated() {
-                        break 'main;
-                    }
-                }
+            let (sharee, clipboard_handle) = build_sharee(&args, clipboard_bridge.clone());
+            let mut runner = ClientRunner::new(stream, sharee);
+            // wake up regularly to fire timers and run commands queued in the meantime
+            runner
+                .transport()
+                .set_read_timeout(Some(Duration::from_millis(100)))
+                .unwrap();
+            if args.chat {
+                let chat_sender = runner.sharee().channel_command_sender(&ChannelName::Chat).unwrap();
+                spawn_chat_input(chat_sender);
+            }
+            if let Some(bridge) = &clipboard_bridge {
+                bridge.spawn_watcher(clipboard_handle);
             }
 
-            stream.shutdown(Shutdown::Both).unwrap();
+            let mut capture = Capture::new(&args);
+            let result = runner.run(EventLogger { capture: &mut capture });
+
+            capture.finish();
 
-            log::info!("Connection with server closed.");
+            match result {
+                Ok(()) => log::info!("Connection with server closed."),
+                Err(ClientError::Proto(e)) => panic!("Fatal error: {}", e),
+                Err(e) => log::error!("Connection with server lost: {}", e),
+            }
         }
         Err(err) => log::error!("Couldn't connect to server: {}", err),
     }
@@ -103,7 +103,10 @@ fn configure_logger(args: &Cli) {
     .unwrap();
 }
 
-fn build_sharee(args: &Cli) -> Sharee<ClientConnectionSeqSM> {
+fn build_sharee(
+    args: &Cli,
+    clipboard_bridge: Option<ClipboardBridge>,
+) -> (Sharee<ClientConnectionSeqSM>, ClipboardHandle) {
     // connection sequence
     let connection_seq = ClientConnectionSeqSM::new(AuthenticateSM::new(args.auth.clone()));
 
@@ -125,6 +128,7 @@ fn build_sharee(args: &Cli) -> Sharee<ClientConnectionSeqSM> {
         Box::new(get_current_timestamp),
         ChatCallback {
             on_sync_message: args.on_sync_message.clone(),
+            interactive: args.chat,
         },
     );
 
@@ -134,8 +138,10 @@ fn build_sharee(args: &Cli) -> Sharee<ClientConnectionSeqSM> {
         clipboard_data,
         ClipboardCallback {
             on_ready_message: args.on_clipboard_ready.clone(),
+            bridge: clipboard_bridge,
         },
     );
+    let clipboard_handle = clipboard_channel_sm.handle();
 
     // channel manager
     let channels_manager = ChannelsManager::new()
@@ -143,37 +149,55 @@ fn build_sharee(args: &Cli) -> Sharee<ClientConnectionSeqSM> {
         .with_sm(clipboard_channel_sm);
 
     // finally, build the sharee
-    Sharee::builder(connection_seq)
+    let sharee = Sharee::builder(connection_seq)
         .supported_auths(configure_available_auth_types())
         .capabilities(configure_capabilities())
         .channels_to_open(configure_channels_to_open())
         .channels_manager(channels_manager)
+        .response_timeout(Duration::from_secs(30))
         .build()
+        .unwrap_or_else(|e| panic!("{}", e));
+
+    (sharee, clipboard_handle)
 }
 
-fn send_packet<W: Write>(writer: &mut W, packet: NowPacket<'_>) {
-    writer.write_all(&packet.encode().unwrap()).unwrap();
-    log::debug!("Sent {:?} packet.", packet.header.body_type());
+struct EventLogger<'a> {
+    capture: &'a mut Capture,
 }
 
-fn handle_events<W: Write>(writer: &mut W, events: Vec<SMEvent<'_>>) {
-    for ev in events {
-        match ev {
+impl EventHandler for EventLogger<'_> {
+    fn on_event(&mut self, event: SMEvent<'_>) {
+        match event {
             SMEvent::StateTransition(s) => log::info!("State transition: {:?}", s),
-            SMEvent::PacketToSend(rsp) => send_packet(writer, rsp),
+            SMEvent::PacketToSend(_) => {}
+            SMEvent::Connection(e) => log::info!("Connection: {:?}", e),
+            SMEvent::Chat(e) => log::info!("Chat: {:?}", e),
+            SMEvent::Clipboard(e) => log::info!("Clipboard: {:?}", e),
+            SMEvent::Access(e) => log::info!("Access control: {:?}", e),
+            SMEvent::System(e) => log::info!("System: {:?}", e),
+            SMEvent::Surface(e) => log::info!("Surface: {:?}", e),
+            SMEvent::Display(_) => {}
             SMEvent::Data(e) => log::info!("Proto data: {:?}", e),
             SMEvent::Warn(e) => log::warn!("Sharee warning: {}", e),
             SMEvent::Error(e) => log::error!("Sharee error: {}", e),
-            SMEvent::Fatal(e) => {
-                log::error!("Sharee FATAL error: {}", e);
-                panic!("Fatal error: {}", e);
-            },
+            SMEvent::Fatal(e) => log::error!("Sharee FATAL error: {}", e),
+            SMEvent::SetTimer(_) | SMEvent::CancelTimer(_) => {}
+            _ => {}
         }
     }
+
+    fn on_packet_received(&mut self, _: &NowPacket<'_>, bytes: &[u8]) {
+        self.capture.on_received(bytes);
+    }
+
+    fn on_packet_sent(&mut self, _: &NowPacket<'_>, bytes: &[u8]) {
+        self.capture.on_sent(bytes);
+    }
 }
 
 struct ClipboardCallback {
     on_ready_message: Option<String>,
+    bridge: Option<ClipboardBridge>,
 }
 
 impl ClipboardChannelCallbackTrait for ClipboardCallback {
@@ -184,6 +208,11 @@ impl ClipboardChannelCallbackTrait for ClipboardCallback {
         to_send: &mut ChannelResponses<'_>,
         _: &NowClipboardControlRspMsg,
     ) {
+        if let Some(bridge) = &self.bridge {
+            bridge.on_enabled(to_send);
+            return;
+        }
+
         to_send.push(NowClipboardFormatListReqMsg::new_with_formats(
             clipboard_data.next_sequence_id(),
             vec![ClipboardFormatDef::new(
@@ -198,8 +227,13 @@ impl ClipboardChannelCallbackTrait for ClipboardCallback {
         clipboard_data: &mut ClipboardData,
         _: &mut SMData,
         to_send: &mut ChannelResponses<'_>,
-        _: &NowClipboardFormatDataReqMsg,
+        msg: &NowClipboardFormatDataReqMsg,
     ) {
+        if let Some(bridge) = &self.bridge {
+            bridge.on_format_data_req(to_send, msg);
+            return;
+        }
+
         if let Some(data) = &self.on_ready_message {
             if clipboard_data.is_owner() {
                 to_send.push(NowClipboardFormatDataRspMsgOwned::new_with_format_data(
@@ -212,17 +246,43 @@ impl ClipboardChannelCallbackTrait for ClipboardCallback {
             }
         }
     }
+
+    fn on_format_data_rsp(
+        &mut self,
+        _: &mut ClipboardData,
+        _: &mut SMData,
+        _: &mut ChannelResponses<'_>,
+        msg: &NowClipboardFormatDataRspMsg,
+    ) {
+        if let Some(bridge) = &self.bridge {
+            bridge.on_format_data_rsp(msg);
+        }
+    }
+
+    fn on_auto_fetch(
+        &mut self,
+        _: &mut ClipboardData,
+        _: &mut SMData,
+        to_send: &mut ChannelResponses<'_>,
+        msg: &NowClipboardFormatListReqMsg,
+    ) {
+        if let Some(bridge) = &self.bridge {
+            bridge.on_peer_formats(to_send, msg);
+        }
+    }
 }
 
 struct ChatCallback {
     on_sync_message: Option<String>,
+    interactive: bool,
 }
 
 impl ChatChannelCallbackTrait for ChatCallback {
     fn on_message(&mut self, chat_data: &mut ChatData, _: &mut ChannelResponses<'_>, text_msg: &NowChatTextMsg) {
         println!(
-            "|Chat| Message from {}: {}",
+            "|Chat| Message from {} (at {}): {}",
             chat_data.distant_friendly_name,
+            chat_data.normalize_timestamp(text_msg.timestamp),
             text_msg.text.as_str()
         );
     }
@@ -240,6 +300,50 @@ impl ChatChannelCallbackTrait for ChatCallback {
             }
         }
     }
+
+    fn on_typing_changed(&mut self, chat_data: &mut ChatData, _: &mut ChannelResponses<'_>, typing: bool) {
+        if self.interactive {
+            if typing {
+                println!("|Chat| {} is typing...", chat_data.distant_friendly_name);
+            } else {
+                println!("|Chat| {} stopped typing.", chat_data.distant_friendly_name);
+            }
+        }
+    }
+
+    fn on_read_up_to(&mut self, chat_data: &mut ChatData, _: &mut ChannelResponses<'_>, timestamp: u32) {
+        if self.interactive {
+            println!(
+                "|Chat| {} read messages up to {}",
+                chat_data.distant_friendly_name, timestamp
+            );
+        }
+    }
+}
+
+/// Reads chat messages from stdin on a separate thread, they are sent by the runner thread.
+fn spawn_chat_input(chat: ChannelCommandSender<NowChatTextMsg>) {
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    log::error!("Couldn't read chat input: {}", e);
+                    break;
+                }
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match NowString65535::try_from(line) {
+                Ok(text) => chat.send(NowChatTextMsg::new(get_current_timestamp(), 0, text)),
+                Err(e) => log::warn!("{}", e),
+            }
+        }
+    });
 }
 
 fn get_current_timestamp() -> u32 {
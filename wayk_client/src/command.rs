@@ -0,0 +1,42 @@
+use crate::runner::ClientError;
+use std::io;
+use std::sync::mpsc;
+use wayk_proto::sharee::Sharee;
+use wayk_proto::sm::SMEvent;
+
+/// Operation queued from another thread and run against the sharee by the runner.
+///
+/// Returned events are processed like the ones produced by the sharee itself.
+pub type Command<ConnectionSeq> = Box<dyn FnOnce(&mut Sharee<ConnectionSeq>) -> Vec<SMEvent<'static>> + Send>;
+
+/// Queues commands to a `ClientRunner`, typically from an input thread.
+///
+/// Commands are run between two reads, so the transport should have a read timeout
+/// for them to be processed while the peer is silent.
+pub struct CommandSender<ConnectionSeq> {
+    sender: mpsc::Sender<Command<ConnectionSeq>>,
+}
+
+impl<ConnectionSeq> Clone for CommandSender<ConnectionSeq> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<ConnectionSeq> CommandSender<ConnectionSeq> {
+    pub(crate) fn new(sender: mpsc::Sender<Command<ConnectionSeq>>) -> Self {
+        Self { sender }
+    }
+
+    /// Fails once the runner is dropped.
+    pub fn send<F>(&self, command: F) -> Result<(), ClientError>
+    where
+        F: FnOnce(&mut Sharee<ConnectionSeq>) -> Vec<SMEvent<'static>> + Send + 'static,
+    {
+        self.sender
+            .send(Box::new(command))
+            .map_err(|_| ClientError::Io(io::Error::new(io::ErrorKind::BrokenPipe, "client runner is gone")))
+    }
+}
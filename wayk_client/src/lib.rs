@@ -0,0 +1,14 @@
+//! Blocking client runner for `wayk_proto`.
+//!
+//! `ClientRunner` drives a `Sharee` over any `Transport`: incoming bytes are accumulated into
+//! packets and fed to the sharee, outgoing packets are written back, and every other event is
+//! forwarded to an `EventHandler`. Other threads may act on the running sharee through a
+//! `CommandSender`.
+
+mod command;
+mod runner;
+pub mod transport;
+
+pub use command::*;
+pub use runner::*;
+pub use transport::*;
@@ -0,0 +1,468 @@
+use crate::command::{Command, CommandSender};
+use crate::transport::Transport;
+use std::fmt;
+use std::io;
+use std::sync::mpsc;
+use std::time::Duration;
+use wayk_proto::error::ProtoError;
+use wayk_proto::header::AbstractNowHeader;
+use wayk_proto::packet::{DecodeFailure, NowPacket, NowPacketAccumulator};
+use wayk_proto::sharee::Sharee;
+use wayk_proto::sm::{ConnectionSM, SMEvent};
+use wayk_proto::timer::{Clock, SystemClock, TimerAction, TimerQueue};
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// Longest wait for a queued command when the transport has nothing to read.
+const IDLE_WAIT: Duration = Duration::from_millis(10);
+
+/// Receives the events produced while running the sharee.
+///
+/// Any `FnMut(SMEvent<'_>)` closure is an event handler.
+pub trait EventHandler {
+    /// Called for every event but `PacketToSend` and timer events, which are handled by the runner.
+    fn on_event(&mut self, event: SMEvent<'_>);
+
+    /// `bytes` is the packet as read from the transport, before any decompression.
+    fn on_packet_received(&mut self, packet: &NowPacket<'_>, bytes: &[u8]) {
+        #![allow(unused_variables)]
+    }
+
+    /// `bytes` is the encoded packet, as written to the transport.
+    fn on_packet_sent(&mut self, packet: &NowPacket<'_>, bytes: &[u8]) {
+        #![allow(unused_variables)]
+    }
+
+    /// Called in lenient mode for each packet skipped because its body couldn't be decoded.
+    fn on_decode_failure(&mut self, failure: DecodeFailure) {
+        log::error!(
+            "Skipped undecodable {:?} packet: {} (raw: {:02x?})",
+            failure.header.body_type(),
+            failure.error,
+            failure.raw
+        );
+    }
+}
+
+impl<F> EventHandler for F
+where
+    F: FnMut(SMEvent<'_>),
+{
+    fn on_event(&mut self, event: SMEvent<'_>) {
+        self(event)
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// Either a fatal sharee error or a packet that couldn't be processed.
+    Proto(ProtoError),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "transport error: {}", e),
+            ClientError::Proto(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            ClientError::Proto(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ProtoError> for ClientError {
+    fn from(e: ProtoError) -> Self {
+        Self::Proto(e)
+    }
+}
+
+/// Runs a sharee over a blocking transport until the session is terminated.
+pub struct ClientRunner<T, ConnectionSeq> {
+    transport: T,
+    sharee: Sharee<ConnectionSeq>,
+    accumulator: NowPacketAccumulator<'static>,
+    /// Reused to encode outgoing packets.
+    send_buf: Vec<u8>,
+    commands: mpsc::Receiver<Command<ConnectionSeq>>,
+    command_sender: mpsc::Sender<Command<ConnectionSeq>>,
+    timers: TimerQueue,
+    clock: SystemClock,
+}
+
+impl<T, ConnectionSeq> ClientRunner<T, ConnectionSeq>
+where
+    T: Transport,
+    ConnectionSeq: ConnectionSM,
+{
+    /// The accumulator is lenient: packets with an undecodable body are skipped and reported
+    /// through `EventHandler::on_decode_failure`.
+    pub fn new(transport: T, sharee: Sharee<ConnectionSeq>) -> Self {
+        let mut accumulator = NowPacketAccumulator::new();
+        accumulator.set_lenient(true);
+        let (command_sender, commands) = mpsc::channel();
+        Self {
+            transport,
+            sharee,
+            accumulator,
+            send_buf: Vec::new(),
+            commands,
+            command_sender,
+            timers: TimerQueue::new(),
+            clock: SystemClock::new(),
+        }
+    }
+
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.accumulator.set_lenient(lenient);
+        self
+    }
+
+    /// Packets longer than this are fatal errors, see `NowPacketAccumulator::set_max_packet_len`.
+    pub fn max_packet_len(mut self, max_packet_len: usize) -> Self {
+        self.accumulator.set_max_packet_len(max_packet_len);
+        self
+    }
+
+    /// Packet size limit and counters (bytes buffered, packets parsed).
+    pub fn accumulator(&self) -> &NowPacketAccumulator<'static> {
+        &self.accumulator
+    }
+
+    pub fn sharee(&self) -> &Sharee<ConnectionSeq> {
+        &self.sharee
+    }
+
+    pub fn sharee_mut(&mut self) -> &mut Sharee<ConnectionSeq> {
+        &mut self.sharee
+    }
+
+    /// Handle to queue commands while the runner is running.
+    pub fn command_sender(&self) -> CommandSender<ConnectionSeq> {
+        CommandSender::new(self.command_sender.clone())
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn into_inner(self) -> (T, Sharee<ConnectionSeq>) {
+        (self.transport, self.sharee)
+    }
+
+    /// Runs the sharee until it is terminated, then shuts the transport down.
+    ///
+    /// Returns early on transport errors, on fatal sharee errors and if the peer closes the
+    /// connection before the session is terminated. Read timeouts aren't errors, they only give
+    /// queued commands and expired timers a chance to run: set one on the transport if the sharee
+    /// requests timers, otherwise they only fire once the peer sends something. Non-blocking
+    /// transports are supported as well, the runner waits for commands while there is nothing to
+    /// read instead of polling the transport in a loop.
+    pub fn run<H: EventHandler>(&mut self, mut handler: H) -> Result<(), ClientError> {
+        let mut buf = [0; READ_BUFFER_SIZE];
+
+        while self.sharee.is_running() {
+            while let Ok(command) = self.commands.try_recv() {
+                let events = command(&mut self.sharee);
+                self.h_dispatch(&mut handler, events)?;
+            }
+
+            for id in self.timers.pop_expired(self.clock.now()) {
+                let events = self.sharee.on_timer_expired(id);
+                self.h_dispatch(&mut handler, events)?;
+            }
+
+            if !self.sharee.is_running() {
+                break;
+            }
+
+            if !self.sharee.waiting_for_packet() {
+                let events = self.sharee.update_without_body();
+                self.h_dispatch(&mut handler, events)?;
+                continue;
+            }
+
+            let next = self.accumulator.next_packet_with_raw(&self.sharee.decode_ctx());
+            match next {
+                Some(Err(e)) => {
+                    self.sharee.report_decode_error(&e);
+                    return Err(e.into());
+                }
+                Some(Ok((packet, bytes))) => {
+                    log::debug!("Received {:?} packet.", packet.header.body_type());
+                    handler.on_packet_received(&packet, bytes);
+                    let events = self.sharee.update_with_packet(&packet);
+                    dispatch_events(
+                        &mut self.transport,
+                        &mut self.send_buf,
+                        (&mut self.timers, &self.clock),
+                        &mut handler,
+                        events,
+                    )?;
+                }
+                None => {
+                    let n = match self.transport.read(&mut buf) {
+                        Ok(0) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                "connection closed by peer before session termination",
+                            )
+                            .into())
+                        }
+                        Ok(n) => n,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                            self.h_wait_command(&mut handler)?;
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                    self.accumulator.accumulate(&buf[..n]);
+                }
+            }
+
+            for failure in self.accumulator.take_decode_failures() {
+                self.sharee.report_decode_error(&failure.error);
+                handler.on_decode_failure(failure);
+            }
+            self.accumulator.purge_old_packets();
+        }
+
+        self.transport.shutdown()?;
+
+        Ok(())
+    }
+
+    /// Parks until a command is queued, the next timer expires or `IDLE_WAIT` elapses.
+    fn h_wait_command<H: EventHandler>(&mut self, handler: &mut H) -> Result<(), ClientError> {
+        let timeout = match self.timers.next_deadline() {
+            Some(deadline) => deadline.saturating_sub(self.clock.now()).min(IDLE_WAIT),
+            None => IDLE_WAIT,
+        };
+        match self.commands.recv_timeout(timeout) {
+            Ok(command) => {
+                let events = command(&mut self.sharee);
+                self.h_dispatch(handler, events)
+            }
+            // the runner holds a sender, the channel can't be disconnected
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn h_dispatch<H: EventHandler>(&mut self, handler: &mut H, events: Vec<SMEvent<'_>>) -> Result<(), ClientError> {
+        dispatch_events(
+            &mut self.transport,
+            &mut self.send_buf,
+            (&mut self.timers, &self.clock),
+            handler,
+            events,
+        )
+    }
+}
+
+fn dispatch_events<T: Transport, H: EventHandler>(
+    transport: &mut T,
+    send_buf: &mut Vec<u8>,
+    (timers, clock): (&mut TimerQueue, &SystemClock),
+    handler: &mut H,
+    events: Vec<SMEvent<'_>>,
+) -> Result<(), ClientError> {
+    for event in events {
+        match event {
+            SMEvent::SetTimer(request) => timers.apply(TimerAction::Set(request), clock.now()),
+            SMEvent::CancelTimer(id) => timers.cancel(id),
+            SMEvent::PacketToSend(packet) => {
+                let bytes = packet.encode_with_scratch(send_buf)?;
+                transport.write_all(bytes)?;
+                log::debug!("Sent {:?} packet.", packet.header.body_type());
+                handler.on_packet_sent(&packet, bytes);
+            }
+            SMEvent::Fatal(e) => {
+                handler.on_event(SMEvent::Fatal(e.clone()));
+                return Err(e.into());
+            }
+            event => handler.on_event(event),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use wayk_proto::message::{NowActivateMsg, NowTerminateMsg};
+    use wayk_proto::serialization::Encode;
+    use wayk_proto::sharee::ShareeState;
+    use wayk_proto::sm::DummyConnectionSM;
+
+    struct MemoryTransport {
+        incoming: io::Cursor<Vec<u8>>,
+        outgoing: Vec<u8>,
+        reads: usize,
+        shut_down: bool,
+        /// Time out reads instead of reporting the end of the stream, as a socket with a read timeout.
+        time_out: bool,
+    }
+
+    impl MemoryTransport {
+        fn new(incoming: Vec<u8>) -> Self {
+            Self {
+                incoming: io::Cursor::new(incoming),
+                outgoing: Vec::new(),
+                reads: 0,
+                shut_down: false,
+                time_out: false,
+            }
+        }
+    }
+
+    impl Read for MemoryTransport {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            // one byte at a time to exercise packet accumulation
+            let len = buf.len().min(1);
+            match self.incoming.read(&mut buf[..len])? {
+                0 if self.time_out => Err(io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for MemoryTransport {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for MemoryTransport {
+        fn shutdown(&mut self) -> io::Result<()> {
+            self.shut_down = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn run_until_terminated() {
+        let mut incoming = NowPacket::from_message(NowActivateMsg::default()).encode().unwrap();
+        incoming.extend(NowPacket::from_message(NowTerminateMsg::default()).encode().unwrap());
+        let sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+
+        let mut runner = ClientRunner::new(MemoryTransport::new(incoming), sharee);
+        let mut received = 0;
+        let mut transitions = 0;
+        runner
+            .run(|event: SMEvent<'_>| {
+                if let SMEvent::StateTransition(_) = event {
+                    transitions += 1;
+                }
+                received += 1;
+            })
+            .unwrap();
+
+        assert!(received > 0);
+        assert!(transitions > 0);
+        let (transport, sharee) = runner.into_inner();
+        assert_eq!(sharee.get_state(), ShareeState::Final);
+        assert!(transport.shut_down);
+    }
+
+    #[test]
+    fn queued_commands() {
+        let incoming = NowPacket::from_message(NowTerminateMsg::default()).encode().unwrap();
+        let sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+        let mut runner = ClientRunner::new(MemoryTransport::new(incoming), sharee);
+
+        let commands = runner.command_sender();
+        commands
+            .send(|_| {
+                vec![SMEvent::PacketToSend(
+                    NowPacket::from_message(NowActivateMsg::default()),
+                )]
+            })
+            .unwrap();
+        runner.run(|_: SMEvent<'_>| {}).unwrap();
+
+        let expected = NowPacket::from_message(NowActivateMsg::default()).encode().unwrap();
+        let (transport, _) = runner.into_inner();
+        assert!(transport.outgoing.starts_with(&expected));
+        assert!(commands.send(|_| Vec::new()).is_err());
+    }
+
+    #[test]
+    fn response_timeout() {
+        use wayk_proto::sm::ClientConnectionSeqSM;
+
+        let sharee = Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM))
+            .response_timeout(Duration::from_millis(10))
+            .build_unchecked();
+        let mut transport = MemoryTransport::new(Vec::new());
+        transport.time_out = true;
+        let mut runner = ClientRunner::new(transport, sharee);
+
+        let mut timer_events = 0;
+        let res = runner.run(|event: SMEvent<'_>| {
+            if let SMEvent::SetTimer(_) | SMEvent::CancelTimer(_) = event {
+                timer_events += 1;
+            }
+        });
+        assert!(matches!(res, Err(ClientError::Proto(_))));
+        assert_eq!(timer_events, 0);
+        assert_eq!(runner.sharee().get_state(), ShareeState::Final);
+    }
+
+    #[test]
+    fn idle_waits_for_commands() {
+        use std::time::Instant;
+        use wayk_proto::error::ProtoErrorKind;
+
+        let sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+        let mut transport = MemoryTransport::new(Vec::new());
+        transport.time_out = true;
+        let mut runner = ClientRunner::new(transport, sharee);
+
+        let commands = runner.command_sender();
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            commands
+                .send(|_| vec![SMEvent::fatal(ProtoErrorKind::Sharee(ShareeState::Active), "stop")])
+                .unwrap();
+        });
+
+        let start = Instant::now();
+        assert!(matches!(runner.run(|_: SMEvent<'_>| {}), Err(ClientError::Proto(_))));
+        sender.join().unwrap();
+
+        // roughly one read per `IDLE_WAIT` instead of a busy loop
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(runner.transport().reads < 100, "{} reads", runner.transport().reads);
+    }
+
+    #[test]
+    fn connection_closed_by_peer() {
+        let sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+        let mut runner = ClientRunner::new(MemoryTransport::new(Vec::new()), sharee);
+        match runner.run(|_: SMEvent<'_>| {}) {
+            Err(ClientError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            res => panic!("expected an unexpected EOF error and got {:?}", res),
+        }
+        assert!(!runner.transport().shut_down);
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Byte stream the runner reads packets from and writes packets to.
+pub trait Transport: Read + Write {
+    /// Closes the connection once the sharee is terminated.
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Transport for TcpStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn shutdown(&mut self) -> io::Result<()> {
+        (**self).shutdown()
+    }
+}
+
+/// Two connected in-memory transports: bytes written to one end are read from the other.
+///
+/// Useful to run a sharee against a peer in the same process, e.g. in tests, without sockets.
+pub fn loopback() -> (LoopbackTransport, LoopbackTransport) {
+    let a_to_b = Arc::new(Pipe::default());
+    let b_to_a = Arc::new(Pipe::default());
+    (
+        LoopbackTransport {
+            incoming: b_to_a.clone(),
+            outgoing: a_to_b.clone(),
+            read_timeout: None,
+        },
+        LoopbackTransport {
+            incoming: a_to_b,
+            outgoing: b_to_a,
+            read_timeout: None,
+        },
+    )
+}
+
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    readable: Condvar,
+}
+
+#[derive(Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Pipe {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.readable.notify_all();
+    }
+}
+
+/// End of an in-memory connection, see `loopback`.
+///
+/// Reads block until bytes are available, as with a socket. Once an end is shut down or dropped,
+/// its peer reads the bytes left and then the end of the stream.
+pub struct LoopbackTransport {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Option<Duration>,
+}
+
+impl LoopbackTransport {
+    /// Reads time out with `io::ErrorKind::TimedOut` instead of blocking forever.
+    pub fn set_read_timeout(&mut self, read_timeout: Option<Duration>) {
+        self.read_timeout = read_timeout;
+    }
+}
+
+impl Read for LoopbackTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.incoming.state.lock().unwrap();
+        while state.bytes.is_empty() && !state.closed {
+            state = match self.read_timeout {
+                Some(timeout) => {
+                    let (state, result) = self.incoming.readable.wait_timeout(state, timeout).unwrap();
+                    if result.timed_out() && state.bytes.is_empty() {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    state
+                }
+                None => self.incoming.readable.wait(state).unwrap(),
+            };
+        }
+
+        let len = buf.len().min(state.bytes.len());
+        for (dst, src) in buf.iter_mut().zip(state.bytes.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for LoopbackTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "loopback transport closed"));
+        }
+        state.bytes.extend(buf);
+        self.outgoing.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.outgoing.close();
+        self.incoming.close();
+        Ok(())
+    }
+}
+
+impl Drop for LoopbackTransport {
+    fn drop(&mut self) {
+        self.outgoing.close();
+        self.incoming.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::ClientRunner;
+    use std::thread;
+    use wayk_proto::channels_manager::ChannelsManager;
+    use wayk_proto::message::{
+        AuthType, ChannelName, NegotiateFlags, NowAssociateMsg, NowBody, NowCapabilitiesMsg, NowCapset, NowChannelDef,
+        NowChannelMsg, NowChatTextMsg, NowHandshakeMsg, NowMessage, NowNegotiateMsg, NowTerminateMsg, VirtChannelsCtx,
+    };
+    use wayk_proto::packet::NowPacket;
+    use wayk_proto::serialization::Encode;
+    use wayk_proto::sharee::{Sharee, ShareeState};
+    use wayk_proto::sm::{
+        ChannelResponses, ChatChannelCallbackTrait, ChatChannelSM, ChatChannelServerSM, ChatData, ChatEvent,
+        ClientConnectionSeqSM, DummyChatChannelCallback, DummyConnectionSM, SMData, SMEvent, SMEvents,
+        VirtualChannelSM,
+    };
+
+    #[test]
+    fn loopback_read_write() {
+        let (mut a, mut b) = loopback();
+        a.write_all(b"hello").unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(b.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+
+        b.set_read_timeout(Some(Duration::from_millis(1)));
+        assert_eq!(b.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        a.write_all(b"bye").unwrap();
+        drop(a);
+        assert_eq!(b.read(&mut buf).unwrap(), 3);
+        assert_eq!(b.read(&mut buf).unwrap(), 0);
+        assert_eq!(b.write(b"?").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    /// Answers a text message with another one.
+    struct ReplyCallback;
+
+    impl ChatChannelCallbackTrait for ReplyCallback {
+        fn on_message(&mut self, chat_data: &mut ChatData, to_send: &mut ChannelResponses<'_>, _: &NowChatTextMsg) {
+            to_send.push(chat_data.conversation.send_text(0, 0, "hi server").unwrap());
+        }
+    }
+
+    const CHAT_CHANNEL_ID: u8 = 0;
+
+    /// Server end of the connection sequence, then chats through `ChatChannelServerSM` and
+    /// terminates the session once the client replied. Returns the texts received.
+    fn serve(mut transport: LoopbackTransport) -> Vec<String> {
+        let mut channels = VirtChannelsCtx::new();
+        channels.insert(CHAT_CHANNEL_ID, ChannelName::Chat);
+        let mut chat = ChatChannelServerSM::new(ChatData::new(), Box::new(|| 0), DummyChatChannelCallback);
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        let mut received = Vec::new();
+
+        let send = |transport: &mut LoopbackTransport, packet: NowPacket<'_>| {
+            transport.write_all(&packet.encode().unwrap()).unwrap()
+        };
+        let mut buf = Vec::new();
+        loop {
+            let packet = NowPacket::read_from(&mut transport, &mut buf, &channels).unwrap();
+            match &packet.body {
+                NowBody::Message(NowMessage::Handshake(_)) => {
+                    send(&mut transport, NowHandshakeMsg::new_success().into());
+                }
+                NowBody::Message(NowMessage::Negotiate(_)) => {
+                    let auths = vec![AuthType::None];
+                    send(
+                        &mut transport,
+                        NowNegotiateMsg::new_with_auth_list(NegotiateFlags::new_empty(), auths).into(),
+                    );
+                    send(&mut transport, NowAssociateMsg::new_info().into());
+                }
+                NowBody::Message(NowMessage::Associate(_)) => {
+                    send(&mut transport, NowAssociateMsg::new_response().into());
+                    let capabilities = NowCapabilitiesMsg::new_with_capabilities(Vec::<NowCapset<'_>>::new());
+                    send(&mut transport, capabilities.into());
+                }
+                NowBody::Message(NowMessage::Channel(msg)) => {
+                    let defs = vec![NowChannelDef::new(ChannelName::Chat)];
+                    let subtype = msg.subtype.response_type().unwrap();
+                    send(&mut transport, NowChannelMsg::new(subtype, defs).into());
+                }
+                NowBody::Message(NowMessage::Capabilities(_)) | NowBody::Message(NowMessage::Activate(_)) => {}
+                NowBody::Message(NowMessage::Terminate(_)) => break,
+                NowBody::VirtualChannel(msg) => {
+                    let mut events = SMEvents::new();
+                    let mut to_send = ChannelResponses::new();
+                    chat.update_with_chan_msg(&mut data, &mut events, &mut to_send, msg);
+                    for (_, rsp) in to_send.unpack() {
+                        send(&mut transport, NowPacket::from_virt_channel(rsp, CHAT_CHANNEL_ID));
+                    }
+                    for event in events.unpack() {
+                        match event {
+                            SMEvent::Chat(ChatEvent::Synced { .. }) => {
+                                let text = NowChatTextMsg::new(0, 1, "hello client".parse().unwrap());
+                                send(&mut transport, NowPacket::from_virt_channel(text, CHAT_CHANNEL_ID));
+                            }
+                            SMEvent::Chat(ChatEvent::Message { text, .. }) => {
+                                received.push(text);
+                                send(&mut transport, NowTerminateMsg::default().into());
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                body => panic!("unexpected {:?}", body),
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn end_to_end() {
+        let (client_end, server_end) = loopback();
+        let server = thread::spawn(move || serve(server_end));
+
+        let sharee = Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM))
+            .supported_auths(vec![AuthType::None])
+            .channels_to_open(vec![ChannelName::Chat])
+            .channels_manager(ChannelsManager::new().with_sm(ChatChannelSM::new(
+                ChatData::new(),
+                Box::new(|| 0),
+                ReplyCallback,
+            )))
+            .build()
+            .unwrap();
+        let mut runner = ClientRunner::new(client_end, sharee);
+
+        let mut texts = Vec::new();
+        runner
+            .run(|event: SMEvent<'_>| {
+                if let SMEvent::Chat(ChatEvent::Message { text, .. }) = event {
+                    texts.push(text);
+                }
+            })
+            .unwrap();
+
+        assert_eq!(runner.sharee().get_state(), ShareeState::Final);
+        assert_eq!(texts, vec!["hello client".to_owned()]);
+        assert_eq!(server.join().unwrap(), vec!["hi server".to_owned()]);
+    }
+}
@@ -0,0 +1,124 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::str::FromStr;
+use wayk_proto::container::Vec16;
+use wayk_proto::header::NowHeader;
+use wayk_proto::message::{
+    AccessCapset, AccessControlCode, AccessControlDef, Codec, EventMouseFlags, InputEvent, MouseCapset,
+    MouseCapsetFlags, MouseMode, NetworkCapset, NetworkCapsetFlags, NowCapabilitiesMsg, NowCapset,
+    NowInputEventKeyboard, NowInputEventMouse, NowString64, NowUpdateMsg, QualityMode, UpdateCapset,
+};
+use wayk_proto::packet::NowPacket;
+use wayk_proto::serialization::{Decode, Encode};
+
+const UPDATE_DATA_LEN: usize = 64 * 1024;
+
+fn capabilities() -> NowCapabilitiesMsg<'static> {
+    NowCapabilitiesMsg::new_with_capabilities(vec![
+        NowCapset::Update(UpdateCapset::new(QualityMode::High, Codec::JPEG)),
+        NowCapset::Mouse(MouseCapset::new(MouseMode::Primary, MouseCapsetFlags::new_empty())),
+        NowCapset::Network(NetworkCapset::new(NetworkCapsetFlags::new_empty().set_rtt(), 30)),
+        NowCapset::Access(AccessCapset::new_with_access_controls(vec![
+            AccessControlDef::new_allowed(AccessControlCode::Viewing),
+            AccessControlDef::new_confirm(AccessControlCode::Interact),
+            AccessControlDef::new_disabled(AccessControlCode::Exec),
+        ])),
+    ])
+}
+
+fn input_events(count: usize) -> Vec16<InputEvent<'static>> {
+    let events = (0..count)
+        .map(|i| {
+            if i % 2 == 0 {
+                InputEvent::Mouse(NowInputEventMouse::new_with_flags_and_position(
+                    EventMouseFlags::None,
+                    i as i16,
+                    i as i16,
+                ))
+            } else {
+                InputEvent::Keyboard(NowInputEventKeyboard::new_with_flags_and_code(0, i as u16))
+            }
+        })
+        .collect();
+    Vec16(events)
+}
+
+fn update_graphics(data_len: usize) -> Vec<u8> {
+    #[rustfmt::skip]
+    let mut bytes = vec![
+        0x01, // subtype
+        0x00, // flags
+        0x02, 0x00, // codecId
+        0x00, 0x00, // surfaceID
+        0x01, 0x00, // frameID
+        0x03, 0x00, 0x00, 0x00, // updateFlags
+        0x00, 0x00, 0x00, 0x00, 0x80, 0x07, 0x38, 0x04, // updateRect
+    ];
+    bytes.extend_from_slice(&(data_len as u32).to_le_bytes());
+    bytes.resize(bytes.len() + data_len, 0xAB);
+    bytes
+}
+
+fn header(c: &mut Criterion) {
+    let short = NowPacket::from_message(capabilities()).encode().unwrap();
+    let long = NowPacket::from_message(NowUpdateMsg::decode(&update_graphics(UPDATE_DATA_LEN)).unwrap())
+        .encode()
+        .unwrap();
+
+    let mut group = c.benchmark_group("header_decode");
+    group.bench_function("short", |b| b.iter(|| NowHeader::decode(black_box(&short)).unwrap()));
+    group.bench_function("long", |b| b.iter(|| NowHeader::decode(black_box(&long)).unwrap()));
+    group.finish();
+}
+
+fn capset(c: &mut Criterion) {
+    let msg = capabilities();
+    let bytes = msg.encode().unwrap();
+
+    let mut group = c.benchmark_group("capabilities");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("decode", |b| {
+        b.iter(|| NowCapabilitiesMsg::decode(black_box(&bytes)).unwrap())
+    });
+    group.bench_function("encode", |b| b.iter(|| black_box(&msg).encode().unwrap()));
+    group.bench_function("name_lookup", |b| {
+        let name = NowString64::from_str("NowNetwork").unwrap();
+        b.iter(|| {
+            msg.capabilities
+                .iter()
+                .filter(|capset| capset.name_as_str() == black_box(&name).as_str())
+                .count()
+        })
+    });
+    group.finish();
+}
+
+fn input(c: &mut Criterion) {
+    let mut group = c.benchmark_group("input_events_decode");
+    for count in [1, 16, 256] {
+        let bytes = input_events(count).encode().unwrap();
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_function(count.to_string(), |b| {
+            b.iter(|| Vec16::<InputEvent>::decode(black_box(&bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_graphics_decode");
+    for data_len in [1024, UPDATE_DATA_LEN] {
+        let bytes = update_graphics(data_len);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        // payload is borrowed, decode cost shouldn't depend on its size
+        group.bench_function(data_len.to_string(), |b| {
+            b.iter(|| match NowUpdateMsg::decode(black_box(&bytes)).unwrap() {
+                NowUpdateMsg::UpdateGraphics(msg) => msg.update_data.len(),
+                _ => unreachable!(),
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, header, capset, input, update);
+criterion_main!(benches);
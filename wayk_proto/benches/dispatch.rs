@@ -0,0 +1,130 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::str::FromStr;
+use wayk_proto::header::{AbstractNowHeader, NowHeader};
+use wayk_proto::message::{
+    AuthType, ChannelName, ClipboardFormatDef, EventMouseFlags, InputEvent, NegotiateFlags, NowActivateMsg,
+    NowChatTextMsg, NowClipboardFormatListReqMsg, NowInputEventMouse, NowInputMsg, NowMessage, NowNegotiateMsg,
+    NowString256, NowString65535, NowTerminateMsg, NowVirtualChannel, VirtChannelsCtx,
+};
+use wayk_proto::packet::NowPacket;
+use wayk_proto::serialization::{Decode, Encode};
+
+const CLIPBOARD_CHANNEL_ID: u8 = 0x00;
+const CHAT_CHANNEL_ID: u8 = 0x01;
+
+fn channels_ctx() -> VirtChannelsCtx {
+    let mut ctx = VirtChannelsCtx::new();
+    ctx.insert(CLIPBOARD_CHANNEL_ID, ChannelName::Clipboard);
+    ctx.insert(CHAT_CHANNEL_ID, ChannelName::Chat);
+    ctx
+}
+
+fn sample_messages() -> Vec<(&'static str, NowMessage<'static>)> {
+    vec![
+        (
+            "negotiate",
+            NowNegotiateMsg::new_with_auth_list(
+                NegotiateFlags::new_empty().set_srp_extended(),
+                vec![AuthType::SRP, AuthType::PFP],
+            )
+            .into(),
+        ),
+        ("activate", NowActivateMsg::default().into()),
+        ("terminate", NowTerminateMsg::default().into()),
+        (
+            "input",
+            NowInputMsg::new_with_events(vec![InputEvent::Mouse(
+                NowInputEventMouse::new_with_flags_and_position(EventMouseFlags::None, 10, 20),
+            )])
+            .into(),
+        ),
+    ]
+}
+
+fn sample_virtual_channels() -> Vec<(&'static str, u8, NowVirtualChannel<'static>)> {
+    vec![
+        (
+            "clipboard_format_list",
+            CLIPBOARD_CHANNEL_ID,
+            NowClipboardFormatListReqMsg::new_with_formats(
+                1,
+                vec![ClipboardFormatDef::new(
+                    13,
+                    NowString256::from_str("CF_UNICODETEXT").unwrap(),
+                )],
+            )
+            .into(),
+        ),
+        (
+            "chat_text",
+            CHAT_CHANNEL_ID,
+            NowChatTextMsg::new(0, 1, NowString65535::from_str("hello, world").unwrap()).into(),
+        ),
+    ]
+}
+
+fn decode_packet<'a>(bytes: &'a [u8], ctx: &VirtChannelsCtx) -> NowPacket<'a> {
+    let header = NowHeader::decode(bytes).unwrap();
+    let body = &bytes[header.len()..];
+    NowPacket::decode_from(header, body, ctx).unwrap()
+}
+
+fn message_dispatch(c: &mut Criterion) {
+    let ctx = channels_ctx();
+    let messages = sample_messages();
+
+    let mut group = c.benchmark_group("message_decode");
+    for (name, msg) in &messages {
+        let bytes = NowPacket::from_message_ref(msg).encode().unwrap();
+        group.bench_function(*name, |b| b.iter(|| decode_packet(black_box(&bytes), &ctx)));
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("message_encode");
+    for (name, msg) in &messages {
+        group.bench_function(*name, |b| b.iter(|| black_box(msg).encode().unwrap()));
+    }
+    group.finish();
+
+    c.bench_function("message_get_type", |b| {
+        b.iter(|| {
+            messages
+                .iter()
+                .map(|(_, msg)| u8::from(black_box(msg).get_type()))
+                .fold(0u8, u8::wrapping_add)
+        })
+    });
+
+    c.bench_function("message_encoded_len", |b| {
+        b.iter(|| {
+            messages
+                .iter()
+                .map(|(_, msg)| black_box(msg).encoded_len())
+                .sum::<usize>()
+        })
+    });
+}
+
+fn virtual_channel_dispatch(c: &mut Criterion) {
+    let ctx = channels_ctx();
+    let channels = sample_virtual_channels();
+
+    let mut group = c.benchmark_group("virtual_channel_decode");
+    for (name, id, msg) in &channels {
+        let bytes = NowPacket::from_virt_channel_ref(msg, *id).encode().unwrap();
+        group.bench_function(*name, |b| b.iter(|| decode_packet(black_box(&bytes), &ctx)));
+    }
+    group.finish();
+
+    c.bench_function("virtual_channel_get_name", |b| {
+        b.iter(|| {
+            channels
+                .iter()
+                .filter(|(_, _, msg)| *black_box(msg).get_name() == ChannelName::Chat)
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, message_dispatch, virtual_channel_dispatch);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wayk_proto::header::{AbstractNowHeader, NowHeader};
+use wayk_proto::message::VirtChannelsCtx;
+use wayk_proto::packet::NowPacket;
+use wayk_proto::serialization::Decode;
+
+// Malformed packets must be rejected without panicking.
+fuzz_target!(|input: (VirtChannelsCtx, &[u8])| {
+    let (channels_ctx, data) = input;
+
+    let header = match NowHeader::decode(data) {
+        Ok(header) => header,
+        Err(_) => return,
+    };
+
+    // like the packets accumulator, only complete packets are decoded
+    let body = &data[header.len()..];
+    if body.len() < header.body_len() {
+        return;
+    }
+
+    let _ = NowPacket::decode_from(header, body, &channels_ctx);
+});
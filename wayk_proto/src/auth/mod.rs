@@ -1 +1,257 @@
+//! Authentication tokens, carried by `NowAuthenticateTokenMsg`.
+//!
+//! [`NowAuthToken`](enum.NowAuthToken.html) types a token after the `AuthType` it was sent with,
+//! so authentication state machines don't need to parse or build payloads by hand.
+//!
+//! ```
+//! use core::str::FromStr;
+//! use wayk_proto::auth::pfp::{NowAuthPFP, NowAuthPFPNegotiate};
+//! use wayk_proto::auth::NowAuthToken;
+//! use wayk_proto::message::{AuthType, NowAuthenticateTokenMsgOwned, NowString256, NowString64};
+//!
+//! let token = NowAuthToken::PFP(NowAuthPFP::Negotiate(NowAuthPFPNegotiate::new(
+//!     NowString64::from_str("Johnny Doe")?,
+//!     NowString256::from_str("It's me.")?,
+//! )));
+//! let msg = NowAuthenticateTokenMsgOwned::from_token(&token)?;
+//! assert_eq!(msg.auth_type, AuthType::PFP);
+//!
+//! match NowAuthToken::decode_for(msg.auth_type, &msg.token_data)? {
+//!     NowAuthToken::PFP(NowAuthPFP::Negotiate(negotiate)) => assert_eq!(negotiate.friendly_name, "Johnny Doe"),
+//!     token => panic!("unexpected token {:?}", token),
+//! }
+//! # Ok::<(), wayk_proto::error::ProtoError>(())
+//! ```
+
 pub mod pfp;
+pub mod srd;
+pub mod srp;
+pub mod token;
+
+use crate::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt, Result};
+use crate::io::{Cursor, NoStdWrite};
+use crate::message::{AuthType, Redacted};
+use crate::serialization::{Decode, Encode, ExpectedSize};
+use core::fmt;
+use pfp::NowAuthPFP;
+use srd::NowAuthSRD;
+use srp::NowAuthSRP;
+use token::NowAuthSessionToken;
+
+/// Token of an authentication method, see the [module documentation](index.html).
+#[derive(Clone)]
+pub enum NowAuthToken<'a> {
+    /// `AuthType::None` doesn't exchange anything, its token is empty.
+    None,
+    PFP(NowAuthPFP<'a>),
+    SRP(NowAuthSRP<'a>),
+    SRD(NowAuthSRD<'a>),
+    /// One-time session token, sent with `AuthType::None` and the `SESSION_TOKEN` flag.
+    Session(NowAuthSessionToken),
+    /// NTLM, SPNEGO, Kerberos and CredSSP tokens are produced and consumed as is by the security
+    /// package implementing the method.
+    Security {
+        auth_type: AuthType,
+        token: &'a [u8],
+    },
+    /// Token of an authentication method unknown to this crate.
+    Custom {
+        auth_type: AuthType,
+        token: &'a [u8],
+    },
+}
+
+impl<'a> NowAuthToken<'a> {
+    /// Decodes `token` as sent with `auth_type`.
+    pub fn decode_for(auth_type: AuthType, token: &'a [u8]) -> Result<Self> {
+        match auth_type {
+            AuthType::None if token.is_empty() => Ok(Self::None),
+            AuthType::None => Err(
+                ProtoError::new(ProtoErrorKind::Decoding(__type_str!(NowAuthToken))).with_desc(format!(
+                    "unexpected {} bytes long token without authentication",
+                    token.len()
+                )),
+            ),
+            AuthType::PFP => NowAuthPFP::decode(token).map(Self::PFP),
+            AuthType::SRP => NowAuthSRP::decode(token).map(Self::SRP),
+            AuthType::SRD => NowAuthSRD::decode(token).map(Self::SRD),
+            AuthType::NTLM | AuthType::SPNEGO | AuthType::Kerberos | AuthType::CredSSP => {
+                Ok(Self::Security { auth_type, token })
+            }
+            AuthType::IGNORED1 | AuthType::Other(_) => Ok(Self::Custom { auth_type, token }),
+        }
+        .chain(ProtoErrorKind::Decoding(__type_str!(NowAuthToken)))
+        .or_else_desc(|| format!("couldn't decode {:?} token", auth_type))
+    }
+
+    /// Decodes a token sent with the `AuthenticateTokenFlags::SESSION_TOKEN` flag.
+    pub fn decode_session(token: &'a [u8]) -> Result<Self> {
+        NowAuthSessionToken::decode(token)
+            .map(Self::Session)
+            .chain(ProtoErrorKind::Decoding(__type_str!(NowAuthToken)))
+            .or_desc("couldn't decode session token")
+    }
+
+    pub fn auth_type(&self) -> AuthType {
+        match self {
+            Self::None | Self::Session(_) => AuthType::None,
+            Self::PFP(_) => AuthType::PFP,
+            Self::SRP(_) => AuthType::SRP,
+            Self::SRD(_) => AuthType::SRD,
+            Self::Security { auth_type, .. } | Self::Custom { auth_type, .. } => *auth_type,
+        }
+    }
+}
+
+impl fmt::Debug for NowAuthToken<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("None"),
+            Self::PFP(token) => f.debug_tuple("PFP").field(token).finish(),
+            Self::SRP(token) => f.debug_tuple("SRP").field(token).finish(),
+            Self::SRD(token) => f.debug_tuple("SRD").field(token).finish(),
+            Self::Session(token) => f.debug_tuple("Session").field(token).finish(),
+            Self::Security { auth_type, token } => f
+                .debug_struct("Security")
+                .field("auth_type", auth_type)
+                .field("token", &Redacted(token))
+                .finish(),
+            Self::Custom { auth_type, token } => f
+                .debug_struct("Custom")
+                .field("auth_type", auth_type)
+                .field("token", &Redacted(token))
+                .finish(),
+        }
+    }
+}
+
+impl Encode for NowAuthToken<'_> {
+    fn expected_size() -> ExpectedSize
+    where
+        Self: Sized,
+    {
+        ExpectedSize::Variable
+    }
+
+    fn encoded_len(&self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::PFP(token) => token.encoded_len(),
+            Self::SRP(token) => token.encoded_len(),
+            Self::SRD(token) => token.encoded_len(),
+            Self::Session(token) => token.encoded_len(),
+            Self::Security { token, .. } | Self::Custom { token, .. } => token.len(),
+        }
+    }
+
+    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+        match self {
+            Self::None => Ok(()),
+            Self::PFP(token) => token.encode_into(writer),
+            Self::SRP(token) => token.encode_into(writer),
+            Self::SRD(token) => token.encode_into(writer),
+            Self::Session(token) => token.encode_into(writer),
+            Self::Security { token, .. } | Self::Custom { token, .. } => {
+                writer.write_all(token)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+// SRP and SRD tokens start with a signature, the message type, a sequence number and flags.
+
+fn h_encode_framed<W: NoStdWrite, T: Encode>(
+    writer: &mut W,
+    signature: u32,
+    subtype: T,
+    seq_num: u8,
+    flags: u16,
+    body: &[u8],
+) -> Result<()> {
+    signature.encode_into(writer)?;
+    subtype.encode_into(writer)?;
+    seq_num.encode_into(writer)?;
+    flags.encode_into(writer)?;
+    writer.write_all(body)?;
+    Ok(())
+}
+
+fn h_decode_framed<'dec, T: Decode<'dec>>(
+    cursor: &mut Cursor<'dec>,
+    signature: u32,
+    type_name: &'static str,
+) -> Result<(T, u8, u16, &'dec [u8])> {
+    let actual = u32::decode_from(cursor)?;
+    if actual != signature {
+        return Err(ProtoError::new(ProtoErrorKind::Decoding(type_name))
+            .with_desc(format!("bad signature {:#010x}, expected {:#010x}", actual, signature)));
+    }
+    let subtype = T::decode_from(cursor)?;
+    let seq_num = u8::decode_from(cursor)?;
+    let flags = u16::decode_from(cursor)?;
+    let body = cursor.peek_rest()?;
+    cursor.forward(body.len());
+    Ok((subtype, seq_num, flags, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::SRPMessageType;
+
+    #[test]
+    fn typed_after_auth_type() {
+        let srp = NowAuthSRP::new(SRPMessageType::SRPOffer, 1, &[0x2a]).encode().unwrap();
+        match NowAuthToken::decode_for(AuthType::SRP, &srp).unwrap() {
+            NowAuthToken::SRP(token) => assert_eq!(token.subtype, SRPMessageType::SRPOffer),
+            token => panic!("expected a SRP token and got {:?}", token),
+        }
+        // not a SRD token
+        assert!(NowAuthToken::decode_for(AuthType::SRD, &srp).is_err());
+
+        let token = NowAuthToken::decode_for(AuthType::Kerberos, &srp).unwrap();
+        assert!(matches!(
+            token,
+            NowAuthToken::Security {
+                auth_type: AuthType::Kerberos,
+                ..
+            }
+        ));
+        assert_eq!(token.encode().unwrap(), srp);
+
+        assert!(matches!(
+            NowAuthToken::decode_for(AuthType::None, &[]),
+            Ok(NowAuthToken::None)
+        ));
+        assert!(NowAuthToken::decode_for(AuthType::None, &srp).is_err());
+    }
+
+    #[test]
+    fn secrets_compared_in_constant_time() {
+        use crate::message::NowString256;
+        use crate::secrets::constant_time_eq;
+        use core::str::FromStr;
+        use pfp::NowAuthPFPResponse;
+
+        let verifier = [0x2a; 32];
+        let mut other = verifier;
+        other[31] = 0x2b;
+        assert!(constant_time_eq(&verifier, &verifier) && !constant_time_eq(&verifier, &other));
+
+        let copy = verifier;
+        let srp = NowAuthSRP::new(SRPMessageType::SRPOffer, 1, &verifier);
+        assert!(srp == NowAuthSRP::new(SRPMessageType::SRPOffer, 1, &copy));
+        assert!(srp != NowAuthSRP::new(SRPMessageType::SRPOffer, 1, &other));
+
+        let srd = NowAuthSRD::new(srd::SRDMessageType::Offer, 1, &verifier);
+        assert!(srd == NowAuthSRD::new(srd::SRDMessageType::Offer, 1, &copy));
+        assert!(srd != NowAuthSRD::new(srd::SRDMessageType::Offer, 1, &other));
+
+        let pfp = |answer| NowAuthPFPResponse::new(NowString256::from_str(answer).unwrap());
+        assert_eq!(pfp("hunter2"), pfp("hunter2"));
+        assert_ne!(pfp("hunter2"), pfp("hunter3"));
+        #[cfg(feature = "zeroize")]
+        assert!(!format!("{:?}", pfp("hunter2")).contains("hunter2"));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::error::Result;
 use crate::message::{AuthType, NowAuthenticateMsg, NowAuthenticateTokenMsgOwned, NowString256, NowString64};
+use crate::secrets::{self, constant_time_eq, SecretField};
 use crate::serialization::Encode;
 use core::str::FromStr;
 
@@ -25,7 +26,7 @@ pub enum PFPMessageFlags {
     Other(u16),
 }
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq)]
 #[meta_enum = "PFPMessageType"]
 pub enum NowAuthPFP<'a> {
     Negotiate(NowAuthPFPNegotiate),
@@ -48,7 +49,7 @@ impl NowAuthPFP<'_> {
 }
 
 // pfp types
-#[derive(Decode, Encode, Debug, Clone)]
+#[derive(Decode, Encode, Debug, Clone, PartialEq)]
 pub struct NowAuthPFPNegotiate {
     pub subtype: PFPMessageType,
     pub flags: PFPMessageFlags,
@@ -69,7 +70,7 @@ impl NowAuthPFPNegotiate {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
 pub struct NowAuthPFPChallenge {
     pub subtype: PFPMessageType,
     pub flags: PFPMessageFlags,
@@ -96,11 +97,12 @@ impl NowAuthPFPChallenge {
     }
 }
 
+/// The answer is held in a `Secret` with the `zeroize` feature and compared in constant time.
 #[derive(Decode, Encode, Debug, Clone)]
 pub struct NowAuthPFPResponse {
     pub subtype: PFPMessageType,
     pub flags: PFPMessageFlags,
-    pub answer: NowString256,
+    answer: SecretField<NowString256>,
 }
 
 impl NowAuthPFPResponse {
@@ -110,9 +112,27 @@ impl NowAuthPFPResponse {
         Self {
             subtype: PFPMessageType::Response,
             flags: PFPMessageFlags::Question,
-            answer,
+            answer: secrets::hold(answer),
         }
     }
+
+    /// Borrows the answer, formerly the public `answer` field.
+    pub fn answer(&self) -> &NowString256 {
+        secrets::expose(&self.answer)
+    }
+
+    /// Replaces the answer, the previous one is wiped with the `zeroize` feature.
+    pub fn set_answer(&mut self, answer: NowString256) {
+        self.answer = secrets::hold(answer);
+    }
+}
+
+impl PartialEq for NowAuthPFPResponse {
+    fn eq(&self, other: &Self) -> bool {
+        self.subtype == other.subtype
+            && self.flags == other.flags
+            && constant_time_eq(self.answer().as_bytes(), other.answer().as_bytes())
+    }
 }
 
 #[cfg(test)]
@@ -220,7 +240,7 @@ mod tests {
         if let NowAuthPFP::Response(msg) = msg {
             assert_eq!(msg.subtype, PFPMessageType::Response);
             assert_eq!(msg.flags, PFPMessageFlags::Question);
-            assert_eq!(msg.answer, "元気");
+            assert_eq!(msg.answer().as_str(), "元気");
         } else {
             panic!("Expected a response message, found {:?}", msg);
         }
@@ -231,4 +251,12 @@ mod tests {
         let msg = NowAuthPFPResponse::new(NowString256::from_str("元気").unwrap());
         assert_eq!(msg.encode().unwrap(), PFP_RESPONSE_TOKEN.to_vec());
     }
+
+    #[test]
+    fn response_answer_replaced() {
+        let mut msg = NowAuthPFPResponse::new(NowString256::from_str("hunter2").unwrap());
+        msg.set_answer(NowString256::from_str("元気").unwrap());
+        assert_eq!(msg.answer().as_str(), "元気");
+        assert_eq!(msg.encode().unwrap(), PFP_RESPONSE_TOKEN.to_vec());
+    }
 }
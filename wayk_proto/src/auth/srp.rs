@@ -0,0 +1,124 @@
+use crate::error::Result;
+use crate::io::{Cursor, NoStdWrite};
+use crate::message::{Redacted, SRPMessageType};
+use crate::secrets::constant_time_eq;
+use crate::serialization::{Decode, Encode, ExpectedSize};
+use core::fmt;
+
+/// SRP token. The header is typed, the body depends on the message type and is left to the
+/// SRP implementation.
+#[derive(Clone)]
+pub struct NowAuthSRP<'a> {
+    pub subtype: SRPMessageType,
+    pub seq_num: u8,
+    pub flags: u16,
+    pub body: &'a [u8],
+}
+
+impl<'a> NowAuthSRP<'a> {
+    /// "SRP\0"
+    pub const SIGNATURE: u32 = 0x0050_5253;
+    pub const REQUIRED_SIZE: usize = 8;
+
+    pub fn new(subtype: SRPMessageType, seq_num: u8, body: &'a [u8]) -> Self {
+        Self {
+            subtype,
+            seq_num,
+            flags: 0,
+            body,
+        }
+    }
+}
+
+impl fmt::Debug for NowAuthSRP<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NowAuthSRP")
+            .field("subtype", &self.subtype)
+            .field("seq_num", &self.seq_num)
+            .field("flags", &self.flags)
+            .field("body", &Redacted(self.body))
+            .finish()
+    }
+}
+
+// bodies carry proofs and verifiers
+impl PartialEq for NowAuthSRP<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.subtype == other.subtype
+            && self.seq_num == other.seq_num
+            && self.flags == other.flags
+            && constant_time_eq(self.body, other.body)
+    }
+}
+
+impl Encode for NowAuthSRP<'_> {
+    fn expected_size() -> ExpectedSize
+    where
+        Self: Sized,
+    {
+        ExpectedSize::Variable
+    }
+
+    fn encoded_len(&self) -> usize {
+        Self::REQUIRED_SIZE + self.body.len()
+    }
+
+    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+        super::h_encode_framed(
+            writer,
+            Self::SIGNATURE,
+            self.subtype,
+            self.seq_num,
+            self.flags,
+            self.body,
+        )
+    }
+}
+
+impl<'dec: 'a, 'a> Decode<'dec> for NowAuthSRP<'a> {
+    fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
+        let (subtype, seq_num, flags, body) = super::h_decode_framed(cursor, Self::SIGNATURE, __type_str!(NowAuthSRP))?;
+        Ok(Self {
+            subtype,
+            seq_num,
+            flags,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const SRP_INITIATE_TOKEN: [u8; 12] = [
+        0x53, 0x52, 0x50, 0x00, // signature
+        0x01, // type
+        0x06, // sequence number
+        0x00, 0x00, // flags
+        0x00, 0x01, 0x12, 0x00, // body
+    ];
+
+    #[test]
+    fn decoding() {
+        let msg = NowAuthSRP::decode(&SRP_INITIATE_TOKEN).unwrap();
+        assert_eq!(msg.subtype, SRPMessageType::SRPInitiate);
+        assert_eq!(msg.seq_num, 0x06);
+        assert_eq!(msg.flags, 0x0000);
+        assert_eq!(msg.body, &SRP_INITIATE_TOKEN[8..]);
+    }
+
+    #[test]
+    fn encoding() {
+        let msg = NowAuthSRP::new(SRPMessageType::SRPInitiate, 0x06, &SRP_INITIATE_TOKEN[8..]);
+        assert_eq!(msg.encode().unwrap(), SRP_INITIATE_TOKEN.to_vec());
+    }
+
+    #[test]
+    fn bad_signature() {
+        let mut token = SRP_INITIATE_TOKEN;
+        token[2] = 0x44;
+        assert!(NowAuthSRP::decode(&token).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::error::Result;
+use crate::message::status::AuthStatusCode;
+use crate::message::{AuthType, AuthenticateTokenFlags, NowAuthenticateTokenMsgOwned, NowString256, Redacted};
+use crate::serialization::Encode;
+use core::fmt;
+
+/// One-time session token, optionally protected by a password.
+///
+/// Sent in place of interactive credentials when both peers set `NegotiateFlags::AUTH_TOKEN`,
+/// with the `AuthenticateTokenFlags::SESSION_TOKEN` flag and `AuthType::None`.
+#[derive(Encode, Decode, Clone, PartialEq)]
+pub struct NowAuthSessionToken {
+    pub token: NowString256,
+    /// Empty if the token isn't password protected.
+    pub password: NowString256,
+}
+
+impl NowAuthSessionToken {
+    pub const MIN_REQUIRED_SIZE: usize = 4;
+
+    pub fn new(token: NowString256) -> Self {
+        Self {
+            token,
+            password: NowString256::new_empty(),
+        }
+    }
+
+    pub fn with_password(self, password: NowString256) -> Self {
+        Self { password, ..self }
+    }
+
+    pub fn has_password(&self) -> bool {
+        !self.password.as_str().is_empty()
+    }
+
+    pub fn to_authenticate_msg(&self) -> Result<NowAuthenticateTokenMsgOwned> {
+        Ok(NowAuthenticateTokenMsgOwned::new(AuthType::None, self.encode()?)
+            .auth_flags(AuthenticateTokenFlags::new_empty().set_session_token()))
+    }
+}
+
+// tokens are credentials, keep them out of logs
+impl fmt::Debug for NowAuthSessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NowAuthSessionToken")
+            .field("token", &Redacted(self.token.as_str().as_bytes()))
+            .field("password", &Redacted(self.password.as_str().as_bytes()))
+            .finish()
+    }
+}
+
+/// Why a session token couldn't be used, carried by `ProtoErrorKind::SessionToken`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionTokenError {
+    /// The server didn't set `NegotiateFlags::AUTH_TOKEN`.
+    NotSupported,
+    /// No token was configured, see `ShareeBuilder::session_token`.
+    Missing,
+    /// The server refused the token (expired, already used, wrong password…).
+    Rejected {
+        status: AuthStatusCode,
+        /// Another token may be tried.
+        retry: bool,
+    },
+}
+
+impl fmt::Display for SessionTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotSupported => write!(f, "session tokens not supported by server"),
+            Self::Missing => write!(f, "no session token configured"),
+            Self::Rejected { status, .. } => write!(f, "session token rejected ({})", status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::NowAuthenticateMsg;
+    use crate::serialization::Decode;
+    use alloc::string::ToString;
+    use core::str::FromStr;
+
+    #[rustfmt::skip]
+    const SESSION_TOKEN: [u8; 14] = [
+        0x05, // token len
+        0x61, 0x62, 0x63, 0x31, 0x32, 0x00, // "abc12"
+        0x05, // password len
+        0x73, 0x33, 0x63, 0x72, 0x33, 0x00, // "s3cr3"
+    ];
+
+    #[test]
+    fn session_token_encoding() {
+        let token = NowAuthSessionToken::new(NowString256::from_str("abc12").unwrap())
+            .with_password(NowString256::from_str("s3cr3").unwrap());
+        assert!(token.has_password());
+        assert_eq!(token.encode().unwrap(), SESSION_TOKEN.to_vec());
+        assert!(!format!("{:?}", token).contains("abc12"));
+
+        let decoded = NowAuthSessionToken::decode(&SESSION_TOKEN).unwrap();
+        assert_eq!(decoded.token, "abc12");
+
+        let msg = token.to_authenticate_msg().unwrap();
+        assert!(msg.auth_flags.session_token());
+        let bytes = NowAuthenticateMsg::from(msg).encode().unwrap();
+        match NowAuthenticateMsg::decode(&bytes).unwrap() {
+            NowAuthenticateMsg::Token(msg) => {
+                assert_eq!(msg.auth_type, AuthType::None);
+                assert!(msg.auth_flags.session_token());
+                assert_eq!(msg.token_data.0, &SESSION_TOKEN[..]);
+            }
+            msg => panic!("expected a token message and got {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn session_token_error_display() {
+        let err = SessionTokenError::Rejected {
+            status: AuthStatusCode::AccountExpired,
+            retry: false,
+        };
+        assert_eq!(err.to_string(), "session token rejected (account expired)");
+    }
+}
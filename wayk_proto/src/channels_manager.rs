@@ -1,44 +1,355 @@
+use crate::compression::Compression;
 use crate::error::{ProtoError, ProtoErrorKind};
-use crate::message::{ChannelName, NowVirtualChannel};
-use crate::sm::{ChannelResponses, SMData, SMEvent, SMEvents, VirtualChannelSM};
+use crate::header::AbstractNowHeader;
+use crate::message::NowBody;
+use crate::message::{
+    ChannelDefFlags, ChannelMessageType, ChannelName, CustomVirtualChannel, CustomVirtualChannelOwned, NowChannelDef,
+    NowChannelMsg, NowVirtualChannel, VirtChannelsCtx,
+};
+use crate::packet::NowPacket;
+use crate::serialization::Encode;
+use crate::sm::{ChannelResponses, ProtoData, SMData, SMEvent, SMEvents, StateInfo, VirtualChannelSM};
+use crate::sync::SharedQueue;
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::time::Duration;
 
 pub type ChannelsManagerResult<'a> = Result<Option<(ChannelName, NowVirtualChannel<'a>)>, ProtoError>;
 
-pub struct ChannelsManager {
-    state_machines: BTreeMap<ChannelName, Box<dyn VirtualChannelSM>>,
+/// Channels with a higher priority are updated and flushed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChannelPriority {
+    /// Bulk transfers.
+    Low,
+    Normal,
+    /// Latency-sensitive control traffic.
+    High,
+}
+
+impl ChannelPriority {
+    pub fn default_for(name: &ChannelName) -> Self {
+        match name {
+            ChannelName::Clipboard => ChannelPriority::High,
+            ChannelName::FileTransfer | ChannelName::Tunnel => ChannelPriority::Low,
+            _ => ChannelPriority::Normal,
+        }
+    }
 }
 
-impl Default for ChannelsManager {
+/// How the channel to update next is picked when several are ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// Highest priority first, registration order among channels of the same priority.
+    /// Lower priority channels may starve.
+    Strict,
+    /// Same as `Strict`, except that a channel passed over `max_skips` times in a row goes first.
+    Fair { max_skips: u32 },
+}
+
+impl SchedulingPolicy {
+    pub const DEFAULT_MAX_SKIPS: u32 = 8;
+}
+
+impl Default for SchedulingPolicy {
     fn default() -> Self {
+        SchedulingPolicy::Fair {
+            max_skips: Self::DEFAULT_MAX_SKIPS,
+        }
+    }
+}
+
+/// Queues messages on a virtual channel from the application, possibly from another thread.
+///
+/// Queued messages are sent in order on the next channels manager update, ahead of the state
+/// machine responses. They are kept while the channel is stopped by the peer.
+pub struct ChannelCommandSender<T> {
+    name: ChannelName,
+    outbox: SharedQueue<NowVirtualChannel<'static>>,
+    _pd: PhantomData<fn(T)>,
+}
+
+impl<T> Clone for ChannelCommandSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            outbox: self.outbox.clone(),
+            _pd: PhantomData,
+        }
+    }
+}
+
+impl<T> ChannelCommandSender<T>
+where
+    T: Into<NowVirtualChannel<'static>>,
+{
+    pub fn channel_name(&self) -> &ChannelName {
+        &self.name
+    }
+
+    pub fn send(&self, msg: T) {
+        self.outbox.push(msg.into());
+    }
+}
+
+struct ChannelEntry {
+    sm: Box<dyn VirtualChannelSM + Send>,
+    /// Bound by `bind_channels` once the channel is opened.
+    channel_id: Option<u8>,
+    priority: ChannelPriority,
+    skips: u32,
+    /// Stopped or closed by the peer.
+    stopped: bool,
+    /// Not opened by the peer, see `bind_channels`.
+    unavailable: bool,
+    /// Messages queued through `ChannelCommandSender`s.
+    outbox: SharedQueue<NowVirtualChannel<'static>>,
+}
+
+impl ChannelEntry {
+    fn new(sm: Box<dyn VirtualChannelSM + Send>, priority: ChannelPriority) -> Self {
+        Self {
+            sm,
+            channel_id: None,
+            priority,
+            skips: 0,
+            stopped: false,
+            unavailable: false,
+            outbox: SharedQueue::default(),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        !self.stopped && !self.unavailable
+    }
+
+    fn command_sender<T>(&self) -> ChannelCommandSender<T> {
+        ChannelCommandSender {
+            name: self.sm.get_channel_name(),
+            outbox: self.outbox.clone(),
+            _pd: PhantomData,
+        }
+    }
+}
+
+/// Traffic on a virtual channel, all instances included, see `ChannelsManager::channel_stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelStats {
+    pub name: ChannelName,
+    pub messages_in: u64,
+    pub messages_out: u64,
+    /// Received messages size, once decompressed.
+    pub bytes_in: u64,
+    /// Sent packets body size, as sent on the wire.
+    pub bytes_out: u64,
+    /// Manager time of the last message in either direction, see `ChannelsManager::advance_time`.
+    pub last_activity: Option<Duration>,
+}
+
+impl ProtoData for ChannelStats {}
+
+impl ChannelStats {
+    fn new(name: ChannelName) -> Self {
         Self {
-            state_machines: BTreeMap::new(),
+            name,
+            messages_in: 0,
+            messages_out: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            last_activity: None,
         }
     }
 }
 
+#[derive(Default)]
+pub struct ChannelsManager {
+    // registration order
+    channels: Vec<ChannelEntry>,
+    policy: SchedulingPolicy,
+    stats: BTreeMap<ChannelName, ChannelStats>,
+    /// Stamps channel activity, see `advance_time`.
+    time: Duration,
+}
+
 impl ChannelsManager {
     pub fn new() -> Self {
         Self::default()
     }
 
+    pub fn with_policy(mut self, policy: SchedulingPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
     pub fn with_sm<VirtChanSM>(mut self, state_machine: VirtChanSM) -> Self
     where
-        VirtChanSM: VirtualChannelSM + 'static,
+        VirtChanSM: VirtualChannelSM + Send + 'static,
     {
         self.add_sm(state_machine);
         self
     }
 
-    pub fn add_sm<VirtChanSM>(&mut self, state_machine: VirtChanSM) -> Option<Box<dyn VirtualChannelSM>>
+    pub fn with_sm_and_priority<VirtChanSM>(mut self, state_machine: VirtChanSM, priority: ChannelPriority) -> Self
+    where
+        VirtChanSM: VirtualChannelSM + Send + 'static,
+    {
+        self.add_sm_with_priority(state_machine, priority);
+        self
+    }
+
+    pub fn with_sm_instance<VirtChanSM>(mut self, state_machine: VirtChanSM) -> Self
+    where
+        VirtChanSM: VirtualChannelSM + Send + 'static,
+    {
+        self.add_sm_instance(state_machine);
+        self
+    }
+
+    /// Registers a state machine with the default priority of its channel, see `ChannelPriority::default_for`.
+    pub fn add_sm<VirtChanSM>(&mut self, state_machine: VirtChanSM) -> Option<Box<dyn VirtualChannelSM + Send>>
+    where
+        VirtChanSM: VirtualChannelSM + Send + 'static,
+    {
+        let priority = ChannelPriority::default_for(&state_machine.get_channel_name());
+        self.add_sm_with_priority(state_machine, priority)
+    }
+
+    /// Returns the state machine previously registered for the same channel, if any.
+    pub fn add_sm_with_priority<VirtChanSM>(
+        &mut self,
+        state_machine: VirtChanSM,
+        priority: ChannelPriority,
+    ) -> Option<Box<dyn VirtualChannelSM + Send>>
+    where
+        VirtChanSM: VirtualChannelSM + Send + 'static,
+    {
+        let entry = ChannelEntry::new(Box::new(state_machine), priority);
+        let name = entry.sm.get_channel_name();
+        match self.channels.iter_mut().find(|e| e.sm.get_channel_name() == name) {
+            Some(existing) => Some(core::mem::replace(existing, entry).sm),
+            None => {
+                self.channels.push(entry);
+                None
+            }
+        }
+    }
+
+    /// Registers one more instance of a `MULTIPLE` channel, next to the state machines already
+    /// registered for it. Instances are bound to the opened channel ids by `bind_channels`.
+    pub fn add_sm_instance<VirtChanSM>(&mut self, state_machine: VirtChanSM)
+    where
+        VirtChanSM: VirtualChannelSM + Send + 'static,
+    {
+        let priority = ChannelPriority::default_for(&state_machine.get_channel_name());
+        self.channels.push(ChannelEntry::new(Box::new(state_machine), priority));
+    }
+
+    /// Binds the registered state machines to the ids channels were opened with.
+    ///
+    /// Instances of a channel are bound in registration order to its ids in ascending order.
+    /// State machines left without id only get messages not addressed to a specific instance.
+    ///
+    /// State machines of channels that aren't opened at all are deactivated until the peer opens
+    /// them: `on_channel_unavailable` is called and they are skipped by updates.
+    pub fn bind_channels(&mut self, data: &mut SMData, events: &mut SMEvents<'_>, channels_ctx: &VirtChannelsCtx) {
+        for entry in self.channels.iter_mut() {
+            entry.channel_id = None;
+        }
+
+        for (channel_id, name) in channels_ctx.iter() {
+            if let Some(entry) = self
+                .channels
+                .iter_mut()
+                .find(|entry| entry.channel_id.is_none() && entry.sm.get_channel_name() == *name)
+            {
+                entry.channel_id = Some(channel_id);
+            }
+        }
+
+        for entry in self.channels.iter_mut() {
+            let name = entry.sm.get_channel_name();
+            let unavailable = channels_ctx.get_ids_by_channel(&name).next().is_none();
+            if unavailable && !entry.unavailable {
+                log::debug!("channel {:?} not opened, state machine deactivated", name);
+                entry.sm.on_channel_unavailable(data, events);
+            }
+            entry.unavailable = unavailable;
+        }
+    }
+
+    /// Ids bound to the instances of this channel, in registration order.
+    pub fn instances(&self, name: &ChannelName) -> Vec<u8> {
+        self.channels
+            .iter()
+            .filter(|entry| entry.sm.get_channel_name() == *name)
+            .filter_map(|entry| entry.channel_id)
+            .collect()
+    }
+
+    pub fn policy(&self) -> SchedulingPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: SchedulingPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn has_sm(&self, name: &ChannelName) -> bool {
+        self.h_find(name).is_some()
+    }
+
+    pub fn is_stopped(&self, name: &ChannelName) -> bool {
+        self.h_find(name).is_some_and(|entry| entry.stopped)
+    }
+
+    /// True if the channel wasn't opened when state machines were last bound, see `bind_channels`.
+    pub fn is_unavailable(&self, name: &ChannelName) -> bool {
+        self.h_find(name).is_some_and(|entry| entry.unavailable)
+    }
+
+    pub fn is_instance_stopped(&self, channel_id: u8) -> bool {
+        self.h_find_instance(channel_id).is_some_and(|entry| entry.stopped)
+    }
+
+    /// Sender queuing messages on this channel, `None` if no state machine is registered for it.
+    ///
+    /// Senders are bound to the state machine registered at the time of the call.
+    pub fn command_sender<T>(&self, name: &ChannelName) -> Option<ChannelCommandSender<T>>
+    where
+        T: Into<NowVirtualChannel<'static>>,
+    {
+        self.h_find(name).map(ChannelEntry::command_sender)
+    }
+
+    /// Same as `command_sender`, for the instance bound to this channel id.
+    pub fn instance_command_sender<T>(&self, channel_id: u8) -> Option<ChannelCommandSender<T>>
     where
-        VirtChanSM: VirtualChannelSM + 'static,
+        T: Into<NowVirtualChannel<'static>>,
     {
-        self.state_machines
-            .insert(state_machine.get_channel_name(), Box::new(state_machine))
+        self.h_find_instance(channel_id).map(ChannelEntry::command_sender)
+    }
+
+    pub fn priority(&self, name: &ChannelName) -> Option<ChannelPriority> {
+        self.h_find(name).map(|entry| entry.priority)
+    }
+
+    /// Applies to every instance of the channel. Returns false if no state machine is registered
+    /// for this channel.
+    pub fn set_priority(&mut self, name: &ChannelName, priority: ChannelPriority) -> bool {
+        let mut found = false;
+        for entry in self
+            .channels
+            .iter_mut()
+            .filter(|entry| entry.sm.get_channel_name() == *name)
+        {
+            entry.priority = priority;
+            found = true;
+        }
+        found
     }
 
+    /// Dispatches to the first instance of the message channel, see `update_with_instance_msg`.
     pub fn update_with_virt_msg<'msg: 'a, 'a>(
         &mut self,
         data: &mut SMData,
@@ -46,9 +357,55 @@ impl ChannelsManager {
         to_send: &mut ChannelResponses<'msg>,
         chan_msg: &'a NowVirtualChannel<'msg>,
     ) {
-        if let Some(sm) = self.state_machines.get_mut(chan_msg.get_name()) {
-            to_send.set_current_channel_name(sm.get_channel_name());
-            sm.update_with_chan_msg(data, events, to_send, chan_msg);
+        let idx = self.h_position(None, chan_msg.get_name());
+        self.h_update_with_virt_msg(idx, data, events, to_send, chan_msg);
+    }
+
+    /// Dispatches to the instance bound to the id the message was received on, falling back to
+    /// the first instance of the message channel if none is bound to it.
+    pub fn update_with_instance_msg<'msg: 'a, 'a>(
+        &mut self,
+        channel_id: u8,
+        data: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut ChannelResponses<'msg>,
+        chan_msg: &'a NowVirtualChannel<'msg>,
+    ) {
+        let idx = self.h_position(Some(channel_id), chan_msg.get_name());
+        self.h_update_with_virt_msg(idx, data, events, to_send, chan_msg);
+    }
+
+    fn h_update_with_virt_msg<'msg: 'a, 'a>(
+        &mut self,
+        idx: Option<usize>,
+        data: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut ChannelResponses<'msg>,
+        chan_msg: &'a NowVirtualChannel<'msg>,
+    ) {
+        let name = chan_msg.get_name();
+        let stats = self.h_stats_mut(name);
+        stats.messages_in += 1;
+        stats.bytes_in += chan_msg.encoded_len() as u64;
+        if let Some(entry) = idx.map(|idx| &mut self.channels[idx]) {
+            if entry.unavailable {
+                events.push(SMEvent::warn(
+                    ProtoErrorKind::ChannelsManager,
+                    format!("message on unavailable channel {:?} dropped", name),
+                ));
+                return;
+            }
+            if entry.stopped {
+                events.push(SMEvent::warn(
+                    ProtoErrorKind::ChannelsManager,
+                    format!("message on stopped channel {:?} dropped", name),
+                ));
+                return;
+            }
+            __enter_span!("channel", name = ?name);
+            to_send.set_current_channel_name(entry.sm.get_channel_name());
+            to_send.set_current_channel_id(entry.channel_id);
+            entry.sm.update_with_chan_msg(data, events, to_send, chan_msg);
         } else {
             events.push(SMEvent::warn(
                 ProtoErrorKind::ChannelsManager,
@@ -57,32 +414,848 @@ impl ChannelsManager {
         }
     }
 
+    /// Flushes messages queued through command senders, then updates the next ready state
+    /// machine according to the scheduling policy.
     pub fn update_without_virt_msg<'msg>(
         &mut self,
         data: &mut SMData,
         events: &mut SMEvents<'msg>,
         to_send: &mut ChannelResponses<'msg>,
     ) {
-        for sm in self.state_machines.values_mut() {
-            if !sm.waiting_for_packet() {
-                to_send.set_current_channel_name(sm.get_channel_name());
-                sm.update_without_chan_msg(data, events, to_send);
+        let mut flushed = false;
+        for entry in self.channels.iter().filter(|entry| entry.is_active()) {
+            to_send.set_current_channel_name(entry.sm.get_channel_name());
+            to_send.set_current_channel_id(entry.channel_id);
+            while let Some(msg) = entry.outbox.pop() {
+                to_send.push(msg);
+                flushed = true;
+            }
+        }
+
+        let next = match self.h_next_ready() {
+            Some(idx) => idx,
+            None if flushed => return,
+            None => {
+                events.push(SMEvent::warn(
+                    ProtoErrorKind::ChannelsManager,
+                    "no channel state machine is ready to update without message",
+                ));
                 return;
             }
+        };
+
+        for (idx, entry) in self.channels.iter_mut().enumerate() {
+            if idx == next {
+                entry.skips = 0;
+            } else if entry.is_active() && !entry.sm.waiting_for_packet() {
+                entry.skips = entry.skips.saturating_add(1);
+            }
         }
 
-        events.push(SMEvent::warn(
-            ProtoErrorKind::ChannelsManager,
-            "no channel state machine is ready to update without message",
-        ));
+        let entry = &mut self.channels[next];
+        __enter_span!("channel", name = ?entry.sm.get_channel_name());
+        to_send.set_current_channel_name(entry.sm.get_channel_name());
+        to_send.set_current_channel_id(entry.channel_id);
+        entry.sm.update_without_chan_msg(data, events, to_send);
+    }
+
+    /// Handles a close, stop, start or open request sent by the peer once channels are opened and
+    /// returns the response to send back.
+    ///
+    /// Closed channels are stopped until the peer opens them again. Requests name channels, so
+    /// they apply to every instance. Channels without state machine are declined with the
+    /// failure status.
+    pub fn update_with_channel_msg(
+        &mut self,
+        data: &mut SMData,
+        events: &mut SMEvents<'_>,
+        msg: &NowChannelMsg,
+    ) -> Option<NowChannelMsg> {
+        let stop = match msg.subtype {
+            ChannelMessageType::ChannelCloseRequest | ChannelMessageType::ChannelStopRequest => true,
+            ChannelMessageType::ChannelStartRequest | ChannelMessageType::ChannelOpenRequest => false,
+            subtype => {
+                events.push(SMEvent::warn(
+                    ProtoErrorKind::ChannelsManager,
+                    format!("unexpected {:?} channel message in active state", subtype),
+                ));
+                return None;
+            }
+        };
+
+        let mut defs = Vec::with_capacity(msg.channel_list.len());
+        for def in msg.channel_list.iter() {
+            let mut found = false;
+            for entry in self.channels.iter_mut().filter(|e| e.sm.get_channel_name() == def.name) {
+                found = true;
+                if entry.stopped != stop {
+                    entry.stopped = stop;
+                    if stop {
+                        entry.sm.on_channel_stopped(data, events);
+                    } else {
+                        entry.sm.on_channel_started(data, events);
+                    }
+                }
+            }
+            let status = if found {
+                ChannelDefFlags::STATUS_SUCCESS
+            } else {
+                events.push(SMEvent::warn(
+                    ProtoErrorKind::ChannelsManager,
+                    format!("{:?} request for unknown channel {:?}", msg.subtype, def.name),
+                ));
+                ChannelDefFlags::STATUS_FAILURE
+            };
+            defs.push(NowChannelDef::new_with_flags(
+                def.name.clone(),
+                ChannelDefFlags::from(def.flags.value | status),
+            ));
+        }
+
+        msg.subtype
+            .response_type()
+            .map(|subtype| NowChannelMsg::new(subtype, defs))
+    }
+
+    /// Orders responses so that higher priority channels are flushed first.
+    ///
+    /// Responses of the same priority keep their relative order.
+    pub fn prioritize<'a>(&self, to_send: ChannelResponses<'a>) -> Vec<(ChannelName, NowVirtualChannel<'a>)> {
+        self.prioritize_with_ids(to_send)
+            .into_iter()
+            .map(|(_, name, msg)| (name, msg))
+            .collect()
+    }
+
+    /// Same as `prioritize`, keeping the instance each response is sent on.
+    pub fn prioritize_with_ids<'a>(
+        &self,
+        to_send: ChannelResponses<'a>,
+    ) -> Vec<(Option<u8>, ChannelName, NowVirtualChannel<'a>)> {
+        let mut responses = to_send.unpack_with_ids();
+        responses.sort_by_key(|(channel_id, name, _)| {
+            let entry = channel_id
+                .and_then(|id| self.h_find_instance(id))
+                .or_else(|| self.h_find(name));
+            core::cmp::Reverse(
+                entry
+                    .map(|entry| entry.priority)
+                    .unwrap_or_else(|| ChannelPriority::default_for(name)),
+            )
+        });
+        responses
+    }
+
+    /// Frames a message on an open channel, outside of any state machine. The message is
+    /// compressed if a compression was negotiated for the channel.
+    ///
+    /// The message is sent on the first instance not stopped by the peer, see `send_on_instance`
+    /// to pick one.
+    ///
+    /// Fails with `ProtoErrorKind::ChannelNotOpen` if the channel isn't in `channels_ctx` or is
+    /// stopped by the peer, and with `ProtoErrorKind::VirtualChannel` if the message belongs to
+    /// another channel.
+    pub fn send_on<'a>(
+        &self,
+        channels_ctx: &VirtChannelsCtx,
+        name: &ChannelName,
+        msg: impl Into<NowVirtualChannel<'a>>,
+    ) -> Result<NowPacket<'a>, ProtoError> {
+        let channel_id = channels_ctx
+            .get_ids_by_channel(name)
+            .find(|id| !self.h_instance_stopped(*id, name));
+        Self::h_send(channels_ctx, name, channel_id, msg.into())
+    }
+
+    /// Same as `send_on`, on the instance opened with this channel id.
+    pub fn send_on_instance<'a>(
+        &self,
+        channels_ctx: &VirtChannelsCtx,
+        channel_id: u8,
+        msg: impl Into<NowVirtualChannel<'a>>,
+    ) -> Result<NowPacket<'a>, ProtoError> {
+        let msg = msg.into();
+        match channels_ctx.get_channel_by_id(channel_id) {
+            Some(name) => {
+                let channel_id = Some(channel_id).filter(|id| !self.h_instance_stopped(*id, name));
+                Self::h_send(channels_ctx, name, channel_id, msg)
+            }
+            None => {
+                let name = msg.get_name().clone();
+                Self::h_send(channels_ctx, &name, None, msg)
+            }
+        }
+    }
+
+    /// Frames a raw payload on every open channel not stopped by the peer, in channel id order.
+    ///
+    /// Channels whose payload couldn't be compressed are skipped.
+    pub fn broadcast<'a>(&self, channels_ctx: &VirtChannelsCtx, payload: &'a [u8]) -> Vec<NowPacket<'a>> {
+        channels_ctx
+            .iter()
+            .filter(|(channel_id, name)| !self.h_instance_stopped(*channel_id, name))
+            .filter_map(|(channel_id, name)| {
+                let msg = CustomVirtualChannel {
+                    name: name.clone(),
+                    payload: payload.into(),
+                };
+                Self::h_frame(channels_ctx, channel_id, NowVirtualChannel::Custom(msg))
+                    .map_err(|e| log::warn!("{:?} channel skipped from broadcast: {}", name, e))
+                    .ok()
+            })
+            .collect()
     }
 
     pub fn waiting_for_packet(&self) -> bool {
-        for sm in self.state_machines.values() {
-            if !sm.waiting_for_packet() {
-                return false;
+        self.channels
+            .iter()
+            .all(|entry| !entry.is_active() || (entry.sm.waiting_for_packet() && entry.outbox.is_empty()))
+    }
+
+    /// Traffic on the channel since the manager was created, if any message went through it.
+    ///
+    /// Received messages are accounted when dispatched, sent ones when reported to `record_sent`.
+    pub fn channel_stats(&self, name: &ChannelName) -> Option<&ChannelStats> {
+        self.stats.get(name)
+    }
+
+    /// Traffic of every channel a message went through.
+    pub fn all_channel_stats(&self) -> impl Iterator<Item = &ChannelStats> {
+        self.stats.values()
+    }
+
+    /// Accounts a packet produced for sending, packets not on a virtual channel are ignored.
+    pub fn record_sent(&mut self, packet: &NowPacket<'_>) {
+        if let NowBody::VirtualChannel(chan_msg) = &packet.body {
+            let body_len = packet.header.body_len() as u64;
+            let stats = self.h_stats_mut(chan_msg.get_name());
+            stats.messages_out += 1;
+            stats.bytes_out += body_len;
+        }
+    }
+
+    /// The manager doesn't read the clock: channel activity is stamped with a time advanced by
+    /// the embedder, from an origin of its choosing.
+    pub fn advance_time(&mut self, elapsed: Duration) {
+        self.time += elapsed;
+    }
+
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    /// State reported by each registered state machine, in registration order.
+    pub fn state_infos(&self) -> Vec<(ChannelName, Option<StateInfo>)> {
+        self.channels
+            .iter()
+            .map(|entry| (entry.sm.get_channel_name(), entry.sm.state_info()))
+            .collect()
+    }
+
+    fn h_stats_mut(&mut self, name: &ChannelName) -> &mut ChannelStats {
+        let time = self.time;
+        let stats = self
+            .stats
+            .entry(name.clone())
+            .or_insert_with(|| ChannelStats::new(name.clone()));
+        stats.last_activity = Some(time);
+        stats
+    }
+
+    fn h_find(&self, name: &ChannelName) -> Option<&ChannelEntry> {
+        self.channels.iter().find(|entry| entry.sm.get_channel_name() == *name)
+    }
+
+    fn h_find_instance(&self, channel_id: u8) -> Option<&ChannelEntry> {
+        self.channels.iter().find(|entry| entry.channel_id == Some(channel_id))
+    }
+
+    fn h_position(&self, channel_id: Option<u8>, name: &ChannelName) -> Option<usize> {
+        channel_id
+            .and_then(|id| self.channels.iter().position(|entry| entry.channel_id == Some(id)))
+            .or_else(|| {
+                self.channels
+                    .iter()
+                    .position(|entry| entry.sm.get_channel_name() == *name)
+            })
+    }
+
+    fn h_instance_stopped(&self, channel_id: u8, name: &ChannelName) -> bool {
+        match self.h_find_instance(channel_id) {
+            Some(entry) => entry.stopped,
+            None => self.is_stopped(name),
+        }
+    }
+
+    fn h_send<'a>(
+        channels_ctx: &VirtChannelsCtx,
+        name: &ChannelName,
+        channel_id: Option<u8>,
+        msg: NowVirtualChannel<'a>,
+    ) -> Result<NowPacket<'a>, ProtoError> {
+        if msg.get_name() != name {
+            return Err(ProtoError::new(ProtoErrorKind::VirtualChannel(name.clone()))
+                .with_desc(format!("can't send a {:?} message on this channel", msg.get_name())));
+        }
+
+        let channel_id = channel_id.ok_or_else(|| {
+            ProtoError::new(ProtoErrorKind::ChannelNotOpen(name.clone())).with_desc("can't send message")
+        })?;
+
+        Self::h_frame(channels_ctx, channel_id, msg)
+    }
+
+    fn h_frame<'a>(
+        channels_ctx: &VirtChannelsCtx,
+        channel_id: u8,
+        msg: NowVirtualChannel<'a>,
+    ) -> Result<NowPacket<'a>, ProtoError> {
+        let compression = channels_ctx.get_compression(channel_id);
+        if compression == Compression::None {
+            return Ok(NowPacket::from_virt_channel(msg, channel_id));
+        }
+
+        let payload = compression.compress(&msg.encode()?)?;
+        let compressed = CustomVirtualChannelOwned {
+            name: msg.get_name().clone(),
+            payload,
+        };
+        Ok(NowPacket::from_virt_channel(
+            NowVirtualChannel::CustomOwned(compressed),
+            channel_id,
+        ))
+    }
+
+    fn h_next_ready(&self) -> Option<usize> {
+        let ready = || {
+            self.channels
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.is_active() && !entry.sm.waiting_for_packet())
+        };
+
+        if let SchedulingPolicy::Fair { max_skips } = self.policy {
+            let starving = ready()
+                .filter(|(_, entry)| entry.skips >= max_skips)
+                .max_by_key(|(idx, entry)| (entry.skips, core::cmp::Reverse(*idx)));
+            if let Some((idx, _)) = starving {
+                return Some(idx);
             }
         }
-        true
+
+        ready()
+            .max_by_key(|(idx, entry)| (entry.priority, core::cmp::Reverse(*idx)))
+            .map(|(idx, _)| idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::header::AbstractNowHeader;
+    use crate::message::{BodyType, NowBody};
+    use crate::serialization::Encode;
+    use alloc::borrow::Cow;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    struct ReadySM {
+        name: ChannelName,
+        updates: Arc<AtomicUsize>,
+    }
+
+    impl ReadySM {
+        fn new(name: ChannelName) -> (Self, Arc<AtomicUsize>) {
+            let updates = Arc::new(AtomicUsize::new(0));
+            (
+                Self {
+                    name,
+                    updates: updates.clone(),
+                },
+                updates,
+            )
+        }
+    }
+
+    impl VirtualChannelSM for ReadySM {
+        fn get_channel_name(&self) -> ChannelName {
+            self.name.clone()
+        }
+
+        fn is_terminated(&self) -> bool {
+            false
+        }
+
+        fn waiting_for_packet(&self) -> bool {
+            false
+        }
+
+        fn update_without_chan_msg<'msg>(
+            &mut self,
+            _: &mut SMData,
+            _: &mut SMEvents<'msg>,
+            to_send: &mut ChannelResponses<'msg>,
+        ) {
+            self.updates.fetch_add(1, Ordering::SeqCst);
+            to_send.push(NowVirtualChannel::Custom(CustomVirtualChannel {
+                name: self.name.clone(),
+                payload: Cow::Borrowed(&[]),
+            }));
+        }
+
+        fn update_with_chan_msg<'msg: 'a, 'a>(
+            &mut self,
+            _: &mut SMData,
+            _: &mut SMEvents<'msg>,
+            _: &mut ChannelResponses<'msg>,
+            _: &'a NowVirtualChannel<'msg>,
+        ) {
+        }
+
+        fn on_channel_unavailable<'msg>(&mut self, _: &mut SMData, events: &mut SMEvents<'msg>) {
+            events.push(SMEvent::warn(ProtoErrorKind::ChannelsManager, "unavailable"));
+        }
+    }
+
+    struct IdleSM(ChannelName);
+
+    impl VirtualChannelSM for IdleSM {
+        fn get_channel_name(&self) -> ChannelName {
+            self.0.clone()
+        }
+
+        fn is_terminated(&self) -> bool {
+            false
+        }
+
+        fn waiting_for_packet(&self) -> bool {
+            true
+        }
+
+        fn update_without_chan_msg<'msg>(
+            &mut self,
+            _: &mut SMData,
+            _: &mut SMEvents<'msg>,
+            _: &mut ChannelResponses<'msg>,
+        ) {
+            panic!("idle state machine updated");
+        }
+
+        fn update_with_chan_msg<'msg: 'a, 'a>(
+            &mut self,
+            _: &mut SMData,
+            _: &mut SMEvents<'msg>,
+            _: &mut ChannelResponses<'msg>,
+            _: &'a NowVirtualChannel<'msg>,
+        ) {
+        }
+    }
+
+    struct EchoSM(ChannelName);
+
+    impl VirtualChannelSM for EchoSM {
+        fn get_channel_name(&self) -> ChannelName {
+            self.0.clone()
+        }
+
+        fn is_terminated(&self) -> bool {
+            false
+        }
+
+        fn waiting_for_packet(&self) -> bool {
+            true
+        }
+
+        fn update_without_chan_msg<'msg>(
+            &mut self,
+            _: &mut SMData,
+            _: &mut SMEvents<'msg>,
+            _: &mut ChannelResponses<'msg>,
+        ) {
+        }
+
+        fn update_with_chan_msg<'msg: 'a, 'a>(
+            &mut self,
+            _: &mut SMData,
+            _: &mut SMEvents<'msg>,
+            to_send: &mut ChannelResponses<'msg>,
+            msg: &'a NowVirtualChannel<'msg>,
+        ) {
+            to_send.push(msg.clone());
+        }
+    }
+
+    fn update_n_times(manager: &mut ChannelsManager, n: usize) {
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        for _ in 0..n {
+            manager.update_without_virt_msg(&mut data, &mut SMEvents::new(), &mut ChannelResponses::new());
+        }
+    }
+
+    #[test]
+    fn strict_priorities() {
+        let (file_transfer, file_transfer_updates) = ReadySM::new(ChannelName::FileTransfer);
+        let (clipboard, clipboard_updates) = ReadySM::new(ChannelName::Clipboard);
+        let mut manager = ChannelsManager::new()
+            .with_policy(SchedulingPolicy::Strict)
+            .with_sm(file_transfer)
+            .with_sm(clipboard);
+        assert_eq!(manager.priority(&ChannelName::Clipboard), Some(ChannelPriority::High));
+
+        update_n_times(&mut manager, 20);
+        assert_eq!(clipboard_updates.load(Ordering::SeqCst), 20);
+        assert_eq!(file_transfer_updates.load(Ordering::SeqCst), 0);
+
+        assert!(manager.set_priority(&ChannelName::FileTransfer, ChannelPriority::High));
+        update_n_times(&mut manager, 1);
+        assert_eq!(file_transfer_updates.load(Ordering::SeqCst), 1); // registered first
+    }
+
+    #[test]
+    fn fair_scheduling() {
+        let (file_transfer, file_transfer_updates) = ReadySM::new(ChannelName::FileTransfer);
+        let (clipboard, clipboard_updates) = ReadySM::new(ChannelName::Clipboard);
+        let mut manager = ChannelsManager::new()
+            .with_policy(SchedulingPolicy::Fair { max_skips: 3 })
+            .with_sm(file_transfer)
+            .with_sm(clipboard);
+
+        update_n_times(&mut manager, 8);
+        assert_eq!(clipboard_updates.load(Ordering::SeqCst), 6);
+        assert_eq!(file_transfer_updates.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn responses_flush_order() {
+        let (chat, _) = ReadySM::new(ChannelName::Chat);
+        let manager = ChannelsManager::new()
+            .with_sm_and_priority(chat, ChannelPriority::Low)
+            .with_sm(ReadySM::new(ChannelName::Clipboard).0);
+
+        let mut to_send = ChannelResponses::new();
+        for name in [
+            ChannelName::Chat,
+            ChannelName::Unknown(Cow::Borrowed("custom")),
+            ChannelName::Clipboard,
+            ChannelName::Chat,
+        ]
+        .iter()
+        {
+            to_send.set_current_channel_name(name.clone());
+            to_send.push(NowVirtualChannel::Custom(CustomVirtualChannel {
+                name: name.clone(),
+                payload: Cow::Borrowed(&[]),
+            }));
+        }
+
+        let names: Vec<ChannelName> = manager.prioritize(to_send).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(
+            names,
+            [
+                ChannelName::Clipboard,
+                ChannelName::Unknown(Cow::Borrowed("custom")),
+                ChannelName::Chat,
+                ChannelName::Chat,
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_and_start() {
+        let (chat, chat_updates) = ReadySM::new(ChannelName::Chat);
+        let mut manager = ChannelsManager::new().with_sm(chat);
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+
+        let request = |subtype, name| NowChannelMsg::new(subtype, vec![NowChannelDef::new(name)]);
+
+        let mut events = SMEvents::new();
+        let rsp = manager
+            .update_with_channel_msg(
+                &mut data,
+                &mut events,
+                &request(ChannelMessageType::ChannelStopRequest, ChannelName::Chat),
+            )
+            .unwrap();
+        assert_eq!(rsp.subtype, ChannelMessageType::ChannelStopResponse);
+        assert!(rsp.channel_list[0].flags.status_success());
+        assert!(manager.is_stopped(&ChannelName::Chat));
+        assert!(manager.waiting_for_packet());
+
+        update_n_times(&mut manager, 3);
+        assert_eq!(chat_updates.load(Ordering::SeqCst), 0);
+
+        let rsp = manager
+            .update_with_channel_msg(
+                &mut data,
+                &mut events,
+                &request(ChannelMessageType::ChannelStartRequest, ChannelName::Chat),
+            )
+            .unwrap();
+        assert_eq!(rsp.subtype, ChannelMessageType::ChannelStartResponse);
+        assert!(!manager.is_stopped(&ChannelName::Chat));
+        update_n_times(&mut manager, 3);
+        assert_eq!(chat_updates.load(Ordering::SeqCst), 3);
+
+        let rsp = manager
+            .update_with_channel_msg(
+                &mut data,
+                &mut events,
+                &request(ChannelMessageType::ChannelCloseRequest, ChannelName::Tunnel),
+            )
+            .unwrap();
+        assert_eq!(rsp.subtype, ChannelMessageType::ChannelCloseResponse);
+        assert_eq!(rsp.channel_list[0].flags, ChannelDefFlags::STATUS_FAILURE);
+        assert_eq!(events.unpack().len(), 1);
+
+        let mut events = SMEvents::new();
+        let open = NowChannelMsg::new(
+            ChannelMessageType::ChannelOpenRequest,
+            vec![
+                NowChannelDef::new(ChannelName::Chat),
+                NowChannelDef::new(ChannelName::Exec),
+            ],
+        );
+        let rsp = manager.update_with_channel_msg(&mut data, &mut events, &open).unwrap();
+        assert_eq!(rsp.subtype, ChannelMessageType::ChannelOpenResponse);
+        assert_eq!(rsp.channel_list[0].flags, ChannelDefFlags::STATUS_SUCCESS);
+        assert_eq!(rsp.channel_list[1].flags, ChannelDefFlags::STATUS_FAILURE);
+        assert!(matches!(events.unpack().as_slice(), [SMEvent::Warn(_)]));
+    }
+
+    #[test]
+    fn unavailable_channels() {
+        let (chat, chat_updates) = ReadySM::new(ChannelName::Chat);
+        let (tunnel, tunnel_updates) = ReadySM::new(ChannelName::Tunnel);
+        let mut manager = ChannelsManager::new().with_sm(chat).with_sm(tunnel);
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        let mut ctx = VirtChannelsCtx::new();
+        ctx.insert(1, ChannelName::Chat);
+
+        let mut events = SMEvents::new();
+        manager.bind_channels(&mut data, &mut events, &ctx);
+        assert!(manager.is_unavailable(&ChannelName::Tunnel));
+        assert!(!manager.is_unavailable(&ChannelName::Chat));
+        assert_eq!(events.unpack().len(), 1);
+
+        // hook isn't called again for channels still unavailable
+        let mut events = SMEvents::new();
+        manager.bind_channels(&mut data, &mut events, &ctx);
+        assert!(events.unpack().is_empty());
+
+        update_n_times(&mut manager, 3);
+        assert_eq!(chat_updates.load(Ordering::SeqCst), 3);
+        assert_eq!(tunnel_updates.load(Ordering::SeqCst), 0);
+
+        ctx.insert(2, ChannelName::Tunnel);
+        manager.bind_channels(&mut data, &mut SMEvents::new(), &ctx);
+        assert!(!manager.is_unavailable(&ChannelName::Tunnel));
+        manager.set_priority(&ChannelName::Tunnel, ChannelPriority::High);
+        update_n_times(&mut manager, 1);
+        assert_eq!(tunnel_updates.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn command_sender() {
+        use crate::message::NowChatTextMsg;
+
+        let mut manager = ChannelsManager::new()
+            .with_sm(IdleSM(ChannelName::Chat))
+            .with_sm(IdleSM(ChannelName::Clipboard));
+        assert!(manager.command_sender::<NowChatTextMsg>(&ChannelName::Tunnel).is_none());
+        let sender = manager.command_sender(&ChannelName::Chat).unwrap();
+        assert!(manager.waiting_for_packet());
+
+        let text = |text: &str| NowChatTextMsg::new(0, 0, text.parse().unwrap());
+        sender.send(text("hello"));
+        sender.clone().send(text("world"));
+        assert!(!manager.waiting_for_packet());
+
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        let mut events = SMEvents::new();
+        let mut to_send = ChannelResponses::new();
+        manager.update_without_virt_msg(&mut data, &mut events, &mut to_send);
+        assert!(events.peek().is_empty());
+        assert!(manager.waiting_for_packet());
+        let sent = to_send.unpack();
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|(name, _)| *name == ChannelName::Chat));
+
+        // kept while stopped
+        let stop = NowChannelMsg::new(
+            ChannelMessageType::ChannelStopRequest,
+            vec![NowChannelDef::new(ChannelName::Chat)],
+        );
+        manager.update_with_channel_msg(&mut data, &mut events, &stop);
+        sender.send(text("later"));
+        assert!(manager.waiting_for_packet());
+        let mut to_send = ChannelResponses::new();
+        manager.update_without_virt_msg(&mut data, &mut SMEvents::new(), &mut to_send);
+        assert!(to_send.peek().is_empty());
+    }
+
+    #[test]
+    fn send_on_and_broadcast() {
+        use crate::message::NowChatTextMsg;
+
+        let mut manager = ChannelsManager::new()
+            .with_sm(IdleSM(ChannelName::Chat))
+            .with_sm(IdleSM(ChannelName::Clipboard));
+        let mut ctx = VirtChannelsCtx::new();
+        ctx.insert(1, ChannelName::Clipboard);
+        ctx.insert(3, ChannelName::Chat);
+
+        let text = NowChatTextMsg::new(0, 0, "hello".parse().unwrap());
+        let packet = manager.send_on(&ctx, &ChannelName::Chat, text.clone()).unwrap();
+        assert!(matches!(
+            packet.body,
+            NowBody::VirtualChannel(NowVirtualChannel::Chat(_))
+        ));
+        assert_eq!(packet.packet_len(), packet.encode().unwrap().len());
+
+        let err = manager
+            .send_on(&ctx, &ChannelName::Clipboard, text.clone())
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::VirtualChannel);
+        let custom = CustomVirtualChannel {
+            name: ChannelName::Tunnel,
+            payload: Cow::Borrowed(&[0x2a]),
+        };
+        let err = manager
+            .send_on(&ctx, &ChannelName::Tunnel, NowVirtualChannel::Custom(custom))
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::ChannelNotOpen);
+
+        let packets = manager.broadcast(&ctx, &[0x2a]);
+        assert_eq!(packets.len(), 2);
+        let channel_ids: Vec<BodyType> = packets.iter().map(|packet| packet.header.body_type()).collect();
+        assert_eq!(channel_ids, [BodyType::VirtualChannel(1), BodyType::VirtualChannel(3)]);
+
+        let stop = NowChannelMsg::new(
+            ChannelMessageType::ChannelStopRequest,
+            vec![NowChannelDef::new(ChannelName::Chat)],
+        );
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        manager.update_with_channel_msg(&mut data, &mut SMEvents::new(), &stop);
+        let err = manager.send_on(&ctx, &ChannelName::Chat, text).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::ChannelNotOpen);
+        assert_eq!(manager.broadcast(&ctx, &[0x2a]).len(), 1);
+    }
+
+    #[test]
+    fn compressed_send_on() {
+        use crate::message::{NowChatMsg, NowChatTextMsg};
+        use crate::packet::NowPacketAccumulator;
+        use crate::serialization::DecodeCtx;
+
+        let compression = match Compression::AVAILABLE.first() {
+            Some(compression) => *compression,
+            None => return,
+        };
+
+        let manager = ChannelsManager::new().with_sm(IdleSM(ChannelName::Chat));
+        let mut ctx = VirtChannelsCtx::new();
+        ctx.insert(3, ChannelName::Chat);
+        ctx.set_compression(3, compression);
+
+        let repeated = "hello ".repeat(32);
+        let text = NowChatTextMsg::new(0, 0, repeated.parse().unwrap());
+        let packet = manager.send_on(&ctx, &ChannelName::Chat, text.clone()).unwrap();
+        assert!(matches!(
+            packet.body,
+            NowBody::VirtualChannel(NowVirtualChannel::CustomOwned(_))
+        ));
+        assert!(packet.packet_len() < NowPacket::from_virt_channel(text, 3).packet_len());
+
+        let encoded = packet.encode().unwrap();
+        let mut acc = NowPacketAccumulator::new();
+        acc.accumulate(&encoded);
+        let decode_ctx = DecodeCtx::default().with_channels(&ctx);
+        let (packet, raw) = acc.next_packet_with_raw(&decode_ctx).unwrap().unwrap();
+        // raw bytes are the compressed ones, as received
+        assert_eq!(raw, &encoded[..]);
+        match packet.body {
+            NowBody::VirtualChannel(NowVirtualChannel::Chat(NowChatMsg::Text(msg))) => {
+                assert_eq!(msg.text, repeated)
+            }
+            body => panic!("expected a chat text message and got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn multiple_instances() {
+        let mut manager = ChannelsManager::new()
+            .with_sm(EchoSM(ChannelName::Tunnel))
+            .with_sm_instance(EchoSM(ChannelName::Tunnel))
+            .with_sm(IdleSM(ChannelName::Chat));
+        let mut ctx = VirtChannelsCtx::new();
+        ctx.insert(6, ChannelName::Tunnel);
+        ctx.insert(3, ChannelName::Chat);
+        ctx.insert(4, ChannelName::Tunnel);
+        manager.bind_channels(
+            &mut SMData::new(Vec::new(), Vec::new(), Vec::new()),
+            &mut SMEvents::new(),
+            &ctx,
+        );
+        assert_eq!(manager.instances(&ChannelName::Tunnel), [4, 6]);
+        assert_eq!(ctx.get_ids_by_channel(&ChannelName::Tunnel).collect::<Vec<_>>(), [4, 6]);
+
+        let msg = NowVirtualChannel::Custom(CustomVirtualChannel {
+            name: ChannelName::Tunnel,
+            payload: Cow::Borrowed(&[0x2a]),
+        });
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        let mut to_send = ChannelResponses::new();
+        manager.update_with_instance_msg(6, &mut data, &mut SMEvents::new(), &mut to_send, &msg);
+        manager.update_with_virt_msg(&mut data, &mut SMEvents::new(), &mut to_send, &msg);
+        let responses = manager.prioritize_with_ids(to_send);
+        let channel_ids: Vec<Option<u8>> = responses.iter().map(|(channel_id, _, _)| *channel_id).collect();
+        assert_eq!(channel_ids, [Some(6), Some(4)]);
+
+        let packet = manager.send_on_instance(&ctx, 6, msg.clone()).unwrap();
+        assert_eq!(packet.header.body_type(), BodyType::VirtualChannel(6));
+        let packet = manager.send_on(&ctx, &ChannelName::Tunnel, msg.clone()).unwrap();
+        assert_eq!(packet.header.body_type(), BodyType::VirtualChannel(4));
+        let err = manager.send_on_instance(&ctx, 3, msg.clone()).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::VirtualChannel);
+        let err = manager.send_on_instance(&ctx, 9, msg).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::ChannelNotOpen);
+
+        ctx.remove(&ChannelName::Tunnel);
+        assert_eq!(ctx.iter().count(), 1);
+    }
+
+    #[test]
+    fn channel_stats() {
+        let mut manager = ChannelsManager::new().with_sm(EchoSM(ChannelName::Tunnel));
+        let mut ctx = VirtChannelsCtx::new();
+        ctx.insert(2, ChannelName::Tunnel);
+        manager.bind_channels(
+            &mut SMData::new(Vec::new(), Vec::new(), Vec::new()),
+            &mut SMEvents::new(),
+            &ctx,
+        );
+        assert!(manager.channel_stats(&ChannelName::Tunnel).is_none());
+
+        let msg = NowVirtualChannel::Custom(CustomVirtualChannel {
+            name: ChannelName::Tunnel,
+            payload: Cow::Borrowed(&[0x01, 0x02, 0x03]),
+        });
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        let mut to_send = ChannelResponses::new();
+        manager.update_with_virt_msg(&mut data, &mut SMEvents::new(), &mut to_send, &msg);
+        manager.advance_time(Duration::from_secs(2));
+        for (_, name, rsp) in manager.prioritize_with_ids(to_send) {
+            let packet = manager.send_on(&ctx, &name, rsp).unwrap();
+            manager.record_sent(&packet);
+        }
+
+        let stats = manager.channel_stats(&ChannelName::Tunnel).unwrap();
+        assert_eq!(stats.messages_in, 1);
+        assert_eq!(stats.bytes_in, 3);
+        assert_eq!(stats.messages_out, 1);
+        assert_eq!(stats.bytes_out, 3);
+        assert_eq!(stats.last_activity, Some(Duration::from_secs(2)));
+        assert_eq!(manager.all_channel_stats().count(), 1);
     }
 }
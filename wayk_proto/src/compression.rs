@@ -0,0 +1,179 @@
+//! Compression of virtual channel payloads.
+//!
+//! Compression is offered per channel during the connection sequence, with the `COMPRESS_*`
+//! flags of `ChannelDefFlags`, and the server answers the method it picked in its open response.
+//! Peers not aware of these flags never set them, in which case payloads are left as is.
+//!
+//! Methods are only available with their feature: `deflate` and `zstd`.
+//!
+//! Compressed payloads are handled transparently: `ChannelsManager::send_on` compresses messages
+//! of channels negotiated as such, and `NowPacketAccumulator` decompresses them before decoding.
+
+use crate::error::{ProtoError, ProtoErrorKind, Result};
+use crate::message::ChannelDefFlags;
+use alloc::vec::Vec;
+
+/// Compression method of a virtual channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Compression {
+    None,
+    Deflate,
+    Zstd,
+}
+
+impl Compression {
+    /// Methods available in this build, preferred first.
+    pub const AVAILABLE: &'static [Compression] = &[
+        #[cfg(feature = "zstd")]
+        Compression::Zstd,
+        #[cfg(feature = "deflate")]
+        Compression::Deflate,
+    ];
+
+    /// Channel definition flag offering or selecting this method.
+    pub fn flag(self) -> u32 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => ChannelDefFlags::COMPRESS_DEFLATE,
+            Compression::Zstd => ChannelDefFlags::COMPRESS_ZSTD,
+        }
+    }
+
+    /// Flags offering every method available in this build.
+    pub fn offer_flags() -> u32 {
+        Self::AVAILABLE.iter().fold(0, |flags, method| flags | method.flag())
+    }
+
+    /// Method selected by the flags answered by the peer.
+    ///
+    /// Falls back to `Compression::None` if the peer didn't select any method available in this
+    /// build, and picks the preferred one if it selected several.
+    pub fn negotiate(answered: ChannelDefFlags) -> Self {
+        Self::AVAILABLE
+            .iter()
+            .copied()
+            .find(|method| answered.value & method.flag() != 0)
+            .unwrap_or(Compression::None)
+    }
+
+    pub fn is_available(self) -> bool {
+        self == Compression::None || Self::AVAILABLE.contains(&self)
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => Ok(miniz_oxide::deflate::compress_to_vec(data, DEFLATE_LEVEL)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(ruzstd::encoding::compress_to_vec(
+                data,
+                ruzstd::encoding::CompressionLevel::Fastest,
+            )),
+            #[allow(unreachable_patterns)]
+            unavailable => Err(ProtoError::new(ProtoErrorKind::Encoding(__type_str!(Compression)))
+                .with_desc(format!("{:?} support not compiled in", unavailable))),
+        }
+    }
+
+    /// Fails with `ProtoErrorKind::LimitExceeded` if the decompressed payload is longer than `max_len`.
+    pub fn decompress(self, data: &[u8], max_len: usize) -> Result<Vec<u8>> {
+        match self {
+            Compression::None if data.len() > max_len => Err(h_too_large(max_len)),
+            Compression::None => Ok(data.to_vec()),
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => {
+                use miniz_oxide::inflate::TINFLStatus;
+
+                miniz_oxide::inflate::decompress_to_vec_with_limit(data, max_len).map_err(|e| match e.status {
+                    TINFLStatus::HasMoreOutput => h_too_large(max_len),
+                    status => ProtoError::new(ProtoErrorKind::Decoding(__type_str!(Compression)))
+                        .with_desc(format!("invalid deflate stream: {:?}", status)),
+                })
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => h_zstd_decompress(data, max_len),
+            #[allow(unreachable_patterns)]
+            unavailable => Err(ProtoError::new(ProtoErrorKind::Decoding(__type_str!(Compression)))
+                .with_desc(format!("{:?} support not compiled in", unavailable))),
+        }
+    }
+}
+
+#[cfg(feature = "deflate")]
+const DEFLATE_LEVEL: u8 = 6;
+
+fn h_too_large(max_len: usize) -> ProtoError {
+    ProtoError::new(ProtoErrorKind::LimitExceeded(__type_str!(Compression)))
+        .with_desc(format!("decompressed payload longer than {} bytes", max_len))
+}
+
+#[cfg(feature = "zstd")]
+fn h_zstd_decompress(data: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    use alloc::string::String;
+    use ruzstd::io::Read;
+
+    let invalid = |desc: String| ProtoError::new(ProtoErrorKind::Decoding(__type_str!(Compression))).with_desc(desc);
+
+    let mut decoder =
+        ruzstd::decoding::StreamingDecoder::new(data).map_err(|e| invalid(format!("invalid zstd frame: {:?}", e)))?;
+
+    let mut decompressed = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        let read = decoder
+            .read(&mut chunk)
+            .map_err(|e| invalid(format!("invalid zstd stream: {:?}", e)))?;
+        if read == 0 {
+            return Ok(decompressed);
+        }
+        if decompressed.len() + read > max_len {
+            return Err(h_too_large(max_len));
+        }
+        decompressed.extend_from_slice(&chunk[..read]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    const PAYLOAD: &[u8] = b"clipboard clipboard clipboard clipboard clipboard clipboard clipboard";
+
+    #[test]
+    fn round_trip() {
+        for &method in &[Compression::None, Compression::Deflate, Compression::Zstd] {
+            if !method.is_available() {
+                assert!(method.compress(PAYLOAD).is_err());
+                continue;
+            }
+            let compressed = method.compress(PAYLOAD).unwrap();
+            if method != Compression::None {
+                assert!(compressed.len() < PAYLOAD.len());
+            }
+            assert_eq!(method.decompress(&compressed, PAYLOAD.len()).unwrap(), PAYLOAD);
+
+            let err = method.decompress(&compressed, PAYLOAD.len() - 1).unwrap_err();
+            assert!(err.has_code(ErrorCode::LimitExceeded));
+        }
+    }
+
+    #[test]
+    fn negotiation_fallback() {
+        // peer unaware of compression
+        assert_eq!(Compression::negotiate(ChannelDefFlags::new_empty()), Compression::None);
+        // low byte is the channel id
+        assert_eq!(
+            Compression::negotiate(ChannelDefFlags::from(0x0000_00ff)),
+            Compression::None
+        );
+
+        let both = ChannelDefFlags::from(ChannelDefFlags::COMPRESS_DEFLATE | ChannelDefFlags::COMPRESS_ZSTD);
+        let expected = Compression::AVAILABLE.first().copied().unwrap_or(Compression::None);
+        assert_eq!(Compression::negotiate(both), expected);
+        assert_eq!(Compression::offer_flags() & 0xff, 0);
+    }
+}
@@ -1,6 +1,12 @@
 macro_rules! impl_container {
     ($ty:ident as Vec with $size_ty:ident) => {
         #[derive(PartialEq, Debug, Clone)]
+        #[cfg_attr(
+            feature = "serde",
+            derive(serde::Serialize, serde::Deserialize),
+            serde(transparent)
+        )]
+        #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
         pub struct $ty<Item>(pub ::alloc::vec::Vec<Item>);
 
         impl<Item> ::core::ops::Deref for $ty<Item> {
@@ -44,15 +50,23 @@ macro_rules! impl_container {
             }
         }
 
+        impl<Item: crate::message::IntoOwned> crate::message::IntoOwned for $ty<Item> {
+            type Owned = $ty<Item::Owned>;
+
+            fn into_owned(self) -> Self::Owned {
+                $ty(crate::message::IntoOwned::into_owned(self.0))
+            }
+        }
+
         impl<Item> From<::alloc::vec::Vec<Item>> for $ty<Item> {
             fn from(v: ::alloc::vec::Vec<Item>) -> Self {
                 Self(v)
             }
         }
 
-        impl<Item> Into<::alloc::vec::Vec<Item>> for $ty<Item> {
-            fn into(self) -> ::alloc::vec::Vec<Item> {
-                self.0
+        impl<Item> From<$ty<Item>> for ::alloc::vec::Vec<Item> {
+            fn from(v: $ty<Item>) -> Self {
+                v.0
             }
         }
 
@@ -108,15 +122,25 @@ macro_rules! impl_container {
             Item: $crate::serialization::Decode<'dec>,
         {
             fn decode_from(cursor: &mut $crate::io::Cursor<'dec>) -> Result<Self, $crate::error::ProtoError> {
+                Self::decode_with_ctx(cursor, &$crate::serialization::DecodeCtx::default())
+            }
+
+            fn decode_with_ctx(
+                cursor: &mut $crate::io::Cursor<'dec>,
+                ctx: &$crate::serialization::DecodeCtx<'_>,
+            ) -> Result<Self, $crate::error::ProtoError> {
                 use $crate::error::*;
 
                 let count = <$size_ty>::decode_from(cursor)
                     .chain($crate::error::ProtoErrorKind::Decoding(stringify!($ty)))
                     .or_desc("couldn't decode list count")?;
-                let mut vec = ::alloc::vec::Vec::new();
+                let limit_count = ::core::convert::TryFrom::try_from(count).unwrap_or(usize::MAX);
+                cursor.limits().check_items(limit_count, stringify!($ty))?;
+                // items are at least one byte long in practice, which bounds preallocation on bogus counts
+                let mut vec = ::alloc::vec::Vec::with_capacity(limit_count.min(cursor.remaining()));
                 for i in 0..count {
                     vec.push(
-                        Item::decode_from(cursor)
+                        Item::decode_with_ctx(cursor, ctx)
                             .chain($crate::error::ProtoErrorKind::Decoding(stringify!($ty)))
                             .or_else_desc(|| format!("couldn't decode item n°{}", i))?,
                     );
@@ -125,21 +149,31 @@ macro_rules! impl_container {
             }
         }
     };
-    ($ty:ident as &[u8] with $size_ty:ident) => {
+    ($ty:ident as bytes with $size_ty:ident) => {
+        /// Borrowed when decoded, owned when built from a `Vec<u8>` or deserialized from a format
+        /// that can't lend bytes.
         #[derive(PartialEq, Debug, Clone)]
-        pub struct $ty<'a>(pub &'a [u8]);
+        #[cfg_attr(
+            feature = "serde",
+            derive(serde::Serialize, serde::Deserialize),
+            serde(transparent)
+        )]
+        #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+        pub struct $ty<'a>(
+            #[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] pub ::alloc::borrow::Cow<'a, [u8]>,
+        );
 
-        impl<'a> ::core::ops::Deref for $ty<'a> {
-            type Target = &'a [u8];
+        impl ::core::ops::Deref for $ty<'_> {
+            type Target = [u8];
 
             fn deref(&self) -> &Self::Target {
                 &self.0
             }
         }
 
-        impl<'a> ::core::iter::IntoIterator for &'a $ty<'a> {
-            type Item = &'a u8;
-            type IntoIter = ::alloc::slice::Iter<'a, u8>;
+        impl<'b> ::core::iter::IntoIterator for &'b $ty<'_> {
+            type Item = &'b u8;
+            type IntoIter = ::alloc::slice::Iter<'b, u8>;
 
             fn into_iter(self) -> Self::IntoIter {
                 self.0.iter()
@@ -148,19 +182,39 @@ macro_rules! impl_container {
 
         impl<'a> From<&'a [u8]> for $ty<'a> {
             fn from(v: &'a [u8]) -> Self {
-                Self(v)
+                Self(::alloc::borrow::Cow::Borrowed(v))
             }
         }
 
-        impl<'a> Into<&'a [u8]> for $ty<'a> {
-            fn into(self) -> &'a [u8] {
-                self.0
+        impl crate::message::IntoOwned for $ty<'_> {
+            type Owned = $ty<'static>;
+
+            fn into_owned(self) -> Self::Owned {
+                $ty(crate::message::IntoOwned::into_owned(self.0))
             }
         }
 
-        impl<'a> PartialEq<&'a [u8]> for $ty<'a> {
-            fn eq(&self, other: &&'a [u8]) -> bool {
-                self.0.eq(*other)
+        impl From<::alloc::vec::Vec<u8>> for $ty<'_> {
+            fn from(v: ::alloc::vec::Vec<u8>) -> Self {
+                Self(::alloc::borrow::Cow::Owned(v))
+            }
+        }
+
+        impl<'a> From<$ty<'a>> for ::alloc::borrow::Cow<'a, [u8]> {
+            fn from(v: $ty<'a>) -> Self {
+                v.0
+            }
+        }
+
+        impl From<$ty<'_>> for ::alloc::vec::Vec<u8> {
+            fn from(v: $ty<'_>) -> Self {
+                v.0.into_owned()
+            }
+        }
+
+        impl PartialEq<&[u8]> for $ty<'_> {
+            fn eq(&self, other: &&[u8]) -> bool {
+                *self.0 == **other
             }
         }
 
@@ -190,7 +244,7 @@ macro_rules! impl_container {
                         )
                     })?;
                 count.encode_into(writer)?;
-                writer.write_all(self.0)?;
+                writer.write_all(&self.0)?;
                 Ok(())
             }
         }
@@ -202,6 +256,8 @@ macro_rules! impl_container {
                 let count = <$size_ty>::decode_from(cursor)
                     .chain($crate::error::ProtoErrorKind::Decoding(stringify!($ty)))
                     .or_desc("couldn't decode list count")?;
+                let limit_count = ::core::convert::TryFrom::try_from(count).unwrap_or(usize::MAX);
+                cursor.limits().check_bytes(limit_count, stringify!($ty))?;
                 let start_inclusive = cursor.position() as usize;
                 let slices_to_end = &cursor.get_ref()[start_inclusive..];
                 if slices_to_end.len() < count as usize {
@@ -214,7 +270,8 @@ macro_rules! impl_container {
                     );
                 }
                 let bytes = &slices_to_end[..count as usize];
-                Ok($ty(bytes))
+                cursor.forward(bytes.len());
+                Ok($ty(::alloc::borrow::Cow::Borrowed(bytes)))
             }
         }
     };
@@ -225,15 +282,17 @@ impl_container! { Vec16 as Vec with u16 }
 impl_container! { Vec32 as Vec with u32 }
 impl_container! { Vec64 as Vec with u64 }
 
-impl_container! { Bytes8  as &[u8] with u8  }
-impl_container! { Bytes16 as &[u8] with u16 }
-impl_container! { Bytes32 as &[u8] with u32 }
-impl_container! { Bytes64 as &[u8] with u64 }
+impl_container! { Bytes8  as bytes with u8  }
+impl_container! { Bytes16 as bytes with u16 }
+impl_container! { Bytes32 as bytes with u32 }
+impl_container! { Bytes64 as bytes with u64 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::serialization::{Decode, Encode};
+    use crate::error::ProtoErrorKind;
+    use crate::io::Cursor;
+    use crate::serialization::{Decode, DecodeLimits, Encode};
 
     const U16_VEC8: [u8; 7] = [0x03, 0x50, 0x10, 0x0a, 0x09, 0x57, 0x0b];
 
@@ -276,7 +335,7 @@ mod tests {
 
     #[test]
     fn encode_bytes8() {
-        let slice = Bytes8(&ENCODED_MSG_WITH_BYTES8[4..=9]);
+        let slice = Bytes8::from(&ENCODED_MSG_WITH_BYTES8[4..=9]);
         assert_eq!(slice.encode().unwrap(), &ENCODED_MSG_WITH_BYTES8[3..=9]);
     }
 
@@ -297,7 +356,7 @@ mod tests {
 
     #[test]
     fn encode_bytes32() {
-        let slice = Bytes32(&ENCODED_MSG_WITH_BYTES32[7..=12]);
+        let slice = Bytes32::from(&ENCODED_MSG_WITH_BYTES32[7..=12]);
         assert_eq!(slice.encode().unwrap(), &ENCODED_MSG_WITH_BYTES32[3..=12]);
     }
 
@@ -308,4 +367,39 @@ mod tests {
             &ENCODED_MSG_WITH_BYTES32[7..=12]
         );
     }
+
+    #[test]
+    fn decode_vec32_count_over_limit() {
+        let limits = DecodeLimits {
+            max_items: 2,
+            ..DecodeLimits::DEFAULT
+        };
+        let err = Vec32::<u16>::decode_from(&mut Cursor::with_limits(&U16_VEC32, limits)).unwrap_err();
+        assert!(matches!(err.kind, ProtoErrorKind::LimitExceeded("Vec32")));
+
+        // absurd count is rejected before decoding any item
+        let _lock = crate::serialization::GLOBAL_LIMITS_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let err = Vec32::<u16>::decode(&[0xff, 0xff, 0xff, 0xff]).unwrap_err();
+        assert!(matches!(err.kind, ProtoErrorKind::LimitExceeded("Vec32")));
+    }
+
+    #[test]
+    fn decode_bytes32_size_over_limit() {
+        let limits = DecodeLimits {
+            max_bytes: 5,
+            ..DecodeLimits::DEFAULT
+        };
+        let mut cursor = Cursor::with_limits(&ENCODED_MSG_WITH_BYTES32[3..], limits);
+        let err = Bytes32::decode_from(&mut cursor).unwrap_err();
+        assert!(matches!(err.kind, ProtoErrorKind::LimitExceeded("Bytes32")));
+    }
+
+    #[test]
+    fn decode_bytes8_advances_cursor() {
+        let mut cursor = crate::io::Cursor::new(&ENCODED_MSG_WITH_BYTES8[3..]);
+        Bytes8::decode_from(&mut cursor).unwrap();
+        assert_eq!(cursor.read_rest().unwrap(), &ENCODED_MSG_WITH_BYTES8[10..]);
+    }
 }
@@ -0,0 +1,317 @@
+//! Sans-IO facade over a sharee.
+//!
+//! A [`Driver`](struct.Driver.html) owns the sharee and the packet accumulator and never touches
+//! any transport: bytes read by the embedder go in through `handle_input`, and everything the
+//! embedder has to do in return (bytes to write, timers to arm, application events) comes back
+//! as [`Actions`](struct.Actions.html). This makes it easy to run the protocol from any event loop,
+//! be it mio, a browser or an embedded executor.
+//!
+//! ```
+//! use wayk_proto::driver::Driver;
+//! use wayk_proto::message::{MessageType, NowActivateMsg};
+//! use wayk_proto::packet::NowPacket;
+//! use wayk_proto::serialization::Encode;
+//! use wayk_proto::sharee::{Sharee, ShareeState};
+//! use wayk_proto::sm::DummyConnectionSM;
+//!
+//! let mut driver = Driver::new(Sharee::builder(DummyConnectionSM).build_unchecked());
+//! let actions = driver.start();
+//! assert!(actions.to_send.is_empty());
+//! assert_eq!(driver.sharee().get_state(), ShareeState::Active);
+//!
+//! let bytes = NowPacket::from_message(NowActivateMsg::default()).encode()?;
+//! let actions = driver.handle_input(&bytes);
+//! assert!(actions.events.is_empty());
+//! # Ok::<(), wayk_proto::error::ProtoError>(())
+//! ```
+
+use crate::error::ProtoError;
+use crate::packet::{NowPacket, NowPacketAccumulator};
+use crate::sharee::Sharee;
+use crate::sm::{ConnectionSM, SMEvent};
+use crate::timer::{TimerAction, TimerId};
+use alloc::vec::Vec;
+
+/// What the embedder has to do after feeding the driver.
+#[derive(Default)]
+pub struct Actions {
+    /// Encoded packets to write to the transport, in order.
+    pub to_send: Vec<u8>,
+    /// Timers to arm or cancel, in order. Expired timers are reported with `handle_timeout`.
+    pub timers: Vec<TimerAction>,
+    /// Application events, `PacketToSend` and timer events excluded since they end up in
+    /// `to_send` and `timers`.
+    pub events: Vec<SMEvent<'static>>,
+}
+
+impl Actions {
+    pub fn is_empty(&self) -> bool {
+        self.to_send.is_empty() && self.timers.is_empty() && self.events.is_empty()
+    }
+
+    fn h_push_events(&mut self, events: Vec<SMEvent<'_>>) {
+        for event in events {
+            let event = match event {
+                SMEvent::PacketToSend(packet) => {
+                    if let Err(e) = h_encode(&mut self.to_send, &packet) {
+                        self.events.push(SMEvent::Fatal(e));
+                    }
+                    continue;
+                }
+                SMEvent::SetTimer(request) => {
+                    self.timers.push(TimerAction::Set(request));
+                    continue;
+                }
+                SMEvent::CancelTimer(id) => {
+                    self.timers.push(TimerAction::Cancel(id));
+                    continue;
+                }
+                SMEvent::StateTransition(state) => SMEvent::StateTransition(state),
+                SMEvent::Connection(e) => SMEvent::Connection(e),
+                SMEvent::Chat(e) => SMEvent::Chat(e),
+                SMEvent::Clipboard(e) => SMEvent::Clipboard(e),
+                SMEvent::Access(e) => SMEvent::Access(e),
+                SMEvent::System(e) => SMEvent::System(e),
+                SMEvent::Surface(e) => SMEvent::Surface(e),
+                SMEvent::Display(e) => SMEvent::Display(e),
+                SMEvent::Data(data) => SMEvent::Data(data),
+                SMEvent::Warn(e) => SMEvent::Warn(e),
+                SMEvent::Error(e) => SMEvent::Error(e),
+                SMEvent::Fatal(e) => SMEvent::Fatal(e),
+            };
+            self.events.push(event);
+        }
+    }
+}
+
+fn h_encode(buf: &mut Vec<u8>, packet: &NowPacket<'_>) -> Result<(), ProtoError> {
+    use crate::serialization::Encode;
+
+    buf.reserve(packet.encoded_size_hint());
+    let len = buf.len();
+    packet.encode_into(buf).inspect_err(|_| buf.truncate(len))
+}
+
+/// Sans-IO sharee driver, see the [module documentation](index.html).
+pub struct Driver<ConnectionSeq> {
+    sharee: Sharee<ConnectionSeq>,
+    accumulator: NowPacketAccumulator<'static>,
+    /// Set when the input stream can't be decoded any further.
+    failed: bool,
+}
+
+impl<ConnectionSeq> Driver<ConnectionSeq>
+where
+    ConnectionSeq: ConnectionSM,
+{
+    /// Packets whose body can't be decoded are skipped and reported as warnings.
+    pub fn new(sharee: Sharee<ConnectionSeq>) -> Self {
+        let mut accumulator = NowPacketAccumulator::new();
+        accumulator.set_lenient(true);
+        Self {
+            sharee,
+            accumulator,
+            failed: false,
+        }
+    }
+
+    pub fn sharee(&self) -> &Sharee<ConnectionSeq> {
+        &self.sharee
+    }
+
+    /// Events returned by direct calls on the sharee should go through `handle_events`.
+    pub fn sharee_mut(&mut self) -> &mut Sharee<ConnectionSeq> {
+        &mut self.sharee
+    }
+
+    /// Exposes the packet size limit and the accumulator counters.
+    pub fn accumulator(&self) -> &NowPacketAccumulator<'static> {
+        &self.accumulator
+    }
+
+    pub fn accumulator_mut(&mut self) -> &mut NowPacketAccumulator<'static> {
+        &mut self.accumulator
+    }
+
+    pub fn into_sharee(self) -> Sharee<ConnectionSeq> {
+        self.sharee
+    }
+
+    /// `true` once the session is over, either terminated or broken by undecodable input.
+    pub fn is_terminated(&self) -> bool {
+        self.failed || self.sharee.is_terminated()
+    }
+
+    /// Runs the sharee until it waits for the peer. Call it once the transport is connected.
+    pub fn start(&mut self) -> Actions {
+        let mut actions = Actions::default();
+        self.h_pump(&mut actions);
+        actions
+    }
+
+    /// Feeds bytes read from the transport, possibly partial packets.
+    pub fn handle_input(&mut self, bytes: &[u8]) -> Actions {
+        self.accumulator.accumulate(bytes);
+        let mut actions = Actions::default();
+        self.h_pump(&mut actions);
+        actions
+    }
+
+    /// Reports a timer requested in `Actions::timers` as expired.
+    pub fn handle_timeout(&mut self, id: TimerId) -> Actions {
+        let mut actions = Actions::default();
+        if !self.is_terminated() {
+            actions.h_push_events(self.sharee.on_timer_expired(id));
+            self.h_pump(&mut actions);
+        }
+        actions
+    }
+
+    /// Processes events returned by a direct call on the sharee, e.g. `Sharee::terminate`.
+    pub fn handle_events(&mut self, events: Vec<SMEvent<'_>>) -> Actions {
+        let mut actions = Actions::default();
+        actions.h_push_events(events);
+        self.h_pump(&mut actions);
+        actions
+    }
+
+    fn h_pump(&mut self, actions: &mut Actions) {
+        while !self.is_terminated() {
+            if !self.sharee.waiting_for_packet() {
+                actions.h_push_events(self.sharee.update_without_body());
+                continue;
+            }
+
+            let next = self.accumulator.next_packet_with_ctx(&self.sharee.decode_ctx());
+            match next {
+                Some(Ok(packet)) => actions.h_push_events(self.sharee.update_with_packet(&packet)),
+                Some(Err(e)) => {
+                    self.sharee.report_decode_error(&e);
+                    actions.events.push(SMEvent::Fatal(e));
+                    self.failed = true;
+                }
+                None => break,
+            }
+        }
+
+        for failure in self.accumulator.take_decode_failures() {
+            self.sharee.report_decode_error(&failure.error);
+            actions.events.push(SMEvent::Warn(failure.error));
+        }
+        self.accumulator.purge_old_packets();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{AbstractNowHeader, NowHeader};
+    use crate::message::{
+        DisconnectStatusCode, MessageType, NowActivateMsg, NowBody, NowMessage, NowStatus, NowTerminateMsg,
+    };
+    use crate::serialization::{Decode, Encode};
+    use crate::sharee::{Disconnected, ShareeState};
+    use crate::sm::{ClientConnectionSeqSM, DummyConnectionSM};
+
+    #[test]
+    fn handshake_sent_on_start() {
+        let mut driver = Driver::new(Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM)).build_unchecked());
+        let actions = driver.start();
+
+        let header = NowHeader::decode(&actions.to_send).unwrap();
+        assert_eq!(
+            header.body_type(),
+            crate::message::BodyType::Message(MessageType::Handshake)
+        );
+        assert!(driver.start().to_send.is_empty());
+    }
+
+    #[test]
+    fn split_input() {
+        let mut driver = Driver::new(Sharee::builder(DummyConnectionSM).build_unchecked());
+        driver.start();
+
+        let status = NowStatus::builder(DisconnectStatusCode::IdleTimeout).build();
+        let mut input = NowPacket::from_message(NowActivateMsg::default()).encode().unwrap();
+        input.extend(NowPacket::from_message(NowTerminateMsg::new(status)).encode().unwrap());
+
+        let (first, second) = input.split_at(10);
+        assert!(driver.handle_input(first).is_empty());
+        let actions = driver.handle_input(second);
+
+        assert!(driver.is_terminated());
+        assert_eq!(driver.sharee().get_state(), ShareeState::Final);
+        let ack = NowPacket::decode_from(
+            NowHeader::decode(&actions.to_send).unwrap(),
+            &actions.to_send[NowHeader::decode(&actions.to_send).unwrap().len()..],
+            driver.sharee().get_channels_ctx(),
+        )
+        .unwrap();
+        assert!(matches!(ack.body, NowBody::Message(NowMessage::Terminate(_))));
+        assert!(actions
+            .events
+            .iter()
+            .any(|e| e.downcast_data::<Disconnected>().is_some()));
+    }
+
+    #[test]
+    fn response_timeout() {
+        let sharee = Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM))
+            .response_timeout(core::time::Duration::from_secs(1))
+            .build_unchecked();
+        let mut driver = Driver::new(sharee);
+
+        let id = match driver.start().timers.as_slice() {
+            [TimerAction::Set(request)] => request.id,
+            timers => panic!("expected a single timer and got {:?}", timers),
+        };
+        let actions = driver.handle_timeout(id);
+        assert!(actions.events.iter().any(|e| matches!(e, SMEvent::Fatal(_))));
+        assert!(driver.is_terminated());
+    }
+
+    #[test]
+    fn registered_message() {
+        use crate::registry::{CustomMessage, MessageRegistry, RegisteredMessage};
+
+        #[derive(Encode, Decode, Debug, Clone, PartialEq)]
+        struct Ping {
+            sequence: u32,
+        }
+
+        impl CustomMessage for Ping {
+            const MESSAGE_TYPE: u8 = 0xA0;
+        }
+
+        let sharee = Sharee::builder(DummyConnectionSM)
+            .message_registry(MessageRegistry::new().with_message::<Ping>().unwrap())
+            .build_unchecked();
+        let mut driver = Driver::new(sharee);
+        driver.start();
+
+        let mut input = NowPacket::from_message(NowActivateMsg::default()).encode().unwrap();
+        input.extend(
+            NowPacket::from_message(RegisteredMessage::new(Ping { sequence: 3 }))
+                .encode()
+                .unwrap(),
+        );
+        let actions = driver.handle_input(&input);
+        let msg = actions
+            .events
+            .iter()
+            .find_map(|e| e.downcast_data::<RegisteredMessage>())
+            .unwrap();
+        assert_eq!(msg.downcast_ref(), Some(&Ping { sequence: 3 }));
+    }
+
+    #[test]
+    fn local_termination() {
+        let mut driver = Driver::new(Sharee::builder(DummyConnectionSM).build_unchecked());
+        driver.start();
+
+        let events = driver.sharee_mut().terminate(DisconnectStatusCode::ByLocalUser);
+        let actions = driver.handle_events(events);
+        assert!(!actions.to_send.is_empty());
+        assert_eq!(driver.sharee().get_state(), ShareeState::Terminating);
+    }
+}
@@ -1,4 +1,5 @@
-use crate::message::{ChannelName, MessageType};
+use crate::auth::token::SessionTokenError;
+use crate::message::{AccessControlCode, ChannelName, MessageType};
 use crate::sharee::ShareeState;
 use crate::sm::ConnectionState;
 use core::fmt;
@@ -6,7 +7,7 @@ use core::num::TryFromIntError;
 
 pub type Result<T> = core::result::Result<T, ProtoError>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ProtoError {
     pub kind: ProtoErrorKind,
     pub description: Option<alloc::borrow::Cow<'static, str>>,
@@ -40,28 +41,52 @@ impl ProtoError {
         }
     }
 
-    #[cfg(feature = "std")]
-    pub fn print_trace(&self) {
-        print!("–– Error trace: ");
-        self.__print_trace();
-        println!();
+    pub fn code(&self) -> ErrorCode {
+        self.kind.code()
     }
 
-    #[cfg(feature = "std")]
-    fn __print_trace(&self) {
-        print!("{}", self.kind);
+    /// Iterates over this error and its sources, outermost first.
+    pub fn iter_chain(&self) -> ErrorChain<'_> {
+        ErrorChain { next: Some(self) }
+    }
 
-        if let Some(desc) = &self.description {
-            print!(" [description: {}]", desc);
-        }
+    /// Innermost error of the chain, usually the one describing what actually went wrong.
+    pub fn root(&self) -> &ProtoError {
+        self.iter_chain().last().unwrap_or(self)
+    }
 
-        if let Some(source) = &self.source {
-            print!("\n\t↳ source: ");
-            source.__print_trace();
-        }
+    pub fn root_kind(&self) -> &ProtoErrorKind {
+        &self.root().kind
+    }
+
+    /// `true` if any error of the chain has the given code.
+    pub fn has_code(&self, code: ErrorCode) -> bool {
+        self.iter_chain().any(|e| e.code() == code)
+    }
+
+    /// `true` if the chain contains a decoding failure of the type named `type_name`.
+    ///
+    /// ```
+    /// use wayk_proto::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt};
+    ///
+    /// let res: Result<(), _> = Err(ProtoError::new(ProtoErrorKind::Decoding("u16")));
+    /// let err = res.chain(ProtoErrorKind::Decoding("NowCapset")).unwrap_err();
+    /// assert!(err.is_decoding_of("NowCapset"));
+    /// assert!(err.is_decoding_of("u16"));
+    /// assert!(!err.is_decoding_of("NowPacket"));
+    /// ```
+    pub fn is_decoding_of(&self, type_name: &str) -> bool {
+        self.iter_chain()
+            .any(|e| matches!(e.kind, ProtoErrorKind::Decoding(name) if name == type_name))
+    }
+
+    /// Renders the whole chain, one error per line.
+    pub fn to_report(&self) -> alloc::string::String {
+        format!("–– Error trace: {:#}", self)
     }
 }
 
+/// Renders the whole chain on a single line. The alternate flag (`{:#}`) puts each source on its own line.
 impl fmt::Display for ProtoError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.kind)?;
@@ -71,13 +96,41 @@ impl fmt::Display for ProtoError {
         }
 
         if let Some(source) = &self.source {
-            write!(f, " [source: {}]", source)?;
+            if f.alternate() {
+                write!(f, "\n\t↳ source: {:#}", source)?;
+            } else {
+                write!(f, " [source: {}]", source)?;
+            }
         }
 
         Ok(())
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for ProtoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn std::error::Error + 'static))
+    }
+}
+
+/// Iterator over a `ProtoError` and its sources, see `ProtoError::iter_chain`.
+pub struct ErrorChain<'a> {
+    next: Option<&'a ProtoError>,
+}
+
+impl<'a> Iterator for ErrorChain<'a> {
+    type Item = &'a ProtoError;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+        self.next = current.source.as_deref();
+        Some(current)
+    }
+}
+
 pub trait ProtoErrorResultExt<T>
 where
     Self: core::marker::Sized,
@@ -134,14 +187,31 @@ impl<T> ProtoErrorResultExt<T> for core::result::Result<T, ProtoError> {
     }
 
     fn chain(self, kind: ProtoErrorKind) -> core::result::Result<T, ProtoError> {
-        self.map_err(|err| ProtoError {
-            kind,
-            description: None,
-            source: Some(alloc::boxed::Box::new(err)),
+        self.map_err(|err| {
+            #[cfg(feature = "tracing")]
+            trace_codec_failure(&kind, &err);
+
+            ProtoError {
+                kind,
+                description: None,
+                source: Some(alloc::boxed::Box::new(err)),
+            }
         })
     }
 }
 
+/// Reports each level of an encode / decode failure as it propagates up.
+#[cfg(feature = "tracing")]
+fn trace_codec_failure(kind: &ProtoErrorKind, source: &ProtoError) {
+    let span = match kind {
+        ProtoErrorKind::Decoding(type_name) => tracing::debug_span!("decode", type_name = *type_name),
+        ProtoErrorKind::Encoding(type_name) => tracing::debug_span!("encode", type_name = *type_name),
+        _ => return,
+    };
+    let _enter = span.enter();
+    tracing::debug!(code = ?source.code(), description = ?source.description, "{}", source.kind);
+}
+
 impl<T> ProtoErrorResultExt<T> for Option<T> {
     fn chain(self, kind: ProtoErrorKind) -> core::result::Result<T, ProtoError> {
         self.ok_or_else(|| ProtoError::from(kind))
@@ -183,19 +253,58 @@ impl From<std::io::Error> for ProtoError {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub enum ProtoErrorKind {
     Decoding(&'static str),
     Encoding(&'static str),
     ConnectionSequence(ConnectionState),
     VirtualChannel(ChannelName),
+    /// Virtual channel not opened during the connection sequence, or stopped by the peer.
+    ChannelNotOpen(ChannelName),
     ChannelsManager,
     UnexpectedMessage(MessageType),
     Sharee(ShareeState),
+    AccessControl(AccessControlCode),
+    /// Token based authentication failed, see `ShareeBuilder::session_token`.
+    SessionToken(SessionTokenError),
     Io(crate::io::NoStdIoError),
     FromUtf8(alloc::string::FromUtf8Error),
     IntConversion(TryFromIntError),
+    LimitExceeded(&'static str),
+    /// Size announced by the packet header doesn't match its body.
+    LengthMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// Packet header announces a packet longer than allowed.
+    PacketTooLarge {
+        len: usize,
+        max: usize,
+    },
+}
+
+impl ProtoErrorKind {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ProtoErrorKind::Decoding(_) => ErrorCode::Decoding,
+            ProtoErrorKind::Encoding(_) => ErrorCode::Encoding,
+            ProtoErrorKind::ConnectionSequence(_) => ErrorCode::ConnectionSequence,
+            ProtoErrorKind::VirtualChannel(_) => ErrorCode::VirtualChannel,
+            ProtoErrorKind::ChannelNotOpen(_) => ErrorCode::ChannelNotOpen,
+            ProtoErrorKind::ChannelsManager => ErrorCode::ChannelsManager,
+            ProtoErrorKind::UnexpectedMessage(_) => ErrorCode::UnexpectedMessage,
+            ProtoErrorKind::Sharee(_) => ErrorCode::Sharee,
+            ProtoErrorKind::AccessControl(_) => ErrorCode::AccessControl,
+            ProtoErrorKind::SessionToken(_) => ErrorCode::SessionToken,
+            ProtoErrorKind::Io(_) => ErrorCode::Io,
+            ProtoErrorKind::FromUtf8(_) => ErrorCode::FromUtf8,
+            ProtoErrorKind::IntConversion(_) => ErrorCode::IntConversion,
+            ProtoErrorKind::LimitExceeded(_) => ErrorCode::LimitExceeded,
+            ProtoErrorKind::LengthMismatch { .. } => ErrorCode::LengthMismatch,
+            ProtoErrorKind::PacketTooLarge { .. } => ErrorCode::PacketTooLarge,
+        }
+    }
 }
 
 impl fmt::Display for ProtoErrorKind {
@@ -205,12 +314,141 @@ impl fmt::Display for ProtoErrorKind {
             ProtoErrorKind::Encoding(desc) => write!(f, "couldn't encode {}", desc),
             ProtoErrorKind::ConnectionSequence(state) => write!(f, "connection sequence failed at state {:?}", state),
             ProtoErrorKind::VirtualChannel(name) => write!(f, "virtual channel {:?} failed", name),
+            ProtoErrorKind::ChannelNotOpen(name) => write!(f, "virtual channel {:?} is not open", name),
             ProtoErrorKind::ChannelsManager => write!(f, "virtual channels manager failed"),
             ProtoErrorKind::UnexpectedMessage(packet) => write!(f, "unexpected {:?} message", packet),
             ProtoErrorKind::Sharee(state) => write!(f, "sharee error in state {:?}", state),
+            ProtoErrorKind::AccessControl(code) => write!(f, "access control {:?} failed", code),
+            ProtoErrorKind::SessionToken(e) => write!(f, "session token authentication failed: {}", e),
             ProtoErrorKind::Io(e) => write!(f, "io error: {}", e),
             ProtoErrorKind::FromUtf8(e) => write!(f, "couldn't parse utf8 string: {}", e),
             ProtoErrorKind::IntConversion(e) => write!(f, "integer conversion failed: {}", e),
+            ProtoErrorKind::LimitExceeded(desc) => write!(f, "decode limits exceeded by {}", desc),
+            ProtoErrorKind::LengthMismatch { expected, actual } => {
+                write!(f, "length mismatch (expected {} bytes, got {})", expected, actual)
+            }
+            ProtoErrorKind::PacketTooLarge { len, max } => {
+                write!(f, "packet too large ({} bytes, at most {} allowed)", len, max)
+            }
         }
     }
 }
+
+/// Payload-less counterpart of `ProtoErrorKind`, handy to branch on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    Decoding,
+    Encoding,
+    ConnectionSequence,
+    VirtualChannel,
+    ChannelNotOpen,
+    ChannelsManager,
+    UnexpectedMessage,
+    Sharee,
+    AccessControl,
+    SessionToken,
+    Io,
+    FromUtf8,
+    IntConversion,
+    LimitExceeded,
+    LengthMismatch,
+    PacketTooLarge,
+}
+
+impl ErrorCode {
+    /// Malformed or oversized data (wire format issue).
+    pub fn is_codec(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::Decoding
+                | ErrorCode::Encoding
+                | ErrorCode::FromUtf8
+                | ErrorCode::IntConversion
+                | ErrorCode::LimitExceeded
+                | ErrorCode::LengthMismatch
+                | ErrorCode::PacketTooLarge
+        )
+    }
+
+    /// Well-formed data not allowed by the protocol at this point.
+    pub fn is_protocol_violation(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::ConnectionSequence
+                | ErrorCode::VirtualChannel
+                | ErrorCode::ChannelNotOpen
+                | ErrorCode::ChannelsManager
+                | ErrorCode::UnexpectedMessage
+                | ErrorCode::Sharee
+                | ErrorCode::AccessControl
+        )
+    }
+
+    pub fn is_io(self) -> bool {
+        self == ErrorCode::Io
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn decoding_error() -> ProtoError {
+        let res: Result<()> = Err(ProtoError::new(ProtoErrorKind::LimitExceeded("NowCapabilitiesMsg")));
+        res.chain(ProtoErrorKind::Decoding("NowCapset"))
+            .or_desc("couldn't decode capset")
+            .chain(ProtoErrorKind::Decoding("NowPacket"))
+            .unwrap_err()
+    }
+
+    #[test]
+    fn error_chain() {
+        let err = decoding_error();
+        assert_eq!(err.code(), ErrorCode::Decoding);
+        assert_eq!(err.iter_chain().count(), 3);
+        assert_eq!(err.root().code(), ErrorCode::LimitExceeded);
+        assert!(matches!(
+            err.root_kind(),
+            ProtoErrorKind::LimitExceeded("NowCapabilitiesMsg")
+        ));
+        assert!(err.has_code(ErrorCode::LimitExceeded));
+        assert!(!err.has_code(ErrorCode::Io));
+        assert!(err.is_decoding_of("NowCapset"));
+        assert!(!err.is_decoding_of("NowCapabilitiesMsg"));
+        assert!(err.code().is_codec());
+        assert!(!err.code().is_protocol_violation());
+
+        let single = ProtoError::new(ProtoErrorKind::ChannelsManager);
+        assert_eq!(single.iter_chain().count(), 1);
+        assert!(single.root().code().is_protocol_violation());
+    }
+
+    #[test]
+    fn report() {
+        let err = decoding_error();
+        assert_eq!(
+            err.to_string(),
+            "couldn't decode NowPacket [source: couldn't decode NowCapset [description: couldn't decode capset] \
+             [source: decode limits exceeded by NowCapabilitiesMsg]]"
+        );
+        assert_eq!(
+            err.to_report(),
+            "–– Error trace: couldn't decode NowPacket\n\
+             \t↳ source: couldn't decode NowCapset [description: couldn't decode capset]\n\
+             \t↳ source: decode limits exceeded by NowCapabilitiesMsg"
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn std_error_source() {
+        use std::error::Error as _;
+
+        let err = decoding_error();
+        let source = err.source().unwrap().downcast_ref::<ProtoError>().unwrap();
+        assert!(matches!(source.kind, ProtoErrorKind::Decoding("NowCapset")));
+        assert!(source.source().unwrap().source().is_none());
+    }
+}
@@ -0,0 +1,204 @@
+//! Protocol extensions negotiation.
+//!
+//! Extensions are advertised during the capabilities exchange through the `NowExtension`
+//! capset. The [`ExtensionRegistry`](struct.ExtensionRegistry.html) remembers what the peer
+//! advertised; this knowledge can be exported and restored on reconnection so that
+//! application code can query it right away, before a new capabilities exchange.
+
+use crate::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt, Result};
+use crate::message::{ExtensionCapset, NowCapset, NowString64};
+use crate::serialization::{Decode, Encode};
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtensionRegistry {
+    local: BTreeSet<String>,
+    peer: Option<BTreeSet<String>>,
+}
+
+impl ExtensionRegistry {
+    /// Extensions are advertised in a capset list prefixed by an `u8` count.
+    pub const MAX_EXTENSIONS: usize = u8::MAX as usize;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a locally supported extension. Names are limited to 64 characters and
+    /// no more than [`MAX_EXTENSIONS`](#associatedconstant.MAX_EXTENSIONS) can be registered.
+    pub fn register(&mut self, name: &str) -> Result<()> {
+        NowString64::from_str(name)
+            .chain(ProtoErrorKind::Encoding(__type_str!(ExtensionRegistry)))
+            .or_else_desc(|| format!("invalid extension name {}", name))?;
+        if self.local.len() >= Self::MAX_EXTENSIONS && !self.local.contains(name) {
+            return Err(
+                ProtoError::new(ProtoErrorKind::Encoding(__type_str!(ExtensionRegistry))).with_desc(format!(
+                    "can't register {}: {} extensions already registered",
+                    name,
+                    Self::MAX_EXTENSIONS
+                )),
+            );
+        }
+        self.local.insert(name.to_string());
+        Ok(())
+    }
+
+    pub fn with_extension(mut self, name: &str) -> Result<Self> {
+        self.register(name)?;
+        Ok(self)
+    }
+
+    pub fn local_extensions(&self) -> impl Iterator<Item = &str> {
+        self.local.iter().map(String::as_str)
+    }
+
+    pub fn peer_extensions(&self) -> impl Iterator<Item = &str> {
+        self.peer.iter().flatten().map(String::as_str)
+    }
+
+    /// `true` once the peer extensions are known, either negotiated or restored.
+    pub fn is_peer_known(&self) -> bool {
+        self.peer.is_some()
+    }
+
+    pub fn peer_supports(&self, name: &str) -> bool {
+        self.peer.as_ref().map(|peer| peer.contains(name)).unwrap_or(false)
+    }
+
+    /// Whether the extension is supported by both ends.
+    pub fn is_supported(&self, name: &str) -> bool {
+        self.local.contains(name) && self.peer_supports(name)
+    }
+
+    /// Capset advertising the local extensions.
+    pub fn local_capset(&self) -> NowCapset<'static> {
+        let extensions = self
+            .local
+            .iter()
+            .map(|name| NowString64::from_str(name).expect("validated on registration"))
+            .collect();
+        NowCapset::Extension(ExtensionCapset::new_with_extensions(extensions))
+    }
+
+    /// Updates peer knowledge from its capabilities.
+    ///
+    /// A peer not sending the extension capset doesn't support any extension.
+    pub fn update_from_capabilities(&mut self, capabilities: &[NowCapset<'_>]) {
+        let peer = capabilities
+            .iter()
+            .filter_map(|capset| match capset {
+                NowCapset::Extension(capset) => Some(capset),
+                _ => None,
+            })
+            .flat_map(|capset| capset.extensions.iter())
+            .map(|name| name.as_str().to_string())
+            .collect();
+        self.peer = Some(peer);
+    }
+
+    pub fn forget_peer(&mut self) {
+        self.peer = None;
+    }
+
+    /// Serializes the peer extensions so they can be restored on reconnection.
+    pub fn export_peer(&self) -> Result<Vec<u8>> {
+        let extensions = self
+            .peer_extensions()
+            .map(|name| NowString64::from_str(name).chain(ProtoErrorKind::Encoding(__type_str!(ExtensionRegistry))))
+            .collect::<Result<Vec<_>>>()?;
+        ExtensionCapset::new_with_extensions(extensions).encode()
+    }
+
+    pub fn restore_peer(&mut self, exported: &[u8]) -> Result<()> {
+        let capset = ExtensionCapset::decode(exported)
+            .chain(ProtoErrorKind::Decoding(__type_str!(ExtensionRegistry)))
+            .or_desc("invalid exported peer extensions")?;
+        self.peer = Some(capset.extensions.iter().map(|name| name.as_str().to_string()).collect());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{NowCapabilitiesMsg, TransportCapset};
+
+    fn peer_capabilities() -> Vec<u8> {
+        let peer = ExtensionRegistry::new()
+            .with_extension("devolutions.files")
+            .unwrap()
+            .with_extension("acme.ink")
+            .unwrap();
+        NowCapabilitiesMsg::new_with_capabilities(vec![
+            NowCapset::Transport(TransportCapset::default()),
+            peer.local_capset(),
+        ])
+        .encode()
+        .unwrap()
+    }
+
+    #[test]
+    fn negotiate_extensions() {
+        let mut registry = ExtensionRegistry::new().with_extension("devolutions.files").unwrap();
+        assert!(!registry.is_peer_known());
+        assert!(!registry.is_supported("devolutions.files"));
+
+        let encoded = peer_capabilities();
+        let msg = NowCapabilitiesMsg::decode(&encoded).unwrap();
+        registry.update_from_capabilities(&msg.capabilities);
+
+        assert!(registry.is_peer_known());
+        assert!(registry.is_supported("devolutions.files"));
+        assert!(registry.peer_supports("acme.ink"));
+        assert!(!registry.is_supported("acme.ink"));
+    }
+
+    #[test]
+    fn restore_after_reconnect() {
+        let mut registry = ExtensionRegistry::new().with_extension("devolutions.files").unwrap();
+        let encoded = peer_capabilities();
+        let msg = NowCapabilitiesMsg::decode(&encoded).unwrap();
+        registry.update_from_capabilities(&msg.capabilities);
+        let exported = registry.export_peer().unwrap();
+
+        let mut reconnected = ExtensionRegistry::new().with_extension("devolutions.files").unwrap();
+        reconnected.restore_peer(&exported).unwrap();
+        assert_eq!(reconnected, registry);
+        assert!(reconnected.is_supported("devolutions.files"));
+    }
+
+    #[test]
+    fn peer_without_extension_capset() {
+        let mut registry = ExtensionRegistry::new().with_extension("devolutions.files").unwrap();
+        registry.update_from_capabilities(&[NowCapset::Transport(TransportCapset::default())]);
+        assert!(registry.is_peer_known());
+        assert!(!registry.is_supported("devolutions.files"));
+    }
+
+    #[test]
+    fn extension_name_too_long() {
+        let name = "x".repeat(65);
+        assert!(ExtensionRegistry::new().register(&name).is_err());
+    }
+
+    #[test]
+    fn too_many_extensions() {
+        let mut registry = ExtensionRegistry::new();
+        for i in 0..ExtensionRegistry::MAX_EXTENSIONS {
+            registry.register(&format!("acme.ext{}", i)).unwrap();
+        }
+        assert!(registry.register("acme.ext0").is_ok());
+        assert!(registry.register("acme.one_too_many").is_err());
+
+        let encoded = NowCapabilitiesMsg::new_with_capabilities(vec![registry.local_capset()])
+            .encode()
+            .unwrap();
+        let msg = NowCapabilitiesMsg::decode(&encoded).unwrap();
+        let mut peer = ExtensionRegistry::new();
+        peer.update_from_capabilities(&msg.capabilities);
+        assert_eq!(peer.peer_extensions().count(), ExtensionRegistry::MAX_EXTENSIONS);
+    }
+}
@@ -3,8 +3,11 @@ use crate::io::{Cursor, NoStdWrite};
 use crate::message::{BodyType, MessageType};
 use crate::serialization::{Decode, Encode};
 use alloc::boxed::Box;
+use core::convert::TryFrom;
 
-const HEADER_VIRTUAL_CHANNEL_FLAG: u8 = 0x01;
+pub(crate) const HEADER_VIRTUAL_CHANNEL_FLAG: u8 = 0x01;
+// set in the last byte of short headers, that is the most significant bit of long headers body size
+const HEADER_SHORT_FLAG: u8 = 0x80;
 
 #[allow(clippy::len_without_is_empty)] // it doesn't make sense in our case
 pub trait AbstractNowHeader {
@@ -16,7 +19,7 @@ pub trait AbstractNowHeader {
     fn packet_len(&self) -> usize;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum NowHeader {
     Short(NowShortHeader),
     Long(NowLongHeader),
@@ -30,9 +33,7 @@ impl Decode<'_> for NowHeader {
             .chain(ProtoErrorKind::Decoding(__type_str!(NowHeader)))
             .or_desc("couldn't read short bit (no enough bytes provided")?;
 
-        let is_short = buffer[3] > 7;
-
-        if is_short {
+        if buffer[3] & HEADER_SHORT_FLAG != 0 {
             Ok(NowHeader::Short(NowShortHeader::decode_from(cursor)?))
         } else {
             Ok(NowHeader::Long(NowLongHeader::decode_from(cursor)?))
@@ -64,8 +65,11 @@ impl Encode for NowHeader {
 }
 
 impl NowHeader {
+    /// Picks a short header whenever the body is small enough.
+    ///
+    /// Bodies larger than `NowLongHeader::MAX_BODY_LEN` can't be encoded, see `for_body`.
     pub fn new(body_type: BodyType, body_len: u32) -> Self {
-        if body_len > u32::from(u16::max_value()) {
+        if body_len > u32::from(NowShortHeader::MAX_BODY_LEN) {
             Self::Long(NowLongHeader::new(body_type, body_len))
         } else {
             Self::Short(NowShortHeader::new(body_type, body_len as u16))
@@ -80,6 +84,35 @@ impl NowHeader {
         Self::new(BodyType::VirtualChannel(channel_id), body_len)
     }
 
+    /// Header sized for `body`, fails if the body is too large to be sent in a single packet.
+    pub fn for_body<B: Encode>(body_type: BodyType, body: &B) -> Result<Self> {
+        let body_len = body.encoded_len();
+        match u32::try_from(body_len) {
+            Ok(body_len) if body_len <= NowLongHeader::MAX_BODY_LEN => Ok(Self::new(body_type, body_len)),
+            _ => Err(ProtoError::new(ProtoErrorKind::Encoding(__type_str!(NowHeader)))
+                .with_desc(format!("body too large ({} bytes)", body_len))),
+        }
+    }
+
+    /// Size of the header picked by `new` for a body of `body_len` bytes.
+    pub fn len_for_body(body_len: usize) -> usize {
+        if body_len > usize::from(NowShortHeader::MAX_BODY_LEN) {
+            NowLongHeader::SIZE
+        } else {
+            NowShortHeader::SIZE
+        }
+    }
+
+    /// Size of the header starting with `prefix`, `None` if less than `NowShortHeader::SIZE` bytes are provided.
+    pub fn header_len(prefix: &[u8]) -> Option<usize> {
+        let short_bit = prefix.get(NowShortHeader::SIZE - 1)?;
+        if short_bit & HEADER_SHORT_FLAG != 0 {
+            Some(NowShortHeader::SIZE)
+        } else {
+            Some(NowLongHeader::SIZE)
+        }
+    }
+
     #[cfg(feature = "std")]
     pub fn read_from<R: std::io::Read>(reader: &mut R) -> Result<Self> {
         let (bytes, short_bit) = {
@@ -90,7 +123,7 @@ impl NowHeader {
                 .chain(ProtoErrorKind::Decoding(__type_str!(NowHeader)))
                 .or_desc("couldn't read short bit (no enough bytes provided")?;
 
-            let is_short = buffer[3] > 7;
+            let is_short = buffer[3] & HEADER_SHORT_FLAG != 0;
 
             if !is_short {
                 buffer.append(&mut vec![0u8; 2]);
@@ -211,11 +244,7 @@ impl Decode<'_> for NowShortHeader {
         let body_len = u16::decode_from(cursor)?;
         let body_type_raw = u8::decode_from(cursor)?;
         let flags = u8::decode_from(cursor)?;
-        let body_type = if flags & HEADER_VIRTUAL_CHANNEL_FLAG != 0 {
-            BodyType::VirtualChannel(body_type_raw)
-        } else {
-            BodyType::Message(MessageType::decode(&[body_type_raw])?)
-        };
+        let body_type = BodyType::from_raw(flags, body_type_raw)?;
 
         Ok(Self {
             body_len,
@@ -227,10 +256,10 @@ impl Decode<'_> for NowShortHeader {
 
 impl NowShortHeader {
     pub const SIZE: usize = 4;
+    pub const MAX_BODY_LEN: u16 = u16::MAX;
 
     pub fn new(body_type: BodyType, body_len: u16) -> Self {
-        let flags = 0x80 // short bit flag
-            | if let BodyType::VirtualChannel { .. } = body_type { HEADER_VIRTUAL_CHANNEL_FLAG } else { 0x00 };
+        let flags = HEADER_SHORT_FLAG | body_type.header_flags();
 
         Self {
             flags,
@@ -258,7 +287,7 @@ impl AbstractNowHeader for NowShortHeader {
     }
 
     fn flags(&self) -> u8 {
-        self.flags & 0b0111_1111 // unset the short bit flag
+        self.flags & !HEADER_SHORT_FLAG
     }
 
     fn body_type(&self) -> BodyType {
@@ -274,23 +303,48 @@ impl AbstractNowHeader for NowShortHeader {
     }
 }
 
-#[derive(Debug, Encode, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct NowLongHeader {
     body_len: u32,
     flags: u8,
     body_type: BodyType,
 }
 
+impl Encode for NowLongHeader {
+    fn expected_size() -> crate::serialization::ExpectedSize
+    where
+        Self: Sized,
+    {
+        crate::serialization::ExpectedSize::Known(Self::SIZE)
+    }
+
+    fn encoded_len(&self) -> usize {
+        Self::SIZE
+    }
+
+    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+        if self.body_len > Self::MAX_BODY_LEN {
+            return Err(ProtoError::new(ProtoErrorKind::Encoding(__type_str!(NowLongHeader)))
+                .with_desc(format!("body too large ({} bytes)", self.body_len)));
+        }
+
+        self.body_len.encode_into(writer)?;
+        self.flags.encode_into(writer)?;
+        self.body_type.encode_into(writer)
+    }
+}
+
 impl Decode<'_> for NowLongHeader {
     fn decode_from(cursor: &mut Cursor<'_>) -> Result<Self> {
         let body_len = u32::decode_from(cursor)?;
+        if body_len > Self::MAX_BODY_LEN {
+            return Err(ProtoError::new(ProtoErrorKind::Decoding(__type_str!(NowLongHeader)))
+                .with_desc("short bit set in long header"));
+        }
+
         let flags = u8::decode_from(cursor)?;
 
-        let body_type = if flags & HEADER_VIRTUAL_CHANNEL_FLAG != 0 {
-            BodyType::VirtualChannel(u8::decode_from(cursor)?)
-        } else {
-            BodyType::Message(MessageType::decode_from(cursor)?)
-        };
+        let body_type = BodyType::decode_with_flags(flags, cursor)?;
 
         Ok(Self {
             body_len,
@@ -302,15 +356,13 @@ impl Decode<'_> for NowLongHeader {
 
 impl NowLongHeader {
     pub const SIZE: usize = 6;
+    /// The most significant bit of the size is the short bit.
+    pub const MAX_BODY_LEN: u32 = 0x7FFF_FFFF;
 
     pub fn new(body_type: BodyType, body_size: u32) -> Self {
         Self {
-            body_len: body_size & 0b0111_1111_1111_1111u32, // unset short bit flag
-            flags: if let BodyType::VirtualChannel { .. } = body_type {
-                HEADER_VIRTUAL_CHANNEL_FLAG
-            } else {
-                0x00
-            },
+            body_len: body_size,
+            flags: body_type.header_flags(),
             body_type,
         }
     }
@@ -438,4 +490,61 @@ mod tests {
         let header = NowHeader::new_with_virt_channel(0x01, 16);
         assert_eq!([0x10, 0x00, 0x01, 0x81], header.encode().unwrap()[..]);
     }
+
+    #[test]
+    fn header_body_len_boundaries() {
+        let cases: [(u32, &[u8]); 5] = [
+            (0xFFFF, &[0xff, 0xff, 0x42, 0x80]),
+            (0x0001_0000, &[0x00, 0x00, 0x01, 0x00, 0x00, 0x42]),
+            (0x0100_0000, &[0x00, 0x00, 0x00, 0x01, 0x00, 0x42]), // 16 MiB
+            (0x0800_0000, &[0x00, 0x00, 0x00, 0x08, 0x00, 0x42]),
+            (NowLongHeader::MAX_BODY_LEN, &[0xff, 0xff, 0xff, 0x7f, 0x00, 0x42]),
+        ];
+
+        for (body_len, encoded) in cases.iter() {
+            let header = NowHeader::new_with_msg_type(MessageType::Update, *body_len);
+            assert_eq!(header.encode().unwrap(), *encoded, "body len {:#x}", body_len);
+            assert_eq!(NowHeader::header_len(encoded), Some(encoded.len()));
+
+            let decoded = NowHeader::decode(encoded).unwrap();
+            assert_eq!(decoded, header);
+            assert_eq!(decoded.body_len(), *body_len as usize);
+            assert_eq!(decoded.body_type(), BodyType::Message(MessageType::Update));
+        }
+    }
+
+    #[test]
+    fn header_body_too_large() {
+        let header = NowHeader::new_with_msg_type(MessageType::Update, NowLongHeader::MAX_BODY_LEN + 1);
+        assert!(header.encode().is_err());
+        assert!(NowLongHeader::decode(&[0x00, 0x00, 0x00, 0x80, 0x00, 0x42]).is_err());
+        assert_eq!(NowHeader::header_len(&[0x00, 0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn header_for_body() {
+        let body = [0u8; 300];
+        let header = NowHeader::for_body(BodyType::VirtualChannel(0x02), &&body[..]).unwrap();
+        assert!(header.is_short());
+        assert_eq!(header.body_len(), 300);
+        assert_eq!(header.flags(), HEADER_VIRTUAL_CHANNEL_FLAG);
+    }
+
+    #[test]
+    fn body_type_from_header_fields() {
+        let channel = BodyType::from_raw(0x81, 0x03).unwrap();
+        assert_eq!(channel, BodyType::VirtualChannel(0x03));
+        assert!(channel.is_virtual_channel());
+        assert_eq!(channel.channel_id(), Some(0x03));
+        assert_eq!(channel.message_type(), None);
+        assert_eq!(channel.header_flags(), HEADER_VIRTUAL_CHANNEL_FLAG);
+
+        let message = BodyType::decode_with_flags(0x00, &mut Cursor::new(&[0x42])).unwrap();
+        assert_eq!(message, BodyType::Message(MessageType::Update));
+        assert!(!message.is_virtual_channel());
+        assert_eq!(message.message_type(), Some(MessageType::Update));
+        assert_eq!(message.header_flags(), 0x00);
+
+        assert_eq!(BodyType::decode(&message.encode().unwrap()).unwrap(), message);
+    }
 }
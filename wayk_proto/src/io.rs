@@ -1,6 +1,6 @@
+use crate::serialization::DecodeLimits;
 use alloc::borrow::Cow;
 use alloc::fmt;
-use core::convert::TryInto;
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 #[non_exhaustive]
@@ -188,15 +188,175 @@ impl NoStdWrite for alloc::vec::Vec<u8> {
     }
 }
 
+#[cfg(not(feature = "std"))]
+impl NoStdWrite for &mut [u8] {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, NoStdIoError> {
+        let n = buf.len().min(self.len());
+        let (dst, rest) = core::mem::take(self).split_at_mut(n);
+        dst.copy_from_slice(&buf[..n]);
+        *self = rest;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), NoStdIoError> {
+        Ok(())
+    }
+}
+
+/// Subset of `std::io::Read` for no_std support
+///
+/// Integers are read in little endian, the byte order of the protocol, unless the method name says
+/// otherwise.
+pub trait NoStdRead {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, NoStdIoError>;
+
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), NoStdIoError> {
+        while !buf.is_empty() {
+            match self.read(buf) {
+                Ok(0) => {
+                    return Err(NoStdIoError::new_with_desc(
+                        NoStdIoErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ));
+                }
+                Ok(n) => buf = &mut buf[n..],
+                Err(ref e) if e.kind() == NoStdIoErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, NoStdIoError> {
+        let mut buf = [0; 1];
+        self.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, NoStdIoError> {
+        self.read_u16_le()
+    }
+
+    fn read_u32(&mut self) -> Result<u32, NoStdIoError> {
+        self.read_u32_le()
+    }
+
+    fn read_u64(&mut self) -> Result<u64, NoStdIoError> {
+        self.read_u64_le()
+    }
+
+    fn read_i8(&mut self) -> Result<i8, NoStdIoError> {
+        self.read_u8().map(|n| n as i8)
+    }
+
+    fn read_i16(&mut self) -> Result<i16, NoStdIoError> {
+        let mut buf = [0; 2];
+        self.read_exact(&mut buf)?;
+        Ok(i16::from_le_bytes(buf))
+    }
+
+    fn read_i32(&mut self) -> Result<i32, NoStdIoError> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf)?;
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    fn read_i64(&mut self) -> Result<i64, NoStdIoError> {
+        let mut buf = [0; 8];
+        self.read_exact(&mut buf)?;
+        Ok(i64::from_le_bytes(buf))
+    }
+
+    fn read_u16_le(&mut self) -> Result<u16, NoStdIoError> {
+        let mut buf = [0; 2];
+        self.read_exact(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn read_u32_le(&mut self) -> Result<u32, NoStdIoError> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_u64_le(&mut self) -> Result<u64, NoStdIoError> {
+        let mut buf = [0; 8];
+        self.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn read_u16_be(&mut self) -> Result<u16, NoStdIoError> {
+        let mut buf = [0; 2];
+        self.read_exact(&mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    fn read_u32_be(&mut self) -> Result<u32, NoStdIoError> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    fn read_u64_be(&mut self) -> Result<u64, NoStdIoError> {
+        let mut buf = [0; 8];
+        self.read_exact(&mut buf)?;
+        Ok(u64::from_be_bytes(buf))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: std::io::Read> NoStdRead for T {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, NoStdIoError> {
+        std::io::Read::read(self, buf).map_err(NoStdIoError::from)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), NoStdIoError> {
+        std::io::Read::read_exact(self, buf).map_err(NoStdIoError::from)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl NoStdRead for &[u8] {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, NoStdIoError> {
+        let n = buf.len().min(self.len());
+        let (src, rest) = self.split_at(n);
+        buf[..n].copy_from_slice(src);
+        *self = rest;
+        Ok(n)
+    }
+}
+
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct Cursor<'a> {
     inner: &'a [u8],
     pos: usize,
+    limits: Option<DecodeLimits>,
 }
 
 impl<'a> Cursor<'a> {
     pub const fn new(inner: &[u8]) -> Cursor<'_> {
-        Cursor { inner, pos: 0 }
+        Cursor {
+            inner,
+            pos: 0,
+            limits: None,
+        }
+    }
+
+    pub const fn with_limits(inner: &[u8], limits: DecodeLimits) -> Cursor<'_> {
+        Cursor {
+            inner,
+            pos: 0,
+            limits: Some(limits),
+        }
+    }
+
+    /// Limits enforced by decoders reading from this cursor, the global ones if none were set.
+    pub fn limits(&self) -> DecodeLimits {
+        self.limits.unwrap_or_else(DecodeLimits::global)
+    }
+
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.limits = Some(limits);
     }
 
     pub const fn position(&self) -> usize {
@@ -209,57 +369,49 @@ impl<'a> Cursor<'a> {
 
     #[inline]
     pub fn peek_u8(&self) -> Result<u8, NoStdIoError> {
-        let v = self
-            .inner
-            .get(self.pos)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
+        let v = self.inner.get(self.pos).ok_or_else(unexpected_eof)?;
         Ok(*v)
     }
 
     #[inline]
     pub fn peek_u16(&self) -> Result<u16, NoStdIoError> {
-        let range = self
-            .inner
-            .get(self.pos..self.pos + 1)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
-        Ok(u16::from_le_bytes(range.try_into().unwrap()))
+        self.peek_array().map(u16::from_le_bytes)
     }
 
     #[inline]
     pub fn peek_u32(&self) -> Result<u32, NoStdIoError> {
-        let range = self
-            .inner
-            .get(self.pos..self.pos + 3)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
-        Ok(u32::from_le_bytes(range.try_into().unwrap()))
+        self.peek_array().map(u32::from_le_bytes)
     }
 
     #[inline]
     pub fn peek_u64(&self) -> Result<u64, NoStdIoError> {
-        let range = self
-            .inner
-            .get(self.pos..self.pos + 7)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
-        Ok(u64::from_le_bytes(range.try_into().unwrap()))
+        self.peek_array().map(u64::from_le_bytes)
     }
 
     #[inline]
     pub fn peek_rest(&self) -> Result<&'a [u8], NoStdIoError> {
+        self.inner.get(self.pos..).ok_or_else(unexpected_eof)
+    }
+
+    #[inline]
+    pub fn peek_n(&self, n: usize) -> Result<&'a [u8], NoStdIoError> {
         self.inner
             .get(self.pos..)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))
+            .and_then(|rest| rest.get(..n))
+            .ok_or_else(unexpected_eof)
     }
 
+    /// Same as `read_array`, without moving forward.
     #[inline]
-    pub fn peek_n(&mut self, n: usize) -> Result<&'a [u8], NoStdIoError> {
-        self.inner
-            .get(self.pos..self.pos + n)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))
+    pub fn peek_array<const N: usize>(&self) -> Result<[u8; N], NoStdIoError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.peek_n(N)?);
+        Ok(array)
     }
 
     #[inline]
     pub fn get_ref(&self) -> &'a [u8] {
-        &self.inner
+        self.inner
     }
 
     #[inline]
@@ -272,103 +424,224 @@ impl<'a> Cursor<'a> {
         self.pos += len;
     }
 
+    /// Number of bytes left to read.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.inner.len().saturating_sub(self.pos)
+    }
+
     #[inline]
     pub fn read_n(&mut self, n: usize) -> Result<&'a [u8], NoStdIoError> {
         let bytes = self
             .inner
-            .get(self.pos..self.pos + n)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..n))
+            .ok_or_else(unexpected_eof)?;
         self.pos += n;
         Ok(bytes)
     }
 
+    /// Reads exactly `N` bytes, bounds are checked once for the whole array.
+    #[inline]
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], NoStdIoError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_n(N)?);
+        Ok(array)
+    }
+
     #[inline]
     pub fn read_u8(&mut self) -> Result<u8, NoStdIoError> {
-        let v = self
-            .inner
-            .get(self.pos)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
+        let v = self.inner.get(self.pos).ok_or_else(unexpected_eof)?;
         self.pos += 1;
         Ok(*v)
     }
 
     #[inline]
     pub fn read_u16(&mut self) -> Result<u16, NoStdIoError> {
-        let range = self
-            .inner
-            .get(self.pos..self.pos + 2)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
-        self.pos += 2;
-        Ok(u16::from_le_bytes(range.try_into().unwrap()))
+        self.read_array().map(u16::from_le_bytes)
     }
 
     #[inline]
     pub fn read_u32(&mut self) -> Result<u32, NoStdIoError> {
-        let range = self
-            .inner
-            .get(self.pos..self.pos + 4)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
-        self.pos += 4;
-        Ok(u32::from_le_bytes(range.try_into().unwrap()))
+        self.read_array().map(u32::from_le_bytes)
     }
 
     #[inline]
     pub fn read_u64(&mut self) -> Result<u64, NoStdIoError> {
-        let range = self
-            .inner
-            .get(self.pos..self.pos + 8)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
-        self.pos += 8;
-        Ok(u64::from_le_bytes(range.try_into().unwrap()))
+        self.read_array().map(u64::from_le_bytes)
     }
 
     #[inline]
     pub fn read_i8(&mut self) -> Result<i8, NoStdIoError> {
-        let v = self
-            .inner
-            .get(self.pos)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
+        let v = self.inner.get(self.pos).ok_or_else(unexpected_eof)?;
         self.pos += 1;
         Ok(*v as i8)
     }
 
     #[inline]
     pub fn read_i16(&mut self) -> Result<i16, NoStdIoError> {
-        let range = self
-            .inner
-            .get(self.pos..self.pos + 2)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
-        self.pos += 2;
-        Ok(i16::from_le_bytes(range.try_into().unwrap()))
+        self.read_array().map(i16::from_le_bytes)
     }
 
     #[inline]
     pub fn read_i32(&mut self) -> Result<i32, NoStdIoError> {
-        let range = self
-            .inner
-            .get(self.pos..self.pos + 4)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
-        self.pos += 4;
-        Ok(i32::from_le_bytes(range.try_into().unwrap()))
+        self.read_array().map(i32::from_le_bytes)
     }
 
     #[inline]
     pub fn read_i64(&mut self) -> Result<i64, NoStdIoError> {
-        let range = self
-            .inner
-            .get(self.pos..self.pos + 8)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
-        self.pos += 8;
-        Ok(i64::from_le_bytes(range.try_into().unwrap()))
+        self.read_array().map(i64::from_le_bytes)
     }
 
     #[inline]
     pub fn read_rest(&mut self) -> Result<&'a [u8], NoStdIoError> {
-        let rest = self
-            .inner
-            .get(self.pos..)
-            .ok_or_else(|| NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof))?;
+        let rest = self.inner.get(self.pos..).ok_or_else(unexpected_eof)?;
         self.pos += rest.len();
         Ok(rest)
     }
 }
+
+// Decoders read fixed-size integers through the trait, its endian-explicit methods spelling out
+// the byte order of the field. Byte slices are still borrowed with the inherent `read_n`.
+impl NoStdRead for Cursor<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, NoStdIoError> {
+        let n = buf.len().min(self.remaining());
+        buf[..n].copy_from_slice(self.read_n(n)?);
+        Ok(n)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), NoStdIoError> {
+        buf.copy_from_slice(self.read_n(buf.len())?);
+        Ok(())
+    }
+
+    #[inline]
+    fn read_u8(&mut self) -> Result<u8, NoStdIoError> {
+        Cursor::read_u8(self)
+    }
+
+    #[inline]
+    fn read_u16_le(&mut self) -> Result<u16, NoStdIoError> {
+        self.read_array().map(u16::from_le_bytes)
+    }
+
+    #[inline]
+    fn read_u32_le(&mut self) -> Result<u32, NoStdIoError> {
+        self.read_array().map(u32::from_le_bytes)
+    }
+
+    #[inline]
+    fn read_u64_le(&mut self) -> Result<u64, NoStdIoError> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    #[inline]
+    fn read_u16_be(&mut self) -> Result<u16, NoStdIoError> {
+        self.read_array().map(u16::from_be_bytes)
+    }
+
+    #[inline]
+    fn read_u32_be(&mut self) -> Result<u32, NoStdIoError> {
+        self.read_array().map(u32::from_be_bytes)
+    }
+
+    #[inline]
+    fn read_u64_be(&mut self) -> Result<u64, NoStdIoError> {
+        self.read_array().map(u64::from_be_bytes)
+    }
+}
+
+// Kept out of line so that the happy path of the read methods stays small enough to be inlined.
+#[cold]
+#[inline(never)]
+fn unexpected_eof() -> NoStdIoError {
+    NoStdIoError::new(NoStdIoErrorKind::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const BYTES: [u8; 7] = [
+        0x01, // u8
+        0x02, 0x01, // u16
+        0x04, 0x03, 0x02, 0x01, // u32
+    ];
+
+    #[test]
+    fn cursor_reads() {
+        let mut cursor = Cursor::new(&BYTES);
+        assert_eq!(cursor.remaining(), 7);
+        assert_eq!(cursor.read_u8().unwrap(), 0x01);
+        assert_eq!(cursor.read_u16().unwrap(), 0x0102);
+        assert_eq!(cursor.read_array::<2>().unwrap(), [0x04, 0x03]);
+        assert_eq!(cursor.remaining(), 2);
+
+        let err = cursor.read_u32().unwrap_err();
+        assert_eq!(err.kind(), NoStdIoErrorKind::UnexpectedEof);
+        assert_eq!(cursor.position(), 5);
+        assert!(cursor.read_n(usize::MAX).is_err());
+        assert_eq!(cursor.read_rest().unwrap(), &[0x02, 0x01]);
+        assert_eq!(cursor.remaining(), 0);
+    }
+
+    #[test]
+    fn cursor_peeks() {
+        let cursor = Cursor::new(&BYTES[3..]);
+        assert_eq!(cursor.peek_u8().unwrap(), 0x04);
+        assert_eq!(cursor.peek_u16().unwrap(), 0x0304);
+        assert_eq!(cursor.peek_u32().unwrap(), 0x0102_0304);
+        assert_eq!(cursor.peek_u64().unwrap_err().kind(), NoStdIoErrorKind::UnexpectedEof);
+        assert_eq!(cursor.position(), 0);
+
+        let cursor = Cursor::new(&BYTES[6..]);
+        assert!(cursor.peek_u16().is_err());
+        assert!(cursor.peek_n(usize::MAX).is_err());
+        assert_eq!(
+            Cursor::new(&[0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01])
+                .peek_u64()
+                .unwrap(),
+            0x0102_0304_0506_0708
+        );
+    }
+
+    #[test]
+    fn read_trait() {
+        fn read_all(reader: &mut impl NoStdRead) -> (u8, u16, u32) {
+            (
+                reader.read_u8().unwrap(),
+                reader.read_u16_be().unwrap(),
+                reader.read_u32_le().unwrap(),
+            )
+        }
+
+        let expected = (0x01, 0x0201, 0x0102_0304);
+        assert_eq!(read_all(&mut Cursor::new(&BYTES)), expected);
+        assert_eq!(read_all(&mut &BYTES[..]), expected);
+
+        let mut reader = &BYTES[5..];
+        let err = NoStdRead::read_u32(&mut reader).unwrap_err();
+        assert_eq!(err.kind(), NoStdIoErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn cursor_read_trait() {
+        let bytes = [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01];
+        let mut cursor = Cursor::new(&bytes);
+        assert_eq!(cursor.read_u64_le().unwrap(), 0x0102_0304_0506_0708);
+        let mut cursor = Cursor::new(&bytes);
+        assert_eq!(cursor.read_u64_be().unwrap(), 0x0807_0605_0403_0201);
+        assert!(cursor.read_u16_le().is_err());
+
+        // failed reads don't move the cursor
+        let mut cursor = Cursor::new(&bytes[5..]);
+        assert_eq!(
+            cursor.read_u32_be().unwrap_err().kind(),
+            NoStdIoErrorKind::UnexpectedEof
+        );
+        assert_eq!(cursor.position(), 0);
+        assert_eq!(cursor.read_u16_be().unwrap(), 0x0302);
+        assert_eq!(NoStdRead::read_u8(&mut cursor).unwrap(), 0x01);
+    }
+}
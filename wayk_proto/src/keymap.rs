@@ -0,0 +1,272 @@
+//! Keyboard keymap.
+//!
+//! Translates cross-platform physical key identifiers into `NowInputEventKeyboard` codes.
+//! Two identifier families are supported:
+//!
+//! - W3C UI Events `code` names (`"KeyA"`, `"AltRight"`, `"NumpadEnter"`…) as used by
+//!   browsers and the `keyboard-types` crate (`Code` implements `Display` with these names),
+//! - USB HID usages from the keyboard/keypad page (`0x07`) and the consumer page (`0x0C`).
+//!
+//! Resulting codes are Windows virtual key codes with the `NOW_VKCODE_EXT` bit set for extended keys.
+
+use crate::message::{NowInputEventKeyboard, KEYBOARD_FLAG_DOWN, NOW_VKCODE_EXT, NOW_VKCODE_MASK};
+
+pub const HID_PAGE_KEYBOARD: u16 = 0x07;
+pub const HID_PAGE_CONSUMER: u16 = 0x0C;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyMapping {
+    pub code: &'static str,
+    pub hid_page: u16,
+    pub hid_usage: u16,
+    /// Virtual key code, `NOW_VKCODE_EXT` included.
+    pub now_code: u16,
+}
+
+impl KeyMapping {
+    const fn new(code: &'static str, hid_usage: u16, vk: u16) -> Self {
+        Self {
+            code,
+            hid_page: HID_PAGE_KEYBOARD,
+            hid_usage,
+            now_code: vk,
+        }
+    }
+
+    const fn ext(code: &'static str, hid_usage: u16, vk: u16) -> Self {
+        Self::new(code, hid_usage, vk | NOW_VKCODE_EXT)
+    }
+
+    const fn consumer(code: &'static str, hid_usage: u16, vk: u16) -> Self {
+        Self {
+            code,
+            hid_page: HID_PAGE_CONSUMER,
+            hid_usage,
+            now_code: vk | NOW_VKCODE_EXT,
+        }
+    }
+
+    pub fn virtual_key(&self) -> u8 {
+        (self.now_code & NOW_VKCODE_MASK) as u8
+    }
+
+    pub fn is_extended(&self) -> bool {
+        self.now_code & NOW_VKCODE_EXT != 0
+    }
+}
+
+#[rustfmt::skip]
+pub const KEYMAP: &[KeyMapping] = &[
+    // letters
+    KeyMapping::new("KeyA", 0x04, 0x41), KeyMapping::new("KeyB", 0x05, 0x42),
+    KeyMapping::new("KeyC", 0x06, 0x43), KeyMapping::new("KeyD", 0x07, 0x44),
+    KeyMapping::new("KeyE", 0x08, 0x45), KeyMapping::new("KeyF", 0x09, 0x46),
+    KeyMapping::new("KeyG", 0x0A, 0x47), KeyMapping::new("KeyH", 0x0B, 0x48),
+    KeyMapping::new("KeyI", 0x0C, 0x49), KeyMapping::new("KeyJ", 0x0D, 0x4A),
+    KeyMapping::new("KeyK", 0x0E, 0x4B), KeyMapping::new("KeyL", 0x0F, 0x4C),
+    KeyMapping::new("KeyM", 0x10, 0x4D), KeyMapping::new("KeyN", 0x11, 0x4E),
+    KeyMapping::new("KeyO", 0x12, 0x4F), KeyMapping::new("KeyP", 0x13, 0x50),
+    KeyMapping::new("KeyQ", 0x14, 0x51), KeyMapping::new("KeyR", 0x15, 0x52),
+    KeyMapping::new("KeyS", 0x16, 0x53), KeyMapping::new("KeyT", 0x17, 0x54),
+    KeyMapping::new("KeyU", 0x18, 0x55), KeyMapping::new("KeyV", 0x19, 0x56),
+    KeyMapping::new("KeyW", 0x1A, 0x57), KeyMapping::new("KeyX", 0x1B, 0x58),
+    KeyMapping::new("KeyY", 0x1C, 0x59), KeyMapping::new("KeyZ", 0x1D, 0x5A),
+
+    // digits
+    KeyMapping::new("Digit1", 0x1E, 0x31), KeyMapping::new("Digit2", 0x1F, 0x32),
+    KeyMapping::new("Digit3", 0x20, 0x33), KeyMapping::new("Digit4", 0x21, 0x34),
+    KeyMapping::new("Digit5", 0x22, 0x35), KeyMapping::new("Digit6", 0x23, 0x36),
+    KeyMapping::new("Digit7", 0x24, 0x37), KeyMapping::new("Digit8", 0x25, 0x38),
+    KeyMapping::new("Digit9", 0x26, 0x39), KeyMapping::new("Digit0", 0x27, 0x30),
+
+    // writing system and whitespace
+    KeyMapping::new("Enter", 0x28, 0x0D),
+    KeyMapping::new("Escape", 0x29, 0x1B),
+    KeyMapping::new("Backspace", 0x2A, 0x08),
+    KeyMapping::new("Tab", 0x2B, 0x09),
+    KeyMapping::new("Space", 0x2C, 0x20),
+    KeyMapping::new("Minus", 0x2D, 0xBD),
+    KeyMapping::new("Equal", 0x2E, 0xBB),
+    KeyMapping::new("BracketLeft", 0x2F, 0xDB),
+    KeyMapping::new("BracketRight", 0x30, 0xDD),
+    KeyMapping::new("Backslash", 0x31, 0xDC),
+    KeyMapping::new("IntlHash", 0x32, 0xDC),
+    KeyMapping::new("Semicolon", 0x33, 0xBA),
+    KeyMapping::new("Quote", 0x34, 0xDE),
+    KeyMapping::new("Backquote", 0x35, 0xC0),
+    KeyMapping::new("Comma", 0x36, 0xBC),
+    KeyMapping::new("Period", 0x37, 0xBE),
+    KeyMapping::new("Slash", 0x38, 0xBF),
+    KeyMapping::new("CapsLock", 0x39, 0x14),
+    KeyMapping::new("IntlBackslash", 0x64, 0xE2),
+
+    // function keys
+    KeyMapping::new("F1", 0x3A, 0x70), KeyMapping::new("F2", 0x3B, 0x71),
+    KeyMapping::new("F3", 0x3C, 0x72), KeyMapping::new("F4", 0x3D, 0x73),
+    KeyMapping::new("F5", 0x3E, 0x74), KeyMapping::new("F6", 0x3F, 0x75),
+    KeyMapping::new("F7", 0x40, 0x76), KeyMapping::new("F8", 0x41, 0x77),
+    KeyMapping::new("F9", 0x42, 0x78), KeyMapping::new("F10", 0x43, 0x79),
+    KeyMapping::new("F11", 0x44, 0x7A), KeyMapping::new("F12", 0x45, 0x7B),
+    KeyMapping::new("F13", 0x68, 0x7C), KeyMapping::new("F14", 0x69, 0x7D),
+    KeyMapping::new("F15", 0x6A, 0x7E), KeyMapping::new("F16", 0x6B, 0x7F),
+    KeyMapping::new("F17", 0x6C, 0x80), KeyMapping::new("F18", 0x6D, 0x81),
+    KeyMapping::new("F19", 0x6E, 0x82), KeyMapping::new("F20", 0x6F, 0x83),
+    KeyMapping::new("F21", 0x70, 0x84), KeyMapping::new("F22", 0x71, 0x85),
+    KeyMapping::new("F23", 0x72, 0x86), KeyMapping::new("F24", 0x73, 0x87),
+
+    // control pad
+    KeyMapping::ext("PrintScreen", 0x46, 0x2C),
+    KeyMapping::new("ScrollLock", 0x47, 0x91),
+    KeyMapping::new("Pause", 0x48, 0x13),
+    KeyMapping::ext("Insert", 0x49, 0x2D),
+    KeyMapping::ext("Home", 0x4A, 0x24),
+    KeyMapping::ext("PageUp", 0x4B, 0x21),
+    KeyMapping::ext("Delete", 0x4C, 0x2E),
+    KeyMapping::ext("End", 0x4D, 0x23),
+    KeyMapping::ext("PageDown", 0x4E, 0x22),
+
+    // arrow pad
+    KeyMapping::ext("ArrowRight", 0x4F, 0x27),
+    KeyMapping::ext("ArrowLeft", 0x50, 0x25),
+    KeyMapping::ext("ArrowDown", 0x51, 0x28),
+    KeyMapping::ext("ArrowUp", 0x52, 0x26),
+
+    // numpad
+    KeyMapping::ext("NumLock", 0x53, 0x90),
+    KeyMapping::ext("NumpadDivide", 0x54, 0x6F),
+    KeyMapping::new("NumpadMultiply", 0x55, 0x6A),
+    KeyMapping::new("NumpadSubtract", 0x56, 0x6D),
+    KeyMapping::new("NumpadAdd", 0x57, 0x6B),
+    KeyMapping::ext("NumpadEnter", 0x58, 0x0D),
+    KeyMapping::new("Numpad1", 0x59, 0x61), KeyMapping::new("Numpad2", 0x5A, 0x62),
+    KeyMapping::new("Numpad3", 0x5B, 0x63), KeyMapping::new("Numpad4", 0x5C, 0x64),
+    KeyMapping::new("Numpad5", 0x5D, 0x65), KeyMapping::new("Numpad6", 0x5E, 0x66),
+    KeyMapping::new("Numpad7", 0x5F, 0x67), KeyMapping::new("Numpad8", 0x60, 0x68),
+    KeyMapping::new("Numpad9", 0x61, 0x69), KeyMapping::new("Numpad0", 0x62, 0x60),
+    KeyMapping::new("NumpadDecimal", 0x63, 0x6E),
+
+    // modifiers
+    KeyMapping::ext("ContextMenu", 0x65, 0x5D),
+    KeyMapping::new("ControlLeft", 0xE0, 0xA2),
+    KeyMapping::new("ShiftLeft", 0xE1, 0xA0),
+    KeyMapping::new("AltLeft", 0xE2, 0xA4),
+    KeyMapping::ext("MetaLeft", 0xE3, 0x5B),
+    KeyMapping::ext("ControlRight", 0xE4, 0xA3),
+    KeyMapping::new("ShiftRight", 0xE5, 0xA1),
+    KeyMapping::ext("AltRight", 0xE6, 0xA5),
+    KeyMapping::ext("MetaRight", 0xE7, 0x5C),
+
+    // media (keyboard page)
+    KeyMapping::ext("AudioVolumeMute", 0x7F, 0xAD),
+    KeyMapping::ext("AudioVolumeUp", 0x80, 0xAF),
+    KeyMapping::ext("AudioVolumeDown", 0x81, 0xAE),
+
+    // media (consumer page)
+    KeyMapping::consumer("MediaTrackNext", 0xB5, 0xB0),
+    KeyMapping::consumer("MediaTrackPrevious", 0xB6, 0xB1),
+    KeyMapping::consumer("MediaStop", 0xB7, 0xB2),
+    KeyMapping::consumer("MediaPlayPause", 0xCD, 0xB3),
+    KeyMapping::consumer("AudioVolumeMute", 0xE2, 0xAD),
+    KeyMapping::consumer("AudioVolumeUp", 0xE9, 0xAF),
+    KeyMapping::consumer("AudioVolumeDown", 0xEA, 0xAE),
+    KeyMapping::consumer("BrowserSearch", 0x221, 0xAA),
+    KeyMapping::consumer("BrowserHome", 0x223, 0xAC),
+    KeyMapping::consumer("BrowserBack", 0x224, 0xA6),
+    KeyMapping::consumer("BrowserForward", 0x225, 0xA7),
+    KeyMapping::consumer("BrowserRefresh", 0x227, 0xA8),
+];
+
+/// Legacy or key-value names accepted in place of their W3C `code` counterpart.
+const CODE_ALIASES: &[(&str, &str)] = &[
+    ("AltGraph", "AltRight"),
+    ("OSLeft", "MetaLeft"),
+    ("OSRight", "MetaRight"),
+    ("VolumeMute", "AudioVolumeMute"),
+    ("VolumeUp", "AudioVolumeUp"),
+    ("VolumeDown", "AudioVolumeDown"),
+];
+
+pub fn mapping_from_code_name(name: &str) -> Option<&'static KeyMapping> {
+    let name = CODE_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, code)| *code)
+        .unwrap_or(name);
+    KEYMAP.iter().find(|mapping| mapping.code == name)
+}
+
+pub fn mapping_from_hid_usage(page: u16, usage: u16) -> Option<&'static KeyMapping> {
+    KEYMAP
+        .iter()
+        .find(|mapping| mapping.hid_page == page && mapping.hid_usage == usage)
+}
+
+/// Returns the Now virtual key code (extended bit included) for a W3C `code` name.
+pub fn now_code_from_code_name(name: &str) -> Option<u16> {
+    mapping_from_code_name(name).map(|mapping| mapping.now_code)
+}
+
+/// Returns the Now virtual key code (extended bit included) for a USB HID usage.
+pub fn now_code_from_hid_usage(page: u16, usage: u16) -> Option<u16> {
+    mapping_from_hid_usage(page, usage).map(|mapping| mapping.now_code)
+}
+
+pub fn keyboard_event(now_code: u16, down: bool) -> NowInputEventKeyboard {
+    let flags = if down { KEYBOARD_FLAG_DOWN } else { 0 };
+    NowInputEventKeyboard::new_with_flags_and_code(flags, now_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialization::Encode;
+
+    #[test]
+    fn alt_gr_is_extended_right_alt() {
+        let alt_gr = mapping_from_code_name("AltGraph").unwrap();
+        assert_eq!(alt_gr.code, "AltRight");
+        assert_eq!(alt_gr.virtual_key(), 0xA5);
+        assert!(alt_gr.is_extended());
+        assert_eq!(now_code_from_hid_usage(HID_PAGE_KEYBOARD, 0xE6), Some(0x01A5));
+        assert_eq!(now_code_from_code_name("AltLeft"), Some(0x00A4));
+    }
+
+    #[test]
+    fn numpad_keys() {
+        assert_eq!(now_code_from_code_name("NumpadEnter"), Some(0x010D));
+        assert_eq!(now_code_from_code_name("Enter"), Some(0x000D));
+        assert_eq!(now_code_from_code_name("NumpadDivide"), Some(0x016F));
+        assert_eq!(now_code_from_code_name("Slash"), Some(0x00BF));
+        assert_eq!(now_code_from_hid_usage(HID_PAGE_KEYBOARD, 0x62), Some(0x0060));
+        assert_eq!(now_code_from_hid_usage(HID_PAGE_KEYBOARD, 0x53), Some(0x0190));
+    }
+
+    #[test]
+    fn media_keys() {
+        assert_eq!(now_code_from_code_name("MediaPlayPause"), Some(0x01B3));
+        assert_eq!(now_code_from_hid_usage(HID_PAGE_CONSUMER, 0xCD), Some(0x01B3));
+        assert_eq!(now_code_from_code_name("VolumeMute"), Some(0x01AD));
+        assert_eq!(now_code_from_hid_usage(HID_PAGE_KEYBOARD, 0x7F), Some(0x01AD));
+        assert_eq!(now_code_from_hid_usage(HID_PAGE_CONSUMER, 0xE2), Some(0x01AD));
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(now_code_from_code_name("NotAKey"), None);
+        assert_eq!(now_code_from_hid_usage(HID_PAGE_KEYBOARD, 0xFF), None);
+        assert_eq!(now_code_from_hid_usage(0x01, 0x04), None);
+    }
+
+    #[test]
+    fn arrow_key_event_encoding() {
+        let code = now_code_from_code_name("ArrowUp").unwrap();
+        assert_eq!(
+            keyboard_event(code, true).encode().unwrap(),
+            vec![0x03, 0x01, 0x26, 0x01]
+        );
+        assert_eq!(
+            keyboard_event(code, false).encode().unwrap(),
+            vec![0x03, 0x00, 0x26, 0x01]
+        );
+    }
+}
@@ -1,8 +1,14 @@
 #![cfg_attr(not(feature = "std"), no_std)]
+// strings are stored inline, trading message size for allocations
+#![cfg_attr(feature = "fixed-strings", allow(clippy::large_enum_variant))]
 
 #[macro_use]
 extern crate alloc;
 
+// tests always link std, even for minimal builds
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
 #[macro_use]
 extern crate wayk_proto_derive;
 
@@ -14,19 +20,52 @@ pub mod macros;
 
 pub mod auth;
 pub mod channels_manager;
+pub mod compression;
 pub mod container;
+pub mod driver;
 pub mod error;
 pub mod event;
+pub mod extension;
 pub mod header;
 pub mod io;
+pub mod keymap;
 pub mod message;
+pub mod metrics;
 pub mod packet;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+pub mod policy;
+pub mod registry;
+pub mod secrets;
 pub mod serialization;
+pub mod session_record;
 pub mod sharee;
 pub mod sm;
+mod sync;
+pub mod tap;
+pub mod testing;
+pub mod timer;
+pub mod trace;
 pub mod version;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 ////////////////////////////////////////////////////////////////////////////////
 
 extern crate self as wayk_proto;
 extern crate static_assertions as sa;
+
+// Used by `Arbitrary` implementations emitted by `#[derive(Decode)]`, see `__arbitrary`.
+#[cfg(feature = "arbitrary")]
+#[doc(hidden)]
+pub mod __arbitrary {
+    pub use arbitrary::{Arbitrary, Error, Result, Unstructured};
+}
+
+// Used by round-trip tests emitted by `#[derive(Decode)]`, see `__roundtrip_test`.
+#[cfg(feature = "roundtrip")]
+#[doc(hidden)]
+pub mod __roundtrip {
+    pub use arbitrary;
+    pub use proptest;
+}
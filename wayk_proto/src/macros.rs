@@ -4,11 +4,42 @@
 
 // === FLAGS ===
 
+/// Iterator over the known flags set in a flags struct, see `__flags_struct!`.
+pub struct FlagsIter<T: 'static> {
+    value: T,
+    known: core::slice::Iter<'static, (&'static str, T)>,
+}
+
+impl<T> FlagsIter<T> {
+    pub fn new(value: T, known: &'static [(&'static str, T)]) -> Self {
+        Self {
+            value,
+            known: known.iter(),
+        }
+    }
+}
+
+impl<T> Iterator for FlagsIter<T>
+where
+    T: Copy + Default + PartialEq + core::ops::BitAnd<Output = T>,
+{
+    type Item = (&'static str, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = self.value;
+        self.known
+            .find(|(_, bits)| *bits != T::default() && value & *bits == *bits)
+            .copied()
+    }
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! __flags_struct {
-    ($flags_type:ident : $underlying_type:ident) => {
-        #[derive(wayk_proto_derive::Encode, wayk_proto_derive::Decode, Debug, PartialEq, Clone, Copy)]
+    (@base $flags_type:ident : $underlying_type:ident) => {
+        #[derive(wayk_proto_derive::Encode, wayk_proto_derive::Decode, PartialEq, Clone, Copy)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+        #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
         pub struct $flags_type {
             pub value: $underlying_type,
         }
@@ -19,9 +50,9 @@ macro_rules! __flags_struct {
             }
         }
 
-        impl Into<$underlying_type> for $flags_type {
-            fn into(self) -> $underlying_type {
-                self.value
+        impl From<$flags_type> for $underlying_type {
+            fn from(flags: $flags_type) -> Self {
+                flags.value
             }
         }
 
@@ -35,6 +66,25 @@ macro_rules! __flags_struct {
             pub fn new_empty() -> Self {
                 Self { value: 0 }
             }
+
+            pub fn is_empty(self) -> bool {
+                self.value == 0
+            }
+
+            /// `true` if all flags set in `other` are also set in `self`.
+            pub fn contains<F: Into<$underlying_type>>(self, other: F) -> bool {
+                let other = other.into();
+                self.value & other == other
+            }
+        }
+    };
+    ($flags_type:ident : $underlying_type:ident) => {
+        $crate::__flags_struct!{ @base $flags_type : $underlying_type }
+
+        impl core::fmt::Debug for $flags_type {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                write!(f, "{}({:#x})", stringify!($flags_type), self.value)
+            }
         }
     };
     (
@@ -42,9 +92,14 @@ macro_rules! __flags_struct {
             $( $lowercase:ident = $UPPERCASE:ident = $const_value:expr , )+
         }
     ) => {
-        $crate::__flags_struct!{ $flags_type : $underlying_type }
+        $crate::__flags_struct!{ @base $flags_type : $underlying_type }
 
         impl $flags_type {
+            /// Name and value of each known flag.
+            pub const KNOWN_FLAGS: &'static [(&'static str, $underlying_type)] = &[
+                $( (stringify!($UPPERCASE), $const_value), )+
+            ];
+
             $(
                 pub const $UPPERCASE: $underlying_type = $const_value;
                 pub fn $lowercase(self) -> bool {
@@ -63,6 +118,56 @@ macro_rules! __flags_struct {
                     }
                 }
             )+
+
+            /// All known flags set.
+            pub fn all() -> Self {
+                Self {
+                    value: 0 $( | Self::$UPPERCASE )+,
+                }
+            }
+
+            /// Drops bits not matching any known flag.
+            pub fn from_bits_truncate(bits: $underlying_type) -> Self {
+                Self {
+                    value: bits & Self::all().value,
+                }
+            }
+
+            /// Known flags set, as `(name, value)` pairs.
+            pub fn iter(self) -> $crate::macros::FlagsIter<$underlying_type> {
+                $crate::macros::FlagsIter::new(self.value, Self::KNOWN_FLAGS)
+            }
+        }
+
+        impl IntoIterator for $flags_type {
+            type Item = (&'static str, $underlying_type);
+            type IntoIter = $crate::macros::FlagsIter<$underlying_type>;
+
+            fn into_iter(self) -> Self::IntoIter {
+                self.iter()
+            }
+        }
+
+        impl core::fmt::Debug for $flags_type {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                write!(f, "{}(", stringify!($flags_type))?;
+                let mut first = true;
+                for (name, _) in self.iter() {
+                    if !first {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", name)?;
+                    first = false;
+                }
+                let unknown = self.value & !Self::all().value;
+                if unknown != 0 || first {
+                    if !first {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{:#x}", unknown)?;
+                }
+                write!(f, ")")
+            }
         }
     };
 }
@@ -77,3 +182,138 @@ macro_rules! __type_str {
         stringify!($typ)
     }};
 }
+
+// === ARBITRARY ===
+
+// Wraps the `Arbitrary` implementations emitted by `#[derive(Decode)]` for enums with a fallback
+// variant, so that they're only compiled along the `arbitrary` feature of this crate.
+#[cfg(feature = "arbitrary")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __arbitrary {
+    ($($item:item)*) => {
+        $($item)*
+    };
+}
+
+#[cfg(not(feature = "arbitrary"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __arbitrary {
+    ($($item:item)*) => {};
+}
+
+// === ROUND-TRIP TESTS ===
+
+// Body of the property test emitted by `#[derive(Decode)]` for types marked `#[roundtrip]`:
+// decoding the encoding of a generated value gives it back.
+//
+// Types marked `#[roundtrip]` have generated values that can't survive a round
+// trip (optional fields without their flag, sizes not matching the data…), their
+// values are only required to be stable once decoded: decoding the encoding of a decoded value
+// gives it back, byte for byte.
+#[cfg(feature = "roundtrip")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __roundtrip_test {
+    ($typ:ty) => {{
+        use $crate::__roundtrip::arbitrary::{Arbitrary, Unstructured};
+        use $crate::__roundtrip::proptest::prelude::*;
+        use $crate::serialization::{Decode, Encode};
+
+        proptest!(|(data in prop::collection::vec(any::<u8>(), 0..1024))| {
+            // rejected cases fail the test once too many, so types that can't be generated are noticed
+            let value = <$typ as Arbitrary<'_>>::arbitrary(&mut Unstructured::new(&data));
+            prop_assume!(value.is_ok());
+            let value = value.unwrap();
+
+            let encoded = value.encode();
+            prop_assert!(encoded.is_ok(), "couldn't encode {:?}", value);
+            let encoded = encoded.unwrap();
+            let decoded = <$typ>::decode(&encoded);
+            prop_assert!(decoded.is_ok(), "couldn't decode encoded {:?}", value);
+            prop_assert_eq!(decoded.unwrap(), value);
+        });
+    }};
+    ($typ:ty, non_canonical) => {{
+        use $crate::__roundtrip::arbitrary::{Arbitrary, Unstructured};
+        use $crate::__roundtrip::proptest::prelude::*;
+        use $crate::serialization::{Decode, Encode};
+
+        proptest!(|(data in prop::collection::vec(any::<u8>(), 0..1024))| {
+            let value = <$typ as Arbitrary<'_>>::arbitrary(&mut Unstructured::new(&data));
+            prop_assume!(value.is_ok());
+            let encoded = value.unwrap().encode();
+            prop_assume!(encoded.is_ok());
+            let encoded = encoded.unwrap();
+            let decoded = <$typ>::decode(&encoded);
+            prop_assume!(decoded.is_ok());
+            let decoded = decoded.unwrap();
+
+            let reencoded = decoded.encode();
+            prop_assert!(reencoded.is_ok(), "couldn't encode decoded {:?}", decoded);
+            let reencoded = reencoded.unwrap();
+            let redecoded = <$typ>::decode(&reencoded);
+            prop_assert!(redecoded.is_ok(), "couldn't decode encoded {:?}", decoded);
+            let redecoded = redecoded.unwrap();
+            prop_assert_eq!(&decoded, &redecoded);
+            prop_assert_eq!(redecoded.encode().unwrap(), reencoded);
+        });
+    }};
+}
+
+// === TRACING ===
+
+// Not exported: the `cfg` below must be evaluated against this crate features.
+// Enters a `tracing` debug span until the end of the enclosing block, expands to nothing when
+// the `tracing` feature is disabled.
+macro_rules! __enter_span {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($($args)*).entered();
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    __flags_struct! {
+        TestFlags: u8 => {
+            first = FIRST = 0x01,
+            second = SECOND = 0x02,
+            last = LAST = 0x80,
+        }
+    }
+
+    #[test]
+    fn flags_debug() {
+        assert_eq!(format!("{:?}", TestFlags::new_empty()), "TestFlags(0x0)");
+        assert_eq!(
+            format!("{:?}", TestFlags::new_empty().set_first().set_last()),
+            "TestFlags(FIRST | LAST)"
+        );
+        assert_eq!(format!("{:?}", TestFlags::from(0x0e)), "TestFlags(SECOND | 0xc)");
+    }
+
+    #[test]
+    fn flags_iter() {
+        let flags = TestFlags::from(0x83);
+        let names: Vec<&str> = flags.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["FIRST", "SECOND", "LAST"]);
+        assert_eq!(
+            TestFlags::new_empty().set_second().iter().collect::<Vec<_>>(),
+            [("SECOND", TestFlags::SECOND)]
+        );
+        assert_eq!(TestFlags::new_empty().iter().count(), 0);
+    }
+
+    #[test]
+    fn flags_bits() {
+        assert_eq!(TestFlags::all(), 0x83);
+        assert_eq!(TestFlags::from_bits_truncate(0xff), 0x83);
+        assert!(TestFlags::from(0x83).contains(TestFlags::FIRST | TestFlags::LAST));
+        assert!(!TestFlags::from(0x81).contains(TestFlags::new_empty().set_second()));
+        assert!(TestFlags::new_empty().is_empty());
+    }
+}
@@ -2,7 +2,10 @@
 
 use core::mem;
 
-#[derive(Encode, Decode, Debug, Clone, Default)]
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct EdgeRect {
     pub left: i16,
     pub top: i16,
@@ -12,6 +15,15 @@ pub struct EdgeRect {
 
 impl EdgeRect {
     pub const REQUIRED_SIZE: usize = mem::size_of::<Self>();
+
+    pub fn new(left: i16, top: i16, right: i16, bottom: i16) -> Self {
+        Self {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -2,9 +2,15 @@
 
 pub mod edge_rect;
 pub mod now_string;
+#[cfg(feature = "fixed-strings")]
+pub mod now_string_fixed;
+pub mod redacted;
 pub mod size_rect;
 
 // re-export
 pub use edge_rect::*;
 pub use now_string::*;
+#[cfg(feature = "fixed-strings")]
+pub use now_string_fixed::*;
+pub use redacted::*;
 pub use size_rect::*;
@@ -6,88 +6,126 @@ use crate::serialization::{Decode, Encode};
 use alloc::borrow::Cow;
 use alloc::string::{String, ToString};
 use core::convert::TryFrom;
-use core::marker::PhantomData;
+use core::ops::Deref;
 use core::str::FromStr;
 
-pub trait NowStringSize {
-    const SIZE: usize;
-}
-
-macro_rules! now_string_size {
-    ( $string_size_name:ident, $string_size_type:ident, $now_string_name:ident, $size:literal ) => {
-        #[derive(Debug, Clone, Copy)]
-        pub struct $string_size_name;
+/// Length prefix of now strings: an u8 up to 256 bytes, an u16 above.
+pub(crate) struct LenPrefix<const MAX: usize>;
 
-        impl NowStringSize for $string_size_name {
-            const SIZE: usize = $size;
+impl<const MAX: usize> LenPrefix<MAX> {
+    const SIZE: usize = {
+        assert!(MAX <= 65535, "now strings can't be longer than 65535 bytes");
+        if MAX <= 256 {
+            1
+        } else {
+            2
         }
+    };
 
-        pub type $now_string_name = NowString<$string_size_name, $string_size_type>;
+    /// Longest string the prefix can announce, an u8 prefix stops at 255 bytes.
+    pub(crate) const MAX_LEN: usize = if Self::SIZE == 1 && MAX > u8::MAX as usize {
+        u8::MAX as usize
+    } else {
+        MAX
     };
 }
 
-now_string_size! { StringSize16,    u8,  NowString16,    16    }
-now_string_size! { StringSize32,    u8,  NowString32,    32    }
-now_string_size! { StringSize64,    u8,  NowString64,    64    }
-now_string_size! { StringSize128,   u8,  NowString128,   128   }
-now_string_size! { StringSize256,   u8,  NowString256,   256   }
-now_string_size! { StringSize65535, u16, NowString65535, 65535 }
+/// Reads the length prefix and the utf8 bytes of a now string, discarding the null terminator.
+pub(crate) fn decode_bytes<'dec, const MAX: usize>(cursor: &mut Cursor<'dec>) -> Result<&'dec [u8]> {
+    let expected_size = if LenPrefix::<MAX>::SIZE == 1 {
+        usize::from(u8::decode_from(cursor)?)
+    } else {
+        usize::from(u16::decode_from(cursor)?)
+    };
+
+    if expected_size > LenPrefix::<MAX>::MAX_LEN {
+        return Err(
+            ProtoError::new(ProtoErrorKind::Decoding("NowString")).with_desc(format!(
+                "attempted to parse a string greater (len: {}) than the NowString{} size limit",
+                expected_size, MAX
+            )),
+        );
+    }
+
+    cursor.limits().check_bytes(expected_size, "NowString")?;
+
+    let utf8_buf = cursor
+        .read_n(expected_size)
+        .map_err(ProtoError::from)
+        .chain(ProtoErrorKind::Decoding("NowString"))
+        .or_else_desc(|| {
+            format!(
+                "no enough bytes to parse the NowString{} (expected {})",
+                MAX, expected_size
+            )
+        })?;
+    cursor.forward(1); // discard the null terminator
+
+    Ok(utf8_buf)
+}
+
+/// Writes the length prefix, the utf8 bytes and the null terminator of a now string.
+pub(crate) fn encode_str<W: NoStdWrite, const MAX: usize>(writer: &mut W, s: &str) -> Result<()> {
+    check_len::<MAX>(s).chain(ProtoErrorKind::Encoding("NowString"))?;
+
+    if LenPrefix::<MAX>::SIZE == 1 {
+        (s.len() as u8).encode_into(writer)?;
+    } else {
+        (s.len() as u16).encode_into(writer)?;
+    }
+    writer.write_all(s.as_bytes())?;
+    writer.write_u8(0u8)?; // null terminator
+    Ok(())
+}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct NowString<Size, SizeType> {
+pub(crate) fn encoded_str_len<const MAX: usize>(s: &str) -> usize {
+    LenPrefix::<MAX>::SIZE + s.len() + core::mem::size_of::<u8>()
+}
+
+#[cfg(not(feature = "fixed-strings"))]
+pub type NowString16 = NowString<16>;
+#[cfg(not(feature = "fixed-strings"))]
+pub type NowString32 = NowString<32>;
+#[cfg(not(feature = "fixed-strings"))]
+pub type NowString64 = NowString<64>;
+#[cfg(not(feature = "fixed-strings"))]
+pub type NowString128 = NowString<128>;
+#[cfg(not(feature = "fixed-strings"))]
+pub type NowString256 = NowString<256>;
+#[cfg(feature = "fixed-strings")]
+pub type NowString16 = super::NowStringFixed<16>;
+#[cfg(feature = "fixed-strings")]
+pub type NowString32 = super::NowStringFixed<32>;
+#[cfg(feature = "fixed-strings")]
+pub type NowString64 = super::NowStringFixed<64>;
+#[cfg(feature = "fixed-strings")]
+pub type NowString128 = super::NowStringFixed<128>;
+#[cfg(feature = "fixed-strings")]
+pub type NowString256 = super::NowStringFixed<256>;
+// always allocated, too large to be stored inline
+pub type NowString65535 = NowString<65535>;
+
+/// Utf8 string of at most `MAX` bytes.
+///
+/// Encoded with its length, as an u8 up to 256 bytes and as an u16 above, followed by the utf8
+/// bytes and a null terminator.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NowString<const MAX: usize> {
     inner: String,
-    _pd: PhantomData<(Size, SizeType)>,
 }
 
-impl<'dec, Size, SizeType> Decode<'dec> for NowString<Size, SizeType>
-where
-    Size: NowStringSize,
-    SizeType: Decode<'dec> + Into<usize>,
-{
+impl<'dec, const MAX: usize> Decode<'dec> for NowString<MAX> {
     fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
-        let expected_size = SizeType::decode_from(cursor)?.into();
-
-        if expected_size > Size::SIZE {
-            return Err(
-                ProtoError::new(ProtoErrorKind::Decoding("NowString")).with_desc(format!(
-                    "attempted to parse a string greater (len: {}) than the NowString{} size limit",
-                    expected_size,
-                    Size::SIZE
-                )),
-            );
-        }
+        let utf8_buf = decode_bytes::<MAX>(cursor)?;
+        let string = String::from_utf8(utf8_buf.to_vec())
+            .map_err(ProtoError::from)
+            .chain(ProtoErrorKind::Decoding("NowString"))?;
 
-        let string = {
-            let utf8_buf = cursor
-                .read_n(expected_size)
-                .map_err(ProtoError::from)
-                .chain(ProtoErrorKind::Decoding("NowString"))
-                .or_else_desc(|| {
-                    format!(
-                        "no enough bytes to parse the NowString{} (expected {})",
-                        Size::SIZE,
-                        expected_size
-                    )
-                })?;
-            cursor.forward(1); // discard the null terminator
-
-            String::from_utf8(utf8_buf.to_vec())
-                .map_err(ProtoError::from)
-                .chain(ProtoErrorKind::Decoding("NowString"))?
-        };
-
-        Ok(NowString {
-            inner: string,
-            _pd: PhantomData,
-        })
+        Ok(NowString { inner: string })
     }
 }
 
-impl<Size, SizeType> Encode for NowString<Size, SizeType>
-where
-    SizeType: Encode + TryFrom<usize>,
-    <SizeType as TryFrom<usize>>::Error: core::fmt::Debug,
-{
+impl<const MAX: usize> Encode for NowString<MAX> {
     fn expected_size() -> crate::serialization::ExpectedSize
     where
         Self: Sized,
@@ -96,48 +134,55 @@ where
     }
 
     fn encoded_len(&self) -> usize {
-        self.inner.len() + core::mem::size_of::<u8>() + core::mem::size_of::<SizeType>()
+        encoded_str_len::<MAX>(&self.inner)
     }
 
     fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
-        let bytes = self.inner.as_bytes();
-        let len = SizeType::try_from(bytes.len()).unwrap(); // should never panic by construction
-        len.encode_into(writer)?;
-        bytes.encode_into(writer)?;
-        writer.write_u8(0u8)?;
-        Ok(())
+        encode_str::<W, MAX>(writer, &self.inner)
     }
 }
 
-impl<Size, SizeType> NowString<Size, SizeType>
-where
-    Size: NowStringSize,
-    SizeType: Encode + TryFrom<usize>,
-    <SizeType as TryFrom<usize>>::Error: core::fmt::Debug,
-{
-    pub fn new_empty() -> Self {
+impl<const MAX: usize> NowString<MAX> {
+    /// Maximum length in bytes, 255 for `NowString<256>` as the length prefix is an u8.
+    pub const MAX_LEN: usize = LenPrefix::<MAX>::MAX_LEN;
+
+    /// Truncates the string to `MAX_LEN` bytes (on a char boundary) instead of failing.
+    pub fn from_string_lossy(mut string: String) -> Self {
+        let mut len = string.len().min(Self::MAX_LEN);
+        while !string.is_char_boundary(len) {
+            len -= 1;
+        }
+        string.truncate(len);
+
+        Self { inner: string }
+    }
+
+    /// Truncates the string to `MAX_LEN` bytes (on a char boundary) instead of failing.
+    pub fn from_str_lossy(s: &str) -> Self {
+        let mut len = s.len().min(Self::MAX_LEN);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+
         Self {
-            inner: String::new(),
-            _pd: PhantomData,
+            inner: s[..len].to_string(),
         }
     }
 
+    pub fn new_empty() -> Self {
+        Self::default()
+    }
+
     /// # Safety
-    /// Provided string len must not exceed `NowStringSize::SIZE`
+    /// Provided string len must not exceed `MAX_LEN`
     pub unsafe fn from_string_unchecked(s: String) -> Self {
-        Self {
-            inner: s,
-            _pd: PhantomData,
-        }
+        Self { inner: s }
     }
 
     /// # Safety
-    /// Provided string slice len must not exceed `NowStringSize::SIZE`
+    /// Provided string slice len must not exceed `MAX_LEN`
     pub unsafe fn from_str_unchecked(s: &str) -> Self {
-        Self {
-            inner: s.to_string(),
-            _pd: PhantomData,
-        }
+        Self { inner: s.to_string() }
     }
 
     pub fn from_string(string: String) -> Result<Self> {
@@ -158,89 +203,172 @@ where
 
     /// Encode an utf8 str into a now string
     pub fn helper_write_into<W: NoStdWrite>(writer: &mut W, s: &str) -> Result<()> {
-        if s.len() > Size::SIZE {
-            return Err(
-                ProtoError::new(ProtoErrorKind::Encoding("NowString")).with_desc(format!(
-                    "provided string greater (len: {}) than NowString{} size limit",
-                    s.len(),
-                    Size::SIZE
-                )),
-            );
-        }
+        encode_str::<W, MAX>(writer, s)
+    }
+}
 
-        let len = SizeType::try_from(s.len()).unwrap(); // should never panic by construction
-        len.encode_into(writer)?;
-        writer.write_all(s.as_bytes())?;
-        writer.write_u8(0u8)?; // null terminator
-        Ok(())
+impl<const MAX: usize> Deref for NowString<MAX> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.inner.as_str()
+    }
+}
+
+impl<const MAX: usize> AsRef<str> for NowString<MAX> {
+    fn as_ref(&self) -> &str {
+        self.inner.as_str()
     }
 }
 
-impl<Size, SizeType> Into<String> for NowString<Size, SizeType> {
-    fn into(self) -> String {
-        self.inner
+impl<const MAX: usize> From<NowString<MAX>> for String {
+    fn from(s: NowString<MAX>) -> Self {
+        s.inner
     }
 }
 
-impl<'a, Size, SizeType> Into<Cow<'a, str>> for NowString<Size, SizeType> {
-    fn into(self) -> Cow<'a, str> {
-        self.inner.into()
+impl<'a, const MAX: usize> From<NowString<MAX>> for Cow<'a, str> {
+    fn from(s: NowString<MAX>) -> Self {
+        s.inner.into()
     }
 }
 
-impl<Size, SizeType> TryFrom<String> for NowString<Size, SizeType>
-where
-    Size: NowStringSize,
-{
+/// Widening conversions, a string always fits in a larger now string.
+macro_rules! now_string_widening {
+    ( $from:literal => $( $to:literal ),+ ) => {
+        $(
+            impl From<NowString<$from>> for NowString<$to> {
+                fn from(s: NowString<$from>) -> Self {
+                    Self { inner: s.inner }
+                }
+            }
+        )+
+    };
+}
+
+now_string_widening! { 16  => 32, 64, 128, 256, 65535 }
+now_string_widening! { 32  => 64, 128, 256, 65535 }
+now_string_widening! { 64  => 128, 256, 65535 }
+now_string_widening! { 128 => 256, 65535 }
+now_string_widening! { 256 => 65535 }
+
+impl<const MAX: usize> TryFrom<String> for NowString<MAX> {
     type Error = ProtoError;
 
     fn try_from(string: String) -> Result<Self> {
-        if string.len() > Size::SIZE {
-            return Err(
-                ProtoError::new(ProtoErrorKind::Decoding("NowString")).with_desc(format!(
-                    "provided string greater (len: {}) than NowString{} size limit",
-                    string.len(),
-                    Size::SIZE
-                )),
-            );
-        }
+        check_len::<MAX>(&string)?;
+        Ok(Self { inner: string })
+    }
+}
+
+impl<const MAX: usize> TryFrom<&str> for NowString<MAX> {
+    type Error = ProtoError;
 
-        Ok(Self {
-            inner: string,
-            _pd: PhantomData,
-        })
+    fn try_from(s: &str) -> Result<Self> {
+        check_len::<MAX>(s)?;
+        Ok(Self { inner: s.to_string() })
+    }
+}
+
+pub(crate) fn check_len<const MAX: usize>(s: &str) -> Result<()> {
+    if s.len() > LenPrefix::<MAX>::MAX_LEN {
+        Err(
+            ProtoError::new(ProtoErrorKind::Decoding("NowString")).with_desc(format!(
+                "provided string greater (len: {}) than NowString{} size limit ({})",
+                s.len(),
+                MAX,
+                LenPrefix::<MAX>::MAX_LEN
+            )),
+        )
+    } else {
+        Ok(())
     }
 }
 
-impl<Size, SizeType> FromStr for NowString<Size, SizeType>
-where
-    Size: NowStringSize,
-{
+/// Builds a now string from a literal, its length is checked at compile time.
+///
+/// ```
+/// use wayk_proto::message::NowString16;
+/// use wayk_proto::now_str;
+///
+/// let name = now_str!(NowString16, "NowChat");
+/// assert_eq!(name, "NowChat");
+/// ```
+///
+/// ```compile_fail
+/// use wayk_proto::message::NowString16;
+/// use wayk_proto::now_str;
+///
+/// let name = now_str!(NowString16, "this literal doesn't fit");
+/// ```
+#[macro_export]
+macro_rules! now_str {
+    ($ty:ty, $s:literal) => {{
+        const _: () = assert!(
+            $s.len() <= <$ty>::MAX_LEN,
+            "string literal exceeds the now string size limit"
+        );
+        // Safety: length checked above
+        unsafe { <$ty>::from_str_unchecked($s) }
+    }};
+}
+
+impl<const MAX: usize> FromStr for NowString<MAX> {
     type Err = ProtoError;
 
     fn from_str(s: &str) -> Result<Self> {
-        Self::try_from(s.to_string())
+        Self::try_from(s)
     }
 }
 
-impl<Size, SizeType> PartialEq<&str> for NowString<Size, SizeType> {
+impl<const MAX: usize> PartialEq<&str> for NowString<MAX> {
     fn eq(&self, other: &&str) -> bool {
         &self.inner == other
     }
 }
 
-impl<Size, SizeType> PartialEq<String> for NowString<Size, SizeType> {
+impl<const MAX: usize> PartialEq<String> for NowString<MAX> {
     fn eq(&self, other: &String) -> bool {
         self.inner.eq(other)
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl<'a, const MAX: usize> arbitrary::Arbitrary<'a> for NowString<MAX> {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        String::arbitrary(u).map(Self::from_string_lossy)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<const MAX: usize> serde::Serialize for NowString<MAX> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.inner)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const MAX: usize> serde::Deserialize<'de> for NowString<MAX> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        Self::try_from(string).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<const MAX: usize> zeroize::Zeroize for NowString<MAX> {
+    fn zeroize(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.inner);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::serialization::Encode;
+    use alloc::vec::Vec;
 
-    const STRING_CHINESE: &'static str = "简介";
+    const STRING_CHINESE: &str = "简介";
 
     #[rustfmt::skip]
     const NOW_STRING_CHINESE: [u8; 8] = [
@@ -267,6 +395,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn decode_now_string_over_limit() {
+        let limits = crate::serialization::DecodeLimits {
+            max_bytes: 4,
+            ..Default::default()
+        };
+        let err = NowString64::decode_from(&mut Cursor::with_limits(&NOW_STRING_CHINESE, limits)).unwrap_err();
+        assert!(matches!(err.kind, ProtoErrorKind::LimitExceeded("NowString")));
+    }
+
+    #[test]
+    fn now_string_try_from_str() {
+        assert_eq!(NowString16::try_from("hello").unwrap(), "hello");
+        assert!(NowString16::try_from("this string is too long").is_err());
+        assert!(NowString16::try_from(String::from("this string is too long")).is_err());
+    }
+
+    #[test]
+    fn now_string_lossy_truncates_on_char_boundary() {
+        assert_eq!(
+            NowString16::from_str_lossy("this string is too long"),
+            "this string is t"
+        );
+        // 3 bytes per char, truncated to 15 bytes rather than in the middle of the 6th char
+        let s = NowString16::from_string_lossy(STRING_CHINESE.repeat(3));
+        assert_eq!(s.len(), 15);
+        assert_eq!(s, "简介简介简");
+    }
+
+    #[test]
+    fn now_str_macro() {
+        let s = crate::now_str!(NowString64, "简介");
+        assert_eq!(s, STRING_CHINESE);
+        assert_eq!(NowString64::MAX_LEN, 64);
+    }
+
     #[test]
     fn decode_too_big_size_now_string_64() {
         let mut bytes = [0; 66];
@@ -312,4 +476,43 @@ mod tests {
         let nstr = NowString65535::from_str(STRING_CHINESE).unwrap();
         assert_eq!(nstr.encode().unwrap(), NOW_STRING_65535_CHINESE.to_vec());
     }
+
+    #[test]
+    fn now_string_deref_and_widening() {
+        let small = NowString16::from_str("NowChat").unwrap();
+        assert!(small.starts_with("Now"));
+        assert_eq!(AsRef::<str>::as_ref(&small), "NowChat");
+
+        let wide = NowString256::from(small.clone());
+        assert_eq!(wide, "NowChat");
+        assert_eq!(NowString65535::from(wide), "NowChat");
+        // the length prefix only depends on the maximum length
+        assert_eq!(
+            NowString::<300>::from_str("NowChat").unwrap().encoded_len(),
+            small.encoded_len() + 1
+        );
+    }
+
+    #[test]
+    fn now_string_256_prefix_limit() {
+        assert_eq!(NowString256::MAX_LEN, 255);
+
+        let longest = NowString256::from_str(&"a".repeat(255)).unwrap();
+        let encoded = longest.encode().unwrap();
+        assert_eq!(encoded.len(), 257);
+        assert_eq!(NowString256::decode(&encoded).unwrap(), longest);
+
+        assert!(NowString256::from_str(&"a".repeat(256)).is_err());
+        assert!(NowString256::helper_write_into(&mut Vec::new(), &"a".repeat(256)).is_err());
+        assert_eq!(NowString256::from_string_lossy("a".repeat(300)).len(), 255);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_now_string_size_limit() {
+        let nstr: NowString16 = serde_json::from_str("\"hello\"").unwrap();
+        assert_eq!(nstr, "hello");
+        assert_eq!(serde_json::to_string(&nstr).unwrap(), "\"hello\"");
+        assert!(serde_json::from_str::<NowString16>("\"this string is too long\"").is_err());
+    }
 }
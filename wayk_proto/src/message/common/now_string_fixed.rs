@@ -0,0 +1,299 @@
+// NOW_STRING (fixed capacity)
+
+use super::now_string::{check_len, decode_bytes, encode_str, encoded_str_len, LenPrefix};
+use super::NowString65535;
+use crate::error::*;
+use crate::io::{Cursor, NoStdWrite};
+use crate::serialization::{Decode, Encode};
+use alloc::borrow::Cow;
+use alloc::string::String;
+use core::convert::TryFrom;
+use core::fmt;
+use core::ops::Deref;
+use core::str::FromStr;
+
+/// Now string stored inline in a `[u8; N]` buffer, nothing is allocated.
+///
+/// Same API and wire format as `NowString`. With the `fixed-strings` feature, the `NowString16`
+/// to `NowString256` aliases used by messages resolve to this type. `NowString65535` is still
+/// heap allocated.
+pub struct NowStringFixed<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<'dec, const N: usize> Decode<'dec> for NowStringFixed<N> {
+    fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
+        let utf8_buf = decode_bytes::<N>(cursor)?;
+        let s = core::str::from_utf8(utf8_buf).map_err(|e| {
+            ProtoError::new(ProtoErrorKind::Decoding("NowString")).with_desc(format!("invalid utf8: {}", e))
+        })?;
+
+        // Safety: length checked when decoding
+        Ok(unsafe { Self::from_str_unchecked(s) })
+    }
+}
+
+impl<const N: usize> Encode for NowStringFixed<N> {
+    fn expected_size() -> crate::serialization::ExpectedSize
+    where
+        Self: Sized,
+    {
+        crate::serialization::ExpectedSize::Variable
+    }
+
+    fn encoded_len(&self) -> usize {
+        encoded_str_len::<N>(self.as_str())
+    }
+
+    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+        encode_str::<W, N>(writer, self.as_str())
+    }
+}
+
+impl<const N: usize> Default for NowStringFixed<N> {
+    fn default() -> Self {
+        Self { buf: [0; N], len: 0 }
+    }
+}
+
+impl<const N: usize> Clone for NowStringFixed<N> {
+    fn clone(&self) -> Self {
+        Self {
+            buf: self.buf,
+            len: self.len,
+        }
+    }
+}
+
+impl<const N: usize> fmt::Debug for NowStringFixed<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("NowStringFixed").field(&self.as_str()).finish()
+    }
+}
+
+impl<const N: usize> NowStringFixed<N> {
+    /// Maximum length in bytes.
+    pub const MAX_LEN: usize = LenPrefix::<N>::MAX_LEN;
+
+    pub fn new_empty() -> Self {
+        Self::default()
+    }
+
+    /// # Safety
+    /// Provided string slice len must not exceed `N`
+    pub unsafe fn from_str_unchecked(s: &str) -> Self {
+        let mut buf = [0; N];
+        buf[..s.len()].copy_from_slice(s.as_bytes());
+        Self { buf, len: s.len() }
+    }
+
+    /// # Safety
+    /// Provided string len must not exceed `N`
+    pub unsafe fn from_string_unchecked(s: String) -> Self {
+        Self::from_str_unchecked(&s)
+    }
+
+    pub fn from_string(string: String) -> Result<Self> {
+        Self::try_from(string.as_str())
+    }
+
+    /// Truncates the string to `MAX_LEN` bytes (on a char boundary) instead of failing.
+    pub fn from_str_lossy(s: &str) -> Self {
+        let mut len = s.len().min(Self::MAX_LEN);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        // Safety: truncated above
+        unsafe { Self::from_str_unchecked(&s[..len]) }
+    }
+
+    /// Truncates the string to `MAX_LEN` bytes (on a char boundary) instead of failing.
+    pub fn from_string_lossy(string: String) -> Self {
+        Self::from_str_lossy(&string)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Safety: only built from valid utf8 strings, truncated on char boundaries
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+
+    /// Encode an utf8 str into a now string
+    pub fn helper_write_into<W: NoStdWrite>(writer: &mut W, s: &str) -> Result<()> {
+        encode_str::<W, N>(writer, s)
+    }
+}
+
+impl<const N: usize> Deref for NowStringFixed<N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> AsRef<str> for NowStringFixed<N> {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> From<NowStringFixed<N>> for String {
+    fn from(s: NowStringFixed<N>) -> Self {
+        String::from(s.as_str())
+    }
+}
+
+impl<'a, const N: usize> From<NowStringFixed<N>> for Cow<'a, str> {
+    fn from(s: NowStringFixed<N>) -> Self {
+        Cow::Owned(s.into())
+    }
+}
+
+/// Widening conversions, a string always fits in a larger now string.
+macro_rules! now_string_fixed_widening {
+    ( $from:literal => $( $to:literal ),+ ) => {
+        $(
+            impl From<NowStringFixed<$from>> for NowStringFixed<$to> {
+                fn from(s: NowStringFixed<$from>) -> Self {
+                    // Safety: $from is lower than $to
+                    unsafe { Self::from_str_unchecked(s.as_str()) }
+                }
+            }
+        )+
+    };
+}
+
+now_string_fixed_widening! { 16  => 32, 64, 128, 256 }
+now_string_fixed_widening! { 32  => 64, 128, 256 }
+now_string_fixed_widening! { 64  => 128, 256 }
+now_string_fixed_widening! { 128 => 256 }
+
+impl<const N: usize> From<NowStringFixed<N>> for NowString65535 {
+    fn from(s: NowStringFixed<N>) -> Self {
+        // only truncated for fixed strings larger than what now strings can encode
+        NowString65535::from_str_lossy(s.as_str())
+    }
+}
+
+impl<const N: usize> TryFrom<String> for NowStringFixed<N> {
+    type Error = ProtoError;
+
+    fn try_from(string: String) -> Result<Self> {
+        Self::try_from(string.as_str())
+    }
+}
+
+impl<const N: usize> TryFrom<&str> for NowStringFixed<N> {
+    type Error = ProtoError;
+
+    fn try_from(s: &str) -> Result<Self> {
+        check_len::<N>(s)?;
+        // Safety: length checked above
+        Ok(unsafe { Self::from_str_unchecked(s) })
+    }
+}
+
+impl<const N: usize> FromStr for NowStringFixed<N> {
+    type Err = ProtoError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::try_from(s)
+    }
+}
+
+impl<const N: usize> PartialEq for NowStringFixed<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<const N: usize> Eq for NowStringFixed<N> {}
+
+impl<const N: usize> PartialEq<&str> for NowStringFixed<N> {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl<const N: usize> PartialEq<String> for NowStringFixed<N> {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a, const N: usize> arbitrary::Arbitrary<'a> for NowStringFixed<N> {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        <&str>::arbitrary(u).map(Self::from_str_lossy)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<const N: usize> serde::Serialize for NowStringFixed<N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const N: usize> serde::Deserialize<'de> for NowStringFixed<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        Self::try_from(string).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<const N: usize> zeroize::Zeroize for NowStringFixed<N> {
+    fn zeroize(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.buf);
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type NowStringFixed8 = NowStringFixed<8>;
+
+    #[rustfmt::skip]
+    const NOW_STRING_CHINESE: [u8; 8] = [
+        0x06, // size
+        0xe7, 0xae, 0x80, 0xe4, 0xbb, 0x8b, // actual UTF8 string
+        0x00, // null terminator
+    ];
+
+    #[test]
+    fn round_trip() {
+        let s = NowStringFixed8::decode(&NOW_STRING_CHINESE).unwrap();
+        assert_eq!(s, "简介");
+        assert_eq!(s.encoded_len(), NOW_STRING_CHINESE.len());
+        assert_eq!(s.encode().unwrap(), NOW_STRING_CHINESE.to_vec());
+    }
+
+    #[test]
+    fn size_limit() {
+        assert!(NowStringFixed8::try_from("too long!").is_err());
+        assert!(NowStringFixed::<4>::decode(&NOW_STRING_CHINESE).is_err());
+        // 3 bytes per char, truncated on a char boundary
+        assert_eq!(NowStringFixed8::from_str_lossy("简介简介"), "简介");
+    }
+
+    #[test]
+    fn widening() {
+        let s = NowStringFixed::<16>::from_str_lossy("简介");
+        assert_eq!(&*NowStringFixed::<64>::from(s.clone()), "简介");
+        assert_eq!(NowString65535::from(s), "简介");
+    }
+}
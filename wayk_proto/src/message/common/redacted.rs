@@ -0,0 +1,64 @@
+// REDACTED
+
+use core::fmt;
+
+/// Debug formatting for sensitive bytes: only the length and a non-cryptographic
+/// hash are shown, enough to tell two payloads apart without leaking them into logs.
+#[derive(Clone, Copy)]
+pub struct Redacted<'a>(pub &'a [u8]);
+
+impl Redacted<'_> {
+    /// 32-bit FNV-1a hash of the bytes.
+    pub fn hash(&self) -> u32 {
+        self.0.iter().fold(0x811c_9dc5u32, |hash, byte| {
+            (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+        })
+    }
+}
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<redacted len={} hash={:#010x}>", self.0.len(), self.hash())
+    }
+}
+
+/// Debug formatting for user content such as chat text or clipboard data.
+///
+/// Content is shown as is, unless the `redact` feature is enabled: it is then formatted like
+/// [`Redacted`](struct.Redacted.html) while the surrounding message structure stays visible.
+#[derive(Clone, Copy)]
+pub struct UserContent<'a, T: ?Sized>(pub &'a T);
+
+impl<T> fmt::Debug for UserContent<'_, T>
+where
+    T: AsRef<[u8]> + fmt::Debug + ?Sized,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if cfg!(feature = "redact") {
+            fmt::Debug::fmt(&Redacted(self.0.as_ref()), f)
+        } else {
+            fmt::Debug::fmt(self.0, f)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_debug() {
+        assert_eq!(format!("{:?}", Redacted(&[])), "<redacted len=0 hash=0x811c9dc5>");
+        assert_eq!(format!("{:?}", Redacted(b"a")), "<redacted len=1 hash=0xe40c292c>");
+    }
+
+    #[test]
+    fn user_content_debug() {
+        let debug = format!("{:?}", UserContent("a"));
+        if cfg!(feature = "redact") {
+            assert_eq!(debug, "<redacted len=1 hash=0xe40c292c>");
+        } else {
+            assert_eq!(debug, "\"a\"");
+        }
+    }
+}
@@ -2,7 +2,10 @@
 
 use core::mem;
 
-#[derive(Decode, Encode, Debug, Clone, Default)]
+#[derive(Decode, Encode, Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct SizeRect {
     pub x: i16,
     pub y: i16,
@@ -12,6 +15,14 @@ pub struct SizeRect {
 
 impl SizeRect {
     pub const REQUIRED_SIZE: usize = mem::size_of::<Self>();
+
+    pub fn new(x: i16, y: i16, width: u16, height: u16) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
 }
 
 #[cfg(test)]
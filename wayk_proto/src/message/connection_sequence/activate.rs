@@ -1,14 +1,11 @@
-#[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[derive(Encode, Decode, Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowActivateMsg {
     flags: u32,
 }
 
-impl Default for NowActivateMsg {
-    fn default() -> Self {
-        NowActivateMsg { flags: 0 }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
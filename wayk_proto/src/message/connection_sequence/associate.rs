@@ -1,8 +1,13 @@
 // NOW_ASSOCIATE_MSG
 
 use crate::message::status::{AssociateStatusCode, NowStatus};
+use crate::message::Redacted;
+use alloc::borrow::Cow;
+use core::fmt;
 
 #[derive(Decode, Encode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum AssociateMessageType {
     #[value = 0x01]
     Info,
@@ -14,14 +19,32 @@ pub enum AssociateMessageType {
     Other(u8),
 }
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "AssociateMessageType"]
+#[roundtrip]
 pub enum NowAssociateMsg<'a> {
     Info(NowAssociateInfoMsg),
     Request(NowAssociateRequestMsg),
     Response(NowAssociateResponseMsg),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
+}
+
+// unknown association payloads may carry session secrets, keep them out of logs
+impl fmt::Debug for NowAssociateMsg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Info(msg) => f.debug_tuple("Info").field(msg).finish(),
+            Self::Request(msg) => f.debug_tuple("Request").field(msg).finish(),
+            Self::Response(msg) => f.debug_tuple("Response").field(msg).finish(),
+            Self::Custom(payload) => f.debug_tuple("Custom").field(&Redacted(payload)).finish(),
+        }
+    }
 }
 
 impl NowAssociateMsg<'_> {
@@ -72,8 +95,12 @@ __flags_struct! {
     }
 }
 
-#[derive(Decode, Encode, Debug, Clone)]
+#[derive(Decode, Encode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowAssociateInfoMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: AssociateMessageType,
     reserved: u8,
     pub flags: AssociateInfoFlags,
@@ -110,8 +137,12 @@ __flags_struct! {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowAssociateRequestMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: AssociateMessageType,
     reserved: u8,
     pub flags: AssociateRequestFlags,
@@ -147,8 +178,12 @@ __flags_struct! {
     }
 }
 
-#[derive(Decode, Encode, Debug, Clone)]
+#[derive(Decode, Encode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowAssociateResponseMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: AssociateMessageType,
     reserved: u8,
     pub flags: AssociateResponseFlags,
@@ -1,10 +1,18 @@
+use crate::auth::NowAuthToken;
 use crate::container::{Bytes16, Vec16};
+use crate::error::Result;
 use crate::message::status::{AuthStatusCode, NowStatus};
+use crate::message::Redacted;
+use crate::serialization::Encode;
+use alloc::borrow::Cow;
 use alloc::vec::Vec;
+use core::fmt;
 
 // TODO: check usage of this enum...
 // SRP message types
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum SRPMessageType {
     #[value = 0x01]
     SRPInitiate,
@@ -19,6 +27,8 @@ pub enum SRPMessageType {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum AuthenticateMessageType {
     #[value = 0x01]
     Token,
@@ -31,6 +41,8 @@ pub enum AuthenticateMessageType {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum AuthType {
     #[value = 0x00]
     None,
@@ -60,21 +72,46 @@ __flags_struct! {
     }
 }
 
+__flags_struct! {
+    AuthenticateTokenFlags: u8 => {
+        session_token = SESSION_TOKEN = 0x01, // token data is a `NowAuthSessionToken`
+    }
+}
+
 // NOW_AUTHENTICATE_MSG
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "AuthenticateMessageType"]
+#[roundtrip]
 pub enum NowAuthenticateMsg<'a> {
     Token(NowAuthenticateTokenMsg<'a>),
     Success(NowAuthenticateSuccessMsg),
     Failure(NowAuthenticateFailureMsg),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
 
     #[decode_ignore]
     OwnedToken(NowAuthenticateTokenMsgOwned),
 }
 
+// authentication payloads may contain credentials, keep them out of logs
+impl fmt::Debug for NowAuthenticateMsg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Token(msg) => f.debug_tuple("Token").field(msg).finish(),
+            Self::Success(msg) => f.debug_tuple("Success").field(msg).finish(),
+            Self::Failure(msg) => f.debug_tuple("Failure").field(msg).finish(),
+            Self::Custom(payload) => f.debug_tuple("Custom").field(&Redacted(payload)).finish(),
+            Self::OwnedToken(msg) => f.debug_tuple("OwnedToken").field(msg).finish(),
+        }
+    }
+}
+
 impl<'a> From<NowAuthenticateTokenMsg<'a>> for NowAuthenticateMsg<'a> {
     fn from(msg: NowAuthenticateTokenMsg<'a>) -> Self {
         Self::Token(msg)
@@ -101,12 +138,20 @@ impl From<NowAuthenticateFailureMsg> for NowAuthenticateMsg<'_> {
 
 // subtypes
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowAuthenticateTokenMsg<'a> {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: AuthenticateMessageType,
     flags: u8,
     pub auth_type: AuthType,
-    auth_flags: u8,
+    pub auth_flags: AuthenticateTokenFlags,
     pub token_data: Bytes16<'a>,
 }
 
@@ -119,18 +164,47 @@ impl<'a> NowAuthenticateTokenMsg<'a> {
             subtype: Self::SUBTYPE,
             flags: 0,
             auth_type,
-            auth_flags: 0,
-            token_data: Bytes16(token_data),
+            auth_flags: AuthenticateTokenFlags::new_empty(),
+            token_data: Bytes16::from(token_data),
+        }
+    }
+
+    pub fn auth_flags(self, auth_flags: AuthenticateTokenFlags) -> Self {
+        Self { auth_flags, ..self }
+    }
+
+    /// Decodes the token after the authentication method it was sent with.
+    pub fn decode_token(&self) -> Result<NowAuthToken<'_>> {
+        if self.auth_flags.session_token() {
+            NowAuthToken::decode_session(&self.token_data)
+        } else {
+            NowAuthToken::decode_for(self.auth_type, &self.token_data)
         }
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+impl fmt::Debug for NowAuthenticateTokenMsg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NowAuthenticateTokenMsg")
+            .field("subtype", &self.subtype)
+            .field("flags", &self.flags)
+            .field("auth_type", &self.auth_type)
+            .field("auth_flags", &self.auth_flags)
+            .field("token_data", &Redacted(&self.token_data))
+            .finish()
+    }
+}
+
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowAuthenticateTokenMsgOwned {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: AuthenticateMessageType,
     flags: u8,
     pub auth_type: AuthType,
-    auth_flags: u8,
+    pub auth_flags: AuthenticateTokenFlags,
     pub token_data: Vec16<u8>,
 }
 
@@ -142,14 +216,42 @@ impl NowAuthenticateTokenMsgOwned {
             subtype: Self::SUBTYPE,
             flags: 0,
             auth_type,
-            auth_flags: 0,
+            auth_flags: AuthenticateTokenFlags::new_empty(),
             token_data: Vec16(token_data),
         }
     }
+
+    pub fn auth_flags(self, auth_flags: AuthenticateTokenFlags) -> Self {
+        Self { auth_flags, ..self }
+    }
+
+    pub fn from_token(token: &NowAuthToken<'_>) -> Result<Self> {
+        let msg = Self::new(token.auth_type(), token.encode()?);
+        match token {
+            NowAuthToken::Session(_) => Ok(msg.auth_flags(AuthenticateTokenFlags::new_empty().set_session_token())),
+            _ => Ok(msg),
+        }
+    }
+}
+
+impl fmt::Debug for NowAuthenticateTokenMsgOwned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NowAuthenticateTokenMsgOwned")
+            .field("subtype", &self.subtype)
+            .field("flags", &self.flags)
+            .field("auth_type", &self.auth_type)
+            .field("auth_flags", &self.auth_flags)
+            .field("token_data", &Redacted(&self.token_data))
+            .finish()
+    }
 }
 
-#[derive(Decode, Encode, Debug, Clone)]
+#[derive(Decode, Encode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowAuthenticateSuccessMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: AuthenticateMessageType,
     flags: u8,
     reserved: u16,
@@ -178,8 +280,29 @@ impl Default for NowAuthenticateSuccessMsg {
     }
 }
 
-#[derive(Decode, Encode, Debug, Clone)]
+impl fmt::Debug for NowAuthenticateSuccessMsg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut cookie = [0u8; 16];
+        for (chunk, part) in cookie.chunks_mut(4).zip(self.cookie.iter()) {
+            chunk.copy_from_slice(&part.to_le_bytes());
+        }
+
+        f.debug_struct("NowAuthenticateSuccessMsg")
+            .field("subtype", &self.subtype)
+            .field("flags", &self.flags)
+            .field("reserved", &self.reserved)
+            .field("session_id", &self.session_id)
+            .field("cookie", &Redacted(&cookie))
+            .finish()
+    }
+}
+
+#[derive(Decode, Encode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowAuthenticateFailureMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: AuthenticateMessageType,
     pub flags: AuthentificationFailureFlags,
     reserved: u16,
@@ -257,6 +380,26 @@ mod tests {
         assert_eq!(msg.token_data.len(), 281);
     }
 
+    #[test]
+    fn token_typed_decoding() {
+        let msg = NowAuthenticateTokenMsg::decode(&AUTHENTICATE_TOKEN_MSG).unwrap();
+        match msg.decode_token().unwrap() {
+            NowAuthToken::SRP(token) => {
+                assert_eq!(token.subtype, SRPMessageType::SRPInitiate);
+                assert_eq!(token.body.len(), 273);
+            }
+            token => panic!("Expected a SRP token, found {:?}", token),
+        }
+    }
+
+    #[test]
+    fn token_debug_is_redacted() {
+        let msg = NowAuthenticateMsg::decode(&AUTHENTICATE_TOKEN_MSG).unwrap();
+        let debug = format!("{:?}", msg);
+        assert!(debug.contains("token_data: <redacted len=281 hash="));
+        assert!(!debug.contains("token_data: ["));
+    }
+
     #[test]
     fn token_encoding() {
         let msg = NowAuthenticateTokenMsg::new(AuthType::SRP, &AUTHENTICATE_TOKEN_MSG[6..]);
@@ -1,8 +1,9 @@
 use crate::container::Vec8;
 use crate::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt, Result};
-use crate::io::{Cursor, NoStdWrite};
-use crate::message::{MouseMode, NowString, NowString64, NowSurfaceListReqMsg, NowSystemOsInfo};
+use crate::io::{Cursor, NoStdRead, NoStdWrite};
+use crate::message::{MouseMode, NowString64, NowSurfaceListReqMsg, NowSystemOsInfo};
 use crate::serialization::{Decode, Encode};
+use alloc::borrow::Cow;
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 use core::convert::TryFrom;
@@ -18,7 +19,11 @@ __flags_struct! {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+// holds surface definitions
+#[roundtrip(non_canonical)]
 pub struct SurfaceCapset {
     pub flags: SurfaceCapsetFlags,
     pub list_req: NowSurfaceListReqMsg,
@@ -35,6 +40,8 @@ impl SurfaceCapset {
 // NOW_UPDATE_CAPSET
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum Codec {
     #[value = 0x0000]
     Unspecified,
@@ -49,6 +56,8 @@ pub enum Codec {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum QualityMode {
     #[value = 0x00]
     Unspecified,
@@ -63,7 +72,11 @@ pub enum QualityMode {
 }
 
 #[derive(Debug, Clone, Decode, Encode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowCodecDef {
+    #[size_prefix]
     size: u16,
     pub id: Codec,
     pub flags: u32,
@@ -87,7 +100,17 @@ impl NowCodecDef {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+// `size` is computed on encode, the size declared by the peer isn't part of the value
+impl PartialEq for NowCodecDef {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.flags == other.flags
+    }
+}
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct UpdateCapset {
     flags: u32,
     pub quality_mode: QualityMode,
@@ -126,6 +149,8 @@ impl UpdateCapset {
 // NOW_INPUT_CAPSET
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum InputActionCode {
     #[value = 0x0001]
     SAS,
@@ -161,7 +186,10 @@ __flags_struct! {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowInputActionDef {
     pub code: InputActionCode,
     pub flags: InputActionFlags,
@@ -183,7 +211,10 @@ impl NowInputActionDef {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct InputCapset {
     flags: u32,
     reserved: u32,
@@ -210,7 +241,10 @@ __flags_struct! {
     }
 }
 
-#[derive(Decode, Encode, Debug, Clone)]
+#[derive(Decode, Encode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct MouseCapset {
     pub flags: MouseCapsetFlags,
     pub mode: MouseMode,
@@ -234,6 +268,8 @@ impl MouseCapset {
 // NOW_ACCESS_CAPSET
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum AccessControlCode {
     #[value = 0x0001]
     Viewing,
@@ -259,7 +295,10 @@ __flags_struct! {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct AccessControlDef {
     pub code: AccessControlCode,
     pub flags: AccessFlags,
@@ -283,7 +322,10 @@ impl AccessControlDef {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct AccessCapset {
     flags: u32,
     reserved: u32,
@@ -312,7 +354,10 @@ __flags_struct! {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct LicenseCapset {
     pub flags: LicenseCapsetFlags,
 }
@@ -323,19 +368,49 @@ impl LicenseCapset {
 
 // NOW_TRANSPORT_CAPSET
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct TransportCapset {
     flags: u32,
 }
 
-impl Default for TransportCapset {
-    fn default() -> Self {
-        TransportCapset { flags: 0 }
+impl TransportCapset {
+    const NAME: &'static str = "NowTransport";
+}
+
+// NOW_NETWORK_CAPSET
+
+__flags_struct! {
+    NetworkCapsetFlags: u32 => {
+        stats = STATS = 0x0000_0001,
+        rtt = RTT = 0x0000_0002,
+        bandwidth = BANDWIDTH = 0x0000_0004,
     }
 }
 
-impl TransportCapset {
-    const NAME: &'static str = "NowTransport";
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NetworkCapset {
+    pub flags: NetworkCapsetFlags,
+    /// Interval between RTT probes in milliseconds (0 to let the peer decide).
+    pub rtt_interval: u16,
+    reserved: u16,
+}
+
+impl NetworkCapset {
+    const NAME: &'static str = "NowNetwork";
+
+    pub fn new(flags: NetworkCapsetFlags, rtt_interval: u16) -> Self {
+        Self {
+            flags,
+            rtt_interval,
+            reserved: 0,
+        }
+    }
 }
 
 // NOW_SYSTEM_CAPSET
@@ -346,9 +421,18 @@ __flags_struct! {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+// `os_info` generated without its flag
+#[roundtrip(non_canonical)]
 pub struct SystemCapset<'a> {
     pub flags: SystemCapsetFlags,
+    #[flag_cond(flags = "flags", bit = "os_info")]
     pub os_info: Option<NowSystemOsInfo<'a>>,
 }
 
@@ -363,53 +447,46 @@ impl<'a> SystemCapset<'a> {
     }
 }
 
-impl Encode for SystemCapset<'_> {
-    fn expected_size() -> crate::serialization::ExpectedSize
-    where
-        Self: Sized,
-    {
-        crate::serialization::ExpectedSize::Variable
-    }
+// extension capset (library specific)
 
-    fn encoded_len(&self) -> usize {
-        let os_info_len = if let Some(os_info) = &self.os_info {
-            os_info.encoded_len()
-        } else {
-            0
-        };
+/// Advertises optional protocol extensions, identified by `vendor.feature` names.
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct ExtensionCapset {
+    pub extensions: Vec8<NowString64>,
+}
 
-        self.flags.encoded_len() + os_info_len
-    }
+impl ExtensionCapset {
+    const NAME: &'static str = "NowExtension";
 
-    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
-        self.flags.encode_into(writer)?;
-        if let Some(os_info) = &self.os_info {
-            os_info.encode_into(writer)?;
+    pub fn new_with_extensions(extensions: Vec<NowString64>) -> Self {
+        Self {
+            extensions: Vec8(extensions),
         }
-        Ok(())
     }
-}
 
-impl<'dec: 'a, 'a> Decode<'dec> for SystemCapset<'a> {
-    fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
-        let flags = SystemCapsetFlags::decode_from(cursor)?;
-        let os_info = if flags.os_info() {
-            Some(NowSystemOsInfo::decode_from(cursor)?)
-        } else {
-            None
-        };
-        Ok(SystemCapset { flags, os_info })
+    pub fn supports(&self, name: &str) -> bool {
+        self.extensions.iter().any(|extension| extension.as_str() == name)
     }
 }
 
 // unknown capset (not specified)
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct UnknownCapset<'a> {
     // capset struct full size (including size bits and name)
     pub size: u16,
     pub name: NowString64,
-    pub data: &'a [u8],
+    #[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))]
+    pub data: Cow<'a, [u8]>,
 }
 
 impl<'a> Encode for UnknownCapset<'a> {
@@ -425,38 +502,60 @@ impl<'a> Encode for UnknownCapset<'a> {
     }
 
     fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
-        self.size.encode_into(writer)?;
+        // computed rather than trusting `size`
+        u16::try_from(self.encoded_len())?.encode_into(writer)?;
         self.name.encode_into(writer)?;
-        for byte in self.data {
-            byte.encode_into(writer)?;
-        }
+        writer.write_all(&self.data)?;
         Ok(())
     }
 }
 
 impl<'dec: 'a, 'a> Decode<'dec> for UnknownCapset<'a> {
     fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
-        let size = cursor.read_u16()?;
+        let size = cursor.read_u16_le()?;
 
-        let name = NowString::decode_from(cursor)
+        let name = NowString64::decode_from(cursor)
             .chain(ProtoErrorKind::Decoding(__type_str!(UnknownCapset)))
             .or_desc("invalid capset name now string 64")?;
 
-        let start_inclusive = cursor.position() as usize;
-        let end_exclusive = start_inclusive + size as usize - mem::size_of_val(&size) - name.encoded_len();
-        let data = &cursor.get_ref()[start_inclusive..end_exclusive];
+        let data = h_read_capset_data(cursor, size, &name)?;
 
-        Ok(UnknownCapset { size, name, data })
+        Ok(UnknownCapset {
+            size,
+            name,
+            data: data.into(),
+        })
     }
 }
 
+/// Reads the capset data following the name, exactly as long as announced by the capset size.
+fn h_read_capset_data<'dec>(cursor: &mut Cursor<'dec>, size: u16, name: &NowString64) -> Result<&'dec [u8]> {
+    let header_len = mem::size_of_val(&size) + name.encoded_len();
+    let data_len = usize::from(size)
+        .checked_sub(header_len)
+        .chain(ProtoErrorKind::Decoding(__type_str!(NowCapset)))
+        .or_else_desc(|| format!("{} capset size ({}) is smaller than its header", name.as_str(), size))?;
+
+    cursor
+        .read_n(data_len)
+        .map_err(ProtoError::from)
+        .chain(ProtoErrorKind::Decoding(__type_str!(NowCapset)))
+        .or_else_desc(|| format!("{} capset size ({}) exceeds available data", name.as_str(), size))
+}
+
 impl<'a> UnknownCapset<'a> {
     pub const REQUIRED_SIZE: usize = 4;
 }
 
 // NOW_CAPABILITIES_MSG
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum NowCapset<'a> {
     Unknown(UnknownCapset<'a>),
     Transport(TransportCapset),
@@ -466,8 +565,9 @@ pub enum NowCapset<'a> {
     Update(UpdateCapset),
     Input(InputCapset),
     Mouse(MouseCapset),
-    //TODO: Network(NetworkCapset),
+    Network(NetworkCapset),
     System(Box<SystemCapset<'a>>),
+    Extension(ExtensionCapset),
 }
 
 impl NowCapset<'_> {
@@ -481,7 +581,9 @@ impl NowCapset<'_> {
             NowCapset::Update(_) => UpdateCapset::NAME,
             NowCapset::Input(_) => InputCapset::NAME,
             NowCapset::Mouse(_) => MouseCapset::NAME,
+            NowCapset::Network(_) => NetworkCapset::NAME,
             NowCapset::System(_) => SystemCapset::NAME,
+            NowCapset::Extension(_) => ExtensionCapset::NAME,
         }
     }
 }
@@ -525,7 +627,9 @@ impl<'a> Encode for NowCapset<'a> {
             NowCapset::Update(capset) => encoded_len_capset_variant!(capset, UpdateCapset),
             NowCapset::Input(capset) => encoded_len_capset_variant!(capset, InputCapset),
             NowCapset::Mouse(capset) => encoded_len_capset_variant!(capset, MouseCapset),
+            NowCapset::Network(capset) => encoded_len_capset_variant!(capset, NetworkCapset),
             NowCapset::System(capset) => encoded_len_capset_variant!(capset, SystemCapset),
+            NowCapset::Extension(capset) => encoded_len_capset_variant!(capset, ExtensionCapset),
         }
     }
 
@@ -553,9 +657,15 @@ impl<'a> Encode for NowCapset<'a> {
             NowCapset::Mouse(capset) => {
                 encode_capset_variant! { capset, MouseCapset, writer }
             }
+            NowCapset::Network(capset) => {
+                encode_capset_variant! { capset, NetworkCapset, writer }
+            }
             NowCapset::System(capset) => {
                 encode_capset_variant! { capset, SystemCapset, writer }
             }
+            NowCapset::Extension(capset) => {
+                encode_capset_variant! { capset, ExtensionCapset, writer }
+            }
         }
 
         Ok(())
@@ -563,28 +673,43 @@ impl<'a> Encode for NowCapset<'a> {
 }
 
 impl<'dec: 'a, 'a> Decode<'dec> for NowCapset<'a> {
+    /// Consumes exactly the capset size: known capsets are decoded from their data only, trailing
+    /// bytes (fields added by a newer peer) are skipped.
     fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
         let size = u16::decode_from(cursor)?;
         let name = NowString64::decode_from(cursor)?;
+        let data = h_read_capset_data(cursor, size, &name)?;
+
+        let data_cursor = &mut Cursor::with_limits(data, cursor.limits());
         match name.as_str() {
-            TransportCapset::NAME => Ok(Self::Transport(TransportCapset::decode_from(cursor)?)),
-            SurfaceCapset::NAME => Ok(Self::Surface(SurfaceCapset::decode_from(cursor)?)),
-            LicenseCapset::NAME => Ok(Self::License(LicenseCapset::decode_from(cursor)?)),
-            AccessCapset::NAME => Ok(Self::Access(AccessCapset::decode_from(cursor)?)),
-            UpdateCapset::NAME => Ok(Self::Update(UpdateCapset::decode_from(cursor)?)),
-            InputCapset::NAME => Ok(Self::Input(InputCapset::decode_from(cursor)?)),
-            MouseCapset::NAME => Ok(Self::Mouse(MouseCapset::decode_from(cursor)?)),
-            SystemCapset::NAME => Ok(Self::System(Box::new(SystemCapset::decode_from(cursor)?))),
+            TransportCapset::NAME => Ok(Self::Transport(TransportCapset::decode_from(data_cursor)?)),
+            SurfaceCapset::NAME => Ok(Self::Surface(SurfaceCapset::decode_from(data_cursor)?)),
+            LicenseCapset::NAME => Ok(Self::License(LicenseCapset::decode_from(data_cursor)?)),
+            AccessCapset::NAME => Ok(Self::Access(AccessCapset::decode_from(data_cursor)?)),
+            UpdateCapset::NAME => Ok(Self::Update(UpdateCapset::decode_from(data_cursor)?)),
+            InputCapset::NAME => Ok(Self::Input(InputCapset::decode_from(data_cursor)?)),
+            MouseCapset::NAME => Ok(Self::Mouse(MouseCapset::decode_from(data_cursor)?)),
+            NetworkCapset::NAME => Ok(Self::Network(NetworkCapset::decode_from(data_cursor)?)),
+            SystemCapset::NAME => Ok(Self::System(Box::new(SystemCapset::decode_from(data_cursor)?))),
+            ExtensionCapset::NAME => Ok(Self::Extension(ExtensionCapset::decode_from(data_cursor)?)),
             _ => Ok(Self::Unknown(UnknownCapset {
                 size,
                 name,
-                data: &cursor.get_ref()[cursor.position() as usize..],
+                data: data.into(),
             })),
         }
     }
 }
 
-#[derive(Encode, Decode, Clone, Debug)]
+#[derive(Encode, Decode, Clone, Debug, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+// holds system and surface capsets
+#[roundtrip(non_canonical)]
 pub struct NowCapabilitiesMsg<'a> {
     flags: u32,
     pub capabilities: Vec8<NowCapset<'a>>,
@@ -599,9 +724,152 @@ impl<'a> NowCapabilitiesMsg<'a> {
     }
 }
 
+// builder
+
+/// Builds a consistent list of capsets to advertise, see `ShareeBuilder::capabilities`.
+///
+/// Transport, license, update (JPEG only) and mouse (primary mode) capsets are advertised by
+/// default. The access capset is only added once an access control is set.
+#[derive(Debug, Clone)]
+pub struct CapabilitiesBuilder {
+    codecs: Vec<NowCodecDef>,
+    quality_mode: QualityMode,
+    mouse_mode: MouseMode,
+    license_flags: LicenseCapsetFlags,
+    access_controls: Vec<AccessControlDef>,
+    network: Option<NetworkCapset>,
+    os_info: Option<NowSystemOsInfo<'static>>,
+    others: Vec<NowCapset<'static>>,
+}
+
+impl Default for CapabilitiesBuilder {
+    fn default() -> Self {
+        Self {
+            codecs: vec![NowCodecDef::new(Codec::JPEG)],
+            quality_mode: QualityMode::Unspecified,
+            mouse_mode: MouseMode::Primary,
+            license_flags: LicenseCapsetFlags::new_empty(),
+            access_controls: Vec::new(),
+            network: None,
+            os_info: None,
+            others: Vec::new(),
+        }
+    }
+}
+
+impl CapabilitiesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Supported codecs, by order of preference.
+    pub fn codecs(self, codecs: impl IntoIterator<Item = Codec>) -> Self {
+        self.codec_defs(codecs.into_iter().map(NowCodecDef::new).collect())
+    }
+
+    /// Same as `codecs`, with codec specific flags.
+    pub fn codec_defs(self, codecs: Vec<NowCodecDef>) -> Self {
+        Self { codecs, ..self }
+    }
+
+    pub fn quality(self, quality_mode: QualityMode) -> Self {
+        Self { quality_mode, ..self }
+    }
+
+    pub fn mouse_mode(self, mouse_mode: MouseMode) -> Self {
+        Self { mouse_mode, ..self }
+    }
+
+    pub fn license_flags(self, license_flags: LicenseCapsetFlags) -> Self {
+        Self { license_flags, ..self }
+    }
+
+    /// Replaces the access control previously set for the same code, if any.
+    pub fn access_control(mut self, def: AccessControlDef) -> Self {
+        self.access_controls.retain(|existing| existing.code != def.code);
+        self.access_controls.push(def);
+        self
+    }
+
+    pub fn enable_clipboard(self) -> Self {
+        self.access_control(AccessControlDef::new_allowed(AccessControlCode::Clipboard))
+    }
+
+    pub fn enable_chat(self) -> Self {
+        self.access_control(AccessControlDef::new_allowed(AccessControlCode::Chat))
+    }
+
+    pub fn enable_file_transfer(self) -> Self {
+        self.access_control(AccessControlDef::new_allowed(AccessControlCode::FileTransfer))
+    }
+
+    pub fn network(self, network: NetworkCapset) -> Self {
+        Self {
+            network: Some(network),
+            ..self
+        }
+    }
+
+    pub fn os_info(self, os_info: NowSystemOsInfo<'static>) -> Self {
+        Self {
+            os_info: Some(os_info),
+            ..self
+        }
+    }
+
+    /// Advertises the running system, see `NowSystemOsInfo::detect`.
+    #[cfg(feature = "std")]
+    pub fn os_info_from_env(self) -> Self {
+        self.os_info(NowSystemOsInfo::detect())
+    }
+
+    /// Advertises a capset not covered by the builder, e.g. an unknown one.
+    pub fn capset(mut self, capset: NowCapset<'static>) -> Self {
+        self.others.push(capset);
+        self
+    }
+
+    pub fn build(self) -> Vec<NowCapset<'static>> {
+        let mut update = UpdateCapset::new_with_supported_codecs(self.codecs);
+        update.quality_mode = self.quality_mode;
+        if let Some(preferred) = update.codecs.first() {
+            update.codec_id = preferred.id;
+        }
+
+        let mut capabilities = vec![
+            NowCapset::Transport(TransportCapset::default()),
+            NowCapset::Update(update),
+            NowCapset::License(LicenseCapset {
+                flags: self.license_flags,
+            }),
+            NowCapset::Mouse(MouseCapset::new(self.mouse_mode, MouseCapsetFlags::new_empty())),
+        ];
+        if !self.access_controls.is_empty() {
+            capabilities.push(NowCapset::Access(AccessCapset::new_with_access_controls(
+                self.access_controls,
+            )));
+        }
+        if let Some(network) = self.network {
+            capabilities.push(NowCapset::Network(network));
+        }
+        if let Some(os_info) = self.os_info {
+            capabilities.push(NowCapset::System(Box::new(SystemCapset::new_os_info(os_info))));
+        }
+        capabilities.extend(self.others);
+        capabilities
+    }
+}
+
+impl From<CapabilitiesBuilder> for Vec<NowCapset<'static>> {
+    fn from(builder: CapabilitiesBuilder) -> Self {
+        builder.build()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::header::{AbstractNowHeader, NowHeader};
     use crate::message::{
         EdgeRect, NowString128, NowString16, NowString32, NowString64, NowSurfaceDef, OsArch, OsType, VirtChannelsCtx,
     };
@@ -699,27 +967,21 @@ mod tests {
 
     #[test]
     fn full_decode() {
-        let mut buffer = Vec::new();
-        let mut reader = std::io::Cursor::new(&CAPABILITIES_PACKET[..]);
-        match NowPacket::read_from(&mut reader, &mut buffer, &VirtChannelsCtx::new()) {
+        let header = NowHeader::decode(&CAPABILITIES_PACKET).unwrap();
+        let body = &CAPABILITIES_PACKET[header.len()..];
+        match NowPacket::decode_from(header, body, &VirtChannelsCtx::new()) {
             Ok(_) => {}
-            Err(e) => {
-                e.print_trace();
-                panic!("couldn't decode capabilities packet");
-            }
+            Err(e) => panic!("couldn't decode capabilities packet: {:#}", e),
         }
     }
 
     #[test]
     fn full_decode_windows() {
-        let mut buffer = Vec::new();
-        let mut reader = std::io::Cursor::new(&CAPABILITIES_WINDOWS_ARCH_PACKET[..]);
-        match NowPacket::read_from(&mut reader, &mut buffer, &VirtChannelsCtx::new()) {
+        let header = NowHeader::decode(&CAPABILITIES_WINDOWS_ARCH_PACKET).unwrap();
+        let body = &CAPABILITIES_WINDOWS_ARCH_PACKET[header.len()..];
+        match NowPacket::decode_from(header, body, &VirtChannelsCtx::new()) {
             Ok(_) => {}
-            Err(e) => {
-                e.print_trace();
-                panic!("couldn't decode capabilities packet");
-            }
+            Err(e) => panic!("couldn't decode capabilities packet: {:#}", e),
         }
     }
 
@@ -920,7 +1182,7 @@ mod tests {
         if let NowCapset::Unknown(capset) = capset {
             assert_eq!(capset.size, 25);
             assert_eq!(capset.name.as_str(), "something_unknown");
-            assert_eq!(capset.data, &[0x02, 0x29, 0x85, 0x12]);
+            assert_eq!(*capset.data, [0x02, 0x29, 0x85, 0x12]);
         } else {
             panic!("expected an unknown capset got {:?}", capset);
         }
@@ -931,11 +1193,82 @@ mod tests {
         let capset = NowCapset::Unknown(UnknownCapset {
             size: 25,
             name: NowString64::from_str("something_unknown").unwrap(),
-            data: &[0x02, 0x29, 0x85, 0x12],
+            data: Cow::Borrowed(&[0x02, 0x29, 0x85, 0x12]),
         });
         assert_eq!(capset.encode().unwrap(), UNKNOWN_CAPSET.to_vec(),)
     }
 
+    #[rustfmt::skip]
+    const NETWORK_CAPSET: [u8; 22] = [
+        // size
+        0x16, 0x00,
+        // name
+        0x0a, 0x4e, 0x6f, 0x77, 0x4e, 0x65, 0x74, 0x77, 0x6f, 0x72, 0x6b, 0x00,
+        // flags
+        0x03, 0x00, 0x00, 0x00,
+        // rtt interval
+        0xe8, 0x03,
+        // reserved
+        0x00, 0x00,
+    ];
+
+    #[test]
+    fn decode_network_capset() {
+        let capset = NowCapset::decode(&NETWORK_CAPSET).unwrap();
+        if let NowCapset::Network(capset) = capset {
+            assert!(capset.flags.stats());
+            assert!(capset.flags.rtt());
+            assert!(!capset.flags.bandwidth());
+            assert_eq!(capset.rtt_interval, 1000);
+        } else {
+            panic!("expected a network capset got {:?}", capset);
+        }
+    }
+
+    #[test]
+    fn encode_network_capset() {
+        let flags = NetworkCapsetFlags::new_empty().set_stats().set_rtt();
+        let capset = NowCapset::Network(NetworkCapset::new(flags, 1000));
+        assert_eq!(capset.encoded_len(), NETWORK_CAPSET.len());
+        assert_eq!(capset.encode().unwrap(), NETWORK_CAPSET.to_vec());
+    }
+
+    #[rustfmt::skip]
+    const CODEC_DEF_WITH_TRAILING_FIELD: [u8; 12] = [
+        0x0a, 0x00, // size
+        0x02, 0x00, // id
+        0x01, 0x00, 0x00, 0x00, // flags
+        0xff, 0xff, // unknown field
+        0x03, 0x00, // next codec id
+    ];
+
+    #[test]
+    fn codec_def_size_prefix_skips_trailing_bytes() {
+        let mut cursor = Cursor::new(&CODEC_DEF_WITH_TRAILING_FIELD);
+        let def = NowCodecDef::decode_from(&mut cursor).unwrap();
+        assert_eq!(def.id, Codec::JPEG);
+        assert_eq!(def.flags, 1);
+        assert_eq!(cursor.position(), 10);
+    }
+
+    #[test]
+    fn codec_def_size_prefix_validated() {
+        // declared size smaller than the fields
+        assert!(NowCodecDef::decode(&[0x04, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00]).is_err());
+        // declared size larger than the available data
+        assert!(NowCodecDef::decode(&[0x10, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn codec_def_size_prefix_computed_on_encode() {
+        let mut def = NowCodecDef::new_with_flags(Codec::JPEG, 1);
+        def.size = 42;
+        assert_eq!(
+            def.encode().unwrap(),
+            vec![0x08, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+    }
+
     const PACKET_WITHOUT_OS_INFO: [u8; 268] = [
         0x08, 0x01, 0x05, 0x80, 0x00, 0x00, 0x00, 0x00, 0x08, 0x14, 0x00, 0x0c, 0x4e, 0x6f, 0x77, 0x54, 0x72, 0x61,
         0x6e, 0x73, 0x70, 0x6f, 0x72, 0x74, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2b, 0x00, 0x0a, 0x4e, 0x6f, 0x77, 0x53,
@@ -956,14 +1289,122 @@ mod tests {
 
     #[test]
     fn full_decode_packet_without_os_info() {
-        let mut buffer = Vec::new();
-        let mut reader = std::io::Cursor::new(&PACKET_WITHOUT_OS_INFO[..]);
-        match NowPacket::read_from(&mut reader, &mut buffer, &VirtChannelsCtx::new()) {
+        let header = NowHeader::decode(&PACKET_WITHOUT_OS_INFO).unwrap();
+        let body = &PACKET_WITHOUT_OS_INFO[header.len()..];
+        match NowPacket::decode_from(header, body, &VirtChannelsCtx::new()) {
             Ok(_) => {}
-            Err(e) => {
-                e.print_trace();
-                panic!("couldn't decode capabilities packet");
+            Err(e) => panic!("couldn't decode capabilities packet: {:#}", e),
+        }
+    }
+
+    #[test]
+    fn capabilities_builder() {
+        let capabilities = CapabilitiesBuilder::new()
+            .codecs([Codec::GFWX, Codec::JPEG])
+            .quality(QualityMode::High)
+            .enable_clipboard()
+            .access_control(AccessControlDef::new_disabled(AccessControlCode::Clipboard))
+            .build();
+        let names: Vec<&str> = capabilities.iter().map(NowCapset::name_as_str).collect();
+        assert_eq!(
+            names,
+            ["NowTransport", "NowUpdate", "NowLicense", "NowMouse", "NowAccess"]
+        );
+
+        match &capabilities[1] {
+            NowCapset::Update(update) => {
+                assert_eq!(update.quality_mode, QualityMode::High);
+                assert_eq!(update.codec_id, Codec::GFWX);
+                assert_eq!(update.codecs.len(), 2);
             }
+            capset => panic!("expected an update capset and got {:?}", capset),
+        }
+        match &capabilities[4] {
+            NowCapset::Access(access) => assert_eq!(
+                access.access_controls.0,
+                [AccessControlDef::new_disabled(AccessControlCode::Clipboard)]
+            ),
+            capset => panic!("expected an access capset and got {:?}", capset),
         }
+
+        let msg = NowCapabilitiesMsg::new_with_capabilities(capabilities);
+        let encoded = msg.encode().unwrap();
+        let decoded = NowCapabilitiesMsg::decode(&encoded).unwrap();
+        assert_eq!(decoded.capabilities.len(), 5);
+    }
+
+    #[test]
+    fn multiple_unknown_capsets() {
+        let unknown = |name: &str, data: &'static [u8]| {
+            let name = NowString64::from_str(name).unwrap();
+            let size = (2 + name.encoded_len() + data.len()) as u16;
+            NowCapset::Unknown(UnknownCapset {
+                size,
+                name,
+                data: data.into(),
+            })
+        };
+        let flags = NetworkCapsetFlags::new_empty().set_rtt();
+        let msg = NowCapabilitiesMsg::new_with_capabilities(vec![
+            unknown("first_unknown", &[0x01, 0x02, 0x03]),
+            NowCapset::Network(NetworkCapset::new(flags, 500)),
+            unknown("second_unknown", &[]),
+            unknown("third_unknown", &[0xff; 7]),
+        ]);
+        let encoded = msg.encode().unwrap();
+
+        let decoded = NowCapabilitiesMsg::decode(&encoded).unwrap();
+        assert_eq!(decoded.capabilities.len(), 4);
+        let expected: [(&str, &[u8]); 3] = [
+            ("first_unknown", &[0x01, 0x02, 0x03]),
+            ("second_unknown", &[]),
+            ("third_unknown", &[0xff; 7]),
+        ];
+        let unknowns = decoded.capabilities.iter().filter_map(|capset| match capset {
+            NowCapset::Unknown(capset) => Some((capset.name.as_str(), &*capset.data)),
+            _ => None,
+        });
+        assert!(unknowns.eq(expected.iter().copied()));
+        match &decoded.capabilities[1] {
+            NowCapset::Network(network) => assert_eq!(network.rtt_interval, 500),
+            capset => panic!("expected a network capset and got {:?}", capset),
+        }
+    }
+
+    #[rustfmt::skip]
+    const NETWORK_CAPSET_WITH_TRAILING_FIELD: [u8; 26] = [
+        // size
+        0x1a, 0x00,
+        // name
+        0x0a, 0x4e, 0x6f, 0x77, 0x4e, 0x65, 0x74, 0x77, 0x6f, 0x72, 0x6b, 0x00,
+        // flags
+        0x03, 0x00, 0x00, 0x00,
+        // rtt interval
+        0xe8, 0x03,
+        // reserved
+        0x00, 0x00,
+        // unknown field
+        0xde, 0xad, 0xbe, 0xef,
+    ];
+
+    #[test]
+    fn known_capset_trailing_bytes_skipped() {
+        let mut cursor = Cursor::new(&NETWORK_CAPSET_WITH_TRAILING_FIELD);
+        match NowCapset::decode_from(&mut cursor).unwrap() {
+            NowCapset::Network(network) => assert_eq!(network.rtt_interval, 1000),
+            capset => panic!("expected a network capset and got {:?}", capset),
+        }
+        assert_eq!(cursor.position(), NETWORK_CAPSET_WITH_TRAILING_FIELD.len());
+    }
+
+    #[test]
+    fn capset_size_validated() {
+        let mut too_small = UNKNOWN_CAPSET;
+        too_small[0] = 10;
+        assert!(NowCapset::decode(&too_small).is_err());
+
+        let mut too_large = UNKNOWN_CAPSET;
+        too_large[0] = 26;
+        assert!(NowCapset::decode(&too_large).is_err());
     }
 }
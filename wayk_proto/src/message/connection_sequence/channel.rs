@@ -8,6 +8,8 @@ use wayk_proto::message::NowString64;
 use wayk_proto::serialization::{Decode, Encode};
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum ChannelMessageType {
     #[value = 0x01]
     ChannelListRequest,
@@ -33,6 +35,20 @@ pub enum ChannelMessageType {
     Other(u8),
 }
 
+impl ChannelMessageType {
+    /// Subtype answering this request subtype.
+    pub fn response_type(self) -> Option<Self> {
+        match self {
+            Self::ChannelListRequest => Some(Self::ChannelListResponse),
+            Self::ChannelOpenRequest => Some(Self::ChannelOpenResponse),
+            Self::ChannelCloseRequest => Some(Self::ChannelCloseResponse),
+            Self::ChannelStartRequest => Some(Self::ChannelStartResponse),
+            Self::ChannelStopRequest => Some(Self::ChannelStopResponse),
+            _ => None,
+        }
+    }
+}
+
 __flags_struct! {
     ChannelDefFlags: u32 => {
         dynamic = DYNAMIC = 0x0000_0001,
@@ -43,13 +59,18 @@ __flags_struct! {
         irp = IRP = 0x0004_0000,
         local = LOCAL = 0x0008_0000,
         proxy = PROXY = 0x0010_0000,
+        compress_deflate = COMPRESS_DEFLATE = 0x0020_0000,
+        compress_zstd = COMPRESS_ZSTD = 0x0040_0000,
         status = STATUS = 0x8000_0000,
         status_success = STATUS_SUCCESS = 0x8000_0000,
         status_failure = STATUS_FAILURE = 0x8000_0001,
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowChannelDef {
     pub flags: ChannelDefFlags,
     pub name: ChannelName,
@@ -66,6 +87,7 @@ impl NowChannelDef {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChannelName {
     Unknown(Cow<'static, str>),
     Clipboard,
@@ -102,7 +124,7 @@ impl Encode for ChannelName {
     }
 }
 
-impl<'dec: 'a, 'a> Decode<'dec> for ChannelName {
+impl<'dec> Decode<'dec> for ChannelName {
     fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
         let name = NowString64::decode_from(cursor)?;
         match name.as_str() {
@@ -116,6 +138,20 @@ impl<'dec: 'a, 'a> Decode<'dec> for ChannelName {
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for ChannelName {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(match u.int_in_range(0..=5)? {
+            0 => Self::Clipboard,
+            1 => Self::FileTransfer,
+            2 => Self::Exec,
+            3 => Self::Chat,
+            4 => Self::Tunnel,
+            _ => Self::Unknown(NowString64::arbitrary(u)?.into()),
+        })
+    }
+}
+
 impl ChannelName {
     pub const CLIPBOARD_STR: &'static str = "NowClipboard";
     pub const FILE_TRANSFER_STR: &'static str = "NowFileTransfer";
@@ -135,7 +171,10 @@ impl ChannelName {
     }
 }
 
-#[derive(Encode, Decode, Clone, Debug)]
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowChannelMsg {
     pub subtype: ChannelMessageType,
     flags: u8,
@@ -155,6 +194,7 @@ impl NowChannelMsg {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::header::{AbstractNowHeader, NowHeader};
     use crate::message::VirtChannelsCtx;
     use crate::packet::NowPacket;
 
@@ -167,14 +207,11 @@ mod tests {
 
     #[test]
     fn full_decode() {
-        let mut buffer = Vec::new();
-        let mut reader = std::io::Cursor::new(&CHANNEL_LIST_REQUEST_PACKET[..]);
-        match NowPacket::read_from(&mut reader, &mut buffer, &VirtChannelsCtx::new()) {
+        let header = NowHeader::decode(&CHANNEL_LIST_REQUEST_PACKET).unwrap();
+        let body = &CHANNEL_LIST_REQUEST_PACKET[header.len()..];
+        match NowPacket::decode_from(header, body, &VirtChannelsCtx::new()) {
             Ok(_) => {}
-            Err(e) => {
-                e.print_trace();
-                panic!("couldn't decode now channels packet");
-            }
+            Err(e) => panic!("couldn't decode now channels packet: {:#}", e),
         }
     }
 
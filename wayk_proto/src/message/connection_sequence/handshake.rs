@@ -1,7 +1,7 @@
 // NOW_HANDSHAKE_MSG
 
 use crate::message::status::{HandshakeStatusCode, NowStatus};
-use crate::version::{WAYK_NOW_VERSION_MAJOR, WAYK_NOW_VERSION_MINOR, WAYK_NOW_VERSION_PATCH};
+use crate::version::{NowVersion, WAYK_NOW_VERSION_MAJOR, WAYK_NOW_VERSION_MINOR, WAYK_NOW_VERSION_PATCH};
 
 __flags_struct! {
     HanshakeFlags: u32 => {
@@ -11,7 +11,10 @@ __flags_struct! {
     }
 }
 
-#[derive(Decode, Encode, Debug, Clone)]
+#[derive(Decode, Encode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowHandshakeMsg {
     pub version_major: u8,
     pub version_minor: u8,
@@ -37,6 +40,19 @@ impl NowHandshakeMsg {
         Self::default()
     }
 
+    pub fn new_with_version(version: NowVersion) -> Self {
+        Self {
+            version_major: version.major,
+            version_minor: version.minor,
+            version_patch: version.patch,
+            ..Self::default()
+        }
+    }
+
+    pub fn version(&self) -> NowVersion {
+        NowVersion::new(self.version_major, self.version_minor, self.version_patch)
+    }
+
     pub fn configure_failure(&mut self, status: NowStatus<HandshakeStatusCode>) {
         self.flags.set_failure();
         self.status = status;
@@ -7,10 +7,14 @@ use alloc::vec::Vec;
 __flags_struct! {
     NegotiateFlags: u32 => {
         srp_extended = SRP_EXTENDED = 0x0000_0001,
+        auth_token = AUTH_TOKEN = 0x0000_0002,
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowNegotiateMsg {
     pub flags: NegotiateFlags,
     pub auth_list: Vec8<AuthType>,
@@ -1,6 +1,9 @@
 use crate::message::status::{DisconnectStatusCode, NowStatus};
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowTerminateMsg {
     flags: u32,
     pub status: NowStatus<DisconnectStatusCode>,
@@ -1,6 +1,7 @@
 pub mod common;
 pub mod connection_sequence;
 pub mod now_messages;
+pub mod owned;
 pub mod status;
 pub mod virtual_channels;
 
@@ -8,17 +9,25 @@ pub mod virtual_channels;
 pub use common::*;
 pub use connection_sequence::*;
 pub use now_messages::*;
+pub use owned::*;
 pub use status::*;
 pub use virtual_channels::*;
 
+use crate::compression::Compression;
 use crate::error::*;
+use crate::header::HEADER_VIRTUAL_CHANNEL_FLAG;
 use crate::io::{Cursor, NoStdWrite};
-use crate::serialization::{Decode, Encode};
+use crate::registry::RegisteredMessage;
+use crate::serialization::{Decode, DecodeCtx, Encode};
+use alloc::borrow::Cow;
 use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 
 // == MESSAGE TYPE == //
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum MessageType {
     #[value = 0x00]
     Status,
@@ -65,8 +74,12 @@ pub enum MessageType {
 // == VIRTUAL CHANNELS CONTEXT ==
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct VirtChannelsCtx {
     entries: BTreeMap<u8, ChannelName>,
+    /// Channels without entry aren't compressed.
+    compressions: BTreeMap<u8, Compression>,
 }
 
 impl Default for VirtChannelsCtx {
@@ -76,9 +89,10 @@ impl Default for VirtChannelsCtx {
 }
 
 impl VirtChannelsCtx {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         Self {
-            entries: Default::default(),
+            entries: BTreeMap::new(),
+            compressions: BTreeMap::new(),
         }
     }
 
@@ -90,14 +104,58 @@ impl VirtChannelsCtx {
         self.entries.get(&id)
     }
 
+    /// Lowest id the channel is opened with, see `get_ids_by_channel` for `MULTIPLE` channels.
     pub fn get_id_by_channel(&self, name: &ChannelName) -> Option<u8> {
-        self.entries.iter().find(|pair| pair.1 == name).map(|pair| *pair.0)
+        self.get_ids_by_channel(name).next()
+    }
+
+    /// Ids of every opened instance of the channel, in ascending order.
+    pub fn get_ids_by_channel<'a>(&'a self, name: &'a ChannelName) -> impl Iterator<Item = u8> + 'a {
+        self.entries
+            .iter()
+            .filter(move |pair| pair.1 == name)
+            .map(|pair| *pair.0)
+    }
+
+    /// Channels ordered by id.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &ChannelName)> {
+        self.entries.iter().map(|(id, name)| (*id, name))
+    }
+
+    /// Forgets every instance of a closed channel, returns the lowest id.
+    pub fn remove(&mut self, name: &ChannelName) -> Option<u8> {
+        let ids: Vec<u8> = self.get_ids_by_channel(name).collect();
+        for id in &ids {
+            self.remove_id(*id);
+        }
+        ids.first().copied()
+    }
+
+    /// Forgets a single channel instance.
+    pub fn remove_id(&mut self, id: u8) -> Option<ChannelName> {
+        self.compressions.remove(&id);
+        self.entries.remove(&id)
+    }
+
+    /// Compression negotiated for the channel, see the `compression` module.
+    pub fn set_compression(&mut self, id: u8, compression: Compression) {
+        if compression == Compression::None {
+            self.compressions.remove(&id);
+        } else {
+            self.compressions.insert(id, compression);
+        }
+    }
+
+    pub fn get_compression(&self, id: u8) -> Compression {
+        self.compressions.get(&id).copied().unwrap_or(Compression::None)
     }
 }
 
 // == BODY TYPE == //
 
 #[derive(Debug, Clone, PartialEq, Copy, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum BodyType {
     Message(MessageType),
     VirtualChannel(u8),
@@ -126,6 +184,57 @@ impl Encode for BodyType {
     }
 }
 
+impl Decode<'_> for BodyType {
+    /// Decodes a message type: the raw byte is a virtual channel id only when the header flags
+    /// say so, see `decode_with_flags` to decode a body type following header flags.
+    fn decode_from(cursor: &mut Cursor<'_>) -> Result<Self> {
+        Ok(Self::Message(MessageType::decode_from(cursor)?))
+    }
+}
+
+impl BodyType {
+    /// Body type announced by the header `flags` and `raw` type byte.
+    pub fn from_raw(flags: u8, raw: u8) -> Result<Self> {
+        if flags & HEADER_VIRTUAL_CHANNEL_FLAG != 0 {
+            Ok(Self::VirtualChannel(raw))
+        } else {
+            Ok(Self::Message(MessageType::decode(&[raw])?))
+        }
+    }
+
+    /// Reads the raw type byte and interprets it following the header `flags`.
+    pub fn decode_with_flags(flags: u8, cursor: &mut Cursor<'_>) -> Result<Self> {
+        let raw = u8::decode_from(cursor)?;
+        Self::from_raw(flags, raw)
+    }
+
+    /// Header flags identifying this body type.
+    pub fn header_flags(&self) -> u8 {
+        match self {
+            Self::Message(_) => 0x00,
+            Self::VirtualChannel(_) => HEADER_VIRTUAL_CHANNEL_FLAG,
+        }
+    }
+
+    pub fn is_virtual_channel(&self) -> bool {
+        matches!(self, Self::VirtualChannel(_))
+    }
+
+    pub fn message_type(&self) -> Option<MessageType> {
+        match self {
+            Self::Message(msg_type) => Some(*msg_type),
+            Self::VirtualChannel(_) => None,
+        }
+    }
+
+    pub fn channel_id(&self) -> Option<u8> {
+        match self {
+            Self::Message(_) => None,
+            Self::VirtualChannel(id) => Some(*id),
+        }
+    }
+}
+
 impl From<MessageType> for BodyType {
     fn from(msg_type: MessageType) -> Self {
         Self::Message(msg_type)
@@ -140,7 +249,13 @@ impl From<u8> for BodyType {
 
 // == NOW BODY == //
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum NowBody<'a> {
     Message(NowMessage<'a>),
     VirtualChannel(NowVirtualChannel<'a>),
@@ -186,13 +301,59 @@ impl<'a> From<NowVirtualChannel<'a>> for NowBody<'a> {
 
 // == NOW VIRTUAL CHANNEL == //
 
-#[derive(Debug, Clone, Encode)]
+#[derive(Debug, Clone, Encode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct CustomVirtualChannel<'a> {
+    // conveyed by the channel id in the header
+    #[encode_ignore]
     pub name: ChannelName,
-    pub payload: &'a [u8],
+    #[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))]
+    pub payload: Cow<'a, [u8]>,
 }
 
-#[derive(Debug, Clone)]
+/// Same as `CustomVirtualChannel`, for payloads encoded on the fly such as `CustomChannel` messages.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct CustomVirtualChannelOwned {
+    // conveyed by the channel id in the header
+    pub name: ChannelName,
+    pub payload: Vec<u8>,
+}
+
+impl Encode for CustomVirtualChannelOwned {
+    fn expected_size() -> crate::serialization::ExpectedSize
+    where
+        Self: Sized,
+    {
+        crate::serialization::ExpectedSize::Variable
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.payload.len()
+    }
+
+    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()>
+    where
+        Self: Sized,
+    {
+        writer.write_all(&self.payload)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum NowVirtualChannel<'a> {
     Clipboard(NowClipboardMsg<'a>),
     Chat(NowChatMsg<'a>),
@@ -200,8 +361,15 @@ pub enum NowVirtualChannel<'a> {
     // TODO: FileTransfer(NowFileTransferMsg),
     // TODO: Tunnel(NowTunnelMsg),
     Custom(CustomVirtualChannel<'a>),
+    /// Only built to be sent, received custom messages are always borrowed.
+    CustomOwned(CustomVirtualChannelOwned),
 }
 
+// Virtual channel messages are moved around for every packet: box large variants instead of growing this enum.
+// Not holding with `fixed-strings`, where strings are stored inline.
+#[cfg(not(feature = "fixed-strings"))]
+sa::const_assert!(core::mem::size_of::<NowVirtualChannel>() <= 64);
+
 impl<'a> Encode for NowVirtualChannel<'a> {
     fn expected_size() -> crate::serialization::ExpectedSize
     where
@@ -215,6 +383,7 @@ impl<'a> Encode for NowVirtualChannel<'a> {
             Self::Clipboard(msg) => msg.encoded_len(),
             Self::Chat(msg) => msg.encoded_len(),
             Self::Custom(msg) => msg.encoded_len(),
+            Self::CustomOwned(msg) => msg.encoded_len(),
         }
     }
 
@@ -226,18 +395,27 @@ impl<'a> Encode for NowVirtualChannel<'a> {
             Self::Clipboard(msg) => msg.encode_into(writer),
             Self::Chat(msg) => msg.encode_into(writer),
             Self::Custom(msg) => msg.encode_into(writer),
+            Self::CustomOwned(msg) => msg.encode_into(writer),
         }
     }
 }
 
 impl<'a> NowVirtualChannel<'a> {
     pub fn decode_from<'dec: 'a>(channel: &ChannelName, cursor: &mut Cursor<'dec>) -> Result<Self> {
+        Self::decode_with_ctx(channel, cursor, &DecodeCtx::default())
+    }
+
+    pub fn decode_with_ctx<'dec: 'a>(
+        channel: &ChannelName,
+        cursor: &mut Cursor<'dec>,
+        ctx: &DecodeCtx<'_>,
+    ) -> Result<Self> {
         Ok(match channel {
-            ChannelName::Clipboard => Self::Clipboard(NowClipboardMsg::decode_from(cursor)?),
-            ChannelName::Chat => Self::Chat(NowChatMsg::decode_from(cursor)?),
+            ChannelName::Clipboard => Self::Clipboard(NowClipboardMsg::decode_with_ctx(cursor, ctx)?),
+            ChannelName::Chat => Self::Chat(NowChatMsg::decode_with_ctx(cursor, ctx)?),
             _ => Self::Custom(CustomVirtualChannel {
                 name: channel.clone(),
-                payload: cursor.read_rest()?,
+                payload: cursor.read_rest()?.into(),
             }),
         })
     }
@@ -247,6 +425,7 @@ impl<'a> NowVirtualChannel<'a> {
             NowVirtualChannel::Clipboard(_) => &ChannelName::Clipboard,
             NowVirtualChannel::Chat(_) => &ChannelName::Chat,
             NowVirtualChannel::Custom(msg) => &msg.name,
+            NowVirtualChannel::CustomOwned(msg) => &msg.name,
         }
     }
 }
@@ -389,9 +568,21 @@ impl<'a> From<CustomVirtualChannel<'a>> for NowVirtualChannel<'a> {
     }
 }
 
+impl From<CustomVirtualChannelOwned> for NowVirtualChannel<'_> {
+    fn from(msg: CustomVirtualChannelOwned) -> Self {
+        Self::CustomOwned(msg)
+    }
+}
+
 // == NOW MESSAGE == //
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum NowMessage<'a> {
     Handshake(NowHandshakeMsg),
     Negotiate(NowNegotiateMsg),
@@ -402,13 +593,29 @@ pub enum NowMessage<'a> {
     Activate(NowActivateMsg),
     Terminate(NowTerminateMsg),
     Input(NowInputMsg<'a>),
+    Mouse(NowMouseMsg<'a>),
+    Network(NowNetworkMsg<'a>),
     Surface(NowSurfaceMsg<'a>),
     Update(NowUpdateMsg<'a>),
     System(NowSystemMsg<'a>),
     Sharing(NowSharingMsg<'a>),
     Access(NowAccessMsg<'a>),
-    Custom { ty: MessageType, payload: &'a [u8] },
-}
+    Desktop(NowDesktopMsg<'a>),
+    Session(NowSessionMsg<'a>),
+    Custom {
+        ty: MessageType,
+        #[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))]
+        payload: Cow<'a, [u8]>,
+    },
+    /// Custom message decoded through a `MessageRegistry`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    #[cfg_attr(feature = "arbitrary", arbitrary(skip))]
+    Registered(RegisteredMessage),
+}
+
+// Same as above, large messages are boxed (e.g. `NowSystemMsg::InfoRsp`) to keep this enum small.
+#[cfg(not(feature = "fixed-strings"))]
+sa::const_assert!(core::mem::size_of::<NowMessage>() <= 64);
 
 impl<'a> Encode for NowMessage<'a> {
     fn expected_size() -> crate::serialization::ExpectedSize
@@ -429,12 +636,17 @@ impl<'a> Encode for NowMessage<'a> {
             NowMessage::Activate(m) => m.encoded_len(),
             NowMessage::Terminate(m) => m.encoded_len(),
             NowMessage::Input(m) => m.encoded_len(),
+            NowMessage::Mouse(m) => m.encoded_len(),
+            NowMessage::Network(m) => m.encoded_len(),
             NowMessage::Surface(m) => m.encoded_len(),
             NowMessage::Update(m) => m.encoded_len(),
             NowMessage::System(m) => m.encoded_len(),
             NowMessage::Sharing(m) => m.encoded_len(),
             NowMessage::Access(m) => m.encoded_len(),
+            NowMessage::Desktop(m) => m.encoded_len(),
+            NowMessage::Session(m) => m.encoded_len(),
             NowMessage::Custom { payload, .. } => payload.len(),
+            NowMessage::Registered(m) => m.encoded_len(),
         }
     }
 
@@ -449,40 +661,60 @@ impl<'a> Encode for NowMessage<'a> {
             NowMessage::Activate(m) => m.encode_into(writer),
             NowMessage::Terminate(m) => m.encode_into(writer),
             NowMessage::Input(m) => m.encode_into(writer),
+            NowMessage::Mouse(m) => m.encode_into(writer),
+            NowMessage::Network(m) => m.encode_into(writer),
             NowMessage::Surface(m) => m.encode_into(writer),
             NowMessage::Update(m) => m.encode_into(writer),
             NowMessage::System(m) => m.encode_into(writer),
             NowMessage::Sharing(m) => m.encode_into(writer),
             NowMessage::Access(m) => m.encode_into(writer),
+            NowMessage::Desktop(m) => m.encode_into(writer),
+            NowMessage::Session(m) => m.encode_into(writer),
             NowMessage::Custom { payload, .. } => {
                 writer.write_all(payload)?;
                 Ok(())
             }
+            NowMessage::Registered(m) => m.encode_into(writer),
         }
     }
 }
 
 impl<'a> NowMessage<'a> {
     pub fn decode_from<'dec: 'a>(msg_type: MessageType, cursor: &mut Cursor<'dec>) -> Result<Self> {
+        Self::decode_with_ctx(msg_type, cursor, &DecodeCtx::default())
+    }
+
+    pub fn decode_with_ctx<'dec: 'a>(
+        msg_type: MessageType,
+        cursor: &mut Cursor<'dec>,
+        ctx: &DecodeCtx<'_>,
+    ) -> Result<Self> {
         Ok(match msg_type {
-            MessageType::Handshake => Self::Handshake(NowHandshakeMsg::decode_from(cursor)?),
-            MessageType::Negotiate => Self::Negotiate(NowNegotiateMsg::decode_from(cursor)?),
-            MessageType::Authenticate => Self::Authenticate(NowAuthenticateMsg::decode_from(cursor)?),
-            MessageType::Associate => Self::Associate(NowAssociateMsg::decode_from(cursor)?),
-            MessageType::Capabilities => Self::Capabilities(NowCapabilitiesMsg::decode_from(cursor)?),
-            MessageType::Channel => Self::Channel(NowChannelMsg::decode_from(cursor)?),
-            MessageType::Activate => Self::Activate(NowActivateMsg::decode_from(cursor)?),
-            MessageType::Terminate => Self::Terminate(NowTerminateMsg::decode_from(cursor)?),
-            MessageType::Surface => Self::Surface(NowSurfaceMsg::decode_from(cursor)?),
-            MessageType::Update => Self::Update(NowUpdateMsg::decode_from(cursor)?),
-            MessageType::System => Self::System(NowSystemMsg::decode_from(cursor)?),
-            MessageType::Input => Self::Input(NowInputMsg::decode_from(cursor)?),
-            MessageType::Sharing => Self::Sharing(NowSharingMsg::decode_from(cursor)?),
-            MessageType::Access => Self::Access(NowAccessMsg::decode_from(cursor)?),
-            _ => {
-                let payload = cursor.read_rest()?;
-                Self::Custom { ty: msg_type, payload }
-            }
+            MessageType::Handshake => Self::Handshake(NowHandshakeMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Negotiate => Self::Negotiate(NowNegotiateMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Authenticate => Self::Authenticate(NowAuthenticateMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Associate => Self::Associate(NowAssociateMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Capabilities => Self::Capabilities(NowCapabilitiesMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Channel => Self::Channel(NowChannelMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Activate => Self::Activate(NowActivateMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Terminate => Self::Terminate(NowTerminateMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Surface => Self::Surface(NowSurfaceMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Update => Self::Update(NowUpdateMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::System => Self::System(NowSystemMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Input => Self::Input(NowInputMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Mouse => Self::Mouse(NowMouseMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Network => Self::Network(NowNetworkMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Sharing => Self::Sharing(NowSharingMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Access => Self::Access(NowAccessMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Desktop => Self::Desktop(NowDesktopMsg::decode_with_ctx(cursor, ctx)?),
+            MessageType::Session => Self::Session(NowSessionMsg::decode_with_ctx(cursor, ctx)?),
+            _ => match ctx.registry.and_then(|registry| registry.decode(msg_type, cursor)) {
+                Some(msg) => Self::Registered(msg?),
+                None => {
+                    let payload = cursor.read_rest()?.into();
+                    Self::Custom { ty: msg_type, payload }
+                }
+            },
         })
     }
 
@@ -497,16 +729,27 @@ impl<'a> NowMessage<'a> {
             NowMessage::Activate(_) => MessageType::Activate,
             NowMessage::Terminate(_) => MessageType::Terminate,
             NowMessage::Input(_) => MessageType::Input,
+            NowMessage::Mouse(_) => MessageType::Mouse,
+            NowMessage::Network(_) => MessageType::Network,
             NowMessage::Surface(_) => MessageType::Surface,
             NowMessage::Update(_) => MessageType::Update,
             NowMessage::System(_) => MessageType::System,
             NowMessage::Sharing(_) => MessageType::Sharing,
-            NowMessage::Access(_) => MessageType::Sharing,
+            NowMessage::Access(_) => MessageType::Access,
+            NowMessage::Desktop(_) => MessageType::Desktop,
+            NowMessage::Session(_) => MessageType::Session,
             NowMessage::Custom { ty, .. } => *ty,
+            NowMessage::Registered(m) => m.get_type(),
         }
     }
 }
 
+impl From<RegisteredMessage> for NowMessage<'_> {
+    fn from(msg: RegisteredMessage) -> Self {
+        Self::Registered(msg)
+    }
+}
+
 impl From<NowHandshakeMsg> for NowMessage<'_> {
     fn from(msg: NowHandshakeMsg) -> Self {
         Self::Handshake(msg)
@@ -561,6 +804,18 @@ impl<'a> From<NowInputMsg<'a>> for NowMessage<'a> {
     }
 }
 
+impl<'a> From<NowMouseMsg<'a>> for NowMessage<'a> {
+    fn from(msg: NowMouseMsg<'a>) -> Self {
+        Self::Mouse(msg)
+    }
+}
+
+impl<'a> From<NowNetworkMsg<'a>> for NowMessage<'a> {
+    fn from(msg: NowNetworkMsg<'a>) -> Self {
+        Self::Network(msg)
+    }
+}
+
 impl<'a> From<NowSurfaceMsg<'a>> for NowMessage<'a> {
     fn from(msg: NowSurfaceMsg<'a>) -> Self {
         Self::Surface(msg)
@@ -590,3 +845,67 @@ impl<'a> From<NowAccessMsg<'a>> for NowMessage<'a> {
         Self::Access(msg)
     }
 }
+
+impl<'a> From<NowDesktopMsg<'a>> for NowMessage<'a> {
+    fn from(msg: NowDesktopMsg<'a>) -> Self {
+        Self::Desktop(msg)
+    }
+}
+
+impl<'a> From<NowSessionMsg<'a>> for NowMessage<'a> {
+    fn from(msg: NowSessionMsg<'a>) -> Self {
+        Self::Session(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_virtual_channel_roundtrip() {
+        let payload = [0x01, 0x02, 0x03];
+        let msg = NowVirtualChannel::from(CustomVirtualChannel {
+            name: ChannelName::Exec,
+            payload: Cow::Borrowed(&payload),
+        });
+        let encoded = msg.encode().unwrap();
+        assert_eq!(encoded, payload);
+
+        match NowVirtualChannel::decode_from(&ChannelName::Exec, &mut Cursor::new(&encoded)).unwrap() {
+            NowVirtualChannel::Custom(msg) => {
+                assert_eq!(msg.name, ChannelName::Exec);
+                assert_eq!(*msg.payload, payload);
+            }
+            msg => panic!("expected a custom virtual channel message and got {:?}", msg),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_json_roundtrip_byte_fields() {
+        let payload = [0xaa, 0xbb, 0xcc];
+        let messages = [
+            NowMessage::from(NowNetworkMsg::from(NowNetworkBandwidthReqMsg::new(7, &payload))),
+            NowMessage::Custom {
+                ty: MessageType::from(0xA7),
+                payload: Cow::Borrowed(&payload),
+            },
+            NowMessage::from(NowNetworkMsg::Custom(Cow::Borrowed(&payload))),
+        ];
+        for msg in messages.iter() {
+            // JSON can't lend bytes to the deserialized value, they must be copied
+            let json = serde_json::to_string(msg).unwrap();
+            let decoded: NowMessage = serde_json::from_str(&json).unwrap();
+            assert_eq!(&decoded, msg);
+        }
+
+        let msg = NowVirtualChannel::from(CustomVirtualChannel {
+            name: ChannelName::Exec,
+            payload: Cow::Borrowed(&payload),
+        });
+        let json = serde_json::to_string(&msg).unwrap();
+        let decoded: NowVirtualChannel = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, msg);
+    }
+}
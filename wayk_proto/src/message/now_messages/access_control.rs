@@ -1,4 +1,5 @@
 use crate::message::{AccessControlCode, AccessFlags};
+use alloc::borrow::Cow;
 
 __flags_struct! {
     AccessControlFlags: u8 => {
@@ -7,6 +8,8 @@ __flags_struct! {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum AccessControlMessageType {
     #[value = 0x01]
     Req,
@@ -28,8 +31,12 @@ __flags_struct! {
 
 // NOW_ACCESS_CONTROL_REQ_MSG
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowAcessControlReq {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: AccessControlMessageType,
     flags: AccessControlFlags,
     pub id: AccessControlCode,
@@ -51,34 +58,83 @@ impl NowAcessControlReq {
 
 // NOW_ACCESS_CONTROL_RSP_MSG
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowAcessControlRsp {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: AccessControlMessageType,
     pub flags: AccessControlFlags,
     pub id: AccessControlCode,
     pub reason: AccessReason,
 }
 
+impl NowAcessControlRsp {
+    pub const SUBTYPE: AccessControlMessageType = AccessControlMessageType::Rsp;
+
+    pub fn new_granted(id: AccessControlCode) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: AccessControlFlags::new_empty(),
+            id,
+            reason: AccessReason::new_empty(),
+        }
+    }
+
+    pub fn new_denied(id: AccessControlCode, reason: AccessReason) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: AccessControlFlags::new_empty().set_failure(),
+            id,
+            reason,
+        }
+    }
+}
+
 // NOW_ACCESS_CONTROL_NTF_MSG
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowAcessControlNtf {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: AccessControlMessageType,
     pub flags: AccessControlFlags,
     pub id: AccessControlCode,
     pub status: AccessFlags,
 }
 
+impl NowAcessControlNtf {
+    pub const SUBTYPE: AccessControlMessageType = AccessControlMessageType::Ntf;
+
+    pub fn new(id: AccessControlCode, status: AccessFlags) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: AccessControlFlags::new_empty(),
+            id,
+            status,
+        }
+    }
+}
+
 // NOW_ACCESS_MSG
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "AccessControlMessageType"]
+#[roundtrip]
 pub enum NowAccessMsg<'a> {
     Req(NowAcessControlReq),
     Rsp(NowAcessControlRsp),
     Ntf(NowAcessControlNtf),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
 }
 
 #[cfg(test)]
@@ -123,6 +179,12 @@ mod tests {
         }
     }
 
+    #[test]
+    fn access_control_rsp_encoding() {
+        let rsp = NowAcessControlRsp::new_denied(AccessControlCode::Chat, AccessReason::from(AccessReason::TIMEOUT));
+        assert_eq!(rsp.encode().unwrap(), ACCESS_CONTROL_RSP_MSG.to_vec());
+    }
+
     const ACCESS_CONTROL_NTF_MSG: [u8; 6] = [0x03, 0x00, 0x03, 0x00, 0x01, 0x00];
 
     #[test]
@@ -0,0 +1,174 @@
+// NOW_DESKTOP_MSG
+
+use alloc::borrow::Cow;
+
+#[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
+pub enum DesktopMessageType {
+    #[value = 0x01]
+    ResizeReq,
+    #[value = 0x02]
+    ResizeRsp,
+    #[fallback]
+    Other(u8),
+}
+
+#[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
+pub enum DesktopResizeStatus {
+    #[value = 0x0000]
+    Success,
+    #[value = 0x0001]
+    Unsupported,
+    #[value = 0x0002]
+    InvalidSize,
+    #[value = 0x0003]
+    Denied,
+    #[fallback]
+    Other(u16),
+}
+
+// NOW_DESKTOP_RESIZE_REQ_MSG
+
+/// Requests the remote desktop resolution to be changed for the given surface.
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowDesktopResizeReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
+    subtype: DesktopMessageType,
+    flags: u8,
+    pub surface_id: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl NowDesktopResizeReqMsg {
+    pub const SUBTYPE: DesktopMessageType = DesktopMessageType::ResizeReq;
+
+    pub fn new(surface_id: u16, width: u16, height: u16) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: 0,
+            surface_id,
+            width,
+            height,
+        }
+    }
+}
+
+// NOW_DESKTOP_RESIZE_RSP_MSG
+
+/// Answers a resize request with the resolution actually applied.
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowDesktopResizeRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
+    subtype: DesktopMessageType,
+    flags: u8,
+    pub surface_id: u16,
+    pub status: DesktopResizeStatus,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl NowDesktopResizeRspMsg {
+    pub const SUBTYPE: DesktopMessageType = DesktopMessageType::ResizeRsp;
+
+    pub fn new(surface_id: u16, status: DesktopResizeStatus, width: u16, height: u16) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: 0,
+            surface_id,
+            status,
+            width,
+            height,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[meta_enum = "DesktopMessageType"]
+#[roundtrip]
+pub enum NowDesktopMsg<'a> {
+    ResizeReq(NowDesktopResizeReqMsg),
+    ResizeRsp(NowDesktopResizeRspMsg),
+    #[fallback]
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
+}
+
+impl From<NowDesktopResizeReqMsg> for NowDesktopMsg<'_> {
+    fn from(msg: NowDesktopResizeReqMsg) -> Self {
+        Self::ResizeReq(msg)
+    }
+}
+
+impl From<NowDesktopResizeRspMsg> for NowDesktopMsg<'_> {
+    fn from(msg: NowDesktopResizeRspMsg) -> Self {
+        Self::ResizeRsp(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{AbstractNowHeader, NowHeader};
+    use crate::message::{NowBody, NowMessage, VirtChannelsCtx};
+    use crate::packet::NowPacket;
+    use crate::serialization::{Decode, Encode};
+
+    #[rustfmt::skip]
+    const RESIZE_REQ_FULL_PACKET: [u8; 12] = [
+        0x08, 0x00, 0x47, 0x80, // header
+        0x01, // subtype
+        0x00, // flags
+        0x00, 0x00, // surface id
+        0x80, 0x07, // width
+        0x38, 0x04, // height
+    ];
+
+    #[rustfmt::skip]
+    const RESIZE_RSP_MSG: [u8; 10] = [
+        0x02, // subtype
+        0x00, // flags
+        0x00, 0x00, // surface id
+        0x02, 0x00, // status
+        0x00, 0x05, // width
+        0x20, 0x03, // height
+    ];
+
+    #[test]
+    fn resize_req_decode_full_packet() {
+        let header = NowHeader::decode(&RESIZE_REQ_FULL_PACKET).unwrap();
+        let body = &RESIZE_REQ_FULL_PACKET[header.len()..];
+        let packet = NowPacket::decode_from(header, body, &VirtChannelsCtx::new()).unwrap();
+        if let NowBody::Message(NowMessage::Desktop(NowDesktopMsg::ResizeReq(msg))) = packet.body {
+            assert_eq!(msg, NowDesktopResizeReqMsg::new(0, 1920, 1080));
+        } else {
+            panic!("expected a desktop resize request message and got {:?}", packet.body);
+        }
+    }
+
+    #[test]
+    fn resize_req_encode_full_packet() {
+        let packet = NowPacket::from_message(NowDesktopMsg::from(NowDesktopResizeReqMsg::new(0, 1920, 1080)));
+        assert_eq!(packet.encode().unwrap(), RESIZE_REQ_FULL_PACKET.to_vec());
+    }
+
+    #[test]
+    fn resize_rsp_roundtrip() {
+        let msg = NowDesktopResizeRspMsg::new(0, DesktopResizeStatus::InvalidSize, 1280, 800);
+        assert_eq!(msg.encode().unwrap(), RESIZE_RSP_MSG.to_vec());
+        assert_eq!(NowDesktopResizeRspMsg::decode(&RESIZE_RSP_MSG).unwrap(), msg);
+    }
+}
@@ -1,4 +1,5 @@
-use crate::io::{Cursor, NoStdWrite};
+use crate::io::{Cursor, NoStdRead, NoStdWrite};
+use alloc::borrow::Cow;
 use alloc::vec::Vec;
 use core::mem;
 use wayk_proto::container::Vec16;
@@ -7,6 +8,8 @@ use wayk_proto::message::connection_sequence::InputActionCode;
 use wayk_proto::serialization::{Decode, Encode};
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum InputMessageType {
     #[value = 0x01]
     Mouse,
@@ -25,6 +28,8 @@ pub enum InputMessageType {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum EventMouseFlags {
     #[value = 0x0]
     None,
@@ -42,8 +47,12 @@ pub enum EventMouseFlags {
     Other(u8),
 }
 
-#[derive(Encode, Decode, Clone, Debug)]
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowInputEventMouse {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = InputMessageType::Mouse))]
     subtype: InputMessageType,
     pub flags: EventMouseFlags,
     pub x: i16,
@@ -61,8 +70,12 @@ impl NowInputEventMouse {
     }
 }
 
-#[derive(Encode, Decode, Clone, Debug)]
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowInputEventScroll {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = InputMessageType::Scroll))]
     subtype: InputMessageType,
     flags: u8,
     pub x: i16,
@@ -80,8 +93,12 @@ impl NowInputEventScroll {
     }
 }
 
-#[derive(Encode, Decode, Clone, Debug)]
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowInputEventKeyboard {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = InputMessageType::Keyboard))]
     subtype: InputMessageType,
     pub flags: u8,
     pub code: u16,
@@ -97,13 +114,16 @@ impl NowInputEventKeyboard {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct NowInputEventUnicode {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = InputMessageType::Unicode))]
     subtype: InputMessageType,
     pub code: Vec<u8>,
 }
 
-impl<'a> Encode for NowInputEventUnicode {
+impl Encode for NowInputEventUnicode {
     fn expected_size() -> crate::serialization::ExpectedSize
     where
         Self: Sized,
@@ -116,6 +136,12 @@ impl<'a> Encode for NowInputEventUnicode {
     }
 
     fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+        // code size (1 to 4 bytes) is stored minus one in the two upper bits of flags
+        if self.code.is_empty() || self.code.len() > 4 {
+            return Err(ProtoError::new(ProtoErrorKind::Encoding("NowInputEventUnicode"))
+                .with_desc(format!("invalid code size ({})", self.code.len())));
+        }
+
         self.subtype.encode_into(writer)?;
         let flags = (self.code.len() as u8 - 1) << 6;
         flags.encode_into(writer)?;
@@ -124,26 +150,19 @@ impl<'a> Encode for NowInputEventUnicode {
     }
 }
 
-impl<'dec: 'a, 'a> Decode<'dec> for NowInputEventUnicode {
+impl<'dec> Decode<'dec> for NowInputEventUnicode {
     fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
-        let _subtype = cursor.read_u8()?;
-        let flags = cursor.read_u8()?;
-
-        let start_inclusive = cursor.position() as usize;
-
-        let code_size = (flags >> 6) + 1;
-        let end_exclusive = start_inclusive + code_size as usize;
-
-        const SUBTYPE_FLAGS_BYTES: usize = 2;
-        let bytes_left = (cursor.get_ref().len() - start_inclusive + SUBTYPE_FLAGS_BYTES) as usize;
-
-        let code = if bytes_left == end_exclusive {
-            cursor.get_ref()[start_inclusive..end_exclusive].to_vec()
-        } else {
-            return Err(ProtoError::new(ProtoErrorKind::Decoding(
-                "NowInputEventUnicode: bytes_left != end_exclusive",
-            )));
-        };
+        let _subtype = NoStdRead::read_u8(cursor)?;
+        let flags = NoStdRead::read_u8(cursor)?;
+
+        // other events may follow, only the announced code bytes belong to this one
+        let code_size = usize::from(flags >> 6) + 1;
+        let code = cursor
+            .read_n(code_size)
+            .map_err(ProtoError::from)
+            .chain(ProtoErrorKind::Decoding(__type_str!(NowInputEventUnicode)))
+            .or_else_desc(|| format!("couldn't read {} code bytes", code_size))?
+            .to_vec();
 
         Ok(NowInputEventUnicode {
             subtype: InputMessageType::Unicode,
@@ -162,6 +181,8 @@ impl NowInputEventUnicode {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum ToggleEventKeys {
     #[value = 0x0001]
     ScrollLock,
@@ -175,8 +196,12 @@ pub enum ToggleEventKeys {
     Other(u16),
 }
 
-#[derive(Encode, Decode, Clone, Debug)]
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowInputEventToggle {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = InputMessageType::Toggle))]
     subtype: InputMessageType,
     flags: u8,
     pub code: u16,
@@ -192,8 +217,12 @@ impl NowInputEventToggle {
     }
 }
 
-#[derive(Encode, Decode, Clone, Debug)]
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowInputEventAction {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = InputMessageType::Action))]
     subtype: InputMessageType,
     flags: u8,
     pub code: InputActionCode,
@@ -209,8 +238,15 @@ impl NowInputEventAction {
     }
 }
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "InputMessageType"]
+// unicode events may be generated without a code, those aren't encoded
+#[roundtrip(non_canonical)]
 pub enum InputEvent<'a> {
     Mouse(NowInputEventMouse),
     Scroll(NowInputEventScroll),
@@ -219,10 +255,17 @@ pub enum InputEvent<'a> {
     Toggle(NowInputEventToggle),
     Action(NowInputEventAction),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
 }
 
-#[derive(Encode, Decode, Clone, Debug)]
+#[derive(Encode, Decode, Clone, Debug, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+// no `roundtrip`: events aren't length prefixed, a `Custom` event swallows the events following it
 pub struct NowInputMsg<'a> {
     input_event: Vec16<InputEvent<'a>>,
 }
@@ -233,12 +276,208 @@ impl<'a> NowInputMsg<'a> {
             input_event: Vec16(input_event),
         }
     }
+
+    pub fn events(&self) -> &[InputEvent<'a>] {
+        &self.input_event
+    }
+}
+
+// builder
+
+/// Keyboard event flag set when the key is pressed (unset on release).
+pub const KEYBOARD_FLAG_DOWN: u8 = 0x01;
+
+/// Default maximum size of a batched `NowInputMsg` body. Small enough to keep input latency low.
+pub const DEFAULT_INPUT_BATCH_LEN: usize = 1024;
+
+/// Fluent helper to generate input event sequences.
+///
+/// The builder keeps track of the last mouse position so that button events are sent
+/// at the pointer location.
+#[derive(Debug, Clone, Default)]
+pub struct InputEventBuilder<'a> {
+    events: Vec<InputEvent<'a>>,
+    x: i16,
+    y: i16,
+}
+
+impl<'a> InputEventBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn event(mut self, event: InputEvent<'a>) -> Self {
+        self.events.push(event);
+        self
+    }
+
+    pub fn mouse_move(mut self, x: i16, y: i16) -> Self {
+        self.x = x;
+        self.y = y;
+        self.mouse(EventMouseFlags::None)
+    }
+
+    /// Mouse event at the current position with `flags` buttons held.
+    pub fn mouse(self, flags: EventMouseFlags) -> Self {
+        let (x, y) = (self.x, self.y);
+        self.event(InputEvent::Mouse(NowInputEventMouse::new_with_flags_and_position(
+            flags, x, y,
+        )))
+    }
+
+    /// Presses and releases `button` at the current position.
+    pub fn click(self, button: EventMouseFlags) -> Self {
+        self.mouse(button).mouse(EventMouseFlags::None)
+    }
+
+    pub fn left_click(self) -> Self {
+        self.click(EventMouseFlags::ButtonLeft)
+    }
+
+    pub fn right_click(self) -> Self {
+        self.click(EventMouseFlags::ButtonRight)
+    }
+
+    pub fn middle_click(self) -> Self {
+        self.click(EventMouseFlags::ButtonMiddle)
+    }
+
+    pub fn scroll(self, x: i16, y: i16) -> Self {
+        self.event(InputEvent::Scroll(NowInputEventScroll::new_with_position(x, y)))
+    }
+
+    /// `code` is a virtual key code (see `NOW_VKCODE_EXT` for extended keys).
+    pub fn key_down(self, code: u16) -> Self {
+        self.event(InputEvent::Keyboard(NowInputEventKeyboard::new_with_flags_and_code(
+            KEYBOARD_FLAG_DOWN,
+            code,
+        )))
+    }
+
+    pub fn key_up(self, code: u16) -> Self {
+        self.event(InputEvent::Keyboard(NowInputEventKeyboard::new_with_flags_and_code(
+            0, code,
+        )))
+    }
+
+    pub fn key_press(self, code: u16) -> Self {
+        self.key_down(code).key_up(code)
+    }
+
+    /// Generates one unicode event per character (UTF-8 encoded).
+    pub fn type_text(self, text: &str) -> Self {
+        text.chars().fold(self, |builder, c| builder.type_char(c))
+    }
+
+    pub fn type_char(self, c: char) -> Self {
+        let mut buf = [0u8; 4];
+        let code = c.encode_utf8(&mut buf).as_bytes().to_vec();
+        self.event(InputEvent::Unicode(NowInputEventUnicode::new(code)))
+    }
+
+    pub fn toggle(self, keys: ToggleEventKeys) -> Self {
+        self.event(InputEvent::Toggle(NowInputEventToggle::new_with_code(u16::from(keys))))
+    }
+
+    pub fn action(self, code: InputActionCode) -> Self {
+        self.event(InputEvent::Action(NowInputEventAction::new_with_code(code)))
+    }
+
+    pub fn events(&self) -> &[InputEvent<'a>] {
+        &self.events
+    }
+
+    pub fn into_events(self) -> Vec<InputEvent<'a>> {
+        self.events
+    }
+
+    /// Splits the events into messages of at most `DEFAULT_INPUT_BATCH_LEN` bytes.
+    pub fn build(self) -> Vec<NowInputMsg<'a>> {
+        let mut batcher = InputBatcher::new();
+        batcher.extend(self.events);
+        batcher.finish()
+    }
+}
+
+/// Coalesces input events into `NowInputMsg` batches of bounded size.
+#[derive(Debug, Clone)]
+pub struct InputBatcher<'a> {
+    max_body_len: usize,
+    current: Vec<InputEvent<'a>>,
+    current_len: usize,
+    batches: Vec<NowInputMsg<'a>>,
+}
+
+impl Default for InputBatcher<'_> {
+    fn default() -> Self {
+        Self::with_max_body_len(DEFAULT_INPUT_BATCH_LEN)
+    }
+}
+
+impl<'a> InputBatcher<'a> {
+    const EVENT_COUNT_LEN: usize = mem::size_of::<u16>();
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A single event bigger than `max_body_len` still gets a batch of its own.
+    pub fn with_max_body_len(max_body_len: usize) -> Self {
+        Self {
+            max_body_len,
+            current: Vec::new(),
+            current_len: Self::EVENT_COUNT_LEN,
+            batches: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: InputEvent<'a>) {
+        let event_len = event.encoded_len();
+        if !self.current.is_empty()
+            && (self.current_len + event_len > self.max_body_len || self.current.len() == usize::from(u16::MAX))
+        {
+            self.flush_current();
+        }
+        self.current_len += event_len;
+        self.current.push(event);
+    }
+
+    pub fn extend<I: IntoIterator<Item = InputEvent<'a>>>(&mut self, events: I) {
+        for event in events {
+            self.push(event);
+        }
+    }
+
+    /// Returns the completed batches, pending events are kept for the next batch.
+    pub fn take_batches(&mut self) -> Vec<NowInputMsg<'a>> {
+        mem::take(&mut self.batches)
+    }
+
+    /// Closes the pending batch and returns all batches.
+    pub fn finish(mut self) -> Vec<NowInputMsg<'a>> {
+        self.flush_current();
+        self.batches
+    }
+
+    pub fn flush(&mut self) -> Vec<NowInputMsg<'a>> {
+        self.flush_current();
+        self.take_batches()
+    }
+
+    fn flush_current(&mut self) {
+        if !self.current.is_empty() {
+            let events = mem::take(&mut self.current);
+            self.batches.push(NowInputMsg::new_with_events(events));
+            self.current_len = Self::EVENT_COUNT_LEN;
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::message::VirtChannelsCtx;
+    use crate::header::{AbstractNowHeader, NowHeader};
+    use crate::message::{NowBody, NowMessage, VirtChannelsCtx};
     use crate::packet::NowPacket;
 
     const TOGGLE_EVENT_FULL_PACKET: [u8; 10] = [0x06, 0x00, 0x43, 0x80, 0x01, 0x00, 0x05, 0x00, 0x02, 0x00];
@@ -277,14 +516,11 @@ mod tests {
 
     #[test]
     fn input_event_mouse_decode_full_packet() {
-        let mut buffer = Vec::new();
-        let mut reader = std::io::Cursor::new(&MOUSE_POSITION_EVENT_FULL_PACKET[..]);
-        match NowPacket::read_from(&mut reader, &mut buffer, &VirtChannelsCtx::new()) {
+        let header = NowHeader::decode(&MOUSE_POSITION_EVENT_FULL_PACKET).unwrap();
+        let body = &MOUSE_POSITION_EVENT_FULL_PACKET[header.len()..];
+        match NowPacket::decode_from(header, body, &VirtChannelsCtx::new()) {
             Ok(_) => {}
-            Err(e) => {
-                e.print_trace();
-                panic!("couldn't decode input mouse event packet");
-            }
+            Err(e) => panic!("couldn't decode input mouse event packet: {:#}", e),
         }
     }
 
@@ -373,4 +609,88 @@ mod tests {
             panic!("didnt decode unicode message")
         }
     }
+
+    #[test]
+    fn input_msg_with_several_unicode_events_roundtrip() {
+        let mut events = InputEventBuilder::new()
+            .type_text("a\u{e9}\u{20ac}\u{1f600}")
+            .into_events();
+        events.push(InputEvent::Mouse(NowInputEventMouse::new_with_flags_and_position(
+            EventMouseFlags::None,
+            1508,
+            631,
+        )));
+        let msg = NowInputMsg::new_with_events(events);
+
+        let encoded = NowPacket::from_message(msg.clone()).encode().unwrap();
+        let header = NowHeader::decode(&encoded).unwrap();
+        let body = &encoded[header.len()..];
+        let packet = NowPacket::decode_from(header, body, &VirtChannelsCtx::new()).unwrap();
+        match packet.body {
+            NowBody::Message(NowMessage::Input(decoded)) => assert_eq!(decoded, msg),
+            body => panic!("expected an input message and got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn builder_click_at_position() {
+        let events = InputEventBuilder::new()
+            .mouse_move(1508, 631)
+            .left_click()
+            .into_events();
+        assert_eq!(events.len(), 3);
+        match &events[1] {
+            InputEvent::Mouse(mouse) => {
+                assert_eq!(mouse.flags, EventMouseFlags::ButtonLeft);
+                assert_eq!((mouse.x, mouse.y), (1508, 631));
+            }
+            _ => panic!("expected a mouse event"),
+        }
+        match &events[2] {
+            InputEvent::Mouse(mouse) => assert_eq!(mouse.flags, EventMouseFlags::None),
+            _ => panic!("expected a mouse event"),
+        }
+    }
+
+    #[test]
+    fn builder_key_press() {
+        let msgs = InputEventBuilder::new().key_press(8).build();
+        assert_eq!(msgs.len(), 1);
+        let packet = NowPacket::from_message(msgs.into_iter().next().unwrap());
+        assert_eq!(
+            packet.encode().unwrap(),
+            vec![0x0a, 0x00, 0x43, 0x80, 0x02, 0x00, 0x03, 0x01, 0x08, 0x00, 0x03, 0x00, 0x08, 0x00]
+        );
+    }
+
+    #[test]
+    fn builder_type_text_flags() {
+        let events = InputEventBuilder::new()
+            .type_text("a\u{e9}\u{20ac}\u{1f600}")
+            .into_events();
+        let encoded: Vec<Vec<u8>> = events.iter().map(|e| e.encode().unwrap()).collect();
+        assert_eq!(encoded[0], vec![0x04, 0x00, 0x61]);
+        assert_eq!(encoded[1], vec![0x04, 0x40, 0xc3, 0xa9]);
+        assert_eq!(encoded[2], vec![0x04, 0x80, 0xe2, 0x82, 0xac]);
+        assert_eq!(encoded[3], vec![0x04, 0xc0, 0xf0, 0x9f, 0x98, 0x80]);
+    }
+
+    #[test]
+    fn batcher_splits_by_size() {
+        // mouse events are 6 bytes, the event count 2 bytes
+        let mut batcher = InputBatcher::with_max_body_len(20);
+        batcher.extend((0..7).map(|i| {
+            InputEvent::Mouse(NowInputEventMouse::new_with_flags_and_position(
+                EventMouseFlags::None,
+                i,
+                i,
+            ))
+        }));
+        assert_eq!(batcher.take_batches().len(), 2);
+
+        let pending = batcher.flush();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].events().len(), 1);
+        assert!(pending[0].encoded_len() <= 20);
+    }
 }
@@ -1,8 +1,11 @@
 // ****** Now Messages ****** //
 
 pub mod access_control;
+pub mod desktop;
 pub mod input;
 pub mod mouse;
+pub mod network;
+pub mod session;
 pub mod sharing;
 pub mod surface;
 pub mod system;
@@ -10,8 +13,11 @@ pub mod update;
 
 // re-export
 pub use access_control::*;
+pub use desktop::*;
 pub use input::*;
 pub use mouse::*;
+pub use network::*;
+pub use session::*;
 pub use sharing::*;
 pub use surface::*;
 pub use system::*;
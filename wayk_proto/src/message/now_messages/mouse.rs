@@ -1,6 +1,16 @@
+use crate::container::Bytes32;
+use crate::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt, Result};
+use crate::io::{Cursor, NoStdRead, NoStdWrite};
+use crate::serialization::{Decode, Encode};
+use alloc::borrow::Cow;
+use core::convert::TryFrom;
+use core::mem;
+
 // NOW_MOUSE_MSG
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum MouseMessageType {
     #[value = 0x01]
     Position,
@@ -27,6 +37,8 @@ __flags_struct! {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum MouseCursorType {
     #[value = 0x00]
     Mono,
@@ -39,6 +51,8 @@ pub enum MouseCursorType {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum MouseMode {
     #[value = 0x01]
     Primary,
@@ -51,6 +65,8 @@ pub enum MouseMode {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum MouseState {
     #[value = 0x01]
     Primary,
@@ -61,3 +77,378 @@ pub enum MouseState {
     #[fallback]
     Other(u8),
 }
+
+// NOW_MOUSE_POSITION_MSG
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowMousePositionMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = MouseMessageType::Position))]
+    subtype: MouseMessageType,
+    pub flags: MousePositionFlags,
+    pub x: i16,
+    pub y: i16,
+}
+
+impl NowMousePositionMsg {
+    pub fn new(x: i16, y: i16) -> Self {
+        Self {
+            subtype: MouseMessageType::Position,
+            flags: MousePositionFlags::new_empty(),
+            x,
+            y,
+        }
+    }
+
+    /// Position unchanged since the last update (pointer shape refresh only).
+    pub fn new_same() -> Self {
+        Self {
+            flags: MousePositionFlags::new_empty().set_same(),
+            ..Self::new(0, 0)
+        }
+    }
+}
+
+// NOW_MOUSE_CURSOR_MSG
+
+/// Pointer shape update.
+///
+/// Dimensions and hotspot coordinates are encoded on 8 bits, or on 16 bits when the `large`
+/// flag is set (requires the `large` flag from `MouseCapset`).
+/// `data` layout depends on the cursor type:
+/// - `Mono`: AND mask followed by XOR mask, 1 bpp each,
+/// - `Color`: AND mask (1 bpp) followed by 32 bpp XRGB colors,
+/// - `Alpha`: 32 bpp premultiplied ARGB.
+#[derive(Debug, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct NowMouseCursorMsg<'a> {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = MouseMessageType::Cursor))]
+    subtype: MouseMessageType,
+    pub flags: MouseCursorFlags,
+    pub cursor_type: MouseCursorType,
+    pub width: u16,
+    pub height: u16,
+    pub x_hotspot: u16,
+    pub y_hotspot: u16,
+    pub data: Bytes32<'a>,
+}
+
+impl<'a> NowMouseCursorMsg<'a> {
+    pub const REQUIRED_SIZE: usize = 11;
+
+    /// The `large` flag is set when a dimension doesn't fit on 8 bits.
+    pub fn new(cursor_type: MouseCursorType, width: u16, height: u16, hotspot: (u16, u16), data: &'a [u8]) -> Self {
+        let (x_hotspot, y_hotspot) = hotspot;
+        let is_large = [width, height, x_hotspot, y_hotspot]
+            .iter()
+            .any(|&v| v > u16::from(u8::MAX));
+        let flags = if is_large {
+            MouseCursorFlags::new_empty().set_large()
+        } else {
+            MouseCursorFlags::new_empty()
+        };
+
+        Self {
+            subtype: MouseMessageType::Cursor,
+            flags,
+            cursor_type,
+            width,
+            height,
+            x_hotspot,
+            y_hotspot,
+            data: Bytes32::from(data),
+        }
+    }
+
+    fn dimensions(&self) -> [u16; 4] {
+        [self.width, self.height, self.x_hotspot, self.y_hotspot]
+    }
+}
+
+impl Encode for NowMouseCursorMsg<'_> {
+    fn expected_size() -> crate::serialization::ExpectedSize
+    where
+        Self: Sized,
+    {
+        crate::serialization::ExpectedSize::Variable
+    }
+
+    fn encoded_len(&self) -> usize {
+        let dimension_size = if self.flags.large() {
+            mem::size_of::<u16>()
+        } else {
+            mem::size_of::<u8>()
+        };
+        mem::size_of::<u8>() * 3 + dimension_size * 4 + self.data.encoded_len()
+    }
+
+    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+        self.subtype.encode_into(writer)?;
+        self.flags.encode_into(writer)?;
+        self.cursor_type.encode_into(writer)?;
+
+        if self.flags.large() {
+            for dimension in &self.dimensions() {
+                dimension.encode_into(writer)?;
+            }
+        } else {
+            for dimension in &self.dimensions() {
+                u8::try_from(*dimension)
+                    .map_err(ProtoError::from)
+                    .chain(ProtoErrorKind::Encoding(__type_str!(NowMouseCursorMsg)))
+                    .or_desc("cursor dimension too large without the large flag")?
+                    .encode_into(writer)?;
+            }
+        }
+
+        self.data.encode_into(writer)
+    }
+}
+
+impl<'dec: 'a, 'a> Decode<'dec> for NowMouseCursorMsg<'a> {
+    fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
+        let subtype = MouseMessageType::decode_from(cursor)?;
+        let flags = MouseCursorFlags::decode_from(cursor)?;
+        let cursor_type = MouseCursorType::decode_from(cursor)?;
+
+        let mut dimensions = [0u16; 4];
+        for dimension in &mut dimensions {
+            *dimension = if flags.large() {
+                cursor.read_u16_le()?
+            } else {
+                u16::from(NoStdRead::read_u8(cursor)?)
+            };
+        }
+        let [width, height, x_hotspot, y_hotspot] = dimensions;
+
+        let data = Bytes32::decode_from(cursor)
+            .chain(ProtoErrorKind::Decoding(__type_str!(NowMouseCursorMsg)))
+            .or_desc("invalid cursor data")?;
+
+        Ok(Self {
+            subtype,
+            flags,
+            cursor_type,
+            width,
+            height,
+            x_hotspot,
+            y_hotspot,
+            data,
+        })
+    }
+}
+
+// NOW_MOUSE_MODE_MSG
+
+/// Requests a mouse mode change, the peer answers with the resulting `NowMouseStateMsg`.
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowMouseModeMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = MouseMessageType::Mode))]
+    subtype: MouseMessageType,
+    flags: u8,
+    pub mode: MouseMode,
+    reserved: u8,
+}
+
+impl NowMouseModeMsg {
+    pub fn new(mode: MouseMode) -> Self {
+        Self {
+            subtype: MouseMessageType::Mode,
+            flags: 0,
+            mode,
+            reserved: 0,
+        }
+    }
+}
+
+// NOW_MOUSE_STATE_MSG
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowMouseStateMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = MouseMessageType::State))]
+    subtype: MouseMessageType,
+    flags: u8,
+    pub state: MouseState,
+    reserved: u8,
+}
+
+impl NowMouseStateMsg {
+    pub fn new(state: MouseState) -> Self {
+        Self {
+            subtype: MouseMessageType::State,
+            flags: 0,
+            state,
+            reserved: 0,
+        }
+    }
+}
+
+// NOW_MOUSE_MSG
+
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[meta_enum = "MouseMessageType"]
+// cursor data length isn't tied to the generated dimensions, those aren't encoded
+#[roundtrip(non_canonical)]
+pub enum NowMouseMsg<'a> {
+    Position(NowMousePositionMsg),
+    Cursor(NowMouseCursorMsg<'a>),
+    Mode(NowMouseModeMsg),
+    State(NowMouseStateMsg),
+    #[fallback]
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
+}
+
+impl From<NowMousePositionMsg> for NowMouseMsg<'_> {
+    fn from(msg: NowMousePositionMsg) -> Self {
+        Self::Position(msg)
+    }
+}
+
+impl<'a> From<NowMouseCursorMsg<'a>> for NowMouseMsg<'a> {
+    fn from(msg: NowMouseCursorMsg<'a>) -> Self {
+        Self::Cursor(msg)
+    }
+}
+
+impl From<NowMouseModeMsg> for NowMouseMsg<'_> {
+    fn from(msg: NowMouseModeMsg) -> Self {
+        Self::Mode(msg)
+    }
+}
+
+impl From<NowMouseStateMsg> for NowMouseMsg<'_> {
+    fn from(msg: NowMouseStateMsg) -> Self {
+        Self::State(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{AbstractNowHeader, NowHeader};
+    use crate::message::{NowBody, NowMessage, VirtChannelsCtx};
+    use crate::packet::NowPacket;
+
+    #[rustfmt::skip]
+    const MOUSE_POSITION_FULL_PACKET: [u8; 10] = [
+        0x06, 0x00, 0x44, 0x80, // header
+        0x01, // subtype
+        0x00, // flags
+        0xe4, 0x05, // x
+        0x77, 0x02, // y
+    ];
+
+    #[rustfmt::skip]
+    const MOUSE_CURSOR_MSG: [u8; 15] = [
+        0x02, // subtype
+        0x00, // flags
+        0x00, // cursor type
+        0x02, // width
+        0x02, // height
+        0x01, // x hotspot
+        0x00, // y hotspot
+        0x04, 0x00, 0x00, 0x00, // data size
+        0xff, 0x00, 0x00, 0xff, // data
+    ];
+
+    #[rustfmt::skip]
+    const LARGE_MOUSE_CURSOR_MSG: [u8; 19] = [
+        0x02, // subtype
+        0x01, // flags
+        0x02, // cursor type
+        0x00, 0x01, // width
+        0x80, 0x00, // height
+        0x10, 0x00, // x hotspot
+        0x20, 0x00, // y hotspot
+        0x04, 0x00, 0x00, 0x00, // data size
+        0x01, 0x02, 0x03, 0x04, // data
+    ];
+
+    #[rustfmt::skip]
+    const MOUSE_MODE_MSG: [u8; 4] = [
+        0x03, // subtype
+        0x00, // flags
+        0x02, // mode
+        0x00, // reserved
+    ];
+
+    #[test]
+    fn position_decode_full_packet() {
+        let header = NowHeader::decode(&MOUSE_POSITION_FULL_PACKET).unwrap();
+        let body = &MOUSE_POSITION_FULL_PACKET[header.len()..];
+        let packet = NowPacket::decode_from(header, body, &VirtChannelsCtx::new()).unwrap();
+        if let NowBody::Message(NowMessage::Mouse(NowMouseMsg::Position(msg))) = packet.body {
+            assert!(!msg.flags.same());
+            assert_eq!(msg.x, 1508);
+            assert_eq!(msg.y, 631);
+        } else {
+            panic!("expected a mouse position message and got {:?}", packet.body);
+        }
+    }
+
+    #[test]
+    fn position_encode_full_packet() {
+        let packet = NowPacket::from_message(NowMouseMsg::from(NowMousePositionMsg::new(1508, 631)));
+        assert_eq!(packet.encode().unwrap(), MOUSE_POSITION_FULL_PACKET.to_vec());
+    }
+
+    #[test]
+    fn cursor_decode() {
+        let msg = NowMouseMsg::decode(&MOUSE_CURSOR_MSG).unwrap();
+        if let NowMouseMsg::Cursor(msg) = msg {
+            assert!(!msg.flags.large());
+            assert_eq!(msg.cursor_type, MouseCursorType::Mono);
+            assert_eq!((msg.width, msg.height), (2, 2));
+            assert_eq!((msg.x_hotspot, msg.y_hotspot), (1, 0));
+            assert_eq!(*msg.data, [0xff, 0x00, 0x00, 0xff]);
+        } else {
+            panic!("expected a mouse cursor message and got {:?}", msg);
+        }
+    }
+
+    #[test]
+    fn cursor_encode() {
+        let msg = NowMouseCursorMsg::new(MouseCursorType::Mono, 2, 2, (1, 0), &[0xff, 0x00, 0x00, 0xff]);
+        assert_eq!(msg.encode().unwrap(), MOUSE_CURSOR_MSG.to_vec());
+    }
+
+    #[test]
+    fn large_cursor_roundtrip() {
+        let msg = NowMouseCursorMsg::new(MouseCursorType::Alpha, 256, 128, (16, 32), &[0x01, 0x02, 0x03, 0x04]);
+        assert!(msg.flags.large());
+        assert_eq!(msg.encode().unwrap(), LARGE_MOUSE_CURSOR_MSG.to_vec());
+
+        let decoded = NowMouseCursorMsg::decode(&LARGE_MOUSE_CURSOR_MSG).unwrap();
+        assert_eq!((decoded.width, decoded.height), (256, 128));
+        assert_eq!((decoded.x_hotspot, decoded.y_hotspot), (16, 32));
+    }
+
+    #[test]
+    fn mode_roundtrip() {
+        let msg = NowMouseMsg::decode(&MOUSE_MODE_MSG).unwrap();
+        if let NowMouseMsg::Mode(msg) = &msg {
+            assert_eq!(msg.mode, MouseMode::Secondary);
+        } else {
+            panic!("expected a mouse mode message and got {:?}", msg);
+        }
+        assert_eq!(msg.encode().unwrap(), MOUSE_MODE_MSG.to_vec());
+    }
+}
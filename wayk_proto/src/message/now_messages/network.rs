@@ -0,0 +1,331 @@
+use crate::container::Bytes32;
+use alloc::borrow::Cow;
+
+// NOW_NETWORK_MSG
+
+#[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
+pub enum NetworkMessageType {
+    #[value = 0x01]
+    Stats,
+    #[value = 0x02]
+    RttReq,
+    #[value = 0x03]
+    RttRsp,
+    #[value = 0x04]
+    BandwidthReq,
+    #[value = 0x05]
+    BandwidthRsp,
+    #[fallback]
+    Other(u8),
+}
+
+// NOW_NETWORK_STATS_MSG
+
+/// Network feedback as measured by the sender.
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowNetworkStatsMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = NetworkMessageType::Stats))]
+    subtype: NetworkMessageType,
+    flags: u8,
+    reserved: u16,
+    /// Round-trip time in milliseconds.
+    pub rtt: u32,
+    /// Round-trip time variation in milliseconds.
+    pub jitter: u32,
+    /// Estimated bandwidth in kilobits per second.
+    pub bandwidth: u32,
+    /// Packet loss in thousandths.
+    pub loss: u16,
+    reserved2: u16,
+}
+
+impl NowNetworkStatsMsg {
+    pub fn new(rtt: u32, jitter: u32, bandwidth: u32, loss: u16) -> Self {
+        Self {
+            subtype: NetworkMessageType::Stats,
+            flags: 0,
+            reserved: 0,
+            rtt,
+            jitter,
+            bandwidth,
+            loss,
+            reserved2: 0,
+        }
+    }
+}
+
+// NOW_NETWORK_RTT_REQ_MSG
+
+/// Round-trip time probe. The peer echoes `sequence` and `timestamp` in a `NowNetworkRttRspMsg`.
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowNetworkRttReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = NetworkMessageType::RttReq))]
+    subtype: NetworkMessageType,
+    flags: u8,
+    reserved: u16,
+    pub sequence: u32,
+    /// Sender clock in milliseconds, opaque to the receiver.
+    pub timestamp: u32,
+}
+
+impl NowNetworkRttReqMsg {
+    pub fn new(sequence: u32, timestamp: u32) -> Self {
+        Self {
+            subtype: NetworkMessageType::RttReq,
+            flags: 0,
+            reserved: 0,
+            sequence,
+            timestamp,
+        }
+    }
+
+    pub fn response(&self) -> NowNetworkRttRspMsg {
+        NowNetworkRttRspMsg {
+            subtype: NetworkMessageType::RttRsp,
+            flags: 0,
+            reserved: 0,
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+// NOW_NETWORK_RTT_RSP_MSG
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowNetworkRttRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = NetworkMessageType::RttRsp))]
+    subtype: NetworkMessageType,
+    flags: u8,
+    reserved: u16,
+    pub sequence: u32,
+    pub timestamp: u32,
+}
+
+impl NowNetworkRttRspMsg {
+    /// Round-trip time in milliseconds, `now` is read from the clock used for the request.
+    pub fn rtt(&self, now: u32) -> u32 {
+        now.wrapping_sub(self.timestamp)
+    }
+}
+
+// NOW_NETWORK_BANDWIDTH_REQ_MSG
+
+/// Bandwidth measurement burst. The receiver times the arrival of the requests sharing the
+/// same `sequence` and reports the result with a `NowNetworkBandwidthRspMsg`.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowNetworkBandwidthReqMsg<'a> {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = NetworkMessageType::BandwidthReq))]
+    subtype: NetworkMessageType,
+    flags: u8,
+    reserved: u16,
+    pub sequence: u32,
+    pub payload: Bytes32<'a>,
+}
+
+impl<'a> NowNetworkBandwidthReqMsg<'a> {
+    pub fn new(sequence: u32, payload: &'a [u8]) -> Self {
+        Self {
+            subtype: NetworkMessageType::BandwidthReq,
+            flags: 0,
+            reserved: 0,
+            sequence,
+            payload: Bytes32::from(payload),
+        }
+    }
+}
+
+// NOW_NETWORK_BANDWIDTH_RSP_MSG
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowNetworkBandwidthRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = NetworkMessageType::BandwidthRsp))]
+    subtype: NetworkMessageType,
+    flags: u8,
+    reserved: u16,
+    pub sequence: u32,
+    /// Bytes received during the measurement.
+    pub byte_count: u32,
+    /// Measurement duration in milliseconds.
+    pub duration: u32,
+}
+
+impl NowNetworkBandwidthRspMsg {
+    pub fn new(sequence: u32, byte_count: u32, duration: u32) -> Self {
+        Self {
+            subtype: NetworkMessageType::BandwidthRsp,
+            flags: 0,
+            reserved: 0,
+            sequence,
+            byte_count,
+            duration,
+        }
+    }
+
+    /// Measured bandwidth in kilobits per second.
+    pub fn bandwidth(&self) -> Option<u32> {
+        if self.duration == 0 {
+            None
+        } else {
+            // bytes/ms * 8 = kbit/s
+            Some((u64::from(self.byte_count) * 8 / u64::from(self.duration)) as u32)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[meta_enum = "NetworkMessageType"]
+#[roundtrip]
+pub enum NowNetworkMsg<'a> {
+    Stats(NowNetworkStatsMsg),
+    RttReq(NowNetworkRttReqMsg),
+    RttRsp(NowNetworkRttRspMsg),
+    BandwidthReq(NowNetworkBandwidthReqMsg<'a>),
+    BandwidthRsp(NowNetworkBandwidthRspMsg),
+    #[fallback]
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
+}
+
+impl From<NowNetworkStatsMsg> for NowNetworkMsg<'_> {
+    fn from(msg: NowNetworkStatsMsg) -> Self {
+        Self::Stats(msg)
+    }
+}
+
+impl From<NowNetworkRttReqMsg> for NowNetworkMsg<'_> {
+    fn from(msg: NowNetworkRttReqMsg) -> Self {
+        Self::RttReq(msg)
+    }
+}
+
+impl From<NowNetworkRttRspMsg> for NowNetworkMsg<'_> {
+    fn from(msg: NowNetworkRttRspMsg) -> Self {
+        Self::RttRsp(msg)
+    }
+}
+
+impl<'a> From<NowNetworkBandwidthReqMsg<'a>> for NowNetworkMsg<'a> {
+    fn from(msg: NowNetworkBandwidthReqMsg<'a>) -> Self {
+        Self::BandwidthReq(msg)
+    }
+}
+
+impl From<NowNetworkBandwidthRspMsg> for NowNetworkMsg<'_> {
+    fn from(msg: NowNetworkBandwidthRspMsg) -> Self {
+        Self::BandwidthRsp(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{AbstractNowHeader, NowHeader};
+    use crate::message::{NowBody, NowMessage, VirtChannelsCtx};
+    use crate::packet::NowPacket;
+    use crate::serialization::{Decode, Encode};
+
+    #[rustfmt::skip]
+    const RTT_REQ_FULL_PACKET: [u8; 16] = [
+        0x0c, 0x00, 0x45, 0x80, // header
+        0x02, // subtype
+        0x00, // flags
+        0x00, 0x00, // reserved
+        0x07, 0x00, 0x00, 0x00, // sequence
+        0xe8, 0x03, 0x00, 0x00, // timestamp
+    ];
+
+    #[rustfmt::skip]
+    const STATS_MSG: [u8; 20] = [
+        0x01, // subtype
+        0x00, // flags
+        0x00, 0x00, // reserved
+        0x28, 0x00, 0x00, 0x00, // rtt
+        0x05, 0x00, 0x00, 0x00, // jitter
+        0x10, 0x27, 0x00, 0x00, // bandwidth
+        0x0c, 0x00, // loss
+        0x00, 0x00, // reserved
+    ];
+
+    #[rustfmt::skip]
+    const BANDWIDTH_REQ_MSG: [u8; 14] = [
+        0x04, // subtype
+        0x00, // flags
+        0x00, 0x00, // reserved
+        0x01, 0x00, 0x00, 0x00, // sequence
+        0x02, 0x00, 0x00, 0x00, // payload size
+        0xaa, 0xaa, // payload
+    ];
+
+    #[test]
+    fn rtt_req_decode_full_packet() {
+        let header = NowHeader::decode(&RTT_REQ_FULL_PACKET).unwrap();
+        let body = &RTT_REQ_FULL_PACKET[header.len()..];
+        let packet = NowPacket::decode_from(header, body, &VirtChannelsCtx::new()).unwrap();
+        if let NowBody::Message(NowMessage::Network(NowNetworkMsg::RttReq(msg))) = packet.body {
+            assert_eq!(msg.sequence, 7);
+            assert_eq!(msg.timestamp, 1000);
+            assert_eq!(msg.response().rtt(1042), 42);
+        } else {
+            panic!("expected a network rtt request message and got {:?}", packet.body);
+        }
+    }
+
+    #[test]
+    fn rtt_req_encode_full_packet() {
+        let packet = NowPacket::from_message(NowNetworkMsg::from(NowNetworkRttReqMsg::new(7, 1000)));
+        assert_eq!(packet.encode().unwrap(), RTT_REQ_FULL_PACKET.to_vec());
+    }
+
+    #[test]
+    fn stats_roundtrip() {
+        let msg = NowNetworkStatsMsg::new(40, 5, 10_000, 12);
+        assert_eq!(msg.encode().unwrap(), STATS_MSG.to_vec());
+        assert_eq!(NowNetworkStatsMsg::decode(&STATS_MSG).unwrap(), msg);
+    }
+
+    #[test]
+    fn bandwidth_req_decode() {
+        let msg = NowNetworkMsg::decode(&BANDWIDTH_REQ_MSG).unwrap();
+        if let NowNetworkMsg::BandwidthReq(msg) = msg {
+            assert_eq!(msg.sequence, 1);
+            assert_eq!(*msg.payload, [0xaa, 0xaa]);
+        } else {
+            panic!("expected a network bandwidth request message and got {:?}", msg);
+        }
+    }
+
+    #[test]
+    fn bandwidth_rsp_computation() {
+        assert_eq!(
+            NowNetworkBandwidthRspMsg::new(1, 125_000, 100).bandwidth(),
+            Some(10_000)
+        );
+        assert_eq!(NowNetworkBandwidthRspMsg::new(1, 125_000, 0).bandwidth(), None);
+    }
+}
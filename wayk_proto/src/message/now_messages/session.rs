@@ -0,0 +1,240 @@
+// NOW_SESSION_MSG
+
+use alloc::borrow::Cow;
+
+#[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
+pub enum SessionMessageType {
+    #[value = 0x01]
+    LogoffReq,
+    #[value = 0x02]
+    LogoffRsp,
+    #[value = 0x03]
+    LockReq,
+    #[value = 0x04]
+    LockRsp,
+    #[fallback]
+    Other(u8),
+}
+
+#[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
+pub enum SessionStatus {
+    #[value = 0x0000]
+    Success,
+    #[value = 0x0001]
+    Denied,
+    #[value = 0x0002]
+    Failed,
+    #[fallback]
+    Other(u16),
+}
+
+__flags_struct! {
+    SessionLogoffFlags: u8 => {
+        force = FORCE = 0x01,
+    }
+}
+
+// NOW_SESSION_LOGOFF_REQ_MSG
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowSessionLogoffReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
+    subtype: SessionMessageType,
+    pub flags: SessionLogoffFlags,
+    reserved: u16,
+}
+
+impl NowSessionLogoffReqMsg {
+    pub const SUBTYPE: SessionMessageType = SessionMessageType::LogoffReq;
+
+    pub fn new(flags: SessionLogoffFlags) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags,
+            reserved: 0,
+        }
+    }
+}
+
+// NOW_SESSION_LOGOFF_RSP_MSG
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowSessionLogoffRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
+    subtype: SessionMessageType,
+    flags: u8,
+    pub status: SessionStatus,
+}
+
+impl NowSessionLogoffRspMsg {
+    pub const SUBTYPE: SessionMessageType = SessionMessageType::LogoffRsp;
+
+    pub fn new(status: SessionStatus) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: 0,
+            status,
+        }
+    }
+}
+
+// NOW_SESSION_LOCK_REQ_MSG
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowSessionLockReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
+    subtype: SessionMessageType,
+    flags: u8,
+    reserved: u16,
+}
+
+impl Default for NowSessionLockReqMsg {
+    fn default() -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: 0,
+            reserved: 0,
+        }
+    }
+}
+
+impl NowSessionLockReqMsg {
+    pub const SUBTYPE: SessionMessageType = SessionMessageType::LockReq;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// NOW_SESSION_LOCK_RSP_MSG
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowSessionLockRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
+    subtype: SessionMessageType,
+    flags: u8,
+    pub status: SessionStatus,
+}
+
+impl NowSessionLockRspMsg {
+    pub const SUBTYPE: SessionMessageType = SessionMessageType::LockRsp;
+
+    pub fn new(status: SessionStatus) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: 0,
+            status,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[meta_enum = "SessionMessageType"]
+#[roundtrip]
+pub enum NowSessionMsg<'a> {
+    LogoffReq(NowSessionLogoffReqMsg),
+    LogoffRsp(NowSessionLogoffRspMsg),
+    LockReq(NowSessionLockReqMsg),
+    LockRsp(NowSessionLockRspMsg),
+    #[fallback]
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
+}
+
+impl From<NowSessionLogoffReqMsg> for NowSessionMsg<'_> {
+    fn from(msg: NowSessionLogoffReqMsg) -> Self {
+        Self::LogoffReq(msg)
+    }
+}
+
+impl From<NowSessionLogoffRspMsg> for NowSessionMsg<'_> {
+    fn from(msg: NowSessionLogoffRspMsg) -> Self {
+        Self::LogoffRsp(msg)
+    }
+}
+
+impl From<NowSessionLockReqMsg> for NowSessionMsg<'_> {
+    fn from(msg: NowSessionLockReqMsg) -> Self {
+        Self::LockReq(msg)
+    }
+}
+
+impl From<NowSessionLockRspMsg> for NowSessionMsg<'_> {
+    fn from(msg: NowSessionLockRspMsg) -> Self {
+        Self::LockRsp(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{AbstractNowHeader, NowHeader};
+    use crate::message::{NowBody, NowMessage, VirtChannelsCtx};
+    use crate::packet::NowPacket;
+    use crate::serialization::{Decode, Encode};
+
+    #[rustfmt::skip]
+    const LOGOFF_REQ_FULL_PACKET: [u8; 8] = [
+        0x04, 0x00, 0x49, 0x80, // header
+        0x01, // subtype
+        0x01, // flags
+        0x00, 0x00, // reserved
+    ];
+
+    #[rustfmt::skip]
+    const LOCK_RSP_MSG: [u8; 4] = [
+        0x04, // subtype
+        0x00, // flags
+        0x01, 0x00, // status
+    ];
+
+    #[test]
+    fn logoff_req_decode_full_packet() {
+        let header = NowHeader::decode(&LOGOFF_REQ_FULL_PACKET).unwrap();
+        let body = &LOGOFF_REQ_FULL_PACKET[header.len()..];
+        let packet = NowPacket::decode_from(header, body, &VirtChannelsCtx::new()).unwrap();
+        if let NowBody::Message(NowMessage::Session(NowSessionMsg::LogoffReq(msg))) = packet.body {
+            assert!(msg.flags.force());
+        } else {
+            panic!("expected a session logoff request message and got {:?}", packet.body);
+        }
+    }
+
+    #[test]
+    fn logoff_req_encode_full_packet() {
+        let msg = NowSessionLogoffReqMsg::new(SessionLogoffFlags::new_empty().set_force());
+        let packet = NowPacket::from_message(NowSessionMsg::from(msg));
+        assert_eq!(packet.encode().unwrap(), LOGOFF_REQ_FULL_PACKET.to_vec());
+    }
+
+    #[test]
+    fn lock_rsp_roundtrip() {
+        let msg = NowSessionMsg::decode(&LOCK_RSP_MSG).unwrap();
+        if let NowSessionMsg::LockRsp(msg) = &msg {
+            assert_eq!(msg.status, SessionStatus::Denied);
+        } else {
+            panic!("expected a session lock response message and got {:?}", msg);
+        }
+        assert_eq!(msg.encode().unwrap(), LOCK_RSP_MSG.to_vec());
+    }
+}
@@ -1,7 +1,10 @@
 use crate::message::NowString256;
+use alloc::borrow::Cow;
 use core::str::FromStr;
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum SharingMessageType {
     #[value = 0x01]
     Suspend,
@@ -11,8 +14,12 @@ pub enum SharingMessageType {
     Other(u8),
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowSharingSuspendMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = SharingMessageType::Suspend))]
     subtype: SharingMessageType,
     flags: u8,
     reserved: u16,
@@ -43,22 +50,48 @@ impl NowSharingSuspendMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowSharingResumeMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = SharingMessageType::Resume))]
     subtype: SharingMessageType,
     flags: u8,
     reserved: u16,
 }
 
+impl Default for NowSharingResumeMsg {
+    fn default() -> Self {
+        NowSharingResumeMsg {
+            subtype: SharingMessageType::Resume,
+            flags: 0,
+            reserved: 0,
+        }
+    }
+}
+
+impl NowSharingResumeMsg {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
 // NOW_SHARING_MSG
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "SharingMessageType"]
+#[roundtrip]
 pub enum NowSharingMsg<'a> {
     Suspend(NowSharingSuspendMsg),
     Resume(NowSharingResumeMsg),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
 }
 
 #[cfg(test)]
@@ -93,4 +126,21 @@ mod tests {
         let msg = NowSharingSuspendMsg::new_with_message(NowString256::from_str("").unwrap());
         assert_eq!(msg.encode().unwrap(), NOW_SHARING_SUSPEND_MSG.to_vec());
     }
+
+    #[rustfmt::skip]
+    const NOW_SHARING_RESUME_MSG: [u8; 4] = [
+        0x02, // subtype
+        0x00, // flags
+        0x00, 0x00, // reserved
+    ];
+
+    #[test]
+    fn resume_roundtrip() {
+        assert_eq!(
+            NowSharingResumeMsg::new().encode().unwrap(),
+            NOW_SHARING_RESUME_MSG.to_vec()
+        );
+        let msg = NowSharingMsg::decode(&NOW_SHARING_RESUME_MSG).unwrap();
+        assert!(matches!(msg, NowSharingMsg::Resume(_)));
+    }
 }
@@ -1,6 +1,11 @@
 use crate::container::Vec8;
+use crate::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt, Result};
+use crate::io::{Cursor, NoStdWrite};
 use crate::message::EdgeRect;
+use crate::serialization::{Decode, Encode, ExpectedSize};
+use alloc::borrow::Cow;
 use alloc::vec::Vec;
+use core::convert::TryFrom;
 use core::mem;
 
 __flags_struct! {
@@ -10,6 +15,8 @@ __flags_struct! {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum SurfaceMessageType {
     #[value = 0x01]
     ListReq,
@@ -47,6 +54,8 @@ impl Default for SurfacePropertiesFlags {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum SurfaceOrientation {
     #[value = 0]
     Landscape,
@@ -60,29 +69,47 @@ pub enum SurfaceOrientation {
     Other(u16),
 }
 
-#[derive(Debug, Clone, Encode, Decode)]
+/// DPI and scaling of a surface, sent by HiDPI aware peers after the base surface definition.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct SurfaceDpi {
+    pub dpi_x: u16,
+    pub dpi_y: u16,
+    /// Scale factor in percent
+    pub pct_scale_x: u16,
+    /// Scale factor in percent
+    pub pct_scale_y: u16,
+    /// Surface rect before scaling
+    pub native_rect: EdgeRect,
+}
+
+impl SurfaceDpi {
+    pub const REQUIRED_SIZE: usize = 16;
+
+    pub fn new(dpi_x: u16, dpi_y: u16, pct_scale_x: u16, pct_scale_y: u16, native_rect: EdgeRect) -> Self {
+        Self {
+            dpi_x,
+            dpi_y,
+            pct_scale_x,
+            pct_scale_y,
+            native_rect,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct NowSurfaceDef {
     size: u16,
     pub flags: SurfacePropertiesFlags,
     pub surface_id: u16,
     pub orientation: SurfaceOrientation,
     pub rect: EdgeRect,
-    // unused fields
-    #[decode_ignore]
-    #[encode_ignore]
-    dpi_x: u16,
-    #[decode_ignore]
-    #[encode_ignore]
-    dpi_y: u16,
-    #[decode_ignore]
-    #[encode_ignore]
-    pct_scale_x: u16,
-    #[decode_ignore]
-    #[encode_ignore]
-    pct_scale_y: u16,
-    #[decode_ignore]
-    #[encode_ignore]
-    native_rect: EdgeRect,
+    /// Only present in 32 bytes surface definitions.
+    pub dpi: Option<SurfaceDpi>,
 }
 
 impl NowSurfaceDef {
@@ -95,11 +122,7 @@ impl NowSurfaceDef {
             surface_id,
             orientation: SurfaceOrientation::Landscape,
             rect,
-            dpi_x: 0,
-            dpi_y: 0,
-            pct_scale_x: 0,
-            pct_scale_y: 0,
-            native_rect: EdgeRect::default(),
+            dpi: None,
         }
     }
 
@@ -116,12 +139,98 @@ impl NowSurfaceDef {
             ..self
         }
     }
+
+    pub fn dpi(self, dpi: SurfaceDpi) -> Self {
+        Self {
+            size: (Self::REQUIRED_SIZE + SurfaceDpi::REQUIRED_SIZE) as u16,
+            dpi: Some(dpi),
+            ..self
+        }
+    }
+}
+
+impl Encode for NowSurfaceDef {
+    fn expected_size() -> ExpectedSize
+    where
+        Self: Sized,
+    {
+        ExpectedSize::Variable
+    }
+
+    fn encoded_len(&self) -> usize {
+        Self::REQUIRED_SIZE + self.dpi.as_ref().map(|dpi| dpi.encoded_len()).unwrap_or(0)
+    }
+
+    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+        // size is computed rather than taken from the field
+        u16::try_from(self.encoded_len())
+            .map_err(ProtoError::from)
+            .chain(ProtoErrorKind::Encoding(__type_str!(NowSurfaceDef)))
+            .or_desc("size doesn't fit into NowSurfaceDef::size")?
+            .encode_into(writer)?;
+        self.flags.encode_into(writer)?;
+        self.surface_id.encode_into(writer)?;
+        self.orientation.encode_into(writer)?;
+        self.rect.encode_into(writer)?;
+        if let Some(dpi) = &self.dpi {
+            dpi.encode_into(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl Decode<'_> for NowSurfaceDef {
+    fn decode_from(cursor: &mut Cursor<'_>) -> Result<Self> {
+        let size = u16::decode_from(cursor)?;
+        let declared = usize::from(size);
+        if declared < Self::REQUIRED_SIZE {
+            return Err(
+                ProtoError::new(ProtoErrorKind::Decoding(__type_str!(NowSurfaceDef))).with_desc(format!(
+                    "declared size ({}) is smaller than {}",
+                    declared,
+                    Self::REQUIRED_SIZE
+                )),
+            );
+        }
+
+        let flags = SurfacePropertiesFlags::decode_from(cursor)?;
+        let surface_id = u16::decode_from(cursor)?;
+        let orientation = SurfaceOrientation::decode_from(cursor)?;
+        let rect = EdgeRect::decode_from(cursor)?;
+
+        // older peers only send the first 16 bytes, unknown trailing fields are skipped
+        let mut remaining = declared - Self::REQUIRED_SIZE;
+        let dpi = if remaining >= SurfaceDpi::REQUIRED_SIZE {
+            remaining -= SurfaceDpi::REQUIRED_SIZE;
+            Some(SurfaceDpi::decode_from(cursor)?)
+        } else {
+            None
+        };
+        cursor
+            .read_n(remaining)
+            .map_err(ProtoError::from)
+            .chain(ProtoErrorKind::Decoding(__type_str!(NowSurfaceDef)))
+            .or_desc("declared size exceeds available data")?;
+
+        Ok(Self {
+            size,
+            flags,
+            surface_id,
+            orientation,
+            rect,
+            dpi,
+        })
+    }
 }
 
 // NOW_SURFACE_MAP
 
 #[derive(Debug, Clone, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowSurfaceMap {
+    #[size_prefix]
     size: u16,
     flags: u16,
     pub surface_id: u16,
@@ -143,10 +252,27 @@ impl NowSurfaceMap {
     }
 }
 
+// `size` is computed on encode, the size declared by the peer isn't part of the value
+impl PartialEq for NowSurfaceMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.flags == other.flags
+            && self.surface_id == other.surface_id
+            && self.output_id == other.output_id
+            && self.output_rect == other.output_rect
+    }
+}
+
 // NOW_SURFACE_MSG
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "SurfaceMessageType"]
+// holds surface definitions
+#[roundtrip(non_canonical)]
 pub enum NowSurfaceMsg<'a> {
     ListReq(NowSurfaceListReqMsg),
     ListRsp(NowSurfaceListRspMsg),
@@ -155,7 +281,7 @@ pub enum NowSurfaceMsg<'a> {
     SelectReq(NowSurfaceSelectReqMsg),
     SelectRsp(NowSurfaceSelectRspMsg),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
 }
 
 impl From<NowSurfaceListReqMsg> for NowSurfaceMsg<'_> {
@@ -196,8 +322,13 @@ impl From<NowSurfaceSelectRspMsg> for NowSurfaceMsg<'_> {
 
 // subtypes
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+// surface definitions `size` is generated, not derived from `dpi`
+#[roundtrip(non_canonical)]
 pub struct NowSurfaceListReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: SurfaceMessageType,
     flags: u8,
     pub sequence_id: u16,
@@ -231,8 +362,12 @@ impl NowSurfaceListReqMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowSurfaceListRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: SurfaceMessageType,
     pub flags: SurfaceResponseFlags,
     pub sequence_id: u16,
@@ -250,8 +385,12 @@ impl NowSurfaceListRspMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowSurfaceMapReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: SurfaceMessageType,
     pub flags: u8, // TODO: find flags values
     pub sequence_id: u16,
@@ -275,7 +414,7 @@ impl NowSurfaceMapReqMsg {
         maps: Vec<NowSurfaceMap>,
     ) -> Self {
         Self {
-            subtype: SurfaceMessageType::ListReq,
+            subtype: Self::SUBTYPE,
             flags: 0,
             sequence_id,
             desktop_width,
@@ -285,8 +424,12 @@ impl NowSurfaceMapReqMsg {
     }
 }
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowSurfaceMapRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: SurfaceMessageType,
     pub flags: SurfaceResponseFlags,
     pub sequence_id: u16,
@@ -304,8 +447,12 @@ impl NowSurfaceMapRspMsg {
     }
 }
 
-#[derive(Debug, Clone, Decode, Encode)]
+#[derive(Debug, Clone, Decode, Encode, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowSurfaceSelectReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: SurfaceMessageType,
     pub flags: u8, // TODO: find flags values
     pub sequence_id: u16,
@@ -327,8 +474,12 @@ impl NowSurfaceSelectReqMsg {
     }
 }
 
-#[derive(Debug, Clone, Decode, Encode)]
+#[derive(Debug, Clone, Decode, Encode, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowSurfaceSelectRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: SurfaceMessageType,
     pub flags: SurfaceResponseFlags,
     pub sequence_id: u16,
@@ -349,7 +500,6 @@ impl NowSurfaceSelectRspMsg {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::serialization::{Decode, Encode};
 
     #[rustfmt::skip]
     const SURFACE_LIST_REQ_MSG: [u8; 25] = [
@@ -404,5 +554,46 @@ mod tests {
         assert_eq!(msg.encode().unwrap(), SURFACE_LIST_REQ_MSG.to_vec());
     }
 
+    #[rustfmt::skip]
+    const SURFACE_DEF_WITH_DPI: [u8; 32] = [
+        0x20, 0x00, // size
+        0x09, 0x00, // flags
+        0x01, 0x00, // surface id
+        0x5a, 0x00, // orientation
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x0a, // rect
+        0x90, 0x00, // dpi x
+        0x90, 0x00, // dpi y
+        0x96, 0x00, // scale x
+        0x96, 0x00, // scale y
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0xab, 0x06, // native rect
+    ];
+
+    #[test]
+    fn surface_def_dpi_round_trip() {
+        let surface = NowSurfaceDef::decode(&SURFACE_DEF_WITH_DPI).unwrap();
+        assert_eq!(surface.orientation, SurfaceOrientation::Portrait);
+        let dpi = surface.dpi.as_ref().unwrap();
+        assert_eq!((dpi.dpi_x, dpi.dpi_y), (144, 144));
+        assert_eq!((dpi.pct_scale_x, dpi.pct_scale_y), (150, 150));
+        assert_eq!(dpi.native_rect.right, 1024);
+        assert_eq!(surface.encode().unwrap(), SURFACE_DEF_WITH_DPI.to_vec());
+    }
+
+    #[test]
+    fn surface_def_unknown_trailing_fields() {
+        let mut encoded = SURFACE_LIST_REQ_MSG[9..].to_vec();
+        encoded[0] = 0x14;
+        encoded.extend_from_slice(&[0xff; 4]);
+
+        let mut cursor = Cursor::new(&encoded);
+        let surface = NowSurfaceDef::decode_from(&mut cursor).unwrap();
+        assert!(surface.dpi.is_none());
+        assert_eq!(cursor.remaining(), 0);
+        assert_eq!(surface.encode().unwrap(), SURFACE_LIST_REQ_MSG[9..].to_vec());
+
+        encoded[0] = 0x0f;
+        assert!(NowSurfaceDef::decode(&encoded).is_err());
+    }
+
     // TODO: test NowSurfaceMapReqMsg
 }
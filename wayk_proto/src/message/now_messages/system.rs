@@ -1,7 +1,8 @@
 use crate::error::*;
-use crate::io::{Cursor, NoStdWrite};
+use crate::io::Cursor;
 use crate::message::{NowString128, NowString16, NowString256, NowString32, NowString64};
-use crate::serialization::{Decode, Encode};
+use crate::serialization::Decode;
+use alloc::borrow::Cow;
 use alloc::boxed::Box;
 
 // NOW_SYSTEM_INFO
@@ -13,7 +14,10 @@ __flags_struct! {
     }
 }
 
-#[derive(Debug, Decode, Encode, Clone)]
+#[derive(Debug, Decode, Encode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct OsInfoExtraWindows {
     pub extra_flags: u16,
     pub product_flags: WindowsProductFlags,
@@ -26,7 +30,10 @@ pub struct OsInfoExtraWindows {
     pub product_name: NowString64,
 }
 
-#[derive(Debug, Decode, Encode, Clone)]
+#[derive(Debug, Decode, Encode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct OsInfoExtraMac {
     pub extra_flags: u16,
     reserved: u16,
@@ -41,7 +48,10 @@ impl OsInfoExtraMac {
     }
 }
 
-#[derive(Debug, Encode, Decode, Clone)]
+#[derive(Debug, Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct OsInfoExtraLinux {
     pub extra_flags: u16,
     reserved: u16,
@@ -56,7 +66,10 @@ impl OsInfoExtraLinux {
     }
 }
 
-#[derive(Debug, Decode, Encode, Clone)]
+#[derive(Debug, Decode, Encode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct OsInfoExtraIOS {
     pub extra_flags: u16,
     reserved: u16,
@@ -71,7 +84,10 @@ impl OsInfoExtraIOS {
     }
 }
 
-#[derive(Debug, Encode, Decode, Clone)]
+#[derive(Debug, Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct OsInfoExtraAndroid {
     pub extra_flags: u16,
     reserved: u16,
@@ -86,7 +102,13 @@ impl OsInfoExtraAndroid {
     }
 }
 
-#[derive(Debug, Clone, Encode)]
+#[derive(Debug, Clone, Encode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[meta_enum]
 pub enum OsInfoExtra<'a> {
     Windows(OsInfoExtraWindows),
@@ -95,10 +117,26 @@ pub enum OsInfoExtra<'a> {
     IOS(OsInfoExtraIOS),
     Android(OsInfoExtraAndroid),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
+}
+
+impl<'a> OsInfoExtra<'a> {
+    /// Extra infos layout depends on the OS type, `None` for unknown OS types.
+    pub fn decode_for_os<'dec: 'a>(os_type: OsType, cursor: &mut Cursor<'dec>) -> Result<Option<Self>> {
+        Ok(match os_type {
+            OsType::Windows => Some(OsInfoExtra::Windows(OsInfoExtraWindows::decode_from(cursor)?)),
+            OsType::Mac => Some(OsInfoExtra::Mac(OsInfoExtraMac::decode_from(cursor)?)),
+            OsType::Linux => Some(OsInfoExtra::Linux(OsInfoExtraLinux::decode_from(cursor)?)),
+            OsType::IOS => Some(OsInfoExtra::IOS(OsInfoExtraIOS::decode_from(cursor)?)),
+            OsType::Android => Some(OsInfoExtra::Android(OsInfoExtraAndroid::decode_from(cursor)?)),
+            OsType::Other(_) => None,
+        })
+    }
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum SystemInfoType {
     #[value = 0x0001]
     Os,
@@ -114,6 +152,8 @@ __flags_struct! {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum OsType {
     #[value = 0x01]
     Windows,
@@ -130,6 +170,8 @@ pub enum OsType {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum OsArch {
     #[value = 0x01]
     X86,
@@ -143,8 +185,17 @@ pub enum OsArch {
     Other(u8),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+// kernel and extra fields generated without their flag
+#[roundtrip(non_canonical)]
 pub struct NowSystemOsInfo<'a> {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: SystemInfoType,
     pub flags: SystemOsInfoFlags,
 
@@ -155,151 +206,23 @@ pub struct NowSystemOsInfo<'a> {
     pub version_patch: u16,
     pub os_build: NowString16,
     pub os_name: NowString64,
+    #[flag_cond(flags = "flags", bit = "kernel")]
     pub kernel_name: NowString16,
+    #[flag_cond(flags = "flags", bit = "kernel")]
     pub kernel_arch: NowString16,
+    #[flag_cond(flags = "flags", bit = "kernel")]
     pub kernel_release: NowString32,
+    #[flag_cond(flags = "flags", bit = "kernel")]
     pub kernel_version: NowString128,
 
+    #[flag_cond(
+        flags = "flags",
+        bit = "extra",
+        decode_with = "OsInfoExtra::decode_for_os(os_type, cursor)"
+    )]
     pub extra: Option<OsInfoExtra<'a>>,
 }
 
-impl<'a> Encode for NowSystemOsInfo<'a> {
-    fn expected_size() -> crate::serialization::ExpectedSize {
-        crate::serialization::ExpectedSize::Variable
-    }
-
-    fn encoded_len(&self) -> usize {
-        self.subtype.encoded_len()
-            + self.flags.encoded_len()
-            + self.os_type.encoded_len()
-            + self.os_arch.encoded_len()
-            + self.version_major.encoded_len()
-            + self.version_minor.encoded_len()
-            + self.version_patch.encoded_len()
-            + self.os_build.encoded_len()
-            + self.os_name.encoded_len()
-            + self.kernel_name.encoded_len()
-            + self.kernel_arch.encoded_len()
-            + self.kernel_release.encoded_len()
-            + self.kernel_version.encoded_len()
-            + if let Some(extra) = &self.extra {
-                extra.encoded_len()
-            } else {
-                0
-            }
-    }
-
-    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
-        self.subtype
-            .encode_into(writer)
-            .or_desc("couldn't encode os info subtype")?;
-        self.flags
-            .encode_into(writer)
-            .or_desc("couldn't encode os info flags")?;
-        self.os_type
-            .encode_into(writer)
-            .or_desc("couldn't encode os info os type")?;
-        self.os_arch
-            .encode_into(writer)
-            .or_desc("couldn't encode os info os arch")?;
-        self.version_major
-            .encode_into(writer)
-            .or_desc("couldn't encode os info version major")?;
-        self.version_minor
-            .encode_into(writer)
-            .or_desc("couldn't encode os info version minor")?;
-        self.version_patch
-            .encode_into(writer)
-            .or_desc("couldn't encode os info version patch")?;
-        self.os_build
-            .encode_into(writer)
-            .or_desc("couldn't encode os info os build")?;
-        self.os_name
-            .encode_into(writer)
-            .or_desc("couldn't encode os info os name")?;
-        self.kernel_name
-            .encode_into(writer)
-            .or_desc("couldn't encode os info kernel name")?;
-        self.kernel_arch
-            .encode_into(writer)
-            .or_desc("couldn't encode os info kernel arch")?;
-        self.kernel_release
-            .encode_into(writer)
-            .or_desc("couldn't encode os info kernel release")?;
-        self.kernel_version
-            .encode_into(writer)
-            .or_desc("couldn't encode os info kernel version")?;
-
-        if let Some(extra) = &self.extra {
-            extra.encode_into(writer).or_desc("couldn't encode os info extra")?;
-        }
-
-        Ok(())
-    }
-}
-
-impl<'dec: 'a, 'a> Decode<'dec> for NowSystemOsInfo<'a> {
-    fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
-        let _ = SystemInfoType::decode_from(cursor).or_desc("couldn't decode system info type")?;
-        let flags = SystemOsInfoFlags::decode_from(cursor).or_desc("couldn't decode os info flags")?;
-        let os_type = OsType::decode_from(cursor).or_desc("couldn't decode os type")?;
-        let os_arch = OsArch::decode_from(cursor).or_desc("couldn't decode os arch")?;
-        let version_major = u16::decode_from(cursor).or_desc("couldn't decode version major")?;
-        let version_minor = u16::decode_from(cursor).or_desc("couldn't decode version minor")?;
-        let version_patch = u16::decode_from(cursor).or_desc("couldn't decode version patch")?;
-        let os_build = NowString16::decode_from(cursor).or_desc("couldn't decode os build")?;
-        let os_name = NowString64::decode_from(cursor).or_desc("couldn't decode os name")?;
-        let kernel_name;
-        let kernel_arch;
-        let kernel_release;
-        let kernel_version;
-
-        {
-            if flags.kernel() {
-                kernel_name = NowString16::decode_from(cursor).or_desc("couldn't decode kernel name")?;
-                kernel_arch = NowString16::decode_from(cursor).or_desc("couldn't decode kernel arch")?;
-                kernel_release = NowString32::decode_from(cursor).or_desc("couldn't decode kernel release")?;
-                kernel_version = NowString128::decode_from(cursor).or_desc("couldn't decode kernel version")?;
-            } else {
-                kernel_name = NowString16::new_empty();
-                kernel_arch = NowString16::new_empty();
-                kernel_release = NowString32::new_empty();
-                kernel_version = NowString128::new_empty();
-            }
-        }
-
-        let extra = if flags.extra() {
-            match os_type {
-                OsType::Windows => Some(OsInfoExtra::Windows(OsInfoExtraWindows::decode_from(cursor)?)),
-                OsType::Mac => Some(OsInfoExtra::Mac(OsInfoExtraMac::decode_from(cursor)?)),
-                OsType::Linux => Some(OsInfoExtra::Linux(OsInfoExtraLinux::decode_from(cursor)?)),
-                OsType::IOS => Some(OsInfoExtra::IOS(OsInfoExtraIOS::decode_from(cursor)?)),
-                OsType::Android => Some(OsInfoExtra::Android(OsInfoExtraAndroid::decode_from(cursor)?)),
-                OsType::Other(_) => None,
-            }
-        } else {
-            None
-        };
-
-        Ok(Self {
-            subtype: SystemInfoType::Os,
-            flags,
-            os_type,
-            os_arch,
-            version_major,
-            version_minor,
-            version_patch,
-            os_build,
-            os_name,
-            kernel_name,
-            kernel_arch,
-            kernel_release,
-            kernel_version,
-            extra,
-        })
-    }
-}
-
 impl<'a> NowSystemOsInfo<'a> {
     const SUBTYPE: SystemInfoType = SystemInfoType::Os;
 
@@ -351,17 +274,185 @@ impl<'a> NowSystemOsInfo<'a> {
     }
 }
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[cfg(feature = "std")]
+impl NowSystemOsInfo<'static> {
+    /// Best effort description of the running system.
+    ///
+    /// Kernel infos are read from `/proc` on Linux and from `uname` on macOS, versions from
+    /// `/etc/os-release`, `sw_vers` or `ver`. Fields that couldn't be read are left empty.
+    pub fn detect() -> Self {
+        use std::env::consts;
+
+        let os_type = match consts::OS {
+            "windows" => OsType::Windows,
+            "macos" => OsType::Mac,
+            "linux" => OsType::Linux,
+            "ios" => OsType::IOS,
+            "android" => OsType::Android,
+            _ => OsType::Other(0),
+        };
+        let os_arch = match consts::ARCH {
+            "x86" => OsArch::X86,
+            "x86_64" => OsArch::X64,
+            "arm" => OsArch::ARM,
+            "aarch64" => OsArch::ARM64,
+            _ => OsArch::Other(0),
+        };
+
+        let detected = match os_type {
+            OsType::Windows => detect::windows(),
+            OsType::Mac => detect::mac(),
+            OsType::Linux | OsType::Android => detect::linux(),
+            _ => detect::Detected::default(),
+        };
+
+        let (version_major, version_minor, version_patch) = detect::parse_version(&detected.version);
+        let mut info = Self::new(
+            os_type,
+            os_arch,
+            version_major,
+            version_minor,
+            version_patch,
+            NowString16::from_str_lossy(&detected.build),
+        );
+        match detected.kernel {
+            Some(kernel) => info.set_kernel_infos(
+                NowString64::from_str_lossy(&detected.name),
+                NowString16::from_str_lossy(&kernel.name),
+                NowString16::from_str_lossy(&kernel.arch),
+                NowString32::from_str_lossy(&kernel.release),
+                NowString128::from_str_lossy(&kernel.version),
+            ),
+            None => info.os_name = NowString64::from_str_lossy(&detected.name),
+        }
+        info
+    }
+}
+
+#[cfg(feature = "std")]
+mod detect {
+    use std::process::Command;
+    use std::string::{String, ToString};
+
+    #[derive(Default)]
+    pub(super) struct Detected {
+        pub name: String,
+        pub version: String,
+        pub build: String,
+        pub kernel: Option<Kernel>,
+    }
+
+    pub(super) struct Kernel {
+        pub name: String,
+        pub arch: String,
+        pub release: String,
+        pub version: String,
+    }
+
+    pub(super) fn linux() -> Detected {
+        let proc = |name: &str| {
+            std::fs::read_to_string(format!("/proc/sys/kernel/{}", name))
+                .ok()
+                .map(|value| value.trim().to_string())
+        };
+        let os_release = std::fs::read_to_string("/etc/os-release").unwrap_or_default();
+        let field = |key: &str| os_release_field(&os_release, key);
+
+        let kernel_release = proc("osrelease").unwrap_or_default();
+        Detected {
+            name: field("PRETTY_NAME").or_else(|| field("NAME")).unwrap_or_default(),
+            version: field("VERSION_ID").unwrap_or_else(|| kernel_release.clone()),
+            build: field("BUILD_ID").unwrap_or_default(),
+            kernel: proc("ostype").map(|name| Kernel {
+                name,
+                arch: std::env::consts::ARCH.to_string(),
+                release: kernel_release,
+                version: proc("version").unwrap_or_default(),
+            }),
+        }
+    }
+
+    pub(super) fn mac() -> Detected {
+        let sw_vers = |arg: &str| command_output("sw_vers", &[arg]).unwrap_or_default();
+        let uname = |arg: &str| command_output("uname", &[arg]);
+        Detected {
+            name: sw_vers("-productName"),
+            version: sw_vers("-productVersion"),
+            build: sw_vers("-buildVersion"),
+            kernel: uname("-s").map(|name| Kernel {
+                name,
+                arch: uname("-m").unwrap_or_default(),
+                release: uname("-r").unwrap_or_default(),
+                version: uname("-v").unwrap_or_default(),
+            }),
+        }
+    }
+
+    pub(super) fn windows() -> Detected {
+        // e.g. "Microsoft Windows [Version 10.0.19045.3803]"
+        let ver = command_output("cmd", &["/C", "ver"]).unwrap_or_default();
+        let version = ver
+            .rsplit(' ')
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches(']')
+            .to_string();
+        Detected {
+            name: String::from("Windows"),
+            build: version.split('.').nth(2).unwrap_or_default().to_string(),
+            version,
+            kernel: None,
+        }
+    }
+
+    /// Major, minor and patch numbers of a dotted version, missing or invalid ones are 0.
+    pub(super) fn parse_version(version: &str) -> (u16, u16, u16) {
+        let mut numbers = version.split(['.', '-']).map(|number| {
+            let digits = number.find(|c: char| !c.is_ascii_digit()).unwrap_or(number.len());
+            number[..digits].parse().unwrap_or(0)
+        });
+        let mut next = || numbers.next().unwrap_or(0);
+        (next(), next(), next())
+    }
+
+    /// Value of a `KEY=value` line, unquoted.
+    pub(super) fn os_release_field(os_release: &str, key: &str) -> Option<String> {
+        os_release.lines().find_map(|line| {
+            let value = line.strip_prefix(key)?.strip_prefix('=')?;
+            Some(value.trim().trim_matches('"').to_string())
+        })
+    }
+
+    fn command_output(program: &str, args: &[&str]) -> Option<String> {
+        let output = Command::new(program).args(args).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "SystemInfoType"]
+// holds a `NowSystemOsInfo`
+#[roundtrip(non_canonical)]
 pub enum NowSystemInfo<'a> {
     Os(NowSystemOsInfo<'a>),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
 }
 
 // NOW_SYSTEM_MSG
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum SystemMessageType {
     #[value = 0x01]
     InfoReq,
@@ -373,8 +464,12 @@ pub enum SystemMessageType {
     Other(u8),
 }
 
-#[derive(Debug, Decode, Encode, Clone)]
+#[derive(Debug, Decode, Encode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowSystemInfoReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: SystemMessageType,
     flags: u8,
 
@@ -393,8 +488,17 @@ impl NowSystemInfoReqMsg {
     }
 }
 
-#[derive(Decode, Encode, Debug, Clone)]
+#[derive(Decode, Encode, Debug, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+// holds a `NowSystemOsInfo`
+#[roundtrip(non_canonical)]
 pub struct NowSystemInfoRspMsg<'a> {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: SystemMessageType,
     flags: u8,
 
@@ -420,8 +524,12 @@ __flags_struct! {
     }
 }
 
-#[derive(Debug, Encode, Decode, Clone)]
+#[derive(Debug, Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowSystemShutdownMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: SystemMessageType,
     pub flags: ShutdownFlags,
 
@@ -446,14 +554,21 @@ impl NowSystemShutdownMsg {
     }
 }
 
-#[derive(Debug, Clone, Decode, Encode)]
+#[derive(Debug, Clone, Decode, Encode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "SystemMessageType"]
+// holds a `NowSystemOsInfo`
+#[roundtrip(non_canonical)]
 pub enum NowSystemMsg<'a> {
     InfoReq(NowSystemInfoReqMsg),
     InfoRsp(Box<NowSystemInfoRspMsg<'a>>),
     Shutdown(NowSystemShutdownMsg),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
 }
 
 #[cfg(test)]
@@ -553,5 +668,49 @@ mod tests {
         NowSystemOsInfo::decode(&WINDOWS_SYSTEM_INFO).unwrap();
     }
 
+    #[test]
+    fn conditional_fields_follow_flags() {
+        let mut info = NowSystemOsInfo::new(OsType::Mac, OsArch::ARM64, 11, 0, 0, NowString16::new_empty());
+        info.kernel_name = NowString16::from_str("Darwin").unwrap();
+        info.extra = Some(OsInfoExtra::Mac(OsInfoExtraMac::new_with_flags(0)));
+
+        // neither kernel nor extra flag is set
+        let encoded = info.encode().unwrap();
+        assert_eq!(encoded.len(), 16);
+        assert_eq!(info.encoded_len(), 16);
+        let decoded = NowSystemOsInfo::decode(&encoded).unwrap();
+        assert_eq!(decoded.kernel_name, "");
+        assert!(decoded.extra.is_none());
+
+        info.flags.set_extra();
+        let encoded = info.encode().unwrap();
+        assert_eq!(encoded.len(), 20);
+        let decoded = NowSystemOsInfo::decode(&encoded).unwrap();
+        assert!(matches!(decoded.extra, Some(OsInfoExtra::Mac(_))));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn os_info_detection() {
+        assert_eq!(detect::parse_version("22.04"), (22, 4, 0));
+        assert_eq!(detect::parse_version("6.18.44-fc-v130"), (6, 18, 44));
+        assert_eq!(detect::parse_version("10.0.19045.3803"), (10, 0, 19045));
+        assert_eq!(detect::parse_version(""), (0, 0, 0));
+
+        let os_release = "NAME=\"Ubuntu\"\nVERSION_ID=\"22.04\"\nPRETTY_NAME=\"Ubuntu 22.04.3 LTS\"\n";
+        assert_eq!(detect::os_release_field(os_release, "VERSION_ID").unwrap(), "22.04");
+        assert_eq!(
+            detect::os_release_field(os_release, "PRETTY_NAME").unwrap(),
+            "Ubuntu 22.04.3 LTS"
+        );
+        assert!(detect::os_release_field(os_release, "BUILD_ID").is_none());
+
+        let info = NowSystemOsInfo::detect();
+        let encoded = info.encode().unwrap();
+        let decoded = NowSystemOsInfo::decode(&encoded).unwrap();
+        assert_eq!(decoded.os_type, info.os_type);
+        assert_eq!(decoded.os_name.as_str(), info.os_name.as_str());
+    }
+
     // TODO: info req message
 }
@@ -2,8 +2,12 @@
 
 use crate::container::{Bytes32, Vec8};
 use crate::message::{common, Codec, SizeRect};
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum UpdateMessageType {
     #[value = 0x01]
     UpdateGraphics,
@@ -11,6 +15,8 @@ pub enum UpdateMessageType {
     UpdateRefresh,
     #[value = 0x03]
     UpdateSuppress,
+    #[value = 0x04]
+    UpdateAck,
     #[fallback]
     Other(u8),
 }
@@ -23,6 +29,8 @@ __flags_struct! {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum UpdateRegionFlag {
     #[value = 0x01]
     Null,
@@ -32,25 +40,80 @@ pub enum UpdateRegionFlag {
     Other(u8),
 }
 
-#[derive(Decode, Encode, Debug, Clone)]
+#[derive(Decode, Encode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowUpdateRegion {
     pub surface_id: u16,
     pub flags: UpdateRegionFlag,
     pub rects: Vec8<SizeRect>,
 }
 
-#[derive(Debug, Clone, Encode, Decode)]
+impl NowUpdateRegion {
+    pub fn new(surface_id: u16, rects: Vec<SizeRect>) -> Self {
+        Self {
+            surface_id,
+            flags: UpdateRegionFlag::Null,
+            rects: Vec8(rects),
+        }
+    }
+
+    /// The whole surface.
+    pub fn new_full(surface_id: u16) -> Self {
+        Self {
+            surface_id,
+            flags: UpdateRegionFlag::Full,
+            rects: Vec8(Vec::new()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "UpdateMessageType"]
+#[roundtrip]
 pub enum NowUpdateMsg<'a> {
     UpdateGraphics(NowUpdateGraphicsMsg<'a>),
     UpdateRefresh(NowUpdateRefreshMsg),
     UpdateSuppress(NowUpdateSuppressMsg),
+    UpdateAck(NowUpdateAckMsg),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
+}
+
+impl From<NowUpdateRefreshMsg> for NowUpdateMsg<'_> {
+    fn from(msg: NowUpdateRefreshMsg) -> Self {
+        Self::UpdateRefresh(msg)
+    }
+}
+
+impl From<NowUpdateSuppressMsg> for NowUpdateMsg<'_> {
+    fn from(msg: NowUpdateSuppressMsg) -> Self {
+        Self::UpdateSuppress(msg)
+    }
+}
+
+impl From<NowUpdateAckMsg> for NowUpdateMsg<'_> {
+    fn from(msg: NowUpdateAckMsg) -> Self {
+        Self::UpdateAck(msg)
+    }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowUpdateGraphicsMsg<'a> {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = UpdateMessageType::UpdateGraphics))]
     pub subtype: UpdateMessageType,
     flags: u8,
 
@@ -66,8 +129,12 @@ impl<'a> NowUpdateGraphicsMsg<'a> {
     pub const REQUIRED_SIZE: usize = 24;
 }
 
-#[derive(Decode, Encode, Debug, Clone)]
+#[derive(Decode, Encode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowUpdateRefreshMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     pub subtype: UpdateMessageType,
     flags: u8,
 
@@ -75,9 +142,27 @@ pub struct NowUpdateRefreshMsg {
     pub regions: Vec8<NowUpdateRegion>,
 }
 
-#[derive(Decode, Encode, Debug, Clone)]
+impl NowUpdateRefreshMsg {
+    pub const SUBTYPE: UpdateMessageType = UpdateMessageType::UpdateRefresh;
+
+    /// Asks the server to send the given regions again.
+    pub fn new(regions: Vec<NowUpdateRegion>) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: 0,
+            reserved: 0,
+            regions: Vec8(regions),
+        }
+    }
+}
+
+#[derive(Decode, Encode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(C)]
+#[roundtrip]
 pub struct NowUpdateSuppressMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     pub subtype: UpdateMessageType,
     flags: u8,
 
@@ -85,11 +170,53 @@ pub struct NowUpdateSuppressMsg {
     pub regions: Vec8<NowUpdateRegion>,
 }
 
+impl NowUpdateSuppressMsg {
+    pub const SUBTYPE: UpdateMessageType = UpdateMessageType::UpdateSuppress;
+
+    /// Asks the server to stop sending updates for the given regions, until they are refreshed.
+    pub fn new(regions: Vec<NowUpdateRegion>) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: 0,
+            reserved: 0,
+            regions: Vec8(regions),
+        }
+    }
+}
+
+/// Acknowledges a complete frame. The server paces graphics updates on acknowledgments.
+#[derive(Decode, Encode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct NowUpdateAckMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
+    pub subtype: UpdateMessageType,
+    flags: u8,
+
+    pub surface_id: u16,
+    pub frame_id: u16,
+}
+
+impl NowUpdateAckMsg {
+    pub const SUBTYPE: UpdateMessageType = UpdateMessageType::UpdateAck;
+    pub const REQUIRED_SIZE: usize = 6;
+
+    pub fn new(surface_id: u16, frame_id: u16) -> Self {
+        Self {
+            subtype: Self::SUBTYPE,
+            flags: 0,
+            surface_id,
+            frame_id,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::header::{AbstractNowHeader, NowHeader};
-    use crate::serialization::Decode;
+    use crate::serialization::{Decode, Encode};
 
     #[rustfmt::skip]
     const WAYK_NOW_UPDATE_GRAPHIC_MSG: [u8; 35] = [
@@ -124,4 +251,38 @@ mod tests {
         assert_eq!(ugm.update_data.len(), 5);
         assert_eq!(ugm.update_data[0], 0x01);
     }
+
+    #[rustfmt::skip]
+    const UPDATE_ACK_MSG: [u8; 6] = [
+        0x04, // subtype
+        0x00, // flags
+        0x01, 0x00, // surfaceID
+        0x2a, 0x00, // frameID
+    ];
+
+    #[test]
+    fn update_ack_round_trip() {
+        match NowUpdateMsg::decode(&UPDATE_ACK_MSG).unwrap() {
+            NowUpdateMsg::UpdateAck(msg) => assert_eq!(msg, NowUpdateAckMsg::new(1, 42)),
+            msg => panic!("expected an update ack and got {:?}", msg),
+        }
+        assert_eq!(NowUpdateAckMsg::new(1, 42).encode().unwrap(), UPDATE_ACK_MSG.to_vec());
+    }
+
+    #[rustfmt::skip]
+    const UPDATE_REFRESH_MSG: [u8; 8] = [
+        0x02, // subtype
+        0x00, // flags
+        0x00, // reserved
+        0x01, // region count
+        0x03, 0x00, // surfaceID
+        0x02, // region flags (full)
+        0x00, // rect count
+    ];
+
+    #[test]
+    fn update_refresh_encoding() {
+        let msg = NowUpdateRefreshMsg::new(vec![NowUpdateRegion::new_full(3)]);
+        assert_eq!(msg.encode().unwrap(), UPDATE_REFRESH_MSG.to_vec());
+    }
 }
@@ -0,0 +1,124 @@
+//! Owned messages.
+//!
+//! Decoded messages borrow from the buffer they were decoded from. [`IntoOwned`](trait.IntoOwned.html)
+//! copies the borrowed bytes so that any message can be queued, stored or sent to another thread
+//! as its `'static` counterpart.
+
+use crate::message::{NowBody, NowMessage, NowVirtualChannel};
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Detaches a decoded value from the buffer it borrows.
+///
+/// Implemented with `#[derive(IntoOwned)]` for types with a lifetime parameter.
+pub trait IntoOwned {
+    type Owned: 'static;
+
+    fn into_owned(self) -> Self::Owned;
+}
+
+impl IntoOwned for Cow<'_, [u8]> {
+    type Owned = Cow<'static, [u8]>;
+
+    fn into_owned(self) -> Cow<'static, [u8]> {
+        Cow::Owned(Cow::into_owned(self))
+    }
+}
+
+impl<T: IntoOwned> IntoOwned for Vec<T> {
+    type Owned = Vec<T::Owned>;
+
+    fn into_owned(self) -> Self::Owned {
+        self.into_iter().map(IntoOwned::into_owned).collect()
+    }
+}
+
+impl<T: IntoOwned> IntoOwned for Option<T> {
+    type Owned = Option<T::Owned>;
+
+    fn into_owned(self) -> Self::Owned {
+        self.map(IntoOwned::into_owned)
+    }
+}
+
+impl<T: IntoOwned> IntoOwned for Box<T> {
+    type Owned = Box<T::Owned>;
+
+    fn into_owned(self) -> Self::Owned {
+        Box::new((*self).into_owned())
+    }
+}
+
+pub type NowMessageOwned = NowMessage<'static>;
+
+pub type NowVirtualChannelOwned = NowVirtualChannel<'static>;
+
+pub type NowBodyOwned = NowBody<'static>;
+
+sa::assert_impl_all!(NowMessageOwned: Sync, Send);
+sa::assert_impl_all!(NowVirtualChannelOwned: Sync, Send);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::Cursor;
+    use crate::message::{
+        ChannelName, MessageType, NowChatTextMsg, NowString65535, NowSystemInfoReqMsg, NowSystemMsg, SystemInfoType,
+    };
+    use crate::serialization::Encode;
+    use core::str::FromStr;
+
+    fn owned_chat_text() -> NowVirtualChannelOwned {
+        let buffer = NowVirtualChannel::from(NowChatTextMsg::new(42, 0, NowString65535::from_str("hello").unwrap()))
+            .encode()
+            .unwrap();
+        // decoded message borrows `buffer`, the owned one outlives it
+        NowVirtualChannel::decode_from(&ChannelName::Chat, &mut Cursor::new(&buffer))
+            .unwrap()
+            .into_owned()
+    }
+
+    #[test]
+    fn owned_virtual_channel_outlives_buffer() {
+        let owned = owned_chat_text();
+        assert_eq!(owned.get_name(), &ChannelName::Chat);
+        match owned {
+            NowVirtualChannel::Chat(crate::message::NowChatMsg::Text(msg)) => {
+                assert_eq!(msg.timestamp, 42);
+                assert_eq!(msg.text.as_str(), "hello");
+            }
+            msg => panic!("expected a chat text message and got {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn owned_message_roundtrip() {
+        let msg = NowMessage::from(NowSystemMsg::InfoReq(NowSystemInfoReqMsg::new(SystemInfoType::Os)));
+        let encoded = msg.encode().unwrap();
+        let owned = msg.clone().into_owned();
+        assert_eq!(owned, msg);
+        assert_eq!(owned.get_type(), MessageType::System);
+        assert_eq!(owned.encode().unwrap(), encoded);
+
+        let body = NowBodyOwned::from(owned);
+        assert!(matches!(body, NowBody::Message(NowMessage::System(_))));
+    }
+
+    #[test]
+    fn owned_custom_payload_is_copied() {
+        let buffer = vec![0x01, 0x02, 0x03];
+        let owned = {
+            let msg = NowMessage::decode_from(MessageType::from(0xA7), &mut Cursor::new(&buffer)).unwrap();
+            msg.into_owned()
+        };
+        drop(buffer);
+        match owned {
+            NowMessage::Custom { payload, .. } => {
+                assert!(matches!(payload, Cow::Owned(_)));
+                assert_eq!(*payload, [0x01, 0x02, 0x03]);
+            }
+            msg => panic!("expected a custom message and got {:?}", msg),
+        }
+    }
+}
@@ -46,6 +46,7 @@ where
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NowStatus<CodeType: From<u16> + Into<u16> + Copy> {
     repr: u32,
 
@@ -101,6 +102,17 @@ where
     }
 }
 
+// the cache is filled from the representation, as when decoding
+#[cfg(feature = "arbitrary")]
+impl<'a, CodeType> arbitrary::Arbitrary<'a> for NowStatus<CodeType>
+where
+    CodeType: From<u16> + Into<u16> + Copy,
+{
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Self::from_u32(u32::arbitrary(u)?).map_err(|_| arbitrary::Error::IncorrectFormat)
+    }
+}
+
 impl<CodeType> From<NowStatus<CodeType>> for u32
 where
     CodeType: From<u16> + Into<u16> + Copy,
@@ -171,6 +183,8 @@ where
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum SeverityLevel {
     #[value = 0x00]
     Info,
@@ -191,6 +205,8 @@ impl fmt::Display for SeverityLevel {
 }
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum StatusType {
     #[value = 0x00]
     None,
@@ -231,6 +247,8 @@ impl fmt::Display for StatusType {
 // NSTATUS_DISCONNECT_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum DisconnectStatusCode {
     #[value = 0x0000]
     Success,
@@ -295,6 +313,8 @@ impl fmt::Display for DisconnectStatusCode {
 // NSTATUS_CONNECT_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum ConnectStatusCode {
     #[value = 0x0000]
     Success,
@@ -335,6 +355,8 @@ impl fmt::Display for ConnectStatusCode {
 // NSTATUS_SECURITY_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum SecurityStatusCode {
     #[value = 0x0000]
     Success,
@@ -366,6 +388,8 @@ impl fmt::Display for SecurityStatusCode {
 // NSTATUS_HANDSHAKE_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum HandshakeStatusCode {
     #[value = 0x0000]
     Success,
@@ -391,6 +415,8 @@ impl fmt::Display for HandshakeStatusCode {
 // NSTATUS_NEGOTIATE_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum NegotiateStatusCode {
     #[value = 0x0000]
     Success,
@@ -413,6 +439,8 @@ impl fmt::Display for NegotiateStatusCode {
 // NSTATUS_AUTH_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum AuthStatusCode {
     #[value = 0x0000]
     Success,
@@ -462,6 +490,8 @@ impl fmt::Display for AuthStatusCode {
 // NSTATUS_ASSOCIATE_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum AssociateStatusCode {
     #[value = 0x0000]
     Success,
@@ -484,6 +514,8 @@ impl fmt::Display for AssociateStatusCode {
 // NSTATUS_CAPABILITIES_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum CapabilitiesStatusCode {
     #[value = 0x0000]
     Success,
@@ -506,6 +538,8 @@ impl fmt::Display for CapabilitiesStatusCode {
 // NSTATUS_CHANNEL_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum ChannelStatusCode {
     #[value = 0x0000]
     Success,
@@ -528,6 +562,8 @@ impl fmt::Display for ChannelStatusCode {
 // NSTATUS_CLIPBOARD_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum ClipboardStatusCode {
     #[value = 0x0000]
     Success,
@@ -550,6 +586,8 @@ impl fmt::Display for ClipboardStatusCode {
 // NSTATUS_FILE_TRANSFER_TYPE
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum FileTransferStatusCode {
     #[value = 0x0000]
     Success,
@@ -572,6 +610,8 @@ impl fmt::Display for FileTransferStatusCode {
 // NSTATUS_EXEC_TYPE (Remote Execution)
 
 #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum ExecStatusCode {
     #[value = 0x0000]
     Success,
@@ -1,8 +1,13 @@
 // Chat
 
 use crate::message::common::now_string::NowString65535;
+use crate::message::UserContent;
+use alloc::borrow::Cow;
+use core::fmt;
 
 #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum ChatMessageType {
     #[value = 0x00]
     Sync,
@@ -22,8 +27,14 @@ pub enum ChatMessageType {
     Other(u8),
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "ChatMessageType"]
+#[roundtrip]
 pub enum NowChatMsg<'a> {
     Sync(NowChatSyncMsg),
     Text(NowChatTextMsg),
@@ -33,7 +44,22 @@ pub enum NowChatMsg<'a> {
     Status(NowChatStatusMsg),
     Poke(NowChatPokeMsg),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
+}
+
+impl fmt::Debug for NowChatMsg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sync(msg) => f.debug_tuple("Sync").field(msg).finish(),
+            Self::Text(msg) => f.debug_tuple("Text").field(msg).finish(),
+            Self::Read(msg) => f.debug_tuple("Read").field(msg).finish(),
+            Self::Typing(msg) => f.debug_tuple("Typing").field(msg).finish(),
+            Self::Name(msg) => f.debug_tuple("Name").field(msg).finish(),
+            Self::Status(msg) => f.debug_tuple("Status").field(msg).finish(),
+            Self::Poke(msg) => f.debug_tuple("Poke").field(msg).finish(),
+            Self::Custom(payload) => f.debug_tuple("Custom").field(&UserContent(&**payload)).finish(),
+        }
+    }
 }
 
 impl From<NowChatSyncMsg> for NowChatMsg<'_> {
@@ -81,6 +107,8 @@ impl From<NowChatPokeMsg> for NowChatMsg<'_> {
 // subtypes
 
 #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum ChatPresenceStatus {
     #[value = 0x00]
     Unknown,
@@ -110,8 +138,12 @@ __flags_struct! {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowChatSyncMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ChatMessageType,
     flags: u8,
     reserved: u16,
@@ -148,14 +180,33 @@ impl NowChatSyncMsg {
     }
 }
 
+impl fmt::Debug for NowChatSyncMsg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NowChatSyncMsg")
+            .field("subtype", &self.subtype)
+            .field("flags", &self.flags)
+            .field("reserved", &self.reserved)
+            .field("timestamp", &self.timestamp)
+            .field("capabilities", &self.capabilities)
+            .field("friendly_name", &UserContent(self.friendly_name.as_str()))
+            .field("presence", &self.presence)
+            .field("status_text", &UserContent(self.status_text.as_str()))
+            .finish()
+    }
+}
+
 __flags_struct! {
     ChatTextFlags: u8 => {
         snippet = SNIPPET = 0x01, // contains a code snippet (monospace font, no rich formatting).
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowChatTextMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ChatMessageType,
     pub flags: ChatTextFlags,
     reserved: u16,
@@ -186,8 +237,26 @@ impl NowChatTextMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+impl fmt::Debug for NowChatTextMsg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NowChatTextMsg")
+            .field("subtype", &self.subtype)
+            .field("flags", &self.flags)
+            .field("reserved", &self.reserved)
+            .field("timestamp", &self.timestamp)
+            .field("session_id", &self.session_id)
+            .field("message_id", &self.message_id)
+            .field("text", &UserContent(self.text.as_str()))
+            .finish()
+    }
+}
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowChatReadMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ChatMessageType,
     flags: u8,
     reserved: u16,
@@ -213,10 +282,14 @@ __flags_struct! {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowChatTypingMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ChatMessageType,
-    flags: u8,
+    pub flags: ChatTypingFlags,
     reserved: u16,
     pub timestamp: u32,
 
@@ -228,9 +301,13 @@ impl NowChatTypingMsg {
     pub const SUBTYPE: ChatMessageType = ChatMessageType::Typing;
 
     pub fn new(timestamp: u32, message_id: u32) -> Self {
+        Self::new_with_flags(timestamp, message_id, ChatTypingFlags::new_empty())
+    }
+
+    pub fn new_with_flags(timestamp: u32, message_id: u32, flags: ChatTypingFlags) -> Self {
         Self {
             subtype: Self::SUBTYPE,
-            flags: 0,
+            flags,
             reserved: 0,
             timestamp,
             session_id: 0,
@@ -239,8 +316,12 @@ impl NowChatTypingMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowChatNameMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ChatMessageType,
     flags: u8,
     reserved: u16,
@@ -260,8 +341,12 @@ impl NowChatNameMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowChatStatusMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ChatMessageType,
     flags: u8,
     reserved: u16,
@@ -281,8 +366,12 @@ impl NowChatStatusMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowChatPokeMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ChatMessageType,
     flags: u8,
     reserved: u16,
@@ -305,11 +394,11 @@ impl NowChatPokeMsg {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::header::{AbstractNowHeader, NowHeader};
     use crate::message::{ChannelName, NowBody, NowVirtualChannel, VirtChannelsCtx};
     use crate::packet::NowPacket;
     use crate::serialization::{Decode, Encode};
     use core::str::FromStr;
-    use std::io::Cursor;
 
     fn get_ctx() -> VirtChannelsCtx {
         let mut vchan_ctx = VirtChannelsCtx::new();
@@ -339,9 +428,9 @@ mod tests {
 
     #[test]
     fn decode_chat_sync() {
-        let mut buffer = Vec::new();
-        let mut reader = Cursor::new(&CHAT_SYNC_WITH_HEADER[..]);
-        match NowPacket::read_from(&mut reader, &mut buffer, &get_ctx()) {
+        let header = NowHeader::decode(&CHAT_SYNC_WITH_HEADER).unwrap();
+        let body = &CHAT_SYNC_WITH_HEADER[header.len()..];
+        match NowPacket::decode_from(header, body, &get_ctx()) {
             Ok(packet) => match packet.body {
                 NowBody::Message(_) => panic!("decoded a now message from a virtual channel packet"),
                 NowBody::VirtualChannel(vchan) => {
@@ -361,10 +450,7 @@ mod tests {
                     }
                 }
             },
-            Err(e) => {
-                e.print_trace();
-                panic!("couldn't decode chat sync packet");
-            }
+            Err(e) => panic!("couldn't decode chat sync packet: {:#}", e),
         }
     }
 
@@ -413,4 +499,20 @@ mod tests {
         let msg = NowChatTextMsg::new(0x5d97a0d1, 1, NowString65535::from_str("ユニコードはどう？").unwrap());
         assert_eq!(msg.encode().unwrap(), TEXT_MSG.to_vec());
     }
+
+    #[test]
+    fn debug_chat_text() {
+        let debug = format!("{:?}", NowChatTextMsg::decode(&TEXT_MSG).unwrap());
+        assert!(debug.contains("message_id: 1"));
+        assert_eq!(debug.contains("ユニコード"), !cfg!(feature = "redact"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_json_roundtrip_chat_text() {
+        let msg = NowVirtualChannel::from(NowChatMsg::Text(NowChatTextMsg::decode(&TEXT_MSG).unwrap()));
+        let json = serde_json::to_string(&msg).unwrap();
+        let msg: NowVirtualChannel = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg.encode().unwrap(), TEXT_MSG.to_vec());
+    }
 }
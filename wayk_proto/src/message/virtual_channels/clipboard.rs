@@ -1,10 +1,14 @@
 // Clipboard
 
 use crate::container::{Bytes32, Vec32, Vec8};
-use crate::message::NowString256;
+use crate::message::{NowString256, UserContent};
+use alloc::borrow::Cow;
 use alloc::vec::Vec;
+use core::fmt;
 
 #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum ClipboardMessageType {
     #[value = 0x01]
     CapabilitiesReq,
@@ -35,6 +39,8 @@ pub enum ClipboardMessageType {
 }
 
 #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum ClipboardControlState {
     #[value = 0x0000]
     None,
@@ -52,7 +58,10 @@ __flags_struct! {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct ClipboardFormatDef {
     pub id: u32,
     pub name: NowString256,
@@ -64,8 +73,14 @@ impl ClipboardFormatDef {
     }
 }
 
-#[derive(Debug, Clone, Encode, Decode)]
+#[derive(Clone, Encode, Decode, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
 #[meta_enum = "ClipboardMessageType"]
+#[roundtrip]
 pub enum NowClipboardMsg<'a> {
     CapabilitiesReq(NowClipboardCapabilitiesReqMsg),
     CapabilitiesRsp(NowClipboardCapabilitiesRspMsg),
@@ -80,12 +95,47 @@ pub enum NowClipboardMsg<'a> {
     FormatDataReq(NowClipboardFormatDataReqMsg),
     FormatDataRsp(NowClipboardFormatDataRspMsg<'a>),
     #[fallback]
-    Custom(&'a [u8]),
+    Custom(#[cfg_attr(feature = "serde", serde(borrow, with = "serde_bytes"))] Cow<'a, [u8]>),
 
     #[decode_ignore]
     FormatDataRspOwned(NowClipboardFormatDataRspMsgOwned),
 }
 
+impl fmt::Debug for NowClipboardMsg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CapabilitiesReq(msg) => f.debug_tuple("CapabilitiesReq").field(msg).finish(),
+            Self::CapabilitiesRsp(msg) => f.debug_tuple("CapabilitiesRsp").field(msg).finish(),
+            Self::ControlReq(msg) => f.debug_tuple("ControlReq").field(msg).finish(),
+            Self::ControlRsp(msg) => f.debug_tuple("ControlRsp").field(msg).finish(),
+            Self::SuspendReq(msg) => f.debug_tuple("SuspendReq").field(msg).finish(),
+            Self::SuspendRsp(msg) => f.debug_tuple("SuspendRsp").field(msg).finish(),
+            Self::ResumeReq(msg) => f.debug_tuple("ResumeReq").field(msg).finish(),
+            Self::ResumeRsp(msg) => f.debug_tuple("ResumeRsp").field(msg).finish(),
+            Self::FormatListReq(msg) => f.debug_tuple("FormatListReq").field(msg).finish(),
+            Self::FormatListRsp(msg) => f.debug_tuple("FormatListRsp").field(msg).finish(),
+            Self::FormatDataReq(msg) => f.debug_tuple("FormatDataReq").field(msg).finish(),
+            Self::FormatDataRsp(msg) => f.debug_tuple("FormatDataRsp").field(msg).finish(),
+            Self::Custom(payload) => f.debug_tuple("Custom").field(&UserContent(&**payload)).finish(),
+            Self::FormatDataRspOwned(msg) => f.debug_tuple("FormatDataRspOwned").field(msg).finish(),
+        }
+    }
+}
+
+impl NowClipboardMsg<'_> {
+    /// Sequence id of format list and format data messages.
+    pub fn sequence_id(&self) -> Option<u16> {
+        match self {
+            NowClipboardMsg::FormatListReq(msg) => Some(msg.sequence_id),
+            NowClipboardMsg::FormatListRsp(msg) => Some(msg.sequence_id),
+            NowClipboardMsg::FormatDataReq(msg) => Some(msg.sequence_id),
+            NowClipboardMsg::FormatDataRsp(msg) => Some(msg.sequence_id),
+            NowClipboardMsg::FormatDataRspOwned(msg) => Some(msg.sequence_id),
+            _ => None,
+        }
+    }
+}
+
 impl From<NowClipboardCapabilitiesReqMsg> for NowClipboardMsg<'_> {
     fn from(msg: NowClipboardCapabilitiesReqMsg) -> Self {
         Self::CapabilitiesReq(msg)
@@ -166,8 +216,12 @@ impl From<NowClipboardFormatDataRspMsgOwned> for NowClipboardMsg<'_> {
 
 // subtypes
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardCapabilitiesReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     flags: u8,
     capabilities: u16,
@@ -191,8 +245,12 @@ impl NowClipboardCapabilitiesReqMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardCapabilitiesRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     pub flags: ClipboardResponseFlags,
     capabilities: u16,
@@ -216,8 +274,12 @@ impl NowClipboardCapabilitiesRspMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardControlReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     flags: u8,
     pub control_state: ClipboardControlState,
@@ -235,8 +297,12 @@ impl NowClipboardControlReqMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardControlRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     pub flags: ClipboardResponseFlags,
     pub control_state: ClipboardControlState,
@@ -258,8 +324,12 @@ impl NowClipboardControlRspMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardSuspendReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     flags: u8,
     reserved: u16,
@@ -279,8 +349,12 @@ impl NowClipboardSuspendReqMsg {
     pub const SUBTYPE: ClipboardMessageType = ClipboardMessageType::SuspendReq;
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardSuspendRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     pub flags: ClipboardResponseFlags,
     reserved: u16,
@@ -304,8 +378,12 @@ impl NowClipboardSuspendRspMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardResumeReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     flags: u8,
     reserved: u16,
@@ -325,8 +403,12 @@ impl NowClipboardResumeReqMsg {
     pub const SUBTYPE: ClipboardMessageType = ClipboardMessageType::ResumeReq;
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardResumeRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     pub flags: ClipboardResponseFlags,
     reserved: u16,
@@ -350,8 +432,12 @@ impl NowClipboardResumeRspMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardFormatListReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     flags: u8,
     pub sequence_id: u16,
@@ -375,8 +461,12 @@ impl NowClipboardFormatListReqMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardFormatListRspMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     pub flags: ClipboardResponseFlags,
     pub sequence_id: u16,
@@ -398,8 +488,12 @@ impl NowClipboardFormatListRspMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardFormatDataReqMsg {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     flags: u8,
     pub sequence_id: u16,
@@ -419,8 +513,16 @@ impl NowClipboardFormatDataReqMsg {
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Clone, PartialEq, IntoOwned)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardFormatDataRspMsg<'a> {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     pub flags: ClipboardResponseFlags,
     pub sequence_id: u16,
@@ -441,7 +543,7 @@ impl<'a> NowClipboardFormatDataRspMsg<'a> {
             flags,
             sequence_id,
             format_id,
-            format_data: Bytes32(&[]),
+            format_data: Bytes32::from(&[][..]),
         }
     }
 
@@ -451,13 +553,29 @@ impl<'a> NowClipboardFormatDataRspMsg<'a> {
             flags: ClipboardResponseFlags::new_empty(),
             sequence_id,
             format_id,
-            format_data: Bytes32(format_data),
+            format_data: Bytes32::from(format_data),
         }
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+impl fmt::Debug for NowClipboardFormatDataRspMsg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NowClipboardFormatDataRspMsg")
+            .field("subtype", &self.subtype)
+            .field("flags", &self.flags)
+            .field("sequence_id", &self.sequence_id)
+            .field("format_id", &self.format_id)
+            .field("format_data", &UserContent(&*self.format_data))
+            .finish()
+    }
+}
+
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
 pub struct NowClipboardFormatDataRspMsgOwned {
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = Self::SUBTYPE))]
     subtype: ClipboardMessageType,
     pub flags: ClipboardResponseFlags,
     pub sequence_id: u16,
@@ -493,13 +611,25 @@ impl NowClipboardFormatDataRspMsgOwned {
     }
 }
 
+impl fmt::Debug for NowClipboardFormatDataRspMsgOwned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NowClipboardFormatDataRspMsgOwned")
+            .field("subtype", &self.subtype)
+            .field("flags", &self.flags)
+            .field("sequence_id", &self.sequence_id)
+            .field("format_id", &self.format_id)
+            .field("format_data", &UserContent(self.format_data.0.as_slice()))
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::header::{AbstractNowHeader, NowHeader};
     use crate::message::{ChannelName, NowBody, NowVirtualChannel, VirtChannelsCtx};
     use crate::packet::NowPacket;
     use crate::serialization::{Decode, Encode};
-    use std::io::Cursor;
 
     fn get_ctx() -> VirtChannelsCtx {
         let mut vchan_ctx = VirtChannelsCtx::new();
@@ -519,9 +649,9 @@ mod tests {
 
     #[test]
     fn clipboard_caps_req_decoding() {
-        let mut buffer = Vec::new();
-        let mut reader = Cursor::new(&NOW_CLIPBOARD_CAPS_REQ_WITH_HEADER[..]);
-        match NowPacket::read_from(&mut reader, &mut buffer, &get_ctx()) {
+        let header = NowHeader::decode(&NOW_CLIPBOARD_CAPS_REQ_WITH_HEADER).unwrap();
+        let body = &NOW_CLIPBOARD_CAPS_REQ_WITH_HEADER[header.len()..];
+        match NowPacket::decode_from(header, body, &get_ctx()) {
             Ok(packet) => match packet.body {
                 NowBody::Message(_) => panic!("decoded a now message from a virtual channel packet"),
                 NowBody::VirtualChannel(vchan) => {
@@ -534,10 +664,7 @@ mod tests {
                     }
                 }
             },
-            Err(e) => {
-                e.print_trace();
-                panic!("couldn't decode capabilities packet");
-            }
+            Err(e) => panic!("couldn't decode capabilities packet: {:#}", e),
         }
     }
 
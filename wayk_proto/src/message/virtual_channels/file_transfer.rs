@@ -1,6 +1,8 @@
 // File Transfer
 
-#[derive(Encode, Decode, Debug, Clone, Copy)]
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
 pub enum FileTransferMessageType {
     #[value = 0x00]
     CapsetReq,
@@ -11,4 +11,3 @@ pub use chat::*;
 pub use clipboard::*;
 pub use exec::*;
 pub use file_transfer::*;
-pub use tunnel::*;
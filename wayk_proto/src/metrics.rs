@@ -0,0 +1,128 @@
+//! Counters reported by a sharee.
+//!
+//! Implement [`Metrics`](trait.Metrics.html) to forward counters to a monitoring system and hand it
+//! over with `ShareeBuilder::metrics`. All methods do nothing by default, so an implementation
+//! only overrides what it is interested in.
+//!
+//! ```
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use std::sync::Arc;
+//! use wayk_proto::message::BodyType;
+//! use wayk_proto::metrics::Metrics;
+//! use wayk_proto::sharee::Sharee;
+//! use wayk_proto::sm::{ClientConnectionSeqSM, DummyConnectionSM};
+//!
+//! #[derive(Default)]
+//! struct SentPackets(Arc<AtomicUsize>);
+//!
+//! impl Metrics for SentPackets {
+//!     fn packet_sent(&mut self, _: BodyType, _: usize) {
+//!         self.0.fetch_add(1, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let sent = SentPackets::default();
+//! let counter = sent.0.clone();
+//! let mut sharee = Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM))
+//!     .metrics(sent)
+//!     .build_unchecked();
+//! sharee.update_without_body(); // handshake
+//! assert_eq!(counter.load(Ordering::Relaxed), 1);
+//! ```
+
+use crate::error::ProtoError;
+use crate::header::AbstractNowHeader;
+use crate::message::{BodyType, ChannelName, NowBody, VirtChannelsCtx};
+use crate::serialization::Encode;
+use crate::sm::{ProtoState, SMEvent};
+use crate::trace::PacketDirection;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::any::TypeId;
+
+/// Receives counters from a sharee, see the [module documentation](index.html).
+pub trait Metrics: Send {
+    /// Current time in milliseconds, from any monotonic origin.
+    ///
+    /// State durations are only reported if a clock is provided.
+    fn now_ms(&mut self) -> Option<u64> {
+        None
+    }
+
+    /// A packet was handed over to the sharee. `body_len` excludes the header.
+    fn packet_received(&mut self, _body_type: BodyType, _body_len: usize) {}
+
+    /// The sharee produced a packet to send. `body_len` excludes the header.
+    fn packet_sent(&mut self, _body_type: BodyType, _body_len: usize) {}
+
+    /// Virtual channel payload bytes, in either direction.
+    fn channel_bytes(&mut self, _name: &ChannelName, _direction: PacketDirection, _len: usize) {}
+
+    /// Received bytes couldn't be decoded into a packet.
+    fn decode_error(&mut self, _error: &ProtoError) {}
+
+    /// A state machine entered `state`. `time_in_previous_ms` is how long it stayed in its
+    /// previous state, provided `now_ms` returns a time and it isn't the first transition.
+    fn state_transition(&mut self, _state: &dyn ProtoState, _time_in_previous_ms: Option<u64>) {}
+}
+
+/// Feeds a `Metrics` implementation from what goes through a sharee.
+pub(crate) struct MetricsReporter {
+    metrics: Box<dyn Metrics>,
+    /// When each state machine, identified by its state type, entered its current state.
+    entered_at: BTreeMap<TypeId, u64>,
+}
+
+impl MetricsReporter {
+    pub fn new(metrics: Box<dyn Metrics>) -> Self {
+        Self {
+            metrics,
+            entered_at: BTreeMap::new(),
+        }
+    }
+
+    pub fn metrics_mut(&mut self) -> &mut dyn Metrics {
+        &mut *self.metrics
+    }
+
+    pub fn on_received(&mut self, body: &NowBody<'_>, channels_ctx: &VirtChannelsCtx) {
+        let body_len = body.encoded_len();
+        match body {
+            NowBody::Message(msg) => self
+                .metrics
+                .packet_received(BodyType::Message(msg.get_type()), body_len),
+            NowBody::VirtualChannel(chan_msg) => {
+                let name = chan_msg.get_name();
+                if let Some(channel_id) = channels_ctx.get_id_by_channel(name) {
+                    self.metrics
+                        .packet_received(BodyType::VirtualChannel(channel_id), body_len);
+                }
+                self.metrics.channel_bytes(name, PacketDirection::Received, body_len);
+            }
+        }
+    }
+
+    pub fn on_events(&mut self, events: &[SMEvent<'_>]) {
+        for event in events {
+            match event {
+                SMEvent::PacketToSend(packet) => {
+                    let body_len = packet.header.body_len();
+                    self.metrics.packet_sent(packet.header.body_type(), body_len);
+                    if let NowBody::VirtualChannel(chan_msg) = &packet.body {
+                        self.metrics
+                            .channel_bytes(chan_msg.get_name(), PacketDirection::Sent, body_len);
+                    }
+                }
+                SMEvent::StateTransition(state) => {
+                    let previous = self.metrics.now_ms().and_then(|now| {
+                        self.entered_at
+                            .insert((**state).type_id(), now)
+                            .map(|t| now.saturating_sub(t))
+                    });
+                    self.metrics.state_transition(&**state, previous);
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use crate::error::{ProtoErrorKind, ProtoErrorResultExt, Result};
-use crate::header::{AbstractNowHeader, NowHeader, NowLongHeader};
+use crate::compression::Compression;
+use crate::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt, Result};
+use crate::header::{AbstractNowHeader, NowHeader};
 use crate::io::{Cursor, NoStdWrite};
-use crate::message::{BodyType, MessageType, NowBody, NowMessage, NowVirtualChannel, VirtChannelsCtx};
-use crate::serialization::{Decode, Encode};
+use crate::message::{BodyType, IntoOwned, MessageType, NowBody, NowMessage, NowVirtualChannel, VirtChannelsCtx};
+use crate::serialization::{Decode, DecodeCtx, Encode};
 use alloc::vec::Vec;
 use core::marker::PhantomData;
 
@@ -31,7 +32,7 @@ impl<'dec: 'a, 'a> Decode<'dec> for NowRawPacket<'a> {
 /// A now packet.
 ///
 /// See [`NowRawPacket`](struct.NowRawPacket.html) if you would rather decode by hand.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, IntoOwned)]
 pub struct NowPacket<'a> {
     pub header: NowHeader,
     pub body: NowBody<'a>,
@@ -61,42 +62,26 @@ impl<'a> NowPacket<'a> {
     pub fn from_message<Message: Into<NowMessage<'a>>>(message: Message) -> Self {
         let message = message.into();
 
-        let header = match &message {
-            NowMessage::Handshake(msg) => {
-                NowHeader::new_with_msg_type(MessageType::Handshake, msg.encoded_len() as u32)
-            }
-            NowMessage::Negotiate(msg) => {
-                NowHeader::new_with_msg_type(MessageType::Negotiate, msg.encoded_len() as u32)
-            }
-            NowMessage::Authenticate(msg) => {
-                NowHeader::new_with_msg_type(MessageType::Authenticate, msg.encoded_len() as u32)
-            }
-            NowMessage::Associate(msg) => {
-                NowHeader::new_with_msg_type(MessageType::Associate, msg.encoded_len() as u32)
-            }
-            NowMessage::Capabilities(msg) => {
-                NowHeader::new_with_msg_type(MessageType::Capabilities, msg.encoded_len() as u32)
-            }
-            NowMessage::Channel(msg) => NowHeader::new_with_msg_type(MessageType::Channel, msg.encoded_len() as u32),
-            NowMessage::Activate(msg) => NowHeader::new_with_msg_type(MessageType::Activate, msg.encoded_len() as u32),
-            NowMessage::Terminate(msg) => {
-                NowHeader::new_with_msg_type(MessageType::Terminate, msg.encoded_len() as u32)
-            }
-            NowMessage::Input(msg) => NowHeader::new_with_msg_type(MessageType::Input, msg.encoded_len() as u32),
-            NowMessage::Surface(msg) => NowHeader::new_with_msg_type(MessageType::Surface, msg.encoded_len() as u32),
-            NowMessage::Update(msg) => NowHeader::new_with_msg_type(MessageType::Update, msg.encoded_len() as u32),
-            NowMessage::System(msg) => NowHeader::new_with_msg_type(MessageType::System, msg.encoded_len() as u32),
-            NowMessage::Sharing(msg) => NowHeader::new_with_msg_type(MessageType::Sharing, msg.encoded_len() as u32),
-            NowMessage::Access(msg) => NowHeader::new_with_msg_type(MessageType::Access, msg.encoded_len() as u32),
-            NowMessage::Custom { ty, payload } => NowHeader::new_with_msg_type(*ty, payload.len() as u32),
-        };
-
         Self {
-            header,
+            header: h_message_header(&message),
             body: NowBody::Message(message),
         }
     }
 
+    /// Packet borrowing `message` instead of taking it, see [`NowPacketRef`](struct.NowPacketRef.html).
+    pub fn from_message_ref<'m>(message: &'m NowMessage<'a>) -> NowPacketRef<'m, 'a> {
+        NowPacketRef {
+            body: BodyRef::Message(message),
+        }
+    }
+
+    /// Packet borrowing `virt_channel` instead of taking it, see [`NowPacketRef`](struct.NowPacketRef.html).
+    pub fn from_virt_channel_ref<'m>(virt_channel: &'m NowVirtualChannel<'a>, channel_id: u8) -> NowPacketRef<'m, 'a> {
+        NowPacketRef {
+            body: BodyRef::VirtualChannel(channel_id, virt_channel),
+        }
+    }
+
     pub fn from_virt_channel<Channel: Into<NowVirtualChannel<'a>>>(virt_channel: Channel, channel_id: u8) -> Self {
         let virt_channel = virt_channel.into();
         let header = NowHeader::new_with_virt_channel(channel_id, virt_channel.encoded_len() as u32);
@@ -107,6 +92,25 @@ impl<'a> NowPacket<'a> {
         }
     }
 
+    /// Size of the encoded packet (header and body), as announced by the header.
+    ///
+    /// Cheaper than `encoded_len` as the body isn't walked, useful to check a packet fits a fixed
+    /// transmit buffer before encoding it.
+    pub fn packet_len(&self) -> usize {
+        self.header.packet_len()
+    }
+
+    /// Encodes into `scratch`, reusing its allocation, and returns the encoded bytes.
+    ///
+    /// Meant for send loops where a single buffer can be kept around instead of allocating
+    /// a new one per packet like `encode` does.
+    pub fn encode_with_scratch<'s>(&self, scratch: &'s mut Vec<u8>) -> Result<&'s [u8]> {
+        scratch.clear();
+        scratch.reserve(self.encoded_size_hint());
+        self.encode_into(scratch)?;
+        Ok(scratch.as_slice())
+    }
+
     #[cfg(feature = "std")]
     pub fn read_from<'dec: 'a, R: std::io::Read>(
         reader: &mut R,
@@ -132,18 +136,56 @@ impl<'a> NowPacket<'a> {
         buffer: &'dec [u8],
         channels_ctx: &VirtChannelsCtx,
     ) -> Result<Self> {
-        let mut cursor = Cursor::new(&buffer[..header.body_len()]);
+        Self::decode_with_ctx(header, buffer, &DecodeCtx::default().with_channels(channels_ctx))
+    }
+
+    /// Virtual channels are looked up in the context channels.
+    ///
+    /// The body must be exactly as long as announced by the header, unless trailing bytes are
+    /// allowed by the context.
+    pub fn decode_with_ctx<'dec: 'a>(header: NowHeader, buffer: &'dec [u8], ctx: &DecodeCtx<'_>) -> Result<Self> {
+        let body_len = header.body_len();
+        let body_bytes = buffer.get(..body_len).ok_or_else(|| {
+            ProtoError::new(ProtoErrorKind::LengthMismatch {
+                expected: body_len,
+                actual: buffer.len(),
+            })
+            .with_desc("packet body is truncated")
+        })?;
+
+        let mut cursor = Cursor::new(body_bytes);
+        if let Some(limits) = ctx.limits {
+            cursor.set_limits(limits);
+        }
         let body = match header.body_type() {
-            BodyType::Message(msg_type) => NowBody::Message(NowMessage::decode_from(msg_type, &mut cursor)?),
+            BodyType::Message(msg_type) => NowBody::Message(NowMessage::decode_with_ctx(msg_type, &mut cursor, ctx)?),
             BodyType::VirtualChannel(id) => {
-                let channel_name = channels_ctx
+                let channel_name = ctx
+                    .channels
                     .get_channel_by_id(id)
                     .chain(ProtoErrorKind::Decoding("NowPacket"))
                     .or_desc("channel name not found in channels context")?;
-                NowBody::VirtualChannel(NowVirtualChannel::decode_from(channel_name, &mut cursor)?)
+                NowBody::VirtualChannel(NowVirtualChannel::decode_with_ctx(channel_name, &mut cursor, ctx)?)
             }
         };
 
+        let consumed = cursor.position();
+        if consumed != body_len {
+            if !ctx.allow_trailing_bytes {
+                return Err(ProtoError::new(ProtoErrorKind::LengthMismatch {
+                    expected: body_len,
+                    actual: consumed,
+                })
+                .with_desc(format!("{:?} body not entirely consumed", header.body_type())));
+            }
+
+            log::trace!(
+                "skipped {} trailing bytes after {:?} body",
+                body_len - consumed,
+                header.body_type()
+            );
+        }
+
         Ok(Self { header, body })
     }
 }
@@ -157,11 +199,147 @@ where
     }
 }
 
+fn h_message_header(message: &NowMessage<'_>) -> NowHeader {
+    match message {
+        NowMessage::Handshake(msg) => NowHeader::new_with_msg_type(MessageType::Handshake, msg.encoded_len() as u32),
+        NowMessage::Negotiate(msg) => NowHeader::new_with_msg_type(MessageType::Negotiate, msg.encoded_len() as u32),
+        NowMessage::Authenticate(msg) => {
+            NowHeader::new_with_msg_type(MessageType::Authenticate, msg.encoded_len() as u32)
+        }
+        NowMessage::Associate(msg) => NowHeader::new_with_msg_type(MessageType::Associate, msg.encoded_len() as u32),
+        NowMessage::Capabilities(msg) => {
+            NowHeader::new_with_msg_type(MessageType::Capabilities, msg.encoded_len() as u32)
+        }
+        NowMessage::Channel(msg) => NowHeader::new_with_msg_type(MessageType::Channel, msg.encoded_len() as u32),
+        NowMessage::Activate(msg) => NowHeader::new_with_msg_type(MessageType::Activate, msg.encoded_len() as u32),
+        NowMessage::Terminate(msg) => NowHeader::new_with_msg_type(MessageType::Terminate, msg.encoded_len() as u32),
+        NowMessage::Input(msg) => NowHeader::new_with_msg_type(MessageType::Input, msg.encoded_len() as u32),
+        NowMessage::Mouse(msg) => NowHeader::new_with_msg_type(MessageType::Mouse, msg.encoded_len() as u32),
+        NowMessage::Network(msg) => NowHeader::new_with_msg_type(MessageType::Network, msg.encoded_len() as u32),
+        NowMessage::Surface(msg) => NowHeader::new_with_msg_type(MessageType::Surface, msg.encoded_len() as u32),
+        NowMessage::Update(msg) => NowHeader::new_with_msg_type(MessageType::Update, msg.encoded_len() as u32),
+        NowMessage::System(msg) => NowHeader::new_with_msg_type(MessageType::System, msg.encoded_len() as u32),
+        NowMessage::Sharing(msg) => NowHeader::new_with_msg_type(MessageType::Sharing, msg.encoded_len() as u32),
+        NowMessage::Access(msg) => NowHeader::new_with_msg_type(MessageType::Access, msg.encoded_len() as u32),
+        NowMessage::Desktop(msg) => NowHeader::new_with_msg_type(MessageType::Desktop, msg.encoded_len() as u32),
+        NowMessage::Session(msg) => NowHeader::new_with_msg_type(MessageType::Session, msg.encoded_len() as u32),
+        NowMessage::Custom { ty, payload } => NowHeader::new_with_msg_type(*ty, payload.len() as u32),
+        NowMessage::Registered(msg) => NowHeader::new_with_msg_type(msg.get_type(), msg.encoded_len() as u32),
+    }
+}
+
+/// A now packet borrowing its body.
+///
+/// Encodes like a [`NowPacket`](struct.NowPacket.html) without the message having to be moved or
+/// cloned in, the header being computed from the body when needed.
+#[derive(Debug, Clone, Copy)]
+pub struct NowPacketRef<'m, 'a> {
+    body: BodyRef<'m, 'a>,
+}
+
+sa::assert_impl_all!(NowPacketRef: Sync, Send);
+
+#[derive(Debug, Clone, Copy)]
+enum BodyRef<'m, 'a> {
+    Message(&'m NowMessage<'a>),
+    VirtualChannel(u8, &'m NowVirtualChannel<'a>),
+}
+
+impl<'m, 'a> NowPacketRef<'m, 'a> {
+    pub fn header(&self) -> NowHeader {
+        match self.body {
+            BodyRef::Message(message) => h_message_header(message),
+            BodyRef::VirtualChannel(channel_id, virt_channel) => {
+                NowHeader::new_with_virt_channel(channel_id, virt_channel.encoded_len() as u32)
+            }
+        }
+    }
+
+    /// See `NowPacket::packet_len`.
+    pub fn packet_len(&self) -> usize {
+        self.header().packet_len()
+    }
+
+    /// Clones the borrowed body into a `NowPacket`.
+    pub fn to_packet(&self) -> NowPacket<'a> {
+        match self.body {
+            BodyRef::Message(message) => NowPacket::from_message(message.clone()),
+            BodyRef::VirtualChannel(channel_id, virt_channel) => {
+                NowPacket::from_virt_channel(virt_channel.clone(), channel_id)
+            }
+        }
+    }
+}
+
+impl Encode for NowPacketRef<'_, '_> {
+    fn expected_size() -> crate::serialization::ExpectedSize
+    where
+        Self: Sized,
+    {
+        crate::serialization::ExpectedSize::Variable
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.packet_len()
+    }
+
+    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+        self.header().encode_into(writer)?;
+        match self.body {
+            BodyRef::Message(message) => message.encode_into(writer),
+            BodyRef::VirtualChannel(_, virt_channel) => virt_channel.encode_into(writer),
+        }
+    }
+}
+
+/// A now packet not borrowing any buffer.
+///
+/// See [`IntoOwned`](../message/trait.IntoOwned.html).
+pub type NowPacketOwned = NowPacket<'static>;
+
+/// A packet whose header is valid but whose body couldn't be decoded.
+///
+/// Reported by a lenient [`NowPacketAccumulator`](struct.NowPacketAccumulator.html)
+/// after the faulty body has been skipped.
+#[derive(Debug, Clone)]
+pub struct DecodeFailure {
+    pub header: NowHeader,
+    /// Raw bytes of the whole packet (header included).
+    pub raw: Vec<u8>,
+    pub error: ProtoError,
+}
+
+sa::assert_impl_all!(DecodeFailure: Sync, Send);
+
+/// Default for [`NowPacketAccumulator::set_max_packet_len`](struct.NowPacketAccumulator.html#method.set_max_packet_len).
+pub const DEFAULT_MAX_PACKET_LEN: usize = 16 * 1024 * 1024;
+
+/// Default for [`NowPacketAccumulator::set_max_decode_failures`](struct.NowPacketAccumulator.html#method.set_max_decode_failures).
+pub const DEFAULT_MAX_DECODE_FAILURES: usize = 64;
+
 /// Accumulate bytes to build into packets
+///
+/// Packets longer than `max_packet_len` are rejected as soon as their header is received
+/// so that a hostile peer can't make the accumulator buffer an arbitrary amount of data.
+///
+/// In lenient mode, a packet whose body fails to decode is discarded
+/// and recorded as a [`DecodeFailure`](struct.DecodeFailure.html) so that
+/// decoding can resume with the next packet. At most `max_decode_failures` are kept until they
+/// are taken, further ones are only counted.
+///
+/// Bodies of compressed virtual channels are decompressed, up to `max_packet_len` bytes, before
+/// being decoded. The header of such packets is then the one of the decompressed body, and they
+/// don't borrow the accumulator.
 #[derive(Debug, Clone)]
 pub struct NowPacketAccumulator<'a> {
     buffer: Vec<u8>,
     cursor: usize,
+    lenient: bool,
+    max_packet_len: usize,
+    decode_failures: Vec<DecodeFailure>,
+    max_decode_failures: usize,
+    decode_failures_dropped: u64,
+    packets_parsed: u64,
     _pd: PhantomData<&'a ()>,
 }
 
@@ -172,6 +350,12 @@ impl Default for NowPacketAccumulator<'_> {
         Self {
             buffer: Vec::new(),
             cursor: 0,
+            lenient: false,
+            max_packet_len: DEFAULT_MAX_PACKET_LEN,
+            decode_failures: Vec::new(),
+            max_decode_failures: DEFAULT_MAX_DECODE_FAILURES,
+            decode_failures_dropped: 0,
+            packets_parsed: 0,
             _pd: PhantomData,
         }
     }
@@ -182,6 +366,52 @@ impl NowPacketAccumulator<'_> {
         Self::default()
     }
 
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    pub fn is_lenient(&self) -> bool {
+        self.lenient
+    }
+
+    /// Maximum length of a packet, header included.
+    pub fn set_max_packet_len(&mut self, max_packet_len: usize) {
+        self.max_packet_len = max_packet_len;
+    }
+
+    pub fn max_packet_len(&self) -> usize {
+        self.max_packet_len
+    }
+
+    /// Number of bytes received but not yet returned as packets.
+    pub fn bytes_buffered(&self) -> usize {
+        self.buffer.len() - self.cursor
+    }
+
+    /// Number of packets returned so far, decode failures excluded.
+    pub fn packets_parsed(&self) -> u64 {
+        self.packets_parsed
+    }
+
+    /// Maximum number of decode failures kept until they are taken.
+    pub fn set_max_decode_failures(&mut self, max_decode_failures: usize) {
+        self.max_decode_failures = max_decode_failures;
+    }
+
+    pub fn max_decode_failures(&self) -> usize {
+        self.max_decode_failures
+    }
+
+    /// Number of decode failures dropped so far because `max_decode_failures` were already kept.
+    pub fn decode_failures_dropped(&self) -> u64 {
+        self.decode_failures_dropped
+    }
+
+    /// Takes the decode failures recorded in lenient mode since the last call.
+    pub fn take_decode_failures(&mut self) -> Vec<DecodeFailure> {
+        core::mem::take(&mut self.decode_failures)
+    }
+
     pub fn accumulate(&mut self, bytes: &[u8]) {
         self.buffer.extend_from_slice(bytes);
     }
@@ -191,36 +421,113 @@ impl NowPacketAccumulator<'_> {
         self.cursor = 0;
     }
 
+    /// Returns the next complete packet, if any.
+    ///
+    /// In lenient mode, packets failing to decode are skipped and
+    /// can be retrieved with [`take_decode_failures`](#method.take_decode_failures).
+    /// Header errors are always returned since the packet length is unknown, same goes for
+    /// packets longer than `max_packet_len`.
     pub fn next_packet<'a>(&'a mut self, channels_ctx: &VirtChannelsCtx) -> Option<Result<NowPacket<'a>>> {
-        if self.buffer.len() < self.cursor + NowLongHeader::SIZE {
-            return None;
-        }
+        self.next_packet_with_ctx(&DecodeCtx::default().with_channels(channels_ctx))
+    }
 
-        let header = match NowHeader::decode(&self.buffer[self.cursor..self.cursor + NowLongHeader::SIZE]) {
-            Ok(header) => header,
-            Err(err) => return Some(Err(err)),
-        };
+    /// Same as [`next_packet`](#method.next_packet), with a full decode context.
+    pub fn next_packet_with_ctx<'a>(&'a mut self, ctx: &DecodeCtx<'_>) -> Option<Result<NowPacket<'a>>> {
+        self.next_packet_with_raw(ctx).map(|res| res.map(|(packet, _)| packet))
+    }
 
-        let packet_len = header.body_len() + header.len();
-        if self.buffer.len() >= self.cursor + packet_len {
-            let header_len = header.len();
-            let packet = NowPacket::decode_from(
-                header,
-                &self.buffer[self.cursor + header_len..self.cursor + packet_len],
-                channels_ctx,
-            );
+    /// Same as [`next_packet_with_ctx`](#method.next_packet_with_ctx), along with the packet bytes
+    /// as received, before any decompression.
+    pub fn next_packet_with_raw<'a>(&'a mut self, ctx: &DecodeCtx<'_>) -> Option<Result<(NowPacket<'a>, &'a [u8])>> {
+        loop {
+            let header_len = NowHeader::header_len(&self.buffer[self.cursor..])?;
+            if self.buffer.len() < self.cursor + header_len {
+                return None;
+            }
+
+            let header = match NowHeader::decode(&self.buffer[self.cursor..self.cursor + header_len]) {
+                Ok(header) => header,
+                Err(err) => return Some(Err(err)),
+            };
+
+            let packet_len = header.body_len() + header.len();
+            if packet_len > self.max_packet_len {
+                return Some(Err(ProtoError::new(ProtoErrorKind::PacketTooLarge {
+                    len: packet_len,
+                    max: self.max_packet_len,
+                })
+                .with_desc(format!("{:?} packet rejected", header.body_type()))));
+            }
+
+            if self.buffer.len() < self.cursor + packet_len {
+                return None;
+            }
+
+            let packet_start = self.cursor;
             self.cursor += packet_len;
-            Some(packet)
-        } else {
-            None
+
+            let body = &self.buffer[packet_start + header_len..packet_start + packet_len];
+            let compression = match header.body_type() {
+                BodyType::VirtualChannel(id) => ctx.channels.get_compression(id),
+                BodyType::Message(_) => Compression::None,
+            };
+            let packet = decode_body(header.clone(), body, compression, self.max_packet_len, ctx);
+
+            match packet {
+                Err(error) if self.lenient => {
+                    log::warn!("skipped packet that couldn't be decoded: {}", error);
+                    if self.decode_failures.len() < self.max_decode_failures {
+                        self.decode_failures.push(DecodeFailure {
+                            header,
+                            raw: self.buffer[packet_start..packet_start + packet_len].to_vec(),
+                            error,
+                        });
+                    } else {
+                        self.decode_failures_dropped += 1;
+                    }
+                }
+                Ok(packet) => {
+                    self.packets_parsed += 1;
+                    return Some(Ok((packet, &self.buffer[packet_start..packet_start + packet_len])));
+                }
+                Err(error) => return Some(Err(error)),
+            }
         }
     }
+
+    /// Same as [`next_packet`](#method.next_packet), but the packet doesn't borrow the accumulator.
+    pub fn next_packet_owned(&mut self, channels_ctx: &VirtChannelsCtx) -> Option<Result<NowPacketOwned>> {
+        self.next_packet(channels_ctx)
+            .map(|packet| packet.map(IntoOwned::into_owned))
+    }
+}
+
+/// Decodes a packet body, decompressing it first when the channel requires it.
+///
+/// Decompressed packets are returned owned: borrowing a buffer of the accumulator would keep it
+/// locked while the lenient loop moves on to the next packet.
+fn decode_body<'a>(
+    header: NowHeader,
+    body: &'a [u8],
+    compression: Compression,
+    max_packet_len: usize,
+    ctx: &DecodeCtx<'_>,
+) -> Result<NowPacket<'a>> {
+    if compression == Compression::None {
+        return NowPacket::decode_with_ctx(header, body, ctx);
+    }
+
+    let decompressed = compression.decompress(body, max_packet_len)?;
+    let header = NowHeader::new(header.body_type(), decompressed.len() as u32);
+    NowPacket::decode_with_ctx(header, &decompressed, ctx).map(IntoOwned::into_owned)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::message::{AuthType, NowBody, VirtChannelsCtx};
+    use crate::message::{AuthType, ChannelName, CustomVirtualChannel, NowBody, VirtChannelsCtx};
+    use crate::serialization::DecodeLimits;
+    use alloc::borrow::Cow;
 
     #[rustfmt::skip]
     const NEGOTIATE_PACKET: [u8; 11] = [
@@ -262,10 +569,7 @@ mod tests {
                 },
                 NowBody::VirtualChannel(_) => panic!("decoded a virtual channel message from a negotiate packet"),
             },
-            Err(e) => {
-                e.print_trace();
-                panic!("couldn't decode negotiate packet");
-            }
+            Err(e) => panic!("couldn't decode negotiate packet: {:#}", e),
         }
 
         assert!(acc.next_packet(&chan_ctx).is_none());
@@ -275,6 +579,132 @@ mod tests {
         assert_eq!(acc.buffer.len(), 0);
     }
 
+    #[test]
+    fn owned_packet_outlives_accumulator() {
+        let owned = {
+            let mut acc = NowPacketAccumulator::new();
+            acc.accumulate(&NEGOTIATE_PACKET);
+            acc.next_packet_owned(&VirtChannelsCtx::new()).unwrap().unwrap()
+        };
+
+        assert_eq!(owned.encode().unwrap(), NEGOTIATE_PACKET.to_vec());
+        match owned.body {
+            NowBody::Message(NowMessage::Negotiate(msg)) => assert_eq!(msg.auth_list.len(), 2),
+            body => panic!("expected a negotiate message and got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn ctx_limits_apply_to_body() {
+        let limits = |max_items| DecodeLimits {
+            max_items,
+            max_bytes: DecodeLimits::DEFAULT.max_bytes,
+        };
+
+        let mut acc = NowPacketAccumulator::new();
+        acc.accumulate(&NEGOTIATE_PACKET);
+        let ctx = DecodeCtx::default().with_limits(limits(1));
+        assert!(acc.next_packet_with_ctx(&ctx).unwrap().is_err());
+
+        acc.accumulate(&NEGOTIATE_PACKET);
+        let ctx = DecodeCtx::default().with_limits(limits(2));
+        assert!(acc.next_packet_with_ctx(&ctx).unwrap().is_ok());
+    }
+
+    #[test]
+    fn packet_ref_encodes_like_packet() {
+        let payload = vec![0x42; 70_000];
+        let message = NowMessage::Custom {
+            ty: MessageType::Update,
+            payload: Cow::Borrowed(&payload),
+        };
+        let packet_ref = NowPacket::from_message_ref(&message);
+        assert!(!packet_ref.header().is_short());
+        assert_eq!(packet_ref.packet_len(), packet_ref.encode().unwrap().len());
+        assert_eq!(
+            packet_ref.encode().unwrap(),
+            NowPacket::from_message(message.clone()).encode().unwrap()
+        );
+
+        let virt_channel = NowVirtualChannel::Custom(CustomVirtualChannel {
+            name: ChannelName::FileTransfer,
+            payload: Cow::Borrowed(&payload[..16]),
+        });
+        let packet_ref = NowPacket::from_virt_channel_ref(&virt_channel, 5);
+        assert_eq!(packet_ref.header().body_type(), BodyType::VirtualChannel(5));
+        assert_eq!(packet_ref.encode().unwrap(), packet_ref.to_packet().encode().unwrap());
+    }
+
+    #[rustfmt::skip]
+    const TRUNCATED_NEGOTIATE_PACKET: [u8; 6] = [
+        // vheader
+        0x02, 0x00, // size
+        0x02, // subtye
+        0x80, // flags
+
+        // negotiate (truncated flags)
+        0x01, 0x00,
+    ];
+
+    #[test]
+    fn lenient_accumulator_skips_invalid_body() {
+        let chan_ctx = VirtChannelsCtx::new();
+
+        let mut acc = NowPacketAccumulator::new();
+        acc.set_lenient(true);
+        acc.accumulate(&TRUNCATED_NEGOTIATE_PACKET);
+        acc.accumulate(&NEGOTIATE_PACKET);
+
+        match acc.next_packet(&chan_ctx) {
+            Some(Ok(packet)) => match packet.body {
+                NowBody::Message(NowMessage::Negotiate(msg)) => assert_eq!(msg.auth_list.len(), 2),
+                _ => panic!("decoded wrong packet after skipping invalid body"),
+            },
+            Some(Err(e)) => panic!("lenient accumulator returned an error: {}", e),
+            None => panic!("no packet returned"),
+        }
+        assert!(acc.next_packet(&chan_ctx).is_none());
+
+        let failures = acc.take_decode_failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].header.body_len(), 2);
+        assert_eq!(failures[0].raw, TRUNCATED_NEGOTIATE_PACKET.to_vec());
+        assert!(acc.take_decode_failures().is_empty());
+    }
+
+    #[test]
+    fn lenient_accumulator_caps_decode_failures() {
+        let chan_ctx = VirtChannelsCtx::new();
+
+        let mut acc = NowPacketAccumulator::new();
+        acc.set_lenient(true);
+        acc.set_max_decode_failures(2);
+        for _ in 0..5 {
+            acc.accumulate(&TRUNCATED_NEGOTIATE_PACKET);
+        }
+        assert!(acc.next_packet(&chan_ctx).is_none());
+        assert_eq!(acc.take_decode_failures().len(), 2);
+        assert_eq!(acc.decode_failures_dropped(), 3);
+
+        acc.accumulate(&TRUNCATED_NEGOTIATE_PACKET);
+        assert!(acc.next_packet(&chan_ctx).is_none());
+        assert_eq!(acc.take_decode_failures().len(), 1);
+        assert_eq!(acc.decode_failures_dropped(), 3);
+    }
+
+    #[test]
+    fn strict_accumulator_returns_body_error() {
+        let chan_ctx = VirtChannelsCtx::new();
+
+        let mut acc = NowPacketAccumulator::new();
+        acc.accumulate(&TRUNCATED_NEGOTIATE_PACKET);
+        acc.accumulate(&NEGOTIATE_PACKET);
+
+        assert!(matches!(acc.next_packet(&chan_ctx), Some(Err(_))));
+        assert!(matches!(acc.next_packet(&chan_ctx), Some(Ok(_))));
+        assert!(acc.take_decode_failures().is_empty());
+    }
+
     #[rustfmt::skip]
     const CUSTOM_MESSAGE: [u8; 8] = [
         // vheader
@@ -295,15 +725,128 @@ mod tests {
             Ok(packet) => match packet.body {
                 NowBody::Message(NowMessage::Custom { ty: msg_type, payload }) => {
                     assert_eq!(u8::from(msg_type), 0xA7);
-                    assert_eq!(payload, &[0x01, 0x02, 0x03, 0x04]);
+                    assert_eq!(*payload, [0x01, 0x02, 0x03, 0x04]);
                 }
                 NowBody::Message(_) => panic!("decoded wrong now message from custom packet"),
                 NowBody::VirtualChannel(_) => panic!("decoded a virtual channel message from a custom packet"),
             },
-            Err(e) => {
-                e.print_trace();
-                panic!("couldn't decode custom packet");
-            }
+            Err(e) => panic!("couldn't decode custom packet: {:#}", e),
+        }
+    }
+
+    #[test]
+    fn accumulator_packet_smaller_than_long_header() {
+        // empty custom message, whole packet is shorter than a long header
+        let packet = [0x00, 0x00, 0xA7, 0x80];
+        let mut acc = NowPacketAccumulator::new();
+        acc.accumulate(&packet[..3]);
+        assert!(acc.next_packet(&VirtChannelsCtx::new()).is_none());
+
+        acc.accumulate(&packet[3..]);
+        match acc.next_packet(&VirtChannelsCtx::new()).unwrap().unwrap().body {
+            NowBody::Message(NowMessage::Custom { payload, .. }) => assert!(payload.is_empty()),
+            body => panic!("expected an empty custom message and got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn accumulator_rejects_oversize_packet() {
+        let chan_ctx = VirtChannelsCtx::new();
+
+        let mut acc = NowPacketAccumulator::new();
+        acc.set_lenient(true);
+        acc.accumulate(&NEGOTIATE_PACKET);
+        assert!(acc.next_packet(&chan_ctx).unwrap().is_ok());
+        assert_eq!(acc.packets_parsed(), 1);
+        assert_eq!(acc.bytes_buffered(), 0);
+
+        acc.set_max_packet_len(NEGOTIATE_PACKET.len() - 1);
+        // header alone is enough to reject the packet
+        acc.accumulate(&NEGOTIATE_PACKET[..4]);
+        assert_eq!(acc.bytes_buffered(), 4);
+        match acc.next_packet(&chan_ctx) {
+            Some(Err(e)) => assert!(matches!(e.kind, ProtoErrorKind::PacketTooLarge { len: 11, max: 10 })),
+            _ => panic!("oversize packet wasn't rejected"),
+        }
+        assert_eq!(acc.packets_parsed(), 1);
+        assert!(acc.take_decode_failures().is_empty());
+    }
+
+    #[rustfmt::skip]
+    const NEGOTIATE_PACKET_WITH_TRAILING_BYTES: [u8; 13] = [
+        // vheader
+        0x09, 0x00, // size
+        0x02, // subtye
+        0x80, // flags
+
+        // negotiate
+        0x01, 0x00, 0x00, 0x00, // flags
+        0x02, // count available auths
+        0x02, // SRP
+        0x01, // PFP
+
+        // trailing bytes
+        0xFF, 0xFF,
+    ];
+
+    #[test]
+    fn body_length_mismatch() {
+        let header = NowHeader::decode(&NEGOTIATE_PACKET_WITH_TRAILING_BYTES).unwrap();
+        let body = &NEGOTIATE_PACKET_WITH_TRAILING_BYTES[header.len()..];
+
+        let err = NowPacket::decode_with_ctx(header.clone(), body, &DecodeCtx::default()).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            ProtoErrorKind::LengthMismatch { expected: 9, actual: 7 }
+        ));
+
+        let lenient = DecodeCtx::default().with_trailing_bytes_allowed(true);
+        let packet = NowPacket::decode_with_ctx(header.clone(), body, &lenient).unwrap();
+        assert!(matches!(packet.body, NowBody::Message(NowMessage::Negotiate(_))));
+
+        let err = NowPacket::decode_with_ctx(header, &body[..5], &lenient).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            ProtoErrorKind::LengthMismatch { expected: 9, actual: 5 }
+        ));
+    }
+
+    #[test]
+    fn encode_without_allocating() {
+        let mut acc = NowPacketAccumulator::new();
+        acc.accumulate(&NEGOTIATE_PACKET);
+        let packet = acc.next_packet(&VirtChannelsCtx::new()).unwrap().unwrap();
+
+        let mut buf = [0xFF; 16];
+        assert_eq!(packet.encode_to_slice(&mut buf).unwrap(), NEGOTIATE_PACKET.len());
+        assert_eq!(&buf[..NEGOTIATE_PACKET.len()], &NEGOTIATE_PACKET[..]);
+
+        let mut too_small = [0xFF; 10];
+        let err = packet.encode_to_slice(&mut too_small).unwrap_err();
+        assert!(matches!(err.kind, ProtoErrorKind::Encoding(_)));
+        assert_eq!(too_small, [0xFF; 10]);
+
+        let mut scratch = vec![0xAA; 32];
+        assert_eq!(packet.encode_with_scratch(&mut scratch).unwrap(), &NEGOTIATE_PACKET[..]);
+        assert_eq!(packet.encode_with_scratch(&mut scratch).unwrap(), &NEGOTIATE_PACKET[..]);
+        assert!(scratch.capacity() >= 32);
+    }
+
+    #[test]
+    fn packet_len_before_encoding() {
+        let payload = vec![0x2a; usize::from(crate::header::NowShortHeader::MAX_BODY_LEN) + 1];
+        for len in [8, payload.len()] {
+            let packet = NowPacket::from_message(NowMessage::Custom {
+                ty: MessageType::Other(0x7f),
+                payload: Cow::Borrowed(&payload[..len]),
+            });
+            assert_eq!(packet.packet_len(), NowHeader::len_for_body(len) + len);
+            assert_eq!(packet.packet_len(), packet.encode().unwrap().len());
         }
+        assert_eq!(NowPacket::expected_size(), crate::serialization::ExpectedSize::Variable);
+        assert_eq!(
+            NowHeader::len_for_body(payload.len()),
+            crate::header::NowLongHeader::SIZE
+        );
     }
 }
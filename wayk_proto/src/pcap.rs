@@ -0,0 +1,180 @@
+//! PCAPNG export.
+//!
+//! Writes Wayk Now packets into a pcapng stream so sessions can be inspected with standard
+//! tooling. Packets are stored raw (Wayk header included) on a single interface using the
+//! `LINKTYPE_USER0` link type, a Wireshark dissector can be bound to it through the
+//! "DLT_USER" preferences.
+
+use crate::error::Result;
+use crate::io::NoStdWrite;
+use crate::packet::NowPacket;
+use crate::serialization::Encode;
+use crate::trace::{PacketDirection, PacketReplayer};
+
+/// `LINKTYPE_USER0`
+pub const WAYK_LINK_TYPE: u16 = 147;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_EPB_FLAGS: u16 = 2;
+const EPB_FLAGS_INBOUND: u32 = 0b01;
+const EPB_FLAGS_OUTBOUND: u32 = 0b10;
+
+fn padding(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+pub struct PcapngWriter<W: NoStdWrite> {
+    writer: W,
+}
+
+impl<W: NoStdWrite> PcapngWriter<W> {
+    /// Writes the section header and interface description blocks.
+    pub fn new(mut writer: W) -> Result<Self> {
+        // section header block
+        let block_len = 28u32;
+        writer.write_u32(SECTION_HEADER_BLOCK)?;
+        writer.write_u32(block_len)?;
+        writer.write_u32(BYTE_ORDER_MAGIC)?;
+        writer.write_u16(1)?; // major version
+        writer.write_u16(0)?; // minor version
+        writer.write_i64(-1)?; // section length is unspecified
+        writer.write_u32(block_len)?;
+
+        // interface description block (timestamps are in microseconds by default)
+        let block_len = 20u32;
+        writer.write_u32(INTERFACE_DESCRIPTION_BLOCK)?;
+        writer.write_u32(block_len)?;
+        writer.write_u16(WAYK_LINK_TYPE)?;
+        writer.write_u16(0)?; // reserved
+        writer.write_u32(0)?; // no snap length limit
+        writer.write_u32(block_len)?;
+
+        Ok(Self { writer })
+    }
+
+    /// Writes an already encoded packet. `timestamp` is in microseconds since the UNIX epoch.
+    pub fn write_raw(&mut self, direction: PacketDirection, timestamp: u64, packet: &[u8]) -> Result<()> {
+        let padding_len = padding(packet.len());
+        let flags = match direction {
+            PacketDirection::Received => Some(EPB_FLAGS_INBOUND),
+            PacketDirection::Sent => Some(EPB_FLAGS_OUTBOUND),
+            PacketDirection::Other(_) => None,
+        };
+        let options_len = if flags.is_some() { 12 } else { 0 };
+        let block_len = (32 + packet.len() + padding_len + options_len) as u32;
+
+        self.writer.write_u32(ENHANCED_PACKET_BLOCK)?;
+        self.writer.write_u32(block_len)?;
+        self.writer.write_u32(0)?; // interface id
+        self.writer.write_u32((timestamp >> 32) as u32)?;
+        self.writer.write_u32(timestamp as u32)?;
+        self.writer.write_u32(packet.len() as u32)?; // captured length
+        self.writer.write_u32(packet.len() as u32)?; // original length
+        self.writer.write_all(packet)?;
+        self.writer.write_all(&[0u8; 3][..padding_len])?;
+        if let Some(flags) = flags {
+            self.writer.write_u16(OPT_EPB_FLAGS)?;
+            self.writer.write_u16(4)?;
+            self.writer.write_u32(flags)?;
+            self.writer.write_u16(OPT_END_OF_OPT)?;
+            self.writer.write_u16(0)?;
+        }
+        self.writer.write_u32(block_len)?;
+
+        Ok(())
+    }
+
+    pub fn write_packet(&mut self, direction: PacketDirection, timestamp: u64, packet: &NowPacket<'_>) -> Result<()> {
+        let bytes = packet.encode()?;
+        self.write_raw(direction, timestamp, &bytes)
+    }
+
+    /// Exports a whole recorded trace (trace timestamps are in milliseconds).
+    pub fn write_trace(&mut self, trace: &PacketReplayer<'_>) -> Result<()> {
+        for record in trace.records() {
+            self.write_raw(record.direction, record.timestamp * 1000, &record.packet)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::NowActivateMsg;
+    use alloc::vec::Vec;
+
+    #[rustfmt::skip]
+    const PCAPNG_HEADER: [u8; 48] = [
+        // section header block
+        0x0a, 0x0d, 0x0d, 0x0a, // block type
+        0x1c, 0x00, 0x00, 0x00, // block length
+        0x4d, 0x3c, 0x2b, 0x1a, // byte-order magic
+        0x01, 0x00, // major version
+        0x00, 0x00, // minor version
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // section length
+        0x1c, 0x00, 0x00, 0x00, // block length
+
+        // interface description block
+        0x01, 0x00, 0x00, 0x00, // block type
+        0x14, 0x00, 0x00, 0x00, // block length
+        0x93, 0x00, // link type
+        0x00, 0x00, // reserved
+        0x00, 0x00, 0x00, 0x00, // snap length
+        0x14, 0x00, 0x00, 0x00, // block length
+    ];
+
+    #[rustfmt::skip]
+    const ACTIVATE_EPB: [u8; 52] = [
+        0x06, 0x00, 0x00, 0x00, // block type
+        0x34, 0x00, 0x00, 0x00, // block length
+        0x00, 0x00, 0x00, 0x00, // interface id
+        0x00, 0x00, 0x00, 0x00, // timestamp (high)
+        0x40, 0x42, 0x0f, 0x00, // timestamp (low)
+        0x08, 0x00, 0x00, 0x00, // captured length
+        0x08, 0x00, 0x00, 0x00, // original length
+        0x04, 0x00, 0x07, 0x80, 0x00, 0x00, 0x00, 0x00, // activate packet
+        0x02, 0x00, 0x04, 0x00, 0x02, 0x00, 0x00, 0x00, // epb_flags (outbound)
+        0x00, 0x00, 0x00, 0x00, // end of options
+        0x34, 0x00, 0x00, 0x00, // block length
+    ];
+
+    #[test]
+    fn pcapng_layout() {
+        let mut pcap = PcapngWriter::new(Vec::new()).unwrap();
+        pcap.write_packet(
+            PacketDirection::Sent,
+            1_000_000,
+            &NowPacket::from_message(NowActivateMsg::default()),
+        )
+        .unwrap();
+        let bytes = pcap.into_inner();
+        assert_eq!(&bytes[..48], &PCAPNG_HEADER[..]);
+        assert_eq!(&bytes[48..], &ACTIVATE_EPB[..]);
+    }
+
+    #[test]
+    fn pcapng_pads_packet_data() {
+        let mut pcap = PcapngWriter::new(Vec::new()).unwrap();
+        pcap.write_raw(PacketDirection::Other(0), 0, &[0xAB; 5]).unwrap();
+        let bytes = pcap.into_inner();
+        let block = &bytes[48..];
+        assert_eq!(block.len(), 40);
+        assert_eq!(&block[4..8], &[40, 0, 0, 0]);
+        assert_eq!(&block[28..36], &[0xAB, 0xAB, 0xAB, 0xAB, 0xAB, 0x00, 0x00, 0x00]);
+    }
+}
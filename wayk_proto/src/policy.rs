@@ -0,0 +1,280 @@
+//! Rules applied to received messages.
+//!
+//! A [`Policy`](struct.Policy.html) evaluates its rules, in registration order, on every message
+//! received by a sharee before any state machine processes it. A rule allows the message, denies
+//! it or replaces it with a modified one, seen by the rules registered after it. Denied messages
+//! are dropped and reported with a [`PolicyAudit`](struct.PolicyAudit.html) data event.
+//!
+//! Policies are packet taps (see the [`tap`](../tap/index.html) module) registered with
+//! `ShareeBuilder::policy`.
+//!
+//! ```
+//! use wayk_proto::message::{MessageType, NowTerminateMsg};
+//! use wayk_proto::packet::NowPacket;
+//! use wayk_proto::policy::{self, Policy, PolicyAudit};
+//! use wayk_proto::sharee::{Sharee, ShareeState};
+//! use wayk_proto::sm::DummyConnectionSM;
+//!
+//! let mut sharee = Sharee::builder(DummyConnectionSM)
+//!     .policy(
+//!         Policy::new()
+//!             .rule("no remote input", policy::deny_message(MessageType::Input))
+//!             .rule("no remote terminate", policy::deny_message(MessageType::Terminate)),
+//!     )
+//!     .build_unchecked();
+//! sharee.update_without_body();
+//! let events = sharee.update_with_packet(&NowPacket::from_message(NowTerminateMsg::default()));
+//! let audit = events[0].downcast_data::<PolicyAudit>().unwrap();
+//! assert_eq!(audit.rule, "no remote terminate");
+//! assert_eq!(sharee.get_state(), ShareeState::Active);
+//! ```
+
+use crate::header::AbstractNowHeader;
+use crate::message::{
+    BodyType, ChannelName, MessageType, NowBody, NowClipboardFormatListReqMsg, NowClipboardMsg, NowVirtualChannel,
+};
+use crate::packet::NowPacket;
+use crate::sm::formats::StandardFormat;
+use crate::sm::ProtoData;
+use crate::sync::SharedQueue;
+use crate::tap::{PacketTap, TapVerdict, TappedPacket};
+use crate::trace::PacketDirection;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Outcome of a rule for a received message.
+#[derive(Debug, Clone)]
+pub enum PolicyOutcome {
+    Allow,
+    /// The message is dropped and audited, rules registered after this one aren't evaluated.
+    Deny,
+    /// The message is processed in place of the received one.
+    Modify(NowBody<'static>),
+}
+
+pub trait PolicyRule: Send {
+    fn evaluate(&mut self, body: &NowBody<'_>) -> PolicyOutcome;
+}
+
+impl<F> PolicyRule for F
+where
+    F: FnMut(&NowBody<'_>) -> PolicyOutcome + Send,
+{
+    fn evaluate(&mut self, body: &NowBody<'_>) -> PolicyOutcome {
+        self(body)
+    }
+}
+
+/// A received message was denied, emitted as a data event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyAudit {
+    /// Name the denying rule was registered with.
+    pub rule: String,
+    pub body_type: BodyType,
+    /// Channel of denied virtual channel messages.
+    pub channel: Option<ChannelName>,
+}
+
+impl ProtoData for PolicyAudit {}
+
+#[derive(Default)]
+pub struct Policy {
+    rules: Vec<(String, Box<dyn PolicyRule>)>,
+    /// Set once registered with a sharee, denials are only logged otherwise.
+    audits: Option<SharedQueue<PolicyAudit>>,
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rule<R: PolicyRule + 'static>(mut self, name: &str, rule: R) -> Self {
+        self.rules.push((String::from(name), Box::new(rule)));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub(crate) fn set_audits(&mut self, audits: SharedQueue<PolicyAudit>) {
+        self.audits = Some(audits);
+    }
+
+    fn h_deny(&self, rule: &str, packet: &TappedPacket<'_, '_>) {
+        let channel = match packet.body {
+            NowBody::Message(_) => None,
+            NowBody::VirtualChannel(chan_msg) => Some(chan_msg.get_name().clone()),
+        };
+        log::info!(
+            "{:?} message denied by policy rule {:?}",
+            packet.header.body_type(),
+            rule
+        );
+        if let Some(audits) = &self.audits {
+            audits.push(PolicyAudit {
+                rule: String::from(rule),
+                body_type: packet.header.body_type(),
+                channel,
+            });
+        }
+    }
+}
+
+impl PacketTap for Policy {
+    fn on_packet(&mut self, direction: PacketDirection, packet: &TappedPacket<'_, '_>) -> TapVerdict {
+        if direction != PacketDirection::Received {
+            return TapVerdict::Pass;
+        }
+
+        let mut modified: Option<NowPacket<'static>> = None;
+        for idx in 0..self.rules.len() {
+            let current = modified.as_ref().map_or_else(|| packet.clone(), TappedPacket::from);
+            match self.rules[idx].1.evaluate(current.body) {
+                PolicyOutcome::Allow => {}
+                PolicyOutcome::Deny => {
+                    self.h_deny(&self.rules[idx].0, &current);
+                    return TapVerdict::Drop;
+                }
+                PolicyOutcome::Modify(body) => {
+                    modified = Some(match body {
+                        NowBody::Message(msg) => NowPacket::from_message(msg),
+                        NowBody::VirtualChannel(chan_msg) => {
+                            let channel_id = match current.header.body_type() {
+                                BodyType::VirtualChannel(id) => id,
+                                BodyType::Message(_) => 0,
+                            };
+                            NowPacket::from_virt_channel(chan_msg, channel_id)
+                        }
+                    })
+                }
+            }
+        }
+        modified.map_or(TapVerdict::Pass, TapVerdict::Replace)
+    }
+}
+
+/// Denies every message of this type.
+pub fn deny_message(msg_type: MessageType) -> impl PolicyRule {
+    move |body: &NowBody<'_>| match body {
+        NowBody::Message(msg) if msg.get_type() == msg_type => PolicyOutcome::Deny,
+        _ => PolicyOutcome::Allow,
+    }
+}
+
+/// Denies every message on this virtual channel.
+pub fn deny_channel(name: ChannelName) -> impl PolicyRule {
+    move |body: &NowBody<'_>| match body {
+        NowBody::VirtualChannel(chan_msg) if *chan_msg.get_name() == name => PolicyOutcome::Deny,
+        _ => PolicyOutcome::Allow,
+    }
+}
+
+/// Removes formats other than text from received clipboard format lists, so that only text is
+/// ever requested from the peer.
+pub fn clipboard_text_only() -> impl PolicyRule {
+    |body: &NowBody<'_>| match body {
+        NowBody::VirtualChannel(NowVirtualChannel::Clipboard(NowClipboardMsg::FormatListReq(msg))) => {
+            let text: Vec<_> = msg
+                .formats
+                .0
+                .iter()
+                .filter(|def| {
+                    matches!(
+                        StandardFormat::from_name(def.name.as_str()),
+                        Some(StandardFormat::Utf8String | StandardFormat::UnicodeText)
+                    )
+                })
+                .cloned()
+                .collect();
+            if text.len() == msg.formats.0.len() {
+                PolicyOutcome::Allow
+            } else {
+                let filtered = NowClipboardFormatListReqMsg::new_with_formats(msg.sequence_id, text);
+                PolicyOutcome::Modify(NowBody::VirtualChannel(NowVirtualChannel::Clipboard(
+                    NowClipboardMsg::FormatListReq(filtered),
+                )))
+            }
+        }
+        _ => PolicyOutcome::Allow,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{ClipboardFormatDef, NowString256};
+
+    #[test]
+    fn rules() {
+        let mut policy = Policy::new()
+            .rule("text only", clipboard_text_only())
+            .rule("no input", deny_message(MessageType::Input))
+            .rule("no empty format list", |body: &NowBody<'_>| match body {
+                NowBody::VirtualChannel(NowVirtualChannel::Clipboard(NowClipboardMsg::FormatListReq(msg)))
+                    if msg.formats.0.is_empty() =>
+                {
+                    PolicyOutcome::Deny
+                }
+                _ => PolicyOutcome::Allow,
+            });
+        let audits = SharedQueue::default();
+        policy.set_audits(audits.clone());
+        assert_eq!(policy.len(), 3);
+
+        let format_list = |formats| {
+            NowPacket::from_virt_channel(
+                NowClipboardMsg::FormatListReq(NowClipboardFormatListReqMsg::new_with_formats(4, formats)),
+                2,
+            )
+        };
+        let png = ClipboardFormatDef::new(0xC002, NowString256::from_str_lossy("image/png"));
+        let packet = format_list(vec![StandardFormat::UnicodeText.def(), png.clone()]);
+        match policy.on_packet(PacketDirection::Received, &(&packet).into()) {
+            TapVerdict::Replace(packet) => {
+                assert_eq!(packet.header.body_type(), BodyType::VirtualChannel(2));
+                match packet.body {
+                    NowBody::VirtualChannel(NowVirtualChannel::Clipboard(NowClipboardMsg::FormatListReq(msg))) => {
+                        assert_eq!(msg.sequence_id, 4);
+                        assert_eq!(msg.formats.0.len(), 1);
+                        assert_eq!(msg.formats.0[0].name, "CF_UNICODETEXT");
+                    }
+                    body => panic!("unexpected {:?}", body),
+                }
+            }
+            verdict => panic!("unexpected {:?}", verdict),
+        }
+        assert!(matches!(
+            policy.on_packet(
+                PacketDirection::Received,
+                &(&format_list(vec![StandardFormat::Utf8String.def()])).into()
+            ),
+            TapVerdict::Pass
+        ));
+        assert!(matches!(
+            policy.on_packet(PacketDirection::Sent, &(&format_list(vec![png.clone()])).into()),
+            TapVerdict::Pass
+        ));
+        assert!(audits.is_empty());
+
+        // later rules see the modified message
+        assert!(matches!(
+            policy.on_packet(PacketDirection::Received, &(&format_list(vec![png])).into()),
+            TapVerdict::Drop
+        ));
+        assert_eq!(
+            audits.pop(),
+            Some(PolicyAudit {
+                rule: String::from("no empty format list"),
+                body_type: BodyType::VirtualChannel(2),
+                channel: Some(ChannelName::Clipboard),
+            })
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Typed custom messages.
+//!
+//! Message types unknown to this crate are decoded as `NowMessage::Custom`, raw bytes the
+//! application has to parse by itself. Proprietary messages can instead be described by a type
+//! implementing [`CustomMessage`](trait.CustomMessage.html) and registered in a
+//! [`MessageRegistry`](struct.MessageRegistry.html): decoding with a `DecodeCtx` carrying the
+//! registry then yields `NowMessage::Registered`, and typed messages are sent the same way.
+//!
+//! ```
+//! use wayk_proto::header::{AbstractNowHeader, NowHeader};
+//! use wayk_proto::message::{BodyType, MessageType, NowBody, NowMessage};
+//! use wayk_proto::packet::NowPacket;
+//! use wayk_proto::registry::{CustomMessage, MessageRegistry, RegisteredMessage};
+//! use wayk_proto::serialization::{Decode, DecodeCtx, Encode};
+//! use wayk_proto_derive::{Decode, Encode};
+//!
+//! #[derive(Encode, Decode, Debug, Clone, PartialEq)]
+//! struct InkStroke {
+//!     x: u16,
+//!     y: u16,
+//! }
+//!
+//! impl CustomMessage for InkStroke {
+//!     const MESSAGE_TYPE: u8 = 0x80;
+//! }
+//!
+//! let registry = MessageRegistry::new().with_message::<InkStroke>()?;
+//!
+//! let packet = NowPacket::from_message(RegisteredMessage::new(InkStroke { x: 1, y: 2 }));
+//! let bytes = packet.encode()?;
+//!
+//! let header = NowHeader::decode(&bytes)?;
+//! assert_eq!(header.body_type(), BodyType::Message(MessageType::Other(0x80)));
+//! let header_len = header.len();
+//! let ctx = DecodeCtx::default().with_registry(&registry);
+//! let packet = NowPacket::decode_with_ctx(header, &bytes[header_len..], &ctx)?;
+//! match packet.body {
+//!     NowBody::Message(NowMessage::Registered(msg)) => {
+//!         assert_eq!(msg.downcast_ref(), Some(&InkStroke { x: 1, y: 2 }))
+//!     }
+//!     _ => unreachable!(),
+//! }
+//! # Ok::<(), wayk_proto::error::ProtoError>(())
+//! ```
+
+use crate::error::{ProtoError, ProtoErrorKind, Result};
+use crate::io::{Cursor, NoStdWrite};
+use crate::message::MessageType;
+use crate::serialization::{Decode, Encode, ExpectedSize};
+use crate::sm::ProtoData;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt;
+
+/// Message carried with a message type this crate doesn't know about.
+pub trait CustomMessage: Encode + for<'dec> Decode<'dec> + fmt::Debug + Clone + Send + Sync + 'static {
+    /// Value of the `MessageType::Other` this message is sent with.
+    const MESSAGE_TYPE: u8;
+}
+
+/// Object safe part of `CustomMessage`.
+trait DynMessage: Any + fmt::Debug + Send + Sync {
+    fn encoded_len(&self) -> usize;
+
+    fn encode_to_vec(&self, buf: &mut Vec<u8>) -> Result<()>;
+
+    fn clone_box(&self) -> Box<dyn DynMessage>;
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: CustomMessage> DynMessage for T {
+    fn encoded_len(&self) -> usize {
+        Encode::encoded_len(self)
+    }
+
+    fn encode_to_vec(&self, buf: &mut Vec<u8>) -> Result<()> {
+        self.encode_into(buf)
+    }
+
+    fn clone_box(&self) -> Box<dyn DynMessage> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Custom message decoded through a `MessageRegistry`, or built to be sent.
+pub struct RegisteredMessage {
+    ty: u8,
+    msg: Box<dyn DynMessage>,
+}
+
+impl RegisteredMessage {
+    pub fn new<T: CustomMessage>(msg: T) -> Self {
+        Self {
+            ty: T::MESSAGE_TYPE,
+            msg: Box::new(msg),
+        }
+    }
+
+    pub fn get_type(&self) -> MessageType {
+        MessageType::Other(self.ty)
+    }
+
+    pub fn downcast_ref<T: CustomMessage>(&self) -> Option<&T> {
+        self.msg.as_any().downcast_ref()
+    }
+
+    pub fn is<T: CustomMessage>(&self) -> bool {
+        self.msg.as_any().is::<T>()
+    }
+}
+
+impl Clone for RegisteredMessage {
+    fn clone(&self) -> Self {
+        Self {
+            ty: self.ty,
+            msg: self.msg.clone_box(),
+        }
+    }
+}
+
+// custom messages aren't required to be comparable, compare their encoding instead
+impl PartialEq for RegisteredMessage {
+    fn eq(&self, other: &Self) -> bool {
+        let encode = |msg: &Self| {
+            let mut buf = Vec::with_capacity(msg.msg.encoded_len());
+            msg.msg.encode_to_vec(&mut buf).map(|_| buf).ok()
+        };
+        self.ty == other.ty && matches!((encode(self), encode(other)), (Some(a), Some(b)) if a == b)
+    }
+}
+
+impl fmt::Debug for RegisteredMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RegisteredMessage").field(&self.msg).finish()
+    }
+}
+
+impl ProtoData for RegisteredMessage {}
+
+impl Encode for RegisteredMessage {
+    fn expected_size() -> ExpectedSize
+    where
+        Self: Sized,
+    {
+        ExpectedSize::Variable
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.msg.encoded_len()
+    }
+
+    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+        let mut buf = Vec::with_capacity(self.msg.encoded_len());
+        self.msg.encode_to_vec(&mut buf)?;
+        writer.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+type DecodeFn = fn(&mut Cursor<'_>) -> Result<RegisteredMessage>;
+
+fn h_decode<T: CustomMessage>(cursor: &mut Cursor<'_>) -> Result<RegisteredMessage> {
+    T::decode_from(cursor).map(RegisteredMessage::new)
+}
+
+/// Decoders for custom message types, see the [module documentation](index.html).
+#[derive(Clone, Default)]
+pub struct MessageRegistry {
+    decoders: BTreeMap<u8, DecodeFn>,
+}
+
+impl MessageRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails if the message type is known to this crate or already registered.
+    pub fn register<T: CustomMessage>(&mut self) -> Result<()> {
+        let ty = T::MESSAGE_TYPE;
+        if !matches!(MessageType::decode(&[ty])?, MessageType::Other(_)) {
+            return Err(ProtoError::new(ProtoErrorKind::Encoding(__type_str!(MessageRegistry)))
+                .with_desc(format!("message type {:#04x} is reserved", ty)));
+        }
+        if self.decoders.contains_key(&ty) {
+            return Err(ProtoError::new(ProtoErrorKind::Encoding(__type_str!(MessageRegistry)))
+                .with_desc(format!("message type {:#04x} is already registered", ty)));
+        }
+        self.decoders.insert(ty, h_decode::<T>);
+        Ok(())
+    }
+
+    pub fn with_message<T: CustomMessage>(mut self) -> Result<Self> {
+        self.register::<T>()?;
+        Ok(self)
+    }
+
+    pub fn is_registered(&self, ty: MessageType) -> bool {
+        match ty {
+            MessageType::Other(ty) => self.decoders.contains_key(&ty),
+            _ => false,
+        }
+    }
+
+    /// Decodes a message of a registered type, `None` if the type isn't registered.
+    pub fn decode(&self, ty: MessageType, cursor: &mut Cursor<'_>) -> Option<Result<RegisteredMessage>> {
+        match ty {
+            MessageType::Other(ty) => self.decoders.get(&ty).map(|decode| decode(cursor)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for MessageRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.decoders.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::NowMessage;
+    use crate::serialization::DecodeCtx;
+
+    #[derive(Encode, Decode, Debug, Clone, PartialEq)]
+    struct Ping {
+        sequence: u32,
+    }
+
+    impl CustomMessage for Ping {
+        const MESSAGE_TYPE: u8 = 0xA0;
+    }
+
+    #[derive(Encode, Decode, Debug, Clone, PartialEq)]
+    struct Reserved {
+        value: u8,
+    }
+
+    impl CustomMessage for Reserved {
+        const MESSAGE_TYPE: u8 = 0x41; // surface
+    }
+
+    #[test]
+    fn registration() {
+        let mut registry = MessageRegistry::new();
+        registry.register::<Ping>().unwrap();
+        assert!(registry.is_registered(MessageType::Other(0xA0)));
+        assert!(registry.register::<Ping>().is_err());
+        assert!(registry.register::<Reserved>().is_err());
+    }
+
+    #[test]
+    fn decode_registered_or_custom() {
+        let payload = Ping { sequence: 7 }.encode().unwrap();
+        let ty = MessageType::Other(0xA0);
+
+        let msg = NowMessage::decode_from(ty, &mut Cursor::new(&payload)).unwrap();
+        assert!(matches!(msg, NowMessage::Custom { .. }));
+
+        let registry = MessageRegistry::new().with_message::<Ping>().unwrap();
+        let ctx = DecodeCtx::default().with_registry(&registry);
+        let msg = NowMessage::decode_with_ctx(ty, &mut Cursor::new(&payload), &ctx).unwrap();
+        match &msg {
+            NowMessage::Registered(registered) => {
+                assert_eq!(registered.downcast_ref(), Some(&Ping { sequence: 7 }));
+            }
+            msg => panic!("expected a registered message and got {:?}", msg),
+        }
+        assert_eq!(msg.get_type(), ty);
+        assert_eq!(msg.encode().unwrap(), payload);
+    }
+}
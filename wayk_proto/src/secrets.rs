@@ -0,0 +1,209 @@
+//! Authentication secrets: passwords, SRP verifiers, session keys...
+//!
+//! With the `zeroize` feature, a [`Secret`](struct.Secret.html) wipes its value when dropped,
+//! compares in constant time and never shows up in `Debug` output. The built-in authentication
+//! tokens hold their credentials in it (PFP answers) so that they don't linger in memory,
+//! `AuthenticateSM` implementations should do the same. Either way, these tokens compare their
+//! secrets with [`constant_time_eq`](fn.constant_time_eq.html).
+//!
+//! ```
+//! # #[cfg(feature = "zeroize")] {
+//! use wayk_proto::secrets::Secret;
+//!
+//! let password = Secret::new(String::from("hunter2"));
+//! assert_eq!(format!("{:?}", password), "Secret(..)");
+//! assert!(password == Secret::new(String::from("hunter2")));
+//! assert_eq!(password.expose(), "hunter2");
+//! # }
+//! ```
+
+#[cfg(feature = "zeroize")]
+pub use self::secret::Secret;
+
+/// Secret field of the built-in authentication tokens: a `Secret<T>` with the `zeroize` feature,
+/// a plain `T` otherwise.
+#[cfg(feature = "zeroize")]
+pub(crate) type SecretField<T> = Secret<T>;
+#[cfg(not(feature = "zeroize"))]
+pub(crate) type SecretField<T> = T;
+
+#[cfg(feature = "zeroize")]
+pub(crate) fn hold<T: zeroize::Zeroize>(value: T) -> SecretField<T> {
+    Secret::new(value)
+}
+
+#[cfg(not(feature = "zeroize"))]
+pub(crate) fn hold<T>(value: T) -> SecretField<T> {
+    value
+}
+
+#[cfg(feature = "zeroize")]
+pub(crate) fn expose<T: zeroize::Zeroize>(field: &SecretField<T>) -> &T {
+    field.expose()
+}
+
+#[cfg(not(feature = "zeroize"))]
+pub(crate) fn expose<T>(field: &SecretField<T>) -> &T {
+    field
+}
+
+/// Compares two byte slices in a time depending on their length only, not on their content.
+///
+/// Slices of different lengths are unequal right away: the length of a secret isn't considered
+/// secret.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    // keeps the compiler from short-circuiting the fold
+    core::hint::black_box(diff) == 0
+}
+
+#[cfg(feature = "zeroize")]
+mod secret {
+    use super::constant_time_eq;
+    use crate::error::Result;
+    use crate::io::{Cursor, NoStdWrite};
+    use crate::serialization::{Decode, DecodeCtx, Encode, ExpectedSize};
+    use core::fmt;
+    use zeroize::Zeroize;
+
+    /// Secret value, wiped on drop, see the [module documentation](index.html).
+    pub struct Secret<T: Zeroize>(T);
+
+    impl<T: Zeroize> Secret<T> {
+        pub fn new(value: T) -> Self {
+            Self(value)
+        }
+
+        /// Borrows the secret value. Copies made from it are not wiped.
+        pub fn expose(&self) -> &T {
+            &self.0
+        }
+    }
+
+    impl<T: Zeroize> Drop for Secret<T> {
+        fn drop(&mut self) {
+            self.0.zeroize();
+        }
+    }
+
+    impl<T: Zeroize> From<T> for Secret<T> {
+        fn from(value: T) -> Self {
+            Self::new(value)
+        }
+    }
+
+    impl<T: Zeroize + Clone> Clone for Secret<T> {
+        fn clone(&self) -> Self {
+            Self::new(self.0.clone())
+        }
+    }
+
+    impl<T: Zeroize> fmt::Debug for Secret<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("Secret(..)")
+        }
+    }
+
+    /// Compared in constant time, see [`constant_time_eq`](fn.constant_time_eq.html).
+    impl<T: Zeroize + AsRef<[u8]>> PartialEq for Secret<T> {
+        fn eq(&self, other: &Self) -> bool {
+            constant_time_eq(self.0.as_ref(), other.0.as_ref())
+        }
+    }
+
+    impl<T: Zeroize + AsRef<[u8]>> Eq for Secret<T> {}
+
+    // same wire format as the wrapped value
+
+    impl<T: Zeroize + Encode> Encode for Secret<T> {
+        fn expected_size() -> ExpectedSize
+        where
+            Self: Sized,
+        {
+            T::expected_size()
+        }
+
+        fn encoded_len(&self) -> usize {
+            self.0.encoded_len()
+        }
+
+        fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+            self.0.encode_into(writer)
+        }
+    }
+
+    impl<'dec, T: Zeroize + Decode<'dec>> Decode<'dec> for Secret<T> {
+        fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self> {
+            T::decode_from(cursor).map(Self::new)
+        }
+
+        fn decode_with_ctx(cursor: &mut Cursor<'dec>, ctx: &DecodeCtx<'_>) -> Result<Self> {
+            T::decode_with_ctx(cursor, ctx).map(Self::new)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_time_comparison() {
+        assert!(constant_time_eq(b"verifier", b"verifier"));
+        assert!(!constant_time_eq(b"verifier", b"verifieR"));
+        assert!(!constant_time_eq(b"verifier", b"verifie"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn secret_comparison() {
+        use alloc::vec::Vec;
+
+        let key = Secret::new(vec![0x2a_u8; 32]);
+        assert!(key == key.clone());
+        assert!(key != Secret::new(Vec::new()));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn wiped_on_drop() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicBool, Ordering};
+        use zeroize::Zeroize;
+
+        struct Key(Arc<AtomicBool>);
+
+        impl Zeroize for Key {
+            fn zeroize(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let wiped = Arc::new(AtomicBool::new(false));
+        let key = Secret::new(Key(wiped.clone()));
+        assert!(!wiped.load(Ordering::SeqCst));
+        drop(key);
+        assert!(wiped.load(Ordering::SeqCst));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn secret_wire_format() {
+        use crate::message::NowString256;
+        use crate::serialization::{Decode, Encode};
+        use core::str::FromStr;
+
+        let password = NowString256::from_str("hunter2").unwrap();
+        let bytes = password.encode().unwrap();
+        assert_eq!(Secret::new(password).encode().unwrap(), bytes);
+        assert_eq!(
+            Secret::<NowString256>::decode(&bytes).unwrap().expose().as_str(),
+            "hunter2"
+        );
+    }
+}
@@ -1,15 +1,32 @@
-use crate::error::ProtoError;
-use crate::io::{Cursor, NoStdWrite};
+use crate::error::{ProtoError, ProtoErrorKind};
+use crate::io::{Cursor, NoStdRead, NoStdWrite};
+use crate::message::{NowCapset, VirtChannelsCtx};
+use crate::registry::MessageRegistry;
+use crate::version::NowVersion;
+use alloc::borrow::Cow;
 use alloc::boxed::Box;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 // === ENCODE ===
 
+/// Encoded size of a type, known before encoding any value for fixed size types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExpectedSize {
     Known(usize),
+    /// Depends on the value, see `Encode::encoded_len`.
     Variable,
 }
 
+impl ExpectedSize {
+    pub fn known(self) -> Option<usize> {
+        match self {
+            Self::Known(size) => Some(size),
+            Self::Variable => None,
+        }
+    }
+}
+
 /// Common interface for encoding
 pub trait Encode {
     fn expected_size() -> ExpectedSize
@@ -22,11 +39,38 @@ pub trait Encode {
     where
         Self: Sized;
 
+    /// Cheap estimate of `encoded_len`, used to presize buffers.
+    ///
+    /// Defaults to the exact length. Types for which computing it means walking a large
+    /// structure may return a quicker upper bound instead.
+    fn encoded_size_hint(&self) -> usize {
+        self.encoded_len()
+    }
+
+    /// Encodes into a caller-provided buffer and returns the number of bytes written.
+    ///
+    /// Nothing is written if `buf` is too small.
+    fn encode_to_slice(&self, buf: &mut [u8]) -> Result<usize, ProtoError>
+    where
+        Self: Sized,
+    {
+        let len = self.encoded_len();
+        let available = buf.len();
+        let mut writer = buf.get_mut(..len).ok_or_else(|| {
+            ProtoError::new(ProtoErrorKind::Encoding(core::any::type_name::<Self>())).with_desc(format!(
+                "buffer too small: {} bytes needed, {} available",
+                len, available
+            ))
+        })?;
+        self.encode_into(&mut writer)?;
+        Ok(len)
+    }
+
     fn encode(&self) -> Result<Vec<u8>, ProtoError>
     where
         Self: Sized,
     {
-        let mut buf = Vec::new();
+        let mut buf = Vec::with_capacity(self.encoded_size_hint());
         self.encode_into(&mut buf)?;
         Ok(buf)
     }
@@ -81,11 +125,162 @@ where
 {
     fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self, ProtoError>;
 
+    /// Decodes a value whose layout depends on the connection state (negotiated version, ...).
+    ///
+    /// Derived implementations forward the context to each field, other types ignore it unless
+    /// they override this method.
+    fn decode_with_ctx(cursor: &mut Cursor<'dec>, ctx: &DecodeCtx<'_>) -> Result<Self, ProtoError> {
+        let _ = ctx;
+        Self::decode_from(cursor)
+    }
+
     fn decode(bytes: &'dec [u8]) -> Result<Self, ProtoError> {
         Self::decode_from(&mut Cursor::new(bytes))
     }
 }
 
+// === DECODE CONTEXT ===
+
+static NO_CHANNELS: VirtChannelsCtx = VirtChannelsCtx::new();
+
+/// Connection state available to decoders.
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeCtx<'a> {
+    /// Protocol version negotiated with the peer.
+    pub version: NowVersion,
+    /// Capabilities advertised by the peer.
+    pub capabilities: &'a [NowCapset<'a>],
+    pub channels: &'a VirtChannelsCtx,
+    /// Bytes left after a packet body are skipped instead of being reported as a length mismatch.
+    pub allow_trailing_bytes: bool,
+    /// Decoders for custom message types, unknown types are kept as raw bytes without it.
+    pub registry: Option<&'a MessageRegistry>,
+    /// Limits enforced when decoding packet bodies, the global ones if `None`.
+    pub limits: Option<DecodeLimits>,
+}
+
+impl<'a> DecodeCtx<'a> {
+    pub fn new(version: NowVersion) -> Self {
+        Self {
+            version,
+            capabilities: &[],
+            channels: &NO_CHANNELS,
+            allow_trailing_bytes: false,
+            registry: None,
+            limits: None,
+        }
+    }
+
+    pub fn with_capabilities(self, capabilities: &'a [NowCapset<'a>]) -> Self {
+        Self { capabilities, ..self }
+    }
+
+    pub fn with_channels(self, channels: &'a VirtChannelsCtx) -> Self {
+        Self { channels, ..self }
+    }
+
+    pub fn with_trailing_bytes_allowed(self, allow_trailing_bytes: bool) -> Self {
+        Self {
+            allow_trailing_bytes,
+            ..self
+        }
+    }
+
+    pub fn with_registry(self, registry: &'a MessageRegistry) -> Self {
+        Self {
+            registry: Some(registry),
+            ..self
+        }
+    }
+
+    pub fn with_limits(self, limits: DecodeLimits) -> Self {
+        Self {
+            limits: Some(limits),
+            ..self
+        }
+    }
+}
+
+/// Current protocol version, no capabilities and no channels.
+impl Default for DecodeCtx<'_> {
+    fn default() -> Self {
+        Self::new(NowVersion::CURRENT)
+    }
+}
+
+// === DECODE LIMITS ===
+
+/// Upper bounds enforced when decoding length-prefixed containers and strings.
+///
+/// Counts are read from the wire: without limits, a hostile peer can make the decoder allocate
+/// arbitrarily large collections. Limits apply to cursors created with
+/// [`Cursor::with_limits`](../io/struct.Cursor.html#method.with_limits), the global ones
+/// (see [`DecodeLimits::set_global`](#method.set_global)) otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum number of items in a length-prefixed list.
+    pub max_items: usize,
+    /// Maximum size in bytes of a length-prefixed byte buffer or string.
+    pub max_bytes: usize,
+}
+
+static GLOBAL_MAX_ITEMS: AtomicUsize = AtomicUsize::new(DecodeLimits::DEFAULT.max_items);
+static GLOBAL_MAX_BYTES: AtomicUsize = AtomicUsize::new(DecodeLimits::DEFAULT.max_bytes);
+
+impl DecodeLimits {
+    pub const DEFAULT: DecodeLimits = DecodeLimits {
+        max_items: 16 * 1024 * 1024,
+        max_bytes: 16 * 1024 * 1024,
+    };
+
+    pub const UNLIMITED: DecodeLimits = DecodeLimits {
+        max_items: usize::MAX,
+        max_bytes: usize::MAX,
+    };
+
+    pub fn global() -> Self {
+        Self {
+            max_items: GLOBAL_MAX_ITEMS.load(Ordering::Relaxed),
+            max_bytes: GLOBAL_MAX_BYTES.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn set_global(limits: DecodeLimits) {
+        GLOBAL_MAX_ITEMS.store(limits.max_items, Ordering::Relaxed);
+        GLOBAL_MAX_BYTES.store(limits.max_bytes, Ordering::Relaxed);
+    }
+
+    pub fn check_items(&self, count: usize, ty: &'static str) -> Result<(), ProtoError> {
+        if count > self.max_items {
+            Err(ProtoError::new(ProtoErrorKind::LimitExceeded(ty)).with_desc(format!(
+                "item count ({}) greater than the limit ({})",
+                count, self.max_items
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check_bytes(&self, size: usize, ty: &'static str) -> Result<(), ProtoError> {
+        if size > self.max_bytes {
+            Err(ProtoError::new(ProtoErrorKind::LimitExceeded(ty))
+                .with_desc(format!("size ({}) greater than the limit ({})", size, self.max_bytes)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Held by tests changing or relying on the global limits, tests run in parallel.
+#[cfg(test)]
+pub(crate) static GLOBAL_LIMITS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
 // === implementation for primitive types ===
 
 impl Encode for u8 {
@@ -124,7 +319,7 @@ impl Encode for u16 {
 
 impl Decode<'_> for u16 {
     fn decode_from(cursor: &mut Cursor<'_>) -> Result<Self, ProtoError> {
-        cursor.read_u16().map_err(ProtoError::from)
+        cursor.read_u16_le().map_err(ProtoError::from)
     }
 }
 
@@ -144,7 +339,7 @@ impl Encode for u32 {
 
 impl Decode<'_> for u32 {
     fn decode_from(cursor: &mut Cursor<'_>) -> Result<Self, ProtoError> {
-        cursor.read_u32().map_err(ProtoError::from)
+        cursor.read_u32_le().map_err(ProtoError::from)
     }
 }
 
@@ -164,7 +359,7 @@ impl Encode for u64 {
 
 impl Decode<'_> for u64 {
     fn decode_from(cursor: &mut Cursor<'_>) -> Result<Self, ProtoError> {
-        cursor.read_u64().map_err(ProtoError::from)
+        cursor.read_u64_le().map_err(ProtoError::from)
     }
 }
 
@@ -268,10 +463,10 @@ impl Encode for [u32; 4] {
 impl Decode<'_> for [u32; 4] {
     fn decode_from(cursor: &mut Cursor<'_>) -> Result<Self, ProtoError> {
         Ok([
-            cursor.read_u32()?,
-            cursor.read_u32()?,
-            cursor.read_u32()?,
-            cursor.read_u32()?,
+            cursor.read_u32_le()?,
+            cursor.read_u32_le()?,
+            cursor.read_u32_le()?,
+            cursor.read_u32_le()?,
         ])
     }
 }
@@ -291,21 +486,83 @@ impl Encode for &[u8] {
     }
 }
 
-impl<'dec: 'a, 'a, T: 'a> Decode<'dec> for Box<T>
+impl Encode for Cow<'_, [u8]> {
+    fn expected_size() -> ExpectedSize {
+        ExpectedSize::Variable
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.len()
+    }
+
+    fn encode_into<W: NoStdWrite>(&self, writer: &mut W) -> Result<(), ProtoError> {
+        writer.write_all(self)?;
+        Ok(())
+    }
+}
+
+impl<'dec, T> Decode<'dec> for Box<T>
 where
     T: Decode<'dec>,
 {
     fn decode_from(cursor: &mut Cursor<'dec>) -> Result<Self, ProtoError> {
         T::decode_from(cursor).map(Box::new)
     }
+
+    fn decode_with_ctx(cursor: &mut Cursor<'dec>, ctx: &DecodeCtx<'_>) -> Result<Self, ProtoError> {
+        T::decode_with_ctx(cursor, ctx).map(Box::new)
+    }
+}
+
+// === BYTE ORDER ===
+
+/// Integers with an explicit byte order.
+///
+/// Integers are little endian on the wire, the `Encode` and `Decode` impls are equivalent to
+/// `encode_le` and `decode_le`. Derived types go through `encode_be` and `decode_be` for fields
+/// marked `#[endian = "big"]` (or all fields when the struct itself is marked).
+pub trait Endian: Sized {
+    fn encode_le<W: NoStdWrite>(&self, writer: &mut W) -> Result<(), ProtoError>;
+
+    fn encode_be<W: NoStdWrite>(&self, writer: &mut W) -> Result<(), ProtoError>;
+
+    fn decode_le(cursor: &mut Cursor<'_>) -> Result<Self, ProtoError>;
+
+    fn decode_be(cursor: &mut Cursor<'_>) -> Result<Self, ProtoError>;
+}
+
+macro_rules! impl_endian {
+    ($($int:ty),+) => {
+        $(
+            impl Endian for $int {
+                fn encode_le<W: NoStdWrite>(&self, writer: &mut W) -> Result<(), ProtoError> {
+                    writer.write_all(&self.to_le_bytes()).map_err(ProtoError::from)
+                }
+
+                fn encode_be<W: NoStdWrite>(&self, writer: &mut W) -> Result<(), ProtoError> {
+                    writer.write_all(&self.to_be_bytes()).map_err(ProtoError::from)
+                }
+
+                fn decode_le(cursor: &mut Cursor<'_>) -> Result<Self, ProtoError> {
+                    cursor.read_array().map(<$int>::from_le_bytes).map_err(ProtoError::from)
+                }
+
+                fn decode_be(cursor: &mut Cursor<'_>) -> Result<Self, ProtoError> {
+                    cursor.read_array().map(<$int>::from_be_bytes).map_err(ProtoError::from)
+                }
+            }
+        )+
+    };
 }
 
+impl_endian!(u8, u16, u32, u64, i8, i16, i32, i64);
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::container::Bytes8;
+    use crate::container::{Bytes8, Vec8};
 
-    #[derive(Encode, Decode)]
+    #[derive(Encode, Decode, PartialEq)]
     struct StructDerive<'a> {
         pub a: u8,
         b: u8,
@@ -330,8 +587,152 @@ mod tests {
             a: 0x10,
             b: 0x20,
             c: 0x4030,
-            update_data: Bytes8(&[0x01, 0x02, 0x03]),
+            update_data: Bytes8::from(&[0x01, 0x02, 0x03][..]),
         };
         assert_eq!(s.encode().unwrap(), STRUCT_DERIVE_ENCODED.to_vec());
     }
+
+    #[derive(Encode, Decode, Debug, PartialEq)]
+    #[endian = "big"]
+    struct NetworkOrder {
+        port: u16,
+        #[endian = "little"]
+        id: u32,
+        tag: u8,
+        addr: u32,
+    }
+
+    #[rustfmt::skip]
+    const NETWORK_ORDER_ENCODED: [u8; 11] = [
+        0x0d, 0x3d, // port
+        0x01, 0x00, 0x00, 0x00, // id
+        0x2a, // tag
+        0x7f, 0x00, 0x00, 0x01, // addr
+    ];
+
+    #[test]
+    fn endian_attribute() {
+        let s = NetworkOrder {
+            port: 3389,
+            id: 1,
+            tag: 0x2a,
+            addr: 0x7f00_0001,
+        };
+        assert_eq!(s.encode().unwrap(), NETWORK_ORDER_ENCODED.to_vec());
+        assert_eq!(NetworkOrder::decode(&NETWORK_ORDER_ENCODED).unwrap(), s);
+
+        let mut cursor = Cursor::new(&NETWORK_ORDER_ENCODED);
+        assert_eq!(u16::decode_le(&mut cursor).unwrap(), 0x3d0d);
+        assert_eq!(u32::decode_be(&mut cursor).unwrap(), 0x0100_0000);
+        assert!(u64::decode_be(&mut cursor).is_err());
+    }
+
+    #[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+    enum AutoNumbered {
+        #[value = 0x10]
+        First,
+        Second,
+        Third,
+        #[value = 0x20]
+        Restarted,
+        Next,
+        #[value_range = "0x80..=0xfd"]
+        Reserved(u8),
+        AfterRange,
+        #[fallback]
+        Other(u8),
+    }
+
+    #[test]
+    fn enum_auto_numbering_and_ranges() {
+        let decode = |v: u8| AutoNumbered::decode(&[v]).unwrap();
+        assert_eq!(decode(0x10), AutoNumbered::First);
+        assert_eq!(decode(0x12), AutoNumbered::Third);
+        assert_eq!(decode(0x21), AutoNumbered::Next);
+        assert_eq!(decode(0x13), AutoNumbered::Other(0x13));
+        assert_eq!(decode(0x80), AutoNumbered::Reserved(0x80));
+        assert_eq!(decode(0xfd), AutoNumbered::Reserved(0xfd));
+        assert_eq!(decode(0xfe), AutoNumbered::AfterRange);
+        assert_eq!(decode(0xff), AutoNumbered::Other(0xff));
+
+        assert_eq!(AutoNumbered::Second.encode().unwrap(), [0x11]);
+        assert_eq!(AutoNumbered::Reserved(0x9a).encode().unwrap(), [0x9a]);
+        assert_eq!(AutoNumbered::AfterRange.encode().unwrap(), [0xfe]);
+    }
+
+    // field only present since 21.2
+    #[derive(Debug, PartialEq)]
+    struct Since21_2(Option<u8>);
+
+    impl Decode<'_> for Since21_2 {
+        fn decode_from(cursor: &mut Cursor<'_>) -> Result<Self, ProtoError> {
+            Self::decode_with_ctx(cursor, &DecodeCtx::default())
+        }
+
+        fn decode_with_ctx(cursor: &mut Cursor<'_>, ctx: &DecodeCtx<'_>) -> Result<Self, ProtoError> {
+            if ctx.version >= NowVersion::new(21, 2, 0) {
+                u8::decode_from(cursor).map(|v| Self(Some(v)))
+            } else {
+                Ok(Self(None))
+            }
+        }
+    }
+
+    #[derive(Decode)]
+    struct Versioned {
+        a: u8,
+        b: Since21_2,
+    }
+
+    #[test]
+    fn derived_decode_forwards_ctx() {
+        let bytes = [0x01, 0x02];
+
+        let mut cursor = Cursor::new(&bytes);
+        let old = Versioned::decode_with_ctx(&mut cursor, &DecodeCtx::new(NowVersion::new(21, 1, 0))).unwrap();
+        assert_eq!(old.a, 0x01);
+        assert_eq!(old.b, Since21_2(None));
+        assert_eq!(cursor.position(), 1);
+
+        let mut cursor = Cursor::new(&bytes);
+        let new = Versioned::decode_with_ctx(&mut cursor, &DecodeCtx::new(NowVersion::new(21, 2, 0))).unwrap();
+        assert_eq!(new.b, Since21_2(Some(0x02)));
+        assert_eq!(cursor.position(), 2);
+    }
+
+    #[derive(Decode)]
+    struct VersionedList {
+        list: Vec8<Since21_2>,
+        boxed: Box<Since21_2>,
+    }
+
+    #[test]
+    fn containers_forward_ctx() {
+        let bytes = [0x02, 0x0a, 0x0b, 0x0c];
+
+        let mut cursor = Cursor::new(&bytes);
+        let old = VersionedList::decode_with_ctx(&mut cursor, &DecodeCtx::new(NowVersion::new(21, 1, 0))).unwrap();
+        assert_eq!(*old.list, [Since21_2(None), Since21_2(None)]);
+        assert_eq!(*old.boxed, Since21_2(None));
+        assert_eq!(cursor.position(), 1);
+
+        let mut cursor = Cursor::new(&bytes);
+        let new = VersionedList::decode_with_ctx(&mut cursor, &DecodeCtx::new(NowVersion::new(21, 2, 0))).unwrap();
+        assert_eq!(*new.list, [Since21_2(Some(0x0a)), Since21_2(Some(0x0b))]);
+        assert_eq!(*new.boxed, Since21_2(Some(0x0c)));
+        assert_eq!(cursor.position(), 4);
+    }
+
+    #[test]
+    fn global_decode_limits() {
+        let _lock = GLOBAL_LIMITS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let limits = DecodeLimits {
+            max_items: DecodeLimits::DEFAULT.max_items + 1,
+            max_bytes: DecodeLimits::DEFAULT.max_bytes + 1,
+        };
+        DecodeLimits::set_global(limits);
+        assert_eq!(DecodeLimits::global(), limits);
+        assert_eq!(Cursor::new(&[]).limits(), limits);
+        DecodeLimits::set_global(DecodeLimits::DEFAULT);
+    }
 }
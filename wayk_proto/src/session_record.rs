@@ -0,0 +1,405 @@
+//! Session recording.
+//!
+//! A [`SessionRecorder`](struct.SessionRecorder.html) persists the inbound surface, update and
+//! input messages of a session along with the time elapsed since the recording started. A
+//! [`SessionPlayer`](struct.SessionPlayer.html) plays such a recording back through the
+//! `SurfaceSM` and `DisplaySM`, so a session can be viewed again without a live server.
+//!
+//! The recording starts with the `WNSR` magic and a version byte followed by [`SessionFrame`]s
+//! encoded one after the other. Frames hold raw packets, Wayk header included.
+//!
+//! [`SessionFrame`]: struct.SessionFrame.html
+
+use crate::container::Bytes32;
+use crate::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt, Result};
+use crate::header::{AbstractNowHeader, NowHeader};
+use crate::io::{Cursor, NoStdWrite};
+use crate::message::{NowBody, NowInputMsg, NowMessage, VirtChannelsCtx};
+use crate::packet::NowPacket;
+use crate::serialization::{Decode, DecodeCtx, DecodeLimits, Encode};
+use crate::sm::{DisplaySM, SMEvent, SMEvents, SurfaceSM};
+use crate::trace::{ClockFn, PacketReplayer};
+use alloc::vec::Vec;
+
+pub const SESSION_RECORD_MAGIC: [u8; 4] = *b"WNSR";
+pub const SESSION_RECORD_VERSION: u8 = 1;
+
+/// Whether a message belongs to a session recording.
+pub fn is_recorded(msg: &NowMessage<'_>) -> bool {
+    matches!(
+        msg,
+        NowMessage::Surface(_) | NowMessage::Update(_) | NowMessage::Input(_)
+    )
+}
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct SessionFrame<'a> {
+    /// Milliseconds elapsed since the recording started.
+    pub elapsed: u32,
+    pub packet: Bytes32<'a>,
+}
+
+impl<'a> SessionFrame<'a> {
+    pub fn decode_packet(&self) -> Result<NowPacket<'_>> {
+        // only now messages are recorded
+        self.decode_packet_with_ctx(&DecodeCtx::default())
+    }
+
+    pub fn decode_packet_with_ctx(&self, ctx: &DecodeCtx<'_>) -> Result<NowPacket<'_>> {
+        let bytes = &*self.packet;
+        let header = NowHeader::decode(bytes)?;
+        let body = bytes
+            .get(header.len()..)
+            .chain(ProtoErrorKind::Decoding(__type_str!(SessionFrame)))
+            .or_desc("packet shorter than its header")?;
+        NowPacket::decode_with_ctx(header, body, ctx)
+    }
+}
+
+// recorder
+
+/// Writes a session recording, e.g. into a `std::fs::File`.
+pub struct SessionRecorder<W: NoStdWrite> {
+    writer: W,
+    clock: ClockFn,
+    start: u64,
+    count: usize,
+}
+
+impl<W: NoStdWrite> SessionRecorder<W> {
+    /// Writes the recording header. Elapsed times are measured from this call.
+    pub fn new(mut writer: W, mut clock: ClockFn) -> Result<Self> {
+        writer.write_all(&SESSION_RECORD_MAGIC)?;
+        writer.write_u8(SESSION_RECORD_VERSION)?;
+        let start = clock();
+        Ok(Self {
+            writer,
+            clock,
+            start,
+            count: 0,
+        })
+    }
+
+    /// Records an inbound packet if it carries a surface, update or input message.
+    ///
+    /// Returns whether the packet was recorded.
+    pub fn record(&mut self, packet: &NowPacket<'_>) -> Result<bool> {
+        match &packet.body {
+            NowBody::Message(msg) if is_recorded(msg) => {
+                let bytes = packet.encode()?;
+                self.h_write_frame(&bytes)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Records every inbound packet of a trace, keeping their relative timing.
+    pub fn record_trace(&mut self, trace: &PacketReplayer<'_>) -> Result<()> {
+        let first = match trace.received().next() {
+            Some(record) => record.timestamp,
+            None => return Ok(()),
+        };
+
+        for record in trace.received() {
+            let packet = record.decode_packet(&VirtChannelsCtx::new());
+            let recorded = match &packet {
+                Ok(packet) => matches!(&packet.body, NowBody::Message(msg) if is_recorded(msg)),
+                // virtual channel messages can't be decoded without the channels context
+                Err(_) => false,
+            };
+            if recorded {
+                let elapsed = record.timestamp.saturating_sub(first);
+                self.h_write_frame_at(h_clamp_elapsed(elapsed), &record.packet)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of recorded frames.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn h_write_frame(&mut self, packet: &[u8]) -> Result<()> {
+        let elapsed = (self.clock)().saturating_sub(self.start);
+        self.h_write_frame_at(h_clamp_elapsed(elapsed), packet)
+    }
+
+    fn h_write_frame_at(&mut self, elapsed: u32, packet: &[u8]) -> Result<()> {
+        let frame = SessionFrame {
+            elapsed,
+            packet: Bytes32::from(packet),
+        };
+        self.writer.write_all(&frame.encode()?)?;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+fn h_clamp_elapsed(elapsed: u64) -> u32 {
+    elapsed.min(u64::from(u32::MAX)) as u32
+}
+
+// player
+
+/// Output of [`SessionPlayer::play_until`](struct.SessionPlayer.html#method.play_until).
+#[derive(Default)]
+pub struct SessionPlayback<'a> {
+    /// Events produced by the surface and display state machines.
+    pub events: Vec<SMEvent<'a>>,
+    /// Recorded input messages, as (elapsed milliseconds, message).
+    pub inputs: Vec<(u32, NowInputMsg<'a>)>,
+}
+
+pub struct SessionPlayer<'a> {
+    frames: Vec<SessionFrame<'a>>,
+    position: usize,
+    limits: Option<DecodeLimits>,
+}
+
+impl<'a> SessionPlayer<'a> {
+    pub fn new(recording: &'a [u8]) -> Result<Self> {
+        Self::h_new(recording, None)
+    }
+
+    /// Decodes the recording and its packets with the given limits instead of the global ones.
+    pub fn with_limits(recording: &'a [u8], limits: DecodeLimits) -> Result<Self> {
+        Self::h_new(recording, Some(limits))
+    }
+
+    fn h_new(recording: &'a [u8], limits: Option<DecodeLimits>) -> Result<Self> {
+        let mut cursor = Cursor::new(recording);
+        if let Some(limits) = limits {
+            cursor.set_limits(limits);
+        }
+
+        let magic = cursor
+            .read_n(SESSION_RECORD_MAGIC.len())
+            .map_err(ProtoError::from)
+            .chain(ProtoErrorKind::Decoding(__type_str!(SessionPlayer)))
+            .or_desc("couldn't read session recording magic")?;
+        if magic != SESSION_RECORD_MAGIC {
+            return Err(ProtoError::new(ProtoErrorKind::Decoding(__type_str!(SessionPlayer)))
+                .with_desc("invalid session recording magic"));
+        }
+
+        let version = cursor.read_u8()?;
+        if version != SESSION_RECORD_VERSION {
+            return Err(ProtoError::new(ProtoErrorKind::Decoding(__type_str!(SessionPlayer)))
+                .with_desc(format!("unsupported session recording version {}", version)));
+        }
+
+        let mut frames = Vec::new();
+        while cursor.position() < recording.len() {
+            let frame = SessionFrame::decode_from(&mut cursor)
+                .chain(ProtoErrorKind::Decoding(__type_str!(SessionPlayer)))
+                .or_else_desc(|| format!("couldn't decode frame n°{}", frames.len()))?;
+            frames.push(frame);
+        }
+
+        Ok(Self {
+            frames,
+            position: 0,
+            limits,
+        })
+    }
+
+    pub fn frames(&self) -> &[SessionFrame<'a>] {
+        &self.frames
+    }
+
+    /// Length of the recording in milliseconds.
+    pub fn duration(&self) -> u32 {
+        self.frames.last().map_or(0, |frame| frame.elapsed)
+    }
+
+    /// Elapsed time of the next frame to play, `None` once finished.
+    pub fn next_deadline(&self) -> Option<u32> {
+        self.frames.get(self.position).map(|frame| frame.elapsed)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.frames.len()
+    }
+
+    /// Restarts playback, state machines should be reset by the caller as well.
+    pub fn rewind(&mut self) {
+        self.position = 0;
+    }
+
+    /// Plays every frame recorded up to `elapsed` milliseconds.
+    ///
+    /// Responses of the state machines (e.g. frame acknowledgements) are discarded since no
+    /// server is on the other side.
+    pub fn play_until(
+        &mut self,
+        elapsed: u32,
+        surface: &mut SurfaceSM,
+        display: &mut DisplaySM,
+    ) -> Result<SessionPlayback<'_>> {
+        let mut events = SMEvents::new();
+        let mut inputs = Vec::new();
+
+        while let Some(frame) = self.frames.get(self.position) {
+            if frame.elapsed > elapsed {
+                break;
+            }
+
+            let packet = match self.limits {
+                Some(limits) => frame.decode_packet_with_ctx(&DecodeCtx::default().with_limits(limits))?,
+                None => frame.decode_packet()?,
+            };
+            match packet.body {
+                NowBody::Message(NowMessage::Surface(msg)) => {
+                    surface.update_with_message(&mut events, &mut Vec::new(), &msg);
+                }
+                NowBody::Message(NowMessage::Update(msg)) => {
+                    display.update_with_message(&mut events, &mut Vec::new(), &msg);
+                }
+                NowBody::Message(NowMessage::Input(msg)) => inputs.push((frame.elapsed, msg)),
+                body => log::warn!("unexpected packet in session recording: {:?}", body),
+            }
+
+            self.position += 1;
+        }
+
+        Ok(SessionPlayback {
+            events: events.unpack(),
+            inputs,
+        })
+    }
+
+    /// Plays the remaining frames at once.
+    pub fn play_all(&mut self, surface: &mut SurfaceSM, display: &mut DisplaySM) -> Result<SessionPlayback<'_>> {
+        self.play_until(u32::MAX, surface, display)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{InputEvent, NowActivateMsg, NowInputEventScroll, NowUpdateGraphicsMsg, NowUpdateMsg};
+    use crate::sm::DisplayEvent;
+    use crate::trace::PacketRecorder;
+    use alloc::boxed::Box;
+
+    #[rustfmt::skip]
+    const UPDATE_GRAPHICS_MSG: [u8; 29] = [
+        0x01, // subtype
+        0x00, // flags
+        0x02, 0x00, // codecId
+        0x01, 0x00, // surfaceID
+        0x07, 0x00, // frameID
+        0x03, 0x00, 0x00, 0x00, // updateFlags (first and last)
+        0x00, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x0c, 0x00, // updateRect
+        0x05, 0x00, 0x00, 0x00, // updateSize
+        0x01, 0x02, 0x03, 0x04, 0x05,
+    ];
+
+    fn fixed_clock() -> ClockFn {
+        let mut now = 1_000;
+        Box::new(move || {
+            now += 10;
+            now
+        })
+    }
+
+    fn graphics_packet() -> NowPacket<'static> {
+        let msg = NowUpdateGraphicsMsg::decode(&UPDATE_GRAPHICS_MSG).unwrap();
+        NowPacket::from_message(NowUpdateMsg::UpdateGraphics(msg))
+    }
+
+    fn input_packet() -> NowPacket<'static> {
+        let event = InputEvent::Scroll(NowInputEventScroll::new_with_position(0, 1));
+        NowPacket::from_message(NowInputMsg::new_with_events(vec![event]))
+    }
+
+    #[test]
+    fn record_and_play() {
+        let mut recorder = SessionRecorder::new(Vec::new(), fixed_clock()).unwrap();
+        assert!(!recorder
+            .record(&NowPacket::from_message(NowActivateMsg::default()))
+            .unwrap());
+        assert!(recorder.record(&graphics_packet()).unwrap());
+        assert!(recorder.record(&input_packet()).unwrap());
+        assert_eq!(recorder.len(), 2);
+        let recording = recorder.into_inner();
+        assert_eq!(&recording[..5], b"WNSR\x01");
+
+        let mut player = SessionPlayer::new(&recording).unwrap();
+        assert_eq!(player.duration(), 20);
+        let (mut surface, mut display) = (SurfaceSM::new(), DisplaySM::new());
+
+        let playback = player.play_until(5, &mut surface, &mut display).unwrap();
+        assert!(playback.events.is_empty());
+        assert_eq!(player.next_deadline(), Some(10));
+
+        let playback = player.play_until(10, &mut surface, &mut display).unwrap();
+        assert_eq!(
+            playback.events[0].downcast_data::<DisplayEvent>(),
+            Some(&DisplayEvent::FrameCompleted {
+                surface_id: 1,
+                frame_id: 7
+            })
+        );
+        assert!(playback.inputs.is_empty());
+        assert!(display.damage().is_dirty(1));
+
+        let playback = player.play_all(&mut surface, &mut display).unwrap();
+        assert_eq!(playback.inputs.len(), 1);
+        assert_eq!(playback.inputs[0].0, 20);
+        assert!(player.is_finished());
+
+        player.rewind();
+        assert_eq!(player.next_deadline(), Some(10));
+    }
+
+    #[test]
+    fn record_from_trace() {
+        let mut trace = PacketRecorder::new(fixed_clock());
+        trace.record_received(&graphics_packet()).unwrap();
+        trace.record_sent(&input_packet()).unwrap();
+        trace.record_received(&input_packet()).unwrap();
+        let log = trace.into_log();
+        let trace = PacketReplayer::new(&log).unwrap();
+
+        let mut recorder = SessionRecorder::new(Vec::new(), fixed_clock()).unwrap();
+        recorder.record_trace(&trace).unwrap();
+        assert_eq!(recorder.len(), 2);
+        let recording = recorder.into_inner();
+
+        let player = SessionPlayer::new(&recording).unwrap();
+        let elapsed: Vec<u32> = player.frames().iter().map(|frame| frame.elapsed).collect();
+        assert_eq!(elapsed, vec![0, 20]);
+    }
+
+    #[test]
+    fn player_rejects_invalid_recording() {
+        assert!(SessionPlayer::new(b"WNTR\x01").is_err());
+        assert!(SessionPlayer::new(b"WNSR\x02").is_err());
+        assert!(SessionPlayer::new(b"WNSR\x01\x00").is_err());
+        assert!(SessionPlayer::new(b"WNSR\x01").unwrap().is_finished());
+    }
+}
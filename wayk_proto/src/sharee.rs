@@ -1,28 +1,102 @@
-use crate::channels_manager::ChannelsManager;
-use crate::error::ProtoErrorKind;
+use crate::auth::token::NowAuthSessionToken;
+use crate::channels_manager::{ChannelCommandSender, ChannelStats, ChannelsManager};
+use crate::compression::Compression;
+use crate::error::{ProtoError, ProtoErrorKind, Result};
+use crate::extension::ExtensionRegistry;
+use crate::header::AbstractNowHeader;
 use crate::message::{
-    AuthType, ChannelName, NowBody, NowCapset, NowChannelDef, NowMessage, NowTerminateMsg, VirtChannelsCtx,
+    AccessControlCode, AuthType, BodyType, ChannelDefFlags, ChannelMessageType, ChannelName, DisconnectStatusCode,
+    NegotiateFlags, NowAccessMsg, NowAuthenticateMsg, NowBody, NowCapset, NowChannelDef, NowMessage, NowStatus,
+    NowSurfaceMap, NowSystemOsInfo, NowTerminateMsg, NowVirtualChannel, ShutdownFlags, VirtChannelsCtx,
 };
+use crate::metrics::{Metrics, MetricsReporter};
 use crate::packet::NowPacket;
-use crate::sm::{ChannelResponses, ConnectionSM, ProtoState, SMData, SMEvent, SMEvents};
+use crate::policy::{Policy, PolicyAudit};
+use crate::registry::MessageRegistry;
+use crate::serialization::DecodeCtx;
+use crate::sm::{
+    AccessControlSM, AccessState, AssociationInfo, ChannelResponses, ConnectionSM, DisplaySM, NegotiatedCapabilities,
+    NegotiatedVersion, PeerCapabilities, ProtoData, ProtoState, SMData, SMEvent, SMEvents, SharingCallbackTrait,
+    SharingSM, StateInfo, SurfaceSM, SystemActions,
+};
+use crate::sync::SharedQueue;
+use crate::tap::{run_taps, PacketTap, TapVerdict, TappedPacket};
+use crate::timer::{TimerId, TimerRequest};
+use crate::trace::PacketDirection;
+use crate::version::NowVersion;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ShareeState {
     Connection,
     Active,
+    /// Terminate message sent, waiting for the peer acknowledgment.
+    Terminating,
     Final,
 }
 
 impl ProtoState for ShareeState {}
 
+/// Emitted as an `SMEvent::Data` when a terminate message ends the session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Disconnected {
+    pub reason: DisconnectStatusCode,
+    /// `true` if the peer initiated the termination.
+    pub by_peer: bool,
+}
+
+impl ProtoData for Disconnected {}
+
+/// What the connection sequence ended up with, see `Sharee::session_info`.
+///
+/// Fields are `None` until the matching phase completed.
+#[derive(Debug, Clone, Default)]
+pub struct SessionInfo {
+    pub version: Option<NegotiatedVersion>,
+    /// Method of the last authentication token sent.
+    pub auth_type: Option<AuthType>,
+    /// Channels currently opened, with their id.
+    pub channels: Vec<(u8, ChannelName)>,
+    /// Remote capabilities, restricted to what both ends support.
+    pub capabilities: Option<NegotiatedCapabilities>,
+    pub association: Option<AssociationInfo>,
+    /// Only known once a `Sharee::query_os_info` is answered.
+    pub os_info: Option<NowSystemOsInfo<'static>>,
+}
+
 pub struct Sharee<ConnectionSeq> {
     state: ShareeState,
     connection_seq: ConnectionSeq,
     channels_manager: ChannelsManager,
+    access_control: AccessControlSM,
+    sharing: SharingSM,
+    system: SystemActions,
+    surface: SurfaceSM,
+    display: DisplaySM,
     sm_data: SMData,
     channels_ctx: VirtChannelsCtx,
+    /// Set once `terminate` is called.
+    terminate_reason: Option<DisconnectStatusCode>,
+    metrics: Option<MetricsReporter>,
+    registry: MessageRegistry,
+    auth_type: Option<AuthType>,
+    /// Channels compression was offered for.
+    compressed_channels: Vec<ChannelName>,
+    channel_stats_interval: Option<Duration>,
+    /// Armed once active, if channel stats are reported.
+    channel_stats_timer: Option<TimerId>,
+    taps: Vec<Box<dyn PacketTap>>,
+    /// Filled by the policies registered with `ShareeBuilder::policy`.
+    policy_audits: Option<SharedQueue<PolicyAudit>>,
 }
 
+// Allows updating the sharee on a reader thread, see `PacketSink`.
+sa::assert_impl_all!(Sharee<crate::sm::ClientConnectionSeqSM>: Send);
+
 impl<ConnectionSeq> Sharee<ConnectionSeq>
 where
     ConnectionSeq: ConnectionSM,
@@ -51,16 +125,19 @@ where
         match self.state {
             ShareeState::Connection => self.connection_seq.waiting_for_packet(),
             ShareeState::Active => self.channels_manager.waiting_for_packet(),
+            ShareeState::Terminating => true,
             ShareeState::Final => false,
         }
     }
 
     pub fn update_without_body<'msg>(&mut self) -> Vec<SMEvent<'msg>> {
+        __enter_span!("update", sharee_state = ?self.state);
         let mut events = SMEvents::new();
         match self.state {
             ShareeState::Connection => {
                 self.connection_seq
                     .update_without_message(&mut self.sm_data, &mut events);
+                self.h_track_auth_type(&events);
                 if self.connection_seq.is_terminated() {
                     self.h_go_to_active_state(&mut events);
                 }
@@ -72,31 +149,152 @@ where
                     .update_without_virt_msg(&mut self.sm_data, &mut events, &mut chan_rsps);
                 self.h_map_channels_manager_result(&mut events, chan_rsps);
             }
+            ShareeState::Terminating => events.push(SMEvent::warn(
+                ProtoErrorKind::Sharee(self.state),
+                "unexpected call to `Sharee::update_without_body` while waiting for terminate acknowledgment",
+            )),
             ShareeState::Final => {
-                events.push(SMEvent::PacketToSend(
-                    NowPacket::from_message(NowTerminateMsg::default()).into(),
-                ));
+                events.push(SMEvent::PacketToSend(NowPacket::from_message(
+                    NowTerminateMsg::default(),
+                )));
             }
         }
-        events.unpack()
+        self.h_unpack(events)
     }
 
+    /// Virtual channel messages go to the first instance of their channel, use
+    /// `update_with_packet` to route them to the instance they were received on.
+    ///
+    /// Packet taps see the body with the header it would be framed with. Virtual channel messages
+    /// of channels that aren't opened can't be framed and aren't shown to taps.
     pub fn update_with_body<'msg: 'a, 'a>(&mut self, body: &'a NowBody<'msg>) -> Vec<SMEvent<'msg>> {
+        if !self.taps.is_empty() {
+            let header = match body {
+                NowBody::Message(msg) => Some(NowPacket::from_message_ref(msg).header()),
+                NowBody::VirtualChannel(chan_msg) => self
+                    .channels_ctx
+                    .get_id_by_channel(chan_msg.get_name())
+                    .map(|channel_id| NowPacket::from_virt_channel_ref(chan_msg, channel_id).header()),
+            };
+            if let Some(header) = header {
+                match run_taps(&mut self.taps, PacketDirection::Received, TappedPacket { header, body }) {
+                    TapVerdict::Pass => {}
+                    TapVerdict::Drop => return self.h_unpack(SMEvents::new()),
+                    TapVerdict::Replace(packet) => return self.h_update_with_packet(&packet),
+                }
+            }
+        }
+        self.h_update_with_body(None, body)
+    }
+
+    /// Same as `update_with_body`, virtual channel messages are dispatched to the instance bound
+    /// to the channel id of the packet header.
+    pub fn update_with_packet<'msg: 'a, 'a>(&mut self, packet: &'a NowPacket<'msg>) -> Vec<SMEvent<'msg>> {
+        match run_taps(&mut self.taps, PacketDirection::Received, packet.into()) {
+            TapVerdict::Pass => self.h_update_with_packet(packet),
+            TapVerdict::Drop => self.h_unpack(SMEvents::new()),
+            TapVerdict::Replace(packet) => self.h_update_with_packet(&packet),
+        }
+    }
+
+    fn h_update_with_packet<'msg: 'a, 'a>(&mut self, packet: &'a NowPacket<'msg>) -> Vec<SMEvent<'msg>> {
+        let channel_id = match packet.header.body_type() {
+            BodyType::VirtualChannel(id) => Some(id),
+            BodyType::Message(_) => None,
+        };
+        self.h_update_with_body(channel_id, &packet.body)
+    }
+
+    fn h_update_with_body<'msg: 'a, 'a>(
+        &mut self,
+        channel_id: Option<u8>,
+        body: &'a NowBody<'msg>,
+    ) -> Vec<SMEvent<'msg>> {
+        #[cfg(feature = "tracing")]
+        let _span = match body {
+            NowBody::Message(msg) => {
+                tracing::debug_span!("packet", sharee_state = ?self.state, message = ?msg.get_type())
+            }
+            NowBody::VirtualChannel(chan_msg) => {
+                tracing::debug_span!("packet", sharee_state = ?self.state, channel = ?chan_msg.get_name())
+            }
+        }
+        .entered();
+        if let Some(reporter) = &mut self.metrics {
+            reporter.on_received(body, &self.channels_ctx);
+        }
         let mut events = SMEvents::new();
         match body {
+            NowBody::Message(NowMessage::Terminate(msg)) if self.state != ShareeState::Final => {
+                self.h_on_terminate(&mut events, msg)
+            }
             NowBody::Message(msg) => match self.state {
                 ShareeState::Connection => {
                     self.connection_seq
                         .update_with_message(&mut self.sm_data, &mut events, msg);
+                    self.h_track_auth_type(&events);
                     if self.connection_seq.is_terminated() {
                         self.h_go_to_active_state(&mut events);
                     }
                     self.h_check_for_fatal(&mut events);
                 }
-                ShareeState::Active => {
-                    if let NowMessage::Terminate(_) = msg {
-                        self.h_transition_state(&mut events, ShareeState::Final);
+                ShareeState::Active => match msg {
+                    NowMessage::Channel(msg) => {
+                        let rsp = self
+                            .channels_manager
+                            .update_with_channel_msg(&mut self.sm_data, &mut events, msg);
+                        match msg.subtype {
+                            ChannelMessageType::ChannelCloseRequest => {
+                                for def in msg.channel_list.iter() {
+                                    self.channels_ctx.remove(&def.name);
+                                }
+                            }
+                            ChannelMessageType::ChannelOpenRequest => {
+                                for def in msg.channel_list.iter() {
+                                    if self.channels_manager.has_sm(&def.name) {
+                                        self.h_open_channel(def);
+                                    }
+                                }
+                                self.channels_manager
+                                    .bind_channels(&mut self.sm_data, &mut events, &self.channels_ctx);
+                            }
+                            _ => {}
+                        }
+                        if let Some(rsp) = rsp {
+                            events.push(SMEvent::PacketToSend(NowPacket::from_message(rsp)));
+                        }
+                    }
+                    NowMessage::Access(msg) => {
+                        self.access_control.update_with_message(&mut events, msg);
+                        self.sm_data.extra_insert(self.access_control.access_states());
                     }
+                    NowMessage::System(msg) => self.system.update_with_message(&mut events, msg),
+                    NowMessage::Sharing(msg) => {
+                        let mut to_send = Vec::new();
+                        self.sharing.update_with_message(&mut events, &mut to_send, msg);
+                        for msg in to_send {
+                            events.push(SMEvent::PacketToSend(NowPacket::from_message(msg)));
+                        }
+                    }
+                    NowMessage::Surface(msg) => {
+                        let mut to_send = Vec::new();
+                        self.surface.update_with_message(&mut events, &mut to_send, msg);
+                        for msg in to_send {
+                            events.push(SMEvent::PacketToSend(NowPacket::from_message(msg)));
+                        }
+                    }
+                    NowMessage::Update(msg) => {
+                        let mut to_send = Vec::new();
+                        self.display.update_with_message(&mut events, &mut to_send, msg);
+                        for msg in to_send {
+                            events.push(SMEvent::PacketToSend(NowPacket::from_message(msg)));
+                        }
+                    }
+                    NowMessage::Registered(msg) => events.push(SMEvent::data(msg.clone())),
+                    _ => {}
+                },
+                ShareeState::Terminating => {
+                    log::trace!("{:?} message dropped while terminating", msg.get_type());
                 }
                 ShareeState::Final => events.push(SMEvent::error(
                     ProtoErrorKind::Sharee(self.state),
@@ -110,27 +308,357 @@ where
                 )),
                 ShareeState::Active => {
                     let mut chan_rsps = ChannelResponses::new();
-                    self.channels_manager.update_with_virt_msg(
-                        &mut self.sm_data,
-                        &mut events,
-                        &mut chan_rsps,
-                        chan_msg,
-                    );
+                    match channel_id {
+                        Some(channel_id) => self.channels_manager.update_with_instance_msg(
+                            channel_id,
+                            &mut self.sm_data,
+                            &mut events,
+                            &mut chan_rsps,
+                            chan_msg,
+                        ),
+                        None => self.channels_manager.update_with_virt_msg(
+                            &mut self.sm_data,
+                            &mut events,
+                            &mut chan_rsps,
+                            chan_msg,
+                        ),
+                    }
                     self.h_map_channels_manager_result(&mut events, chan_rsps);
                 }
+                ShareeState::Terminating => {
+                    log::trace!("{:?} channel message dropped while terminating", chan_msg.get_name());
+                }
                 ShareeState::Final => events.push(SMEvent::error(
                     ProtoErrorKind::Sharee(self.state),
                     "unexpected call to `Sharee::update_with_body` in final state with a virtual channel message",
                 )),
             },
         }
-        events.unpack()
+        self.h_unpack(events)
+    }
+
+    /// Notifies the state machines that a timer requested with `SMEvent::SetTimer` expired.
+    pub fn on_timer_expired<'msg>(&mut self, id: TimerId) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        match self.state {
+            ShareeState::Connection => {
+                self.connection_seq.on_timer_expired(&mut self.sm_data, &mut events, id);
+                self.h_check_for_fatal(&mut events);
+            }
+            ShareeState::Active if self.channel_stats_timer == Some(id) => self.h_report_channel_stats(&mut events),
+            _ => log::trace!("{:?} expired in {:?} state, ignored", id, self.state),
+        }
+        self.h_unpack(events)
+    }
+
+    /// Starts closing the session politely: a terminate message is sent and the sharee waits
+    /// for the peer to acknowledge it. Messages received in the meantime are dropped.
+    pub fn terminate<'msg>(&mut self, reason: DisconnectStatusCode) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        match self.state {
+            ShareeState::Connection | ShareeState::Active => {
+                let status = NowStatus::builder(reason).build();
+                events.push(SMEvent::PacketToSend(NowPacket::from_message(NowTerminateMsg::new(
+                    status,
+                ))));
+                self.terminate_reason = Some(reason);
+                if let Some(id) = self.channel_stats_timer.take() {
+                    events.push(SMEvent::CancelTimer(id));
+                }
+                self.h_transition_state(&mut events, ShareeState::Terminating);
+            }
+            ShareeState::Terminating | ShareeState::Final => events.push(SMEvent::warn(
+                ProtoErrorKind::Sharee(self.state),
+                "session is already terminated or terminating",
+            )),
+        }
+        self.h_unpack(events)
+    }
+
+    /// Snapshot of what was negotiated with the peer so far.
+    pub fn session_info(&self) -> SessionInfo {
+        SessionInfo {
+            version: self.sm_data.extra_get::<NegotiatedVersion>().copied(),
+            auth_type: self.auth_type,
+            channels: self.channels_ctx.iter().map(|(id, name)| (id, name.clone())).collect(),
+            capabilities: self.sm_data.extra_get::<NegotiatedCapabilities>().cloned(),
+            association: self.sm_data.extra_get::<AssociationInfo>().cloned(),
+            os_info: self.system.os_info().cloned(),
+        }
+    }
+
+    /// State of the sharee itself, see `to_dot` for the connection sequence and channels.
+    pub fn state_info(&self) -> StateInfo {
+        match self.state {
+            ShareeState::Connection => StateInfo::new("Connection", &["Active", "Final"]),
+            ShareeState::Active => StateInfo::new("Active", &["Terminating", "Final"]),
+            ShareeState::Terminating => StateInfo::new("Terminating", &["Final"]),
+            ShareeState::Final => StateInfo::new("Final", &[]),
+        }
+    }
+
+    /// Renders the current state of the sharee, of its connection sequence and of each virtual
+    /// channel as a Graphviz dot graph, for diagnostics.
+    ///
+    /// Every state machine is a cluster in which the current state is filled, with edges to the
+    /// states reachable from it. Clusters of channels stopped by the peer are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph sharee {\n    node [shape=box];\n");
+        h_write_dot_cluster(&mut dot, "sharee", "sharee", Some(self.state_info()), false);
+        h_write_dot_cluster(
+            &mut dot,
+            "connection",
+            "connection sequence",
+            self.connection_seq.state_info(),
+            false,
+        );
+        for (idx, (name, info)) in self.channels_manager.state_infos().into_iter().enumerate() {
+            let stopped = self.channels_manager.is_stopped(&name);
+            h_write_dot_cluster(&mut dot, &format!("channel{}", idx), name.as_str(), info, stopped);
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn get_access_control(&self) -> &AccessControlSM {
+        &self.access_control
+    }
+
+    /// Effective state of an access control, as last notified by the peer.
+    pub fn access_state(&self, code: AccessControlCode) -> AccessState {
+        self.access_control.access_state(code)
+    }
+
+    /// Asks the peer for elevated access. The outcome is reported by an `AccessEvent`.
+    pub fn request_access<'msg>(&mut self, code: AccessControlCode, timeout: u16) -> Vec<SMEvent<'msg>> {
+        let msg = self
+            .h_check_active()
+            .and_then(|_| self.access_control.request_access(code, timeout));
+        self.h_access_msg_events(msg)
+    }
+
+    /// Answers an access request from the peer (`AccessEvent::Requested`).
+    pub fn respond_access<'msg>(&mut self, code: AccessControlCode, allow: bool) -> Vec<SMEvent<'msg>> {
+        let msg = self
+            .h_check_active()
+            .and_then(|_| self.access_control.respond(code, allow));
+        self.h_access_msg_events(msg)
+    }
+
+    pub fn get_sharing(&self) -> &SharingSM {
+        &self.sharing
+    }
+
+    /// Suspends the session, `message` is displayed to the peer.
+    pub fn relinquish_control<'msg>(&mut self, message: &str) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        let msg = self
+            .h_check_active()
+            .and_then(|_| self.sharing.relinquish_control(&mut events, message));
+        Self::h_push_msg_event(&mut events, msg);
+        self.h_unpack(events)
+    }
+
+    /// Resumes a session suspended by either end.
+    pub fn reclaim_control<'msg>(&mut self) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        let msg = self
+            .h_check_active()
+            .and_then(|_| self.sharing.reclaim_control(&mut events));
+        Self::h_push_msg_event(&mut events, msg);
+        self.h_unpack(events)
+    }
+
+    pub fn get_system_actions(&self) -> &SystemActions {
+        &self.system
+    }
+
+    /// Queries the remote OS infos. The answer is reported by a `SystemEvent::OsInfo` event.
+    pub fn query_os_info<'msg>(&mut self) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        let msg = self.h_check_active().map(|_| self.system.query_os_info());
+        Self::h_push_msg_event(&mut events, msg);
+        self.h_unpack(events)
+    }
+
+    /// Shuts the remote host down, provided access was granted by the peer.
+    pub fn shutdown_remote<'msg>(&mut self, flags: ShutdownFlags, timeout: u32, message: &str) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        let msg = self.h_check_active().and_then(|_| {
+            self.system
+                .shutdown_remote(&self.access_control, flags, timeout, message)
+        });
+        Self::h_push_msg_event(&mut events, msg);
+        self.h_unpack(events)
+    }
+
+    pub fn get_surfaces(&self) -> &SurfaceSM {
+        &self.surface
+    }
+
+    /// Displays the given surface only. The outcome is reported by a `SurfaceEvent`.
+    pub fn select_surface<'msg>(&mut self, surface_id: u16) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        let msg = self
+            .h_check_active()
+            .and_then(|_| self.surface.select_surface(surface_id));
+        Self::h_push_msg_event(&mut events, msg);
+        self.h_unpack(events)
+    }
+
+    /// Maps surfaces onto local outputs. The outcome is reported by a `SurfaceEvent`.
+    pub fn map_surfaces<'msg>(&mut self, mappings: Vec<NowSurfaceMap>) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        let msg = self.h_check_active().map(|_| self.surface.map_surfaces(mappings));
+        Self::h_push_msg_event(&mut events, msg);
+        self.h_unpack(events)
+    }
+
+    pub fn get_display(&self) -> &DisplaySM {
+        &self.display
+    }
+
+    /// Asks the server to send a surface again, resuming its updates if they were suppressed.
+    pub fn request_full_refresh<'msg>(&mut self, surface_id: u16) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        let msg = self
+            .h_check_active()
+            .map(|_| self.display.request_full_refresh(surface_id));
+        Self::h_push_msg_event(&mut events, msg);
+        self.h_unpack(events)
+    }
+
+    /// Asks the server to stop sending updates of a surface, until a refresh is requested.
+    pub fn suppress_updates<'msg>(&mut self, surface_id: u16) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        let msg = self.h_check_active().map(|_| self.display.suppress_updates(surface_id));
+        Self::h_push_msg_event(&mut events, msg);
+        self.h_unpack(events)
+    }
+
+    /// Acknowledges the frames completed so far, when built with `ShareeBuilder::manual_frame_ack`.
+    pub fn ack_frames<'msg>(&mut self) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        for msg in self.display.ack_frames() {
+            events.push(SMEvent::PacketToSend(NowPacket::from_message(msg)));
+        }
+        self.h_unpack(events)
+    }
+
+    /// Sender queuing messages on a virtual channel from the application, see `ChannelCommandSender`.
+    pub fn channel_command_sender<T>(&self, name: &ChannelName) -> Option<ChannelCommandSender<T>>
+    where
+        T: Into<NowVirtualChannel<'static>>,
+    {
+        self.channels_manager.command_sender(name)
+    }
+
+    /// Sends a message on an open virtual channel, see `ChannelsManager::send_on`.
+    pub fn send_on<'msg>(&mut self, name: &ChannelName, msg: impl Into<NowVirtualChannel<'msg>>) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        match self
+            .h_check_active()
+            .and_then(|_| self.channels_manager.send_on(&self.channels_ctx, name, msg))
+        {
+            Ok(packet) => events.push(SMEvent::PacketToSend(packet)),
+            Err(e) => events.push(SMEvent::Error(e)),
+        }
+        self.h_unpack(events)
+    }
+
+    /// Sends a message on a given instance of a `MULTIPLE` virtual channel, see
+    /// `ChannelsManager::send_on_instance`.
+    pub fn send_on_instance<'msg>(
+        &mut self,
+        channel_id: u8,
+        msg: impl Into<NowVirtualChannel<'msg>>,
+    ) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        match self.h_check_active().and_then(|_| {
+            self.channels_manager
+                .send_on_instance(&self.channels_ctx, channel_id, msg)
+        }) {
+            Ok(packet) => events.push(SMEvent::PacketToSend(packet)),
+            Err(e) => events.push(SMEvent::Error(e)),
+        }
+        self.h_unpack(events)
+    }
+
+    /// Traffic on a virtual channel, see `ChannelsManager::channel_stats`.
+    pub fn channel_stats(&self, name: &ChannelName) -> Option<&ChannelStats> {
+        self.channels_manager.channel_stats(name)
+    }
+
+    /// Ids every instance of the channel was opened with.
+    pub fn channel_instances(&self, name: &ChannelName) -> Vec<u8> {
+        self.channels_ctx.get_ids_by_channel(name).collect()
+    }
+
+    /// Sends a raw payload on every open virtual channel, see `ChannelsManager::broadcast`.
+    pub fn broadcast<'msg>(&mut self, payload: &'msg [u8]) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        match self.h_check_active() {
+            Ok(()) => {
+                for packet in self.channels_manager.broadcast(&self.channels_ctx, payload) {
+                    events.push(SMEvent::PacketToSend(packet));
+                }
+            }
+            Err(e) => events.push(SMEvent::Error(e)),
+        }
+        self.h_unpack(events)
     }
 
     pub fn get_channels_ctx(&self) -> &VirtChannelsCtx {
         &self.channels_ctx
     }
 
+    pub fn get_message_registry(&self) -> &MessageRegistry {
+        &self.registry
+    }
+
+    /// Context to decode received packets with: version and peer capabilities once negotiated,
+    /// open channels and registered custom messages.
+    ///
+    /// The local version is used until the handshake is done.
+    pub fn decode_ctx(&self) -> DecodeCtx<'_> {
+        let version = self
+            .sm_data
+            .extra_get::<NegotiatedVersion>()
+            .map_or(self.sm_data.version, NegotiatedVersion::effective);
+        let ctx = DecodeCtx::new(version)
+            .with_channels(&self.channels_ctx)
+            .with_registry(&self.registry);
+        match self.sm_data.extra_get::<PeerCapabilities>() {
+            Some(capabilities) => ctx.with_capabilities(&capabilities.0),
+            None => ctx,
+        }
+    }
+
+    pub fn get_extensions(&self) -> Option<&ExtensionRegistry> {
+        self.sm_data.extra_get()
+    }
+
+    /// Takes back the extension registry, e.g. to keep peer knowledge for the next connection.
+    pub fn take_extensions(&mut self) -> Option<ExtensionRegistry> {
+        self.sm_data.extra_remove()
+    }
+
+    pub fn get_metrics_mut(&mut self) -> Option<&mut dyn Metrics> {
+        self.metrics.as_mut().map(MetricsReporter::metrics_mut)
+    }
+
+    /// Registers a tap after the ones already registered, see the [`tap`](../tap/index.html) module.
+    pub fn add_packet_tap<T: PacketTap + 'static>(&mut self, tap: T) {
+        self.taps.push(Box::new(tap));
+    }
+
+    /// Reports bytes received for this sharee that couldn't be decoded into a packet.
+    pub fn report_decode_error(&mut self, error: &ProtoError) {
+        if let Some(reporter) = &mut self.metrics {
+            reporter.metrics_mut().decode_error(error);
+        }
+    }
+
     fn h_check_for_fatal(&mut self, events: &mut SMEvents<'_>) {
         if events.peek().iter().any(|e| matches!(e, SMEvent::Fatal(_))) {
             log::trace!("A fatal error occurred. Set sharee state to final state.");
@@ -138,35 +666,190 @@ where
         }
     }
 
+    fn h_track_auth_type(&mut self, events: &SMEvents<'_>) {
+        for event in events.peek() {
+            if let SMEvent::PacketToSend(packet) = event {
+                match &packet.body {
+                    NowBody::Message(NowMessage::Authenticate(NowAuthenticateMsg::Token(msg))) => {
+                        self.auth_type = Some(msg.auth_type)
+                    }
+                    NowBody::Message(NowMessage::Authenticate(NowAuthenticateMsg::OwnedToken(msg))) => {
+                        self.auth_type = Some(msg.auth_type)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn h_on_terminate(&mut self, events: &mut SMEvents<'_>, msg: &NowTerminateMsg) {
+        let disconnected = match self.terminate_reason.take() {
+            Some(reason) => {
+                log::trace!("terminate acknowledged by peer");
+                Disconnected { reason, by_peer: false }
+            }
+            None => {
+                log::trace!("session terminated by peer: {}", msg.status.code());
+                events.push(SMEvent::PacketToSend(NowPacket::from_message(msg.clone())));
+                Disconnected {
+                    reason: msg.status.code(),
+                    by_peer: true,
+                }
+            }
+        };
+        events.push(SMEvent::data(disconnected));
+        if let Some(id) = self.channel_stats_timer.take() {
+            events.push(SMEvent::CancelTimer(id));
+        }
+        self.h_transition_state(events, ShareeState::Final);
+    }
+
     fn h_go_to_active_state(&mut self, events: &mut SMEvents<'_>) {
         log::trace!("enter active state.");
         self.h_transition_state(events, ShareeState::Active);
-        for def in &self.sm_data.channel_defs {
-            self.channels_ctx.insert(def.flags.value as u8, def.name.clone());
+        for def in self.sm_data.channel_defs.clone() {
+            self.h_open_channel(&def);
         }
+        self.channels_manager
+            .bind_channels(&mut self.sm_data, events, &self.channels_ctx);
         log::debug!("virtual channels context: {:#?}", self.channels_ctx);
+        if let Some(negotiated) = self.sm_data.extra_get::<NegotiatedCapabilities>() {
+            self.access_control = AccessControlSM::new_with_access_controls(negotiated.access_controls.clone());
+        }
+        self.sm_data.extra_insert(self.access_control.access_states());
+        if let Some(delay) = self.channel_stats_interval {
+            let id = self.sm_data.new_timer_id();
+            self.channel_stats_timer = Some(id);
+            events.push(SMEvent::SetTimer(TimerRequest { id, delay }));
+        }
+    }
+
+    /// Emits the stats of every channel and arms the timer for the next report.
+    fn h_report_channel_stats(&mut self, events: &mut SMEvents<'_>) {
+        if let (Some(id), Some(delay)) = (self.channel_stats_timer, self.channel_stats_interval) {
+            self.channels_manager.advance_time(delay);
+            for stats in self.channels_manager.all_channel_stats() {
+                events.push(SMEvent::data(stats.clone()));
+            }
+            events.push(SMEvent::SetTimer(TimerRequest { id, delay }));
+        }
+    }
+
+    fn h_open_channel(&mut self, def: &NowChannelDef) {
+        let channel_id = def.flags.value as u8;
+        self.channels_ctx.insert(channel_id, def.name.clone());
+        if self.compressed_channels.contains(&def.name) {
+            self.channels_ctx
+                .set_compression(channel_id, Compression::negotiate(def.flags));
+        }
+    }
+
+    fn h_check_active(&self) -> Result<()> {
+        if self.state == ShareeState::Active {
+            Ok(())
+        } else {
+            Err(ProtoError::new(ProtoErrorKind::Sharee(self.state)).with_desc("only available in active state"))
+        }
+    }
+
+    fn h_access_msg_events<'msg>(&mut self, msg: Result<NowAccessMsg<'static>>) -> Vec<SMEvent<'msg>> {
+        let mut events = SMEvents::new();
+        Self::h_push_msg_event(&mut events, msg);
+        self.h_unpack(events)
+    }
+
+    fn h_push_msg_event<'msg>(events: &mut SMEvents<'msg>, msg: Result<impl Into<NowMessage<'static>>>) {
+        match msg {
+            Ok(msg) => events.push(SMEvent::PacketToSend(NowPacket::from_message(msg))),
+            Err(e) => events.push(SMEvent::Error(e)),
+        }
     }
 
     fn h_map_channels_manager_result<'msg>(&self, events: &mut SMEvents<'msg>, to_send: ChannelResponses<'msg>) {
-        for (name, virt_rsp) in to_send.unpack() {
-            match self.channels_ctx.get_id_by_channel(&name) {
-                Some(channel_id) => events.push(SMEvent::PacketToSend(NowPacket::from_virt_channel(
-                    virt_rsp, channel_id,
-                ))),
-                None => events.push(SMEvent::warn(
-                    ProtoErrorKind::ChannelsManager,
-                    format!("channel id for {:?} not found in channels context", name),
-                )),
+        for (channel_id, name, virt_rsp) in self.channels_manager.prioritize_with_ids(to_send) {
+            let packet = match channel_id {
+                Some(channel_id) => self
+                    .channels_manager
+                    .send_on_instance(&self.channels_ctx, channel_id, virt_rsp),
+                None => self.channels_manager.send_on(&self.channels_ctx, &name, virt_rsp),
+            };
+            match packet {
+                Ok(packet) => events.push(SMEvent::PacketToSend(packet)),
+                Err(e) => events.push(SMEvent::Warn(e)),
             }
         }
     }
 
+    fn h_unpack<'msg>(&mut self, events: SMEvents<'msg>) -> Vec<SMEvent<'msg>> {
+        let mut events = match &self.policy_audits {
+            Some(audits) => {
+                let mut audit_events = Vec::new();
+                while let Some(audit) = audits.pop() {
+                    audit_events.push(SMEvent::data(audit));
+                }
+                audit_events.extend(events.unpack());
+                audit_events
+            }
+            None => events.unpack(),
+        };
+        if !self.taps.is_empty() {
+            let taps = &mut self.taps;
+            events = events
+                .into_iter()
+                .filter_map(|event| match event {
+                    SMEvent::PacketToSend(packet) => match run_taps(taps, PacketDirection::Sent, (&packet).into()) {
+                        TapVerdict::Pass => Some(SMEvent::PacketToSend(packet)),
+                        TapVerdict::Drop => None,
+                        TapVerdict::Replace(packet) => Some(SMEvent::PacketToSend(packet)),
+                    },
+                    event => Some(event),
+                })
+                .collect();
+        }
+        for event in events.iter() {
+            if let SMEvent::PacketToSend(packet) = event {
+                self.channels_manager.record_sent(packet);
+            }
+        }
+        if let Some(reporter) = &mut self.metrics {
+            reporter.on_events(&events);
+        }
+        events
+    }
+
     fn h_transition_state(&mut self, events: &mut SMEvents<'_>, state: ShareeState) {
         self.state = state;
         events.push(SMEvent::transition(state));
     }
 }
 
+fn h_write_dot_cluster(dot: &mut String, id: &str, label: &str, info: Option<StateInfo>, dashed: bool) {
+    use core::fmt::Write as _;
+
+    let label = label.replace('"', "\\\"");
+    let style = if dashed { "dashed" } else { "solid" };
+    // writing to a string can't fail
+    let _ = writeln!(dot, "    subgraph cluster_{} {{", id);
+    let _ = writeln!(dot, "        label=\"{}\";\n        style={};", label, style);
+    match info {
+        Some(info) => {
+            let _ = writeln!(
+                dot,
+                "        \"{}.{}\" [label=\"{}\", style=filled];",
+                id, info.name, info.name
+            );
+            for next in info.transitions {
+                let _ = writeln!(dot, "        \"{}.{}\" [label=\"{}\"];", id, next, next);
+                let _ = writeln!(dot, "        \"{}.{}\" -> \"{}.{}\";", id, info.name, id, next);
+            }
+        }
+        None => {
+            let _ = writeln!(dot, "        \"{}.unknown\" [label=\"?\"];", id);
+        }
+    }
+    dot.push_str("    }\n");
+}
+
 // builder
 
 pub struct ShareeBuilder<ConnectionSeq>
@@ -174,10 +857,24 @@ where
     ConnectionSeq: ConnectionSM,
 {
     connection_sm: ConnectionSeq,
+    version: Option<NowVersion>,
     supported_auths: Vec<AuthType>,
+    negotiate_flags: Option<NegotiateFlags>,
     capabilities: Vec<NowCapset<'static>>,
     channels_to_open: Vec<NowChannelDef>,
     channels_manager: ChannelsManager,
+    extensions: Option<ExtensionRegistry>,
+    sharing: SharingSM,
+    metrics: Option<Box<dyn Metrics>>,
+    response_timeout: Option<Duration>,
+    require_common_codec: bool,
+    registry: MessageRegistry,
+    display: DisplaySM,
+    compressed_channels: Vec<ChannelName>,
+    channel_stats_interval: Option<Duration>,
+    session_token: Option<NowAuthSessionToken>,
+    taps: Vec<Box<dyn PacketTap>>,
+    policy_audits: Option<SharedQueue<PolicyAudit>>,
 }
 
 impl<ConnectionSeq> ShareeBuilder<ConnectionSeq>
@@ -187,10 +884,32 @@ where
     pub fn new(connection_sm: ConnectionSeq) -> Self {
         Self {
             connection_sm,
+            version: None,
             supported_auths: Vec::new(),
+            negotiate_flags: None,
             capabilities: Vec::new(),
             channels_to_open: Vec::new(),
             channels_manager: ChannelsManager::default(),
+            extensions: None,
+            sharing: SharingSM::default(),
+            metrics: None,
+            response_timeout: None,
+            require_common_codec: false,
+            registry: MessageRegistry::new(),
+            display: DisplaySM::new(),
+            compressed_channels: Vec::new(),
+            channel_stats_interval: None,
+            session_token: None,
+            taps: Vec::new(),
+            policy_audits: None,
+        }
+    }
+
+    /// Protocol version advertised during the handshake, the current one by default.
+    pub fn version(self, version: NowVersion) -> Self {
+        Self {
+            version: Some(version),
+            ..self
         }
     }
 
@@ -201,8 +920,20 @@ where
         }
     }
 
-    pub fn capabilities(self, capabilities: Vec<NowCapset<'static>>) -> Self {
-        Self { capabilities, ..self }
+    /// Flags sent during the negotiate phase, in place of the default extended SRP flag.
+    pub fn negotiate_flags(self, negotiate_flags: NegotiateFlags) -> Self {
+        Self {
+            negotiate_flags: Some(negotiate_flags),
+            ..self
+        }
+    }
+
+    /// Capsets to advertise, as a list or a `CapabilitiesBuilder`.
+    pub fn capabilities(self, capabilities: impl Into<Vec<NowCapset<'static>>>) -> Self {
+        Self {
+            capabilities: capabilities.into(),
+            ..self
+        }
     }
 
     pub fn channels_to_open(self, channels_to_open: Vec<ChannelName>) -> Self {
@@ -219,13 +950,809 @@ where
         }
     }
 
-    pub fn build(self) -> Sharee<ConnectionSeq> {
+    /// Enables extensions negotiation. The registry may hold peer knowledge restored from a previous connection.
+    pub fn extensions(self, extensions: ExtensionRegistry) -> Self {
+        Self {
+            extensions: Some(extensions),
+            ..self
+        }
+    }
+
+    pub fn sharing_callback<C: SharingCallbackTrait + Send + 'static>(self, user_callback: C) -> Self {
+        Self {
+            sharing: SharingSM::new(user_callback),
+            ..self
+        }
+    }
+
+    /// Reports packets, channel bytes and state transitions to `metrics`.
+    pub fn metrics<M: Metrics + 'static>(self, metrics: M) -> Self {
+        Self {
+            metrics: Some(Box::new(metrics)),
+            ..self
+        }
+    }
+
+    /// Shows packets to `tap` before they are processed or sent, see the [`tap`](../tap/index.html)
+    /// module. Taps run in registration order.
+    pub fn packet_tap<T: PacketTap + 'static>(mut self, tap: T) -> Self {
+        self.taps.push(Box::new(tap));
+        self
+    }
+
+    /// Applies `policy` to received messages, see the [`policy`](../policy/index.html) module.
+    /// The policy runs as a packet tap, after the taps registered before it.
+    pub fn policy(mut self, mut policy: Policy) -> Self {
+        let audits = self.policy_audits.get_or_insert_with(SharedQueue::default);
+        policy.set_audits(audits.clone());
+        self.taps.push(Box::new(policy));
+        self
+    }
+
+    /// Fails the connection sequence if the peer doesn't answer within `timeout`. The timer is
+    /// requested with `SMEvent::SetTimer`, so the embedder has to arm it.
+    pub fn response_timeout(self, timeout: Duration) -> Self {
+        Self {
+            response_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Fails the connection sequence if the server supports none of the codecs advertised in
+    /// the update capset, instead of only warning about it.
+    pub fn require_common_codec(self) -> Self {
+        Self {
+            require_common_codec: true,
+            ..self
+        }
+    }
+
+    /// Custom messages received in active state are then emitted as `RegisteredMessage` data
+    /// events, provided packets are decoded with `Sharee::decode_ctx`.
+    pub fn message_registry(self, registry: MessageRegistry) -> Self {
+        Self { registry, ..self }
+    }
+
+    /// Graphics frames are then acknowledged by `Sharee::ack_frames` instead of on reception,
+    /// which slows the server down to the application rendering pace.
+    pub fn manual_frame_ack(self) -> Self {
+        Self {
+            display: DisplaySM::new_with_manual_ack(),
+            ..self
+        }
+    }
+
+    /// Offers the compression methods enabled at build time for these channels, see the
+    /// `compression` module. Payloads are left uncompressed if the server doesn't pick one.
+    pub fn compressed_channels(self, compressed_channels: Vec<ChannelName>) -> Self {
+        Self {
+            compressed_channels,
+            ..self
+        }
+    }
+
+    /// Emits a `ChannelStats` data event per active channel every `interval` once the session is
+    /// active. The timer is requested with `SMEvent::SetTimer`, so the embedder has to arm it.
+    pub fn channel_stats_interval(self, interval: Duration) -> Self {
+        Self {
+            channel_stats_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Connects with a one-time token instead of interactive credentials, `SessionTokenSM` has to
+    /// be the authentication state machine. Also sets `NegotiateFlags::AUTH_TOKEN`.
+    pub fn session_token(self, token: NowAuthSessionToken) -> Self {
+        Self {
+            session_token: Some(token),
+            ..self
+        }
+    }
+
+    /// Builds the sharee, provided the configuration is consistent.
+    pub fn build(self) -> core::result::Result<Sharee<ConnectionSeq>, ShareeConfigError> {
+        let problems = self.h_check();
+        if problems.is_empty() {
+            Ok(self.build_unchecked())
+        } else {
+            Err(ShareeConfigError { problems })
+        }
+    }
+
+    /// Builds the sharee without checking the configuration.
+    pub fn build_unchecked(self) -> Sharee<ConnectionSeq> {
+        let compressed_channels = self.compressed_channels;
+        let mut channels_to_open = self.channels_to_open;
+        for def in channels_to_open
+            .iter_mut()
+            .filter(|def| compressed_channels.contains(&def.name))
+        {
+            def.flags = ChannelDefFlags::from(def.flags.value | Compression::offer_flags());
+        }
+
+        let mut sm_data = SMData::new(self.supported_auths, self.capabilities, channels_to_open);
+        if let Some(version) = self.version {
+            sm_data.version = version;
+        }
+        if let Some(negotiate_flags) = self.negotiate_flags {
+            sm_data.negotiate_flags = negotiate_flags;
+        }
+        if self.session_token.is_some() {
+            sm_data.negotiate_flags.set_auth_token();
+        }
+        sm_data.session_token = self.session_token;
+        if let Some(extensions) = self.extensions {
+            sm_data.extra_insert(extensions);
+        }
+        sm_data.response_timeout = self.response_timeout;
+        sm_data.require_common_codec = self.require_common_codec;
+
         Sharee {
             state: ShareeState::Connection,
             connection_seq: self.connection_sm,
             channels_manager: self.channels_manager,
-            sm_data: SMData::new(self.supported_auths, self.capabilities, self.channels_to_open),
+            access_control: AccessControlSM::new(),
+            sharing: self.sharing,
+            system: SystemActions::new(),
+            surface: SurfaceSM::new(),
+            display: self.display,
+            sm_data,
             channels_ctx: VirtChannelsCtx::new(),
+            terminate_reason: None,
+            metrics: self.metrics.map(MetricsReporter::new),
+            registry: self.registry,
+            auth_type: None,
+            compressed_channels,
+            channel_stats_interval: self.channel_stats_interval,
+            channel_stats_timer: None,
+            taps: self.taps,
+            policy_audits: self.policy_audits,
+        }
+    }
+
+    fn h_check(&self) -> Vec<ShareeConfigProblem> {
+        let mut problems = Vec::new();
+
+        // a session token doesn't need any other authentication method
+        if self.supported_auths.is_empty() && self.session_token.is_none() {
+            problems.push(ShareeConfigProblem::NoSupportedAuth);
+        }
+
+        for (i, capset) in self.capabilities.iter().enumerate() {
+            let name = capset.name_as_str();
+            if self.capabilities[..i].iter().any(|other| other.name_as_str() == name) {
+                problems.push(ShareeConfigProblem::DuplicateCapset(String::from(name)));
+            }
+        }
+
+        for (i, def) in self.channels_to_open.iter().enumerate() {
+            if self.channels_to_open[..i].iter().any(|other| other.name == def.name) {
+                problems.push(ShareeConfigProblem::DuplicateChannel(def.name.clone()));
+            } else if !self.channels_manager.has_sm(&def.name) {
+                problems.push(ShareeConfigProblem::ChannelWithoutSM(def.name.clone()));
+            }
+        }
+
+        problems
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShareeConfigProblem {
+    /// At least one authentication method is required to connect.
+    NoSupportedAuth,
+    /// A capability set is listed more than once.
+    DuplicateCapset(String),
+    /// A channel to open is listed more than once.
+    DuplicateChannel(ChannelName),
+    /// A channel to open has no state machine registered in the channels manager.
+    ChannelWithoutSM(ChannelName),
+}
+
+impl fmt::Display for ShareeConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShareeConfigProblem::NoSupportedAuth => write!(f, "no supported authentication method"),
+            ShareeConfigProblem::DuplicateCapset(name) => write!(f, "capability set {} listed more than once", name),
+            ShareeConfigProblem::DuplicateChannel(name) => write!(f, "channel {:?} listed more than once", name),
+            ShareeConfigProblem::ChannelWithoutSM(name) => {
+                write!(f, "no state machine registered for channel {:?}", name)
+            }
+        }
+    }
+}
+
+/// Returned by `ShareeBuilder::build` when the configuration is inconsistent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShareeConfigError {
+    pub problems: Vec<ShareeConfigProblem>,
+}
+
+impl fmt::Display for ShareeConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid sharee configuration")?;
+        for (i, problem) in self.problems.iter().enumerate() {
+            write!(f, "{} {}", if i == 0 { ":" } else { ";" }, problem)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ShareeConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{AccessCapset, BodyType, MouseCapset, MouseCapsetFlags, MouseMode};
+    use crate::sm::{ChatChannelSM, ChatData, DummyChatChannelCallback, DummyConnectionSM};
+    use alloc::boxed::Box;
+    use alloc::string::ToString;
+
+    #[test]
+    fn build_validation() {
+        let mouse = || NowCapset::Mouse(MouseCapset::new(MouseMode::Primary, MouseCapsetFlags::new_empty()));
+        let err = Sharee::builder(DummyConnectionSM)
+            .capabilities(vec![
+                mouse(),
+                NowCapset::Access(AccessCapset::new_with_access_controls(Vec::new())),
+                mouse(),
+            ])
+            .channels_to_open(vec![ChannelName::Chat, ChannelName::Clipboard, ChannelName::Chat])
+            .channels_manager(ChannelsManager::new().with_sm(ChatChannelSM::new(
+                ChatData::new(),
+                Box::new(|| 0),
+                DummyChatChannelCallback,
+            )))
+            .build()
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            err.problems,
+            vec![
+                ShareeConfigProblem::NoSupportedAuth,
+                ShareeConfigProblem::DuplicateCapset("NowMouse".to_string()),
+                ShareeConfigProblem::ChannelWithoutSM(ChannelName::Clipboard),
+                ShareeConfigProblem::DuplicateChannel(ChannelName::Chat),
+            ]
+        );
+        assert!(err
+            .to_string()
+            .starts_with("invalid sharee configuration: no supported"));
+
+        let sharee = Sharee::builder(DummyConnectionSM)
+            .supported_auths(vec![AuthType::PFP])
+            .capabilities(vec![mouse()])
+            .build()
+            .unwrap();
+        assert_eq!(sharee.get_state(), ShareeState::Connection);
+    }
+
+    #[test]
+    fn response_timeout() {
+        use crate::message::NowHandshakeMsg;
+        use crate::sm::ClientConnectionSeqSM;
+
+        let timer = |events: &[SMEvent<'_>]| {
+            events.iter().find_map(|e| match e {
+                SMEvent::SetTimer(request) => Some(*request),
+                _ => None,
+            })
+        };
+
+        let mut sharee = Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM)).build_unchecked();
+        assert_eq!(timer(&sharee.update_without_body()), None);
+
+        let delay = Duration::from_secs(5);
+        let mut sharee = Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM))
+            .response_timeout(delay)
+            .build_unchecked();
+        let TimerRequest { id, delay: requested } = timer(&sharee.update_without_body()).unwrap();
+        assert_eq!(requested, delay);
+
+        // rearmed with the same id once the negotiate message is sent
+        let events = sharee.update_with_body(&NowBody::Message(NowMessage::Handshake(NowHandshakeMsg::new_success())));
+        assert!(!events.iter().any(|e| matches!(e, SMEvent::CancelTimer(_))));
+        assert_eq!(timer(&sharee.update_without_body()).map(|request| request.id), Some(id));
+
+        let events = sharee.on_timer_expired(TimerId(id.0 + 1));
+        assert!(events.is_empty());
+        assert_eq!(sharee.get_state(), ShareeState::Connection);
+
+        let events = sharee.on_timer_expired(id);
+        assert!(events.iter().any(|e| matches!(e, SMEvent::Fatal(_))));
+        assert_eq!(sharee.get_state(), ShareeState::Final);
+    }
+
+    #[test]
+    fn session_info() {
+        use crate::message::NowAuthenticateTokenMsgOwned;
+        use crate::version::NowVersion;
+
+        /// Sends a PFP token and terminates.
+        struct TokenSM(bool);
+
+        impl ConnectionSM for TokenSM {
+            fn is_terminated(&self) -> bool {
+                self.0
+            }
+
+            fn waiting_for_packet(&self) -> bool {
+                false
+            }
+
+            fn update_without_message<'msg>(&mut self, _: &mut SMData, events: &mut SMEvents<'msg>) {
+                let msg = NowAuthenticateTokenMsgOwned::new(AuthType::PFP, Vec::new());
+                events.push(SMEvent::PacketToSend(NowPacket::from_message(
+                    NowMessage::Authenticate(msg.into()),
+                )));
+                self.0 = true;
+            }
+
+            fn update_with_message<'msg: 'a, 'a>(
+                &mut self,
+                _: &mut SMData,
+                _: &mut SMEvents<'msg>,
+                _: &'a NowMessage<'msg>,
+            ) {
+            }
+        }
+
+        let mut sharee = Sharee::builder(TokenSM(false))
+            .channels_to_open(vec![ChannelName::Chat])
+            .build_unchecked();
+        let info = sharee.session_info();
+        assert!(info.version.is_none());
+        assert!(info.auth_type.is_none());
+        assert!(info.channels.is_empty());
+
+        let version = NegotiatedVersion {
+            local: NowVersion::CURRENT,
+            peer: NowVersion::CURRENT,
+        };
+        sharee.sm_data.extra_insert(version);
+        sharee.update_without_body();
+        assert_eq!(sharee.get_state(), ShareeState::Active);
+
+        let info = sharee.session_info();
+        assert_eq!(info.version, Some(version));
+        assert_eq!(info.auth_type, Some(AuthType::PFP));
+        assert_eq!(info.channels, vec![(0, ChannelName::Chat)]);
+        assert!(info.capabilities.is_none());
+        assert!(info.os_info.is_none());
+    }
+
+    #[test]
+    fn decode_ctx_uses_negotiated_version() {
+        let mut sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+        assert_eq!(sharee.decode_ctx().version, NowVersion::CURRENT);
+
+        let peer = NowVersion::new(21, 1, 0);
+        sharee.sm_data.extra_insert(NegotiatedVersion {
+            local: NowVersion::CURRENT,
+            peer,
+        });
+        assert_eq!(sharee.decode_ctx().version, peer);
+    }
+
+    #[test]
+    fn channel_stats_reports() {
+        use crate::message::NowChatTextMsg;
+
+        let mut sharee = Sharee::builder(DummyConnectionSM)
+            .channels_to_open(vec![ChannelName::Chat])
+            .channel_stats_interval(Duration::from_secs(5))
+            .build_unchecked();
+        let id = sharee
+            .update_without_body()
+            .iter()
+            .find_map(|event| match event {
+                SMEvent::SetTimer(request) => Some(request.id),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(sharee.get_state(), ShareeState::Active);
+
+        let text = NowChatTextMsg::new(0, 0, "hello".parse().unwrap());
+        sharee.send_on(&ChannelName::Chat, text.clone());
+        sharee.update_with_body(&NowBody::VirtualChannel(text.into()));
+
+        let events = sharee.on_timer_expired(id);
+        let stats = events
+            .iter()
+            .find_map(|event| event.downcast_data::<ChannelStats>())
+            .unwrap();
+        assert_eq!(stats.name, ChannelName::Chat);
+        assert_eq!((stats.messages_in, stats.messages_out), (1, 1));
+        assert_eq!(stats.last_activity, Some(Duration::ZERO));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, SMEvent::SetTimer(request) if request.id == id)));
+
+        let events = sharee.terminate(DisconnectStatusCode::ByLocalUser);
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, SMEvent::CancelTimer(t) if *t == id)));
+
+        // terminated by peer
+        let mut sharee = Sharee::builder(DummyConnectionSM)
+            .channel_stats_interval(Duration::from_secs(5))
+            .build_unchecked();
+        let id = sharee
+            .update_without_body()
+            .iter()
+            .find_map(|event| match event {
+                SMEvent::SetTimer(request) => Some(request.id),
+                _ => None,
+            })
+            .unwrap();
+        let events = sharee.update_with_body(&NowBody::Message(NowMessage::Terminate(NowTerminateMsg::default())));
+        assert_eq!(sharee.get_state(), ShareeState::Final);
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, SMEvent::CancelTimer(t) if *t == id)));
+    }
+
+    #[test]
+    fn compression_negotiation() {
+        /// Checks the offer and answers it like a server would, then terminates.
+        struct OpenSM(Vec<NowChannelDef>);
+
+        impl ConnectionSM for OpenSM {
+            fn is_terminated(&self) -> bool {
+                self.0.is_empty()
+            }
+
+            fn waiting_for_packet(&self) -> bool {
+                false
+            }
+
+            fn update_without_message<'msg>(&mut self, data: &mut SMData, _: &mut SMEvents<'msg>) {
+                let offered: Vec<u32> = data.channel_defs.iter().map(|def| def.flags.value).collect();
+                assert_eq!(offered, [Compression::offer_flags(), 0, Compression::offer_flags()]);
+                data.channel_defs = core::mem::take(&mut self.0);
+            }
+
+            fn update_with_message<'msg: 'a, 'a>(
+                &mut self,
+                _: &mut SMData,
+                _: &mut SMEvents<'msg>,
+                _: &'a NowMessage<'msg>,
+            ) {
+            }
         }
+
+        let answer = |name, id, method: Compression| {
+            NowChannelDef::new_with_flags(name, ChannelDefFlags::from(id | method.flag()))
+        };
+        let mut sharee = Sharee::builder(OpenSM(vec![
+            answer(ChannelName::Chat, 1, Compression::Deflate),
+            // compression not offered
+            answer(ChannelName::Clipboard, 2, Compression::Zstd),
+            // server unaware of compression
+            answer(ChannelName::FileTransfer, 3, Compression::None),
+        ]))
+        .channels_to_open(vec![
+            ChannelName::Chat,
+            ChannelName::Clipboard,
+            ChannelName::FileTransfer,
+        ])
+        .compressed_channels(vec![ChannelName::Chat, ChannelName::FileTransfer])
+        .build_unchecked();
+        sharee.update_without_body();
+        assert_eq!(sharee.get_state(), ShareeState::Active);
+
+        let ctx = sharee.get_channels_ctx();
+        let deflate = if Compression::Deflate.is_available() {
+            Compression::Deflate
+        } else {
+            Compression::None
+        };
+        assert_eq!(ctx.get_compression(1), deflate);
+        assert_eq!(ctx.get_compression(2), Compression::None);
+        assert_eq!(ctx.get_compression(3), Compression::None);
+    }
+
+    #[test]
+    fn dot_export() {
+        use crate::sm::ClientConnectionSeqSM;
+
+        let sharee = Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM))
+            .channels_manager(ChannelsManager::new().with_sm(ChatChannelSM::new(
+                ChatData::new(),
+                Box::new(|| 0),
+                DummyChatChannelCallback,
+            )))
+            .build_unchecked();
+        assert_eq!(sharee.state_info().transitions, &["Active", "Final"]);
+
+        let dot = sharee.to_dot();
+        assert!(dot.starts_with("digraph sharee {"));
+        assert!(dot.contains("\"sharee.Connection\" [label=\"Connection\", style=filled];"));
+        assert!(dot.contains("\"connection.Handshake\" -> \"connection.Negotiate\";"));
+        assert!(dot.contains("label=\"NowChat\";"));
+        assert!(dot.contains("\"channel0.Initial\" -> \"channel0.Sync\";"));
+    }
+
+    #[test]
+    fn negotiate_flags() {
+        use crate::message::{MessageType, NowHandshakeMsg, NowNegotiateMsg};
+        use crate::sm::{ClientConnectionSeqSM, ConnectionEvent};
+        use crate::testing::MockPeer;
+
+        let peer = MockPeer::new()
+            .expect_message(MessageType::Handshake)
+            .respond(&NowPacket::from_message(NowHandshakeMsg::new_success()))
+            .unwrap()
+            .expect("negotiate with token flag", |packet, _| {
+                matches!(&packet.body, NowBody::Message(NowMessage::Negotiate(msg)) if msg.flags.auth_token())
+            })
+            .respond(&NowPacket::from_message(NowNegotiateMsg::new_with_auth_list(
+                NegotiateFlags::new_empty().set_srp_extended(),
+                vec![AuthType::SRP],
+            )))
+            .unwrap();
+
+        let mut sharee = Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM))
+            .supported_auths(vec![AuthType::SRP])
+            .negotiate_flags(NegotiateFlags::new_empty().set_srp_extended().set_auth_token())
+            .build_unchecked();
+        let events = peer.run(&mut sharee).unwrap();
+
+        let flags = events
+            .iter()
+            .find_map(|e| match e {
+                SMEvent::Connection(ConnectionEvent::NegotiateFlags(flags)) => Some(*flags),
+                _ => None,
+            })
+            .expect("negotiate flags event");
+        assert!(flags.srp_extended());
+        assert!(flags.local.auth_token());
+        assert!(!flags.auth_token());
+    }
+
+    #[test]
+    fn session_token() {
+        use crate::auth::token::SessionTokenError;
+        use crate::message::status::AuthStatusCode;
+        use crate::message::{
+            AuthentificationFailureFlags, MessageType, NowAuthenticateFailureMsg, NowAuthenticateMsg, NowHandshakeMsg,
+            NowNegotiateMsg, NowString256,
+        };
+        use crate::sm::{ClientConnectionSeqSM, SessionTokenSM};
+        use crate::testing::MockPeer;
+        use core::str::FromStr;
+
+        let token = NowAuthSessionToken::new(NowString256::from_str("0123-4567").unwrap())
+            .with_password(NowString256::from_str("kiosk").unwrap());
+        let sharee = || {
+            Sharee::builder(ClientConnectionSeqSM::new(SessionTokenSM::new()))
+                .session_token(token.clone())
+                .build()
+                .unwrap()
+        };
+        let token_error = |events: &[SMEvent<'_>]| {
+            events.iter().find_map(|e| match e {
+                SMEvent::Fatal(ProtoError {
+                    kind: ProtoErrorKind::SessionToken(e),
+                    ..
+                }) => Some(*e),
+                _ => None,
+            })
+        };
+
+        let failure = NowAuthenticateFailureMsg::new(
+            AuthentificationFailureFlags::new_empty(),
+            NowStatus::builder(AuthStatusCode::AccountExpired).build(),
+        );
+        let peer = MockPeer::new()
+            .expect_message(MessageType::Handshake)
+            .respond(&NowPacket::from_message(NowHandshakeMsg::new_success()))
+            .unwrap()
+            .expect("negotiate with token flag", |packet, _| {
+                matches!(&packet.body, NowBody::Message(NowMessage::Negotiate(msg)) if msg.flags.auth_token())
+            })
+            .respond(&NowPacket::from_message(NowNegotiateMsg::new(
+                NegotiateFlags::new_empty().set_auth_token(),
+            )))
+            .unwrap()
+            .expect("session token", |packet, _| match &packet.body {
+                NowBody::Message(NowMessage::Authenticate(NowAuthenticateMsg::OwnedToken(msg))) => {
+                    msg.auth_flags.session_token() && msg.auth_type == AuthType::None
+                }
+                _ => false,
+            })
+            .respond(&NowPacket::from_message(NowAuthenticateMsg::from(failure)))
+            .unwrap();
+        let events = peer.run(&mut sharee()).unwrap();
+        assert_eq!(
+            token_error(&events),
+            Some(SessionTokenError::Rejected {
+                status: AuthStatusCode::AccountExpired,
+                retry: false,
+            })
+        );
+
+        // server without token support
+        let peer = MockPeer::new()
+            .expect_message(MessageType::Handshake)
+            .respond(&NowPacket::from_message(NowHandshakeMsg::new_success()))
+            .unwrap()
+            .expect_message(MessageType::Negotiate)
+            .respond(&NowPacket::from_message(NowNegotiateMsg::default()))
+            .unwrap();
+        let events = peer.run(&mut sharee()).unwrap();
+        assert_eq!(token_error(&events), Some(SessionTokenError::NotSupported));
+    }
+
+    #[test]
+    fn terminate() {
+        use crate::message::MessageType;
+        use crate::testing::MockPeer;
+
+        let disconnected =
+            |events: &[SMEvent<'_>]| events.iter().find_map(|e| e.downcast_data::<Disconnected>().copied());
+
+        // local user leaving
+        let mut sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+        sharee.update_without_body();
+        assert_eq!(sharee.get_state(), ShareeState::Active);
+
+        let events = sharee.terminate(DisconnectStatusCode::ByLocalUser);
+        assert!(matches!(&events[0], SMEvent::PacketToSend(packet) if matches!(
+            &packet.body,
+            NowBody::Message(NowMessage::Terminate(msg)) if msg.status.code() == DisconnectStatusCode::ByLocalUser
+        )));
+        assert_eq!(sharee.get_state(), ShareeState::Terminating);
+        assert!(sharee.waiting_for_packet());
+
+        let ack = NowBody::Message(NowMessage::Terminate(NowTerminateMsg::default()));
+        let events = sharee.update_with_body(&ack);
+        assert_eq!(sharee.get_state(), ShareeState::Final);
+        assert_eq!(
+            disconnected(&events),
+            Some(Disconnected {
+                reason: DisconnectStatusCode::ByLocalUser,
+                by_peer: false,
+            })
+        );
+
+        // server shutting down, terminate is acknowledged
+        let status = NowStatus::builder(DisconnectStatusCode::SystemShutdown).build();
+        let peer = MockPeer::new()
+            .respond(&NowPacket::from_message(NowTerminateMsg::new(status)))
+            .unwrap()
+            .expect_message(MessageType::Terminate);
+        let mut sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+        let events = peer.run(&mut sharee).unwrap();
+        assert!(sharee.is_terminated());
+        assert_eq!(
+            disconnected(&events),
+            Some(Disconnected {
+                reason: DisconnectStatusCode::SystemShutdown,
+                by_peer: true,
+            })
+        );
+    }
+
+    #[test]
+    fn metrics() {
+        use crate::trace::PacketDirection;
+        use alloc::sync::Arc;
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorded {
+            received: Vec<(BodyType, usize)>,
+            sent: Vec<(BodyType, usize)>,
+            transitions: Vec<(ShareeState, Option<u64>)>,
+            decode_errors: usize,
+        }
+
+        struct TestMetrics {
+            now: u64,
+            recorded: Arc<Mutex<Recorded>>,
+        }
+
+        impl Metrics for TestMetrics {
+            fn now_ms(&mut self) -> Option<u64> {
+                self.now += 10;
+                Some(self.now)
+            }
+
+            fn packet_received(&mut self, body_type: BodyType, body_len: usize) {
+                self.recorded.lock().unwrap().received.push((body_type, body_len));
+            }
+
+            fn packet_sent(&mut self, body_type: BodyType, body_len: usize) {
+                self.recorded.lock().unwrap().sent.push((body_type, body_len));
+            }
+
+            fn channel_bytes(&mut self, _: &ChannelName, _: PacketDirection, _: usize) {
+                panic!("no channel is opened");
+            }
+
+            fn decode_error(&mut self, _: &ProtoError) {
+                self.recorded.lock().unwrap().decode_errors += 1;
+            }
+
+            fn state_transition(&mut self, state: &dyn crate::sm::ProtoState, time_in_previous_ms: Option<u64>) {
+                let state = (state as &dyn core::any::Any).downcast_ref::<ShareeState>().unwrap();
+                self.recorded
+                    .lock()
+                    .unwrap()
+                    .transitions
+                    .push((*state, time_in_previous_ms));
+            }
+        }
+
+        let recorded = Arc::new(Mutex::new(Recorded::default()));
+        let mut sharee = Sharee::builder(DummyConnectionSM)
+            .metrics(TestMetrics {
+                now: 0,
+                recorded: recorded.clone(),
+            })
+            .build_unchecked();
+
+        sharee.update_without_body();
+        sharee.update_with_body(&NowBody::Message(NowMessage::Terminate(NowTerminateMsg::default())));
+        sharee.report_decode_error(&ProtoError::new(ProtoErrorKind::Decoding("NowPacket")));
+
+        let recorded = recorded.lock().unwrap();
+        let terminate = BodyType::Message(crate::message::MessageType::Terminate);
+        assert_eq!(recorded.received, vec![(terminate, 8)]);
+        assert_eq!(recorded.sent, vec![(terminate, 8)]); // acknowledgment
+        assert_eq!(
+            recorded.transitions,
+            vec![(ShareeState::Active, None), (ShareeState::Final, Some(10))]
+        );
+        assert_eq!(recorded.decode_errors, 1);
+    }
+
+    #[test]
+    fn packet_taps() {
+        use crate::message::MessageType;
+        use crate::tap::TapVerdict;
+        use alloc::sync::Arc;
+        use std::sync::Mutex;
+
+        let terminate = || NowPacket::from_message(NowTerminateMsg::default());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = {
+            let seen = seen.clone();
+            move |direction, packet: &TappedPacket<'_, '_>| {
+                seen.lock().unwrap().push((direction, packet.header.body_type()));
+                TapVerdict::Pass
+            }
+        };
+        let mut sharee = Sharee::builder(DummyConnectionSM)
+            .packet_tap(recorder)
+            .packet_tap(|direction, _: &TappedPacket<'_, '_>| match direction {
+                PacketDirection::Sent => TapVerdict::Drop,
+                _ => TapVerdict::Pass,
+            })
+            .build_unchecked();
+        sharee.update_without_body();
+        let events = sharee.update_with_packet(&terminate());
+        assert!(!events.iter().any(|e| matches!(e, SMEvent::PacketToSend(_))));
+        assert_eq!(sharee.get_state(), ShareeState::Final);
+        let body_type = BodyType::Message(MessageType::Terminate);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (PacketDirection::Received, body_type),
+                (PacketDirection::Sent, body_type)
+            ]
+        );
+
+        let mut sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+        sharee.update_without_body();
+        sharee.add_packet_tap(|direction, _: &TappedPacket<'_, '_>| match direction {
+            PacketDirection::Received => TapVerdict::Drop,
+            _ => TapVerdict::Pass,
+        });
+        assert!(sharee.update_with_body(&terminate().body).is_empty());
+        assert_eq!(sharee.get_state(), ShareeState::Active);
     }
 }
@@ -0,0 +1,383 @@
+use crate::error::{ProtoError, ProtoErrorKind, Result};
+use crate::message::{
+    AccessControlCode, AccessControlDef, AccessFlags, AccessReason, MessageType, NowAccessMsg, NowAcessControlReq,
+    NowAcessControlRsp,
+};
+use crate::sm::{ProtoData, SMEvent, SMEvents};
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessEvent {
+    /// Peer asks for access and waits for an answer (see `AccessControlSM::respond`).
+    Requested {
+        code: AccessControlCode,
+        timeout: u16,
+    },
+    Granted(AccessControlCode),
+    Denied {
+        code: AccessControlCode,
+        reason: AccessReason,
+    },
+    /// A previously granted access was withdrawn by the peer.
+    Revoked(AccessControlCode),
+}
+
+impl ProtoData for AccessEvent {}
+
+/// Effective state of an access control.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessState {
+    Allowed,
+    /// Allowed once the peer accepts a request, see `AccessControlSM::request_access`.
+    NeedsConfirmation,
+    Disabled,
+    /// Not negotiated.
+    Unknown,
+}
+
+impl AccessState {
+    fn from_flags(flags: Option<AccessFlags>) -> Self {
+        match flags {
+            Some(flags) if flags.disabled() => Self::Disabled,
+            Some(flags) if flags.confirm() => Self::NeedsConfirmation,
+            Some(flags) if flags.allowed() => Self::Allowed,
+            Some(_) => Self::Disabled,
+            None => Self::Unknown,
+        }
+    }
+}
+
+/// Emitted as an `SMEvent::Data` whenever the effective state of an access control changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccessChanged {
+    pub code: AccessControlCode,
+    pub previous: AccessState,
+    pub current: AccessState,
+}
+
+impl ProtoData for AccessChanged {}
+
+/// Snapshot of the access controls state.
+///
+/// The sharee keeps an up to date copy in `SMData` extras so virtual channel state machines can
+/// refuse operations the peer disallowed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessStates {
+    controls: Vec<AccessControlDef>,
+}
+
+impl AccessStates {
+    pub fn get(&self, code: AccessControlCode) -> AccessState {
+        AccessState::from_flags(self.controls.iter().find(|def| def.code == code).map(|def| def.flags))
+    }
+
+    /// `false` only if the peer disallowed `code`, unknown access controls are not enforced.
+    pub fn permits(&self, code: AccessControlCode) -> bool {
+        match self.get(code) {
+            AccessState::Allowed | AccessState::Unknown => true,
+            AccessState::NeedsConfirmation | AccessState::Disabled => false,
+        }
+    }
+}
+
+/// Drives the access control confirm flow.
+///
+/// Local requests are answered by the peer through `NowAcessControlRsp`, peer requests are surfaced
+/// as `AccessEvent::Requested` and answered with `respond`. Current status of each access control is
+/// kept up to date, starting from the negotiated capabilities.
+#[derive(Debug, Clone, Default)]
+pub struct AccessControlSM {
+    controls: Vec<AccessControlDef>,
+    pending: Vec<AccessControlCode>,
+    prompts: Vec<AccessControlCode>,
+}
+
+impl AccessControlSM {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_with_access_controls(controls: Vec<AccessControlDef>) -> Self {
+        Self {
+            controls,
+            ..Self::default()
+        }
+    }
+
+    pub fn access_control(&self, code: AccessControlCode) -> Option<AccessFlags> {
+        self.controls.iter().find(|def| def.code == code).map(|def| def.flags)
+    }
+
+    /// `true` if allowed without confirmation.
+    pub fn is_allowed(&self, code: AccessControlCode) -> bool {
+        self.access_control(code)
+            .map(|flags| flags.allowed() && !flags.confirm() && !flags.disabled())
+            .unwrap_or(false)
+    }
+
+    pub fn access_state(&self, code: AccessControlCode) -> AccessState {
+        AccessState::from_flags(self.access_control(code))
+    }
+
+    pub fn access_states(&self) -> AccessStates {
+        AccessStates {
+            controls: self.controls.clone(),
+        }
+    }
+
+    /// `true` while a local request waits for the peer answer.
+    pub fn is_pending(&self, code: AccessControlCode) -> bool {
+        self.pending.contains(&code)
+    }
+
+    /// Peer requests waiting for a local answer.
+    pub fn prompts(&self) -> &[AccessControlCode] {
+        &self.prompts
+    }
+
+    /// Builds a request for elevated access. `timeout` is in seconds.
+    pub fn request_access(&mut self, code: AccessControlCode, timeout: u16) -> Result<NowAccessMsg<'static>> {
+        if self.is_pending(code) {
+            return Err(ProtoError::new(ProtoErrorKind::AccessControl(code)).with_desc("request already pending"));
+        }
+
+        if self.access_control(code).map(|flags| flags.disabled()).unwrap_or(false) {
+            return Err(ProtoError::new(ProtoErrorKind::AccessControl(code)).with_desc("access disabled by peer"));
+        }
+
+        self.pending.push(code);
+        Ok(NowAccessMsg::Req(NowAcessControlReq::new(code, timeout)))
+    }
+
+    /// Answers a peer request previously surfaced as `AccessEvent::Requested`.
+    pub fn respond(&mut self, code: AccessControlCode, allow: bool) -> Result<NowAccessMsg<'static>> {
+        let idx = self.prompts.iter().position(|&prompt| prompt == code).ok_or_else(|| {
+            ProtoError::new(ProtoErrorKind::AccessControl(code)).with_desc("no such request from peer")
+        })?;
+        self.prompts.remove(idx);
+
+        if allow {
+            Ok(NowAccessMsg::Rsp(NowAcessControlRsp::new_granted(code)))
+        } else {
+            Ok(NowAccessMsg::Rsp(NowAcessControlRsp::new_denied(
+                code,
+                AccessReason::new_empty().set_denied(),
+            )))
+        }
+    }
+
+    pub fn update_with_message<'msg>(&mut self, events: &mut SMEvents<'msg>, msg: &NowAccessMsg<'msg>) {
+        match msg {
+            NowAccessMsg::Req(req) => {
+                log::trace!("peer asks for {:?} access", req.id);
+                if !self.prompts.contains(&req.id) {
+                    self.prompts.push(req.id);
+                }
+                events.push(SMEvent::Access(AccessEvent::Requested {
+                    code: req.id,
+                    timeout: req.timeout,
+                }));
+            }
+            NowAccessMsg::Rsp(rsp) => {
+                match self.pending.iter().position(|&code| code == rsp.id) {
+                    Some(idx) => {
+                        self.pending.remove(idx);
+                    }
+                    None => {
+                        events.push(SMEvent::warn(
+                            ProtoErrorKind::AccessControl(rsp.id),
+                            "received an access control response without pending request",
+                        ));
+                        return;
+                    }
+                }
+
+                if rsp.flags.failure() {
+                    log::trace!("{:?} access denied (reason: {:?})", rsp.id, rsp.reason);
+                    events.push(SMEvent::Access(AccessEvent::Denied {
+                        code: rsp.id,
+                        reason: rsp.reason,
+                    }));
+                } else {
+                    log::trace!("{:?} access granted", rsp.id);
+                    self.h_set_status(events, rsp.id, AccessFlags::new_empty().set_allowed());
+                    events.push(SMEvent::Access(AccessEvent::Granted(rsp.id)));
+                }
+            }
+            NowAccessMsg::Ntf(ntf) => {
+                let was_allowed = self.is_allowed(ntf.id);
+                self.h_set_status(events, ntf.id, ntf.status);
+                let is_allowed = self.is_allowed(ntf.id);
+
+                if is_allowed && !was_allowed {
+                    events.push(SMEvent::Access(AccessEvent::Granted(ntf.id)));
+                } else if !is_allowed && was_allowed {
+                    events.push(SMEvent::Access(AccessEvent::Revoked(ntf.id)));
+                }
+            }
+            NowAccessMsg::Custom(_) => events.push(SMEvent::warn(
+                ProtoErrorKind::UnexpectedMessage(MessageType::Access),
+                "unknown access control message",
+            )),
+        }
+    }
+
+    fn h_set_status(&mut self, events: &mut SMEvents<'_>, code: AccessControlCode, flags: AccessFlags) {
+        let previous = self.access_state(code);
+        match self.controls.iter_mut().find(|def| def.code == code) {
+            Some(def) => def.flags = flags,
+            None => self.controls.push(AccessControlDef::new_with_flags(code, flags)),
+        }
+
+        let current = self.access_state(code);
+        if current != previous {
+            events.push(SMEvent::data(AccessChanged {
+                code,
+                previous,
+                current,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::NowAcessControlNtf;
+
+    fn access_events(sm: &mut AccessControlSM, msg: NowAccessMsg<'static>) -> Vec<AccessEvent> {
+        let mut events = SMEvents::new();
+        sm.update_with_message(&mut events, &msg);
+        events
+            .unpack()
+            .into_iter()
+            .filter_map(|e| match e {
+                SMEvent::Access(e) => Some(e),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn request_granted_then_revoked() {
+        let mut sm = AccessControlSM::new_with_access_controls(vec![AccessControlDef::new_confirm(
+            AccessControlCode::Clipboard,
+        )]);
+        assert!(!sm.is_allowed(AccessControlCode::Clipboard));
+
+        match sm.request_access(AccessControlCode::Clipboard, 30).unwrap() {
+            NowAccessMsg::Req(req) => {
+                assert_eq!(req.id, AccessControlCode::Clipboard);
+                assert_eq!(req.timeout, 30);
+            }
+            msg => panic!("expected an access control request and got {:?}", msg),
+        }
+        assert!(sm.is_pending(AccessControlCode::Clipboard));
+        assert!(sm.request_access(AccessControlCode::Clipboard, 30).is_err());
+
+        let events = access_events(
+            &mut sm,
+            NowAccessMsg::Rsp(NowAcessControlRsp::new_granted(AccessControlCode::Clipboard)),
+        );
+        assert_eq!(events, vec![AccessEvent::Granted(AccessControlCode::Clipboard)]);
+        assert!(sm.is_allowed(AccessControlCode::Clipboard));
+        assert!(!sm.is_pending(AccessControlCode::Clipboard));
+
+        let events = access_events(
+            &mut sm,
+            NowAccessMsg::Ntf(NowAcessControlNtf::new(
+                AccessControlCode::Clipboard,
+                AccessFlags::new_empty().set_disabled(),
+            )),
+        );
+        assert_eq!(events, vec![AccessEvent::Revoked(AccessControlCode::Clipboard)]);
+        assert!(sm.request_access(AccessControlCode::Clipboard, 30).is_err());
+    }
+
+    #[test]
+    fn access_changed() {
+        let mut sm = AccessControlSM::new_with_access_controls(vec![AccessControlDef::new_allowed(
+            AccessControlCode::Clipboard,
+        )]);
+        assert_eq!(sm.access_state(AccessControlCode::Clipboard), AccessState::Allowed);
+        assert_eq!(sm.access_state(AccessControlCode::Chat), AccessState::Unknown);
+
+        let disable = NowAccessMsg::Ntf(NowAcessControlNtf::new(
+            AccessControlCode::Clipboard,
+            AccessFlags::new_empty().set_disabled(),
+        ));
+        let mut events = SMEvents::new();
+        sm.update_with_message(&mut events, &disable);
+        let events = events.unpack();
+        assert_eq!(
+            events[0].downcast_data::<AccessChanged>(),
+            Some(&AccessChanged {
+                code: AccessControlCode::Clipboard,
+                previous: AccessState::Allowed,
+                current: AccessState::Disabled,
+            })
+        );
+        assert!(!sm.access_states().permits(AccessControlCode::Clipboard));
+        assert!(sm.access_states().permits(AccessControlCode::Chat));
+
+        // same state notified again
+        let mut events = SMEvents::new();
+        sm.update_with_message(&mut events, &disable);
+        assert!(events.peek().is_empty());
+    }
+
+    #[test]
+    fn request_denied() {
+        let mut sm = AccessControlSM::new();
+        sm.request_access(AccessControlCode::FileTransfer, 30).unwrap();
+        let reason = AccessReason::new_empty().set_timeout();
+        let events = access_events(
+            &mut sm,
+            NowAccessMsg::Rsp(NowAcessControlRsp::new_denied(AccessControlCode::FileTransfer, reason)),
+        );
+        assert_eq!(
+            events,
+            vec![AccessEvent::Denied {
+                code: AccessControlCode::FileTransfer,
+                reason
+            }]
+        );
+        assert!(!sm.is_allowed(AccessControlCode::FileTransfer));
+
+        // unsolicited response
+        let mut events = SMEvents::new();
+        sm.update_with_message(
+            &mut events,
+            &NowAccessMsg::Rsp(NowAcessControlRsp::new_granted(AccessControlCode::Exec)),
+        );
+        assert!(matches!(events.peek(), [SMEvent::Warn(_)]));
+        assert!(!sm.is_allowed(AccessControlCode::Exec));
+    }
+
+    #[test]
+    fn answer_peer_prompt() {
+        let mut sm = AccessControlSM::new();
+        let events = access_events(
+            &mut sm,
+            NowAccessMsg::Req(NowAcessControlReq::new(AccessControlCode::Interact, 15)),
+        );
+        assert_eq!(
+            events,
+            vec![AccessEvent::Requested {
+                code: AccessControlCode::Interact,
+                timeout: 15
+            }]
+        );
+        assert_eq!(sm.prompts(), &[AccessControlCode::Interact]);
+
+        match sm.respond(AccessControlCode::Interact, false).unwrap() {
+            NowAccessMsg::Rsp(rsp) => {
+                assert!(rsp.flags.failure());
+                assert!(rsp.reason.denied());
+            }
+            msg => panic!("expected an access control response and got {:?}", msg),
+        }
+        assert!(sm.prompts().is_empty());
+        assert!(sm.respond(AccessControlCode::Interact, true).is_err());
+    }
+}
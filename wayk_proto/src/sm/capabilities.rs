@@ -0,0 +1,269 @@
+use crate::message::{
+    AccessCapset, AccessControlCode, AccessControlDef, AccessFlags, Codec, NowCapset, QualityMode, SurfaceCapset,
+    SurfaceCapsetFlags, UpdateCapset,
+};
+use crate::sm::ProtoData;
+use alloc::vec::Vec;
+
+/// Outcome of the capabilities exchange.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NegotiatedCapabilities {
+    /// Codec supported by both ends, `Codec::Unspecified` if none.
+    pub codec: Codec,
+    /// Every codec supported by both ends, by local order of preference.
+    pub codecs: Vec<Codec>,
+    pub quality_mode: QualityMode,
+    /// Access controls as advertised by the remote, restricted by the local ones.
+    pub access_controls: Vec<AccessControlDef>,
+    /// Surface features supported by both ends.
+    pub surface_flags: SurfaceCapsetFlags,
+}
+
+impl ProtoData for NegotiatedCapabilities {}
+
+/// Capabilities as advertised by the peer, kept in `SMData` extras to decode later messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerCapabilities(pub Vec<NowCapset<'static>>);
+
+impl ProtoData for PeerCapabilities {}
+
+impl NegotiatedCapabilities {
+    pub fn access_control(&self, code: AccessControlCode) -> Option<AccessFlags> {
+        self.access_controls
+            .iter()
+            .find(|def| def.code == code)
+            .map(|def| def.flags)
+    }
+
+    /// `true` if allowed without confirmation.
+    pub fn is_allowed(&self, code: AccessControlCode) -> bool {
+        self.access_control(code)
+            .map(|flags| flags.allowed() && !flags.confirm() && !flags.disabled())
+            .unwrap_or(false)
+    }
+}
+
+/// Computes the intersection of local and remote capabilities.
+pub struct CapabilitiesNegotiator<'a> {
+    local: &'a [NowCapset<'a>],
+    remote: &'a [NowCapset<'a>],
+}
+
+impl<'a> CapabilitiesNegotiator<'a> {
+    pub fn new(local: &'a [NowCapset<'a>], remote: &'a [NowCapset<'a>]) -> Self {
+        Self { local, remote }
+    }
+
+    pub fn negotiate(&self) -> NegotiatedCapabilities {
+        NegotiatedCapabilities {
+            codec: self.codec(),
+            codecs: self.common_codecs(),
+            quality_mode: self.quality_mode(),
+            access_controls: self.access_controls(),
+            surface_flags: self.surface_flags(),
+        }
+    }
+
+    /// Codecs advertised locally, by order of preference.
+    pub fn local_codecs(&self) -> Vec<Codec> {
+        advertised_codecs(find_update(self.local))
+    }
+
+    /// Local codecs also supported by the remote, by local order of preference.
+    pub fn common_codecs(&self) -> Vec<Codec> {
+        let remote = find_update(self.remote);
+        self.local_codecs()
+            .into_iter()
+            .filter(|&codec| supports_codec(remote, codec))
+            .collect()
+    }
+
+    fn codec(&self) -> Codec {
+        let local = find_update(self.local);
+        let remote = find_update(self.remote);
+        let supports = supports_codec;
+
+        // codec explicitly requested by the remote
+        if let Some(codec) = remote.map(|capset| capset.codec_id) {
+            if codec != Codec::Unspecified && supports(local, codec) {
+                return codec;
+            }
+        }
+
+        // otherwise, first local codec (by order of preference) also supported by the remote
+        local
+            .into_iter()
+            .flat_map(|capset| capset.codecs.iter().map(|def| def.id))
+            .find(|&codec| codec != Codec::Unspecified && supports(remote, codec))
+            .unwrap_or(Codec::Unspecified)
+    }
+
+    fn quality_mode(&self) -> QualityMode {
+        let remote = find_update(self.remote).map(|capset| capset.quality_mode);
+        let local = find_update(self.local).map(|capset| capset.quality_mode);
+        match (remote, local) {
+            (Some(mode), _) if mode != QualityMode::Unspecified => mode,
+            (_, Some(mode)) => mode,
+            _ => QualityMode::Unspecified,
+        }
+    }
+
+    fn access_controls(&self) -> Vec<AccessControlDef> {
+        let local = find_access(self.local);
+        find_access(self.remote)
+            .map(|remote| {
+                remote
+                    .access_controls
+                    .iter()
+                    .map(|remote_def| {
+                        let local_flags = local.and_then(|local| {
+                            local
+                                .access_controls
+                                .iter()
+                                .find(|def| def.code == remote_def.code)
+                                .map(|def| def.flags)
+                        });
+                        AccessControlDef::new_with_flags(remote_def.code, merge_access(remote_def.flags, local_flags))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn surface_flags(&self) -> SurfaceCapsetFlags {
+        match (find_surface(self.local), find_surface(self.remote)) {
+            (Some(local), Some(remote)) => SurfaceCapsetFlags::from(local.flags.value & remote.flags.value),
+            _ => SurfaceCapsetFlags::new_empty(),
+        }
+    }
+}
+
+fn supports_codec(capset: Option<&UpdateCapset>, codec: Codec) -> bool {
+    capset
+        .map(|capset| capset.codec_id == codec || capset.codecs.iter().any(|def| def.id == codec))
+        .unwrap_or(false)
+}
+
+fn advertised_codecs(capset: Option<&UpdateCapset>) -> Vec<Codec> {
+    let mut codecs = Vec::new();
+    if let Some(capset) = capset {
+        let ids = capset.codecs.iter().map(|def| def.id).chain(Some(capset.codec_id));
+        for codec in ids {
+            if codec != Codec::Unspecified && !codecs.contains(&codec) {
+                codecs.push(codec);
+            }
+        }
+    }
+    codecs
+}
+
+fn merge_access(remote: AccessFlags, local: Option<AccessFlags>) -> AccessFlags {
+    match local {
+        Some(local) if local.disabled() => AccessFlags::new_empty().set_disabled(),
+        Some(local) if local.confirm() && remote.allowed() => AccessFlags::new_empty().set_confirm(),
+        _ => remote,
+    }
+}
+
+fn find_update<'a>(capabilities: &'a [NowCapset<'_>]) -> Option<&'a UpdateCapset> {
+    capabilities.iter().find_map(|capset| match capset {
+        NowCapset::Update(capset) => Some(capset),
+        _ => None,
+    })
+}
+
+fn find_access<'a>(capabilities: &'a [NowCapset<'_>]) -> Option<&'a AccessCapset> {
+    capabilities.iter().find_map(|capset| match capset {
+        NowCapset::Access(capset) => Some(capset),
+        _ => None,
+    })
+}
+
+fn find_surface<'a>(capabilities: &'a [NowCapset<'_>]) -> Option<&'a SurfaceCapset> {
+    capabilities.iter().find_map(|capset| match capset {
+        NowCapset::Surface(capset) => Some(capset),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{NowCodecDef, NowSurfaceListReqMsg};
+
+    fn local() -> Vec<NowCapset<'static>> {
+        vec![
+            NowCapset::Update(UpdateCapset::new_with_supported_codecs(vec![
+                NowCodecDef::new(Codec::GFWX),
+                NowCodecDef::new(Codec::JPEG),
+            ])),
+            NowCapset::Access(AccessCapset::new_with_access_controls(vec![
+                AccessControlDef::new_disabled(AccessControlCode::FileTransfer),
+                AccessControlDef::new_confirm(AccessControlCode::Clipboard),
+            ])),
+            NowCapset::Surface(SurfaceCapset::new(
+                SurfaceCapsetFlags::new_empty().set_list_req().set_select(),
+                NowSurfaceListReqMsg::new(0, 1920, 1080),
+            )),
+        ]
+    }
+
+    fn remote() -> Vec<NowCapset<'static>> {
+        let mut update = UpdateCapset::new(QualityMode::High, Codec::Unspecified);
+        update.codecs.push(NowCodecDef::new(Codec::JPEG));
+        update.codecs.push(NowCodecDef::new(Codec::Thor));
+
+        vec![
+            NowCapset::Update(update),
+            NowCapset::Access(AccessCapset::new_with_access_controls(vec![
+                AccessControlDef::new_allowed(AccessControlCode::Viewing),
+                AccessControlDef::new_allowed(AccessControlCode::FileTransfer),
+                AccessControlDef::new_allowed(AccessControlCode::Clipboard),
+            ])),
+            NowCapset::Surface(SurfaceCapset::new(
+                SurfaceCapsetFlags::new_empty().set_list_req().set_multi(),
+                NowSurfaceListReqMsg::new(0, 1920, 1080),
+            )),
+        ]
+    }
+
+    #[test]
+    fn negotiate_intersection() {
+        let local = local();
+        let remote = remote();
+        let negotiated = CapabilitiesNegotiator::new(&local, &remote).negotiate();
+
+        assert_eq!(negotiated.codec, Codec::JPEG);
+        assert_eq!(negotiated.codecs, [Codec::JPEG]);
+        assert_eq!(negotiated.quality_mode, QualityMode::High);
+        assert!(negotiated.surface_flags.list_req());
+        assert!(!negotiated.surface_flags.select());
+        assert!(!negotiated.surface_flags.multi());
+
+        assert!(negotiated.is_allowed(AccessControlCode::Viewing));
+        assert!(!negotiated.is_allowed(AccessControlCode::FileTransfer));
+        assert!(negotiated
+            .access_control(AccessControlCode::FileTransfer)
+            .unwrap()
+            .disabled());
+        assert!(negotiated
+            .access_control(AccessControlCode::Clipboard)
+            .unwrap()
+            .confirm());
+        assert_eq!(negotiated.access_control(AccessControlCode::Chat), None);
+    }
+
+    #[test]
+    fn remote_requested_codec() {
+        let local = local();
+        let remote = vec![NowCapset::Update(UpdateCapset::new(
+            QualityMode::Unspecified,
+            Codec::GFWX,
+        ))];
+        let negotiated = CapabilitiesNegotiator::new(&local, &remote).negotiate();
+        assert_eq!(negotiated.codec, Codec::GFWX);
+        assert_eq!(negotiated.codecs, [Codec::GFWX]);
+        assert_eq!(negotiated.quality_mode, QualityMode::Unspecified);
+        assert!(negotiated.access_controls.is_empty());
+    }
+}
@@ -1,14 +1,95 @@
 use crate::alloc::borrow::ToOwned;
-use crate::error::ProtoErrorKind;
+use crate::error::{ProtoErrorKind, Result};
 use crate::message::{
-    ChannelName, ChatCapabilitiesFlags, NowChatMsg, NowChatSyncMsg, NowChatTextMsg, NowString65535, NowVirtualChannel,
+    ChannelName, ChatCapabilitiesFlags, ChatPresenceStatus, ChatTypingFlags, NowChatMsg, NowChatReadMsg,
+    NowChatStatusMsg, NowChatSyncMsg, NowChatTextMsg, NowChatTypingMsg, NowString65535, NowVirtualChannel, UserContent,
 };
-use crate::sm::{ChannelResponses, ProtoState, SMData, SMEvent, SMEvents, VirtualChannelSM};
+use crate::sm::{ChannelResponses, ProtoData, ProtoState, SMData, SMEvent, SMEvents, StateInfo, VirtualChannelSM};
 use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::string::String;
+use core::fmt;
 use core::str::FromStr;
 
-pub type TimestampFn = Box<dyn FnMut() -> u32>;
+pub type TimestampFn = Box<dyn FnMut() -> u32 + Send>;
+
+/// Clock skew (in seconds) tolerated before a `ChatClockSkew` event is emitted.
+pub const DEFAULT_CLOCK_SKEW_THRESHOLD: u32 = 120;
+
+/// Number of messages kept in the conversation history by default.
+pub const DEFAULT_MAX_HISTORY: usize = 1024;
+
+/// Emitted when the distant peer clock drifts beyond the configured threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChatClockSkew {
+    /// Distant clock minus local clock, in seconds.
+    pub skew: i64,
+    pub threshold: u32,
+}
+
+impl ProtoData for ChatClockSkew {}
+
+/// Outcome of [`ChatData::gate_outgoing`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChatGating {
+    Allowed,
+    /// Unsupported content was removed, the message can still be sent.
+    Stripped(&'static str),
+    /// The message must not be sent.
+    Rejected(&'static str),
+}
+
+#[derive(Clone, PartialEq)]
+pub enum ChatEvent {
+    Synced {
+        distant_friendly_name: String,
+        distant_status_text: String,
+    },
+    Message {
+        /// Distant timestamp converted into the local clock.
+        timestamp: u32,
+        distant_timestamp: u32,
+        text: String,
+    },
+    ClockSkew(ChatClockSkew),
+}
+
+impl fmt::Debug for ChatEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatEvent::Synced {
+                distant_friendly_name,
+                distant_status_text,
+            } => f
+                .debug_struct("Synced")
+                .field("distant_friendly_name", &UserContent(distant_friendly_name.as_str()))
+                .field("distant_status_text", &UserContent(distant_status_text.as_str()))
+                .finish(),
+            ChatEvent::Message {
+                timestamp,
+                distant_timestamp,
+                text,
+            } => f
+                .debug_struct("Message")
+                .field("timestamp", timestamp)
+                .field("distant_timestamp", distant_timestamp)
+                .field("text", &UserContent(text.as_str()))
+                .finish(),
+            ChatEvent::ClockSkew(skew) => f.debug_tuple("ClockSkew").field(skew).finish(),
+        }
+    }
+}
+
+impl ProtoData for ChatEvent {}
+
+impl ChatEvent {
+    pub fn as_data(&self) -> &dyn ProtoData {
+        match self {
+            ChatEvent::ClockSkew(skew) => skew,
+            _ => self,
+        }
+    }
+}
 
 pub trait ChatChannelCallbackTrait {
     fn on_message(&mut self, chat_data: &mut ChatData, to_send: &mut ChannelResponses<'_>, text_msg: &NowChatTextMsg) {
@@ -18,6 +99,29 @@ pub trait ChatChannelCallbackTrait {
     fn on_synced(&mut self, chat_data: &mut ChatData, to_send: &mut ChannelResponses<'_>) {
         #![allow(unused_variables)]
     }
+
+    /// Distant peer started or stopped typing.
+    fn on_typing_changed(&mut self, chat_data: &mut ChatData, to_send: &mut ChannelResponses<'_>, typing: bool) {
+        #![allow(unused_variables)]
+    }
+
+    /// Distant peer read all messages sent up to `timestamp` (local clock).
+    fn on_read_up_to(&mut self, chat_data: &mut ChatData, to_send: &mut ChannelResponses<'_>, timestamp: u32) {
+        #![allow(unused_variables)]
+    }
+
+    /// Distant peer updated its presence or status text after the initial sync.
+    ///
+    /// Previous values are still in `chat_data` and overwritten once this returns.
+    fn on_peer_presence_changed(
+        &mut self,
+        chat_data: &mut ChatData,
+        to_send: &mut ChannelResponses<'_>,
+        presence: ChatPresenceStatus,
+        status_text: &str,
+    ) {
+        #![allow(unused_variables)]
+    }
 }
 
 sa::assert_obj_safe!(ChatChannelCallbackTrait);
@@ -29,12 +133,20 @@ impl ChatChannelCallbackTrait for DummyChatChannelCallback {}
 #[derive(Debug, Clone, PartialEq)]
 pub struct ChatData {
     pub friendly_name: String,
+    pub presence: ChatPresenceStatus,
     pub status_text: String,
 
     pub distant_friendly_name: String,
+    pub distant_presence: ChatPresenceStatus,
     pub distant_status_text: String,
 
     pub capabilities: ChatCapabilitiesFlags,
+
+    pub clock_skew_threshold: u32,
+    /// Distant clock minus local clock, in seconds. Measured on each received timestamp.
+    pub clock_skew: Option<i64>,
+
+    pub conversation: ChatConversation,
 }
 
 impl Default for ChatData {
@@ -47,10 +159,15 @@ impl ChatData {
     pub fn new() -> Self {
         Self {
             friendly_name: "Anonymous".to_owned(),
+            presence: ChatPresenceStatus::Available,
             status_text: "None".to_owned(),
             distant_friendly_name: "Unknown".to_owned(),
+            distant_presence: ChatPresenceStatus::Unknown,
             distant_status_text: "None".to_owned(),
             capabilities: ChatCapabilitiesFlags::new_empty(),
+            clock_skew_threshold: DEFAULT_CLOCK_SKEW_THRESHOLD,
+            clock_skew: None,
+            conversation: ChatConversation::new(),
         }
     }
 
@@ -65,18 +182,232 @@ impl ChatData {
         }
     }
 
+    pub fn presence(self, presence: ChatPresenceStatus) -> Self {
+        Self { presence, ..self }
+    }
+
     pub fn status_text<S: Into<String>>(self, status_text: S) -> Self {
         Self {
             status_text: status_text.into(),
             ..self
         }
     }
+
+    pub fn clock_skew_threshold(self, clock_skew_threshold: u32) -> Self {
+        Self {
+            clock_skew_threshold,
+            ..self
+        }
+    }
+
+    pub fn is_clock_skewed(&self) -> bool {
+        self.clock_skew
+            .map(|skew| skew.unsigned_abs() > u64::from(self.clock_skew_threshold))
+            .unwrap_or(false)
+    }
+
+    /// Converts a timestamp from the distant peer clock into the local clock.
+    ///
+    /// Useful to display distant times and to order messages from both peers.
+    pub fn normalize_timestamp(&self, distant_timestamp: u32) -> u32 {
+        match self.clock_skew {
+            Some(skew) => (i64::from(distant_timestamp) - skew).clamp(0, i64::from(u32::MAX)) as u32,
+            None => distant_timestamp,
+        }
+    }
+
+    /// Converts a local timestamp into the distant peer clock.
+    pub fn to_distant_timestamp(&self, local_timestamp: u32) -> u32 {
+        match self.clock_skew {
+            Some(skew) => (i64::from(local_timestamp) + skew).clamp(0, i64::from(u32::MAX)) as u32,
+            None => local_timestamp,
+        }
+    }
+
+    /// Checks an outgoing message against `capabilities`, stripping emoji from text if unsupported.
+    ///
+    /// `capabilities` only holds the negotiated set once the channel is synced.
+    pub fn gate_outgoing(&self, msg: &mut NowChatMsg<'_>) -> ChatGating {
+        match msg {
+            NowChatMsg::Poke(_) if !self.capabilities.poke() => ChatGating::Rejected("poke is not supported by peer"),
+            NowChatMsg::Read(_) if !self.capabilities.read() => {
+                ChatGating::Rejected("read notifications are not supported by peer")
+            }
+            NowChatMsg::Text(text_msg)
+                if !self.capabilities.emoji() && text_msg.text.as_str().chars().any(is_emoji) =>
+            {
+                let stripped: String = text_msg.text.as_str().chars().filter(|c| !is_emoji(*c)).collect();
+                // stripping can only shorten the text
+                text_msg.text = NowString65535::from_str(&stripped).expect("stripped text fits");
+                ChatGating::Stripped("emoji are not supported by peer")
+            }
+            _ => ChatGating::Allowed,
+        }
+    }
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1F000}'..='\u{1FAFF}' // pictographs, emoticons, flags, ...
+            | '\u{2300}'..='\u{23FF}' // misc technical (watch, hourglass, ...)
+            | '\u{2600}'..='\u{27BF}' // misc symbols and dingbats
+            | '\u{2B00}'..='\u{2BFF}' // arrows and stars
+            | '\u{200D}' // zero width joiner
+            | '\u{FE0F}' // emoji presentation selector
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChatDirection {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatHistoryEntry {
+    pub direction: ChatDirection,
+    /// Assigned by the sender: ids of sent and received messages are independent.
+    pub message_id: u32,
+    /// Local clock.
+    pub timestamp: u32,
+    pub text: String,
+}
+
+/// Message history along with read receipts and typing state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatConversation {
+    history: VecDeque<ChatHistoryEntry>,
+    max_history: usize,
+    next_message_id: u32,
+    peer_typing: bool,
+    peer_read_up_to: Option<u32>,
+    read_up_to: Option<u32>,
+}
+
+impl Default for ChatConversation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChatConversation {
+    pub fn new() -> Self {
+        Self {
+            history: VecDeque::new(),
+            max_history: DEFAULT_MAX_HISTORY,
+            next_message_id: 1,
+            peer_typing: false,
+            peer_read_up_to: None,
+            read_up_to: None,
+        }
+    }
+
+    /// Oldest messages are dropped once `max_history` is reached.
+    pub fn max_history(self, max_history: usize) -> Self {
+        Self { max_history, ..self }
+    }
+
+    pub fn messages(&self) -> impl Iterator<Item = &ChatHistoryEntry> {
+        self.history.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    pub fn last_message(&self) -> Option<&ChatHistoryEntry> {
+        self.history.back()
+    }
+
+    pub fn find(&self, direction: ChatDirection, message_id: u32) -> Option<&ChatHistoryEntry> {
+        self.history
+            .iter()
+            .find(|entry| entry.direction == direction && entry.message_id == message_id)
+    }
+
+    pub fn is_peer_typing(&self) -> bool {
+        self.peer_typing
+    }
+
+    /// Timestamp (local clock) up to which sent messages were read by the distant peer.
+    pub fn peer_read_up_to(&self) -> Option<u32> {
+        self.peer_read_up_to
+    }
+
+    pub fn is_read_by_peer(&self, message_id: u32) -> bool {
+        match (self.find(ChatDirection::Sent, message_id), self.peer_read_up_to) {
+            (Some(entry), Some(read_up_to)) => entry.timestamp <= read_up_to,
+            _ => false,
+        }
+    }
+
+    /// Received messages not yet marked as read locally.
+    pub fn unread_count(&self) -> usize {
+        self.history
+            .iter()
+            .filter(|entry| entry.direction == ChatDirection::Received)
+            .filter(|entry| {
+                self.read_up_to
+                    .map(|read_up_to| entry.timestamp > read_up_to)
+                    .unwrap_or(true)
+            })
+            .count()
+    }
+
+    /// Records an outgoing message and builds it.
+    ///
+    /// `distant_timestamp` is sent on the wire, `timestamp` (local clock) is kept in history.
+    pub fn send_text(&mut self, timestamp: u32, distant_timestamp: u32, text: &str) -> Result<NowChatTextMsg> {
+        let msg = NowChatTextMsg::new(distant_timestamp, self.next_message_id, NowString65535::from_str(text)?);
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        self.push(ChatHistoryEntry {
+            direction: ChatDirection::Sent,
+            message_id: msg.message_id,
+            timestamp,
+            text: text.to_owned(),
+        });
+        Ok(msg)
+    }
+
+    /// Marks received messages up to `timestamp` (local clock) as read and builds the read receipt.
+    pub fn mark_read(&mut self, timestamp: u32, distant_timestamp: u32) -> NowChatReadMsg {
+        self.read_up_to = Some(timestamp);
+        NowChatReadMsg::new(distant_timestamp)
+    }
+
+    /// Builds a typing notification for the message being written.
+    pub fn typing_msg(&self, distant_timestamp: u32, typing: bool) -> NowChatTypingMsg {
+        let flags = if typing {
+            ChatTypingFlags::new_empty().set_typing()
+        } else {
+            ChatTypingFlags::new_empty()
+        };
+        NowChatTypingMsg::new_with_flags(distant_timestamp, self.next_message_id, flags)
+    }
+
+    fn push(&mut self, entry: ChatHistoryEntry) {
+        if self.max_history == 0 {
+            return;
+        }
+
+        while self.history.len() >= self.max_history {
+            self.history.pop_front();
+        }
+        self.history.push_back(entry);
+    }
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 enum ChatState {
     Initial,
     Sync,
+    /// Server role, waiting for the client to sync first.
+    SyncReq,
     Active,
     Terminated,
 }
@@ -86,6 +417,8 @@ impl ProtoState for ChatState {}
 pub struct ChatChannelSM<UserCallback> {
     state: ChatState,
     data: ChatData,
+    /// Advertised by the distant peer on sync.
+    peer_capabilities: Option<ChatCapabilitiesFlags>,
     timestamp_fn: TimestampFn,
     user_callback: UserCallback,
 }
@@ -98,11 +431,61 @@ where
         Self {
             state: ChatState::Initial,
             data: config,
+            peer_capabilities: None,
             timestamp_fn,
             user_callback,
         }
     }
 
+    pub fn data(&self) -> &ChatData {
+        &self.data
+    }
+
+    /// Capabilities advertised by the distant peer, `None` until synced.
+    ///
+    /// Negotiated capabilities are found in `data().capabilities`.
+    pub fn peer_capabilities(&self) -> Option<ChatCapabilitiesFlags> {
+        self.peer_capabilities
+    }
+
+    /// Updates local presence, notifying the distant peer if the channel is already synced.
+    pub fn set_presence(&mut self, presence: ChatPresenceStatus, to_send: &mut ChannelResponses<'_>) -> Result<()> {
+        self.data.presence = presence;
+        self.h_send_update(to_send)
+    }
+
+    /// Updates local status text, notifying the distant peer if the channel is already synced.
+    pub fn set_status_text<S: Into<String>>(
+        &mut self,
+        status_text: S,
+        to_send: &mut ChannelResponses<'_>,
+    ) -> Result<()> {
+        let status_text = status_text.into();
+        NowString65535::from_str(&status_text)?;
+        self.data.status_text = status_text;
+        self.h_send_update(to_send)
+    }
+
+    fn h_sync_msg(&mut self) -> Result<NowChatSyncMsg> {
+        let friendly_name = NowString65535::from_str(&self.data.friendly_name)?;
+        let status_text = NowString65535::from_str(&self.data.status_text)?;
+        Ok(
+            NowChatSyncMsg::new((self.timestamp_fn)(), self.data.capabilities, friendly_name)
+                .presence(self.data.presence)
+                .status_text(status_text),
+        )
+    }
+
+    fn h_send_update(&mut self, to_send: &mut ChannelResponses<'_>) -> Result<()> {
+        // before the initial sync, updated data is sent along with it
+        if self.state == ChatState::Active {
+            let sync = self.h_sync_msg()?;
+            to_send.push(NowChatStatusMsg::new(sync.timestamp));
+            to_send.push(sync);
+        }
+        Ok(())
+    }
+
     fn h_unexpected_with_call<'msg>(&self, events: &mut SMEvents<'msg>) {
         events.push(SMEvent::error(
             ProtoErrorKind::VirtualChannel(self.get_channel_name()),
@@ -127,12 +510,137 @@ where
         ))
     }
 
+    fn h_update_clock_skew(&mut self, events: &mut SMEvents<'_>, distant_timestamp: u32) {
+        let was_skewed = self.data.is_clock_skewed();
+        let skew = i64::from(distant_timestamp) - i64::from((self.timestamp_fn)());
+        self.data.clock_skew = Some(skew);
+
+        if self.data.is_clock_skewed() && !was_skewed {
+            log::warn!("distant clock is skewed by {} seconds", skew);
+            events.push(SMEvent::Chat(ChatEvent::ClockSkew(ChatClockSkew {
+                skew,
+                threshold: self.data.clock_skew_threshold,
+            })));
+        }
+    }
+
+    /// Drops or strips messages pushed by callbacks from `start` that the distant peer can't handle.
+    fn h_gate_outgoing(&self, events: &mut SMEvents<'_>, to_send: &mut ChannelResponses<'_>, start: usize) {
+        if self.peer_capabilities.is_none() {
+            return;
+        }
+
+        to_send.retain_since(start, |msg| {
+            let gating = match msg {
+                NowVirtualChannel::Chat(msg) => self.data.gate_outgoing(msg),
+                _ => ChatGating::Allowed,
+            };
+
+            match gating {
+                ChatGating::Allowed => true,
+                ChatGating::Stripped(reason) => {
+                    events.push(SMEvent::warn(
+                        ProtoErrorKind::VirtualChannel(self.get_channel_name()),
+                        format!("outgoing message altered: {}", reason),
+                    ));
+                    true
+                }
+                ChatGating::Rejected(reason) => {
+                    events.push(SMEvent::warn(
+                        ProtoErrorKind::VirtualChannel(self.get_channel_name()),
+                        format!("outgoing message dropped: {}", reason),
+                    ));
+                    false
+                }
+            }
+        });
+    }
+
     fn h_transition_state(&mut self, events: &mut SMEvents<'_>, state: ChatState) {
         self.state = state;
         events.push(SMEvent::transition(state));
     }
 }
 
+/// Server role of the chat channel: waits for the `ChatChannelSM` sync and answers with its own,
+/// then behaves the same.
+pub struct ChatChannelServerSM<UserCallback>(ChatChannelSM<UserCallback>);
+
+impl<UserCallback> ChatChannelServerSM<UserCallback>
+where
+    UserCallback: ChatChannelCallbackTrait,
+{
+    pub fn new(config: ChatData, timestamp_fn: TimestampFn, user_callback: UserCallback) -> Self {
+        Self(ChatChannelSM {
+            state: ChatState::SyncReq,
+            ..ChatChannelSM::new(config, timestamp_fn, user_callback)
+        })
+    }
+
+    pub fn data(&self) -> &ChatData {
+        self.0.data()
+    }
+
+    /// Capabilities advertised by the distant peer, `None` until synced.
+    pub fn peer_capabilities(&self) -> Option<ChatCapabilitiesFlags> {
+        self.0.peer_capabilities()
+    }
+
+    /// Updates local presence, notifying the distant peer if the channel is already synced.
+    pub fn set_presence(&mut self, presence: ChatPresenceStatus, to_send: &mut ChannelResponses<'_>) -> Result<()> {
+        self.0.set_presence(presence, to_send)
+    }
+
+    /// Updates local status text, notifying the distant peer if the channel is already synced.
+    pub fn set_status_text<S: Into<String>>(
+        &mut self,
+        status_text: S,
+        to_send: &mut ChannelResponses<'_>,
+    ) -> Result<()> {
+        self.0.set_status_text(status_text, to_send)
+    }
+}
+
+impl<UserCallback> VirtualChannelSM for ChatChannelServerSM<UserCallback>
+where
+    UserCallback: ChatChannelCallbackTrait,
+{
+    fn get_channel_name(&self) -> ChannelName {
+        self.0.get_channel_name()
+    }
+
+    fn is_terminated(&self) -> bool {
+        self.0.is_terminated()
+    }
+
+    fn waiting_for_packet(&self) -> bool {
+        self.0.waiting_for_packet()
+    }
+
+    fn update_without_chan_msg<'msg>(
+        &mut self,
+        data: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut ChannelResponses<'msg>,
+    ) {
+        self.0.update_without_chan_msg(data, events, to_send)
+    }
+
+    fn update_with_chan_msg<'msg: 'a, 'a>(
+        &mut self,
+        data: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut ChannelResponses<'msg>,
+        chan_msg: &'a NowVirtualChannel<'msg>,
+    ) {
+        self.0.update_with_chan_msg(data, events, to_send, chan_msg)
+    }
+
+    fn state_info(&self) -> Option<StateInfo> {
+        self.0.state_info()
+    }
+}
+
 impl<UserCallback> VirtualChannelSM for ChatChannelSM<UserCallback>
 where
     UserCallback: ChatChannelCallbackTrait,
@@ -146,7 +654,7 @@ where
     }
 
     fn waiting_for_packet(&self) -> bool {
-        self.state == ChatState::Active || self.state == ChatState::Sync
+        matches!(self.state, ChatState::Active | ChatState::Sync | ChatState::SyncReq)
     }
 
     fn update_without_chan_msg<'msg>(
@@ -159,26 +667,13 @@ where
             ChatState::Initial => {
                 log::trace!("start syncing");
 
-                let friendly_name = match NowString65535::from_str(&self.data.friendly_name) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        events.push(SMEvent::Error(e));
-                        return;
-                    }
-                };
-
-                let status_text = match NowString65535::from_str(&self.data.status_text) {
-                    Ok(s) => s,
+                match self.h_sync_msg() {
+                    Ok(msg) => to_send.push(msg),
                     Err(e) => {
                         events.push(SMEvent::Error(e));
                         return;
                     }
-                };
-
-                to_send.push(
-                    NowChatSyncMsg::new((self.timestamp_fn)(), self.data.capabilities, friendly_name)
-                        .status_text(status_text),
-                );
+                }
 
                 self.h_transition_state(events, ChatState::Sync);
             }
@@ -193,28 +688,422 @@ where
         to_send: &mut ChannelResponses<'msg>,
         chan_msg: &'a NowVirtualChannel<'msg>,
     ) {
+        let to_send_start = to_send.len();
+
         match chan_msg {
             NowVirtualChannel::Chat(msg) => match self.state {
-                ChatState::Sync => match msg {
+                ChatState::Sync | ChatState::SyncReq => match msg {
                     NowChatMsg::Sync(msg) => {
+                        if self.state == ChatState::SyncReq {
+                            match self.h_sync_msg() {
+                                Ok(sync) => to_send.push(sync),
+                                Err(e) => {
+                                    events.push(SMEvent::Error(e));
+                                    return;
+                                }
+                            }
+                        }
+
+                        self.h_update_clock_skew(events, msg.timestamp);
+
                         // update config
                         self.data.capabilities.value &= msg.capabilities.value;
+                        self.peer_capabilities = Some(msg.capabilities);
                         self.data.distant_friendly_name = msg.friendly_name.as_str().to_owned();
+                        self.data.distant_presence = msg.presence;
                         self.data.distant_status_text = msg.status_text.as_str().to_owned();
 
                         log::trace!("channel synced");
                         self.state = ChatState::Active;
+                        events.push(SMEvent::Chat(ChatEvent::Synced {
+                            distant_friendly_name: self.data.distant_friendly_name.clone(),
+                            distant_status_text: self.data.distant_status_text.clone(),
+                        }));
                         self.user_callback.on_synced(&mut self.data, to_send);
                     }
                     _ => self.h_unexpected_message(events, chan_msg),
                 },
                 ChatState::Active => match msg {
-                    NowChatMsg::Text(msg) => self.user_callback.on_message(&mut self.data, to_send, msg),
+                    NowChatMsg::Text(msg) => {
+                        self.h_update_clock_skew(events, msg.timestamp);
+                        events.push(SMEvent::Chat(ChatEvent::Message {
+                            timestamp: self.data.normalize_timestamp(msg.timestamp),
+                            distant_timestamp: msg.timestamp,
+                            text: msg.text.as_str().to_owned(),
+                        }));
+                        let timestamp = self.data.normalize_timestamp(msg.timestamp);
+                        self.data.conversation.peer_typing = false;
+                        self.data.conversation.push(ChatHistoryEntry {
+                            direction: ChatDirection::Received,
+                            message_id: msg.message_id,
+                            timestamp,
+                            text: msg.text.as_str().to_owned(),
+                        });
+                        self.user_callback.on_message(&mut self.data, to_send, msg);
+                    }
+                    NowChatMsg::Sync(msg) => {
+                        self.h_update_clock_skew(events, msg.timestamp);
+                        self.data.distant_friendly_name = msg.friendly_name.as_str().to_owned();
+
+                        let status_text = msg.status_text.as_str();
+                        if msg.presence != self.data.distant_presence || status_text != self.data.distant_status_text {
+                            log::trace!("distant presence changed to {:?}", msg.presence);
+                            self.user_callback.on_peer_presence_changed(
+                                &mut self.data,
+                                to_send,
+                                msg.presence,
+                                status_text,
+                            );
+                            self.data.distant_presence = msg.presence;
+                            self.data.distant_status_text = status_text.to_owned();
+                        }
+                    }
+                    NowChatMsg::Status(_) => {
+                        // updated values follow in a sync message
+                        log::trace!("distant status update");
+                    }
+                    NowChatMsg::Read(msg) => {
+                        let timestamp = self.data.normalize_timestamp(msg.timestamp);
+                        self.data.conversation.peer_read_up_to = Some(timestamp);
+                        self.user_callback.on_read_up_to(&mut self.data, to_send, timestamp);
+                    }
+                    NowChatMsg::Typing(msg) => {
+                        let typing = msg.flags.typing();
+                        if typing != self.data.conversation.peer_typing {
+                            self.data.conversation.peer_typing = typing;
+                            self.user_callback.on_typing_changed(&mut self.data, to_send, typing);
+                        }
+                    }
                     _ => self.h_unexpected_message(events, chan_msg),
                 },
                 _ => self.h_unexpected_with_call(events),
             },
             _ => self.h_unexpected_message(events, chan_msg),
         }
+
+        self.h_gate_outgoing(events, to_send, to_send_start);
+    }
+
+    fn state_info(&self) -> Option<StateInfo> {
+        Some(match self.state {
+            ChatState::Initial => StateInfo::new("Initial", &["Sync"]),
+            ChatState::Sync => StateInfo::new("Sync", &["Active"]),
+            ChatState::SyncReq => StateInfo::new("SyncReq", &["Active"]),
+            ChatState::Active => StateInfo::new("Active", &[]),
+            ChatState::Terminated => StateInfo::new("Terminated", &[]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{NowChatPokeMsg, NowChatTextMsg};
+    use alloc::vec::Vec;
+
+    fn chat_sm(now: u32) -> ChatChannelSM<DummyChatChannelCallback> {
+        let mut sm = ChatChannelSM::new(
+            ChatData::new().clock_skew_threshold(60),
+            Box::new(move || now),
+            DummyChatChannelCallback,
+        );
+        sm.state = ChatState::Active;
+        sm
+    }
+
+    fn receive_text_events(
+        sm: &mut ChatChannelSM<DummyChatChannelCallback>,
+        timestamp: u32,
+        text: &str,
+    ) -> Vec<ChatEvent> {
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        let mut events = SMEvents::new();
+        let mut to_send = ChannelResponses::new();
+        let msg = NowVirtualChannel::from(NowChatTextMsg::new(
+            timestamp,
+            0,
+            NowString65535::from_str(text).unwrap(),
+        ));
+        sm.update_with_chan_msg(&mut data, &mut events, &mut to_send, &msg);
+        events
+            .unpack()
+            .into_iter()
+            .filter_map(|e| match e {
+                SMEvent::Chat(e) => Some(e),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn receive_text(sm: &mut ChatChannelSM<DummyChatChannelCallback>, timestamp: u32) -> usize {
+        receive_text_events(sm, timestamp, "")
+            .iter()
+            .filter(|e| matches!(e, ChatEvent::ClockSkew(_)))
+            .count()
+    }
+
+    #[test]
+    fn clock_skew_event_emitted_once() {
+        let mut sm = chat_sm(10_000);
+        assert_eq!(receive_text(&mut sm, 10_030), 0);
+        assert_eq!(sm.data.clock_skew, Some(30));
+        assert!(!sm.data.is_clock_skewed());
+
+        assert_eq!(receive_text(&mut sm, 9_000), 1);
+        assert_eq!(sm.data.clock_skew, Some(-1_000));
+        assert_eq!(receive_text(&mut sm, 9_001), 0);
+
+        assert_eq!(receive_text(&mut sm, 10_000), 0);
+        assert_eq!(receive_text(&mut sm, 11_000), 1);
+    }
+
+    #[test]
+    fn typed_message_event() {
+        let mut sm = chat_sm(10_000);
+        sm.data.clock_skew_threshold = 600;
+        let events = receive_text_events(&mut sm, 10_100, "hello");
+        assert_eq!(
+            events,
+            vec![ChatEvent::Message {
+                timestamp: 10_000,
+                distant_timestamp: 10_100,
+                text: "hello".to_owned(),
+            }]
+        );
+
+        let event = SMEvent::Chat(events[0].clone());
+        assert_eq!(event.channel_name(), Some(ChannelName::Chat));
+        assert_eq!(event.downcast_data::<ChatEvent>(), Some(&events[0]));
+    }
+
+    #[derive(Default)]
+    struct RecordingCallback {
+        typing: Vec<bool>,
+        read_up_to: Vec<u32>,
+        presence: Vec<(ChatPresenceStatus, String)>,
+    }
+
+    impl ChatChannelCallbackTrait for RecordingCallback {
+        fn on_typing_changed(&mut self, _: &mut ChatData, _: &mut ChannelResponses<'_>, typing: bool) {
+            self.typing.push(typing);
+        }
+
+        fn on_read_up_to(&mut self, _: &mut ChatData, _: &mut ChannelResponses<'_>, timestamp: u32) {
+            self.read_up_to.push(timestamp);
+        }
+
+        fn on_peer_presence_changed(
+            &mut self,
+            _: &mut ChatData,
+            _: &mut ChannelResponses<'_>,
+            presence: ChatPresenceStatus,
+            status_text: &str,
+        ) {
+            self.presence.push((presence, status_text.to_owned()));
+        }
+    }
+
+    fn receive<C: ChatChannelCallbackTrait>(sm: &mut ChatChannelSM<C>, msg: NowVirtualChannel<'_>) {
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        let mut events = SMEvents::new();
+        let mut to_send = ChannelResponses::new();
+        sm.update_with_chan_msg(&mut data, &mut events, &mut to_send, &msg);
+    }
+
+    #[test]
+    fn conversation_history() {
+        let mut sm = chat_sm(10_000);
+        sm.data.clock_skew_threshold = 600;
+        let sent = sm.data.conversation.send_text(9_990, 9_990, "hi").unwrap();
+        assert_eq!(sent.message_id, 1);
+        receive_text_events(&mut sm, 10_100, "hello");
+
+        let conversation = &sm.data.conversation;
+        assert_eq!(conversation.len(), 2);
+        assert_eq!(conversation.find(ChatDirection::Sent, 1).unwrap().text, "hi");
+        let last = conversation.last_message().unwrap();
+        assert_eq!(last.direction, ChatDirection::Received);
+        assert_eq!(last.timestamp, 10_000);
+        assert_eq!(last.text, "hello");
+        assert_eq!(conversation.unread_count(), 1);
+
+        let read = sm.data.conversation.mark_read(10_000, 10_100);
+        assert_eq!(read.timestamp, 10_100);
+        assert_eq!(sm.data.conversation.unread_count(), 0);
+    }
+
+    #[test]
+    fn conversation_max_history() {
+        let mut conversation = ChatConversation::new().max_history(2);
+        for text in &["a", "b", "c"] {
+            conversation.send_text(0, 0, text).unwrap();
+        }
+        let texts: Vec<&str> = conversation.messages().map(|entry| entry.text.as_str()).collect();
+        assert_eq!(texts, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn read_receipts_and_typing() {
+        let mut sm = ChatChannelSM::new(ChatData::new(), Box::new(|| 1_000), RecordingCallback::default());
+        sm.state = ChatState::Active;
+        sm.data.conversation.send_text(900, 900, "first").unwrap();
+        sm.data.conversation.send_text(1_100, 1_100, "second").unwrap();
+
+        receive(&mut sm, NowChatReadMsg::new(1_000).into());
+        assert_eq!(sm.user_callback.read_up_to, vec![1_000]);
+        assert!(sm.data.conversation.is_read_by_peer(1));
+        assert!(!sm.data.conversation.is_read_by_peer(2));
+
+        let typing = NowChatTypingMsg::new_with_flags(1_000, 1, ChatTypingFlags::new_empty().set_typing());
+        receive(&mut sm, typing.clone().into());
+        receive(&mut sm, typing.into());
+        assert!(sm.data.conversation.is_peer_typing());
+        receive(&mut sm, NowChatTypingMsg::new(1_000, 1).into());
+        assert_eq!(sm.user_callback.typing, vec![true, false]);
+    }
+
+    #[test]
+    fn local_presence_update() {
+        let mut sm = ChatChannelSM::new(ChatData::new(), Box::new(|| 1_000), DummyChatChannelCallback);
+        let mut to_send = ChannelResponses::new();
+        sm.set_presence(ChatPresenceStatus::Away, &mut to_send).unwrap();
+        assert!(to_send.peek().is_empty());
+
+        sm.state = ChatState::Active;
+        sm.set_status_text("lunch", &mut to_send).unwrap();
+        let sent = to_send.unpack();
+        assert_eq!(sent.len(), 2);
+        assert!(matches!(sent[0].1, NowVirtualChannel::Chat(NowChatMsg::Status(_))));
+        match &sent[1].1 {
+            NowVirtualChannel::Chat(NowChatMsg::Sync(msg)) => {
+                assert_eq!(msg.presence, ChatPresenceStatus::Away);
+                assert_eq!(msg.status_text.as_str(), "lunch");
+            }
+            msg => panic!("expected a chat sync message and got {:?}", msg),
+        }
+        assert_eq!(sm.data().status_text, "lunch");
+    }
+
+    #[test]
+    fn peer_presence_changed() {
+        let mut sm = ChatChannelSM::new(ChatData::new(), Box::new(|| 1_000), RecordingCallback::default());
+        sm.state = ChatState::Active;
+        let sync = |presence| {
+            NowChatSyncMsg::new(
+                1_000,
+                ChatCapabilitiesFlags::new_empty(),
+                NowString65535::from_str("Bob").unwrap(),
+            )
+            .presence(presence)
+            .status_text(NowString65535::from_str("None").unwrap())
+        };
+
+        receive(&mut sm, NowChatStatusMsg::new(1_000).into());
+        receive(&mut sm, sync(ChatPresenceStatus::Busy).into());
+        receive(&mut sm, sync(ChatPresenceStatus::Busy).into());
+        assert_eq!(
+            sm.user_callback.presence,
+            vec![(ChatPresenceStatus::Busy, "None".to_owned())]
+        );
+        assert_eq!(sm.data.distant_presence, ChatPresenceStatus::Busy);
+        assert_eq!(sm.data.distant_friendly_name, "Bob");
+    }
+
+    #[test]
+    fn normalize_timestamps() {
+        let mut data = ChatData::new();
+        assert_eq!(data.normalize_timestamp(500), 500);
+
+        data.clock_skew = Some(-100);
+        assert_eq!(data.normalize_timestamp(500), 600);
+        assert_eq!(data.to_distant_timestamp(600), 500);
+        assert_eq!(data.normalize_timestamp(u32::MAX), u32::MAX);
+
+        data.clock_skew = Some(1_000);
+        assert_eq!(data.normalize_timestamp(500), 0);
+    }
+
+    struct ReplyingCallback;
+
+    impl ChatChannelCallbackTrait for ReplyingCallback {
+        fn on_message(&mut self, chat_data: &mut ChatData, to_send: &mut ChannelResponses<'_>, _: &NowChatTextMsg) {
+            to_send.push(NowChatPokeMsg::new(1_000));
+            to_send.push(chat_data.conversation.mark_read(1_000, 1_000));
+            to_send.push(chat_data.conversation.send_text(1_000, 1_000, "hi \u{1F44B}!").unwrap());
+        }
+    }
+
+    #[test]
+    fn outgoing_gated_on_capabilities() {
+        let local = ChatCapabilitiesFlags::new_empty().set_emoji().set_poke().set_read();
+        let mut sm = ChatChannelSM::new(
+            ChatData::new().capabilities(local),
+            Box::new(|| 1_000),
+            ReplyingCallback,
+        );
+        sm.state = ChatState::Sync;
+        assert_eq!(sm.peer_capabilities(), None);
+
+        let peer = ChatCapabilitiesFlags::new_empty().set_read();
+        let sync = NowChatSyncMsg::new(1_000, peer, NowString65535::from_str("Bob").unwrap());
+        receive(&mut sm, sync.into());
+        assert_eq!(sm.peer_capabilities(), Some(peer));
+        assert_eq!(sm.data().capabilities, peer);
+
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        let mut events = SMEvents::new();
+        let mut to_send = ChannelResponses::new();
+        let msg = NowVirtualChannel::from(NowChatTextMsg::new(
+            1_000,
+            1,
+            NowString65535::from_str("hello").unwrap(),
+        ));
+        sm.update_with_chan_msg(&mut data, &mut events, &mut to_send, &msg);
+
+        let warnings = events
+            .unpack()
+            .into_iter()
+            .filter(|e| matches!(e, SMEvent::Warn(_)))
+            .count();
+        assert_eq!(warnings, 2);
+
+        let sent = to_send.unpack();
+        assert_eq!(sent.len(), 2);
+        assert!(matches!(sent[0].1, NowVirtualChannel::Chat(NowChatMsg::Read(_))));
+        match &sent[1].1 {
+            NowVirtualChannel::Chat(NowChatMsg::Text(msg)) => assert_eq!(msg.text.as_str(), "hi !"),
+            msg => panic!("expected a chat text message and got {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn client_and_server() {
+        let mut client = ChatChannelSM::new(
+            ChatData::new().friendly_name("Alice"),
+            Box::new(|| 1_000),
+            DummyChatChannelCallback,
+        );
+        let mut server = ChatChannelServerSM::new(
+            ChatData::new().friendly_name("Bob"),
+            Box::new(|| 1_000),
+            DummyChatChannelCallback,
+        );
+        assert!(server.waiting_for_packet());
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+
+        let mut to_server = ChannelResponses::new();
+        client.update_without_chan_msg(&mut data, &mut SMEvents::new(), &mut to_server);
+        let mut to_client = ChannelResponses::new();
+        for (_, msg) in to_server.unpack() {
+            server.update_with_chan_msg(&mut data, &mut SMEvents::new(), &mut to_client, &msg);
+        }
+        for (_, msg) in to_client.unpack() {
+            client.update_with_chan_msg(&mut data, &mut SMEvents::new(), &mut ChannelResponses::new(), &msg);
+        }
+
+        assert_eq!(client.state, ChatState::Active);
+        assert_eq!(server.0.state, ChatState::Active);
+        assert_eq!(client.data().distant_friendly_name, "Bob");
+        assert_eq!(server.data().distant_friendly_name, "Alice");
     }
 }
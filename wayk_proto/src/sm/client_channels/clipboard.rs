@@ -1,11 +1,20 @@
+pub mod formats;
+
 use crate::error::ProtoErrorKind;
 use crate::message::{
-    ChannelName, ClipboardControlState, ClipboardResponseFlags, NowClipboardCapabilitiesReqMsg,
-    NowClipboardControlReqMsg, NowClipboardControlRspMsg, NowClipboardFormatDataReqMsg, NowClipboardFormatDataRspMsg,
+    AccessControlCode, ChannelName, ClipboardControlState, ClipboardFormatDef, ClipboardResponseFlags,
+    NowClipboardCapabilitiesReqMsg, NowClipboardCapabilitiesRspMsg, NowClipboardControlReqMsg,
+    NowClipboardControlRspMsg, NowClipboardFormatDataReqMsg, NowClipboardFormatDataRspMsg,
     NowClipboardFormatListReqMsg, NowClipboardFormatListRspMsg, NowClipboardMsg, NowClipboardResumeReqMsg,
     NowClipboardResumeRspMsg, NowClipboardSuspendReqMsg, NowClipboardSuspendRspMsg, NowVirtualChannel,
 };
-use crate::sm::{ChannelResponses, ProtoState, SMData, SMEvent, SMEvents, VirtualChannelSM};
+use crate::sm::{
+    AccessStates, ChannelResponses, ProtoData, ProtoState, SMData, SMEvent, SMEvents, StateInfo, VirtualChannelSM,
+};
+use crate::sync::SharedQueue;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU8, Ordering};
 
 pub trait ClipboardChannelCallbackTrait {
     fn on_control_rsp(
@@ -124,6 +133,10 @@ impl ClipboardChannelCallbackTrait for DummyClipboardChannelCallback {}
 enum ClipboardState {
     Initial,
     Capabilities,
+    /// Server role, waiting for the client capabilities.
+    CapabilitiesReq,
+    /// Server role, waiting for the client to set the control state.
+    ControlReq,
     Disabled,
     Enabled,
     Terminated,
@@ -131,6 +144,18 @@ enum ClipboardState {
 
 impl ProtoState for ClipboardState {}
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClipboardEvent {
+    Enabled,
+    Disabled,
+    /// Local clipboard is now the shared one.
+    OwnershipTaken,
+    /// Peer took the clipboard ownership.
+    OwnershipLost,
+}
+
+impl ProtoData for ClipboardEvent {}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ClipboardData {
     is_owner: bool,
@@ -175,10 +200,96 @@ impl ClipboardData {
     }
 }
 
+enum ClipboardCommand {
+    AnnounceFormats(Vec<ClipboardFormatDef>),
+    RequestFormatData(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SyncRequest {
+    Suspend,
+    Resume,
+}
+
+/// Last suspend or resume asked by the application, only the latest one matters.
+#[derive(Clone, Default)]
+struct SyncToggle(Arc<AtomicU8>);
+
+impl SyncToggle {
+    const NONE: u8 = 0;
+    const SUSPEND: u8 = 1;
+    const RESUME: u8 = 2;
+
+    fn set(&self, request: SyncRequest) {
+        let value = match request {
+            SyncRequest::Suspend => Self::SUSPEND,
+            SyncRequest::Resume => Self::RESUME,
+        };
+        self.0.store(value, Ordering::Release);
+    }
+
+    fn peek(&self) -> Option<SyncRequest> {
+        Self::h_decode(self.0.load(Ordering::Acquire))
+    }
+
+    fn take(&self) -> Option<SyncRequest> {
+        Self::h_decode(self.0.swap(Self::NONE, Ordering::AcqRel))
+    }
+
+    fn h_decode(value: u8) -> Option<SyncRequest> {
+        match value {
+            Self::SUSPEND => Some(SyncRequest::Suspend),
+            Self::RESUME => Some(SyncRequest::Resume),
+            _ => None,
+        }
+    }
+}
+
+/// Drives the clipboard channel from the application, possibly from another thread.
+///
+/// Requests are queued and sent on the next channel update once the clipboard is enabled, so the
+/// sharee must be updated for them to go out (see `Sharee::waiting_for_packet`).
+#[derive(Clone)]
+pub struct ClipboardHandle {
+    commands: SharedQueue<ClipboardCommand>,
+    toggle: SyncToggle,
+}
+
+impl ClipboardHandle {
+    /// Takes the clipboard ownership and publishes the formats available locally.
+    ///
+    /// `ClipboardEvent::OwnershipTaken` is emitted once the peer accepts.
+    pub fn announce_formats(&self, formats: Vec<ClipboardFormatDef>) {
+        self.commands.push(ClipboardCommand::AnnounceFormats(formats));
+    }
+
+    /// Fetches the peer clipboard content in the given format, the content is handed over to
+    /// `ClipboardChannelCallbackTrait::on_format_data_rsp`. Ignored while owning the clipboard.
+    pub fn request_format_data(&self, format_id: u32) {
+        self.commands.push(ClipboardCommand::RequestFormatData(format_id));
+    }
+
+    /// Pauses the clipboard synchronization, see `ClipboardChannelSM::suspend`.
+    pub fn suspend(&self) {
+        self.toggle.set(SyncRequest::Suspend);
+    }
+
+    /// Resumes the clipboard synchronization, see `ClipboardChannelSM::resume`.
+    pub fn resume(&self) {
+        self.toggle.set(SyncRequest::Resume);
+    }
+}
+
 pub struct ClipboardChannelSM<UserCallback> {
     state: ClipboardState,
     data: ClipboardData,
     user_callback: UserCallback,
+    commands: SharedQueue<ClipboardCommand>,
+    toggle: SyncToggle,
+    /// Disabled by a suspend, by either side, rather than waiting for the control response.
+    suspended: bool,
+    /// A suspend or resume request is waiting for its response.
+    toggle_pending: bool,
 }
 
 impl<UserCallback> ClipboardChannelSM<UserCallback>
@@ -190,7 +301,114 @@ where
             state: ClipboardState::Initial,
             data,
             user_callback,
+            commands: SharedQueue::default(),
+            toggle: SyncToggle::default(),
+            suspended: false,
+            toggle_pending: false,
+        }
+    }
+
+    /// Handle to publish or fetch clipboard contents at any time, see `ClipboardHandle`.
+    pub fn handle(&self) -> ClipboardHandle {
+        ClipboardHandle {
+            commands: self.commands.clone(),
+            toggle: self.toggle.clone(),
+        }
+    }
+
+    /// Asks the peer to pause the clipboard synchronization, for instance on a privacy toggle.
+    ///
+    /// The request is sent on the next channel update once the clipboard is enabled and
+    /// `ClipboardEvent::Disabled` is emitted when the peer accepts. A later `resume` call
+    /// supersedes a suspend that isn't sent yet.
+    pub fn suspend(&self) {
+        self.toggle.set(SyncRequest::Suspend);
+    }
+
+    /// Asks the peer to resume a suspended clipboard synchronization, `ClipboardEvent::Enabled`
+    /// is emitted when the peer accepts.
+    pub fn resume(&self) {
+        self.toggle.set(SyncRequest::Resume);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state == ClipboardState::Enabled
+    }
+
+    /// Suspended by either side, and not resumed since.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Sends the suspend or resume asked by the application, if it applies to the current state.
+    fn h_run_toggle(&mut self, to_send: &mut ChannelResponses<'_>) {
+        if !self.h_toggle_ready() {
+            return;
         }
+
+        match self.toggle.take() {
+            Some(SyncRequest::Suspend) if self.state == ClipboardState::Enabled => {
+                log::trace!("asking peer for suspending");
+                self.toggle_pending = true;
+                to_send.push(NowClipboardSuspendReqMsg::default());
+            }
+            Some(SyncRequest::Resume) if self.suspended => {
+                log::trace!("asking peer for resuming");
+                self.toggle_pending = true;
+                to_send.push(NowClipboardResumeReqMsg::default());
+            }
+            Some(request) => log::trace!("{:?} ignored in state {:?}", request, self.state),
+            None => {}
+        }
+    }
+
+    /// Whether an application suspend or resume request can be handled now.
+    fn h_toggle_ready(&self) -> bool {
+        let settled = match self.state {
+            ClipboardState::Enabled => true,
+            ClipboardState::Disabled => self.suspended,
+            _ => false,
+        };
+        settled && !self.toggle_pending && self.toggle.peek().is_some()
+    }
+
+    fn h_run_commands(&mut self, data: &SMData, events: &mut SMEvents<'_>, to_send: &mut ChannelResponses<'_>) {
+        while let Some(command) = self.commands.pop() {
+            if !Self::h_access_permitted(data) {
+                events.push(SMEvent::warn(
+                    ProtoErrorKind::AccessControl(AccessControlCode::Clipboard),
+                    "clipboard command dropped: access disallowed by peer",
+                ));
+                continue;
+            }
+
+            match command {
+                ClipboardCommand::AnnounceFormats(formats) => {
+                    log::trace!("announcing {} formats", formats.len());
+                    to_send.push(NowClipboardFormatListReqMsg::new_with_formats(
+                        self.data.next_sequence_id(),
+                        formats,
+                    ));
+                }
+                ClipboardCommand::RequestFormatData(_) if self.data.is_owner => events.push(SMEvent::warn(
+                    ProtoErrorKind::VirtualChannel(ChannelName::Clipboard),
+                    "format data request dropped: local clipboard is the shared one",
+                )),
+                ClipboardCommand::RequestFormatData(format_id) => {
+                    to_send.push(NowClipboardFormatDataReqMsg::new(
+                        self.data.next_sequence_id(),
+                        format_id,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Peer may disallow clipboard access mid-session, see `AccessStates`.
+    fn h_access_permitted(data: &SMData) -> bool {
+        data.extra_get::<AccessStates>()
+            .map(|states| states.permits(AccessControlCode::Clipboard))
+            .unwrap_or(true)
     }
 
     fn h_unexpected_with_call<'msg>(&self, events: &mut SMEvents<'msg>) {
@@ -223,6 +441,68 @@ where
     }
 }
 
+/// Server role of the clipboard channel: answers the capabilities and control requests sent by
+/// `ClipboardChannelSM`, then behaves the same (ownership transfers, format data, suspend and
+/// resume).
+pub struct ClipboardChannelServerSM<UserCallback>(ClipboardChannelSM<UserCallback>);
+
+impl<UserCallback> ClipboardChannelServerSM<UserCallback>
+where
+    UserCallback: ClipboardChannelCallbackTrait,
+{
+    pub fn new(data: ClipboardData, user_callback: UserCallback) -> Self {
+        Self(ClipboardChannelSM {
+            state: ClipboardState::CapabilitiesReq,
+            ..ClipboardChannelSM::new(data, user_callback)
+        })
+    }
+
+    /// Handle to publish or fetch clipboard contents at any time, see `ClipboardHandle`.
+    pub fn handle(&self) -> ClipboardHandle {
+        self.0.handle()
+    }
+}
+
+impl<UserCallback> VirtualChannelSM for ClipboardChannelServerSM<UserCallback>
+where
+    UserCallback: ClipboardChannelCallbackTrait,
+{
+    fn get_channel_name(&self) -> ChannelName {
+        self.0.get_channel_name()
+    }
+
+    fn is_terminated(&self) -> bool {
+        self.0.is_terminated()
+    }
+
+    fn waiting_for_packet(&self) -> bool {
+        self.0.waiting_for_packet()
+    }
+
+    fn update_without_chan_msg<'msg>(
+        &mut self,
+        data: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut ChannelResponses<'msg>,
+    ) {
+        self.0.update_without_chan_msg(data, events, to_send)
+    }
+
+    fn update_with_chan_msg<'msg: 'a, 'a>(
+        &mut self,
+        data: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut ChannelResponses<'msg>,
+        msg: &'a NowVirtualChannel<'msg>,
+    ) {
+        self.0.update_with_chan_msg(data, events, to_send, msg)
+    }
+
+    fn state_info(&self) -> Option<StateInfo> {
+        self.0.state_info()
+    }
+}
+
 impl<UserCallback> VirtualChannelSM for ClipboardChannelSM<UserCallback>
 where
     UserCallback: ClipboardChannelCallbackTrait,
@@ -239,15 +519,16 @@ where
         match self.state {
             ClipboardState::Initial => false,
             ClipboardState::Capabilities => true,
-            ClipboardState::Disabled => true,
-            ClipboardState::Enabled => true,
+            ClipboardState::CapabilitiesReq | ClipboardState::ControlReq => true,
+            ClipboardState::Disabled => !self.h_toggle_ready(),
+            ClipboardState::Enabled => self.commands.is_empty() && !self.h_toggle_ready(),
             ClipboardState::Terminated => false,
         }
     }
 
     fn update_without_chan_msg<'msg>(
         &mut self,
-        _: &mut SMData,
+        data: &mut SMData,
         events: &mut SMEvents<'msg>,
         to_send: &mut ChannelResponses<'msg>,
     ) {
@@ -256,6 +537,11 @@ where
                 self.h_transition_state(events, ClipboardState::Capabilities);
                 to_send.push(NowClipboardCapabilitiesReqMsg::default());
             }
+            ClipboardState::Enabled => {
+                self.h_run_toggle(to_send);
+                self.h_run_commands(data, events, to_send);
+            }
+            ClipboardState::Disabled if self.h_toggle_ready() => self.h_run_toggle(to_send),
             _ => {
                 self.h_unexpected_without_call(events);
             }
@@ -275,6 +561,7 @@ where
             self.h_unexpected_message(events, msg);
             return;
         };
+        __enter_span!("clipboard", state = ?self.state, sequence_id = ?m.sequence_id());
 
         match self.state {
             ClipboardState::Capabilities => match m {
@@ -294,6 +581,26 @@ where
                     self.h_unexpected_message(events, msg);
                 }
             },
+            ClipboardState::CapabilitiesReq => match m {
+                NowClipboardMsg::CapabilitiesReq(_) => {
+                    self.h_transition_state(events, ClipboardState::ControlReq);
+                    to_send.push(NowClipboardCapabilitiesRspMsg::default());
+                }
+                _ => {
+                    self.h_unexpected_message(events, msg);
+                }
+            },
+            ClipboardState::ControlReq => match m {
+                NowClipboardMsg::ControlReq(m) => {
+                    self.h_transition_state(events, ClipboardState::Enabled);
+                    events.push(SMEvent::Clipboard(ClipboardEvent::Enabled));
+                    log::trace!("enabled (control: {:?})", m.control_state);
+                    to_send.push(NowClipboardControlRspMsg::new(m.control_state));
+                }
+                _ => {
+                    self.h_unexpected_message(events, msg);
+                }
+            },
             ClipboardState::Disabled => match m {
                 NowClipboardMsg::ControlRsp(m) => {
                     if m.flags.failure() {
@@ -305,6 +612,7 @@ where
                     }
 
                     self.h_transition_state(events, ClipboardState::Enabled);
+                    events.push(SMEvent::Clipboard(ClipboardEvent::Enabled));
                     log::trace!("enabled (control: {:?})", m.control_state);
                     self.user_callback.on_control_rsp(&mut self.data, data, to_send, m);
                 }
@@ -312,7 +620,9 @@ where
                     log::trace!("peer asked for resuming");
                     if self.user_callback.accept_resume(&mut self.data, data, m) {
                         log::trace!("resume request accepted");
+                        self.suspended = false;
                         self.h_transition_state(events, ClipboardState::Enabled);
+                        events.push(SMEvent::Clipboard(ClipboardEvent::Enabled));
                         to_send.push(NowClipboardResumeRspMsg::default());
                     } else {
                         log::trace!("resume request refused");
@@ -322,6 +632,7 @@ where
                     }
                 }
                 NowClipboardMsg::ResumeRsp(m) => {
+                    self.toggle_pending = false;
                     if m.flags.failure() {
                         events.push(SMEvent::error(
                             ProtoErrorKind::VirtualChannel(self.get_channel_name()),
@@ -330,7 +641,9 @@ where
                         return;
                     }
 
+                    self.suspended = false;
                     self.h_transition_state(events, ClipboardState::Enabled);
+                    events.push(SMEvent::Clipboard(ClipboardEvent::Enabled));
                     log::trace!("resumed");
                     self.user_callback.on_resume_rsp(&mut self.data, data, to_send, m);
                 }
@@ -340,6 +653,7 @@ where
             },
             ClipboardState::Enabled => match m {
                 NowClipboardMsg::SuspendRsp(m) => {
+                    self.toggle_pending = false;
                     if m.flags.failure() {
                         events.push(SMEvent::error(
                             ProtoErrorKind::VirtualChannel(self.get_channel_name()),
@@ -348,14 +662,39 @@ where
                         return;
                     }
 
+                    self.suspended = true;
                     self.h_transition_state(events, ClipboardState::Disabled);
+                    events.push(SMEvent::Clipboard(ClipboardEvent::Disabled));
                     log::trace!("disabled");
                     self.user_callback.on_suspend_rsp(&mut self.data, data, to_send, m);
                 }
+                NowClipboardMsg::SuspendReq(m) => {
+                    log::trace!("peer asked for suspending");
+                    if self.user_callback.on_suspend_req(&mut self.data, data, to_send, m) {
+                        log::trace!("suspend request accepted");
+                        self.suspended = true;
+                        self.h_transition_state(events, ClipboardState::Disabled);
+                        events.push(SMEvent::Clipboard(ClipboardEvent::Disabled));
+                        to_send.push(NowClipboardSuspendRspMsg::default());
+                    } else {
+                        log::trace!("suspend request refused");
+                        to_send.push(NowClipboardSuspendRspMsg::new_with_flags(
+                            ClipboardResponseFlags::new_empty().set_failure(),
+                        ));
+                    }
+                }
+                NowClipboardMsg::FormatListReq(_) if !Self::h_access_permitted(data) => {
+                    log::trace!("ownership transfer refused: clipboard access disallowed");
+                    to_send.push(NowClipboardFormatListRspMsg::new_with_flags(
+                        self.data.next_sequence_id(),
+                        ClipboardResponseFlags::new_empty().set_failure(),
+                    ));
+                }
                 NowClipboardMsg::FormatListReq(m) => {
                     log::trace!("peer asked for ownership");
                     if self.user_callback.transfer_ownership_to_peer(&mut self.data, data, m) {
                         self.data.is_owner = false;
+                        events.push(SMEvent::Clipboard(ClipboardEvent::OwnershipLost));
                         log::trace!("ownership transferred to peer");
                         to_send.push(NowClipboardFormatListRspMsg::new(self.data.next_sequence_id()));
                         self.user_callback.on_auto_fetch(&mut self.data, data, to_send, m);
@@ -377,9 +716,18 @@ where
                     }
 
                     self.data.is_owner = true;
+                    events.push(SMEvent::Clipboard(ClipboardEvent::OwnershipTaken));
                     log::trace!("took ownership");
                     self.user_callback.on_format_list_rsp(&mut self.data, data, to_send, m);
                 }
+                NowClipboardMsg::FormatDataReq(m) if !Self::h_access_permitted(data) => {
+                    log::trace!("format data request refused: clipboard access disallowed");
+                    to_send.push(NowClipboardFormatDataRspMsg::new_with_flags(
+                        m.sequence_id,
+                        m.format_id,
+                        ClipboardResponseFlags::new_empty().set_failure(),
+                    ));
+                }
                 NowClipboardMsg::FormatDataReq(m) => {
                     if self.data.is_owner || self.data.auto_fetch {
                         self.user_callback.on_format_data_req(&mut self.data, data, to_send, m);
@@ -409,4 +757,214 @@ where
             }
         }
     }
+
+    fn state_info(&self) -> Option<StateInfo> {
+        Some(match self.state {
+            ClipboardState::Initial => StateInfo::new("Initial", &["Capabilities"]),
+            ClipboardState::Capabilities => StateInfo::new("Capabilities", &["Disabled"]),
+            ClipboardState::CapabilitiesReq => StateInfo::new("CapabilitiesReq", &["ControlReq"]),
+            ClipboardState::ControlReq => StateInfo::new("ControlReq", &["Enabled"]),
+            ClipboardState::Disabled => StateInfo::new("Disabled", &["Enabled"]),
+            ClipboardState::Enabled => StateInfo::new("Enabled", &["Disabled"]),
+            ClipboardState::Terminated => StateInfo::new("Terminated", &[]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{AccessControlDef, AccessFlags, NowAccessMsg, NowAcessControlNtf, NowString256};
+    use crate::sm::AccessControlSM;
+    use core::str::FromStr;
+
+    fn sm_data() -> SMData {
+        SMData::new(Vec::new(), Vec::new(), Vec::new())
+    }
+
+    fn clipboard_msg<'a>(msg: &'a NowVirtualChannel<'a>) -> &'a NowClipboardMsg<'a> {
+        match msg {
+            NowVirtualChannel::Clipboard(msg) => msg,
+            msg => panic!("expected a clipboard message and got {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn ownership_through_handle() {
+        let mut sm = ClipboardChannelSM::new(ClipboardData::new(), DummyClipboardChannelCallback);
+        sm.state = ClipboardState::Enabled;
+        let handle = sm.handle();
+        assert!(sm.waiting_for_packet());
+
+        // publish local content
+        let text = ClipboardFormatDef::new(0, NowString256::from_str("UTF8_STRING").unwrap());
+        handle.announce_formats(vec![text]);
+        assert!(!sm.waiting_for_packet());
+
+        let mut to_send = ChannelResponses::new();
+        sm.update_without_chan_msg(&mut sm_data(), &mut SMEvents::new(), &mut to_send);
+        assert!(sm.waiting_for_packet());
+        let sent = to_send.unpack();
+        assert_eq!(sent.len(), 1);
+        match clipboard_msg(&sent[0].1) {
+            NowClipboardMsg::FormatListReq(req) => {
+                assert_eq!(req.sequence_id, 1);
+                assert_eq!(req.formats.len(), 1);
+            }
+            msg => panic!("expected a format list request and got {:?}", msg),
+        }
+
+        let rsp = NowVirtualChannel::from(NowClipboardMsg::from(NowClipboardFormatListRspMsg::new(1)));
+        let mut events = SMEvents::new();
+        sm.update_with_chan_msg(&mut sm_data(), &mut events, &mut ChannelResponses::new(), &rsp);
+        assert!(sm.data.is_owner());
+        assert!(matches!(
+            events.peek(),
+            [SMEvent::Clipboard(ClipboardEvent::OwnershipTaken)]
+        ));
+
+        // nothing to fetch while owner
+        handle.request_format_data(0);
+        let mut events = SMEvents::new();
+        let mut to_send = ChannelResponses::new();
+        sm.update_without_chan_msg(&mut sm_data(), &mut events, &mut to_send);
+        assert!(to_send.peek().is_empty());
+        assert!(matches!(events.peek(), [SMEvent::Warn(_)]));
+
+        // peer took the ownership back
+        sm.data.is_owner = false;
+        handle.request_format_data(13);
+        let mut to_send = ChannelResponses::new();
+        sm.update_without_chan_msg(&mut sm_data(), &mut SMEvents::new(), &mut to_send);
+        match clipboard_msg(&to_send.peek()[0].1) {
+            NowClipboardMsg::FormatDataReq(req) => assert_eq!(req.format_id, 13),
+            msg => panic!("expected a format data request and got {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn refused_when_access_disabled() {
+        let mut sm = ClipboardChannelSM::new(ClipboardData::new(), DummyClipboardChannelCallback);
+        sm.state = ClipboardState::Enabled;
+
+        let mut access = AccessControlSM::new_with_access_controls(vec![AccessControlDef::new_allowed(
+            AccessControlCode::Clipboard,
+        )]);
+        access.update_with_message(
+            &mut SMEvents::new(),
+            &NowAccessMsg::Ntf(NowAcessControlNtf::new(
+                AccessControlCode::Clipboard,
+                AccessFlags::new_empty().set_disabled(),
+            )),
+        );
+        let mut data = sm_data();
+        data.extra_insert(access.access_states());
+
+        let req = NowVirtualChannel::from(NowClipboardMsg::from(NowClipboardFormatDataReqMsg::new(3, 13)));
+        let mut to_send = ChannelResponses::new();
+        sm.update_with_chan_msg(&mut data, &mut SMEvents::new(), &mut to_send, &req);
+        match clipboard_msg(&to_send.peek()[0].1) {
+            NowClipboardMsg::FormatDataRsp(rsp) => assert!(rsp.flags.failure()),
+            msg => panic!("expected a format data response and got {:?}", msg),
+        }
+
+        sm.handle().request_format_data(13);
+        let mut events = SMEvents::new();
+        let mut to_send = ChannelResponses::new();
+        sm.update_without_chan_msg(&mut data, &mut events, &mut to_send);
+        assert!(to_send.peek().is_empty());
+        assert!(matches!(events.peek(), [SMEvent::Warn(_)]));
+    }
+
+    #[test]
+    fn client_and_server() {
+        let mut client = ClipboardChannelSM::new(ClipboardData::new(), DummyClipboardChannelCallback);
+        let mut server = ClipboardChannelServerSM::new(ClipboardData::new(), DummyClipboardChannelCallback);
+        assert!(server.waiting_for_packet());
+
+        let mut to_server = ChannelResponses::new();
+        client.update_without_chan_msg(&mut sm_data(), &mut SMEvents::new(), &mut to_server);
+        // capabilities, then control state
+        for _ in 0..2 {
+            let mut to_client = ChannelResponses::new();
+            for (_, msg) in to_server.unpack() {
+                server.update_with_chan_msg(&mut sm_data(), &mut SMEvents::new(), &mut to_client, &msg);
+            }
+            to_server = ChannelResponses::new();
+            for (_, msg) in to_client.unpack() {
+                client.update_with_chan_msg(&mut sm_data(), &mut SMEvents::new(), &mut to_server, &msg);
+            }
+        }
+        assert_eq!(client.state, ClipboardState::Enabled);
+        assert_eq!(server.0.state, ClipboardState::Enabled);
+
+        // server takes the ownership
+        let text = ClipboardFormatDef::new(0, NowString256::from_str("UTF8_STRING").unwrap());
+        server.handle().announce_formats(vec![text]);
+        let mut to_client = ChannelResponses::new();
+        server.update_without_chan_msg(&mut sm_data(), &mut SMEvents::new(), &mut to_client);
+        let mut to_server = ChannelResponses::new();
+        let mut client_events = SMEvents::new();
+        for (_, msg) in to_client.unpack() {
+            client.update_with_chan_msg(&mut sm_data(), &mut client_events, &mut to_server, &msg);
+        }
+        assert!(matches!(
+            client_events.peek(),
+            [SMEvent::Clipboard(ClipboardEvent::OwnershipLost)]
+        ));
+        for (_, msg) in to_server.unpack() {
+            server.update_with_chan_msg(&mut sm_data(), &mut SMEvents::new(), &mut ChannelResponses::new(), &msg);
+        }
+        assert!(server.0.data.is_owner());
+    }
+
+    #[test]
+    fn suspend_and_resume() {
+        let mut client = ClipboardChannelSM::new(ClipboardData::new(), DummyClipboardChannelCallback);
+        client.state = ClipboardState::Enabled;
+        let mut server = ClipboardChannelServerSM::new(ClipboardData::new(), DummyClipboardChannelCallback);
+        server.0.state = ClipboardState::Enabled;
+
+        // sends the pending request to the server and hands its answer back
+        let exchange = |client: &mut ClipboardChannelSM<_>, server: &mut ClipboardChannelServerSM<_>| {
+            let mut to_server = ChannelResponses::new();
+            client.update_without_chan_msg(&mut sm_data(), &mut SMEvents::new(), &mut to_server);
+            let mut to_client = ChannelResponses::new();
+            for (_, msg) in to_server.unpack() {
+                server.update_with_chan_msg(&mut sm_data(), &mut SMEvents::new(), &mut to_client, &msg);
+            }
+            let mut events = SMEvents::new();
+            for (_, msg) in to_client.unpack() {
+                client.update_with_chan_msg(&mut sm_data(), &mut events, &mut ChannelResponses::new(), &msg);
+            }
+            events.unpack()
+        };
+
+        // nothing to resume while enabled
+        client.resume();
+        assert!(!client.waiting_for_packet());
+        assert!(exchange(&mut client, &mut server).is_empty());
+        assert!(client.waiting_for_packet());
+
+        client.handle().suspend();
+        assert!(!client.waiting_for_packet());
+        let events = exchange(&mut client, &mut server);
+        assert!(matches!(
+            events.as_slice(),
+            [_, SMEvent::Clipboard(ClipboardEvent::Disabled)]
+        ));
+        assert!(client.is_suspended() && !client.is_enabled());
+        assert!(server.0.is_suspended());
+        assert!(client.waiting_for_packet());
+
+        client.resume();
+        assert!(!client.waiting_for_packet());
+        let events = exchange(&mut client, &mut server);
+        assert!(matches!(
+            events.as_slice(),
+            [_, SMEvent::Clipboard(ClipboardEvent::Enabled)]
+        ));
+        assert!(!client.is_suspended() && client.is_enabled());
+        assert!(!server.0.is_suspended());
+    }
 }
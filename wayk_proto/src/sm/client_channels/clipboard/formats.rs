@@ -0,0 +1,401 @@
+//! Well-known clipboard formats.
+//!
+//! Format ids are only meaningful to the peer that advertised them: the requesting side looks the
+//! format up by name in the received format list and asks for the associated id. Local content is
+//! converted to the wire representation of the requested format when answering a format data
+//! request.
+
+use crate::error::*;
+use crate::message::{
+    ChannelName, ClipboardFormatDef, ClipboardResponseFlags, NowClipboardFormatDataReqMsg,
+    NowClipboardFormatDataRspMsg, NowClipboardFormatDataRspMsgOwned, NowClipboardFormatListReqMsg, NowString256,
+};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Formats with a well-known name and wire representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StandardFormat {
+    /// UTF-8 text, no terminator.
+    Utf8String,
+    /// UTF-8 html fragment.
+    Html,
+    /// PNG image.
+    Png,
+    /// UTF-16LE text, null terminated.
+    UnicodeText,
+    /// `\r\n` separated list of URIs as per RFC 2483.
+    FileList,
+}
+
+impl StandardFormat {
+    pub const ALL: [StandardFormat; 5] = [
+        StandardFormat::Utf8String,
+        StandardFormat::Html,
+        StandardFormat::Png,
+        StandardFormat::UnicodeText,
+        StandardFormat::FileList,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StandardFormat::Utf8String => "UTF8_STRING",
+            StandardFormat::Html => "text/html",
+            StandardFormat::Png => "image/png",
+            StandardFormat::UnicodeText => "CF_UNICODETEXT",
+            StandardFormat::FileList => "text/uri-list",
+        }
+    }
+
+    /// Id used when advertising this format locally.
+    ///
+    /// `CF_UNICODETEXT` keeps its Windows clipboard id, others use the registered formats range.
+    pub fn default_id(self) -> u32 {
+        match self {
+            StandardFormat::Utf8String => 0xC000,
+            StandardFormat::Html => 0xC001,
+            StandardFormat::Png => 0xC002,
+            StandardFormat::UnicodeText => 13,
+            StandardFormat::FileList => 0xC003,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|format| format.name() == name)
+    }
+
+    pub fn def(self) -> ClipboardFormatDef {
+        ClipboardFormatDef::new(self.default_id(), NowString256::from_str_lossy(self.name()))
+    }
+}
+
+/// Local clipboard content, independent of any wire format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClipboardContent {
+    Text(String),
+    Html(String),
+    Png(Vec<u8>),
+    Files(Vec<String>),
+}
+
+impl ClipboardContent {
+    /// Formats this content can be served as, by order of preference.
+    pub fn formats(&self) -> &'static [StandardFormat] {
+        match self {
+            ClipboardContent::Text(_) => &[StandardFormat::Utf8String, StandardFormat::UnicodeText],
+            ClipboardContent::Html(_) => &[StandardFormat::Html],
+            ClipboardContent::Png(_) => &[StandardFormat::Png],
+            ClipboardContent::Files(_) => &[StandardFormat::FileList],
+        }
+    }
+
+    /// Wire representation of this content in the given format, `None` if not convertible.
+    pub fn encode_as(&self, format: StandardFormat) -> Option<Vec<u8>> {
+        match (self, format) {
+            (ClipboardContent::Text(text), StandardFormat::Utf8String) => Some(text.as_bytes().to_vec()),
+            (ClipboardContent::Text(text), StandardFormat::UnicodeText) => {
+                let mut data = Vec::with_capacity((text.len() + 1) * 2);
+                for unit in text.encode_utf16().chain(core::iter::once(0)) {
+                    data.extend_from_slice(&unit.to_le_bytes());
+                }
+                Some(data)
+            }
+            (ClipboardContent::Html(html), StandardFormat::Html) => Some(html.as_bytes().to_vec()),
+            (ClipboardContent::Png(png), StandardFormat::Png) => Some(png.clone()),
+            (ClipboardContent::Files(files), StandardFormat::FileList) => {
+                let mut list = String::new();
+                for file in files {
+                    list.push_str(file);
+                    list.push_str("\r\n");
+                }
+                Some(list.into_bytes())
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses data received for the given format.
+    pub fn decode_from(format: StandardFormat, data: &[u8]) -> Result<Self> {
+        match format {
+            StandardFormat::Utf8String => Ok(ClipboardContent::Text(String::from_utf8(data.to_vec())?)),
+            StandardFormat::Html => Ok(ClipboardContent::Html(String::from_utf8(data.to_vec())?)),
+            StandardFormat::Png => Ok(ClipboardContent::Png(data.to_vec())),
+            StandardFormat::UnicodeText => {
+                let units = data.chunks_exact(2);
+                if !units.remainder().is_empty() {
+                    return Err(ProtoError::new(ProtoErrorKind::Decoding("CF_UNICODETEXT"))
+                        .with_desc("odd number of bytes in utf-16 data"));
+                }
+
+                let units = units
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .take_while(|&unit| unit != 0);
+                let text = core::char::decode_utf16(units)
+                    .collect::<core::result::Result<String, _>>()
+                    .map_err(|_| {
+                        ProtoError::new(ProtoErrorKind::Decoding("CF_UNICODETEXT")).with_desc("invalid utf-16 data")
+                    })?;
+                Ok(ClipboardContent::Text(text))
+            }
+            StandardFormat::FileList => {
+                let list = String::from_utf8(data.to_vec())?;
+                let files = list
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from)
+                    .collect();
+                Ok(ClipboardContent::Files(files))
+            }
+        }
+    }
+}
+
+/// Format list as advertised by one end.
+///
+/// Built locally to advertise available formats, or from a received format list to resolve the
+/// peer's ids.
+#[derive(Debug, Clone, Default)]
+pub struct ClipboardFormats {
+    defs: Vec<ClipboardFormatDef>,
+}
+
+impl ClipboardFormats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Formats advertised by the peer.
+    pub fn from_defs(defs: &[ClipboardFormatDef]) -> Self {
+        Self { defs: defs.to_vec() }
+    }
+
+    /// All formats the given content can be served as.
+    pub fn for_content(content: &ClipboardContent) -> Self {
+        content
+            .formats()
+            .iter()
+            .fold(Self::new(), |formats, &format| formats.with_standard(format))
+    }
+
+    /// Adds a standard format, unless already present.
+    pub fn with_standard(mut self, format: StandardFormat) -> Self {
+        if self.id_of(format).is_none() {
+            self.defs.push(format.def());
+        }
+        self
+    }
+
+    /// Adds an application specific format.
+    pub fn with_custom(mut self, id: u32, name: NowString256) -> Self {
+        self.defs.push(ClipboardFormatDef::new(id, name));
+        self
+    }
+
+    pub fn defs(&self) -> &[ClipboardFormatDef] {
+        &self.defs
+    }
+
+    /// Suitable for `NowClipboardFormatListReqMsg::new_with_formats`.
+    pub fn into_defs(self) -> Vec<ClipboardFormatDef> {
+        self.defs
+    }
+
+    pub fn find_by_id(&self, id: u32) -> Option<&ClipboardFormatDef> {
+        self.defs.iter().find(|def| def.id == id)
+    }
+
+    /// Standard format advertised under the given id, if any.
+    pub fn standard_by_id(&self, id: u32) -> Option<StandardFormat> {
+        self.find_by_id(id)
+            .and_then(|def| StandardFormat::from_name(def.name.as_str()))
+    }
+
+    /// Id advertised for the given standard format, if any.
+    pub fn id_of(&self, format: StandardFormat) -> Option<u32> {
+        self.defs
+            .iter()
+            .find(|def| def.name.as_str() == format.name())
+            .map(|def| def.id)
+    }
+
+    /// First of the given formats (by order of preference) advertised, with its id.
+    pub fn select(&self, preferred: &[StandardFormat]) -> Option<(StandardFormat, u32)> {
+        preferred
+            .iter()
+            .find_map(|&format| self.id_of(format).map(|id| (format, id)))
+    }
+
+    /// Advertises these formats to the peer, i.e. takes the clipboard ownership.
+    pub fn format_list_req(&self, sequence_id: u16) -> NowClipboardFormatListReqMsg {
+        NowClipboardFormatListReqMsg::new_with_formats(sequence_id, self.defs.clone())
+    }
+
+    /// Requests the data of the first of the given formats advertised by the peer.
+    ///
+    /// The returned format is needed to decode the response (see `decode_format_data_rsp`).
+    pub fn format_data_req(
+        &self,
+        sequence_id: u16,
+        preferred: &[StandardFormat],
+    ) -> Option<(StandardFormat, NowClipboardFormatDataReqMsg)> {
+        self.select(preferred)
+            .map(|(format, id)| (format, NowClipboardFormatDataReqMsg::new(sequence_id, id)))
+    }
+
+    /// Parses the data received in answer to a format data request.
+    pub fn decode_format_data_rsp(&self, rsp: &NowClipboardFormatDataRspMsg<'_>) -> Result<ClipboardContent> {
+        if rsp.flags.failure() {
+            return Err(ProtoError::new(ProtoErrorKind::VirtualChannel(ChannelName::Clipboard))
+                .with_desc("format data request failed (failure flag received)"));
+        }
+
+        let format = self.standard_by_id(rsp.format_id).ok_or_else(|| {
+            ProtoError::new(ProtoErrorKind::VirtualChannel(ChannelName::Clipboard))
+                .with_desc(format!("format data received for unknown format id {}", rsp.format_id))
+        })?;
+        ClipboardContent::decode_from(format, &rsp.format_data)
+    }
+
+    /// Answers a format data request with the given local content.
+    ///
+    /// The failure flag is set if the requested id is unknown or the content can't be converted.
+    pub fn format_data_rsp(
+        &self,
+        req: &NowClipboardFormatDataReqMsg,
+        content: &ClipboardContent,
+    ) -> NowClipboardFormatDataRspMsgOwned {
+        match self
+            .standard_by_id(req.format_id)
+            .and_then(|format| content.encode_as(format))
+        {
+            Some(data) => NowClipboardFormatDataRspMsgOwned::new_with_format_data(req.sequence_id, req.format_id, data),
+            None => NowClipboardFormatDataRspMsgOwned::new_with_flags(
+                req.sequence_id,
+                req.format_id,
+                ClipboardResponseFlags::new_empty().set_failure(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const UNICODE_TEXT_DATA: [u8; 8] = [
+        0x68, 0x00, // h
+        0x69, 0x00, // i
+        0x60, 0x4f, // 你
+        0x00, 0x00, // null
+    ];
+
+    #[test]
+    fn unicode_text_conversion() {
+        let content = ClipboardContent::Text(String::from("hi你"));
+        assert_eq!(
+            content.encode_as(StandardFormat::UnicodeText).unwrap(),
+            UNICODE_TEXT_DATA.to_vec()
+        );
+        assert_eq!(
+            ClipboardContent::decode_from(StandardFormat::UnicodeText, &UNICODE_TEXT_DATA).unwrap(),
+            content
+        );
+        assert!(ClipboardContent::decode_from(StandardFormat::UnicodeText, &UNICODE_TEXT_DATA[..3]).is_err());
+        assert_eq!(content.encode_as(StandardFormat::Png), None);
+    }
+
+    #[test]
+    fn file_list_conversion() {
+        let content = ClipboardContent::Files(vec![
+            String::from("file:///tmp/a.txt"),
+            String::from("file:///tmp/b.txt"),
+        ]);
+        let data = content.encode_as(StandardFormat::FileList).unwrap();
+        assert_eq!(data, b"file:///tmp/a.txt\r\nfile:///tmp/b.txt\r\n".to_vec());
+        assert_eq!(
+            ClipboardContent::decode_from(
+                StandardFormat::FileList,
+                b"# comment\r\nfile:///tmp/a.txt\r\nfile:///tmp/b.txt"
+            )
+            .unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn format_list_for_content() {
+        let formats = ClipboardFormats::for_content(&ClipboardContent::Text(String::from("hello")))
+            .with_standard(StandardFormat::Utf8String)
+            .with_custom(0xC100, NowString256::from_str_lossy("application/x-custom"));
+        let defs = formats.defs();
+        assert_eq!(defs.len(), 3);
+        assert_eq!(defs[0].id, StandardFormat::Utf8String.default_id());
+        assert_eq!(defs[0].name.as_str(), "UTF8_STRING");
+        assert_eq!(defs[1].id, 13);
+        assert_eq!(defs[1].name.as_str(), "CF_UNICODETEXT");
+        assert_eq!(formats.standard_by_id(0xC100), None);
+    }
+
+    #[test]
+    fn peer_format_ids_resolved_by_name() {
+        let peer = ClipboardFormats::from_defs(&[
+            ClipboardFormatDef::new(49_300, NowString256::from_str_lossy("text/html")),
+            ClipboardFormatDef::new(7, NowString256::from_str_lossy("UTF8_STRING")),
+        ]);
+        assert_eq!(peer.id_of(StandardFormat::Utf8String), Some(7));
+        assert_eq!(peer.id_of(StandardFormat::Png), None);
+        assert_eq!(
+            peer.select(&[StandardFormat::Png, StandardFormat::Html, StandardFormat::Utf8String]),
+            Some((StandardFormat::Html, 49_300))
+        );
+    }
+
+    #[test]
+    fn answer_format_data_req() {
+        let content = ClipboardContent::Text(String::from("hello"));
+        let formats = ClipboardFormats::for_content(&content);
+
+        let rsp = formats.format_data_rsp(&NowClipboardFormatDataReqMsg::new(3, 0xC000), &content);
+        assert!(!rsp.flags.failure());
+        assert_eq!(rsp.sequence_id, 3);
+        assert_eq!(rsp.format_id, 0xC000);
+        assert_eq!(rsp.format_data.0, b"hello".to_vec());
+
+        let rsp = formats.format_data_rsp(&NowClipboardFormatDataReqMsg::new(4, 0xC002), &content);
+        assert!(rsp.flags.failure());
+        assert!(rsp.format_data.0.is_empty());
+    }
+
+    #[test]
+    fn fetch_peer_content() {
+        let peer = ClipboardFormats::from_defs(
+            ClipboardFormats::for_content(&ClipboardContent::Text(String::from("hello")))
+                .format_list_req(1)
+                .formats
+                .0
+                .as_slice(),
+        );
+
+        let (format, req) = peer
+            .format_data_req(2, &[StandardFormat::Html, StandardFormat::UnicodeText])
+            .unwrap();
+        assert_eq!(format, StandardFormat::UnicodeText);
+        assert_eq!(req.sequence_id, 2);
+        assert_eq!(req.format_id, 13);
+        assert!(peer.format_data_req(3, &[StandardFormat::Png]).is_none());
+
+        let rsp = NowClipboardFormatDataRspMsg::new_with_format_data(2, 13, &UNICODE_TEXT_DATA);
+        assert_eq!(
+            peer.decode_format_data_rsp(&rsp).unwrap(),
+            ClipboardContent::Text(String::from("hi你"))
+        );
+
+        let failed =
+            NowClipboardFormatDataRspMsg::new_with_flags(2, 13, ClipboardResponseFlags::new_empty().set_failure());
+        assert!(peer.decode_format_data_rsp(&failed).is_err());
+        let unknown = NowClipboardFormatDataRspMsg::new_with_format_data(2, 0xC002, &[]);
+        assert!(peer.decode_format_data_rsp(&unknown).is_err());
+    }
+}
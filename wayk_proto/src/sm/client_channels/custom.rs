@@ -0,0 +1,257 @@
+//! Application defined virtual channels.
+//!
+//! Channels unknown to this crate only carry raw bytes. Implement [`CustomChannelSM`] with the
+//! message type of the channel protocol and wrap it in a [`CustomChannel`] to register it in a
+//! `ChannelsManager`: payloads are decoded before reaching the state machine, and its responses
+//! are encoded on the way out.
+//!
+//! [`CustomChannelSM`]: trait.CustomChannelSM.html
+//! [`CustomChannel`]: struct.CustomChannel.html
+
+use crate::error::{ProtoErrorKind, ProtoErrorResultExt};
+use crate::message::{ChannelName, CustomVirtualChannelOwned, NowVirtualChannel};
+use crate::serialization::{Decode, Encode};
+use crate::sm::{ChannelResponses, SMData, SMEvent, SMEvents, StateInfo, VirtualChannelSM};
+use alloc::vec::Vec;
+
+/// Typed state machine for an application defined channel, see the [module documentation](index.html).
+pub trait CustomChannelSM {
+    /// Message of the channel protocol, one per virtual channel packet.
+    type Message: Encode + for<'dec> Decode<'dec>;
+
+    fn get_channel_name(&self) -> ChannelName;
+
+    fn is_terminated(&self) -> bool;
+
+    fn waiting_for_packet(&self) -> bool;
+
+    fn update_without_msg<'msg>(
+        &mut self,
+        data: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut Vec<Self::Message>,
+    );
+
+    fn update_with_msg<'msg>(
+        &mut self,
+        data: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut Vec<Self::Message>,
+        msg: Self::Message,
+    );
+
+    fn on_channel_stopped<'msg>(&mut self, _data: &mut SMData, _events: &mut SMEvents<'msg>) {}
+
+    fn on_channel_started<'msg>(&mut self, _data: &mut SMData, _events: &mut SMEvents<'msg>) {}
+
+    /// See `VirtualChannelSM::state_info`.
+    fn state_info(&self) -> Option<StateInfo> {
+        None
+    }
+}
+
+/// Plugs a `CustomChannelSM` into a `ChannelsManager`.
+///
+/// Payloads that can't be decoded are reported as errors and dropped.
+pub struct CustomChannel<SM> {
+    sm: SM,
+}
+
+impl<SM: CustomChannelSM> CustomChannel<SM> {
+    pub fn new(sm: SM) -> Self {
+        Self { sm }
+    }
+
+    pub fn get_sm(&self) -> &SM {
+        &self.sm
+    }
+
+    pub fn get_sm_mut(&mut self) -> &mut SM {
+        &mut self.sm
+    }
+
+    pub fn into_sm(self) -> SM {
+        self.sm
+    }
+
+    fn h_send<'msg>(&self, events: &mut SMEvents<'msg>, to_send: &mut ChannelResponses<'msg>, msgs: Vec<SM::Message>) {
+        let name = self.sm.get_channel_name();
+        for msg in msgs {
+            match msg
+                .encode()
+                .chain(ProtoErrorKind::VirtualChannel(name.clone()))
+                .or_desc("couldn't encode custom channel message")
+            {
+                Ok(payload) => to_send.push(CustomVirtualChannelOwned {
+                    name: name.clone(),
+                    payload,
+                }),
+                Err(e) => events.push(SMEvent::Error(e)),
+            }
+        }
+    }
+}
+
+impl<SM: CustomChannelSM> VirtualChannelSM for CustomChannel<SM> {
+    fn get_channel_name(&self) -> ChannelName {
+        self.sm.get_channel_name()
+    }
+
+    fn is_terminated(&self) -> bool {
+        self.sm.is_terminated()
+    }
+
+    fn waiting_for_packet(&self) -> bool {
+        self.sm.waiting_for_packet()
+    }
+
+    fn update_without_chan_msg<'msg>(
+        &mut self,
+        data: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut ChannelResponses<'msg>,
+    ) {
+        let mut msgs = Vec::new();
+        self.sm.update_without_msg(data, events, &mut msgs);
+        self.h_send(events, to_send, msgs);
+    }
+
+    fn update_with_chan_msg<'msg: 'a, 'a>(
+        &mut self,
+        data: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut ChannelResponses<'msg>,
+        msg: &'a NowVirtualChannel<'msg>,
+    ) {
+        let payload = match msg {
+            NowVirtualChannel::Custom(msg) => &msg.payload,
+            NowVirtualChannel::CustomOwned(msg) => msg.payload.as_slice(),
+            unexpected => {
+                events.push(SMEvent::warn(
+                    ProtoErrorKind::VirtualChannel(self.sm.get_channel_name()),
+                    format!("unexpected {:?} message on a custom channel", unexpected.get_name()),
+                ));
+                return;
+            }
+        };
+
+        match SM::Message::decode(payload)
+            .chain(ProtoErrorKind::VirtualChannel(self.sm.get_channel_name()))
+            .or_desc("couldn't decode custom channel message")
+        {
+            Ok(msg) => {
+                let mut msgs = Vec::new();
+                self.sm.update_with_msg(data, events, &mut msgs, msg);
+                self.h_send(events, to_send, msgs);
+            }
+            Err(e) => events.push(SMEvent::Error(e)),
+        }
+    }
+
+    fn on_channel_stopped<'msg>(&mut self, data: &mut SMData, events: &mut SMEvents<'msg>) {
+        self.sm.on_channel_stopped(data, events);
+    }
+
+    fn on_channel_started<'msg>(&mut self, data: &mut SMData, events: &mut SMEvents<'msg>) {
+        self.sm.on_channel_started(data, events);
+    }
+
+    fn state_info(&self) -> Option<StateInfo> {
+        self.sm.state_info()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::CustomVirtualChannel;
+    use crate::sm::ProtoData;
+    use alloc::borrow::Cow;
+
+    #[derive(Encode, Decode, Debug, Clone, PartialEq)]
+    struct Ping {
+        sequence: u32,
+    }
+
+    impl ProtoData for Ping {}
+
+    /// Answers pings with the next sequence number.
+    struct PingSM;
+
+    impl CustomChannelSM for PingSM {
+        type Message = Ping;
+
+        fn get_channel_name(&self) -> ChannelName {
+            ChannelName::Unknown("ping".into())
+        }
+
+        fn is_terminated(&self) -> bool {
+            false
+        }
+
+        fn waiting_for_packet(&self) -> bool {
+            true
+        }
+
+        fn update_without_msg<'msg>(&mut self, _: &mut SMData, _: &mut SMEvents<'msg>, _: &mut Vec<Ping>) {}
+
+        fn update_with_msg<'msg>(
+            &mut self,
+            _: &mut SMData,
+            events: &mut SMEvents<'msg>,
+            to_send: &mut Vec<Ping>,
+            msg: Ping,
+        ) {
+            to_send.push(Ping {
+                sequence: msg.sequence + 1,
+            });
+            events.push(SMEvent::data(msg));
+        }
+    }
+
+    #[test]
+    fn typed_messages() {
+        let mut channel = CustomChannel::new(PingSM);
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+        let mut events = SMEvents::new();
+        let mut to_send = ChannelResponses::new();
+
+        let payload = Ping { sequence: 1 }.encode().unwrap();
+        let msg = NowVirtualChannel::Custom(CustomVirtualChannel {
+            name: channel.get_channel_name(),
+            payload: Cow::Borrowed(&payload),
+        });
+        channel.update_with_chan_msg(&mut data, &mut events, &mut to_send, &msg);
+
+        let events = events.unpack();
+        assert_eq!(events[0].downcast_data::<Ping>(), Some(&Ping { sequence: 1 }));
+        match &to_send.peek()[0].1 {
+            NowVirtualChannel::CustomOwned(rsp) => {
+                let rsp = Ping::decode(&rsp.payload).unwrap();
+                assert_eq!(rsp, Ping { sequence: 2 });
+            }
+            rsp => panic!("expected an owned custom message and got {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn undecodable_payload() {
+        let mut channel = CustomChannel::new(PingSM);
+        let mut events = SMEvents::new();
+        let mut to_send = ChannelResponses::new();
+
+        let msg = NowVirtualChannel::Custom(CustomVirtualChannel {
+            name: channel.get_channel_name(),
+            payload: Cow::Borrowed(&[0x01]),
+        });
+        channel.update_with_chan_msg(
+            &mut SMData::new(Vec::new(), Vec::new(), Vec::new()),
+            &mut events,
+            &mut to_send,
+            &msg,
+        );
+
+        assert!(matches!(events.unpack().as_slice(), [SMEvent::Error(_)]));
+        assert!(to_send.peek().is_empty());
+    }
+}
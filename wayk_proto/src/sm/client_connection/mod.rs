@@ -1,21 +1,152 @@
 mod sub_sm;
 
-use crate::error::ProtoErrorKind;
-use crate::message::{AuthType, NowChannelDef, NowMessage};
-use crate::sm::{ConnectionSM, DummyConnectionSM, ProtoData, ProtoState, SMData, SMEvent, SMEvents};
+pub use sub_sm::SessionTokenSM;
+
+use crate::error::{ProtoError, ProtoErrorKind};
+use crate::message::{AssociateInfoFlags, AuthType, NegotiateFlags, NowChannelDef, NowMessage, Redacted};
+use crate::sm::{
+    ConnectionSM, DummyConnectionSM, NegotiatedCapabilities, ProtoData, ProtoState, SMData, SMEvent, SMEvents,
+    StateInfo,
+};
+use crate::timer::{TimerId, TimerRequest};
+use crate::version::NowVersion;
 use alloc::boxed::Box;
 use alloc::vec::Vec;
+use core::fmt;
 
 #[derive(Debug, Clone)]
-pub struct AvailableAuthTypes(Vec<AuthType>);
+pub struct AvailableAuthTypes(pub Vec<AuthType>);
 
 impl ProtoData for AvailableAuthTypes {}
 
 #[derive(Debug, Clone)]
-pub struct Channels(Vec<NowChannelDef>);
+pub struct Channels(pub Vec<NowChannelDef>);
 
 impl ProtoData for Channels {}
 
+/// Negotiate flags sent by each side.
+///
+/// Also stored in `SMData` extras at the end of the negotiate phase so the authenticate state
+/// machine can pick what the server supports (SRP variant, token based authentication…).
+#[derive(Debug, Clone, Copy)]
+pub struct NegotiatedFlags {
+    pub local: NegotiateFlags,
+    pub peer: NegotiateFlags,
+}
+
+impl NegotiatedFlags {
+    /// Flags set on both sides.
+    pub fn common(&self) -> NegotiateFlags {
+        NegotiateFlags::from(self.local.value & self.peer.value)
+    }
+
+    pub fn srp_extended(&self) -> bool {
+        self.common().srp_extended()
+    }
+
+    pub fn auth_token(&self) -> bool {
+        self.common().auth_token()
+    }
+}
+
+impl ProtoData for NegotiatedFlags {}
+
+/// Protocol versions exchanged during the handshake.
+///
+/// Also stored in `SMData` extras once the handshake succeeded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NegotiatedVersion {
+    pub local: NowVersion,
+    pub peer: NowVersion,
+}
+
+impl NegotiatedVersion {
+    /// Version spoken for the rest of the session.
+    pub fn effective(&self) -> NowVersion {
+        core::cmp::min(self.local, self.peer)
+    }
+}
+
+impl ProtoData for NegotiatedVersion {}
+
+/// Session identity obtained during the associate phase.
+///
+/// Emitted as an `SMEvent::Data` and kept in `SMData` extras, where a reconnection may pick it up
+/// to associate with the same session again.
+#[derive(Clone, PartialEq)]
+pub struct AssociationInfo {
+    /// Session announced by the server associate info.
+    pub session_id: u32,
+    pub flags: AssociateInfoFlags,
+    /// Session id granted by the server associate response.
+    pub token: u32,
+}
+
+// the token allows to join the session again, keep it out of logs
+impl fmt::Debug for AssociationInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AssociationInfo")
+            .field("session_id", &self.session_id)
+            .field("flags", &self.flags)
+            .field("token", &Redacted(&self.token.to_le_bytes()))
+            .finish()
+    }
+}
+
+impl ProtoData for AssociationInfo {}
+
+/// Data produced by the connection sequence.
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+    Version(NegotiatedVersion),
+    AuthTypes(AvailableAuthTypes),
+    NegotiateFlags(NegotiatedFlags),
+    Capabilities(NegotiatedCapabilities),
+    Channels(Channels),
+}
+
+impl ConnectionEvent {
+    pub fn as_data(&self) -> &dyn ProtoData {
+        match self {
+            ConnectionEvent::Version(data) => data,
+            ConnectionEvent::AuthTypes(data) => data,
+            ConnectionEvent::NegotiateFlags(data) => data,
+            ConnectionEvent::Capabilities(data) => data,
+            ConnectionEvent::Channels(data) => data,
+        }
+    }
+}
+
+impl From<AvailableAuthTypes> for ConnectionEvent {
+    fn from(data: AvailableAuthTypes) -> Self {
+        Self::AuthTypes(data)
+    }
+}
+
+impl From<NegotiatedVersion> for ConnectionEvent {
+    fn from(data: NegotiatedVersion) -> Self {
+        Self::Version(data)
+    }
+}
+
+impl From<NegotiatedFlags> for ConnectionEvent {
+    fn from(data: NegotiatedFlags) -> Self {
+        Self::NegotiateFlags(data)
+    }
+}
+
+impl From<NegotiatedCapabilities> for ConnectionEvent {
+    fn from(data: NegotiatedCapabilities) -> Self {
+        Self::Capabilities(data)
+    }
+}
+
+impl From<Channels> for ConnectionEvent {
+    fn from(data: Channels) -> Self {
+        Self::Channels(data)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ConnectionState {
     Handshake,
@@ -31,16 +162,22 @@ impl ProtoState for ConnectionState {}
 
 pub struct ClientConnectionSeqSM {
     state: ConnectionState,
-    current_sm: Box<dyn ConnectionSM>,
-    authenticate_sm: Box<dyn ConnectionSM>,
+    current_sm: Box<dyn ConnectionSM + Send>,
+    authenticate_sm: Box<dyn ConnectionSM + Send>,
+    /// Armed while waiting for the peer, if `SMData::response_timeout` is set.
+    response_timer: Option<TimerId>,
+    /// Allocated on first use and reused, so that arming it again pushes the deadline back.
+    timer_id: Option<TimerId>,
 }
 
 impl ClientConnectionSeqSM {
-    pub fn new<P: ConnectionSM + 'static>(sm: P) -> Self {
+    pub fn new<P: ConnectionSM + Send + 'static>(sm: P) -> Self {
         Self {
             state: ConnectionState::Handshake,
             current_sm: Box::new(sub_sm::HandshakeSM::new()),
             authenticate_sm: Box::new(sm),
+            response_timer: None,
+            timer_id: None,
         }
     }
 
@@ -55,6 +192,20 @@ impl ClientConnectionSeqSM {
         }
     }
 
+    /// Restarts the response timer whenever the peer is expected to answer, and stops it once the
+    /// sequence is over.
+    fn __update_response_timer(&mut self, data: &mut SMData, events: &mut SMEvents<'_>) {
+        if self.is_terminated() {
+            if let Some(id) = self.response_timer.take() {
+                events.push(SMEvent::CancelTimer(id));
+            }
+        } else if let (Some(delay), true) = (data.response_timeout, self.waiting_for_packet()) {
+            let id = *self.timer_id.get_or_insert_with(|| data.new_timer_id());
+            self.response_timer = Some(id);
+            events.push(SMEvent::SetTimer(TimerRequest { id, delay }));
+        }
+    }
+
     fn __go_to_next_state<'msg>(&mut self, events: &mut SMEvents<'msg>) {
         match self.state {
             ConnectionState::Handshake => {
@@ -66,7 +217,7 @@ impl ClientConnectionSeqSM {
                 core::mem::swap(&mut self.current_sm, &mut self.authenticate_sm);
 
                 // set invalid authenticate_sm field to dummy connection state machine
-                let mut dummy_sm: Box<dyn ConnectionSM> = Box::new(DummyConnectionSM);
+                let mut dummy_sm: Box<dyn ConnectionSM + Send> = Box::new(DummyConnectionSM);
                 core::mem::swap(&mut self.authenticate_sm, &mut dummy_sm);
 
                 self.state = ConnectionState::Authenticate;
@@ -111,12 +262,14 @@ impl ConnectionSM for ClientConnectionSeqSM {
     }
 
     fn update_without_message<'msg>(&mut self, data: &mut SMData, events: &mut SMEvents<'msg>) {
+        __enter_span!("connection", state = ?self.state);
         self.current_sm.update_without_message(data, events);
         if self.current_sm.is_terminated() {
             self.__go_to_next_state(events);
         } else {
             self.__check_for_fatal(events);
         }
+        self.__update_response_timer(data, events);
     }
 
     fn update_with_message<'msg: 'a, 'a>(
@@ -125,11 +278,41 @@ impl ConnectionSM for ClientConnectionSeqSM {
         events: &mut SMEvents<'msg>,
         msg: &'a NowMessage<'msg>,
     ) {
+        __enter_span!("connection", state = ?self.state);
         self.current_sm.update_with_message(data, events, msg);
         if self.current_sm.is_terminated() {
             self.__go_to_next_state(events);
         } else {
             self.__check_for_fatal(events);
         }
+        self.__update_response_timer(data, events);
+    }
+
+    fn on_timer_expired<'msg>(&mut self, data: &mut SMData, events: &mut SMEvents<'msg>, id: TimerId) {
+        if self.response_timer == Some(id) {
+            self.response_timer = None;
+            events.push(SMEvent::Fatal(
+                ProtoError::new(ProtoErrorKind::ConnectionSequence(self.state)).with_desc(format!(
+                    "no response from peer within {:?}",
+                    data.response_timeout.unwrap_or_default()
+                )),
+            ));
+            self.state = ConnectionState::Final;
+        } else {
+            self.current_sm.on_timer_expired(data, events, id);
+            self.__check_for_fatal(events);
+        }
+    }
+
+    fn state_info(&self) -> Option<StateInfo> {
+        Some(match self.state {
+            ConnectionState::Handshake => StateInfo::new("Handshake", &["Negotiate", "Final"]),
+            ConnectionState::Negotiate => StateInfo::new("Negotiate", &["Authenticate", "Final"]),
+            ConnectionState::Authenticate => StateInfo::new("Authenticate", &["Associate", "Final"]),
+            ConnectionState::Associate => StateInfo::new("Associate", &["Capabilities", "Final"]),
+            ConnectionState::Capabilities => StateInfo::new("Capabilities", &["Channels", "Final"]),
+            ConnectionState::Channels => StateInfo::new("Channels", &["Final"]),
+            ConnectionState::Final => StateInfo::new("Final", &[]),
+        })
     }
 }
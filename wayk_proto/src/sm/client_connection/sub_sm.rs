@@ -1,8 +1,15 @@
 use crate::alloc::string::ToString;
-use crate::error::ProtoErrorKind;
-use crate::message::{NowActivateMsg, NowCapabilitiesMsg, NowMessage};
-use crate::sm::client_connection::{AvailableAuthTypes, Channels};
-use crate::sm::{ConnectionSM, ConnectionState, ProtoState, SMData, SMEvent, SMEvents};
+use crate::auth::token::SessionTokenError;
+use crate::error::{ProtoError, ProtoErrorKind};
+use crate::extension::ExtensionRegistry;
+use crate::message::{
+    ChannelDefFlags, ChannelMessageType, Codec, IntoOwned, NowActivateMsg, NowCapabilitiesMsg, NowChannelDef,
+    NowChannelMsg, NowMessage,
+};
+use crate::sm::client_connection::{AssociationInfo, AvailableAuthTypes, Channels, NegotiatedFlags, NegotiatedVersion};
+use crate::sm::{
+    CapabilitiesNegotiator, ConnectionSM, ConnectionState, PeerCapabilities, ProtoState, SMData, SMEvent, SMEvents,
+};
 use alloc::vec::Vec;
 use log::info;
 
@@ -75,12 +82,14 @@ impl ConnectionSM for HandshakeSM {
         self.state == BasicState::Ready
     }
 
-    fn update_without_message<'msg>(&mut self, _: &mut SMData, events: &mut SMEvents<'msg>) {
+    fn update_without_message<'msg>(&mut self, data: &mut SMData, events: &mut SMEvents<'msg>) {
         use wayk_proto::message::NowHandshakeMsg;
 
         match self.state {
             BasicState::Initial => {
-                events.push(SMEvent::PacketToSend(NowHandshakeMsg::new_success().into()));
+                events.push(SMEvent::PacketToSend(
+                    NowHandshakeMsg::new_with_version(data.version).into(),
+                ));
                 state_transition!(self, events, BasicState::Ready);
             }
             _ => events.push(unexpected_call!(Self, self, "update_without_message")),
@@ -89,7 +98,7 @@ impl ConnectionSM for HandshakeSM {
 
     fn update_with_message<'msg: 'a, 'a>(
         &mut self,
-        _: &mut SMData,
+        data: &mut SMData,
         events: &mut SMEvents<'msg>,
         msg: &'a NowMessage<'msg>,
     ) {
@@ -98,8 +107,24 @@ impl ConnectionSM for HandshakeSM {
         match self.state {
             BasicState::Ready => match msg {
                 NowMessage::Handshake(msg) => match msg.status.code() {
+                    HandshakeStatusCode::Success if !data.version.is_compatible_with(&msg.version()) => {
+                        events.push(SMEvent::fatal(
+                            ProtoErrorKind::ConnectionSequence(ConnectionState::Handshake),
+                            format!(
+                                "version incompatible: local version is {} but peer version is {}",
+                                data.version,
+                                msg.version()
+                            ),
+                        ))
+                    }
                     HandshakeStatusCode::Success => {
-                        log::trace!("handshake succeeded");
+                        let version = NegotiatedVersion {
+                            local: data.version,
+                            peer: msg.version(),
+                        };
+                        log::trace!("handshake succeeded, protocol version {}", version.effective());
+                        data.extra_insert(version);
+                        events.push(SMEvent::Connection(version.into()));
                         state_transition!(self, events, BasicState::Terminated);
                     }
                     HandshakeStatusCode::Failure => events.push(SMEvent::fatal(
@@ -108,7 +133,11 @@ impl ConnectionSM for HandshakeSM {
                     )),
                     HandshakeStatusCode::Incompatible => events.push(SMEvent::fatal(
                         ProtoErrorKind::ConnectionSequence(ConnectionState::Handshake),
-                        "version incompatible",
+                        format!(
+                            "version incompatible: peer version {} refused local version {}",
+                            msg.version(),
+                            data.version
+                        ),
                     )),
                     HandshakeStatusCode::Other(code) => events.push(SMEvent::error(
                         ProtoErrorKind::ConnectionSequence(ConnectionState::Handshake),
@@ -149,16 +178,12 @@ impl ConnectionSM for NegotiateSM {
     }
 
     fn update_without_message<'msg>(&mut self, data: &mut SMData, events: &mut SMEvents<'msg>) {
-        use wayk_proto::message::{NegotiateFlags, NowNegotiateMsg};
+        use wayk_proto::message::NowNegotiateMsg;
 
         match &self.state {
             BasicState::Initial => {
                 events.push(SMEvent::PacketToSend(
-                    NowNegotiateMsg::new_with_auth_list(
-                        NegotiateFlags::new_empty().set_srp_extended(),
-                        data.supported_auths.clone(),
-                    )
-                    .into(),
+                    NowNegotiateMsg::new_with_auth_list(data.negotiate_flags, data.supported_auths.clone()).into(),
                 ));
                 state_transition!(self, events, BasicState::Ready);
             }
@@ -177,6 +202,13 @@ impl ConnectionSM for NegotiateSM {
                 NowMessage::Negotiate(msg) => {
                     info!("Available authentication methods on server: {:?}", msg.auth_list.0);
 
+                    let flags = NegotiatedFlags {
+                        local: data.negotiate_flags,
+                        peer: msg.flags,
+                    };
+                    data.extra_insert(flags);
+                    events.push(SMEvent::Connection(flags.into()));
+
                     let common_auth_types = msg
                         .auth_list
                         .iter()
@@ -184,7 +216,7 @@ impl ConnectionSM for NegotiateSM {
                         .copied()
                         .collect();
 
-                    events.push(SMEvent::data(AvailableAuthTypes(common_auth_types)));
+                    events.push(SMEvent::Connection(AvailableAuthTypes(common_auth_types).into()));
 
                     state_transition!(self, events, BasicState::Terminated);
                 }
@@ -195,6 +227,105 @@ impl ConnectionSM for NegotiateSM {
     }
 }
 
+// session token
+
+/// Authenticates with the one-time token of `SMData::session_token`, to be given to
+/// `ClientConnectionSeqSM::new` in place of an interactive authentication state machine.
+///
+/// Failures are reported as fatal `ProtoErrorKind::SessionToken` errors.
+pub struct SessionTokenSM {
+    state: BasicState,
+}
+
+impl Default for SessionTokenSM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionTokenSM {
+    const CONNECTION_STATE: ConnectionState = ConnectionState::Authenticate;
+    const NAME: &'static str = "SessionTokenSM";
+
+    pub fn new() -> Self {
+        Self {
+            state: BasicState::Initial,
+        }
+    }
+
+    fn h_fail(&mut self, events: &mut SMEvents<'_>, error: SessionTokenError) {
+        events.push(SMEvent::Fatal(
+            ProtoError::new(ProtoErrorKind::SessionToken(error)).with_desc(error.to_string()),
+        ));
+        state_transition!(self, events, BasicState::Terminated);
+    }
+}
+
+impl ConnectionSM for SessionTokenSM {
+    fn is_terminated(&self) -> bool {
+        self.state == BasicState::Terminated
+    }
+
+    fn waiting_for_packet(&self) -> bool {
+        self.state == BasicState::Ready
+    }
+
+    fn update_without_message<'msg>(&mut self, data: &mut SMData, events: &mut SMEvents<'msg>) {
+        match self.state {
+            BasicState::Initial => {
+                let token = match &data.session_token {
+                    Some(token) => token,
+                    None => return self.h_fail(events, SessionTokenError::Missing),
+                };
+
+                let supported = data
+                    .extra_get::<NegotiatedFlags>()
+                    .map(NegotiatedFlags::auth_token)
+                    .unwrap_or(false);
+                if !supported {
+                    return self.h_fail(events, SessionTokenError::NotSupported);
+                }
+
+                match token.to_authenticate_msg() {
+                    Ok(msg) => {
+                        events.push(SMEvent::PacketToSend(NowMessage::Authenticate(msg.into()).into()));
+                        state_transition!(self, events, BasicState::Ready);
+                    }
+                    Err(e) => events.push(SMEvent::Fatal(e)),
+                }
+            }
+            _ => events.push(unexpected_call!(Self, self, "update_without_message")),
+        }
+    }
+
+    fn update_with_message<'msg: 'a, 'a>(
+        &mut self,
+        _: &mut SMData,
+        events: &mut SMEvents<'msg>,
+        msg: &'a NowMessage<'msg>,
+    ) {
+        use wayk_proto::message::NowAuthenticateMsg;
+
+        match self.state {
+            BasicState::Ready => match msg {
+                NowMessage::Authenticate(NowAuthenticateMsg::Success(_)) => {
+                    log::trace!("session token accepted");
+                    state_transition!(self, events, BasicState::Terminated);
+                }
+                NowMessage::Authenticate(NowAuthenticateMsg::Failure(msg)) => {
+                    let error = SessionTokenError::Rejected {
+                        status: msg.status.code(),
+                        retry: msg.flags.retry(),
+                    };
+                    self.h_fail(events, error);
+                }
+                unexpected => events.push(unexpected_msg!(Self, self, unexpected)),
+            },
+            _ => events.push(unexpected_call!(Self, self, "update_with_message")),
+        }
+    }
+}
+
 // associate
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -208,6 +339,7 @@ impl ProtoState for AssociateState {}
 
 pub struct AssociateSM {
     state: AssociateState,
+    info: Option<AssociationInfo>,
 }
 
 impl AssociateSM {
@@ -217,6 +349,7 @@ impl AssociateSM {
     pub fn new() -> Self {
         Self {
             state: AssociateState::WaitInfo,
+            info: None,
         }
     }
 }
@@ -236,7 +369,7 @@ impl ConnectionSM for AssociateSM {
 
     fn update_with_message<'msg: 'a, 'a>(
         &mut self,
-        _: &mut SMData,
+        data: &mut SMData,
         events: &mut SMEvents<'msg>,
         msg: &'a NowMessage<'msg>,
     ) {
@@ -251,6 +384,11 @@ impl ConnectionSM for AssociateSM {
                     } else {
                         events.push(SMEvent::PacketToSend(NowAssociateMsg::new_request().into()));
                     }
+                    self.info = Some(AssociationInfo {
+                        session_id: msg.session_id,
+                        flags: msg.flags,
+                        token: 0,
+                    });
                     state_transition!(self, events, AssociateState::WaitResponse);
                 }
                 unexpected => events.push(unexpected_msg!(Self, self, unexpected)),
@@ -260,6 +398,12 @@ impl ConnectionSM for AssociateSM {
                     AssociateStatusCode::Success => {
                         state_transition!(self, events, AssociateState::Terminated);
                         log::trace!("associate process succeeded");
+
+                        if let Some(mut info) = self.info.take() {
+                            info.token = msg.session_id;
+                            data.extra_insert(info.clone());
+                            events.push(SMEvent::data(info));
+                        }
                     }
                     AssociateStatusCode::Failure => events.push(SMEvent::fatal(
                         ProtoErrorKind::ConnectionSequence(ConnectionState::Associate),
@@ -292,6 +436,36 @@ impl CapabilitiesSM {
             state: BasicState::Ready,
         }
     }
+
+    /// Warns about local codecs the peer doesn't support. Returns `false` if the exchange can't
+    /// proceed because no codec is common and `SMData::require_common_codec` is set.
+    fn h_check_codecs(data: &SMData, events: &mut SMEvents<'_>, local: &[Codec], common: &[Codec]) -> bool {
+        if local.is_empty() {
+            return true;
+        }
+
+        if common.is_empty() {
+            let desc = format!("peer supports none of the local codecs {:?}", local);
+            if data.require_common_codec {
+                events.push(SMEvent::fatal(
+                    ProtoErrorKind::ConnectionSequence(Self::CONNECTION_STATE),
+                    desc,
+                ));
+                return false;
+            }
+            events.push(SMEvent::warn(
+                ProtoErrorKind::ConnectionSequence(Self::CONNECTION_STATE),
+                desc,
+            ));
+        } else if common.len() < local.len() {
+            let unsupported: Vec<Codec> = local.iter().filter(|codec| !common.contains(codec)).copied().collect();
+            events.push(SMEvent::warn(
+                ProtoErrorKind::ConnectionSequence(Self::CONNECTION_STATE),
+                format!("codecs {:?} not supported by peer, using {:?}", unsupported, common),
+            ));
+        }
+        true
+    }
 }
 
 impl ConnectionSM for CapabilitiesSM {
@@ -327,8 +501,24 @@ impl ConnectionSM for CapabilitiesSM {
                     );
                     log::trace!("Server capabilities details: {:#?}", msg.capabilities.0);
 
+                    let negotiator = CapabilitiesNegotiator::new(&data.capabilities, &msg.capabilities);
+                    let negotiated = negotiator.negotiate();
+                    log::debug!("Negotiated capabilities: {:?}", negotiated);
+                    if !Self::h_check_codecs(data, events, &negotiator.local_codecs(), &negotiated.codecs) {
+                        return;
+                    }
+                    data.extra_insert(negotiated.clone());
+                    data.extra_insert(PeerCapabilities(msg.capabilities.0.clone().into_owned()));
+                    events.push(SMEvent::Connection(negotiated.into()));
+
+                    let mut capabilities = data.capabilities.clone();
+                    if let Some(extensions) = data.extra_get_mut::<ExtensionRegistry>() {
+                        extensions.update_from_capabilities(&msg.capabilities);
+                        capabilities.push(extensions.local_capset());
+                    }
+
                     events.push(SMEvent::PacketToSend(
-                        NowCapabilitiesMsg::new_with_capabilities(data.capabilities.clone()).into(),
+                        NowCapabilitiesMsg::new_with_capabilities(capabilities).into(),
                     ));
                     state_transition!(self, events, BasicState::Terminated);
                 }
@@ -364,6 +554,54 @@ impl ChannelsSM {
             state: ChannelPairingState::SendListRequest,
         }
     }
+
+    /// Server refused some channels while pairing, they won't be opened.
+    fn h_on_close_request(&self, data: &mut SMData, events: &mut SMEvents<'_>, msg: &NowChannelMsg) {
+        log::info!(
+            "Channel(s) closed by server: {:?}",
+            msg.channel_list.iter().map(|def| &def.name).collect::<Vec<_>>()
+        );
+
+        data.channel_defs
+            .retain(|def| !msg.channel_list.iter().any(|closed| closed.name == def.name));
+
+        let defs = msg
+            .channel_list
+            .iter()
+            .map(|def| {
+                NowChannelDef::new_with_flags(
+                    def.name.clone(),
+                    ChannelDefFlags::from(def.flags.value | ChannelDefFlags::STATUS_SUCCESS),
+                )
+            })
+            .collect();
+        events.push(SMEvent::PacketToSend(
+            NowChannelMsg::new(ChannelMessageType::ChannelCloseResponse, defs).into(),
+        ));
+    }
+
+    /// Server asked to open channels while pairing, only the ones we requested are accepted.
+    fn h_on_open_request(&self, data: &SMData, events: &mut SMEvents<'_>, msg: &NowChannelMsg) {
+        let mut defs = Vec::with_capacity(msg.channel_list.len());
+        for def in msg.channel_list.iter() {
+            let status = if data.channel_defs.iter().any(|requested| requested.name == def.name) {
+                ChannelDefFlags::STATUS_SUCCESS
+            } else {
+                events.push(SMEvent::warn(
+                    ProtoErrorKind::ConnectionSequence(Self::CONNECTION_STATE),
+                    format!("Open request for unsupported channel {:?} declined", def.name),
+                ));
+                ChannelDefFlags::STATUS_FAILURE
+            };
+            defs.push(NowChannelDef::new_with_flags(
+                def.name.clone(),
+                ChannelDefFlags::from(def.flags.value | status),
+            ));
+        }
+        events.push(SMEvent::PacketToSend(
+            NowChannelMsg::new(ChannelMessageType::ChannelOpenResponse, defs).into(),
+        ));
+    }
 }
 
 impl ConnectionSM for ChannelsSM {
@@ -376,7 +614,6 @@ impl ConnectionSM for ChannelsSM {
     }
 
     fn update_without_message<'msg>(&mut self, data: &mut SMData, events: &mut SMEvents<'msg>) {
-        use crate::message::{ChannelMessageType, NowChannelMsg};
         match self.state {
             ChannelPairingState::SendListRequest => {
                 events.push(SMEvent::PacketToSend(
@@ -408,6 +645,16 @@ impl ConnectionSM for ChannelsSM {
     ) {
         use crate::message::ChannelName;
 
+        if let NowMessage::Channel(request) = msg {
+            if self.waiting_for_packet() {
+                match request.subtype {
+                    ChannelMessageType::ChannelCloseRequest => return self.h_on_close_request(data, events, request),
+                    ChannelMessageType::ChannelOpenRequest => return self.h_on_open_request(data, events, request),
+                    _ => {}
+                }
+            }
+        }
+
         match self.state {
             ChannelPairingState::SendListRequest => events.push(unexpected_call!(Self, self, "update_with_message")),
             ChannelPairingState::WaitListResponse => match msg {
@@ -436,7 +683,7 @@ impl ConnectionSM for ChannelsSM {
                             .retain(|def| !unavailable_channels.contains(&def.name));
                     }
 
-                    events.push(SMEvent::data(Channels(data.channel_defs.clone())));
+                    events.push(SMEvent::Connection(Channels(data.channel_defs.clone()).into()));
                     state_transition!(self, events, ChannelPairingState::SendOpenRequest);
                 }
                 unexpected => events.push(unexpected_msg!(Self, self, unexpected)),
@@ -463,3 +710,189 @@ impl ConnectionSM for ChannelsSM {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::status::NowStatus;
+    use crate::message::{AssociateInfoFlags, AssociateResponseFlags, NowAssociateInfoMsg, NowAssociateResponseMsg};
+
+    #[test]
+    fn association_info() {
+        let mut sm = AssociateSM::new();
+        let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+
+        let mut events = SMEvents::new();
+        let info = NowAssociateInfoMsg::new_with_session_id(AssociateInfoFlags::new_empty(), 0x2A);
+        sm.update_with_message(&mut data, &mut events, &NowMessage::Associate(info.into()));
+        assert!(data.extra_get::<AssociationInfo>().is_none());
+
+        let mut events = SMEvents::new();
+        let response = NowAssociateResponseMsg::new_with_session_id(
+            AssociateResponseFlags::new_empty(),
+            NowStatus::default(),
+            0xDEAD_BEEF,
+        );
+        sm.update_with_message(&mut data, &mut events, &NowMessage::Associate(response.into()));
+        assert!(sm.is_terminated());
+
+        let expected = AssociationInfo {
+            session_id: 0x2A,
+            flags: AssociateInfoFlags::new_empty(),
+            token: 0xDEAD_BEEF,
+        };
+        assert_eq!(data.extra_get::<AssociationInfo>(), Some(&expected));
+        let event_data = events
+            .unpack()
+            .into_iter()
+            .find_map(|e| match e {
+                SMEvent::Data(data) => Some(data),
+                _ => None,
+            })
+            .unwrap();
+        assert!(!format!("{:?}", event_data).contains("deadbeef"));
+    }
+
+    #[test]
+    fn channel_closed_while_pairing() {
+        use crate::message::ChannelName;
+
+        let mut sm = ChannelsSM::new();
+        let defs = vec![
+            NowChannelDef::new(ChannelName::Chat),
+            NowChannelDef::new(ChannelName::Clipboard),
+        ];
+        let mut data = SMData::new(Vec::new(), Vec::new(), defs);
+
+        sm.update_without_message(&mut data, &mut SMEvents::new());
+        assert!(sm.waiting_for_packet());
+
+        let mut events = SMEvents::new();
+        let close = NowChannelMsg::new(
+            ChannelMessageType::ChannelCloseRequest,
+            vec![NowChannelDef::new(ChannelName::Clipboard)],
+        );
+        sm.update_with_message(&mut data, &mut events, &NowMessage::Channel(close));
+
+        assert!(sm.waiting_for_packet());
+        assert_eq!(data.channel_defs.len(), 1);
+        assert_eq!(data.channel_defs[0].name, ChannelName::Chat);
+        match events.unpack().as_slice() {
+            [SMEvent::PacketToSend(packet)] => match &packet.body {
+                crate::message::NowBody::Message(NowMessage::Channel(rsp)) => {
+                    assert_eq!(rsp.subtype, ChannelMessageType::ChannelCloseResponse);
+                    assert!(rsp.channel_list[0].flags.status_success());
+                }
+                body => panic!("expected a channel message and got {:?}", body),
+            },
+            _ => panic!("expected a single close response"),
+        }
+    }
+
+    #[test]
+    fn unknown_channel_open_declined() {
+        use crate::message::ChannelName;
+
+        let mut sm = ChannelsSM::new();
+        let mut data = SMData::new(Vec::new(), Vec::new(), vec![NowChannelDef::new(ChannelName::Chat)]);
+        sm.update_without_message(&mut data, &mut SMEvents::new());
+
+        let mut events = SMEvents::new();
+        let open = NowChannelMsg::new(
+            ChannelMessageType::ChannelOpenRequest,
+            vec![
+                NowChannelDef::new(ChannelName::Chat),
+                NowChannelDef::new(ChannelName::Tunnel),
+            ],
+        );
+        sm.update_with_message(&mut data, &mut events, &NowMessage::Channel(open));
+
+        assert!(sm.waiting_for_packet());
+        match events.unpack().as_slice() {
+            [SMEvent::Warn(_), SMEvent::PacketToSend(packet)] => match &packet.body {
+                crate::message::NowBody::Message(NowMessage::Channel(rsp)) => {
+                    assert_eq!(rsp.subtype, ChannelMessageType::ChannelOpenResponse);
+                    assert_eq!(rsp.channel_list[0].flags, ChannelDefFlags::STATUS_SUCCESS);
+                    assert_eq!(rsp.channel_list[1].flags, ChannelDefFlags::STATUS_FAILURE);
+                }
+                body => panic!("expected a channel message and got {:?}", body),
+            },
+            _ => panic!("expected a warning and an open response"),
+        }
+    }
+
+    #[test]
+    fn handshake_version() {
+        use crate::message::NowHandshakeMsg;
+        use crate::version::NowVersion;
+
+        let handshake = |peer: NowVersion| {
+            let mut sm = HandshakeSM::new();
+            let mut data = SMData::new(Vec::new(), Vec::new(), Vec::new());
+            data.version = NowVersion::new(21, 2, 0);
+            sm.update_without_message(&mut data, &mut SMEvents::new());
+
+            let mut events = SMEvents::new();
+            let msg = NowMessage::Handshake(NowHandshakeMsg::new_with_version(peer));
+            sm.update_with_message(&mut data, &mut events, &msg);
+            (sm.is_terminated(), data, events.unpack())
+        };
+
+        let (terminated, data, _) = handshake(NowVersion::new(21, 1, 0));
+        assert!(terminated);
+        let version = data.extra_get::<NegotiatedVersion>().unwrap();
+        assert_eq!(version.peer, NowVersion::new(21, 1, 0));
+        assert_eq!(version.effective(), NowVersion::new(21, 1, 0));
+
+        let (terminated, data, events) = handshake(NowVersion::new(22, 0, 0));
+        assert!(!terminated);
+        assert!(data.extra_get::<NegotiatedVersion>().is_none());
+        match events.as_slice() {
+            [SMEvent::Fatal(e)] => assert!(e.to_string().contains("22.0.0")),
+            _ => panic!("expected a single fatal error"),
+        }
+    }
+
+    #[test]
+    fn codecs_validation() {
+        use crate::message::{NowCapset, NowCodecDef, QualityMode, UpdateCapset};
+        use crate::sm::client_connection::ConnectionEvent;
+
+        let local = vec![NowCapset::Update(UpdateCapset::new_with_supported_codecs(vec![
+            NowCodecDef::new(Codec::GFWX),
+            NowCodecDef::new(Codec::JPEG),
+        ]))];
+        let remote = |codec| {
+            NowMessage::Capabilities(NowCapabilitiesMsg::new_with_capabilities(vec![NowCapset::Update(
+                UpdateCapset::new(QualityMode::High, codec),
+            )]))
+        };
+
+        let mut sm = CapabilitiesSM::new();
+        let mut data = SMData::new(Vec::new(), local.clone(), Vec::new());
+        let mut events = SMEvents::new();
+        sm.update_with_message(&mut data, &mut events, &remote(Codec::JPEG));
+        assert!(sm.is_terminated());
+        assert!(matches!(events.peek()[0], SMEvent::Warn(_)));
+        match &events.peek()[1] {
+            SMEvent::Connection(ConnectionEvent::Capabilities(negotiated)) => {
+                assert_eq!(negotiated.codecs, [Codec::JPEG])
+            }
+            _ => panic!("expected negotiated capabilities"),
+        }
+
+        // no common codec, only a warning by default
+        let mut sm = CapabilitiesSM::new();
+        let mut events = SMEvents::new();
+        sm.update_with_message(&mut data, &mut events, &remote(Codec::Thor));
+        assert!(sm.is_terminated());
+        assert!(matches!(events.peek()[0], SMEvent::Warn(_)));
+
+        let mut sm = CapabilitiesSM::new();
+        data.require_common_codec = true;
+        let mut events = SMEvents::new();
+        sm.update_with_message(&mut data, &mut events, &remote(Codec::Thor));
+        assert!(!sm.is_terminated());
+        assert!(matches!(events.peek(), [SMEvent::Fatal(_)]));
+    }
+}
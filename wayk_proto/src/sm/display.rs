@@ -0,0 +1,370 @@
+use crate::error::ProtoErrorKind;
+use crate::message::{
+    MessageType, NowUpdateAckMsg, NowUpdateMsg, NowUpdateRefreshMsg, NowUpdateRegion, NowUpdateSuppressMsg, SizeRect,
+};
+use crate::sm::{ProtoData, SMEvent, SMEvents};
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayEvent {
+    /// Last graphics update of a frame received.
+    FrameCompleted { surface_id: u16, frame_id: u16 },
+}
+
+impl ProtoData for DisplayEvent {}
+
+/// Default for [`DamageTracker::new_with_max_rects`](struct.DamageTracker.html#method.new_with_max_rects).
+pub const DEFAULT_MAX_DAMAGE_RECTS: usize = 16;
+
+/// Regions of each surface touched by graphics updates.
+///
+/// Overlapping or adjacent rectangles are merged into their bounding rectangle, and surfaces
+/// with more than `max_rects` rectangles are collapsed into a single one, so that renderers only
+/// repaint a short list of dirty rectangles instead of the full surface.
+#[derive(Debug, Clone)]
+pub struct DamageTracker {
+    max_rects: usize,
+    surfaces: Vec<(u16, Vec<SizeRect>)>,
+}
+
+impl Default for DamageTracker {
+    fn default() -> Self {
+        Self::new_with_max_rects(DEFAULT_MAX_DAMAGE_RECTS)
+    }
+}
+
+impl DamageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_with_max_rects(max_rects: usize) -> Self {
+        Self {
+            max_rects: max_rects.max(1),
+            surfaces: Vec::new(),
+        }
+    }
+
+    /// Marks `rect` as dirty, empty rectangles are ignored.
+    pub fn add(&mut self, surface_id: u16, rect: &SizeRect) {
+        if rect.is_empty() {
+            return;
+        }
+
+        let max_rects = self.max_rects;
+        let rects = self.h_rects_mut(surface_id);
+
+        // a merged rectangle may touch rectangles already checked, hence the scan from the start
+        let mut merged = rect.clone();
+        while let Some(pos) = rects.iter().position(|other| h_touches(other, &merged)) {
+            merged = h_union(&rects.swap_remove(pos), &merged);
+        }
+        rects.push(merged);
+
+        if rects.len() > max_rects {
+            let first = rects[0].clone();
+            let bounding = rects.drain(..).fold(first, |acc, rect| h_union(&acc, &rect));
+            rects.push(bounding);
+        }
+    }
+
+    /// Dirty rectangles of a surface, none of them overlapping or adjacent.
+    pub fn dirty_rects(&self, surface_id: u16) -> &[SizeRect] {
+        self.surfaces
+            .iter()
+            .find(|(id, _)| *id == surface_id)
+            .map_or(&[], |(_, rects)| rects.as_slice())
+    }
+
+    pub fn is_dirty(&self, surface_id: u16) -> bool {
+        !self.dirty_rects(surface_id).is_empty()
+    }
+
+    /// Smallest rectangle covering every dirty rectangle of a surface.
+    pub fn bounding_rect(&self, surface_id: u16) -> Option<SizeRect> {
+        let mut rects = self.dirty_rects(surface_id).iter();
+        let first = rects.next()?.clone();
+        Some(rects.fold(first, |acc, rect| h_union(&acc, rect)))
+    }
+
+    /// Takes the dirty rectangles of a surface, which is clean afterwards.
+    pub fn take(&mut self, surface_id: u16) -> Vec<SizeRect> {
+        match self.surfaces.iter().position(|(id, _)| *id == surface_id) {
+            Some(pos) => self.surfaces.swap_remove(pos).1,
+            None => Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self, surface_id: u16) {
+        self.surfaces.retain(|(id, _)| *id != surface_id);
+    }
+
+    fn h_rects_mut(&mut self, surface_id: u16) -> &mut Vec<SizeRect> {
+        let pos = match self.surfaces.iter().position(|(id, _)| *id == surface_id) {
+            Some(pos) => pos,
+            None => {
+                self.surfaces.push((surface_id, Vec::new()));
+                self.surfaces.len() - 1
+            }
+        };
+        &mut self.surfaces[pos].1
+    }
+}
+
+/// Edges as (left, top, right, bottom), right and bottom excluded.
+fn h_edges(rect: &SizeRect) -> (i32, i32, i32, i32) {
+    let (x, y) = (i32::from(rect.x), i32::from(rect.y));
+    (x, y, x + i32::from(rect.width), y + i32::from(rect.height))
+}
+
+/// Overlapping or sharing an edge.
+fn h_touches(a: &SizeRect, b: &SizeRect) -> bool {
+    let (a_left, a_top, a_right, a_bottom) = h_edges(a);
+    let (b_left, b_top, b_right, b_bottom) = h_edges(b);
+    a_left <= b_right && b_left <= a_right && a_top <= b_bottom && b_top <= a_bottom
+}
+
+fn h_union(a: &SizeRect, b: &SizeRect) -> SizeRect {
+    let (a_left, a_top, a_right, a_bottom) = h_edges(a);
+    let (b_left, b_top, b_right, b_bottom) = h_edges(b);
+    let (left, top) = (a_left.min(b_left), a_top.min(b_top));
+    let (right, bottom) = (a_right.max(b_right), a_bottom.max(b_bottom));
+    SizeRect::new(
+        left as i16,
+        top as i16,
+        (right - left).min(i32::from(u16::MAX)) as u16,
+        (bottom - top).min(i32::from(u16::MAX)) as u16,
+    )
+}
+
+/// Acknowledges graphics frames so the server keeps sending updates, and builds refresh or
+/// suppress requests.
+///
+/// Frames are acknowledged as soon as they are complete by default. Applications that would
+/// rather throttle the server on their rendering use `new_with_manual_ack` and call `ack_frames`
+/// once frames are displayed.
+///
+/// Regions updated by the last frame of each surface are tracked by a
+/// [`DamageTracker`](struct.DamageTracker.html), reset when the next frame starts.
+#[derive(Debug, Clone)]
+pub struct DisplaySM {
+    auto_ack: bool,
+    /// Completed frames, as (surface id, frame id), waiting for `ack_frames`.
+    unacked: Vec<(u16, u16)>,
+    suppressed: Vec<u16>,
+    damage: DamageTracker,
+}
+
+impl Default for DisplaySM {
+    fn default() -> Self {
+        Self {
+            auto_ack: true,
+            unacked: Vec::new(),
+            suppressed: Vec::new(),
+            damage: DamageTracker::new(),
+        }
+    }
+}
+
+impl DisplaySM {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_with_manual_ack() -> Self {
+        Self {
+            auto_ack: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_auto_ack(&self) -> bool {
+        self.auto_ack
+    }
+
+    /// Frames completed but not acknowledged yet, as (surface id, frame id).
+    pub fn unacked_frames(&self) -> &[(u16, u16)] {
+        &self.unacked
+    }
+
+    pub fn is_suppressed(&self, surface_id: u16) -> bool {
+        self.suppressed.contains(&surface_id)
+    }
+
+    /// Regions updated since the current frame of each surface started.
+    pub fn damage(&self) -> &DamageTracker {
+        &self.damage
+    }
+
+    /// Acknowledges the frames completed so far.
+    pub fn ack_frames(&mut self) -> Vec<NowUpdateMsg<'static>> {
+        self.unacked
+            .drain(..)
+            .map(|(surface_id, frame_id)| NowUpdateAckMsg::new(surface_id, frame_id).into())
+            .collect()
+    }
+
+    /// Builds a request to send the whole surface again. Also resumes updates if they were suppressed.
+    pub fn request_full_refresh(&mut self, surface_id: u16) -> NowUpdateMsg<'static> {
+        self.suppressed.retain(|id| *id != surface_id);
+        NowUpdateRefreshMsg::new(vec![NowUpdateRegion::new_full(surface_id)]).into()
+    }
+
+    /// Builds a request to stop updates of a surface, until a refresh is requested.
+    pub fn suppress_updates(&mut self, surface_id: u16) -> NowUpdateMsg<'static> {
+        if !self.is_suppressed(surface_id) {
+            self.suppressed.push(surface_id);
+        }
+        NowUpdateSuppressMsg::new(vec![NowUpdateRegion::new_full(surface_id)]).into()
+    }
+
+    pub fn update_with_message<'msg>(
+        &mut self,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut Vec<NowUpdateMsg<'msg>>,
+        msg: &NowUpdateMsg<'msg>,
+    ) {
+        match msg {
+            NowUpdateMsg::UpdateGraphics(msg) => {
+                if msg.update_flags.frame_first() {
+                    self.damage.reset(msg.surface_id);
+                }
+                self.damage.add(msg.surface_id, &msg.update_rect);
+
+                if msg.update_flags.frame_last() {
+                    if self.auto_ack {
+                        to_send.push(NowUpdateAckMsg::new(msg.surface_id, msg.frame_id).into());
+                    } else {
+                        self.unacked.push((msg.surface_id, msg.frame_id));
+                    }
+                    events.push(SMEvent::Display(DisplayEvent::FrameCompleted {
+                        surface_id: msg.surface_id,
+                        frame_id: msg.frame_id,
+                    }));
+                }
+            }
+            unexpected => events.push(SMEvent::warn(
+                ProtoErrorKind::UnexpectedMessage(MessageType::Update),
+                format!("unexpected update message: {:?}", unexpected),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{NowUpdateGraphicsMsg, UpdateMessageType};
+    use crate::serialization::Decode;
+
+    #[rustfmt::skip]
+    const UPDATE_GRAPHICS_MSG: [u8; 29] = [
+        0x01, // subtype
+        0x00, // flags
+        0x02, 0x00, // codecId
+        0x01, 0x00, // surfaceID
+        0x07, 0x00, // frameID
+        0x03, 0x00, 0x00, 0x00, // updateFlags (first and last)
+        0x00, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x0c, 0x00, // updateRect
+        0x05, 0x00, 0x00, 0x00, // updateSize
+        0x01, 0x02, 0x03, 0x04, 0x05,
+    ];
+
+    fn frame() -> NowUpdateMsg<'static> {
+        NowUpdateGraphicsMsg::decode(&UPDATE_GRAPHICS_MSG)
+            .map(NowUpdateMsg::UpdateGraphics)
+            .unwrap()
+    }
+
+    #[test]
+    fn auto_ack() {
+        let mut sm = DisplaySM::new();
+        let mut events = SMEvents::new();
+        let mut to_send = Vec::new();
+        sm.update_with_message(&mut events, &mut to_send, &frame());
+
+        assert_eq!(
+            events.unpack()[0].downcast_data::<DisplayEvent>(),
+            Some(&DisplayEvent::FrameCompleted {
+                surface_id: 1,
+                frame_id: 7
+            })
+        );
+        match to_send.as_slice() {
+            [NowUpdateMsg::UpdateAck(ack)] => assert_eq!(*ack, NowUpdateAckMsg::new(1, 7)),
+            msgs => panic!("expected a single ack and got {:?}", msgs),
+        }
+        assert!(sm.unacked_frames().is_empty());
+    }
+
+    #[test]
+    fn manual_ack() {
+        let mut sm = DisplaySM::new_with_manual_ack();
+        let mut to_send = Vec::new();
+        sm.update_with_message(&mut SMEvents::new(), &mut to_send, &frame());
+        assert!(to_send.is_empty());
+        assert_eq!(sm.unacked_frames(), &[(1, 7)]);
+
+        assert_eq!(sm.ack_frames().len(), 1);
+        assert!(sm.unacked_frames().is_empty());
+    }
+
+    #[test]
+    fn suppress_and_refresh() {
+        let mut sm = DisplaySM::new();
+        match sm.suppress_updates(2) {
+            NowUpdateMsg::UpdateSuppress(msg) => assert_eq!(msg.subtype, UpdateMessageType::UpdateSuppress),
+            msg => panic!("expected a suppress request and got {:?}", msg),
+        }
+        assert!(sm.is_suppressed(2));
+
+        match sm.request_full_refresh(2) {
+            NowUpdateMsg::UpdateRefresh(msg) => assert_eq!(msg.regions[0].surface_id, 2),
+            msg => panic!("expected a refresh request and got {:?}", msg),
+        }
+        assert!(!sm.is_suppressed(2));
+    }
+
+    #[test]
+    fn damage_coalescing() {
+        let mut damage = DamageTracker::new_with_max_rects(3);
+        damage.add(1, &SizeRect::new(0, 0, 10, 10));
+        // adjacent
+        damage.add(1, &SizeRect::new(10, 0, 10, 10));
+        assert_eq!(damage.dirty_rects(1), &[SizeRect::new(0, 0, 20, 10)]);
+
+        damage.add(1, &SizeRect::new(100, 100, 5, 5));
+        damage.add(1, &SizeRect::new(0, 0, 0, 5));
+        assert_eq!(damage.dirty_rects(1).len(), 2);
+        assert!(!damage.is_dirty(2));
+
+        // overlaps and bridges both rectangles
+        damage.add(1, &SizeRect::new(15, 5, 90, 96));
+        assert_eq!(damage.dirty_rects(1), &[SizeRect::new(0, 0, 105, 105)]);
+
+        damage.add(1, &SizeRect::new(200, 0, 1, 1));
+        damage.add(1, &SizeRect::new(300, 0, 1, 1));
+        damage.add(1, &SizeRect::new(400, 0, 1, 1));
+        assert_eq!(damage.dirty_rects(1), &[SizeRect::new(0, 0, 401, 105)]);
+        assert_eq!(damage.bounding_rect(1), Some(SizeRect::new(0, 0, 401, 105)));
+
+        assert_eq!(damage.take(1).len(), 1);
+        assert!(!damage.is_dirty(1));
+        assert_eq!(damage.bounding_rect(1), None);
+    }
+
+    #[test]
+    fn damage_reset_per_frame() {
+        let mut sm = DisplaySM::new();
+        let mut first = frame();
+        if let NowUpdateMsg::UpdateGraphics(msg) = &mut first {
+            msg.update_rect = SizeRect::new(100, 100, 4, 4);
+        }
+        sm.update_with_message(&mut SMEvents::new(), &mut Vec::new(), &first);
+        assert_eq!(sm.damage().dirty_rects(1), &[SizeRect::new(100, 100, 4, 4)]);
+
+        // frame 7 starts again, previous damage dropped
+        sm.update_with_message(&mut SMEvents::new(), &mut Vec::new(), &frame());
+        assert_eq!(sm.damage().dirty_rects(1), &[SizeRect::new(0, 0, 12, 12)]);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::message::{EventMouseFlags, InputBatcher, InputEvent, NowInputMsg};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// Default for [`InputSM::with_frame_interval`](struct.InputSM.html#method.with_frame_interval).
+pub const DEFAULT_INPUT_FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Default for [`InputSM::with_max_events_per_sec`](struct.InputSM.html#method.with_max_events_per_sec).
+pub const DEFAULT_MAX_INPUT_EVENTS_PER_SEC: u32 = 240;
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Paces input events so that a fast mouse doesn't flood the link.
+///
+/// Events are queued with `push` and released as `NowInputMsg` batches by `poll`, at most once
+/// per frame interval and within the events per second limit. While queued, consecutive mouse
+/// moves collapse into the last position and consecutive scrolls add up, other events are kept
+/// as is and in order. Events over the rate limit are delayed, never dropped.
+///
+/// The state machine doesn't read the clock, `now` is any monotonic time (see `timer::Clock`).
+#[derive(Debug, Clone)]
+pub struct InputSM<'a> {
+    frame_interval: Duration,
+    max_events_per_sec: u32,
+    pending: Vec<InputEvent<'a>>,
+    last_poll: Option<Duration>,
+    window_start: Duration,
+    window_sent: u32,
+}
+
+impl Default for InputSM<'_> {
+    fn default() -> Self {
+        Self {
+            frame_interval: DEFAULT_INPUT_FRAME_INTERVAL,
+            max_events_per_sec: DEFAULT_MAX_INPUT_EVENTS_PER_SEC,
+            pending: Vec::new(),
+            last_poll: None,
+            window_start: Duration::from_secs(0),
+            window_sent: 0,
+        }
+    }
+}
+
+impl<'a> InputSM<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_frame_interval(self, frame_interval: Duration) -> Self {
+        Self { frame_interval, ..self }
+    }
+
+    pub fn with_max_events_per_sec(self, max_events_per_sec: u32) -> Self {
+        Self {
+            max_events_per_sec: max_events_per_sec.max(1),
+            ..self
+        }
+    }
+
+    pub fn pending_events(&self) -> &[InputEvent<'a>] {
+        &self.pending
+    }
+
+    pub fn push(&mut self, event: InputEvent<'a>) {
+        match (self.pending.last_mut(), event) {
+            // mouse events carry the buttons held, so a release may merge with the next move
+            (Some(InputEvent::Mouse(last)), InputEvent::Mouse(mouse))
+                if last.flags == EventMouseFlags::None && mouse.flags == EventMouseFlags::None =>
+            {
+                *last = mouse;
+            }
+            (Some(InputEvent::Scroll(last)), InputEvent::Scroll(scroll)) => {
+                last.x = last.x.saturating_add(scroll.x);
+                last.y = last.y.saturating_add(scroll.y);
+            }
+            (_, event) => self.pending.push(event),
+        }
+    }
+
+    pub fn extend<I: IntoIterator<Item = InputEvent<'a>>>(&mut self, events: I) {
+        for event in events {
+            self.push(event);
+        }
+    }
+
+    /// Earliest time `poll` may release events, `None` if nothing is queued.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        let frame = self
+            .last_poll
+            .map_or(Duration::from_secs(0), |last| last + self.frame_interval);
+        if self.window_sent >= self.max_events_per_sec {
+            Some(frame.max(self.window_start + RATE_WINDOW))
+        } else {
+            Some(frame)
+        }
+    }
+
+    /// Releases the queued events allowed at `now`, the remaining ones are kept for a later poll.
+    pub fn poll(&mut self, now: Duration) -> Vec<NowInputMsg<'a>> {
+        match self.next_deadline() {
+            Some(deadline) if deadline <= now => {}
+            _ => return Vec::new(),
+        }
+        self.last_poll = Some(now);
+
+        if now >= self.window_start + RATE_WINDOW {
+            self.window_start = now;
+            self.window_sent = 0;
+        }
+
+        let allowed = (self.max_events_per_sec - self.window_sent) as usize;
+        let released: Vec<InputEvent<'a>> = if allowed < self.pending.len() {
+            self.pending.drain(..allowed).collect()
+        } else {
+            core::mem::take(&mut self.pending)
+        };
+        self.window_sent += released.len() as u32;
+
+        let mut batcher = InputBatcher::new();
+        batcher.extend(released);
+        batcher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::InputEventBuilder;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn coalescing() {
+        let mut sm = InputSM::new();
+        sm.extend(
+            InputEventBuilder::new()
+                .mouse_move(1, 1)
+                .mouse_move(2, 2)
+                .mouse_move(3, 3)
+                .left_click()
+                .mouse_move(4, 4)
+                .scroll(0, 120)
+                .scroll(0, 120)
+                .key_press(0x41)
+                .into_events(),
+        );
+
+        let events = sm.pending_events();
+        assert_eq!(events.len(), 6);
+        assert!(matches!(&events[0], InputEvent::Mouse(mouse) if (mouse.x, mouse.y) == (3, 3)));
+        assert!(matches!(&events[1], InputEvent::Mouse(mouse) if mouse.flags == EventMouseFlags::ButtonLeft));
+        // button release merged with the next move
+        assert!(matches!(&events[2], InputEvent::Mouse(mouse) if (mouse.x, mouse.y) == (4, 4)));
+        assert!(matches!(&events[3], InputEvent::Scroll(scroll) if scroll.y == 240));
+
+        let batches = sm.poll(ms(0));
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].events().len(), 6);
+        assert!(sm.pending_events().is_empty());
+        assert_eq!(sm.next_deadline(), None);
+    }
+
+    #[test]
+    fn frame_interval() {
+        let mut sm = InputSM::new().with_frame_interval(ms(10));
+        sm.extend(InputEventBuilder::new().mouse_move(1, 1).into_events());
+        assert_eq!(sm.poll(ms(100)).len(), 1);
+
+        sm.extend(InputEventBuilder::new().mouse_move(2, 2).into_events());
+        assert_eq!(sm.next_deadline(), Some(ms(110)));
+        assert!(sm.poll(ms(105)).is_empty());
+        sm.extend(InputEventBuilder::new().mouse_move(3, 3).into_events());
+
+        let batches = sm.poll(ms(110));
+        assert_eq!(batches.len(), 1);
+        assert!(matches!(&batches[0].events(), [InputEvent::Mouse(mouse)] if (mouse.x, mouse.y) == (3, 3)));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut sm = InputSM::new().with_frame_interval(ms(0)).with_max_events_per_sec(3);
+        sm.extend(InputEventBuilder::new().key_press(0x41).key_press(0x42).into_events());
+
+        assert_eq!(sm.poll(ms(0))[0].events().len(), 3);
+        assert_eq!(sm.pending_events().len(), 1);
+        assert_eq!(sm.next_deadline(), Some(ms(1000)));
+        assert!(sm.poll(ms(500)).is_empty());
+
+        // delayed, not dropped
+        assert_eq!(sm.poll(ms(1000))[0].events().len(), 1);
+        assert!(sm.pending_events().is_empty());
+    }
+}
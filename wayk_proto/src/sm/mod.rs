@@ -1,30 +1,45 @@
+pub mod access_control;
+pub mod capabilities;
 pub mod client_channels;
 pub mod client_connection;
+pub mod display;
+pub mod input;
+pub mod sharing;
+pub mod surface;
+pub mod system;
 
 // re-export
+pub use access_control::*;
+pub use capabilities::*;
 pub use client_channels::*;
 pub use client_connection::*;
+pub use display::*;
+pub use input::*;
+pub use sharing::*;
+pub use surface::*;
+pub use system::*;
 
+use crate::auth::token::NowAuthSessionToken;
 use crate::error::{ProtoError, ProtoErrorKind};
-use crate::message::{AuthType, ChannelName, NowCapset, NowChannelDef, NowMessage, NowVirtualChannel};
-use crate::packet::NowPacket;
+use crate::message::{
+    AuthType, ChannelName, IntoOwned, NegotiateFlags, NowCapset, NowChannelDef, NowMessage, NowVirtualChannel,
+};
+use crate::packet::{NowPacket, NowPacketOwned};
 use crate::sharee::ShareeState;
+use crate::timer::{TimerId, TimerRequest};
+use crate::version::NowVersion;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
+use core::any::{Any, TypeId};
 use core::fmt::Debug;
-use std::any::{Any, TypeId};
-use std::collections::HashMap;
-use std::hash::{BuildHasherDefault, Hasher};
+use core::time::Duration;
 
 // === State Machine Event == //
 
+#[derive(Default)]
 pub struct SMEvents<'a>(Vec<SMEvent<'a>>);
 
-impl Default for SMEvents<'_> {
-    fn default() -> Self {
-        Self(Vec::new())
-    }
-}
-
 impl<'a> SMEvents<'a> {
     pub fn new() -> Self {
         Self::default()
@@ -43,15 +58,33 @@ impl<'a> SMEvents<'a> {
     }
 }
 
+#[non_exhaustive]
 pub enum SMEvent<'event> {
     StateTransition(Box<dyn ProtoState>),
     PacketToSend(NowPacket<'event>),
+    Connection(ConnectionEvent),
+    Chat(ChatEvent),
+    Clipboard(ClipboardEvent),
+    Access(AccessEvent),
+    System(SystemEvent),
+    Surface(SurfaceEvent),
+    Display(DisplayEvent),
+    /// Untyped data, for state machines without a dedicated event type.
     Data(Box<dyn ProtoData>),
+    /// Arms a timer, `Sharee::on_timer_expired` must be called once it expires.
+    SetTimer(TimerRequest),
+    CancelTimer(TimerId),
     Warn(ProtoError),
     Error(ProtoError),
     Fatal(ProtoError),
 }
 
+// Every state machine update returns a list of events, keep them cheap to move.
+#[cfg(not(feature = "fixed-strings"))]
+sa::const_assert!(core::mem::size_of::<SMEvent>() <= 96);
+
+sa::assert_impl_all!(SMEvent: Send, Sync);
+
 impl<'event> SMEvent<'event> {
     pub fn transition(s: impl ProtoState) -> Self {
         Self::StateTransition(Box::new(s))
@@ -72,38 +105,121 @@ impl<'event> SMEvent<'event> {
     pub fn fatal(kind: ProtoErrorKind, s: impl Into<alloc::borrow::Cow<'static, str>>) -> Self {
         Self::Fatal(ProtoError::new(kind).with_desc(s))
     }
-}
 
-pub trait ProtoState: Any + Debug {}
+    /// Virtual channel the event originates from, if any.
+    pub fn channel_name(&self) -> Option<ChannelName> {
+        match self {
+            Self::Chat(_) => Some(ChannelName::Chat),
+            Self::Clipboard(_) => Some(ChannelName::Clipboard),
+            _ => None,
+        }
+    }
 
-pub trait ProtoData: Any + Debug {}
+    /// Data carried by the event, typed or not.
+    ///
+    /// Typed events are still reachable this way so existing downcasting code keeps working.
+    pub fn as_data(&self) -> Option<&dyn ProtoData> {
+        match self {
+            Self::Connection(e) => Some(e.as_data()),
+            Self::Chat(e) => Some(e.as_data()),
+            Self::Clipboard(e) => Some(e),
+            Self::Access(e) => Some(e),
+            Self::System(e) => Some(e.as_data()),
+            Self::Surface(e) => Some(e),
+            Self::Display(e) => Some(e),
+            Self::Data(data) => Some(&**data),
+            _ => None,
+        }
+    }
 
-// === State Machine Data === //
+    pub fn downcast_data<T: ProtoData>(&self) -> Option<&T> {
+        self.as_data().and_then(|data| (data as &dyn Any).downcast_ref())
+    }
+}
 
-#[derive(Default)]
-struct IdHasher(u64);
+// === Packet sink === //
+
+/// Destination of outgoing packets.
+///
+/// Packets are handed over as owned packets so the sink may live on another thread, e.g. a
+/// `Sharee` updated on a reader thread while a writer thread owns the socket.
+pub trait PacketSink: Send + Sync {
+    fn send_packet(&self, packet: NowPacketOwned) -> Result<(), ProtoError>;
+
+    /// Sends all `PacketToSend` events and returns the remaining events.
+    fn send_events<'a>(&self, events: Vec<SMEvent<'a>>) -> Result<Vec<SMEvent<'a>>, ProtoError> {
+        let mut remaining = Vec::with_capacity(events.len());
+        for event in events {
+            match event {
+                SMEvent::PacketToSend(packet) => self.send_packet(packet.into_owned())?,
+                event => remaining.push(event),
+            }
+        }
+        Ok(remaining)
+    }
+}
+
+sa::assert_obj_safe!(PacketSink);
 
-impl Hasher for IdHasher {
-    fn write(&mut self, _: &[u8]) {
-        unreachable!("TypeId calls write_u64");
+#[cfg(feature = "std")]
+impl PacketSink for std::sync::mpsc::Sender<NowPacketOwned> {
+    fn send_packet(&self, packet: NowPacketOwned) -> Result<(), ProtoError> {
+        self.send(packet).map_err(|_| sink_disconnected())
     }
+}
 
-    #[inline]
-    fn write_u64(&mut self, id: u64) {
-        self.0 = id;
+#[cfg(feature = "std")]
+impl PacketSink for std::sync::mpsc::SyncSender<NowPacketOwned> {
+    fn send_packet(&self, packet: NowPacketOwned) -> Result<(), ProtoError> {
+        self.send(packet).map_err(|_| sink_disconnected())
     }
+}
 
-    #[inline]
-    fn finish(&self) -> u64 {
-        self.0
+#[cfg(feature = "std")]
+fn sink_disconnected() -> ProtoError {
+    ProtoError::from(crate::io::NoStdIoError::new_with_desc(
+        crate::io::NoStdIoErrorKind::BrokenPipe,
+        "packet sink receiver disconnected",
+    ))
+}
+
+pub trait ProtoState: Any + Debug + Send + Sync {}
+
+pub trait ProtoData: Any + Debug + Send + Sync {}
+
+/// Current state of a state machine, reported for diagnostics (see `Sharee::to_dot`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateInfo {
+    pub name: &'static str,
+    /// States reachable from the current one.
+    pub transitions: &'static [&'static str],
+}
+
+impl StateInfo {
+    pub const fn new(name: &'static str, transitions: &'static [&'static str]) -> Self {
+        Self { name, transitions }
     }
 }
 
+// === State Machine Data === //
+
 pub struct SMData {
+    /// Version advertised during the handshake.
+    pub version: NowVersion,
     pub supported_auths: Vec<AuthType>,
+    /// Flags sent during the negotiate phase, extended SRP only by default.
+    pub negotiate_flags: NegotiateFlags,
     pub capabilities: Vec<NowCapset<'static>>,
     pub channel_defs: Vec<NowChannelDef>,
-    extra: HashMap<TypeId, Box<dyn Any + Send + Sync>, BuildHasherDefault<IdHasher>>,
+    /// Maximum time to wait for the peer during the connection sequence, no limit by default.
+    pub response_timeout: Option<Duration>,
+    /// Fails the capabilities exchange if the peer supports none of the local codecs, it only
+    /// warns by default.
+    pub require_common_codec: bool,
+    /// One-time token sent by `SessionTokenSM` in place of interactive credentials.
+    pub session_token: Option<NowAuthSessionToken>,
+    next_timer_id: u32,
+    extra: BTreeMap<TypeId, Box<dyn Any + Send + Sync>>,
 }
 
 impl SMData {
@@ -114,13 +230,25 @@ impl SMData {
         channel_defs: Vec<NowChannelDef>,
     ) -> Self {
         Self {
+            version: NowVersion::CURRENT,
             supported_auths,
+            negotiate_flags: NegotiateFlags::new_empty().set_srp_extended(),
             capabilities,
             channel_defs,
-            extra: HashMap::default(),
+            response_timeout: None,
+            require_common_codec: false,
+            session_token: None,
+            next_timer_id: 0,
+            extra: BTreeMap::new(),
         }
     }
 
+    /// Allocates an id for a timer, unique for the session.
+    pub fn new_timer_id(&mut self) -> TimerId {
+        self.next_timer_id = self.next_timer_id.wrapping_add(1);
+        TimerId(self.next_timer_id)
+    }
+
     pub fn extra_insert<T: Send + Sync + 'static>(&mut self, val: T) -> Option<T> {
         self.extra
             .insert(TypeId::of::<T>(), Box::new(val))
@@ -172,6 +300,14 @@ pub trait ConnectionSM {
     fn is_running(&self) -> bool {
         !self.is_terminated()
     }
+
+    /// Called when a timer requested with `SMEvent::SetTimer` expires.
+    fn on_timer_expired<'msg>(&mut self, _data: &mut SMData, _events: &mut SMEvents<'msg>, _id: TimerId) {}
+
+    /// Current state and allowed transitions, `None` if not reported.
+    fn state_info(&self) -> Option<StateInfo> {
+        None
+    }
 }
 
 pub struct DummyConnectionSM;
@@ -211,14 +347,19 @@ sa::assert_obj_safe!(ConnectionSM);
 
 pub struct ChannelResponses<'a> {
     inner: Vec<(ChannelName, NowVirtualChannel<'a>)>,
+    /// Instance each response is sent on, `None` for the first instance of its channel.
+    channel_ids: Vec<Option<u8>>,
     current_channel_name: ChannelName,
+    current_channel_id: Option<u8>,
 }
 
 impl Default for ChannelResponses<'_> {
     fn default() -> Self {
         Self {
             inner: Vec::new(),
+            channel_ids: Vec::new(),
             current_channel_name: ChannelName::Unknown("unbound".into()),
+            current_channel_id: None,
         }
     }
 }
@@ -228,21 +369,64 @@ impl<'a> ChannelResponses<'a> {
         Self::default()
     }
 
+    /// Also resets the current channel id.
     pub fn set_current_channel_name(&mut self, name: ChannelName) {
         self.current_channel_name = name;
+        self.current_channel_id = None;
+    }
+
+    /// Instance of a `MULTIPLE` channel the next responses are sent on.
+    pub fn set_current_channel_id(&mut self, channel_id: Option<u8>) {
+        self.current_channel_id = channel_id;
     }
 
     pub fn push<'msg: 'a>(&mut self, msg: impl Into<NowVirtualChannel<'msg>>) {
         self.inner.push((self.current_channel_name.clone(), msg.into()));
+        self.channel_ids.push(self.current_channel_id);
     }
 
     pub fn peek(&self) -> &[(ChannelName, NowVirtualChannel<'a>)] {
         self.inner.as_slice()
     }
 
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Keeps responses pushed from index `start` only if `f` returns `true`. Kept responses may be modified.
+    pub fn retain_since<F>(&mut self, start: usize, mut f: F)
+    where
+        F: FnMut(&mut NowVirtualChannel<'a>) -> bool,
+    {
+        if start >= self.inner.len() {
+            return;
+        }
+
+        let inner = self.inner.split_off(start);
+        let channel_ids = self.channel_ids.split_off(start);
+        for ((name, mut msg), channel_id) in inner.into_iter().zip(channel_ids) {
+            if f(&mut msg) {
+                self.inner.push((name, msg));
+                self.channel_ids.push(channel_id);
+            }
+        }
+    }
+
     pub fn unpack(self) -> Vec<(ChannelName, NowVirtualChannel<'a>)> {
         self.inner
     }
+
+    pub fn unpack_with_ids(self) -> Vec<(Option<u8>, ChannelName, NowVirtualChannel<'a>)> {
+        self.channel_ids
+            .into_iter()
+            .zip(self.inner)
+            .map(|(channel_id, (name, msg))| (channel_id, name, msg))
+            .collect()
+    }
 }
 
 pub trait VirtualChannelSM {
@@ -270,6 +454,62 @@ pub trait VirtualChannelSM {
     fn is_running(&self) -> bool {
         !self.is_terminated()
     }
+
+    /// Called when the peer stops or closes the channel. The state machine isn't updated
+    /// until the channel is started again.
+    fn on_channel_stopped<'msg>(&mut self, _data: &mut SMData, _events: &mut SMEvents<'msg>) {}
+
+    /// Called when the peer starts the channel again after stopping it.
+    fn on_channel_started<'msg>(&mut self, _data: &mut SMData, _events: &mut SMEvents<'msg>) {}
+
+    /// Called when the channel wasn't opened during the connection sequence, usually because
+    /// the server refused it. The state machine isn't updated until the peer opens the channel.
+    fn on_channel_unavailable<'msg>(&mut self, _data: &mut SMData, _events: &mut SMEvents<'msg>) {}
+
+    /// Current state and allowed transitions, `None` if not reported.
+    fn state_info(&self) -> Option<StateInfo> {
+        None
+    }
 }
 
 sa::assert_obj_safe!(VirtualChannelSM);
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::message::NowActivateMsg;
+    use crate::serialization::Encode;
+
+    #[test]
+    fn packets_sent_to_writer_thread() {
+        let (sender, receiver) = std::sync::mpsc::channel::<NowPacketOwned>();
+        let writer = std::thread::spawn(move || {
+            receiver
+                .iter()
+                .map(|packet| packet.encode().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let events = vec![
+            SMEvent::transition(ConnectionState::Final),
+            SMEvent::PacketToSend(NowPacket::from_message(NowActivateMsg::default())),
+        ];
+        let remaining = sender.send_events(events).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert!(matches!(remaining[0], SMEvent::StateTransition(_)));
+        drop(sender);
+
+        let written = writer.join().unwrap();
+        assert_eq!(written.len(), 1);
+        let expected = NowPacket::from_message(NowActivateMsg::default()).encode().unwrap();
+        assert_eq!(written[0], expected);
+    }
+
+    #[test]
+    fn disconnected_sink() {
+        let (sender, receiver) = std::sync::mpsc::channel::<NowPacketOwned>();
+        drop(receiver);
+        let packet = NowPacket::from_message(NowActivateMsg::default()).into_owned();
+        assert!(sender.send_packet(packet).is_err());
+    }
+}
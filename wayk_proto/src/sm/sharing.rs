@@ -0,0 +1,213 @@
+use crate::error::{ProtoError, ProtoErrorKind, Result};
+use crate::message::{MessageType, NowSharingMsg, NowSharingResumeMsg, NowSharingSuspendMsg, NowString256};
+use crate::sm::{ProtoState, SMEvent, SMEvents};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+pub trait SharingCallbackTrait {
+    /// Peer wants to resume a session suspended locally. Return false to keep it suspended.
+    fn accept_resume(&mut self) -> bool {
+        true
+    }
+
+    /// Peer relinquished control, `message` is meant to be displayed to the user.
+    fn on_suspended(&mut self, message: &str) {
+        #![allow(unused_variables)]
+    }
+
+    /// Peer reclaimed control.
+    fn on_resumed(&mut self) {}
+}
+
+sa::assert_obj_safe!(SharingCallbackTrait);
+
+pub struct DummySharingCallback;
+
+impl SharingCallbackTrait for DummySharingCallback {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SharingState {
+    Active,
+    /// Control relinquished locally.
+    Suspended,
+    /// Control relinquished by the peer.
+    SuspendedByPeer,
+}
+
+impl ProtoState for SharingState {}
+
+/// Drives the sharing suspend/resume workflow.
+///
+/// Either end may relinquish control with a suspend message and reclaim it with a resume message.
+/// A resume from the end that didn't suspend the session is a request: it is refused by suspending
+/// again.
+pub struct SharingSM {
+    state: SharingState,
+    user_callback: Box<dyn SharingCallbackTrait + Send>,
+}
+
+impl Default for SharingSM {
+    fn default() -> Self {
+        Self::new(DummySharingCallback)
+    }
+}
+
+impl SharingSM {
+    pub fn new<C: SharingCallbackTrait + Send + 'static>(user_callback: C) -> Self {
+        Self {
+            state: SharingState::Active,
+            user_callback: Box::new(user_callback),
+        }
+    }
+
+    pub fn get_state(&self) -> SharingState {
+        self.state
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.state != SharingState::Active
+    }
+
+    /// Suspends the session, `message` is displayed to the peer.
+    pub fn relinquish_control(&mut self, events: &mut SMEvents<'_>, message: &str) -> Result<NowSharingMsg<'static>> {
+        if self.state == SharingState::Suspended {
+            return Err(ProtoError::new(ProtoErrorKind::UnexpectedMessage(MessageType::Sharing))
+                .with_desc("control already relinquished"));
+        }
+
+        let message = NowString256::from_str(message)?;
+        self.h_transition_state(events, SharingState::Suspended);
+        Ok(NowSharingMsg::Suspend(NowSharingSuspendMsg::new_with_message(message)))
+    }
+
+    /// Resumes the session. If suspended by the peer, the peer may refuse by suspending again.
+    pub fn reclaim_control(&mut self, events: &mut SMEvents<'_>) -> Result<NowSharingMsg<'static>> {
+        if self.state == SharingState::Active {
+            return Err(ProtoError::new(ProtoErrorKind::UnexpectedMessage(MessageType::Sharing))
+                .with_desc("session is not suspended"));
+        }
+
+        self.h_transition_state(events, SharingState::Active);
+        Ok(NowSharingMsg::Resume(NowSharingResumeMsg::new()))
+    }
+
+    pub fn update_with_message<'msg>(
+        &mut self,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut Vec<NowSharingMsg<'msg>>,
+        msg: &NowSharingMsg<'msg>,
+    ) {
+        match msg {
+            NowSharingMsg::Suspend(msg) => {
+                log::trace!("peer relinquished control: {}", msg.message.as_str());
+                self.h_transition_state(events, SharingState::SuspendedByPeer);
+                self.user_callback.on_suspended(msg.message.as_str());
+            }
+            NowSharingMsg::Resume(_) => match self.state {
+                SharingState::Active => events.push(SMEvent::warn(
+                    ProtoErrorKind::UnexpectedMessage(MessageType::Sharing),
+                    "received a sharing resume message while not suspended",
+                )),
+                SharingState::Suspended => {
+                    if self.user_callback.accept_resume() {
+                        log::trace!("peer resume accepted");
+                        self.h_transition_state(events, SharingState::Active);
+                        self.user_callback.on_resumed();
+                    } else {
+                        log::trace!("peer resume refused");
+                        to_send.push(NowSharingMsg::Suspend(NowSharingSuspendMsg::new()));
+                    }
+                }
+                SharingState::SuspendedByPeer => {
+                    log::trace!("peer reclaimed control");
+                    self.h_transition_state(events, SharingState::Active);
+                    self.user_callback.on_resumed();
+                }
+            },
+            NowSharingMsg::Custom(_) => events.push(SMEvent::warn(
+                ProtoErrorKind::UnexpectedMessage(MessageType::Sharing),
+                "unknown sharing message",
+            )),
+        }
+    }
+
+    fn h_transition_state(&mut self, events: &mut SMEvents<'_>, state: SharingState) {
+        self.state = state;
+        events.push(SMEvent::transition(state));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    struct RefusingCallback {
+        suspended: Arc<AtomicUsize>,
+    }
+
+    impl SharingCallbackTrait for RefusingCallback {
+        fn accept_resume(&mut self) -> bool {
+            false
+        }
+
+        fn on_suspended(&mut self, _: &str) {
+            self.suspended.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn receive(sm: &mut SharingSM, msg: NowSharingMsg<'static>) -> Vec<NowSharingMsg<'static>> {
+        let mut events = SMEvents::new();
+        let mut to_send = Vec::new();
+        sm.update_with_message(&mut events, &mut to_send, &msg);
+        to_send
+    }
+
+    #[test]
+    fn relinquish_and_reclaim() {
+        let mut sm = SharingSM::default();
+        let mut events = SMEvents::new();
+
+        match sm.relinquish_control(&mut events, "brb").unwrap() {
+            NowSharingMsg::Suspend(msg) => assert_eq!(msg.message.as_str(), "brb"),
+            msg => panic!("expected a sharing suspend message and got {:?}", msg),
+        }
+        assert_eq!(sm.get_state(), SharingState::Suspended);
+        assert!(sm.relinquish_control(&mut events, "").is_err());
+
+        assert!(matches!(
+            sm.reclaim_control(&mut events).unwrap(),
+            NowSharingMsg::Resume(_)
+        ));
+        assert_eq!(sm.get_state(), SharingState::Active);
+        assert!(sm.reclaim_control(&mut events).is_err());
+        assert_eq!(events.unpack().len(), 2);
+    }
+
+    #[test]
+    fn peer_suspend_and_resume() {
+        let mut sm = SharingSM::default();
+        assert!(receive(&mut sm, NowSharingMsg::Suspend(NowSharingSuspendMsg::new())).is_empty());
+        assert_eq!(sm.get_state(), SharingState::SuspendedByPeer);
+        assert!(receive(&mut sm, NowSharingMsg::Resume(NowSharingResumeMsg::new())).is_empty());
+        assert_eq!(sm.get_state(), SharingState::Active);
+    }
+
+    #[test]
+    fn peer_resume_refused() {
+        let suspended = Arc::new(AtomicUsize::new(0));
+        let mut sm = SharingSM::new(RefusingCallback {
+            suspended: suspended.clone(),
+        });
+        sm.relinquish_control(&mut SMEvents::new(), "").unwrap();
+
+        let to_send = receive(&mut sm, NowSharingMsg::Resume(NowSharingResumeMsg::new()));
+        assert!(matches!(to_send.as_slice(), [NowSharingMsg::Suspend(_)]));
+        assert_eq!(sm.get_state(), SharingState::Suspended);
+
+        receive(&mut sm, NowSharingMsg::Suspend(NowSharingSuspendMsg::new()));
+        assert_eq!(suspended.load(Ordering::SeqCst), 1);
+    }
+}
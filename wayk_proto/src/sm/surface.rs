@@ -0,0 +1,234 @@
+use crate::error::{ProtoError, ProtoErrorKind, Result};
+use crate::message::{
+    MessageType, NowSurfaceDef, NowSurfaceListRspMsg, NowSurfaceMap, NowSurfaceMapReqMsg, NowSurfaceMsg,
+    NowSurfaceSelectReqMsg, SurfaceResponseFlags,
+};
+use crate::sm::{ProtoData, SMEvent, SMEvents};
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SurfaceEvent {
+    /// Peer advertised its surfaces (monitors).
+    List(Vec<NowSurfaceDef>),
+    Selected {
+        surface_id: u16,
+        success: bool,
+    },
+    Mapped {
+        success: bool,
+    },
+}
+
+impl ProtoData for SurfaceEvent {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PendingRequest {
+    Select { sequence_id: u16, surface_id: u16 },
+    Map { sequence_id: u16 },
+}
+
+/// Keeps track of the peer surfaces and of surface selection / mapping requests.
+#[derive(Debug, Clone, Default)]
+pub struct SurfaceSM {
+    desktop_width: u16,
+    desktop_height: u16,
+    surfaces: Vec<NowSurfaceDef>,
+    sequence_id: u16,
+    pending: Vec<PendingRequest>,
+}
+
+impl SurfaceSM {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Surfaces last advertised by the peer.
+    pub fn surfaces(&self) -> &[NowSurfaceDef] {
+        &self.surfaces
+    }
+
+    pub fn desktop_size(&self) -> (u16, u16) {
+        (self.desktop_width, self.desktop_height)
+    }
+
+    pub fn selected_surface(&self) -> Option<&NowSurfaceDef> {
+        self.surfaces.iter().find(|surface| surface.flags.selected())
+    }
+
+    /// Builds a request to display the given surface only. The outcome is reported by a
+    /// `SurfaceEvent::Selected` event.
+    pub fn select_surface(&mut self, surface_id: u16) -> Result<NowSurfaceMsg<'static>> {
+        if !self.surfaces.is_empty() && !self.surfaces.iter().any(|surface| surface.surface_id == surface_id) {
+            return Err(ProtoError::new(ProtoErrorKind::UnexpectedMessage(MessageType::Surface))
+                .with_desc(format!("unknown surface {}", surface_id)));
+        }
+
+        let sequence_id = self.h_next_sequence_id();
+        self.pending.push(PendingRequest::Select {
+            sequence_id,
+            surface_id,
+        });
+        Ok(NowSurfaceSelectReqMsg::new(0, sequence_id, surface_id).into())
+    }
+
+    /// Builds a request to map surfaces onto local outputs. The outcome is reported by a
+    /// `SurfaceEvent::Mapped` event.
+    pub fn map_surfaces(&mut self, mappings: Vec<NowSurfaceMap>) -> NowSurfaceMsg<'static> {
+        let sequence_id = self.h_next_sequence_id();
+        self.pending.push(PendingRequest::Map { sequence_id });
+        NowSurfaceMapReqMsg::new_with_mappings(sequence_id, self.desktop_width, self.desktop_height, mappings).into()
+    }
+
+    pub fn update_with_message<'msg>(
+        &mut self,
+        events: &mut SMEvents<'msg>,
+        to_send: &mut Vec<NowSurfaceMsg<'msg>>,
+        msg: &NowSurfaceMsg<'msg>,
+    ) {
+        match msg {
+            NowSurfaceMsg::ListReq(req) => {
+                self.desktop_width = req.desktop_width;
+                self.desktop_height = req.desktop_height;
+                self.surfaces = req.surfaces.0.clone();
+                log::trace!("received {} surface(s)", self.surfaces.len());
+                to_send.push(NowSurfaceListRspMsg::new(SurfaceResponseFlags::new_empty(), req.sequence_id).into());
+                events.push(SMEvent::Surface(SurfaceEvent::List(self.surfaces.clone())));
+            }
+            NowSurfaceMsg::SelectRsp(rsp) => match self.h_take_pending(rsp.sequence_id) {
+                Some(PendingRequest::Select { surface_id, .. }) => {
+                    let success = !rsp.flags.failure();
+                    if success {
+                        for surface in &mut self.surfaces {
+                            if surface.surface_id == surface_id {
+                                surface.flags.set_selected();
+                            } else {
+                                surface.flags.unset_selected();
+                            }
+                        }
+                    }
+                    events.push(SMEvent::Surface(SurfaceEvent::Selected { surface_id, success }));
+                }
+                _ => self.h_unexpected_response(events, rsp.sequence_id),
+            },
+            NowSurfaceMsg::MapRsp(rsp) => match self.h_take_pending(rsp.sequence_id) {
+                Some(PendingRequest::Map { .. }) => events.push(SMEvent::Surface(SurfaceEvent::Mapped {
+                    success: !rsp.flags.failure(),
+                })),
+                _ => self.h_unexpected_response(events, rsp.sequence_id),
+            },
+            unexpected => events.push(SMEvent::warn(
+                ProtoErrorKind::UnexpectedMessage(MessageType::Surface),
+                format!("unexpected surface message: {:?}", unexpected),
+            )),
+        }
+    }
+
+    fn h_next_sequence_id(&mut self) -> u16 {
+        self.sequence_id = self.sequence_id.wrapping_add(1);
+        self.sequence_id
+    }
+
+    fn h_take_pending(&mut self, sequence_id: u16) -> Option<PendingRequest> {
+        let idx = self.pending.iter().position(|pending| match pending {
+            PendingRequest::Select { sequence_id: id, .. } | PendingRequest::Map { sequence_id: id } => {
+                *id == sequence_id
+            }
+        })?;
+        Some(self.pending.remove(idx))
+    }
+
+    fn h_unexpected_response(&self, events: &mut SMEvents<'_>, sequence_id: u16) {
+        events.push(SMEvent::warn(
+            ProtoErrorKind::UnexpectedMessage(MessageType::Surface),
+            format!("no pending surface request with sequence id {}", sequence_id),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{EdgeRect, NowSurfaceListReqMsg, NowSurfaceMapRspMsg, NowSurfaceSelectRspMsg};
+
+    fn surface_events(sm: &mut SurfaceSM, msg: NowSurfaceMsg<'static>) -> (Vec<SurfaceEvent>, usize) {
+        let mut events = SMEvents::new();
+        let mut to_send = Vec::new();
+        sm.update_with_message(&mut events, &mut to_send, &msg);
+        let events = events
+            .unpack()
+            .iter()
+            .filter_map(|e| match e {
+                SMEvent::Surface(e) => Some(e.clone()),
+                _ => None,
+            })
+            .collect();
+        (events, to_send.len())
+    }
+
+    fn two_monitors() -> NowSurfaceMsg<'static> {
+        NowSurfaceListReqMsg::new_with_surfaces(
+            7,
+            3840,
+            1080,
+            vec![
+                NowSurfaceDef::new(0, EdgeRect::new(0, 0, 1919, 1079)),
+                NowSurfaceDef::new(1, EdgeRect::new(1920, 0, 3839, 1079)).flags(0),
+            ],
+        )
+        .into()
+    }
+
+    #[test]
+    fn select_surface() {
+        let mut sm = SurfaceSM::new();
+        let (events, responses) = surface_events(&mut sm, two_monitors());
+        assert_eq!(responses, 1);
+        assert!(matches!(events.as_slice(), [SurfaceEvent::List(surfaces)] if surfaces.len() == 2));
+        assert_eq!(sm.desktop_size(), (3840, 1080));
+        assert_eq!(sm.selected_surface().unwrap().surface_id, 0);
+
+        assert!(sm.select_surface(5).is_err());
+        let sequence_id = match sm.select_surface(1).unwrap() {
+            NowSurfaceMsg::SelectReq(req) => {
+                assert_eq!(req.surface_id, 1);
+                req.sequence_id
+            }
+            msg => panic!("expected a surface select request and got {:?}", msg),
+        };
+
+        let rsp = NowSurfaceSelectRspMsg::new(SurfaceResponseFlags::new_empty(), sequence_id);
+        let (events, _) = surface_events(&mut sm, rsp.clone().into());
+        assert_eq!(
+            events,
+            vec![SurfaceEvent::Selected {
+                surface_id: 1,
+                success: true
+            }]
+        );
+        assert_eq!(sm.selected_surface().unwrap().surface_id, 1);
+
+        // already answered
+        let (events, _) = surface_events(&mut sm, rsp.into());
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn map_surfaces_failure() {
+        let mut sm = SurfaceSM::new();
+        surface_events(&mut sm, two_monitors());
+        let first = sm.select_surface(0).unwrap();
+        let sequence_id = match sm.map_surfaces(vec![NowSurfaceMap::new(1, 0, EdgeRect::new(0, 0, 1919, 1079))]) {
+            NowSurfaceMsg::MapReq(req) => {
+                assert_eq!((req.desktop_width, req.desktop_height), (3840, 1080));
+                assert_eq!(req.maps.0.len(), 1);
+                req.sequence_id
+            }
+            msg => panic!("expected a surface map request and got {:?}", msg),
+        };
+        assert!(matches!(first, NowSurfaceMsg::SelectReq(req) if req.sequence_id != sequence_id));
+
+        let rsp = NowSurfaceMapRspMsg::new(SurfaceResponseFlags::new_empty().set_failure(), sequence_id);
+        let (events, _) = surface_events(&mut sm, rsp.into());
+        assert_eq!(events, vec![SurfaceEvent::Mapped { success: false }]);
+    }
+}
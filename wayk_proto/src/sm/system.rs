@@ -0,0 +1,155 @@
+use crate::error::{ProtoError, ProtoErrorKind, Result};
+use crate::message::{
+    AccessControlCode, IntoOwned, MessageType, NowString256, NowSystemInfo, NowSystemInfoReqMsg, NowSystemMsg,
+    NowSystemOsInfo, NowSystemShutdownMsg, ShutdownFlags, SystemInfoType,
+};
+use crate::sm::{AccessControlSM, ProtoData, SMEvent, SMEvents};
+use alloc::boxed::Box;
+use core::str::FromStr;
+
+/// Access control required to shut the remote host down.
+pub const SHUTDOWN_ACCESS_CONTROL: AccessControlCode = AccessControlCode::Exec;
+
+/// Emitted when the peer answers an OS infos query.
+#[derive(Debug, Clone)]
+pub struct RemoteOsInfo(pub NowSystemOsInfo<'static>);
+
+impl ProtoData for RemoteOsInfo {}
+
+#[derive(Debug, Clone)]
+pub enum SystemEvent {
+    OsInfo(Box<RemoteOsInfo>),
+}
+
+impl SystemEvent {
+    pub fn as_data(&self) -> &dyn ProtoData {
+        match self {
+            SystemEvent::OsInfo(data) => &**data,
+        }
+    }
+}
+
+/// High-level system actions on the remote host.
+#[derive(Debug, Clone, Default)]
+pub struct SystemActions {
+    os_info_pending: bool,
+    os_info: Option<NowSystemOsInfo<'static>>,
+}
+
+impl SystemActions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Last OS infos received from the peer.
+    pub fn os_info(&self) -> Option<&NowSystemOsInfo<'static>> {
+        self.os_info.as_ref()
+    }
+
+    pub fn is_os_info_pending(&self) -> bool {
+        self.os_info_pending
+    }
+
+    /// Builds an OS infos query. The answer is reported by a `SystemEvent::OsInfo` event.
+    pub fn query_os_info(&mut self) -> NowSystemMsg<'static> {
+        self.os_info_pending = true;
+        NowSystemMsg::InfoReq(NowSystemInfoReqMsg::new(SystemInfoType::Os))
+    }
+
+    /// Builds a shutdown request, provided access was granted by the peer. `timeout` is in seconds.
+    pub fn shutdown_remote(
+        &self,
+        access_control: &AccessControlSM,
+        flags: ShutdownFlags,
+        timeout: u32,
+        message: &str,
+    ) -> Result<NowSystemMsg<'static>> {
+        if !access_control.is_allowed(SHUTDOWN_ACCESS_CONTROL) {
+            return Err(ProtoError::new(ProtoErrorKind::AccessControl(SHUTDOWN_ACCESS_CONTROL))
+                .with_desc("remote shutdown not allowed"));
+        }
+
+        Ok(NowSystemMsg::Shutdown(NowSystemShutdownMsg::new(
+            flags,
+            timeout,
+            NowString256::from_str(message)?,
+        )))
+    }
+
+    pub fn update_with_message<'msg>(&mut self, events: &mut SMEvents<'msg>, msg: &NowSystemMsg<'msg>) {
+        match msg {
+            NowSystemMsg::InfoRsp(rsp) => match &rsp.info_data {
+                NowSystemInfo::Os(info) => {
+                    if !self.os_info_pending {
+                        log::trace!("received unsolicited OS infos");
+                    }
+                    self.os_info_pending = false;
+
+                    let info = info.clone().into_owned();
+                    self.os_info = Some(info.clone());
+                    events.push(SMEvent::System(SystemEvent::OsInfo(Box::new(RemoteOsInfo(info)))));
+                }
+                NowSystemInfo::Custom(_) => events.push(SMEvent::warn(
+                    ProtoErrorKind::UnexpectedMessage(MessageType::System),
+                    "unknown system info type",
+                )),
+            },
+            unexpected => events.push(SMEvent::warn(
+                ProtoErrorKind::UnexpectedMessage(MessageType::System),
+                format!("unexpected system message: {:?}", unexpected),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{AccessControlDef, NowString16, NowSystemInfoRspMsg, OsArch, OsType};
+
+    #[test]
+    fn query_os_info() {
+        let mut actions = SystemActions::new();
+        match actions.query_os_info() {
+            NowSystemMsg::InfoReq(req) => assert_eq!(req.info_type, SystemInfoType::Os),
+            msg => panic!("expected a system info request and got {:?}", msg),
+        }
+        assert!(actions.is_os_info_pending());
+
+        let info = NowSystemOsInfo::new(OsType::Linux, OsArch::X64, 5, 4, 0, NowString16::new_empty());
+        let rsp = NowSystemMsg::InfoRsp(Box::new(NowSystemInfoRspMsg::new(NowSystemInfo::Os(info))));
+        let mut events = SMEvents::new();
+        actions.update_with_message(&mut events, &rsp);
+
+        let events = events.unpack();
+        let remote = match &events[0] {
+            SMEvent::System(SystemEvent::OsInfo(remote)) => remote,
+            _ => panic!("expected an OS infos event"),
+        };
+        assert_eq!(remote.0.os_type, OsType::Linux);
+        assert!(events[0].downcast_data::<RemoteOsInfo>().is_some());
+        assert_eq!(remote.0.version_major, 5);
+        assert!(!actions.is_os_info_pending());
+        assert_eq!(actions.os_info().unwrap().version_minor, 4);
+    }
+
+    #[test]
+    fn shutdown_requires_access() {
+        let actions = SystemActions::new();
+        let flags = ShutdownFlags::new_empty().set_reboot();
+
+        let denied = AccessControlSM::new();
+        assert!(actions.shutdown_remote(&denied, flags, 30, "maintenance").is_err());
+
+        let granted =
+            AccessControlSM::new_with_access_controls(vec![AccessControlDef::new_allowed(SHUTDOWN_ACCESS_CONTROL)]);
+        match actions.shutdown_remote(&granted, flags, 30, "maintenance").unwrap() {
+            NowSystemMsg::Shutdown(msg) => {
+                assert!(msg.flags.reboot());
+                assert_eq!(msg.timeout, 30);
+                assert_eq!(msg.message.as_str(), "maintenance");
+            }
+            msg => panic!("expected a shutdown message and got {:?}", msg),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Minimal synchronization primitives, usable without `std`.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Spin lock for short critical sections, such as pushing to a queue.
+pub(crate) struct SpinMutex<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: `value` is only accessed while `locked` is held, see `with`.
+unsafe impl<T: Send> Sync for SpinMutex<T> {}
+
+impl<T> SpinMutex<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+
+        // releases the lock even if `f` panics
+        struct Unlock<'a>(&'a AtomicBool);
+
+        impl Drop for Unlock<'_> {
+            fn drop(&mut self) {
+                self.0.store(false, Ordering::Release);
+            }
+        }
+
+        let _unlock = Unlock(&self.locked);
+        // SAFETY: the lock is held until `_unlock` is dropped.
+        f(unsafe { &mut *self.value.get() })
+    }
+}
+
+/// Queue shared between application threads and a state machine.
+pub(crate) struct SharedQueue<T> {
+    inner: Arc<SpinMutex<VecDeque<T>>>,
+}
+
+impl<T> Clone for SharedQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Default for SharedQueue<T> {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(SpinMutex::new(VecDeque::new())),
+        }
+    }
+}
+
+impl<T> SharedQueue<T> {
+    pub fn push(&self, value: T) {
+        self.inner.with(|queue| queue.push_back(value));
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.inner.with(VecDeque::pop_front)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.with(|queue| queue.is_empty())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_between_threads() {
+        let queue = SharedQueue::default();
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let queue = queue.clone();
+                std::thread::spawn(move || (0..100).for_each(|j| queue.push(i * 100 + j)))
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        let mut values: Vec<_> = core::iter::from_fn(|| queue.pop()).collect();
+        values.sort_unstable();
+        assert_eq!(values, (0..400).collect::<Vec<_>>());
+        assert!(queue.is_empty());
+    }
+}
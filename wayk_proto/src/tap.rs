@@ -0,0 +1,103 @@
+//! Packet taps.
+//!
+//! A [`PacketTap`](trait.PacketTap.html) registered with `ShareeBuilder::packet_tap` or
+//! `Sharee::add_packet_tap` sees every packet handed over to the sharee before any state machine
+//! processes it, and every packet the sharee produces before it is returned to the embedder.
+//! Taps can drop or replace packets, so logging, filtering or policy enforcement can be plugged in
+//! without touching the state machines, see also the [`policy`](../policy/index.html) module. Closures taking the direction and the packet are taps.
+//!
+//! Taps see a [`TappedPacket`](struct.TappedPacket.html) borrowing the body, received bodies
+//! aren't copied for them.
+//!
+//! ```
+//! use wayk_proto::message::{NowBody, NowMessage, NowTerminateMsg};
+//! use wayk_proto::packet::NowPacket;
+//! use wayk_proto::sharee::{Sharee, ShareeState};
+//! use wayk_proto::sm::DummyConnectionSM;
+//! use wayk_proto::tap::{TapVerdict, TappedPacket};
+//! use wayk_proto::trace::PacketDirection;
+//!
+//! let mut sharee = Sharee::builder(DummyConnectionSM)
+//!     .packet_tap(|direction, packet: &TappedPacket<'_, '_>| match packet.body {
+//!         NowBody::Message(NowMessage::Terminate(_)) if direction == PacketDirection::Received => TapVerdict::Drop,
+//!         _ => TapVerdict::Pass,
+//!     })
+//!     .build_unchecked();
+//! sharee.update_without_body();
+//! let events = sharee.update_with_packet(&NowPacket::from_message(NowTerminateMsg::default()));
+//! assert!(events.is_empty());
+//! assert_eq!(sharee.get_state(), ShareeState::Active);
+//! ```
+
+use crate::header::{AbstractNowHeader, NowHeader};
+use crate::message::NowBody;
+use crate::packet::NowPacket;
+use crate::trace::PacketDirection;
+use alloc::boxed::Box;
+
+/// What happens to a packet once a tap saw it.
+#[derive(Debug, Clone)]
+pub enum TapVerdict {
+    Pass,
+    /// Received packets aren't processed, packets to send aren't returned. Taps registered
+    /// after this one don't see the packet.
+    Drop,
+    /// The packet is processed or sent in place of the one the tap saw. Taps registered after
+    /// this one see the replacement.
+    Replace(NowPacket<'static>),
+}
+
+/// A packet going through a sharee, its body is borrowed from the caller or from the packet.
+#[derive(Debug, Clone)]
+pub struct TappedPacket<'p, 'a> {
+    pub header: NowHeader,
+    pub body: &'p NowBody<'a>,
+}
+
+impl<'p, 'a> From<&'p NowPacket<'a>> for TappedPacket<'p, 'a> {
+    fn from(packet: &'p NowPacket<'a>) -> Self {
+        Self {
+            header: packet.header.clone(),
+            body: &packet.body,
+        }
+    }
+}
+
+/// Sees the packets going through a sharee, see the [module documentation](index.html).
+pub trait PacketTap: Send {
+    fn on_packet(&mut self, direction: PacketDirection, packet: &TappedPacket<'_, '_>) -> TapVerdict;
+}
+
+impl<F> PacketTap for F
+where
+    F: FnMut(PacketDirection, &TappedPacket<'_, '_>) -> TapVerdict + Send,
+{
+    fn on_packet(&mut self, direction: PacketDirection, packet: &TappedPacket<'_, '_>) -> TapVerdict {
+        self(direction, packet)
+    }
+}
+
+/// Runs taps in registration order, stopping at the first one dropping the packet.
+pub(crate) fn run_taps(
+    taps: &mut [Box<dyn PacketTap>],
+    direction: PacketDirection,
+    packet: TappedPacket<'_, '_>,
+) -> TapVerdict {
+    let mut replacement: Option<NowPacket<'static>> = None;
+    for tap in taps.iter_mut() {
+        let current = replacement.as_ref().map_or_else(|| packet.clone(), TappedPacket::from);
+        match tap.on_packet(direction, &current) {
+            TapVerdict::Pass => {}
+            TapVerdict::Drop => {
+                log::trace!(
+                    "{:?} packet dropped by tap: {:?}",
+                    direction,
+                    current.header.body_type()
+                );
+                return TapVerdict::Drop;
+            }
+            TapVerdict::Replace(packet) => replacement = Some(packet),
+        }
+    }
+    replacement.map_or(TapVerdict::Pass, TapVerdict::Replace)
+}
@@ -0,0 +1,321 @@
+//! Scripted peer for deterministic integration tests.
+//!
+//! A [`MockPeer`](struct.MockPeer.html) holds a script of packets the sharee is expected to send
+//! and of canned responses, usually byte fixtures. Running the script drives the sharee the same
+//! way a transport loop would and fails on the first packet deviating from the script.
+//!
+//! ```
+//! use wayk_proto::message::{MessageType, NowActivateMsg, NowTerminateMsg};
+//! use wayk_proto::packet::NowPacket;
+//! use wayk_proto::sharee::{Sharee, ShareeState};
+//! use wayk_proto::sm::DummyConnectionSM;
+//! use wayk_proto::testing::MockPeer;
+//!
+//! let peer = MockPeer::new()
+//!     .respond(&NowPacket::from_message(NowActivateMsg::default()))?
+//!     .respond(&NowPacket::from_message(NowTerminateMsg::default()))?
+//!     .expect_message(MessageType::Terminate);
+//!
+//! let mut sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+//! peer.run(&mut sharee)?;
+//! assert_eq!(sharee.get_state(), ShareeState::Final);
+//! # Ok::<(), wayk_proto::error::ProtoError>(())
+//! ```
+
+use crate::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt, Result};
+use crate::header::{AbstractNowHeader, NowHeader};
+use crate::message::{ChannelName, MessageType, NowBody};
+use crate::packet::NowPacket;
+use crate::serialization::{Decode, Encode};
+use crate::sharee::Sharee;
+use crate::sm::{ConnectionSM, SMEvent};
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+type PacketMatcher = Box<dyn Fn(&NowPacket<'_>, &[u8]) -> bool + Send + Sync>;
+
+enum Step<'a> {
+    Expect { what: String, matcher: PacketMatcher },
+    Respond(Cow<'a, [u8]>),
+}
+
+impl fmt::Debug for Step<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Expect { what, .. } => write!(f, "expect {}", what),
+            Step::Respond(bytes) => write!(f, "respond with {} bytes", bytes.len()),
+        }
+    }
+}
+
+/// Scripted peer, see the [module documentation](index.html).
+#[derive(Debug, Default)]
+pub struct MockPeer<'a> {
+    script: Vec<Step<'a>>,
+}
+
+impl<'a> MockPeer<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Next packet sent by the sharee must be this exact encoded packet.
+    pub fn expect_bytes(self, fixture: &[u8]) -> Self {
+        let fixture = fixture.to_vec();
+        self.expect(format!("bytes {:02x?}", fixture), move |_, bytes| {
+            bytes == fixture.as_slice()
+        })
+    }
+
+    /// Next packet sent by the sharee must be a message of this type.
+    pub fn expect_message(self, message_type: MessageType) -> Self {
+        self.expect(
+            format!("{:?} message", message_type),
+            move |packet, _| matches!(&packet.body, NowBody::Message(msg) if msg.get_type() == message_type),
+        )
+    }
+
+    /// Next packet sent by the sharee must be a message on this virtual channel.
+    pub fn expect_channel(self, name: ChannelName) -> Self {
+        self.expect(
+            format!("{:?} channel message", name),
+            move |packet, _| matches!(&packet.body, NowBody::VirtualChannel(msg) if *msg.get_name() == name),
+        )
+    }
+
+    /// Next packet sent by the sharee must satisfy `matcher`, called with the packet and its
+    /// encoded form. `what` describes the expected packet in errors.
+    pub fn expect<F>(mut self, what: impl Into<String>, matcher: F) -> Self
+    where
+        F: Fn(&NowPacket<'_>, &[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.script.push(Step::Expect {
+            what: what.into(),
+            matcher: Box::new(matcher),
+        });
+        self
+    }
+
+    /// Feeds this encoded packet to the sharee once it waits for a packet.
+    pub fn respond_bytes(mut self, fixture: &'a [u8]) -> Self {
+        self.script.push(Step::Respond(Cow::Borrowed(fixture)));
+        self
+    }
+
+    /// Same as `respond_bytes` with a packet encoded on the fly.
+    pub fn respond(mut self, packet: &NowPacket<'_>) -> Result<Self> {
+        self.script.push(Step::Respond(Cow::Owned(packet.encode()?)));
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.script.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.script.is_empty()
+    }
+
+    /// Drives the sharee through the whole script and returns all the events produced.
+    ///
+    /// Fails if the sharee sends an unexpected packet, doesn't send an expected one or terminates
+    /// before the end of the script. Sharee updates are stopped as soon as it waits for a packet
+    /// past the end of the script.
+    pub fn run<ConnectionSeq>(&self, sharee: &mut Sharee<ConnectionSeq>) -> Result<Vec<SMEvent<'_>>>
+    where
+        ConnectionSeq: ConnectionSM,
+    {
+        let mut events = Vec::new();
+        let mut steps = self.script.iter().enumerate().peekable();
+
+        loop {
+            while sharee.is_running() && !sharee.waiting_for_packet() {
+                let new_events = sharee.update_without_body();
+                h_check_sent(sharee, &mut steps, &new_events)?;
+                events.extend(new_events);
+            }
+
+            match steps.next() {
+                None => return Ok(events),
+                Some((idx, step)) if sharee.is_terminated() => {
+                    return Err(ProtoError::new(ProtoErrorKind::Sharee(sharee.get_state()))
+                        .with_desc(format!("sharee terminated before step n°{} ({:?})", idx, step)));
+                }
+                Some((_, Step::Respond(bytes))) => {
+                    let header = NowHeader::decode(bytes)?;
+                    let body = bytes
+                        .get(header.len()..)
+                        .chain(ProtoErrorKind::Decoding(__type_str!(MockPeer)))
+                        .or_desc("scripted packet shorter than its header")?;
+                    let packet = NowPacket::decode_with_ctx(header, body, &sharee.decode_ctx())?;
+
+                    let new_events = sharee.update_with_packet(&packet);
+                    h_check_sent(sharee, &mut steps, &new_events)?;
+                    events.extend(new_events);
+                }
+                Some((idx, step)) => {
+                    return Err(ProtoError::new(ProtoErrorKind::Sharee(sharee.get_state()))
+                        .with_desc(format!("sharee waits for a packet but step n°{} is to {:?}", idx, step)));
+                }
+            }
+        }
+    }
+}
+
+fn h_check_sent<'s, ConnectionSeq, I>(
+    sharee: &Sharee<ConnectionSeq>,
+    steps: &mut core::iter::Peekable<I>,
+    events: &[SMEvent<'_>],
+) -> Result<()>
+where
+    ConnectionSeq: ConnectionSM,
+    I: Iterator<Item = (usize, &'s Step<'s>)>,
+{
+    for event in events {
+        let packet = match event {
+            SMEvent::PacketToSend(packet) => packet,
+            _ => continue,
+        };
+        let bytes = packet.encode()?;
+
+        match steps.peek() {
+            Some((_, Step::Expect { matcher, .. })) if matcher(packet, &bytes) => {
+                steps.next();
+            }
+            Some((idx, step)) => {
+                return Err(
+                    ProtoError::new(ProtoErrorKind::Sharee(sharee.get_state())).with_desc(format!(
+                        "sharee sent {:?} but step n°{} is to {:?}",
+                        packet.body, idx, step
+                    )),
+                );
+            }
+            None => {
+                return Err(ProtoError::new(ProtoErrorKind::Sharee(sharee.get_state()))
+                    .with_desc(format!("sharee sent {:?} past the end of the script", packet.body)));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{
+        AuthType, ChannelMessageType, NowActivateMsg, NowAssociateMsg, NowCapabilitiesMsg, NowChannelDef,
+        NowChannelMsg, NowHandshakeMsg, NowNegotiateMsg, NowTerminateMsg,
+    };
+    use crate::message::{MouseCapset, MouseCapsetFlags, MouseMode, NegotiateFlags, NowCapset};
+    use crate::sharee::ShareeState;
+    use crate::sm::{
+        AvailableAuthTypes, Channels, ClientConnectionSeqSM, ConnectionState, DummyConnectionSM,
+        NegotiatedCapabilities, NegotiatedFlags,
+    };
+
+    #[rustfmt::skip]
+    const ASSOCIATE_INFO: [u8; 12] = [
+        0x08, 0x00, 0x04, 0x80, // header
+        0x01, // subtype (info)
+        0x00, // reserved
+        0x00, 0x00, // flags
+        0x00, 0x00, 0x00, 0x00, // session id
+    ];
+
+    #[rustfmt::skip]
+    const ACTIVATE: [u8; 8] = [
+        0x04, 0x00, 0x07, 0x80, // header
+        0x00, 0x00, 0x00, 0x00, // flags
+    ];
+
+    fn mouse_capset() -> NowCapset<'static> {
+        NowCapset::Mouse(MouseCapset::new(MouseMode::Primary, MouseCapsetFlags::new_empty()))
+    }
+
+    fn channels_msg(subtype: ChannelMessageType) -> NowPacket<'static> {
+        NowPacket::from_message(NowChannelMsg::new(subtype, vec![NowChannelDef::new(ChannelName::Chat)]))
+    }
+
+    #[test]
+    fn client_connection_sequence() {
+        let peer = MockPeer::new()
+            .expect_message(MessageType::Handshake)
+            .respond(&NowPacket::from_message(NowHandshakeMsg::new_success()))
+            .unwrap()
+            .expect_message(MessageType::Negotiate)
+            .respond(&NowPacket::from_message(NowNegotiateMsg::new_with_auth_list(
+                NegotiateFlags::new_empty(),
+                vec![AuthType::None],
+            )))
+            .unwrap()
+            .respond_bytes(&ASSOCIATE_INFO)
+            .expect_message(MessageType::Associate)
+            .respond(&NowPacket::from_message(NowAssociateMsg::new_response()))
+            .unwrap()
+            .respond(&NowPacket::from_message(NowCapabilitiesMsg::new_with_capabilities(
+                vec![mouse_capset()],
+            )))
+            .unwrap()
+            .expect_message(MessageType::Capabilities)
+            .expect_message(MessageType::Channel)
+            .respond(&channels_msg(ChannelMessageType::ChannelListResponse))
+            .unwrap()
+            .expect_message(MessageType::Channel)
+            .respond(&channels_msg(ChannelMessageType::ChannelOpenResponse))
+            .unwrap()
+            .expect_bytes(&ACTIVATE);
+
+        let mut sharee = Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM))
+            .supported_auths(vec![AuthType::None])
+            .build_unchecked();
+        let events = peer.run(&mut sharee).unwrap();
+
+        assert_eq!(sharee.get_state(), ShareeState::Active);
+        assert_eq!(sharee.get_connection_seq().get_state(), ConnectionState::Final);
+        assert!(events
+            .iter()
+            .all(|e| !matches!(e, SMEvent::Error(_) | SMEvent::Fatal(_))));
+
+        // formerly emitted as `SMEvent::Data`, still reachable by downcasting
+        let auth_types = events.iter().find_map(|e| e.downcast_data::<AvailableAuthTypes>());
+        assert_eq!(auth_types.unwrap().0, [AuthType::None]);
+        assert!(events.iter().any(|e| e.downcast_data::<NegotiatedFlags>().is_some()));
+        assert!(events
+            .iter()
+            .any(|e| e.downcast_data::<NegotiatedCapabilities>().is_some()));
+        assert!(events.iter().any(|e| e.downcast_data::<Channels>().is_some()));
+
+        assert_eq!(sharee.decode_ctx().capabilities, [mouse_capset()]);
+    }
+
+    #[test]
+    fn script_mismatch() {
+        let sharee = || Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM)).build_unchecked();
+
+        // wrong packet sent
+        let peer = MockPeer::new().expect_message(MessageType::Negotiate);
+        match peer.run(&mut sharee()) {
+            Err(e) => assert!(matches!(e.kind, ProtoErrorKind::Sharee(ShareeState::Connection))),
+            Ok(_) => panic!("handshake message shouldn't match the script"),
+        }
+
+        // expected packet never sent
+        let peer = MockPeer::new()
+            .expect_message(MessageType::Handshake)
+            .expect_channel(ChannelName::Chat);
+        assert!(peer.run(&mut sharee()).is_err());
+
+        // terminated too early
+        let peer = MockPeer::new()
+            .respond(&NowPacket::from_message(NowTerminateMsg::default()))
+            .unwrap()
+            .respond(&NowPacket::from_message(NowActivateMsg::default()))
+            .unwrap();
+        let mut sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+        assert!(peer.run(&mut sharee).is_err());
+        assert_eq!(peer.len(), 2);
+    }
+}
@@ -0,0 +1,155 @@
+//! Timers requested by state machines.
+//!
+//! State machines never read the clock themselves: they ask for a timer with
+//! `SMEvent::SetTimer` and are notified once it expires through `Sharee::on_timer_expired`.
+//! Embedders may arm timers with their own runtime, or keep them in a
+//! [`TimerQueue`](struct.TimerQueue.html) polled against a [`Clock`](trait.Clock.html).
+//!
+//! ```
+//! use core::time::Duration;
+//! use wayk_proto::timer::{TimerAction, TimerId, TimerQueue, TimerRequest};
+//!
+//! let mut timers = TimerQueue::new();
+//! let id = TimerId(1);
+//! timers.apply(TimerAction::Set(TimerRequest { id, delay: Duration::from_secs(5) }), Duration::from_secs(10));
+//! assert_eq!(timers.next_deadline(), Some(Duration::from_secs(15)));
+//! assert!(timers.pop_expired(Duration::from_secs(12)).is_empty());
+//! assert_eq!(timers.pop_expired(Duration::from_secs(15)), vec![id]);
+//! ```
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// Identifies a timer, allocated with `SMData::new_timer_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(pub u32);
+
+/// Timer to arm on behalf of a state machine. Setting a timer again replaces its deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerRequest {
+    pub id: TimerId,
+    pub delay: Duration,
+}
+
+/// Timer update, in the order requested by state machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerAction {
+    Set(TimerRequest),
+    Cancel(TimerId),
+}
+
+/// Source of time for a `TimerQueue`.
+pub trait Clock {
+    /// Time elapsed since an arbitrary, fixed origin.
+    fn now(&self) -> Duration;
+}
+
+/// Monotonic clock whose origin is its creation.
+///
+/// Not available on `wasm32` where `Instant::now` panics, browsers should provide a clock backed
+/// by `performance.now()`.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    origin: std::time::Instant,
+}
+
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            origin: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+/// Pending timers with their deadline, for embedders without a timer facility of their own.
+#[derive(Debug, Default, Clone)]
+pub struct TimerQueue {
+    timers: Vec<(TimerId, Duration)>,
+}
+
+impl TimerQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `now` is the current time of the clock used to poll the queue.
+    pub fn apply(&mut self, action: TimerAction, now: Duration) {
+        match action {
+            TimerAction::Set(request) => {
+                self.cancel(request.id);
+                self.timers.push((request.id, now + request.delay));
+            }
+            TimerAction::Cancel(id) => self.cancel(id),
+        }
+    }
+
+    pub fn cancel(&mut self, id: TimerId) {
+        self.timers.retain(|(timer_id, _)| *timer_id != id);
+    }
+
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.timers.iter().map(|(_, deadline)| *deadline).min()
+    }
+
+    /// Removes and returns the timers expired at `now`, earliest first.
+    pub fn pop_expired(&mut self, now: Duration) -> Vec<TimerId> {
+        let mut expired: Vec<(TimerId, Duration)> = Vec::new();
+        self.timers.retain(|&(id, deadline)| {
+            if deadline <= now {
+                expired.push((id, deadline));
+                false
+            } else {
+                true
+            }
+        });
+        expired.sort_by_key(|(_, deadline)| *deadline);
+        expired.into_iter().map(|(id, _)| id).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_replace_and_cancel() {
+        let set = |id, secs| {
+            TimerAction::Set(TimerRequest {
+                id: TimerId(id),
+                delay: Duration::from_secs(secs),
+            })
+        };
+
+        let mut timers = TimerQueue::new();
+        timers.apply(set(1, 10), Duration::ZERO);
+        timers.apply(set(2, 5), Duration::ZERO);
+        timers.apply(set(3, 1), Duration::ZERO);
+        timers.apply(set(1, 2), Duration::ZERO); // replaced
+        timers.apply(TimerAction::Cancel(TimerId(3)), Duration::ZERO);
+        assert_eq!(timers.next_deadline(), Some(Duration::from_secs(2)));
+
+        assert_eq!(timers.pop_expired(Duration::from_secs(6)), vec![TimerId(1), TimerId(2)]);
+        assert!(timers.is_empty());
+        assert_eq!(timers.next_deadline(), None);
+    }
+}
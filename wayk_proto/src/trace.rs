@@ -0,0 +1,322 @@
+//! Packet capture and replay.
+//!
+//! A [`PacketRecorder`](struct.PacketRecorder.html) stores every sent and received packet
+//! along with a timestamp into a binary log. This log can be fed back through the state
+//! machines using a [`PacketReplayer`](struct.PacketReplayer.html) for regression testing.
+//!
+//! The log starts with the `WNTR` magic and a version byte followed by [`TraceRecord`]s
+//! encoded one after the other.
+//!
+//! [`TraceRecord`]: struct.TraceRecord.html
+
+use crate::container::Bytes32;
+use crate::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt, Result};
+use crate::header::{AbstractNowHeader, NowHeader};
+use crate::io::{Cursor, NoStdWrite};
+use crate::message::VirtChannelsCtx;
+use crate::packet::NowPacket;
+use crate::serialization::{Decode, DecodeCtx, DecodeLimits, Encode};
+use crate::sharee::Sharee;
+use crate::sm::{ConnectionSM, SMEvent};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+pub const TRACE_MAGIC: [u8; 4] = *b"WNTR";
+pub const TRACE_VERSION: u8 = 1;
+
+/// Returns a timestamp in milliseconds.
+pub type ClockFn = Box<dyn FnMut() -> u64 + Send>;
+
+/// Clock returning milliseconds elapsed since the UNIX epoch.
+///
+/// Not available on `wasm32` where `SystemTime::now` panics, provide a clock backed by
+/// `Date.now()` to `PacketRecorder::new` instead.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub fn system_clock() -> ClockFn {
+    Box::new(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    })
+}
+
+#[derive(Encode, Decode, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[roundtrip]
+pub enum PacketDirection {
+    #[value = 0x00]
+    Sent,
+    #[value = 0x01]
+    Received,
+    #[fallback]
+    Other(u8),
+}
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "'de: 'a"))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[roundtrip]
+pub struct TraceRecord<'a> {
+    pub direction: PacketDirection,
+    pub timestamp: u64,
+    pub packet: Bytes32<'a>,
+}
+
+impl<'a> TraceRecord<'a> {
+    pub fn decode_packet(&self, channels_ctx: &VirtChannelsCtx) -> Result<NowPacket<'_>> {
+        self.decode_packet_with_ctx(&DecodeCtx::default().with_channels(channels_ctx))
+    }
+
+    pub fn decode_packet_with_ctx(&self, ctx: &DecodeCtx<'_>) -> Result<NowPacket<'_>> {
+        let bytes = &*self.packet;
+        let header = NowHeader::decode(bytes)?;
+        let body = bytes
+            .get(header.len()..)
+            .chain(ProtoErrorKind::Decoding(__type_str!(TraceRecord)))
+            .or_desc("packet shorter than its header")?;
+        NowPacket::decode_with_ctx(header, body, ctx)
+    }
+}
+
+// recorder
+
+pub struct PacketRecorder {
+    log: Vec<u8>,
+    clock: ClockFn,
+    count: usize,
+}
+
+impl PacketRecorder {
+    pub fn new(clock: ClockFn) -> Self {
+        let mut log = Vec::new();
+        log.extend_from_slice(&TRACE_MAGIC);
+        log.push(TRACE_VERSION);
+        Self { log, clock, count: 0 }
+    }
+
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    pub fn with_system_clock() -> Self {
+        Self::new(system_clock())
+    }
+
+    pub fn record_sent(&mut self, packet: &NowPacket<'_>) -> Result<()> {
+        self.record(PacketDirection::Sent, packet)
+    }
+
+    pub fn record_received(&mut self, packet: &NowPacket<'_>) -> Result<()> {
+        self.record(PacketDirection::Received, packet)
+    }
+
+    pub fn record(&mut self, direction: PacketDirection, packet: &NowPacket<'_>) -> Result<()> {
+        let bytes = packet.encode()?;
+        self.record_raw(direction, &bytes)
+    }
+
+    /// Records an already encoded packet.
+    pub fn record_raw(&mut self, direction: PacketDirection, packet: &[u8]) -> Result<()> {
+        let record = TraceRecord {
+            direction,
+            timestamp: (self.clock)(),
+            packet: Bytes32::from(packet),
+        };
+        record.encode_into(&mut self.log)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn log(&self) -> &[u8] {
+        &self.log
+    }
+
+    pub fn into_log(self) -> Vec<u8> {
+        self.log
+    }
+
+    pub fn write_to<W: NoStdWrite>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.log)?;
+        Ok(())
+    }
+}
+
+// replayer
+
+pub struct PacketReplayer<'a> {
+    records: Vec<TraceRecord<'a>>,
+    limits: Option<DecodeLimits>,
+}
+
+impl<'a> PacketReplayer<'a> {
+    pub fn new(log: &'a [u8]) -> Result<Self> {
+        Self::h_new(log, None)
+    }
+
+    /// Decodes the log and its packets with the given limits instead of the global ones.
+    pub fn with_limits(log: &'a [u8], limits: DecodeLimits) -> Result<Self> {
+        Self::h_new(log, Some(limits))
+    }
+
+    fn h_new(log: &'a [u8], limits: Option<DecodeLimits>) -> Result<Self> {
+        let mut cursor = Cursor::new(log);
+        if let Some(limits) = limits {
+            cursor.set_limits(limits);
+        }
+
+        let magic = cursor
+            .read_n(TRACE_MAGIC.len())
+            .map_err(ProtoError::from)
+            .chain(ProtoErrorKind::Decoding(__type_str!(PacketReplayer)))
+            .or_desc("couldn't read trace magic")?;
+        if magic != TRACE_MAGIC {
+            return Err(
+                ProtoError::new(ProtoErrorKind::Decoding(__type_str!(PacketReplayer))).with_desc("invalid trace magic")
+            );
+        }
+
+        let version = cursor.read_u8()?;
+        if version != TRACE_VERSION {
+            return Err(ProtoError::new(ProtoErrorKind::Decoding(__type_str!(PacketReplayer)))
+                .with_desc(format!("unsupported trace version {}", version)));
+        }
+
+        let mut records = Vec::new();
+        while cursor.position() < log.len() {
+            let record = TraceRecord::decode_from(&mut cursor)
+                .chain(ProtoErrorKind::Decoding(__type_str!(PacketReplayer)))
+                .or_else_desc(|| format!("couldn't decode record n°{}", records.len()))?;
+            records.push(record);
+        }
+
+        Ok(Self { records, limits })
+    }
+
+    pub fn records(&self) -> &[TraceRecord<'a>] {
+        &self.records
+    }
+
+    pub fn sent(&self) -> impl Iterator<Item = &TraceRecord<'a>> {
+        self.records.iter().filter(|r| r.direction == PacketDirection::Sent)
+    }
+
+    pub fn received(&self) -> impl Iterator<Item = &TraceRecord<'a>> {
+        self.records.iter().filter(|r| r.direction == PacketDirection::Received)
+    }
+
+    /// Feeds every received packet to the sharee, driving it the same way a transport loop would,
+    /// and returns all the events produced.
+    pub fn replay<ConnectionSeq>(&self, sharee: &mut Sharee<ConnectionSeq>) -> Result<Vec<SMEvent<'_>>>
+    where
+        ConnectionSeq: ConnectionSM,
+    {
+        let mut events = Vec::new();
+
+        for record in self.received() {
+            while sharee.is_running() && !sharee.waiting_for_packet() {
+                events.extend(sharee.update_without_body());
+            }
+
+            if sharee.is_terminated() {
+                log::warn!("sharee terminated before the end of the trace");
+                return Ok(events);
+            }
+
+            let ctx = sharee.decode_ctx();
+            let ctx = DecodeCtx {
+                limits: self.limits.or(ctx.limits),
+                ..ctx
+            };
+            let packet = record.decode_packet_with_ctx(&ctx)?;
+            events.extend(sharee.update_with_packet(&packet));
+        }
+
+        while sharee.is_running() && !sharee.waiting_for_packet() {
+            events.extend(sharee.update_without_body());
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{NowActivateMsg, NowTerminateMsg};
+    use crate::sharee::ShareeState;
+    use crate::sm::DummyConnectionSM;
+
+    fn fixed_clock() -> ClockFn {
+        let mut now = 1_000;
+        Box::new(move || {
+            now += 10;
+            now
+        })
+    }
+
+    #[rustfmt::skip]
+    const TRACE_LOG: [u8; 39] = [
+        0x57, 0x4e, 0x54, 0x52, // magic
+        0x01, // version
+
+        // record
+        0x00, // direction
+        0xf2, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // timestamp
+        0x08, 0x00, 0x00, 0x00, // packet size
+        0x04, 0x00, 0x07, 0x80, 0x00, 0x00, 0x00, 0x00, // activate packet
+
+        // record
+        0x01, // direction
+        0xfc, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // timestamp
+        0x08, 0x00, 0x00, 0x00, // packet size
+    ];
+
+    #[test]
+    fn recorder_log_layout() {
+        let mut recorder = PacketRecorder::new(fixed_clock());
+        recorder
+            .record_sent(&NowPacket::from_message(NowActivateMsg::default()))
+            .unwrap();
+        assert_eq!(recorder.len(), 1);
+        assert_eq!(recorder.log(), &TRACE_LOG[..26]);
+    }
+
+    #[test]
+    fn replay_recorded_session() {
+        let mut recorder = PacketRecorder::new(fixed_clock());
+        recorder
+            .record_sent(&NowPacket::from_message(NowActivateMsg::default()))
+            .unwrap();
+        recorder
+            .record_received(&NowPacket::from_message(NowTerminateMsg::default()))
+            .unwrap();
+        let log = recorder.into_log();
+
+        let replayer = PacketReplayer::new(&log).unwrap();
+        assert_eq!(replayer.records().len(), 2);
+        assert_eq!(replayer.sent().count(), 1);
+        assert_eq!(replayer.received().count(), 1);
+        assert_eq!(replayer.records()[1].timestamp, 1_020);
+
+        let mut sharee = Sharee::builder(DummyConnectionSM).build_unchecked();
+        let events = replayer.replay(&mut sharee).unwrap();
+        assert_eq!(sharee.get_state(), ShareeState::Final);
+        assert!(!events.is_empty());
+    }
+
+    #[test]
+    fn replayer_rejects_truncated_log() {
+        assert!(PacketReplayer::new(&TRACE_LOG).is_err());
+        assert!(PacketReplayer::new(&TRACE_LOG[..3]).is_err());
+    }
+}
@@ -22,3 +22,64 @@ pub const WAYK_NOW_VERSION_PATCH: u8 = patch!();
 pub const WAYK_NOW_NAME_STRING: &str = "Wayk Now";
 pub const WAYK_NOW_VERSION_STRING: &str = concat!(major!(), ".", minor!(), ".", patch!());
 pub const WAYK_NOW_VERSION: [u16; 3] = [major!() * 1000, minor!() * 100, patch!()];
+
+/// Wayk Now protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct NowVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl NowVersion {
+    pub const CURRENT: NowVersion =
+        NowVersion::new(WAYK_NOW_VERSION_MAJOR, WAYK_NOW_VERSION_MINOR, WAYK_NOW_VERSION_PATCH);
+
+    pub const fn new(major: u8, minor: u8, patch: u8) -> Self {
+        Self { major, minor, patch }
+    }
+
+    /// Versions sharing the same major version are able to talk to each other.
+    pub fn is_compatible_with(&self, other: &NowVersion) -> bool {
+        self.major == other.major
+    }
+
+    /// Version both sides have to speak: the oldest one, provided they are compatible.
+    pub fn effective_with(&self, peer: &NowVersion) -> Option<NowVersion> {
+        if self.is_compatible_with(peer) {
+            Some(core::cmp::min(*self, *peer))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for NowVersion {
+    fn default() -> Self {
+        Self::CURRENT
+    }
+}
+
+impl core::fmt::Display for NowVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effective_version() {
+        let local = NowVersion::new(21, 2, 0);
+        assert_eq!(
+            local.effective_with(&NowVersion::new(21, 1, 3)),
+            Some(NowVersion::new(21, 1, 3))
+        );
+        assert_eq!(local.effective_with(&NowVersion::new(21, 4, 0)), Some(local));
+        assert_eq!(local.effective_with(&NowVersion::new(20, 2, 0)), None);
+    }
+}
@@ -0,0 +1,172 @@
+//! WebSocket framing for browser embedders.
+//!
+//! Browsers only give access to message-oriented WebSocket transports. A
+//! [`WebSocketSession`](struct.WebSocketSession.html) wraps a [`Driver`](../driver/struct.Driver.html)
+//! and translates between binary WebSocket messages and the Wayk Now byte stream: every packet
+//! to send goes out as its own binary message, and incoming messages are fed to the driver as is
+//! since a peer may split or coalesce packets freely.
+//!
+//! This module doesn't depend on any binding crate so it can be used from `wasm-bindgen`,
+//! `web-sys` or any other glue code.
+//!
+//! ```
+//! use wayk_proto::sharee::Sharee;
+//! use wayk_proto::sm::{ClientConnectionSeqSM, DummyConnectionSM};
+//! use wayk_proto::wasm::WebSocketSession;
+//!
+//! let sharee = Sharee::builder(ClientConnectionSeqSM::new(DummyConnectionSM)).build_unchecked();
+//! let mut session = WebSocketSession::new(sharee);
+//!
+//! // once the socket `open` event fires
+//! let output = session.on_open();
+//! assert_eq!(output.frames.len(), 1); // handshake message
+//! ```
+
+use crate::driver::{Actions, Driver};
+use crate::error::{ProtoError, ProtoErrorKind, Result};
+use crate::header::{AbstractNowHeader, NowHeader};
+use crate::message::DisconnectStatusCode;
+use crate::serialization::Decode;
+use crate::sharee::Sharee;
+use crate::sm::{ConnectionSM, SMEvent};
+use crate::timer::{TimerAction, TimerId};
+use alloc::vec::Vec;
+
+/// What the embedder has to do after feeding the session.
+#[derive(Default)]
+pub struct WebSocketOutput {
+    /// Binary messages to send on the socket, in order.
+    pub frames: Vec<Vec<u8>>,
+    /// Timers to arm with `setTimeout` or cancel, report expired ones with `on_timeout`.
+    pub timers: Vec<TimerAction>,
+    pub events: Vec<SMEvent<'static>>,
+    /// The socket should be closed, the session is over.
+    pub close: bool,
+}
+
+/// WebSocket adapter over a sans-IO driver, see the [module documentation](index.html).
+pub struct WebSocketSession<ConnectionSeq> {
+    driver: Driver<ConnectionSeq>,
+}
+
+impl<ConnectionSeq> WebSocketSession<ConnectionSeq>
+where
+    ConnectionSeq: ConnectionSM,
+{
+    pub fn new(sharee: Sharee<ConnectionSeq>) -> Self {
+        Self {
+            driver: Driver::new(sharee),
+        }
+    }
+
+    pub fn driver(&self) -> &Driver<ConnectionSeq> {
+        &self.driver
+    }
+
+    pub fn driver_mut(&mut self) -> &mut Driver<ConnectionSeq> {
+        &mut self.driver
+    }
+
+    /// Starts the connection sequence. Call it on the socket `open` event.
+    pub fn on_open(&mut self) -> WebSocketOutput {
+        let actions = self.driver.start();
+        self.h_output(actions)
+    }
+
+    /// Feeds a binary message received on the socket.
+    pub fn on_message(&mut self, data: &[u8]) -> WebSocketOutput {
+        let actions = self.driver.handle_input(data);
+        self.h_output(actions)
+    }
+
+    /// Reports a timer from `WebSocketOutput::timers` as expired.
+    pub fn on_timeout(&mut self, id: TimerId) -> WebSocketOutput {
+        let actions = self.driver.handle_timeout(id);
+        self.h_output(actions)
+    }
+
+    /// Terminates the session gracefully, the socket should be closed once `close` is set.
+    pub fn terminate(&mut self, reason: DisconnectStatusCode) -> WebSocketOutput {
+        let events = self.driver.sharee_mut().terminate(reason);
+        let actions = self.driver.handle_events(events);
+        self.h_output(actions)
+    }
+
+    fn h_output(&self, actions: Actions) -> WebSocketOutput {
+        let mut output = WebSocketOutput {
+            frames: Vec::new(),
+            timers: actions.timers,
+            events: actions.events,
+            close: self.driver.is_terminated(),
+        };
+
+        match split_packets(&actions.to_send) {
+            Ok(frames) => output.frames = frames,
+            Err(e) => {
+                output.events.push(SMEvent::Fatal(e));
+                output.close = true;
+            }
+        }
+
+        output
+    }
+}
+
+/// Splits a buffer of whole encoded packets into one frame per packet.
+pub fn split_packets(mut bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut frames = Vec::new();
+    while !bytes.is_empty() {
+        let header = NowHeader::decode(bytes)?;
+        let packet_len = header.packet_len();
+        if packet_len > bytes.len() {
+            return Err(
+                ProtoError::new(ProtoErrorKind::Decoding(__type_str!(NowHeader))).with_desc(format!(
+                    "packet is {} bytes long but only {} bytes are left",
+                    packet_len,
+                    bytes.len()
+                )),
+            );
+        }
+        let (frame, rest) = bytes.split_at(packet_len);
+        frames.push(frame.to_vec());
+        bytes = rest;
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{NowActivateMsg, NowTerminateMsg};
+    use crate::packet::NowPacket;
+    use crate::serialization::Encode;
+    use crate::sm::DummyConnectionSM;
+
+    #[test]
+    fn one_frame_per_packet() {
+        let mut bytes = NowPacket::from_message(NowActivateMsg::default()).encode().unwrap();
+        let first_len = bytes.len();
+        bytes.extend(NowPacket::from_message(NowTerminateMsg::default()).encode().unwrap());
+
+        let frames = split_packets(&bytes).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0], &bytes[..first_len]);
+        assert_eq!(frames[1], &bytes[first_len..]);
+
+        assert!(split_packets(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn close_on_peer_termination() {
+        let mut session = WebSocketSession::new(Sharee::builder(DummyConnectionSM).build_unchecked());
+        session.on_open();
+
+        let activate = NowPacket::from_message(NowActivateMsg::default()).encode().unwrap();
+        assert!(!session.on_message(&activate).close);
+
+        let terminate = NowPacket::from_message(NowTerminateMsg::default()).encode().unwrap();
+        let output = session.on_message(&terminate);
+        assert!(output.close);
+        assert_eq!(output.frames.len(), 1); // terminate acknowledgement
+    }
+}
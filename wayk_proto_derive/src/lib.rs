@@ -4,13 +4,17 @@ extern crate alloc;
 extern crate proc_macro;
 extern crate proc_macro2;
 
+use alloc::string::ToString;
 use alloc::vec::Vec;
 use proc_macro::TokenStream;
 use proc_macro2::{Span, TokenStream as TokenStream2};
 use quote::quote;
 use syn::punctuated::Punctuated;
 use syn::token::Add;
-use syn::{Attribute, Data, Fields, Generics, Ident, Lifetime, LifetimeDef, Lit, LitInt, Meta, Type};
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Fields, GenericArgument, Generics, Ident, Lifetime, LifetimeDef,
+    Lit, LitInt, Meta, NestedMeta, PathArguments, Type,
+};
 
 mod parsed {
     use alloc::vec::Vec;
@@ -32,8 +36,26 @@ mod parsed {
     pub struct Field<'a> {
         pub decode_ignore: bool,
         pub encode_ignore: bool,
+        pub size_prefix: bool,
+        pub flag_cond: Option<FlagCond>,
+        /// Byte order requested by `#[endian = "..."]`, on the field or on the struct
+        pub endian: Option<Endian>,
         pub name: &'a syn::Ident,
         pub ty: &'a syn::Type,
+        /// `T` when the field type is `Option<T>`
+        pub option_inner: Option<&'a syn::Type>,
+    }
+
+    pub struct FlagCond {
+        pub flags: syn::Ident,
+        pub bit: syn::Ident,
+        pub decode_with: Option<syn::Expr>,
+    }
+
+    #[derive(Clone, Copy)]
+    pub enum Endian {
+        Little,
+        Big,
     }
 
     // == Trivial Enum with fallback == //
@@ -42,14 +64,23 @@ mod parsed {
         pub name: &'a syn::Ident,
         pub underlying_repr: &'a syn::Type,
         pub variants: Vec<VariantWithValue<'a>>,
+        pub range_variants: Vec<VariantWithRange<'a>>,
         pub fallback_variant: &'a syn::Ident,
     }
 
     pub struct VariantWithValue<'a> {
         pub ident: &'a syn::Ident,
+        /// Explicit or following the previous variant value
         pub value: syn::LitInt,
     }
 
+    /// Variant carrying the raw value, for values in `start..=end`
+    pub struct VariantWithRange<'a> {
+        pub ident: &'a syn::Ident,
+        pub start: syn::LitInt,
+        pub end: syn::LitInt,
+    }
+
     // == Meta Enum == //
 
     pub struct MetaEnum<'a> {
@@ -58,6 +89,8 @@ mod parsed {
         pub meta: syn::Meta,
         pub variants: Vec<MetaEnumVariant<'a>>,
         pub fallback_variant_ident: &'a syn::Ident,
+        /// `Arbitrary` is implemented by the `Decode` derive for `#[roundtrip]` meta enums
+        pub arbitrary: bool,
     }
 
     pub struct MetaEnumVariant<'a> {
@@ -68,64 +101,162 @@ mod parsed {
     }
 }
 
-#[proc_macro_derive(Encode, attributes(meta_enum, encode_ignore, value, fallback))]
+#[proc_macro_derive(
+    Encode,
+    attributes(
+        meta_enum,
+        encode_ignore,
+        size_prefix,
+        flag_cond,
+        endian,
+        value,
+        value_range,
+        fallback
+    )
+)]
 pub fn encode_macro_derive(input: TokenStream) -> TokenStream {
-    let ast = syn::parse(input).expect("failed to parse input");
+    let ast = parse_macro_input!(input as DeriveInput);
     impl_trait(&ast, impl_encode)
 }
 
-fn impl_encode(ty: parsed::Type<'_>) -> TokenStream {
+fn impl_encode(ty: parsed::Type<'_>) -> syn::Result<TokenStream2> {
     match ty {
         parsed::Type::Struct(data) => {
             let ty = data.name;
             let (impl_generics, ty_generics, where_clause) = data.generics.split_for_impl();
 
-            let fields: Vec<&Ident> = data
-                .fields
+            let encoded_fields: Vec<&parsed::Field> = data.fields.iter().filter(|field| !field.encode_ignore).collect();
+
+            let types: Vec<&Type> = encoded_fields.iter().map(|field| field.ty).collect();
+
+            let is_variable = encoded_fields.iter().any(|field| field.flag_cond.is_some());
+
+            let expected_size = if is_variable {
+                quote! { ::wayk_proto::serialization::ExpectedSize::Variable }
+            } else {
+                quote! {
+                    use ::wayk_proto::serialization::ExpectedSize;
+                    ExpectedSize::Known( #(
+                        if let ExpectedSize::Known(v) = <#types as ::wayk_proto::serialization::Encode>::expected_size() {
+                            v
+                        } else {
+                            return ExpectedSize::Variable;
+                        }
+                    )+* )
+                }
+            };
+
+            let lens: Vec<TokenStream2> = encoded_fields
                 .iter()
-                .filter(|field| !field.encode_ignore)
-                .map(|field| field.name)
+                .map(|field| {
+                    let name = field.name;
+                    match (&field.flag_cond, field.option_inner) {
+                        (Some(cond), Some(_)) => {
+                            let (flags, bit) = (&cond.flags, &cond.bit);
+                            quote! {
+                                if self.#flags.#bit() {
+                                    self.#name.as_ref().map(|v| v.encoded_len()).unwrap_or(0)
+                                } else {
+                                    0
+                                }
+                            }
+                        }
+                        (Some(cond), None) => {
+                            let (flags, bit) = (&cond.flags, &cond.bit);
+                            quote! { if self.#flags.#bit() { self.#name.encoded_len() } else { 0 } }
+                        }
+                        (None, _) => quote! { self.#name.encoded_len() },
+                    }
+                })
                 .collect();
 
-            let types: Vec<&Type> = data
-                .fields
+            let encode_stmts: Vec<TokenStream2> = encoded_fields
                 .iter()
-                .filter(|field| !field.encode_ignore)
-                .map(|field| field.ty)
+                .map(|field| {
+                    let name = field.name;
+                    let field_ty = field.ty;
+
+                    let or_desc = quote! {
+                        .chain(ProtoErrorKind::Encoding(stringify!(#ty)))
+                        .or_else_desc(|| format!("couldn't encode {}::{}", stringify!(#ty), stringify!(#name)))?;
+                    };
+
+                    // `value` is a reference to the value to encode
+                    let encode = |value: TokenStream2| match field.endian {
+                        Some(parsed::Endian::Little) => {
+                            quote! { ::wayk_proto::serialization::Endian::encode_le(#value, writer) }
+                        }
+                        Some(parsed::Endian::Big) => {
+                            quote! { ::wayk_proto::serialization::Endian::encode_be(#value, writer) }
+                        }
+                        None => quote! { (#value).encode_into(writer) },
+                    };
+
+                    if field.size_prefix {
+                        // the size prefix is computed rather than taken from the field
+                        let encode = encode(quote! { &__size });
+                        return quote! {
+                            let __size = <#field_ty as ::core::convert::TryFrom<usize>>::try_from(self.encoded_len())
+                                .map_err(ProtoError::from)
+                                .chain(ProtoErrorKind::Encoding(stringify!(#ty)))
+                                .or_desc(concat!("size doesn't fit into ", stringify!(#ty), "::", stringify!(#name)))?;
+                            #encode #or_desc
+                        };
+                    }
+
+                    match (&field.flag_cond, field.option_inner) {
+                        (Some(cond), Some(_)) => {
+                            let (flags, bit) = (&cond.flags, &cond.bit);
+                            let encode = encode(quote! { v });
+                            quote! {
+                                if self.#flags.#bit() {
+                                    if let Some(v) = &self.#name {
+                                        #encode #or_desc
+                                    }
+                                }
+                            }
+                        }
+                        (Some(cond), None) => {
+                            let (flags, bit) = (&cond.flags, &cond.bit);
+                            let encode = encode(quote! { &self.#name });
+                            quote! {
+                                if self.#flags.#bit() {
+                                    #encode #or_desc
+                                }
+                            }
+                        }
+                        (None, _) => {
+                            let encode = encode(quote! { &self.#name });
+                            quote! { #encode #or_desc }
+                        }
+                    }
+                })
                 .collect();
 
             let expanded = quote! {
                 impl #impl_generics ::wayk_proto::serialization::Encode for #ty #ty_generics #where_clause {
                     fn expected_size() -> ::wayk_proto::serialization::ExpectedSize {
-                        use ::wayk_proto::serialization::ExpectedSize;
-                        ExpectedSize::Known( #(
-                            if let ExpectedSize::Known(v) = <#types as ::wayk_proto::serialization::Encode>::expected_size() {
-                                v
-                            } else {
-                                return ExpectedSize::Variable;
-                            }
-                        )+* )
+                        #expected_size
                     }
 
                     fn encoded_len(&self) -> usize {
                         #(
-                            self.#fields.encoded_len()
+                            (#lens)
                         )+*
                     }
 
                     fn encode_into<W: ::wayk_proto::io::NoStdWrite>(&self, writer: &mut W) -> ::core::result::Result<(), ::wayk_proto::error::ProtoError> {
-                        use ::wayk_proto::error::{ProtoErrorKind, ProtoErrorResultExt as _};
+                        #[allow(unused_imports)]
+                        use ::wayk_proto::error::{ProtoError, ProtoErrorKind, ProtoErrorResultExt as _};
                         #(
-                            self.#fields.encode_into(writer)
-                                .chain(ProtoErrorKind::Encoding(stringify!(#ty)))
-                                .or_else_desc(|| format!("couldn't encode {}::{}", stringify!(#ty), stringify!(#fields)))?;
+                            #encode_stmts
                         )*
                         Ok(())
                     }
                 }
             };
 
-            expanded.into()
+            Ok(expanded)
         }
         parsed::Type::MetaEnum(data) => {
             let ty = data.name;
@@ -172,7 +303,7 @@ fn impl_encode(ty: parsed::Type<'_>) -> TokenStream {
                 }
             };
 
-            expanded.into()
+            Ok(expanded)
         }
         parsed::Type::EnumWithFallback(data) => {
             let ty = data.name;
@@ -182,6 +313,7 @@ fn impl_encode(ty: parsed::Type<'_>) -> TokenStream {
 
             let idents: Vec<&Ident> = variants.iter().map(|variant| variant.ident).collect();
             let values: Vec<&LitInt> = variants.iter().map(|variant| &variant.value).collect();
+            let range_idents: Vec<&Ident> = data.range_variants.iter().map(|variant| variant.ident).collect();
 
             let expanded = quote! {
                 impl ::wayk_proto::serialization::Encode for #ty {
@@ -209,6 +341,9 @@ fn impl_encode(ty: parsed::Type<'_>) -> TokenStream {
                             #(
                                 #ty::#idents => #values,
                             )*
+                            #(
+                                #ty::#range_idents(inner) => inner,
+                            )*
                             #ty::#fallback_variant(inner) => inner,
                         }
                     }
@@ -216,15 +351,93 @@ fn impl_encode(ty: parsed::Type<'_>) -> TokenStream {
 
             };
 
-            expanded.into()
+            Ok(expanded)
         }
     }
 }
 
-#[proc_macro_derive(Decode, attributes(meta_enum, decode_ignore, value, fallback))]
+#[proc_macro_derive(
+    Decode,
+    attributes(
+        meta_enum,
+        decode_ignore,
+        size_prefix,
+        flag_cond,
+        endian,
+        value,
+        value_range,
+        fallback,
+        roundtrip
+    )
+)]
 pub fn decode_macro_derive(input: TokenStream) -> TokenStream {
-    let ast = syn::parse(input).expect("failed to parse input");
-    impl_trait(&ast, impl_decode)
+    let ast = parse_macro_input!(input as DeriveInput);
+    let roundtrip_test = match find_attr(&ast.attrs, "roundtrip")
+        .map(|attr| impl_roundtrip_test(&ast, attr))
+        .transpose()
+    {
+        Ok(test) => test,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let mut expanded = TokenStream2::from(impl_trait(&ast, impl_decode));
+    expanded.extend(roundtrip_test);
+    expanded.into()
+}
+
+/// Emits, for `#[roundtrip]` types, a property test checking generated values survive an
+/// encode/decode round trip, or only decoded values for `#[roundtrip(non_canonical)]` types. Only
+/// compiled in tests of crates with a `roundtrip` feature enabled, the type has to implement
+/// `arbitrary::Arbitrary`, `PartialEq` and `Debug`.
+fn impl_roundtrip_test(ast: &syn::DeriveInput, attr: &Attribute) -> syn::Result<TokenStream2> {
+    if let Some(param) = ast.generics.type_params().next() {
+        return Err(syn::Error::new_spanned(
+            param,
+            "`roundtrip` isn't supported on types with type parameters",
+        ));
+    }
+    let wrong_meta = |meta: &Meta| {
+        syn::Error::new_spanned(
+            meta,
+            "wrong meta for `roundtrip`. Expected `roundtrip` or `roundtrip(non_canonical)`.",
+        )
+    };
+    let non_canonical = match attr.parse_meta()? {
+        Meta::Path(_) => false,
+        Meta::List(list) => match list.nested.iter().collect::<Vec<&NestedMeta>>().as_slice() {
+            [NestedMeta::Meta(Meta::Path(path))] if path.is_ident("non_canonical") => true,
+            _ => return Err(wrong_meta(&Meta::List(list))),
+        },
+        meta => return Err(wrong_meta(&meta)),
+    };
+
+    let ty = &ast.ident;
+    let lifetimes = ast
+        .generics
+        .lifetimes()
+        .map(|_| Lifetime::new("'_", Span::call_site()))
+        .collect::<Vec<Lifetime>>();
+    let ty_generics = if lifetimes.is_empty() {
+        quote! {}
+    } else {
+        quote! { <#(#lifetimes),*> }
+    };
+    let module = Ident::new(&alloc::format!("__roundtrip_{}", ty), Span::call_site());
+    let non_canonical = if non_canonical {
+        quote! { , non_canonical }
+    } else {
+        quote! {}
+    };
+
+    Ok(quote! {
+        #[cfg(all(test, feature = "roundtrip"))]
+        #[allow(non_snake_case)]
+        mod #module {
+            #[test]
+            fn roundtrip() {
+                ::wayk_proto::__roundtrip_test!(super::#ty #ty_generics #non_canonical);
+            }
+        }
+    })
 }
 
 fn build_decode_impl_generics(generics: &Generics) -> TokenStream2 {
@@ -250,7 +463,16 @@ fn build_decode_impl_generics(generics: &Generics) -> TokenStream2 {
     }
 }
 
-fn impl_decode(enc_dec_ty: parsed::Type<'_>) -> TokenStream {
+/// Same as `build_decode_impl_generics`, with `'arbitrary` outliving the type lifetimes.
+fn build_arbitrary_impl_generics(generics: &Generics) -> TokenStream2 {
+    let lifetimes: Vec<&Lifetime> = generics.lifetimes().map(|def| &def.lifetime).collect();
+
+    quote! {
+        <'arbitrary: #(#lifetimes)+*, #(#lifetimes),*>
+    }
+}
+
+fn impl_decode(enc_dec_ty: parsed::Type<'_>) -> syn::Result<TokenStream2> {
     match enc_dec_ty {
         parsed::Type::Struct(data) => {
             let ty = data.name;
@@ -258,12 +480,67 @@ fn impl_decode(enc_dec_ty: parsed::Type<'_>) -> TokenStream {
             let impl_generics = build_decode_impl_generics(data.generics);
             let (_, ty_generics, where_clause) = data.generics.split_for_impl();
 
-            let fields_ty = data
+            let decode_stmts = data
                 .fields
                 .iter()
                 .filter(|field| !field.decode_ignore)
-                .map(|field| field.ty)
-                .collect::<Vec<&Type>>();
+                .map(|field| {
+                    let name = field.name;
+                    let decode = |field_ty: &Type| {
+                        let decode = match field.endian {
+                            Some(parsed::Endian::Little) => {
+                                quote! { <#field_ty as ::wayk_proto::serialization::Endian>::decode_le(cursor) }
+                            }
+                            Some(parsed::Endian::Big) => {
+                                quote! { <#field_ty as ::wayk_proto::serialization::Endian>::decode_be(cursor) }
+                            }
+                            None => {
+                                quote! { <#field_ty as ::wayk_proto::serialization::Decode>::decode_with_ctx(cursor, ctx) }
+                            }
+                        };
+                        quote! {
+                            #decode
+                                .chain(ProtoErrorKind::Decoding(stringify!(#ty)))
+                                .or_desc(concat!(
+                                    "couldn't decode ",
+                                    stringify!(#field_ty),
+                                    " into ",
+                                    stringify!(#ty), "::", stringify!(#name)
+                                ))?
+                        }
+                    };
+
+                    // fields only present when a flag is set, previously decoded fields are in scope
+                    match &field.flag_cond {
+                        Some(cond) => {
+                            let (flags, bit) = (&cond.flags, &cond.bit);
+                            let value = match (&cond.decode_with, field.option_inner) {
+                                (Some(decode_with), _) => quote! {
+                                    (#decode_with)
+                                        .chain(ProtoErrorKind::Decoding(stringify!(#ty)))
+                                        .or_desc(concat!("couldn't decode ", stringify!(#ty), "::", stringify!(#name)))?
+                                },
+                                (None, Some(inner_ty)) => {
+                                    let decode = decode(inner_ty);
+                                    quote! { Some(#decode) }
+                                }
+                                (None, None) => decode(field.ty),
+                            };
+                            quote! {
+                                let #name = if #flags.#bit() {
+                                    #value
+                                } else {
+                                    ::core::default::Default::default()
+                                };
+                            }
+                        }
+                        None => {
+                            let decode = decode(field.ty);
+                            quote! { let #name = #decode; }
+                        }
+                    }
+                })
+                .collect::<Vec<TokenStream2>>();
             let fields = data
                 .fields
                 .iter()
@@ -277,20 +554,52 @@ fn impl_decode(enc_dec_ty: parsed::Type<'_>) -> TokenStream {
                 .map(|field| field.name)
                 .collect::<Vec<&Ident>>();
 
+            // declared size covers the whole struct: trailing bytes are skipped (fields added by
+            // a newer peer), a size smaller than what was consumed is an error.
+            let size_check = data.fields.iter().find(|field| field.size_prefix).map(|field| {
+                let name = field.name;
+                quote! {
+                    let __consumed = cursor.position() - __start;
+                    let __declared = <usize as ::core::convert::TryFrom<_>>::try_from(#name)
+                        .ok()
+                        .chain(ProtoErrorKind::Decoding(stringify!(#ty)))
+                        .or_desc(concat!("invalid ", stringify!(#ty), "::", stringify!(#name)))?;
+                    if __declared < __consumed {
+                        return Err(ProtoError::new(ProtoErrorKind::Decoding(stringify!(#ty))).with_desc(format!(
+                            "declared size ({}) is smaller than decoded size ({})",
+                            __declared, __consumed
+                        )));
+                    }
+                    cursor
+                        .read_n(__declared - __consumed)
+                        .map_err(ProtoError::from)
+                        .chain(ProtoErrorKind::Decoding(stringify!(#ty)))
+                        .or_desc("declared size exceeds available data")?;
+                }
+            });
+
             let expanded = quote! {
                 impl #impl_generics ::wayk_proto::serialization::Decode<'dec> for #ty #ty_generics #where_clause {
                     fn decode_from(cursor: &mut ::wayk_proto::io::Cursor<'dec>) -> ::core::result::Result<Self, ::wayk_proto::error::ProtoError> {
-                        use ::wayk_proto::error::{ProtoErrorResultExt as _, ProtoErrorKind};
+                        Self::decode_with_ctx(cursor, &::wayk_proto::serialization::DecodeCtx::default())
+                    }
+
+                    #[allow(unused_variables)]
+                    fn decode_with_ctx(
+                        cursor: &mut ::wayk_proto::io::Cursor<'dec>,
+                        ctx: &::wayk_proto::serialization::DecodeCtx<'_>,
+                    ) -> ::core::result::Result<Self, ::wayk_proto::error::ProtoError> {
+                        #[allow(unused_imports)]
+                        use ::wayk_proto::error::{ProtoError, ProtoErrorResultExt as _, ProtoErrorKind};
+                        #[allow(unused_variables)]
+                        let __start = cursor.position();
+                        #(
+                            #decode_stmts
+                        )*
+                        #size_check
                         Ok(Self {
                             #(
-                                #fields: <#fields_ty as ::wayk_proto::serialization::Decode>::decode_from(cursor)
-                                    .chain(ProtoErrorKind::Decoding(stringify!(#ty)))
-                                    .or_desc(concat!(
-                                        "couldn't decode ",
-                                        stringify!(#fields_ty),
-                                        " into ",
-                                        stringify!(#ty), "::", stringify!(#fields)
-                                    ))?,
+                                #fields,
                             )*
                             #(
                                 #ignored_fields: ::core::default::Default::default(),
@@ -300,21 +609,29 @@ fn impl_decode(enc_dec_ty: parsed::Type<'_>) -> TokenStream {
                 }
             };
 
-            expanded.into()
+            Ok(expanded)
         }
         parsed::Type::MetaEnum(data) => {
             let ty = data.name;
             let generics = data.generics;
             let fallback_variant_ident = data.fallback_variant_ident;
 
-            let subtype_enum_ty = if let Meta::NameValue(name) = data.meta {
-                if let Lit::Str(s) = name.lit {
-                    Ident::new(&s.value(), Span::call_site())
-                } else {
-                    panic!("wrong literal in `meta_enum` attribute parameter. Expected a string literal for the subtype enum.");
+            let subtype_enum_ty = match data.meta {
+                Meta::NameValue(name) => match name.lit {
+                    Lit::Str(s) => s.parse::<Ident>()?,
+                    lit => {
+                        return Err(syn::Error::new_spanned(
+                            lit,
+                            "wrong literal in `meta_enum` attribute parameter. Expected a string literal for the subtype enum.",
+                        ))
+                    }
+                },
+                meta => {
+                    return Err(syn::Error::new_spanned(
+                        meta,
+                        r#"wrong meta for `meta_enum`. Expected a name value (eg: meta_enum = "...")."#,
+                    ))
                 }
-            } else {
-                panic!(r#"wrong meta for `meta_enum`. Expected a name value (eg: meta_enum = "...")."#);
             };
 
             let variants: Vec<&Ident> = data
@@ -330,12 +647,62 @@ fn impl_decode(enc_dec_ty: parsed::Type<'_>) -> TokenStream {
                 .map(|variant| variant.field_type)
                 .collect();
 
+            let arbitrary_variants: Vec<&Ident> = data
+                .variants
+                .iter()
+                .filter(|variant| !variant.decode_ignore && !variant.encode_ignore)
+                .map(|variant| variant.name)
+                .collect();
+            let variants_count = arbitrary_variants.len();
+            let variants_index = 0..variants_count;
+
             let impl_generics = build_decode_impl_generics(generics);
+            let arbitrary_impl_generics = build_arbitrary_impl_generics(generics);
             let (_, ty_generics, where_clause) = generics.split_for_impl();
 
+            // other meta enums aren't required to be generated, their variants may not implement `Arbitrary`
+            let arbitrary_impl = if data.arbitrary {
+                quote! {
+                    // variants ignored by the decoder are never generated, nor custom messages of a known subtype
+                    ::wayk_proto::__arbitrary! {
+                        impl #arbitrary_impl_generics ::wayk_proto::__arbitrary::Arbitrary<'arbitrary> for #ty #ty_generics #where_clause {
+                            fn arbitrary(
+                                u: &mut ::wayk_proto::__arbitrary::Unstructured<'arbitrary>,
+                            ) -> ::wayk_proto::__arbitrary::Result<Self> {
+                                use ::wayk_proto::__arbitrary::Arbitrary;
+
+                                let variant = u.choose_index(#variants_count + 1)?;
+                                #(
+                                    if variant == #variants_index {
+                                        return Ok(Self::#arbitrary_variants(Arbitrary::arbitrary(u)?));
+                                    }
+                                )*
+
+                                let msg = <&'arbitrary [u8]>::arbitrary(u)?;
+                                match <#subtype_enum_ty as ::wayk_proto::serialization::Decode>::decode(msg) {
+                                    Ok(#(#subtype_enum_ty::#variants)|*) | Err(_) => {
+                                        Err(::wayk_proto::__arbitrary::Error::IncorrectFormat)
+                                    }
+                                    Ok(_) => Ok(Self::#fallback_variant_ident(msg.into())),
+                                }
+                            }
+                        }
+                    }
+                }
+            } else {
+                quote! {}
+            };
+
             let expanded = quote! {
                 impl #impl_generics ::wayk_proto::serialization::Decode<'dec> for #ty #ty_generics #where_clause {
                     fn decode_from(cursor: &mut ::wayk_proto::io::Cursor<'dec>) -> ::core::result::Result<Self, ::wayk_proto::error::ProtoError> {
+                        Self::decode_with_ctx(cursor, &::wayk_proto::serialization::DecodeCtx::default())
+                    }
+
+                    fn decode_with_ctx(
+                        cursor: &mut ::wayk_proto::io::Cursor<'dec>,
+                        ctx: &::wayk_proto::serialization::DecodeCtx<'_>,
+                    ) -> ::core::result::Result<Self, ::wayk_proto::error::ProtoError> {
                         use ::wayk_proto::error::{ProtoError, ProtoErrorResultExt as _, ProtoErrorKind};
                         use ::wayk_proto::serialization::Encode;
 
@@ -346,7 +713,7 @@ fn impl_decode(enc_dec_ty: parsed::Type<'_>) -> TokenStream {
 
                         match subtype {
                             #(
-                                #subtype_enum_ty::#variants => <#variants_field_ty as ::wayk_proto::serialization::Decode>::decode_from(cursor)
+                                #subtype_enum_ty::#variants => <#variants_field_ty as ::wayk_proto::serialization::Decode>::decode_with_ctx(cursor, ctx)
                                     .map(Self::#variants)
                                     .chain(ProtoErrorKind::Decoding(stringify!(#ty)))
                                     .or_desc(concat!(
@@ -360,13 +727,15 @@ fn impl_decode(enc_dec_ty: parsed::Type<'_>) -> TokenStream {
                                 .map_err(ProtoError::from)
                                 .chain(ProtoErrorKind::Encoding(stringify!(#ty)))
                                 .or_desc("couldn't decode custom message")
-                                .map(Self::#fallback_variant_ident),
+                                .map(|msg| Self::#fallback_variant_ident(msg.into())),
                         }
                     }
                 }
+
+                #arbitrary_impl
             };
 
-            expanded.into()
+            Ok(expanded)
         }
         parsed::Type::EnumWithFallback(data) => {
             let ty = data.name;
@@ -376,6 +745,9 @@ fn impl_decode(enc_dec_ty: parsed::Type<'_>) -> TokenStream {
 
             let idents: Vec<&Ident> = variants.iter().map(|variant| variant.ident).collect();
             let values: Vec<&LitInt> = variants.iter().map(|variant| &variant.value).collect();
+            let range_idents: Vec<&Ident> = data.range_variants.iter().map(|variant| variant.ident).collect();
+            let range_starts: Vec<&LitInt> = data.range_variants.iter().map(|variant| &variant.start).collect();
+            let range_ends: Vec<&LitInt> = data.range_variants.iter().map(|variant| &variant.end).collect();
 
             let expanded = quote! {
                 impl ::wayk_proto::serialization::Decode<'_> for #ty {
@@ -395,144 +767,502 @@ fn impl_decode(enc_dec_ty: parsed::Type<'_>) -> TokenStream {
                             #(
                                 #values => #ty::#idents,
                             )*
+                            #(
+                                #range_starts..=#range_ends => #ty::#range_idents(v),
+                            )*
                             _ => #ty::#fallback_variant(v),
                         }
                     }
                 }
+
+                // the fallback variant never holds a known value: decoding wouldn't give it back
+                ::wayk_proto::__arbitrary! {
+                    impl<'arbitrary> ::wayk_proto::__arbitrary::Arbitrary<'arbitrary> for #ty {
+                        fn arbitrary(
+                            u: &mut ::wayk_proto::__arbitrary::Unstructured<'arbitrary>,
+                        ) -> ::wayk_proto::__arbitrary::Result<Self> {
+                            // known values would hardly ever be picked out of a wide representation otherwise
+                            let known: &[#underlying_repr] = &[#(#values),*];
+                            let v = if !known.is_empty() && u.arbitrary()? {
+                                *u.choose(known)?
+                            } else {
+                                <#underlying_repr as ::wayk_proto::__arbitrary::Arbitrary<'_>>::arbitrary(u)?
+                            };
+                            Ok(#ty::from(v))
+                        }
+                    }
+                }
             };
 
-            expanded.into()
+            Ok(expanded)
         }
     }
 }
 
+#[proc_macro_derive(IntoOwned)]
+pub fn into_owned_macro_derive(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    impl_into_owned(&ast).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Implements `IntoOwned` for a type borrowing through a single lifetime parameter.
+///
+/// Fields whose type mentions the lifetime are converted, others are moved as is.
+fn impl_into_owned(ast: &syn::DeriveInput) -> syn::Result<TokenStream2> {
+    if let Some(param) = ast.generics.type_params().next() {
+        return Err(syn::Error::new_spanned(
+            param,
+            "`IntoOwned` isn't supported on types with type parameters",
+        ));
+    }
+    let mut lifetimes = ast.generics.lifetimes();
+    let lifetime = match (lifetimes.next(), lifetimes.next()) {
+        (Some(def), None) => &def.lifetime,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &ast.ident,
+                "`IntoOwned` requires exactly one lifetime parameter",
+            ))
+        }
+    };
+
+    let ty = &ast.ident;
+    let convert = |fields: &Fields, prefix: &str| -> (TokenStream2, TokenStream2) {
+        let bindings: Vec<Ident> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| match &field.ident {
+                Some(ident) => Ident::new(&alloc::format!("{}{}", prefix, ident), Span::call_site()),
+                None => Ident::new(&alloc::format!("{}{}", prefix, i), Span::call_site()),
+            })
+            .collect();
+        let values: Vec<TokenStream2> = fields
+            .iter()
+            .zip(bindings.iter())
+            .map(|(field, binding)| {
+                let field_ty = &field.ty;
+                if mentions_lifetime(quote! { #field_ty }, &lifetime.ident) {
+                    quote! { ::wayk_proto::message::IntoOwned::into_owned(#binding) }
+                } else {
+                    quote! { #binding }
+                }
+            })
+            .collect();
+        match fields {
+            Fields::Named(_) => {
+                let names: Vec<&Ident> = fields.iter().filter_map(|field| field.ident.as_ref()).collect();
+                (
+                    quote! { { #(#names: #bindings),* } },
+                    quote! { { #(#names: #values),* } },
+                )
+            }
+            Fields::Unnamed(_) => (quote! { ( #(#bindings),* ) }, quote! { ( #(#values),* ) }),
+            Fields::Unit => (quote! {}, quote! {}),
+        }
+    };
+
+    let body = match &ast.data {
+        Data::Struct(data) => {
+            let (pattern, value) = convert(&data.fields, "__");
+            quote! {
+                let #ty #pattern = self;
+                #ty #value
+            }
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().map(|variant| {
+                let name = &variant.ident;
+                let (pattern, value) = convert(&variant.fields, "__");
+                quote! { #ty::#name #pattern => #ty::#name #value }
+            });
+            quote! {
+                match self {
+                    #(#arms,)*
+                }
+            }
+        }
+        Data::Union(_) => return Err(syn::Error::new_spanned(ty, "`IntoOwned` isn't supported on unions")),
+    };
+
+    Ok(quote! {
+        impl ::wayk_proto::message::IntoOwned for #ty<'_> {
+            type Owned = #ty<'static>;
+
+            fn into_owned(self) -> #ty<'static> {
+                #body
+            }
+        }
+    })
+}
+
+fn mentions_lifetime(tokens: TokenStream2, lifetime: &Ident) -> bool {
+    let mut after_quote = false;
+    for token in tokens {
+        match &token {
+            proc_macro2::TokenTree::Group(group) if mentions_lifetime(group.stream(), lifetime) => return true,
+            proc_macro2::TokenTree::Ident(ident) if after_quote && ident == lifetime => return true,
+            _ => {}
+        }
+        after_quote = matches!(&token, proc_macro2::TokenTree::Punct(punct) if punct.as_char() == '\'');
+    }
+    false
+}
+
 fn find_attr<'a>(attrs: &'a [Attribute], name: &str) -> Option<&'a Attribute> {
     attrs
         .iter()
         .find(|attr| attr.path.segments.iter().any(|seg| seg.ident == name))
 }
 
-fn impl_trait<F>(ast: &syn::DeriveInput, implementor: F) -> TokenStream
-where
-    F: FnOnce(parsed::Type<'_>) -> TokenStream,
-{
+/// Parses `#[flag_cond(flags = "...", bit = "...")]` with an optional `decode_with = "..."`
+/// expression evaluating to a `Result` of the field type (`cursor`, `ctx` and previously decoded
+/// fields are in scope).
+fn parse_flag_cond(attr: &Attribute) -> syn::Result<parsed::FlagCond> {
+    let list = match attr.parse_meta()? {
+        Meta::List(list) => list,
+        meta => {
+            return Err(syn::Error::new_spanned(
+                meta,
+                r#"wrong meta for `flag_cond`. Expected a list (eg: flag_cond(flags = "flags", bit = "extra"))."#,
+            ))
+        }
+    };
+
+    let mut flags = None;
+    let mut bit = None;
+    let mut decode_with = None;
+
+    for nested in list.nested.iter() {
+        let name_value = match nested {
+            NestedMeta::Meta(Meta::NameValue(name_value)) => name_value,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    nested,
+                    "wrong `flag_cond` parameter. Expected a name value.",
+                ))
+            }
+        };
+        let value = match &name_value.lit {
+            Lit::Str(s) => s,
+            lit => {
+                return Err(syn::Error::new_spanned(
+                    lit,
+                    "wrong literal in `flag_cond` parameter. Expected a string literal.",
+                ))
+            }
+        };
+
+        if name_value.path.is_ident("flags") {
+            flags = Some(value.parse::<Ident>()?);
+        } else if name_value.path.is_ident("bit") {
+            bit = Some(value.parse::<Ident>()?);
+        } else if name_value.path.is_ident("decode_with") {
+            decode_with = Some(value.parse::<syn::Expr>()?);
+        } else {
+            return Err(syn::Error::new_spanned(
+                &name_value.path,
+                "unknown `flag_cond` parameter. Expected one of `flags`, `bit` or `decode_with`.",
+            ));
+        }
+    }
+
+    Ok(parsed::FlagCond {
+        flags: flags.ok_or_else(|| syn::Error::new_spanned(&list, "`flags` parameter missing in `flag_cond`"))?,
+        bit: bit.ok_or_else(|| syn::Error::new_spanned(&list, "`bit` parameter missing in `flag_cond`"))?,
+        decode_with,
+    })
+}
+
+fn parse_endian_attr(attr: &Attribute) -> syn::Result<parsed::Endian> {
+    match attr.parse_meta()? {
+        Meta::NameValue(name) => match name.lit {
+            Lit::Str(lit_str) if lit_str.value() == "little" => Ok(parsed::Endian::Little),
+            Lit::Str(lit_str) if lit_str.value() == "big" => Ok(parsed::Endian::Big),
+            lit => Err(syn::Error::new_spanned(
+                lit,
+                r#"wrong literal in `endian` attribute parameter. Expected "little" or "big"."#,
+            )),
+        },
+        meta => Err(syn::Error::new_spanned(
+            meta,
+            r#"wrong meta for `endian`. Expected a name value (eg: endian = "big")."#,
+        )),
+    }
+}
+
+/// Whether `ty` is a primitive integer, the fields a struct level `#[endian = "..."]` applies to.
+fn is_integer_type(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => path
+            .path
+            .get_ident()
+            .map(|ident| {
+                ["u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64"]
+                    .iter()
+                    .any(|int| ident == int)
+            })
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn option_inner_type(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last()?,
+        _ => return None,
+    };
+
+    if segment.ident != "Option" {
+        return None;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// Type of the single unnamed field of an enum variant.
+fn variant_field_type(variant: &syn::Variant) -> syn::Result<&Type> {
+    match &variant.fields {
+        Fields::Unnamed(fields) if fields.unnamed.len() == 1 => Ok(&fields.unnamed[0].ty),
+        Fields::Unnamed(fields) => Err(syn::Error::new_spanned(fields, "expected exactly one unnamed field")),
+        Fields::Named(fields) => Err(syn::Error::new_spanned(fields, "named fields unsupported")),
+        Fields::Unit => Err(syn::Error::new_spanned(variant, "unexpected unit variant")),
+    }
+}
+
+fn find_fallback_variant<'a>(ast: &'a syn::DeriveInput, data: &'a syn::DataEnum) -> syn::Result<&'a syn::Variant> {
+    data.variants
+        .iter()
+        .find(|v| find_attr(&v.attrs, "fallback").is_some())
+        .ok_or_else(|| syn::Error::new_spanned(&ast.ident, "fallback variant missing (eg: #[fallback] Other(u8))"))
+}
+
+fn parse_value_attr(attr: &Attribute) -> syn::Result<LitInt> {
+    match attr.parse_meta()? {
+        Meta::NameValue(name) => match name.lit {
+            Lit::Int(lit_int) => Ok(lit_int),
+            lit => Err(syn::Error::new_spanned(
+                lit,
+                "wrong literal in `value` attribute parameter. Expected a int literal.",
+            )),
+        },
+        meta => Err(syn::Error::new_spanned(
+            meta,
+            r#"wrong meta for `value`. Expected a name value (eg: value = 1)."#,
+        )),
+    }
+}
+
+/// Parses `#[value_range = "start..=end"]`, bounds are int literals.
+fn parse_value_range_attr(attr: &Attribute) -> syn::Result<(LitInt, LitInt)> {
+    let lit_str = match attr.parse_meta()? {
+        Meta::NameValue(name) => match name.lit {
+            Lit::Str(lit_str) => lit_str,
+            lit => {
+                return Err(syn::Error::new_spanned(
+                    lit,
+                    "wrong literal in `value_range` attribute parameter. Expected a string literal.",
+                ))
+            }
+        },
+        meta => {
+            return Err(syn::Error::new_spanned(
+                meta,
+                r#"wrong meta for `value_range`. Expected a name value (eg: value_range = "0x80..=0xff")."#,
+            ))
+        }
+    };
+
+    let range = lit_str.value();
+    let (start, end) = range.split_once("..=").ok_or_else(|| {
+        syn::Error::new_spanned(
+            &lit_str,
+            "expected an inclusive range of int literals (eg: 0x80..=0xff)",
+        )
+    })?;
+    let parse_bound = |bound: &str| {
+        syn::parse_str::<LitInt>(bound.trim())
+            .map(|mut lit_int| {
+                lit_int.set_span(lit_str.span());
+                lit_int
+            })
+            .map_err(|_| syn::Error::new_spanned(&lit_str, "range bounds must be int literals"))
+    };
+    let (start, end) = (parse_bound(start)?, parse_bound(end)?);
+    if start.base10_parse::<u128>()? > end.base10_parse::<u128>()? {
+        return Err(syn::Error::new_spanned(&lit_str, "range start is greater than its end"));
+    }
+
+    Ok((start, end))
+}
+
+fn parse_type(ast: &syn::DeriveInput) -> syn::Result<parsed::Type<'_>> {
     let ty = &ast.ident;
     let generics = &ast.generics;
     let enc_dec_type = match &ast.data {
         Data::Struct(data) => {
-            if let Fields::Named(fields) = &data.fields {
-                let fields = fields
-                    .named
-                    .iter()
-                    .map(|field| parsed::Field {
-                        decode_ignore: find_attr(&field.attrs, "decode_ignore").is_some(),
-                        encode_ignore: find_attr(&field.attrs, "encode_ignore").is_some(),
-                        name: field.ident.as_ref().unwrap(),
-                        ty: &field.ty,
-                    })
-                    .collect();
-
-                parsed::Type::Struct(parsed::Struct {
-                    name: ty,
-                    generics,
-                    fields,
-                })
-            } else {
-                unimplemented!("currently only named fields are supported");
+            let named = match &data.fields {
+                Fields::Named(fields) => &fields.named,
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        ty,
+                        "currently only structs with named fields are supported",
+                    ))
+                }
+            };
+
+            let struct_endian = find_attr(&ast.attrs, "endian").map(parse_endian_attr).transpose()?;
+
+            let mut fields = Vec::new();
+            for (i, field) in named.iter().enumerate() {
+                let size_prefix = find_attr(&field.attrs, "size_prefix");
+                if let (Some(attr), true) = (size_prefix, i > 0) {
+                    return Err(syn::Error::new_spanned(
+                        attr,
+                        "`size_prefix` is only supported on the first field",
+                    ));
+                }
+
+                let flag_cond = find_attr(&field.attrs, "flag_cond").map(parse_flag_cond).transpose()?;
+                let option_inner = option_inner_type(&field.ty);
+
+                // optional fields are decoded as their inner type when behind a flag
+                let value_ty = match (&flag_cond, option_inner) {
+                    (Some(_), Some(inner_ty)) => inner_ty,
+                    _ => &field.ty,
+                };
+                let endian = match find_attr(&field.attrs, "endian") {
+                    Some(attr) => Some(parse_endian_attr(attr)?),
+                    None => struct_endian.filter(|_| is_integer_type(value_ty)),
+                };
+
+                fields.push(parsed::Field {
+                    decode_ignore: find_attr(&field.attrs, "decode_ignore").is_some(),
+                    encode_ignore: find_attr(&field.attrs, "encode_ignore").is_some(),
+                    size_prefix: size_prefix.is_some(),
+                    flag_cond,
+                    endian,
+                    name: field.ident.as_ref().expect("named field"),
+                    ty: &field.ty,
+                    option_inner,
+                });
             }
+
+            parsed::Type::Struct(parsed::Struct {
+                name: ty,
+                generics,
+                fields,
+            })
         }
         Data::Enum(data) => {
-            let meta_enum_attr = find_attr(&ast.attrs, "meta_enum");
-            if let Some(meta_enum_attr) = meta_enum_attr {
-                let meta = meta_enum_attr
-                    .parse_meta()
-                    .expect("failed to parse `meta_enum` argument");
-
-                let variants = data
-                    .variants
-                    .iter()
-                    .filter_map(|v| {
-                        if find_attr(&v.attrs, "fallback").is_some() {
-                            return None;
-                        }
-
-                        let field_type = match &v.fields {
-                            Fields::Unnamed(fields) => &fields.unnamed.first().unwrap().ty,
-                            Fields::Named(_) => panic!("named fields unsupported"),
-                            Fields::Unit => panic!("unexpected unit field"),
-                        };
+            let fallback_variant = find_fallback_variant(ast, data)?;
+            if let Some(meta_enum_attr) = find_attr(&ast.attrs, "meta_enum") {
+                let meta = meta_enum_attr.parse_meta()?;
+
+                let mut variants = Vec::new();
+                for v in data.variants.iter() {
+                    if find_attr(&v.attrs, "fallback").is_some() {
+                        continue;
+                    }
 
-                        Some(parsed::MetaEnumVariant {
-                            decode_ignore: find_attr(&v.attrs, "decode_ignore").is_some(),
-                            encode_ignore: find_attr(&v.attrs, "encode_ignore").is_some(),
-                            name: &v.ident,
-                            field_type,
-                        })
-                    })
-                    .collect();
+                    variants.push(parsed::MetaEnumVariant {
+                        decode_ignore: find_attr(&v.attrs, "decode_ignore").is_some(),
+                        encode_ignore: find_attr(&v.attrs, "encode_ignore").is_some(),
+                        name: &v.ident,
+                        field_type: variant_field_type(v)?,
+                    });
+                }
 
-                let fallback_variant_ident = data
-                    .variants
-                    .iter()
-                    .find(|v| find_attr(&v.attrs, "fallback").is_some())
-                    .map(|v| match &v.fields {
-                        Fields::Unnamed(_) => &v.ident,
-                        Fields::Named(_) => panic!("unexpected named field"),
-                        Fields::Unit => panic!("unexpected unit field"),
-                    })
-                    .expect("fallback variant missing");
+                variant_field_type(fallback_variant)?;
 
                 parsed::Type::MetaEnum(parsed::MetaEnum {
                     name: ty,
                     generics,
                     meta,
                     variants,
-                    fallback_variant_ident,
+                    fallback_variant_ident: &fallback_variant.ident,
+                    arbitrary: find_attr(&ast.attrs, "roundtrip").is_some(),
                 })
             } else {
-                let variants = data
-                    .variants
-                    .iter()
-                    .filter_map(|variant| {
-                        let attr = find_attr(&variant.attrs, "value")?;
-                        let meta = attr.parse_meta().expect("failed to parse `value` attribute");
-                        let lit_int = if let Meta::NameValue(name) = meta {
-                            if let Lit::Int(lit_int) = name.lit {
-                                lit_int
-                            } else {
-                                panic!("wrong literal in `value` attribute parameter. Expected a int literal.");
-                            }
-                        } else {
-                            panic!(r#"wrong meta for `value`. Expected a name value (eg: value = 1)."#);
-                        };
+                let mut variants = Vec::new();
+                let mut range_variants = Vec::new();
+                // variants without `value` attribute follow the previous one, or the end of the previous range
+                let mut previous_value: Option<u128> = None;
+                let mut values = Vec::new();
+                let mut ranges: Vec<(u128, u128)> = Vec::new();
+                for variant in data.variants.iter() {
+                    if find_attr(&variant.attrs, "fallback").is_some() {
+                        continue;
+                    }
 
-                        Some(parsed::VariantWithValue {
+                    if let Some(attr) = find_attr(&variant.attrs, "value_range") {
+                        variant_field_type(variant)?;
+                        let (start, end) = parse_value_range_attr(attr)?;
+                        let bounds = (start.base10_parse()?, end.base10_parse()?);
+                        if ranges.iter().any(|&(s, e)| bounds.0 <= e && s <= bounds.1) {
+                            return Err(syn::Error::new_spanned(attr, "value range overlaps a previous range"));
+                        }
+                        ranges.push(bounds);
+                        previous_value = Some(bounds.1);
+                        range_variants.push(parsed::VariantWithRange {
                             ident: &variant.ident,
-                            value: lit_int,
-                        })
-                    })
-                    .collect();
+                            start,
+                            end,
+                        });
+                        continue;
+                    }
 
-                let fallback_variant = data
-                    .variants
-                    .iter()
-                    .find(|v| find_attr(&v.attrs, "fallback").is_some())
-                    .expect("fallback variant not found");
+                    let value = match (find_attr(&variant.attrs, "value"), previous_value) {
+                        (Some(attr), _) => parse_value_attr(attr)?,
+                        (None, Some(previous)) => LitInt::new(&(previous + 1).to_string(), variant.ident.span()),
+                        (None, None) => {
+                            return Err(syn::Error::new_spanned(
+                                variant,
+                                "variant value missing (eg: #[value = 1])",
+                            ))
+                        }
+                    };
+                    let parsed_value = value.base10_parse()?;
+                    previous_value = Some(parsed_value);
+                    values.push((value.clone(), parsed_value));
+                    variants.push(parsed::VariantWithValue {
+                        ident: &variant.ident,
+                        value,
+                    });
+                }
+
+                for (lit, value) in values {
+                    if ranges.iter().any(|&(start, end)| start <= value && value <= end) {
+                        return Err(syn::Error::new(lit.span(), "variant value is inside a value range"));
+                    }
+                }
 
                 parsed::Type::EnumWithFallback(parsed::EnumWithFallback {
                     name: ty,
-                    underlying_repr: match &fallback_variant.fields {
-                        Fields::Unnamed(field) => &field.unnamed.first().unwrap().ty,
-                        Fields::Named(_) => panic!("named fields unsupported"),
-                        Fields::Unit => panic!("unexpected unit field"),
-                    },
+                    underlying_repr: variant_field_type(fallback_variant)?,
                     variants,
+                    range_variants,
                     fallback_variant: &fallback_variant.ident,
                 })
             }
         }
-        Data::Union(_) => unimplemented!("union"),
+        Data::Union(data) => return Err(syn::Error::new_spanned(data.union_token, "unions are not supported")),
     };
 
-    implementor(enc_dec_type)
+    Ok(enc_dec_type)
+}
+
+fn impl_trait<F>(ast: &syn::DeriveInput, implementor: F) -> TokenStream
+where
+    F: FnOnce(parsed::Type<'_>) -> syn::Result<TokenStream2>,
+{
+    parse_type(ast)
+        .and_then(implementor)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
 }
@@ -0,0 +1,11 @@
+use wayk_proto_derive::{Decode, Encode};
+
+#[derive(Encode, Decode, Clone, Copy)]
+enum Kind {
+    #[value = 1]
+    One,
+    #[value = 2]
+    Two,
+}
+
+fn main() {}
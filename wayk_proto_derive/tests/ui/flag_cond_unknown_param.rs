@@ -0,0 +1,10 @@
+use wayk_proto_derive::{Decode, Encode};
+
+#[derive(Encode, Decode)]
+struct Info {
+    flags: u16,
+    #[flag_cond(flags = "flags", bit = "extra", decode = "decode_extra(cursor)")]
+    extra: u32,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use wayk_proto_derive::Decode;
+
+#[derive(Decode)]
+#[meta_enum = 1]
+enum Msg<'a> {
+    Ping(u8),
+    #[fallback]
+    Custom(&'a [u8]),
+}
+
+fn main() {}
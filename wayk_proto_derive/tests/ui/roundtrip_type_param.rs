@@ -0,0 +1,9 @@
+use wayk_proto_derive::Decode;
+
+#[derive(Decode)]
+#[roundtrip]
+struct Wrapper<T> {
+    value: T,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use wayk_proto_derive::{Decode, Encode};
+
+#[derive(Encode, Decode)]
+struct Def {
+    id: u8,
+    #[size_prefix]
+    size: u16,
+}
+
+fn main() {}
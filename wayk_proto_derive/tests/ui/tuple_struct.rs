@@ -0,0 +1,6 @@
+use wayk_proto_derive::Encode;
+
+#[derive(Encode)]
+struct Pair(u8, u8);
+
+fn main() {}
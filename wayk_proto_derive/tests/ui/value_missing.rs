@@ -0,0 +1,12 @@
+use wayk_proto_derive::{Decode, Encode};
+
+#[derive(Encode, Decode, Clone, Copy)]
+enum Kind {
+    One,
+    #[value = 2]
+    Two,
+    #[fallback]
+    Other(u8),
+}
+
+fn main() {}
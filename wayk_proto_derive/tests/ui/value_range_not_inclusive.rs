@@ -0,0 +1,13 @@
+use wayk_proto_derive::{Decode, Encode};
+
+#[derive(Encode, Decode, Clone, Copy)]
+enum Kind {
+    #[value = 1]
+    One,
+    #[value_range = "0x80..0xff"]
+    Reserved(u8),
+    #[fallback]
+    Other(u8),
+}
+
+fn main() {}
@@ -0,0 +1,15 @@
+use wayk_proto_derive::{Decode, Encode};
+
+#[derive(Encode, Decode, Clone, Copy)]
+enum Kind {
+    #[value = 1]
+    One,
+    #[value_range = "0x80..=0x8f"]
+    Reserved(u8),
+    #[value_range = "0x88..=0xff"]
+    Vendor(u8),
+    #[fallback]
+    Other(u8),
+}
+
+fn main() {}